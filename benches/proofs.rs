@@ -0,0 +1,160 @@
+//! Proof generation and decryption times, to catch regressions when bumping `solana-zk-sdk`.
+//!
+//! Run with `cargo bench`; `cargo bench -- withdraw` runs one group. The decryption group builds
+//! the decode table on first use, like the CLI does.
+
+use confidential_transfer::decode_table::DecodeTable;
+use confidential_transfer_core::{
+    balance::decrypt_decryptable_balance,
+    proofs::{TransferFeeParams, pubkey_validity_proof, transfer_proofs, withdraw_proofs},
+};
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use spl_token_client::spl_token_2022::{
+    extension::{
+        confidential_transfer::account_info::{TransferAccountInfo, WithdrawAccountInfo},
+        transfer_fee::TransferFee,
+    },
+    solana_zk_sdk::encryption::{
+        auth_encryption::AeKey, elgamal::ElGamalKeypair, pod::auth_encryption::PodAeCiphertext,
+    },
+};
+use std::hint::black_box;
+
+//Available balance of the source account, large enough for every amount below
+const BALANCE: u64 = (1 << 48) - 1;
+//Amounts from a single base unit to the largest a transfer can carry
+const AMOUNTS: [u64; 4] = [1, 1 << 16, 1 << 32, (1 << 48) - 1];
+//Decrypted values, up to the largest the decode table covers
+const DECRYPTED: [u64; 3] = [1 << 8, 1 << 20, (1 << 36) - 1];
+
+// Keys of an account and of a transfer recipient, with the account's balance ciphertexts
+struct Fixture {
+    elgamal_keypair: ElGamalKeypair,
+    aes_key: AeKey,
+    destination_elgamal_keypair: ElGamalKeypair,
+    auditor_elgamal_keypair: ElGamalKeypair,
+    withdraw_account: WithdrawAccountInfo,
+    transfer_account: TransferAccountInfo,
+}
+
+impl Fixture {
+    fn new() -> Self {
+        let elgamal_keypair = ElGamalKeypair::new_rand();
+        let aes_key = AeKey::new_rand();
+        let available_balance = elgamal_keypair.pubkey().encrypt(BALANCE).into();
+        let decryptable_available_balance = aes_key.encrypt(BALANCE).into();
+        Self {
+            destination_elgamal_keypair: ElGamalKeypair::new_rand(),
+            auditor_elgamal_keypair: ElGamalKeypair::new_rand(),
+            withdraw_account: WithdrawAccountInfo {
+                available_balance,
+                decryptable_available_balance,
+            },
+            transfer_account: TransferAccountInfo {
+                available_balance,
+                decryptable_available_balance,
+            },
+            elgamal_keypair,
+            aes_key,
+        }
+    }
+}
+
+fn pubkey_validity(c: &mut Criterion) {
+    let elgamal_keypair = ElGamalKeypair::new_rand();
+    c.bench_function("pubkey_validity", |b| {
+        b.iter(|| pubkey_validity_proof(black_box(&elgamal_keypair)).unwrap())
+    });
+}
+
+fn withdraw(c: &mut Criterion) {
+    let fixture = Fixture::new();
+    let mut group = c.benchmark_group("withdraw");
+    for amount in AMOUNTS {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(amount),
+            &amount,
+            |b, &amount| {
+                b.iter(|| {
+                    withdraw_proofs(
+                        &fixture.withdraw_account,
+                        black_box(amount),
+                        &fixture.elgamal_keypair,
+                        &fixture.aes_key,
+                    )
+                    .unwrap()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn transfer(c: &mut Criterion) {
+    let fixture = Fixture::new();
+    //1 % capped at 10_000 base units
+    let transfer_fee = TransferFeeParams {
+        transfer_fee: TransferFee {
+            epoch: 0u64.into(),
+            maximum_fee: 10_000u64.into(),
+            transfer_fee_basis_points: 100u16.into(),
+        },
+        withdraw_withheld_authority_elgamal_pubkey: *ElGamalKeypair::new_rand().pubkey(),
+    };
+    let mut group = c.benchmark_group("transfer");
+    //Fewer samples: transfer proofs take far longer than the others
+    group.sample_size(10);
+    for amount in AMOUNTS {
+        for (name, fee) in [("plain", None), ("with_fee", Some(&transfer_fee))] {
+            group.bench_with_input(BenchmarkId::new(name, amount), &amount, |b, &amount| {
+                b.iter(|| {
+                    transfer_proofs(
+                        &fixture.transfer_account,
+                        black_box(amount),
+                        &fixture.elgamal_keypair,
+                        &fixture.aes_key,
+                        fixture.destination_elgamal_keypair.pubkey(),
+                        Some(fixture.auditor_elgamal_keypair.pubkey()),
+                        fee,
+                    )
+                    .unwrap()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn decryption(c: &mut Criterion) {
+    let fixture = Fixture::new();
+    let decode_table = DecodeTable::open_default().unwrap();
+    let mut group = c.benchmark_group("decryption");
+    for value in DECRYPTED {
+        let ciphertext = fixture.elgamal_keypair.pubkey().encrypt(value);
+        group.bench_with_input(
+            BenchmarkId::new("elgamal", value),
+            &ciphertext,
+            |b, ciphertext| {
+                b.iter(|| {
+                    decode_table
+                        .decrypt(fixture.elgamal_keypair.secret(), black_box(ciphertext))
+                        .unwrap()
+                })
+            },
+        );
+        let ciphertext: PodAeCiphertext = fixture.aes_key.encrypt(value).into();
+        group.bench_with_input(
+            BenchmarkId::new("aes", value),
+            &ciphertext,
+            |b, &ciphertext| {
+                b.iter(|| {
+                    decrypt_decryptable_balance(black_box(ciphertext), &fixture.aes_key).unwrap()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, pubkey_validity, withdraw, transfer, decryption);
+criterion_main!(benches);
@@ -0,0 +1,6 @@
+//Generates the gRPC service of the `grpc` feature from its proto definitions; needs `protoc`
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/confidential_transfer.proto")?;
+    Ok(())
+}
@@ -0,0 +1,31 @@
+use solana_zk_sdk::encryption::{
+    auth_encryption::{AeCiphertext, AeKey},
+    pod::auth_encryption::PodAeCiphertext,
+};
+#[cfg(feature = "token-2022")]
+use spl_token_2022::extension::confidential_transfer::ConfidentialTransferAccount;
+
+/// Decrypts the available balance of a `ConfidentialTransferAccount` extension with the AES key.
+///
+/// Accounts configured from an ElGamal registry start with an empty decryptable balance rather
+/// than an encryption of zero; it reads as zero until the first apply replaces it.
+#[cfg(feature = "token-2022")]
+pub fn decrypt_available_balance(
+    extension_data: &ConfidentialTransferAccount,
+    aes_key: &AeKey,
+) -> Option<u64> {
+    decrypt_decryptable_balance(extension_data.decryptable_available_balance, aes_key)
+}
+
+/// Same as [`decrypt_available_balance`] for the decryptable available balance alone.
+pub fn decrypt_decryptable_balance(
+    decryptable_available_balance: PodAeCiphertext,
+    aes_key: &AeKey,
+) -> Option<u64> {
+    if decryptable_available_balance == PodAeCiphertext::default() {
+        return Some(0);
+    }
+    AeCiphertext::try_from(decryptable_available_balance)
+        .ok()
+        .and_then(|ciphertext| aes_key.decrypt(&ciphertext))
+}
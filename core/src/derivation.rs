@@ -0,0 +1,162 @@
+use anyhow::{Result, anyhow, bail};
+use sha3::{Digest, Sha3_512};
+use solana_pubkey::Pubkey;
+use solana_signature::Signature;
+use solana_signer::Signer;
+use solana_zk_sdk::encryption::{
+    AE_KEY_LEN, ELGAMAL_PUBKEY_LEN,
+    auth_encryption::AeKey,
+    elgamal::{ElGamalKeypair, ElGamalPubkey, ElGamalSecretKey},
+};
+use std::{fmt, str::FromStr};
+
+/// Message the owner signs to derive the ElGamal keypair and AES key of a token account.
+///
+/// Written as a versioned spec, so the scheme of stored settings stays unambiguous:
+///
+/// - `v1` — the default, compatible with the `spl-token` CLI: `ElGamalKeypair::new_from_signer`
+///   and `AeKey::new_from_signer` with the token account address as seed, i.e. signatures of
+///   `"ElGamalSecretKey" || address` and `"AeKey" || address`.
+/// - `v2:<TEMPLATE>` — the UTF-8 message of `TEMPLATE` is signed as is, for integrators that
+///   already derive keys from a message of their own, e.g. one set by a wallet standard. `{key}`
+///   is replaced by `ElGamalSecretKey` or `AeKey` and must appear, so the two keys differ;
+///   `{account}` is replaced by the base58 token account address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum SeedMessage {
+    /// Version 1: the token account address as seed of the zk-sdk signer derivation.
+    #[default]
+    AccountAddress,
+    /// Version 2: a message template signed as is.
+    Template(String),
+}
+
+impl SeedMessage {
+    /// Version of the scheme.
+    pub fn version(&self) -> u8 {
+        match self {
+            SeedMessage::AccountAddress => 1,
+            SeedMessage::Template(_) => 2,
+        }
+    }
+
+    /// Messages signed to derive the ElGamal keypair and the AES key of `account`, in this order.
+    ///
+    /// Signers that cannot run this crate, e.g. a web wallet, sign these and hand the signatures to
+    /// [`keys_from_signatures`](Self::keys_from_signatures) to get the same keys.
+    pub fn messages(&self, account: &Pubkey) -> [Vec<u8>; 2] {
+        ["ElGamalSecretKey", "AeKey"].map(|key| match self {
+            SeedMessage::AccountAddress => [key.as_bytes(), account.as_ref()].concat(),
+            SeedMessage::Template(template) => expand(template, key, account).into_bytes(),
+        })
+    }
+
+    /// Derives the keys from the signatures of the two [`messages`](Self::messages).
+    pub fn keys_from_signatures(
+        elgamal_signature: &Signature,
+        aes_signature: &Signature,
+    ) -> Result<(ElGamalKeypair, AeKey)> {
+        for signature in [elgamal_signature, aes_signature] {
+            if *signature == Signature::default() {
+                bail!("Rejecting the default signature, which is no key material");
+            }
+        }
+        //The zk-sdk derivation of `new_from_signature`, which its wasm32 builds leave out
+        let secret = ElGamalSecretKey::from_seed(&sha3_512(elgamal_signature.as_ref()))
+            .map_err(|e| anyhow!("Failed to generate ElGamal keypair: {e}"))?;
+        let keypair_bytes = [
+            <[u8; ELGAMAL_PUBKEY_LEN]>::from(ElGamalPubkey::new(&secret)).as_slice(),
+            secret.as_bytes(),
+        ]
+        .concat();
+        let elgamal_keypair = ElGamalKeypair::try_from(keypair_bytes.as_slice())
+            .map_err(|e| anyhow!("Failed to generate ElGamal keypair: {e}"))?;
+        let aes_seed = sha3_512(aes_signature.as_ref());
+        let aes_key = AeKey::try_from(&sha3_512(&aes_seed)[..AE_KEY_LEN])
+            .map_err(|e| anyhow!("Failed to generate AES key: {e}"))?;
+        Ok((elgamal_keypair, aes_key))
+    }
+
+    /// Derives the keys of `account` from signatures of its `owner` handed over as bytes, e.g. by
+    /// the `signMessage` of a browser wallet.
+    ///
+    /// Unlike [`keys_from_signatures`](Self::keys_from_signatures), each signature is first
+    /// verified against its message and `owner`, so keys cannot be derived from signatures of
+    /// another key or message.
+    pub fn keys_from_signed_messages(
+        &self,
+        owner: &Pubkey,
+        account: &Pubkey,
+        elgamal_signature: &Signature,
+        aes_signature: &Signature,
+    ) -> Result<(ElGamalKeypair, AeKey)> {
+        let [elgamal_message, aes_message] = self.messages(account);
+        for (key, message, signature) in [
+            ("ElGamal", elgamal_message, elgamal_signature),
+            ("AES", aes_message, aes_signature),
+        ] {
+            if !signature.verify(owner.as_ref(), &message) {
+                bail!("The {key} key signature is not {owner}'s signature of its seed message");
+            }
+        }
+        Self::keys_from_signatures(elgamal_signature, aes_signature)
+    }
+
+    /// Derives the keys of `account` from signatures of `signer`.
+    ///
+    /// Hardware wallets sign the messages as off-chain messages, so `confidential-transfer` calls
+    /// this through its `mint::derive_keys`.
+    pub fn derive(&self, signer: &dyn Signer, account: &Pubkey) -> Result<(ElGamalKeypair, AeKey)> {
+        let [elgamal_message, aes_message] = self.messages(account);
+        Self::keys_from_signatures(
+            &sign(signer, &elgamal_message)?,
+            &sign(signer, &aes_message)?,
+        )
+    }
+}
+
+impl FromStr for SeedMessage {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        match spec.split_once(':') {
+            None if spec == "v1" => Ok(SeedMessage::AccountAddress),
+            Some(("v2", template)) => {
+                if !template.contains("{key}") {
+                    bail!("Seed message template `{template}` lacks the {{key}} placeholder");
+                }
+                Ok(SeedMessage::Template(template.to_string()))
+            }
+            _ => bail!("Unknown seed message `{spec}`: expected `v1` or `v2:<TEMPLATE>`"),
+        }
+    }
+}
+
+impl fmt::Display for SeedMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SeedMessage::AccountAddress => write!(f, "v1"),
+            SeedMessage::Template(template) => write!(f, "v2:{template}"),
+        }
+    }
+}
+
+// Message of `template` for the key named `key` of `account`
+fn expand(template: &str, key: &str, account: &Pubkey) -> String {
+    template
+        .replace("{key}", key)
+        .replace("{account}", &account.to_string())
+}
+
+// SHA3-512 digest of `bytes`, the seed hash of the zk-sdk key derivation
+fn sha3_512(bytes: &[u8]) -> [u8; 64] {
+    Sha3_512::digest(bytes).into()
+}
+
+// Signs `message`, rejecting the default signature some signers return, which is no key material
+fn sign(signer: &dyn Signer, message: &[u8]) -> Result<Signature> {
+    let signature = signer.try_sign_message(message)?;
+    if signature == Signature::default() {
+        bail!("Rejecting the default signature of {}", signer.pubkey());
+    }
+    Ok(signature)
+}
@@ -0,0 +1,61 @@
+use anyhow::Result;
+use bytemuck::Pod;
+use spl_token_2022::{
+    solana_program::{
+        instruction::Instruction, pubkey::Pubkey, rent::Rent, system_instruction::create_account,
+    },
+    solana_zk_sdk::zk_elgamal_proof_program::{
+        self,
+        instruction::{ContextStateInfo, close_context_state},
+        proof_data::ZkProofData,
+        state::ProofContextState,
+    },
+};
+use spl_token_confidential_transfer_proof_extraction::instruction::zk_proof_type_to_instruction;
+
+/// Instructions creating the context state account `context_account`, its rent paid by
+/// `fee_payer`, then verifying `proof_data` into it, managed by `authority`.
+///
+/// Both fit in one transaction only for the smaller proofs; send them in separate ones when they
+/// do not.
+pub fn context_account_instructions<ZK: Pod + ZkProofData<U>, U: Pod>(
+    fee_payer: &Pubkey,
+    context_account: &Pubkey,
+    authority: &Pubkey,
+    proof_data: &ZK,
+    rent: &Rent,
+) -> Result<[Instruction; 2]> {
+    let space = size_of::<ProofContextState<U>>();
+    let verify = zk_proof_type_to_instruction(ZK::PROOF_TYPE)?.encode_verify_proof(
+        Some(ContextStateInfo {
+            context_state_account: context_account,
+            context_state_authority: authority,
+        }),
+        proof_data,
+    );
+    Ok([
+        create_account(
+            fee_payer,                       //Payer of the rent
+            context_account,                 //New context state account
+            rent.minimum_balance(space),     //Rent-exempt balance
+            space as u64,                    //Size of the verified proof context
+            &zk_elgamal_proof_program::id(), //Owner program
+        ),
+        verify,
+    ])
+}
+
+/// Instruction closing the context state account `context_account`, managed by `authority`,
+/// refunding its rent to `authority`.
+pub fn close_context_account_instruction(
+    context_account: &Pubkey,
+    authority: &Pubkey,
+) -> Instruction {
+    close_context_state(
+        ContextStateInfo {
+            context_state_account: context_account,
+            context_state_authority: authority,
+        },
+        authority,
+    )
+}
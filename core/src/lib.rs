@@ -0,0 +1,17 @@
+//! Pure building blocks of Token-2022 confidential transfers: proof generation, key derivation,
+//! decryptable balances and the instructions of proof context accounts.
+//!
+//! Nothing here talks to a cluster, so on-chain programs and Anchor clients depend on this crate
+//! without the RPC stack of `confidential-transfer`, which builds its operations on it.
+//!
+//! Without the default `token-2022` feature only the key derivation and balance decryption are
+//! left, which build for `wasm32-unknown-unknown`; the `wasm` feature exports them to JavaScript.
+
+pub mod balance;
+pub mod derivation;
+#[cfg(feature = "token-2022")]
+pub mod instructions;
+#[cfg(feature = "token-2022")]
+pub mod proofs;
+#[cfg(feature = "wasm")]
+pub mod wasm;
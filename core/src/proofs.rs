@@ -0,0 +1,346 @@
+use anyhow::{Result, anyhow, bail};
+use bytemuck::Pod;
+use spl_token_2022::{
+    extension::{
+        confidential_transfer::account_info::{TransferAccountInfo, WithdrawAccountInfo},
+        transfer_fee::TransferFee,
+    },
+    solana_zk_sdk::{
+        encryption::{
+            auth_encryption::AeKey,
+            elgamal::{ElGamal, ElGamalCiphertext, ElGamalKeypair, ElGamalPubkey},
+            grouped_elgamal::{GroupedElGamal, GroupedElGamalCiphertext},
+            pedersen::{Pedersen, PedersenOpening},
+            pod::{
+                auth_encryption::PodAeCiphertext, elgamal::PodElGamalCiphertext,
+                grouped_elgamal::PodGroupedElGamalCiphertext3Handles,
+            },
+        },
+        zk_elgamal_proof_program::proof_data::{
+            BatchedGroupedCiphertext3HandlesValidityProofData, BatchedRangeProofU64Data,
+            BatchedRangeProofU128Data, CiphertextCommitmentEqualityProofData,
+            ProofType as ZkProofType, PubkeyValidityProofData, ZkProofData,
+        },
+    },
+};
+use spl_token_confidential_transfer_proof_generation::{
+    CiphertextValidityProofWithAuditorCiphertext, REMAINING_BALANCE_BIT_LENGTH,
+    TRANSFER_AMOUNT_HI_BITS, TRANSFER_AMOUNT_LO_BITS, transfer::TransferProofData,
+    transfer_with_fee::TransferWithFeeProofData, try_combine_lo_hi_ciphertexts, try_split_u64,
+    withdraw::WithdrawProofData,
+};
+
+use crate::balance::decrypt_decryptable_balance;
+
+//Bit length padding the range proof of a transfer to 128 bits
+const RANGE_PROOF_PADDING_BIT_LENGTH: usize = 16;
+
+/// Generates the pubkey validity proof that configures an account for `elgamal_keypair`.
+pub fn pubkey_validity_proof(elgamal_keypair: &ElGamalKeypair) -> Result<PubkeyValidityProofData> {
+    PubkeyValidityProofData::new(elgamal_keypair)
+        .map_err(|_| anyhow!("Failed to generate pubkey validity proof data"))
+}
+
+/// Generates the equality and range proofs of withdrawing `amount` from an account whose
+/// available balance is `account`.
+///
+/// Both proofs are about the same commitment to the remaining balance, so they are generated in
+/// parallel once it is computed.
+pub fn withdraw_proofs(
+    account: &WithdrawAccountInfo,
+    amount: u64,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+) -> Result<WithdrawProofData> {
+    let remaining_balance =
+        check_amount(account.decryptable_available_balance, amount, aes_key)? - amount;
+    let (remaining_balance_commitment, remaining_balance_opening) =
+        Pedersen::new(remaining_balance);
+    //Remaining balance ciphertext, as the program computes it
+    let remaining_balance_ciphertext =
+        available_balance_ciphertext(account.available_balance)? - ElGamal::encode(amount);
+    let (equality_proof_data, range_proof_data) = rayon::join(
+        || {
+            CiphertextCommitmentEqualityProofData::new(
+                elgamal_keypair,
+                &remaining_balance_ciphertext,
+                &remaining_balance_commitment,
+                &remaining_balance_opening,
+                remaining_balance,
+            )
+        },
+        || {
+            BatchedRangeProofU64Data::new(
+                vec![&remaining_balance_commitment],
+                vec![remaining_balance],
+                vec![REMAINING_BALANCE_BIT_LENGTH],
+                vec![&remaining_balance_opening],
+            )
+        },
+    );
+    Ok(WithdrawProofData {
+        equality_proof_data: equality_proof_data?,
+        range_proof_data: range_proof_data?,
+    })
+}
+
+/// Proof data of a transfer.
+pub enum TransferProofs {
+    /// Equality, ciphertext validity and range proofs.
+    Plain(Box<TransferProofData>),
+    /// The same, plus the percentage-with-cap and fee ciphertext validity proofs of a mint with
+    /// transfer fees.
+    WithFee(Box<TransferWithFeeProofData>),
+}
+
+impl TransferProofs {
+    /// Verifies every proof with [`verify_proof`].
+    pub fn verify(&self) -> Result<()> {
+        match self {
+            TransferProofs::Plain(proof_data) => {
+                verify_proof(&proof_data.equality_proof_data)?;
+                verify_proof(
+                    &proof_data
+                        .ciphertext_validity_proof_data_with_ciphertext
+                        .proof_data,
+                )?;
+                verify_proof(&proof_data.range_proof_data)
+            }
+            TransferProofs::WithFee(proof_data) => {
+                verify_proof(&proof_data.equality_proof_data)?;
+                verify_proof(
+                    &proof_data
+                        .transfer_amount_ciphertext_validity_proof_data_with_ciphertext
+                        .proof_data,
+                )?;
+                verify_proof(&proof_data.range_proof_data)?;
+                verify_proof(&proof_data.percentage_with_cap_proof_data)?;
+                verify_proof(&proof_data.fee_ciphertext_validity_proof_data)
+            }
+        }
+    }
+}
+
+/// Generates the proofs of transferring `amount` from an account whose available balance is
+/// `account` to the holder of `destination_elgamal_pubkey`.
+///
+/// The amount is encrypted under the auditor pubkey as well, if any, and on mints with transfer
+/// fees the fee under the withdraw withheld authority pubkey of `transfer_fee`.
+#[allow(clippy::too_many_arguments)]
+pub fn transfer_proofs(
+    account: &TransferAccountInfo,
+    amount: u64,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+    destination_elgamal_pubkey: &ElGamalPubkey,
+    auditor_elgamal_pubkey: Option<&ElGamalPubkey>,
+    transfer_fee: Option<&TransferFeeParams>,
+) -> Result<TransferProofs> {
+    let available_balance = check_amount(account.decryptable_available_balance, amount, aes_key)?;
+    Ok(match transfer_fee {
+        None => TransferProofs::Plain(Box::new(plain_transfer_proofs(
+            account,
+            available_balance,
+            amount,
+            elgamal_keypair,
+            destination_elgamal_pubkey,
+            auditor_elgamal_pubkey,
+        )?)),
+        //The fee proofs build on each other, so they are generated in sequence
+        Some(fee_params) => TransferProofs::WithFee(Box::new(
+            account.generate_split_transfer_with_fee_proof_data(
+                amount,
+                elgamal_keypair,
+                aes_key,
+                destination_elgamal_pubkey,
+                auditor_elgamal_pubkey,
+                &fee_params.withdraw_withheld_authority_elgamal_pubkey,
+                u16::from(fee_params.transfer_fee.transfer_fee_basis_points),
+                u64::from(fee_params.transfer_fee.maximum_fee),
+            )?,
+        )),
+    })
+}
+
+// Proofs of a transfer without fee, the same as `TransferAccountInfo` generates but with the
+// equality, ciphertext validity and range proofs generated in parallel once the amount is
+// encrypted
+fn plain_transfer_proofs(
+    account: &TransferAccountInfo,
+    available_balance: u64,
+    amount: u64,
+    elgamal_keypair: &ElGamalKeypair,
+    destination_elgamal_pubkey: &ElGamalPubkey,
+    auditor_elgamal_pubkey: Option<&ElGamalPubkey>,
+) -> Result<TransferProofData> {
+    let default_auditor_elgamal_pubkey = ElGamalPubkey::default();
+    let auditor_elgamal_pubkey = auditor_elgamal_pubkey.unwrap_or(&default_auditor_elgamal_pubkey);
+    let pubkeys = [
+        elgamal_keypair.pubkey(),
+        destination_elgamal_pubkey,
+        auditor_elgamal_pubkey,
+    ];
+    //The amount is encrypted in two parts so recipients can decrypt it
+    let (amount_lo, amount_hi) = try_split_u64(amount, TRANSFER_AMOUNT_LO_BITS)
+        .ok_or_else(|| anyhow!("Invalid transfer amount {amount}"))?;
+    let opening_lo = PedersenOpening::new_rand();
+    let ciphertext_lo = GroupedElGamal::<3>::encrypt_with(pubkeys, amount_lo, &opening_lo);
+    let opening_hi = PedersenOpening::new_rand();
+    let ciphertext_hi = GroupedElGamal::<3>::encrypt_with(pubkeys, amount_hi, &opening_hi);
+    //Remaining balance ciphertext, as the program computes it from the source handles
+    let source_ciphertext = |ciphertext: &GroupedElGamalCiphertext<3>| {
+        ciphertext
+            .to_elgamal_ciphertext(0)
+            .map_err(|_| anyhow!("Invalid transfer amount ciphertext"))
+    };
+    let amount_ciphertext = try_combine_lo_hi_ciphertexts(
+        &source_ciphertext(&ciphertext_lo)?,
+        &source_ciphertext(&ciphertext_hi)?,
+        TRANSFER_AMOUNT_LO_BITS,
+    )
+    .ok_or_else(|| anyhow!("Invalid transfer amount ciphertext"))?;
+    let remaining_balance_ciphertext =
+        available_balance_ciphertext(account.available_balance)? - amount_ciphertext;
+    let remaining_balance = available_balance - amount;
+    let (remaining_balance_commitment, remaining_balance_opening) =
+        Pedersen::new(remaining_balance);
+    //The range proof bit lengths must add up to a power of two, padded with a commitment to 0
+    let (padding_commitment, padding_opening) = Pedersen::new(0_u64);
+
+    let (equality_proof_data, (ciphertext_validity_proof_data, range_proof_data)) = rayon::join(
+        || {
+            CiphertextCommitmentEqualityProofData::new(
+                elgamal_keypair,
+                &remaining_balance_ciphertext,
+                &remaining_balance_commitment,
+                &remaining_balance_opening,
+                remaining_balance,
+            )
+        },
+        || {
+            rayon::join(
+                || {
+                    BatchedGroupedCiphertext3HandlesValidityProofData::new(
+                        pubkeys[0],
+                        pubkeys[1],
+                        pubkeys[2],
+                        &ciphertext_lo,
+                        &ciphertext_hi,
+                        amount_lo,
+                        amount_hi,
+                        &opening_lo,
+                        &opening_hi,
+                    )
+                },
+                || {
+                    BatchedRangeProofU128Data::new(
+                        vec![
+                            &remaining_balance_commitment,
+                            &ciphertext_lo.commitment,
+                            &ciphertext_hi.commitment,
+                            &padding_commitment,
+                        ],
+                        vec![remaining_balance, amount_lo, amount_hi, 0],
+                        vec![
+                            REMAINING_BALANCE_BIT_LENGTH,
+                            TRANSFER_AMOUNT_LO_BITS,
+                            TRANSFER_AMOUNT_HI_BITS,
+                            RANGE_PROOF_PADDING_BIT_LENGTH,
+                        ],
+                        vec![
+                            &remaining_balance_opening,
+                            &opening_lo,
+                            &opening_hi,
+                            &padding_opening,
+                        ],
+                    )
+                },
+            )
+        },
+    );
+    let ciphertext_validity_proof_data = ciphertext_validity_proof_data?;
+    //The transfer instruction carries the auditor ciphertexts of the amount
+    let context = ciphertext_validity_proof_data.context_data();
+    let auditor_ciphertext = |ciphertext: &PodGroupedElGamalCiphertext3Handles| {
+        ciphertext
+            .try_extract_ciphertext(2)
+            .map_err(|_| anyhow!("Invalid transfer amount ciphertext"))
+    };
+    Ok(TransferProofData {
+        equality_proof_data: equality_proof_data?,
+        ciphertext_validity_proof_data_with_ciphertext:
+            CiphertextValidityProofWithAuditorCiphertext {
+                ciphertext_lo: auditor_ciphertext(&context.grouped_ciphertext_lo)?,
+                ciphertext_hi: auditor_ciphertext(&context.grouped_ciphertext_hi)?,
+                proof_data: ciphertext_validity_proof_data,
+            },
+        range_proof_data: range_proof_data?,
+    })
+}
+
+// Available balance ciphertext of an account, to compute the remaining balance from
+fn available_balance_ciphertext(
+    available_balance: PodElGamalCiphertext,
+) -> Result<ElGamalCiphertext> {
+    ElGamalCiphertext::try_from(available_balance)
+        .map_err(|_| anyhow!("Invalid available balance ciphertext"))
+}
+
+// Decrypted available balance, failing with the reason when `amount` cannot be taken from it: an
+// AES key that does not decrypt it, or an amount above it
+fn check_amount(
+    decryptable_available_balance: PodAeCiphertext,
+    amount: u64,
+    aes_key: &AeKey,
+) -> Result<u64> {
+    let available_balance = decrypt_decryptable_balance(decryptable_available_balance, aes_key)
+        .ok_or_else(|| {
+            anyhow!("The AES key does not decrypt the available balance; wrong owner or keys?")
+        })?;
+    if amount > available_balance {
+        bail!("Amount {amount} exceeds the available balance of {available_balance}");
+    }
+    Ok(available_balance)
+}
+
+/// Verifies `proof_data` with the local ZK ElGamal verifier, which runs the checks of the proof
+/// program, so a proof that would fail on chain is caught before any fee or rent is spent.
+///
+/// Proofs generated by this crate verify unless their inputs were inconsistent; the error names
+/// the likely cause for each proof type.
+pub fn verify_proof<ZK: Pod + ZkProofData<U>, U: Pod>(proof_data: &ZK) -> Result<()> {
+    proof_data.verify_proof().map_err(|err| {
+        let cause = match ZK::PROOF_TYPE {
+            ZkProofType::PubkeyValidity => "the ElGamal keypair is invalid",
+            ZkProofType::CiphertextCommitmentEquality => {
+                "the keys do not match the available balance ciphertext, or the balance is stale"
+            }
+            ZkProofType::BatchedRangeProofU64
+            | ZkProofType::BatchedRangeProofU128
+            | ZkProofType::BatchedRangeProofU256 => {
+                "the amount or remaining balance is out of range; the amount may exceed the balance"
+            }
+            ZkProofType::BatchedGroupedCiphertext3HandlesValidity => {
+                "the amount ciphertexts do not match the source, recipient and auditor pubkeys"
+            }
+            ZkProofType::BatchedGroupedCiphertext2HandlesValidity => {
+                "the fee ciphertexts do not match the recipient and withheld fee pubkeys"
+            }
+            ZkProofType::PercentageWithCap => "the fee does not match the mint's fee rate and cap",
+            _ => "the proof data is inconsistent",
+        };
+        anyhow!(
+            "{:?} proof does not verify ({err}): {cause}",
+            ZK::PROOF_TYPE
+        )
+    })
+}
+
+/// Transfer fee of a mint with the `TransferFeeConfig` extension.
+#[derive(Debug, Clone, Copy)]
+pub struct TransferFeeParams {
+    /// Fee in effect for the current epoch.
+    pub transfer_fee: TransferFee,
+    /// ElGamal pubkey the withheld fees are encrypted to, from `ConfidentialTransferFeeConfig`.
+    pub withdraw_withheld_authority_elgamal_pubkey: ElGamalPubkey,
+}
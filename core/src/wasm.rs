@@ -0,0 +1,167 @@
+//! JavaScript exports of the key derivation and balance decryption, so a web wallet derives the
+//! same keys as `confidential-transfer` and reads its balances in the browser.
+//!
+//! Keys, addresses, signatures and ciphertexts cross the boundary as bytes: 32-byte addresses,
+//! 64-byte signatures, the 32-byte ElGamal secret key and the 16-byte AES key. Proof generation is
+//! not exported, as it does not build for wasm32 yet.
+
+use solana_pubkey::Pubkey;
+use solana_signature::Signature;
+use solana_zk_sdk::encryption::{
+    AE_CIPHERTEXT_LEN, AE_KEY_LEN, ELGAMAL_PUBKEY_LEN,
+    auth_encryption::AeKey,
+    elgamal::{ElGamalCiphertext, ElGamalKeypair, ElGamalSecretKey},
+    pod::auth_encryption::PodAeCiphertext,
+};
+use std::fmt::Display;
+use wasm_bindgen::prelude::*;
+
+use crate::{balance::decrypt_decryptable_balance, derivation::SeedMessage};
+
+//Bit length of the low ciphertext of a pending balance
+const PENDING_BALANCE_LO_BIT_LENGTH: u32 = 16;
+
+/// ElGamal keypair and AES key of a token account.
+#[wasm_bindgen]
+pub struct DerivedKeys {
+    elgamal_keypair: ElGamalKeypair,
+    aes_key: AeKey,
+}
+
+#[wasm_bindgen]
+impl DerivedKeys {
+    /// The 32-byte ElGamal pubkey, as registered in the account.
+    #[wasm_bindgen(getter, js_name = elgamalPubkey)]
+    pub fn elgamal_pubkey(&self) -> Vec<u8> {
+        <[u8; ELGAMAL_PUBKEY_LEN]>::from(*self.elgamal_keypair.pubkey()).to_vec()
+    }
+
+    /// The 32-byte ElGamal secret key.
+    #[wasm_bindgen(getter, js_name = elgamalSecretKey)]
+    pub fn elgamal_secret_key(&self) -> Vec<u8> {
+        self.elgamal_keypair.secret().as_bytes().to_vec()
+    }
+
+    /// The 16-byte AES key.
+    #[wasm_bindgen(getter, js_name = aesKey)]
+    pub fn aes_key(&self) -> Vec<u8> {
+        <[u8; AE_KEY_LEN]>::from(self.aes_key.clone()).to_vec()
+    }
+}
+
+/// Message signed to derive the ElGamal keypair of `account` under the seed message `spec`,
+/// `v1` or `v2:<TEMPLATE>`.
+#[wasm_bindgen(js_name = elgamalSeedMessage)]
+pub fn elgamal_seed_message(spec: &str, account: &[u8]) -> Result<Vec<u8>, JsError> {
+    let [elgamal_message, _] = messages(spec, account)?;
+    Ok(elgamal_message)
+}
+
+/// Message signed to derive the AES key of `account` under the seed message `spec`.
+#[wasm_bindgen(js_name = aesSeedMessage)]
+pub fn aes_seed_message(spec: &str, account: &[u8]) -> Result<Vec<u8>, JsError> {
+    let [_, aes_message] = messages(spec, account)?;
+    Ok(aes_message)
+}
+
+/// Derives the keys from the signatures of the two seed messages, as
+/// [`SeedMessage::keys_from_signatures`].
+#[wasm_bindgen(js_name = keysFromSignatures)]
+pub fn keys_from_signatures(
+    elgamal_signature: &[u8],
+    aes_signature: &[u8],
+) -> Result<DerivedKeys, JsError> {
+    let (elgamal_keypair, aes_key) = SeedMessage::keys_from_signatures(
+        &signature(elgamal_signature)?,
+        &signature(aes_signature)?,
+    )
+    .map_err(js_error)?;
+    Ok(DerivedKeys {
+        elgamal_keypair,
+        aes_key,
+    })
+}
+
+/// Derives the keys of `account` after checking both signatures are `owner`'s signatures of the
+/// seed messages of `spec`, as [`SeedMessage::keys_from_signed_messages`].
+#[wasm_bindgen(js_name = keysFromSignedMessages)]
+pub fn keys_from_signed_messages(
+    spec: &str,
+    owner: &[u8],
+    account: &[u8],
+    elgamal_signature: &[u8],
+    aes_signature: &[u8],
+) -> Result<DerivedKeys, JsError> {
+    let (elgamal_keypair, aes_key) = seed_message(spec)?
+        .keys_from_signed_messages(
+            &pubkey(owner)?,
+            &pubkey(account)?,
+            &signature(elgamal_signature)?,
+            &signature(aes_signature)?,
+        )
+        .map_err(js_error)?;
+    Ok(DerivedKeys {
+        elgamal_keypair,
+        aes_key,
+    })
+}
+
+/// Decrypts the 36-byte decryptable available balance of an account with its AES key; undefined
+/// if the key does not decrypt it.
+#[wasm_bindgen(js_name = decryptAvailableBalance)]
+pub fn decrypt_available_balance(
+    aes_key: &[u8],
+    decryptable_available_balance: &[u8],
+) -> Result<Option<u64>, JsError> {
+    let aes_key = AeKey::try_from(aes_key).map_err(|_| JsError::new("Invalid AES key"))?;
+    let ciphertext = <[u8; AE_CIPHERTEXT_LEN]>::try_from(decryptable_available_balance)
+        .map(PodAeCiphertext::from)
+        .map_err(|_| JsError::new("Invalid decryptable balance"))?;
+    Ok(decrypt_decryptable_balance(ciphertext, &aes_key))
+}
+
+/// Decrypts the pending balance of an account from its two 64-byte ciphertexts with its ElGamal
+/// secret key; undefined if either holds more than 32 bits, until the balance is applied.
+///
+/// Without the decode table of `confidential-transfer`, each discrete log is brute-forced, so this
+/// takes seconds for large pending balances.
+#[wasm_bindgen(js_name = decryptPendingBalance)]
+pub fn decrypt_pending_balance(
+    elgamal_secret_key: &[u8],
+    pending_balance_lo: &[u8],
+    pending_balance_hi: &[u8],
+) -> Result<Option<u64>, JsError> {
+    let secret = ElGamalSecretKey::try_from(elgamal_secret_key)
+        .map_err(|_| JsError::new("Invalid ElGamal secret key"))?;
+    let [lo, hi] = [pending_balance_lo, pending_balance_hi].map(|bytes| {
+        ElGamalCiphertext::from_bytes(bytes).map(|ciphertext| secret.decrypt_u32(&ciphertext))
+    });
+    let (Some(lo), Some(hi)) = (lo, hi) else {
+        return Err(JsError::new("Invalid pending balance ciphertext"));
+    };
+    Ok(lo.zip(hi).and_then(|(lo, hi)| {
+        hi.checked_shl(PENDING_BALANCE_LO_BIT_LENGTH)?
+            .checked_add(lo)
+    }))
+}
+
+// Seed messages of `account` under `spec`
+fn messages(spec: &str, account: &[u8]) -> Result<[Vec<u8>; 2], JsError> {
+    Ok(seed_message(spec)?.messages(&pubkey(account)?))
+}
+
+fn seed_message(spec: &str) -> Result<SeedMessage, JsError> {
+    spec.parse().map_err(js_error)
+}
+
+fn pubkey(bytes: &[u8]) -> Result<Pubkey, JsError> {
+    Pubkey::try_from(bytes).map_err(|_| JsError::new("Expected a 32-byte address"))
+}
+
+fn signature(bytes: &[u8]) -> Result<Signature, JsError> {
+    Signature::try_from(bytes).map_err(|_| JsError::new("Expected a 64-byte signature"))
+}
+
+fn js_error(error: impl Display) -> JsError {
+    JsError::new(&error.to_string())
+}
@@ -0,0 +1,104 @@
+/*
+ * C ABI of the `confidential-transfer` crate, built with the `ffi` feature:
+ *
+ *     cargo rustc --release --lib --features ffi --crate-type cdylib
+ *
+ * Every function that can fail returns a CtStatus; on failure, ct_last_error() holds the
+ * message until the next failing call on the same thread. Handles and buffers returned by the
+ * library are freed with the matching ct_*_free function. A handle must not be used from two
+ * threads at once.
+ */
+#ifndef CONFIDENTIAL_TRANSFER_H
+#define CONFIDENTIAL_TRANSFER_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum CtStatus {
+    CT_STATUS_OK = 0,
+    CT_STATUS_INVALID_ARGUMENT = 1,
+    CT_STATUS_FAILED = 2,
+    CT_STATUS_PANICKED = 3,
+} CtStatus;
+
+/* Connection to a cluster and the keypair that pays the fees and owns the accounts. */
+typedef struct CtClient CtClient;
+/* ElGamal keypair and AES key of a token account. */
+typedef struct CtKeys CtKeys;
+/* Decode table solving the discrete logs of pending balances. */
+typedef struct CtDecodeTable CtDecodeTable;
+
+typedef struct CtBytes {
+    uint8_t *data;
+    size_t len;
+} CtBytes;
+
+typedef struct CtBalances {
+    uint64_t public_balance;
+    uint64_t pending_balance;
+    uint64_t available_balance;
+} CtBalances;
+
+typedef struct CtWithdrawProofs {
+    CtBytes equality;
+    CtBytes range;
+    uint8_t new_decryptable_available_balance[36];
+} CtWithdrawProofs;
+
+typedef struct CtTransferProofs {
+    CtBytes equality;
+    CtBytes ciphertext_validity;
+    CtBytes range;
+    uint8_t auditor_ciphertext_lo[64];
+    uint8_t auditor_ciphertext_hi[64];
+    uint8_t new_decryptable_available_balance[36];
+} CtTransferProofs;
+
+const char *ct_last_error(void);
+
+/* Any string argument may be NULL to use the config file, or its default. */
+CtStatus ct_client_new(const char *config_path, const char *rpc_url, const char *keypair_path,
+                       CtClient **out);
+void ct_client_free(CtClient *client);
+
+/* *out_signature is NULL when the account was already configured. */
+CtStatus ct_configure_account(const CtClient *client, const char *mint, CtKeys **out_keys,
+                              char **out_ata, char **out_signature);
+
+CtStatus ct_keys_derive(const CtClient *client, const char *mint, CtKeys **out);
+CtStatus ct_keys_from_bytes(const uint8_t elgamal_secret_key[32], const uint8_t aes_key[16],
+                            CtKeys **out);
+CtStatus ct_keys_to_bytes(const CtKeys *keys, uint8_t out_elgamal_secret_key[32],
+                          uint8_t out_aes_key[16]);
+CtStatus ct_keys_elgamal_pubkey(const CtKeys *keys, char **out);
+void ct_keys_free(CtKeys *keys);
+
+/* A NULL path opens the default table in the cache directory. */
+CtStatus ct_decode_table_open(const char *path, CtDecodeTable **out);
+void ct_decode_table_free(CtDecodeTable *decode_table);
+
+CtStatus ct_decrypt_balances(const CtKeys *keys, const CtDecodeTable *decode_table,
+                             const uint8_t *account_data, size_t account_len, CtBalances *out);
+
+CtStatus ct_pubkey_validity_proof(const CtKeys *keys, CtBytes *out);
+CtStatus ct_withdraw_proofs(const CtKeys *keys, const uint8_t *account_data, size_t account_len,
+                            uint64_t amount, CtWithdrawProofs *out);
+void ct_withdraw_proofs_free(CtWithdrawProofs *proofs);
+/* A NULL auditor_elgamal_pubkey is for mints without an auditor. */
+CtStatus ct_transfer_proofs(const CtKeys *keys, const uint8_t *account_data, size_t account_len,
+                            uint64_t amount, const char *destination_elgamal_pubkey,
+                            const char *auditor_elgamal_pubkey, CtTransferProofs *out);
+void ct_transfer_proofs_free(CtTransferProofs *proofs);
+
+void ct_bytes_free(CtBytes bytes);
+void ct_string_free(char *string);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
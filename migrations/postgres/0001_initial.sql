@@ -0,0 +1,44 @@
+-- Records of the `storage` feature, as in the SQLite schema of src/storage.rs; amounts and Unix
+-- times are BIGINT, signatures arrays of base58 strings.
+CREATE TABLE mints (
+    address TEXT PRIMARY KEY,
+    authority TEXT NOT NULL,
+    decimals SMALLINT NOT NULL,
+    signature TEXT NOT NULL,
+    created_at BIGINT NOT NULL
+);
+
+CREATE TABLE accounts (
+    address TEXT PRIMARY KEY,
+    owner TEXT NOT NULL,
+    mint TEXT NOT NULL,
+    elgamal_pubkey TEXT NOT NULL,
+    signature TEXT,
+    configured_at BIGINT NOT NULL
+);
+CREATE INDEX accounts_owner ON accounts (owner);
+
+CREATE TABLE operations (
+    id BIGSERIAL PRIMARY KEY,
+    kind TEXT NOT NULL,
+    owner TEXT NOT NULL,
+    mint TEXT NOT NULL,
+    account TEXT NOT NULL,
+    counterparty TEXT,
+    amount BIGINT,
+    signatures TEXT[] NOT NULL,
+    recorded_at BIGINT NOT NULL
+);
+CREATE INDEX operations_owner ON operations (owner, mint);
+
+CREATE TABLE balances (
+    id BIGSERIAL PRIMARY KEY,
+    account TEXT NOT NULL,
+    mint TEXT NOT NULL,
+    public BIGINT NOT NULL,
+    pending BIGINT NOT NULL,
+    available BIGINT NOT NULL,
+    pending_credits BIGINT NOT NULL,
+    recorded_at BIGINT NOT NULL
+);
+CREATE INDEX balances_account ON balances (account);
@@ -0,0 +1,23 @@
+-- Confidential transfer events of `index`, as in the SQLite schema of src/storage.rs, and the
+-- cursor of every address walked.
+CREATE TABLE events (
+    id BIGSERIAL PRIMARY KEY,
+    signature TEXT NOT NULL,
+    instruction_index INTEGER NOT NULL,
+    slot BIGINT NOT NULL,
+    block_time BIGINT,
+    kind TEXT NOT NULL,
+    mint TEXT NOT NULL,
+    account TEXT NOT NULL,
+    counterparty TEXT,
+    amount BIGINT,
+    UNIQUE (signature, instruction_index)
+);
+CREATE INDEX events_mint ON events (mint, slot);
+CREATE INDEX events_account ON events (account, slot);
+
+CREATE TABLE index_cursors (
+    address TEXT PRIMARY KEY,
+    signature TEXT NOT NULL,
+    indexed_at BIGINT NOT NULL
+);
@@ -0,0 +1,391 @@
+//! `confidential_transfer` Python module: the mint, configure, deposit, transfer, withdraw and
+//! balance operations of the library for scripts, each run to completion on a Tokio runtime.
+//!
+//! Addresses and ElGamal pubkeys are passed and returned as strings, amounts as base units, and
+//! results as dicts shaped like the responses of `serve`.
+
+use anyhow::{Result, anyhow};
+use confidential_transfer::{
+    TokenClient,
+    authority::Authority,
+    balance,
+    client::{self, ComputeBudget, RpcSender},
+    config::Config,
+    decode_table::DecodeTable,
+    deposit,
+    mint::{self, MintOptions, TOKEN_DECIMALS},
+    placement::Packing,
+    retry, transfer,
+    utils::parse_elgamal_pubkey,
+    withdraw,
+};
+use pyo3::{
+    create_exception,
+    exceptions::{PyException, PyValueError},
+    prelude::*,
+    types::PyDict,
+};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Signature, read_keypair_file},
+    signer::Signer,
+};
+use spl_token_client::{
+    client::ProgramClient,
+    spl_token_2022::solana_zk_sdk::encryption::{auth_encryption::AeKey, elgamal::ElGamalKeypair},
+};
+use std::{cell::OnceCell, path::PathBuf, str::FromStr, sync::Arc};
+use tokio::runtime::{Builder, Runtime};
+
+create_exception!(
+    confidential_transfer,
+    ConfidentialTransferError,
+    PyException,
+    "An operation failed; the message carries the whole chain of causes."
+);
+
+/// Connection to a cluster and the keypair that pays the fees, owns the accounts and has the
+/// mint authority of the mints it creates.
+///
+/// `config` is the config file of the CLI, the default one when not given; `rpc_url` and
+/// `keypair` override its RPC URL and keypair path. Account keys are derived from the keypair
+/// with the seed message of the config, so the CLI operates on the same accounts.
+#[pyclass(unsendable)]
+struct Client {
+    runtime: Runtime,
+    program_client: Arc<dyn ProgramClient<RpcSender>>,
+    keypair: Arc<dyn Signer>,
+    owner: Authority,
+    compute_budget: ComputeBudget,
+    //Opened on the first balance read, which builds it into the cache directory if missing
+    decode_table: OnceCell<DecodeTable>,
+}
+
+#[pymethods]
+impl Client {
+    #[new]
+    #[pyo3(signature = (config = None, rpc_url = None, keypair = None))]
+    fn new(
+        config: Option<PathBuf>,
+        rpc_url: Option<String>,
+        keypair: Option<PathBuf>,
+    ) -> PyResult<Self> {
+        Self::connect(config, rpc_url, keypair).map_err(error)
+    }
+
+    /// Address of the keypair.
+    #[getter]
+    fn pubkey(&self) -> String {
+        self.keypair.pubkey().to_string()
+    }
+
+    /// Creates a confidential transfer mint, auditable by the holder of
+    /// `auditor_elgamal_pubkey` if given; returns `mint` and `signature`.
+    #[pyo3(signature = (decimals = TOKEN_DECIMALS, auditor_elgamal_pubkey = None))]
+    fn create_mint<'py>(
+        &self,
+        py: Python<'py>,
+        decimals: u8,
+        auditor_elgamal_pubkey: Option<&str>,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let options = MintOptions {
+            decimals,
+            auditor_elgamal_pubkey: auditor_elgamal_pubkey
+                .map(parse_elgamal_pubkey)
+                .transpose()
+                .map_err(|err| PyValueError::new_err(err.to_string()))?,
+            ..MintOptions::default()
+        };
+        let created = self.run(mint::initialize_mint(
+            self.program_client.clone(),
+            self.keypair.clone(),
+            self.keypair.as_ref(),
+            &options,
+            &self.compute_budget,
+        ))?;
+        let dict = PyDict::new(py);
+        dict.set_item("mint", created.mint_keypair.pubkey().to_string())?;
+        dict.set_item("signature", created.signature.to_string())?;
+        Ok(dict)
+    }
+
+    /// Creates and configures the associated token account of the keypair for `mint`; returns
+    /// `ata`, `elgamal_pubkey` and `signature`, `None` if it was already configured.
+    fn configure_account<'py>(&self, py: Python<'py>, mint: &str) -> PyResult<Bound<'py, PyDict>> {
+        let mint = pubkey(mint)?;
+        let configured = self.run(async {
+            let (token, _decimals) = self.token(&mint).await?;
+            mint::create_configure_ata(&token, &self.keypair.pubkey(), &self.owner).await
+        })?;
+        let dict = PyDict::new(py);
+        dict.set_item("ata", configured.ata.to_string())?;
+        dict.set_item(
+            "elgamal_pubkey",
+            configured.elgamal_keypair.pubkey().to_string(),
+        )?;
+        dict.set_item("signature", configured.signature.map(|s| s.to_string()))?;
+        Ok(dict)
+    }
+
+    /// Mints `amount` public tokens of a mint created by the keypair to its configured account;
+    /// returns the signature.
+    fn mint_to(&self, mint: &str, amount: u64) -> PyResult<String> {
+        let mint = pubkey(mint)?;
+        let signature = self.run(async {
+            let (token, _decimals) = self.token(&mint).await?;
+            mint::mint_to(&token, self.keypair.clone(), &self.ata(&mint), amount).await
+        })?;
+        Ok(signature.to_string())
+    }
+
+    /// Deposits `amount` public tokens into the pending balance, applied to the available
+    /// balance in the same transaction unless `apply` is false; returns the signature.
+    #[pyo3(signature = (mint, amount, apply = true, memo = None))]
+    fn deposit(
+        &self,
+        mint: &str,
+        amount: u64,
+        apply: bool,
+        memo: Option<&str>,
+    ) -> PyResult<String> {
+        let mint = pubkey(mint)?;
+        let ata = self.ata(&mint);
+        let signature = self.run(async {
+            let (token, decimals) = self.token(&mint).await?;
+            if !apply {
+                return deposit::deposit(&token, &self.owner, &ata, amount, decimals, memo).await;
+            }
+            let (elgamal_keypair, aes_key) = self.keys(&token, &ata).await?;
+            deposit::deposit_and_apply(
+                &token,
+                &self.owner,
+                &ata,
+                amount,
+                decimals,
+                &elgamal_keypair,
+                &aes_key,
+                memo,
+            )
+            .await
+        })?;
+        Ok(signature.to_string())
+    }
+
+    /// Applies the pending balance, e.g. incoming transfers, to the available balance; returns
+    /// the signature.
+    fn apply_pending_balance(&self, mint: &str) -> PyResult<String> {
+        let mint = pubkey(mint)?;
+        let ata = self.ata(&mint);
+        let signature = self.run(async {
+            let (token, _decimals) = self.token(&mint).await?;
+            let (elgamal_keypair, aes_key) = self.keys(&token, &ata).await?;
+            deposit::apply_pending_balance(&token, &self.owner, &ata, &elgamal_keypair, &aes_key)
+                .await
+        })?;
+        Ok(signature.to_string())
+    }
+
+    /// Transfers `amount` confidentially to the associated token account of `recipient`; returns
+    /// `destination`, `amount`, `fee` and the `signatures` of the proof accounts, the transfer
+    /// and the closing of the proof accounts.
+    #[pyo3(signature = (mint, recipient, amount, memo = None))]
+    fn transfer<'py>(
+        &self,
+        py: Python<'py>,
+        mint: &str,
+        recipient: &str,
+        amount: u64,
+        memo: Option<&str>,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let (mint, recipient) = (pubkey(mint)?, pubkey(recipient)?);
+        let (source, destination) = (self.ata(&mint), mint::get_ata(&recipient, &mint));
+        let result = self.run(async {
+            let (token, _decimals) = self.token(&mint).await?;
+            let (elgamal_keypair, aes_key) = self.keys(&token, &source).await?;
+            transfer::transfer(
+                &token,
+                &self.owner,
+                &source,
+                &destination,
+                amount,
+                &elgamal_keypair,
+                &aes_key,
+                memo,
+                Packing::Separate,
+            )
+            .await
+        })?;
+        let signatures = PyDict::new(py);
+        signatures.set_item("proof_accounts", strings(&result.proof_signatures))?;
+        signatures.set_item("transfer", result.transfer_signature.to_string())?;
+        signatures.set_item("close", strings(&result.close_signatures))?;
+        let dict = PyDict::new(py);
+        dict.set_item("destination", destination.to_string())?;
+        dict.set_item("amount", amount)?;
+        dict.set_item("fee", result.fee)?;
+        dict.set_item("signatures", signatures)?;
+        Ok(dict)
+    }
+
+    /// Withdraws `amount` from the available balance to the public balance, or all of it when
+    /// `amount` is `None`; returns `amount` and the signature of the `withdraw`.
+    #[pyo3(signature = (mint, amount = None, memo = None))]
+    fn withdraw<'py>(
+        &self,
+        py: Python<'py>,
+        mint: &str,
+        amount: Option<u64>,
+        memo: Option<&str>,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let mint = pubkey(mint)?;
+        let ata = self.ata(&mint);
+        let result = self.run(async {
+            let (token, decimals) = self.token(&mint).await?;
+            let (elgamal_keypair, aes_key) = self.keys(&token, &ata).await?;
+            match amount {
+                Some(amount) => {
+                    withdraw::withdraw(
+                        &token,
+                        &self.owner,
+                        &ata,
+                        amount,
+                        decimals,
+                        &elgamal_keypair,
+                        &aes_key,
+                        memo,
+                        Packing::Separate,
+                    )
+                    .await
+                }
+                None => {
+                    withdraw::withdraw_all(
+                        &token,
+                        &self.owner,
+                        &ata,
+                        decimals,
+                        &elgamal_keypair,
+                        &aes_key,
+                        memo,
+                        Packing::Separate,
+                    )
+                    .await
+                }
+            }
+        })?;
+        let dict = PyDict::new(py);
+        dict.set_item("amount", result.amount)?;
+        dict.set_item("withdraw", result.withdraw_signature.to_string())?;
+        Ok(dict)
+    }
+
+    /// Reads and decrypts the balances of the keypair's account of `mint`; returns `ata`,
+    /// `decimals`, `public`, `pending` and `available`, in base units.
+    fn balance<'py>(&self, py: Python<'py>, mint: &str) -> PyResult<Bound<'py, PyDict>> {
+        let mint = pubkey(mint)?;
+        let ata = self.ata(&mint);
+        let decode_table = match self.decode_table.get() {
+            Some(decode_table) => decode_table,
+            None => {
+                let decode_table = DecodeTable::open_default().map_err(error)?;
+                self.decode_table.get_or_init(|| decode_table)
+            }
+        };
+        let (decimals, balances) = self.run(async {
+            let (token, decimals) = self.token(&mint).await?;
+            let (elgamal_keypair, aes_key) = self.keys(&token, &ata).await?;
+            let balances =
+                balance::get_balances(&token, &ata, &elgamal_keypair, &aes_key, decode_table)
+                    .await?;
+            Ok((decimals, balances))
+        })?;
+        let dict = PyDict::new(py);
+        dict.set_item("ata", ata.to_string())?;
+        dict.set_item("decimals", decimals)?;
+        dict.set_item("public", balances.public)?;
+        dict.set_item("pending", balances.pending)?;
+        dict.set_item("available", balances.available)?;
+        Ok(dict)
+    }
+}
+
+impl Client {
+    fn connect(
+        config: Option<PathBuf>,
+        rpc_url: Option<String>,
+        keypair: Option<PathBuf>,
+    ) -> Result<Self> {
+        let mut config = Config::load(config.as_deref())?;
+        config.rpc_url = rpc_url.or(config.rpc_url);
+        config.keypair_path = keypair.or(config.keypair_path);
+        let keypair_path = config.keypair_path()?;
+        let keypair = read_keypair_file(&keypair_path)
+            .map_err(|err| anyhow!("Unable to read keypair {}: {err}", keypair_path.display()))?;
+        let keypair: Arc<dyn Signer> = Arc::new(keypair);
+        let runtime = Builder::new_multi_thread().enable_all().build()?;
+        let _runtime = runtime.enter();
+        let rpc_client = Arc::new(retry::rpc_client(
+            config.rpc_url(),
+            config.commitment()?,
+            config.retry_policy()?,
+            config.rate_limit()?,
+        ));
+        //No websocket subscription outlives a call, so confirmations are polled
+        let program_client = client::program_client(rpc_client, RpcSender::new(false));
+        Ok(Self {
+            program_client,
+            owner: Authority::single(keypair.clone()).with_seed_message(config.seed_message()?),
+            keypair,
+            compute_budget: ComputeBudget::default(),
+            decode_table: OnceCell::new(),
+            runtime,
+        })
+    }
+
+    // Runs `operation` to completion, raising its error as `ConfidentialTransferError`
+    fn run<T>(&self, operation: impl Future<Output = Result<T>>) -> PyResult<T> {
+        self.runtime.block_on(operation).map_err(error)
+    }
+
+    async fn token(&self, mint: &Pubkey) -> Result<(TokenClient, u8)> {
+        mint::load_token(
+            self.program_client.clone(),
+            self.keypair.clone(),
+            mint,
+            &self.compute_budget,
+        )
+        .await
+    }
+
+    fn ata(&self, mint: &Pubkey) -> Pubkey {
+        mint::get_ata(&self.keypair.pubkey(), mint)
+    }
+
+    // Keys of `ata` derived from the keypair, checked against its ElGamal pubkey
+    async fn keys(&self, token: &TokenClient, ata: &Pubkey) -> Result<(ElGamalKeypair, AeKey)> {
+        mint::load_keys(token, self.keypair.as_ref(), self.owner.seed_message(), ata).await
+    }
+}
+
+fn error(err: anyhow::Error) -> PyErr {
+    ConfidentialTransferError::new_err(format!("{err:#}"))
+}
+
+fn pubkey(address: &str) -> PyResult<Pubkey> {
+    Pubkey::from_str(address)
+        .map_err(|_| PyValueError::new_err(format!("Invalid address {address}")))
+}
+
+fn strings(signatures: &[Signature]) -> Vec<String> {
+    signatures.iter().map(ToString::to_string).collect()
+}
+
+#[pymodule]
+#[pyo3(name = "confidential_transfer")]
+fn module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Client>()?;
+    m.add(
+        "ConfidentialTransferError",
+        m.py().get_type::<ConfidentialTransferError>(),
+    )?;
+    Ok(())
+}
@@ -0,0 +1,134 @@
+use anyhow::{Result, anyhow, bail};
+use std::{fmt, str::FromStr};
+
+//Suffix of amounts given in base units
+const RAW_SUFFIX: &str = "raw";
+
+/// Token amount given on the command line, resolved to base units against the decimals of its
+/// mint by [`Amount::to_base_units`].
+///
+/// Amounts are in tokens, e.g. `12.5`, or in base units with a `raw` suffix, e.g.
+/// `12_500_000 raw`. Underscores may separate digits in both.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Amount {
+    /// Amount in tokens: its whole and fractional digits, without separators.
+    Tokens {
+        /// Digits before the decimal point, possibly empty.
+        whole: String,
+        /// Digits after the decimal point, possibly empty.
+        fraction: String,
+    },
+    /// Amount in base units.
+    Raw(u64),
+}
+
+impl Amount {
+    /// Amount of `tokens` whole tokens.
+    pub fn whole(tokens: u64) -> Self {
+        Amount::Tokens {
+            whole: tokens.to_string(),
+            fraction: String::new(),
+        }
+    }
+
+    /// Whether the amount is zero, whatever the decimals of its mint.
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Amount::Tokens { whole, fraction } => whole
+                .bytes()
+                .chain(fraction.bytes())
+                .all(|digit| digit == b'0'),
+            Amount::Raw(base_units) => *base_units == 0,
+        }
+    }
+
+    /// Base units of the amount for a mint with `decimals` decimals.
+    ///
+    /// Fails when a token amount has more significant fractional digits than the mint has
+    /// decimals, or when it does not fit in a `u64`.
+    pub fn to_base_units(&self, decimals: u8) -> Result<u64> {
+        let (whole, fraction) = match self {
+            Amount::Raw(base_units) => return Ok(*base_units),
+            Amount::Tokens { whole, fraction } => (whole, fraction),
+        };
+        let places = usize::from(decimals);
+        let (kept, dropped) = fraction.split_at(fraction.len().min(places));
+        if dropped.bytes().any(|digit| digit != b'0') {
+            bail!("Amount {self} is finer than the {decimals} decimals of the mint");
+        }
+        //Base units are the digits with the fraction padded to the decimals of the mint
+        let padding = std::iter::repeat_n(b'0', places - kept.len());
+        whole
+            .bytes()
+            .chain(kept.bytes())
+            .chain(padding)
+            .try_fold(0u64, |base_units, digit| {
+                base_units
+                    .checked_mul(10)?
+                    .checked_add(u64::from(digit - b'0'))
+            })
+            .ok_or_else(|| {
+                anyhow!(
+                    "Amount {self} is above {}, the largest amount of a mint with {decimals} decimals",
+                    format(u64::MAX, decimals)
+                )
+            })
+    }
+}
+
+impl FromStr for Amount {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let value = value.trim();
+        if let Some(base_units) = value.strip_suffix(RAW_SUFFIX) {
+            let base_units = digits(base_units.trim_end(), value)?;
+            if base_units.is_empty() {
+                bail!("Invalid amount: {value}");
+            }
+            return base_units
+                .parse()
+                .map(Amount::Raw)
+                .map_err(|_| anyhow!("Amount {value} does not fit in 64 bits"));
+        }
+        let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+        let (whole, fraction) = (digits(whole, value)?, digits(fraction, value)?);
+        if whole.is_empty() && fraction.is_empty() {
+            bail!("Invalid amount: {value}");
+        }
+        Ok(Amount::Tokens { whole, fraction })
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Amount::Tokens { whole, fraction } if fraction.is_empty() => write!(f, "{whole}"),
+            Amount::Tokens { whole, fraction } => write!(f, "{whole}.{fraction}"),
+            Amount::Raw(base_units) => write!(f, "{base_units} {RAW_SUFFIX}"),
+        }
+    }
+}
+
+/// Formats `base_units` of a mint with `decimals` decimals as a token amount, without trailing
+/// zeros, e.g. `12.5`; every amount shown to the user goes through it.
+pub fn format(base_units: u64, decimals: u8) -> String {
+    let decimals = usize::from(decimals);
+    let digits = format!("{base_units:0>width$}", width = decimals + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals);
+    match fraction.trim_end_matches('0') {
+        "" => whole.to_string(),
+        fraction => format!("{whole}.{fraction}"),
+    }
+}
+
+// Digits of `part` of the amount `value`, without the underscores separating them
+fn digits(part: &str, value: &str) -> Result<String> {
+    if !part
+        .bytes()
+        .all(|byte| byte.is_ascii_digit() || byte == b'_')
+    {
+        bail!("Invalid amount: {value}");
+    }
+    Ok(part.replace('_', ""))
+}
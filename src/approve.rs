@@ -0,0 +1,47 @@
+use anyhow::Result;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Signature, signer::Signer};
+use spl_token_client::spl_token_2022::extension::{
+    BaseStateWithExtensions, confidential_transfer::ConfidentialTransferAccount,
+};
+use std::sync::Arc;
+use tracing::instrument;
+
+use crate::{TokenClient, inspect::get_token_accounts, utils::into_signature};
+
+/// Approves `account` for confidential transfers on a mint created without auto-approval.
+///
+/// `authority` must be the confidential transfer authority of the mint of `token`.
+#[instrument(skip_all, fields(%account, authority = %authority.pubkey()))]
+pub async fn approve_account(
+    token: &TokenClient,
+    authority: Arc<dyn Signer>,
+    account: &Pubkey,
+) -> Result<Signature> {
+    let response = token
+        .confidential_transfer_approve_account(account, &authority.pubkey(), &[&authority])
+        .await?;
+    into_signature(response)
+}
+
+/// Lists the token accounts of `mint` that are configured for confidential transfers but not
+/// yet approved.
+///
+/// Scans the token accounts of the mint with [`get_token_accounts`], so the RPC node must allow
+/// `getProgramAccounts` for the Token-2022 program.
+#[instrument(skip_all, fields(%mint))]
+pub async fn list_unapproved_accounts(
+    rpc_client: &RpcClient,
+    mint: &Pubkey,
+) -> Result<Vec<Pubkey>> {
+    Ok(get_token_accounts(rpc_client, mint)
+        .await?
+        .into_iter()
+        .filter(|(_, state)| {
+            state
+                .get_extension::<ConfidentialTransferAccount>()
+                .is_ok_and(|extension| !bool::from(extension.approved))
+        })
+        .map(|(address, _)| address)
+        .collect())
+}
@@ -0,0 +1,141 @@
+use anyhow::{Result, anyhow, bail};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use spl_token_client::spl_token_2022::{
+    extension::confidential_transfer::{
+        account_info::combine_balances,
+        instruction::{
+            ConfidentialTransferInstruction, TransferInstructionData,
+            TransferWithFeeInstructionData,
+        },
+    },
+    id as token_2022_program_id,
+    instruction::{TokenInstruction, decode_instruction_data, decode_instruction_type},
+    solana_zk_sdk::encryption::{
+        elgamal::{ElGamalCiphertext, ElGamalSecretKey},
+        pod::elgamal::PodElGamalCiphertext,
+    },
+};
+use tracing::{debug, instrument};
+
+use crate::{
+    decode_table::DecodeTable,
+    indexer::{FetchedTransaction, get_transaction},
+};
+
+/// A confidential transfer decrypted with the auditor key.
+#[derive(Debug, Clone, Copy)]
+pub struct AuditedTransfer {
+    /// Index of the transfer instruction in the transaction.
+    pub instruction_index: usize,
+    /// Source token account.
+    pub source: Pubkey,
+    /// Mint of the transferred tokens.
+    pub mint: Pubkey,
+    /// Destination token account.
+    pub destination: Pubkey,
+    /// Transferred amount in base units.
+    pub amount: u64,
+}
+
+/// Decrypts the amounts of all confidential transfers in transaction `signature` with the
+/// auditor ElGamal secret key.
+///
+/// The transfer instruction data carries the amount encrypted under the auditor pubkey as two
+/// ciphertexts (low 16 and high 32 bits), taken from the ciphertext validity proof. Both
+/// `Transfer` and `TransferWithFee` instructions are decoded; transactions with address lookup
+/// tables are supported.
+#[instrument(skip_all, fields(%signature))]
+pub async fn audit_transaction(
+    rpc_client: &RpcClient,
+    signature: &Signature,
+    auditor_secret_key: &ElGamalSecretKey,
+    decode_table: &DecodeTable,
+) -> Result<Vec<AuditedTransfer>> {
+    let FetchedTransaction {
+        transaction,
+        account_keys,
+        ..
+    } = get_transaction(rpc_client, signature, rpc_client.commitment()).await?;
+
+    let mut transfers = vec![];
+    for (instruction_index, instruction) in transaction.message.instructions().iter().enumerate() {
+        let program_id = account_keys.get(instruction.program_id_index as usize);
+        if program_id != Some(&token_2022_program_id()) {
+            continue;
+        }
+        let Some((ciphertext_lo, ciphertext_hi)) = auditor_ciphertexts(&instruction.data)? else {
+            continue;
+        };
+        let account = |position: usize| {
+            instruction
+                .accounts
+                .get(position)
+                .and_then(|&index| account_keys.get(index as usize))
+                .copied()
+                .ok_or_else(|| {
+                    anyhow!("Missing account {position} of instruction {instruction_index}")
+                })
+        };
+        let amount_lo = decrypt(auditor_secret_key, ciphertext_lo, decode_table)?;
+        let amount_hi = decrypt(auditor_secret_key, ciphertext_hi, decode_table)?;
+        debug!(
+            instruction_index,
+            amount_lo, amount_hi, "Decrypted transfer amount"
+        );
+        transfers.push(AuditedTransfer {
+            instruction_index,
+            source: account(0)?,
+            mint: account(1)?,
+            destination: account(2)?,
+            amount: combine_balances(amount_lo, amount_hi)
+                .ok_or_else(|| anyhow!("Transfer amount overflow"))?,
+        });
+    }
+    if transfers.is_empty() {
+        bail!("Transaction {signature} contains no confidential transfer");
+    }
+    Ok(transfers)
+}
+
+// Auditor ciphertexts of a Token-2022 instruction, if it is a confidential transfer
+fn auditor_ciphertexts(
+    data: &[u8],
+) -> Result<Option<(PodElGamalCiphertext, PodElGamalCiphertext)>> {
+    if !matches!(
+        TokenInstruction::unpack(data),
+        Ok(TokenInstruction::ConfidentialTransferExtension)
+    ) {
+        return Ok(None);
+    }
+    let data = &data[1..];
+    Ok(match decode_instruction_type(data)? {
+        ConfidentialTransferInstruction::Transfer => {
+            let data = decode_instruction_data::<TransferInstructionData>(data)?;
+            Some((
+                data.transfer_amount_auditor_ciphertext_lo,
+                data.transfer_amount_auditor_ciphertext_hi,
+            ))
+        }
+        ConfidentialTransferInstruction::TransferWithFee => {
+            let data = decode_instruction_data::<TransferWithFeeInstructionData>(data)?;
+            Some((
+                data.transfer_amount_auditor_ciphertext_lo,
+                data.transfer_amount_auditor_ciphertext_hi,
+            ))
+        }
+        _ => None,
+    })
+}
+
+fn decrypt(
+    auditor_secret_key: &ElGamalSecretKey,
+    ciphertext: PodElGamalCiphertext,
+    decode_table: &DecodeTable,
+) -> Result<u64> {
+    let ciphertext = ElGamalCiphertext::try_from(ciphertext)
+        .map_err(|_| anyhow!("Invalid auditor ciphertext"))?;
+    decode_table
+        .decrypt(auditor_secret_key, &ciphertext)
+        .ok_or_else(|| anyhow!("Unable to decrypt the transfer amount; wrong auditor key?"))
+}
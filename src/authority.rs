@@ -0,0 +1,157 @@
+use anyhow::{Result, anyhow, bail};
+use confidential_transfer_core::derivation::SeedMessage;
+use solana_sdk::{program_pack::Pack, pubkey::Pubkey, signer::Signer};
+use spl_token_client::{client::ProgramClient, spl_token_2022::state::Multisig};
+use std::sync::Arc;
+
+use crate::{client::RpcSender, inspect::get_token_2022_account_data};
+
+/// Owner of a token account: a single keypair, or an SPL multisig with enough of its signers.
+///
+/// Operations sign with every signer and reference them as multisig signers when the owner is a
+/// multisig. The first signer also derives the confidential keys of the account, unless another
+/// key source is set with [`Authority::with_key_source`], and manages the proof context accounts,
+/// which cannot be owned by a multisig, unless another proof authority is set with
+/// [`Authority::with_proof_authority`]; the key source and its seed message (see
+/// [`Authority::with_seed_message`]) must be the same on every call, or the derived keys will not
+/// match the configured account.
+///
+/// SPL delegates cannot act for the owner: the confidential transfer instructions only accept
+/// the account owner as authority.
+#[derive(Clone)]
+pub struct Authority {
+    address: Pubkey,
+    signers: Vec<Arc<dyn Signer>>,
+    multisig: bool,
+    key_source: Option<Arc<dyn Signer>>,
+    proof_authority: Option<Arc<dyn Signer>>,
+    seed_message: SeedMessage,
+}
+
+impl Authority {
+    /// Authority of an account owned by `signer`.
+    pub fn single(signer: Arc<dyn Signer>) -> Self {
+        Self {
+            address: signer.pubkey(),
+            signers: vec![signer],
+            multisig: false,
+            key_source: None,
+            proof_authority: None,
+            seed_message: SeedMessage::default(),
+        }
+    }
+
+    /// Authority of an account owned by the multisig at `address`, without checking `signers`
+    /// against it; see [`load_multisig`].
+    pub fn multisig(address: Pubkey, signers: Vec<Arc<dyn Signer>>) -> Result<Self> {
+        if signers.is_empty() {
+            bail!("Multisig {address} needs at least one signer");
+        }
+        Ok(Self {
+            address,
+            signers,
+            multisig: true,
+            key_source: None,
+            proof_authority: None,
+            seed_message: SeedMessage::default(),
+        })
+    }
+
+    /// Address of the owner, as stored in the token account.
+    pub fn pubkey(&self) -> Pubkey {
+        self.address
+    }
+
+    /// Keypairs signing for the owner.
+    pub fn signers(&self) -> &Vec<Arc<dyn Signer>> {
+        &self.signers
+    }
+
+    /// Signer the confidential keys are derived from when no other key source is set, and the
+    /// proof context accounts managed by when no other proof authority is set.
+    pub fn key_signer(&self) -> &Arc<dyn Signer> {
+        &self.signers[0]
+    }
+
+    /// Derives the confidential keys from `key_source` instead of the key signer, e.g. a keypair
+    /// recovered from a seed phrase with [`derive_key_source`](crate::seed::derive_key_source).
+    pub fn with_key_source(mut self, key_source: Arc<dyn Signer>) -> Self {
+        self.key_source = Some(key_source);
+        self
+    }
+
+    /// Signer the confidential keys of the owner's accounts are derived from.
+    pub fn key_source(&self) -> &Arc<dyn Signer> {
+        self.key_source
+            .as_ref()
+            .unwrap_or_else(|| self.key_signer())
+    }
+
+    /// Manages the proof context accounts with `proof_authority` instead of the key signer, so the
+    /// permission to close them, and the rent they refund, stay apart from the owner's keys.
+    pub fn with_proof_authority(mut self, proof_authority: Arc<dyn Signer>) -> Self {
+        self.proof_authority = Some(proof_authority);
+        self
+    }
+
+    /// Signer managing the proof context accounts of the owner's operations: it is set as their
+    /// context state authority, closes them and receives their rent.
+    pub fn proof_authority(&self) -> &Arc<dyn Signer> {
+        self.proof_authority
+            .as_ref()
+            .unwrap_or_else(|| self.key_signer())
+    }
+
+    /// Derives the confidential keys from signatures of `seed_message` instead of the default one.
+    pub fn with_seed_message(mut self, seed_message: SeedMessage) -> Self {
+        self.seed_message = seed_message;
+        self
+    }
+
+    /// Message the key source signs to derive the confidential keys of the owner's accounts.
+    pub fn seed_message(&self) -> &SeedMessage {
+        &self.seed_message
+    }
+
+    /// Multisig signer pubkeys to reference in instructions; empty for a single owner.
+    pub fn multisig_signers(&self) -> Vec<Pubkey> {
+        if self.multisig {
+            self.signers.iter().map(|signer| signer.pubkey()).collect()
+        } else {
+            vec![]
+        }
+    }
+}
+
+impl From<Arc<dyn Signer>> for Authority {
+    fn from(signer: Arc<dyn Signer>) -> Self {
+        Self::single(signer)
+    }
+}
+
+/// Fetches the SPL multisig at `address` and checks that `signers` are members reaching its
+/// threshold.
+pub async fn load_multisig(
+    program_client: &dyn ProgramClient<RpcSender>,
+    address: Pubkey,
+    signers: Vec<Arc<dyn Signer>>,
+) -> Result<Authority> {
+    let data = get_token_2022_account_data(program_client, &address).await?;
+    let multisig =
+        Multisig::unpack(&data).map_err(|_| anyhow!("Account {address} is not an SPL multisig"))?;
+    let members = &multisig.signers[..usize::from(multisig.n)];
+    for signer in &signers {
+        if !members.contains(&signer.pubkey()) {
+            bail!("{} is not a signer of multisig {address}", signer.pubkey());
+        }
+    }
+    if signers.len() < usize::from(multisig.m) {
+        bail!(
+            "Multisig {address} requires {} of {} signers, got {}",
+            multisig.m,
+            multisig.n,
+            signers.len()
+        );
+    }
+    Authority::multisig(address, signers)
+}
@@ -0,0 +1,132 @@
+use anyhow::{Context, Result, anyhow, bail};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use spl_token_client::spl_token_2022::solana_zk_sdk::encryption::{
+    auth_encryption::AeKey,
+    elgamal::{ElGamalKeypair, ElGamalSecretKey},
+};
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::debug;
+
+use crate::keystore::{KeyFile, open, seal, write_private};
+
+//Authenticated with the ciphertext, so a keystore file cannot pass for a backup
+const BACKUP_ID: &str = "confidential-transfer-backup-v1";
+
+/// Keys of one token account, as carried by a backup.
+pub struct BackupEntry {
+    /// Owner of the token account.
+    pub owner: Pubkey,
+    /// Mint of the token account.
+    pub mint: Pubkey,
+    /// Token account address.
+    pub account: Pubkey,
+    /// ElGamal keypair of the account.
+    pub elgamal_keypair: ElGamalKeypair,
+    /// AES key of the account.
+    pub aes_key: AeKey,
+    /// How the keys were derived, e.g. from which signer or seed phrase path, if known; kept so
+    /// they can be re-derived without the backup.
+    pub derivation: Option<String>,
+}
+
+// Plaintext of a backup
+#[derive(Serialize, Deserialize)]
+struct BackupContents {
+    created_at: u64,
+    entries: Vec<BackupRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BackupRecord {
+    owner: String,
+    mint: String,
+    account: String,
+    elgamal_pubkey: String,
+    elgamal_secret_key: String,
+    aes_key: String,
+    derivation: Option<String>,
+}
+
+/// Writes `entries` to a new backup at `path`, encrypted with `passphrase` like the keystore
+/// files: AES-256-GCM under an Argon2id stretch of the passphrase.
+///
+/// Refuses to overwrite an existing file.
+pub fn write_backup(path: &Path, entries: &[BackupEntry], passphrase: &str) -> Result<()> {
+    if path.exists() {
+        bail!("Refusing to overwrite existing file {}", path.display());
+    }
+    let contents = BackupContents {
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default(),
+        entries: entries
+            .iter()
+            .map(|entry| BackupRecord {
+                owner: entry.owner.to_string(),
+                mint: entry.mint.to_string(),
+                account: entry.account.to_string(),
+                elgamal_pubkey: entry.elgamal_keypair.pubkey().to_string(),
+                elgamal_secret_key: BASE64_STANDARD
+                    .encode(entry.elgamal_keypair.secret().as_bytes()),
+                aes_key: BASE64_STANDARD.encode(<[u8; 16]>::from(entry.aes_key.clone())),
+                derivation: entry.derivation.clone(),
+            })
+            .collect(),
+    };
+    let sealed = seal(
+        &serde_json::to_vec(&contents)?,
+        passphrase,
+        BACKUP_ID.as_bytes(),
+    )
+    .context("Failed to encrypt the backup")?;
+    write_private(path, &serde_json::to_vec_pretty(&sealed)?)?;
+    debug!(path = %path.display(), entries = entries.len(), "Wrote key backup");
+    Ok(())
+}
+
+/// Reads and decrypts the backup at `path`.
+///
+/// Every ElGamal secret key is checked against the pubkey recorded next to it, so a corrupted
+/// entry fails instead of restoring keys that decrypt nothing.
+pub fn read_backup(path: &Path, passphrase: &str) -> Result<Vec<BackupEntry>> {
+    let contents =
+        std::fs::read(path).with_context(|| format!("Unable to read backup {}", path.display()))?;
+    let sealed: KeyFile = serde_json::from_slice(&contents)
+        .with_context(|| format!("Invalid backup {}", path.display()))?;
+    let plaintext = open(&sealed, passphrase, BACKUP_ID.as_bytes(), path)?;
+    let contents: BackupContents = serde_json::from_slice(&plaintext)
+        .with_context(|| format!("Invalid backup {}", path.display()))?;
+    contents
+        .entries
+        .into_iter()
+        .map(|record| {
+            let invalid = || anyhow!("Invalid backup entry for account {}", record.account);
+            let decode = |field: &str| BASE64_STANDARD.decode(field).map_err(|_| invalid());
+            let elgamal_keypair = ElGamalKeypair::new(
+                ElGamalSecretKey::try_from(decode(&record.elgamal_secret_key)?.as_slice())
+                    .map_err(|_| invalid())?,
+            );
+            if elgamal_keypair.pubkey().to_string() != record.elgamal_pubkey {
+                bail!(
+                    "The ElGamal secret key of {} in the backup does not match its pubkey",
+                    record.account
+                );
+            }
+            Ok(BackupEntry {
+                owner: record.owner.parse().map_err(|_| invalid())?,
+                mint: record.mint.parse().map_err(|_| invalid())?,
+                account: record.account.parse().map_err(|_| invalid())?,
+                elgamal_keypair,
+                aes_key: AeKey::try_from(decode(&record.aes_key)?.as_slice())
+                    .map_err(|_| invalid())?,
+                derivation: record.derivation,
+            })
+        })
+        .collect()
+}
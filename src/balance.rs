@@ -0,0 +1,177 @@
+use anyhow::{Result, anyhow};
+use confidential_transfer_core::balance::decrypt_available_balance;
+use solana_sdk::pubkey::Pubkey;
+use spl_token_client::spl_token_2022::{
+    extension::{
+        BaseStateWithExtensions,
+        confidential_transfer::{ConfidentialTransferAccount, account_info::combine_balances},
+    },
+    solana_zk_sdk::encryption::{
+        auth_encryption::AeKey,
+        elgamal::{ElGamalCiphertext, ElGamalKeypair, ElGamalSecretKey},
+        pod::elgamal::PodElGamalCiphertext,
+    },
+};
+use tracing::{debug, instrument};
+
+use crate::{TokenClient, decode_table::DecodeTable, metrics};
+
+/// Balances of a confidential token account, in base units.
+#[derive(Debug, Clone, Copy)]
+pub struct Balances {
+    /// Normal (transparent) token balance.
+    pub public: u64,
+    /// Confidential credits received but not yet applied.
+    pub pending: u64,
+    /// Confidential balance available for transfers and withdrawals.
+    pub available: u64,
+    /// Pending credit counter of the account against its maximum.
+    pub pending_credits: PendingCredits,
+}
+
+/// Share of the maximum pending credit counter, in percent, from which an apply is due.
+pub const PENDING_CREDITS_WARNING_PERCENT: u64 = 75;
+
+/// Pending credit counter of a confidential token account against its maximum.
+///
+/// Every deposit and incoming confidential transfer increments the counter; once it reaches the
+/// maximum, the account rejects further credits until the owner applies the pending balance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PendingCredits {
+    /// Credits received since the last apply.
+    pub counter: u64,
+    /// Credits the account accepts before an apply is required.
+    pub maximum: u64,
+}
+
+/// How urgently the pending balance of an account has to be applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ApplyUrgency {
+    /// The counter is below [`PENDING_CREDITS_WARNING_PERCENT`] of the maximum.
+    None,
+    /// The counter is close to the maximum.
+    Soon,
+    /// The counter is at the maximum; credits are rejected until the next apply.
+    Now,
+}
+
+impl PendingCredits {
+    /// Reads the counters of a `ConfidentialTransferAccount` extension.
+    pub fn new(extension_data: &ConfidentialTransferAccount) -> Self {
+        Self {
+            counter: u64::from(extension_data.pending_balance_credit_counter),
+            maximum: u64::from(extension_data.maximum_pending_balance_credit_counter),
+        }
+    }
+
+    /// Credits the account still accepts before an apply.
+    pub fn remaining(&self) -> u64 {
+        self.maximum.saturating_sub(self.counter)
+    }
+
+    /// Classifies the counter against [`PENDING_CREDITS_WARNING_PERCENT`] of the maximum.
+    pub fn urgency(&self) -> ApplyUrgency {
+        if self.counter >= self.maximum {
+            ApplyUrgency::Now
+        } else if self.counter > 0
+            && self.counter.saturating_mul(100)
+                >= self.maximum.saturating_mul(PENDING_CREDITS_WARNING_PERCENT)
+        {
+            ApplyUrgency::Soon
+        } else {
+            ApplyUrgency::None
+        }
+    }
+}
+
+/// Reads the pending credit counter of `ata_pubkey`; needs no keys, so it also works for other
+/// owners' accounts, e.g. to check a recipient before a transfer.
+pub async fn get_pending_credits(
+    token: &TokenClient,
+    ata_pubkey: &Pubkey,
+) -> Result<PendingCredits> {
+    let token_account = token.get_account_info(ata_pubkey).await?;
+    Ok(PendingCredits::new(
+        token_account.get_extension::<ConfidentialTransferAccount>()?,
+    ))
+}
+
+/// Reads the available confidential balance of `ata_pubkey`, decrypted with the AES key; unlike
+/// [`get_balances`] it needs no decode table.
+pub async fn get_available_balance(
+    token: &TokenClient,
+    ata_pubkey: &Pubkey,
+    aes_key: &AeKey,
+) -> Result<u64> {
+    let token_account = token.get_account_info(ata_pubkey).await?;
+    decrypt_available_balance(
+        token_account.get_extension::<ConfidentialTransferAccount>()?,
+        aes_key,
+    )
+    .ok_or_else(|| anyhow!("Failed to decrypt the available balance of {ata_pubkey}"))
+}
+
+/// Reads the balances of `ata_pubkey` and decrypts its confidential balances with the owner's
+/// keys.
+///
+/// The available balance is read from `decryptable_available_balance`, which the owner
+/// re-encrypts with the AES key on every apply, withdraw and outgoing transfer. The pending
+/// balance only exists as ElGamal ciphertexts and is recovered with [`decrypt_pending_balance`].
+#[instrument(skip_all, fields(ata = %ata_pubkey))]
+pub async fn get_balances(
+    token: &TokenClient,
+    ata_pubkey: &Pubkey,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+    decode_table: &DecodeTable,
+) -> Result<Balances> {
+    let token_account = token.get_account_info(ata_pubkey).await?;
+    let extension_data = token_account.get_extension::<ConfidentialTransferAccount>()?;
+    let available = decrypt_available_balance(extension_data, aes_key)
+        .ok_or_else(|| anyhow!("Failed to decrypt the available balance of {ata_pubkey}"))?;
+    let pending = decrypt_pending_balance(extension_data, elgamal_keypair.secret(), decode_table)?;
+    let pending_credits = PendingCredits::new(extension_data);
+    metrics::set_pending_credits(ata_pubkey, pending_credits.counter);
+    Ok(Balances {
+        public: token_account.base.amount,
+        pending,
+        available,
+        pending_credits,
+    })
+}
+
+/// Decrypts the pending balance of a `ConfidentialTransferAccount` extension.
+///
+/// Incoming credits are accumulated in two ElGamal ciphertexts holding the low 16 and the high
+/// 32 bits of each amount. Each is decrypted by solving a discrete log against `decode_table`,
+/// which succeeds while the accumulated value is within the table's range.
+pub fn decrypt_pending_balance(
+    extension_data: &ConfidentialTransferAccount,
+    elgamal_secret_key: &ElGamalSecretKey,
+    decode_table: &DecodeTable,
+) -> Result<u64> {
+    let pending_lo = decrypt(
+        elgamal_secret_key,
+        extension_data.pending_balance_lo,
+        decode_table,
+    )?;
+    let pending_hi = decrypt(
+        elgamal_secret_key,
+        extension_data.pending_balance_hi,
+        decode_table,
+    )?;
+    debug!(pending_lo, pending_hi, "Decrypted pending balance");
+    combine_balances(pending_lo, pending_hi).ok_or_else(|| anyhow!("Pending balance overflow"))
+}
+
+fn decrypt(
+    elgamal_secret_key: &ElGamalSecretKey,
+    ciphertext: PodElGamalCiphertext,
+    decode_table: &DecodeTable,
+) -> Result<u64> {
+    let ciphertext = ElGamalCiphertext::try_from(ciphertext)
+        .map_err(|_| anyhow!("Invalid pending balance ciphertext"))?;
+    decode_table
+        .decrypt(elgamal_secret_key, &ciphertext)
+        .ok_or_else(|| anyhow!("Pending balance is too large to decrypt; apply it first"))
+}
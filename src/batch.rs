@@ -0,0 +1,288 @@
+use anyhow::{Context, Result, anyhow, bail};
+use futures::{StreamExt, channel::mpsc, stream};
+use solana_sdk::{pubkey::Pubkey, signer::Signer};
+use spl_token_client::spl_token_2022::{
+    extension::{
+        BaseStateWithExtensions,
+        confidential_transfer::{ConfidentialTransferAccount, account_info::TransferAccountInfo},
+    },
+    solana_zk_sdk::encryption::{auth_encryption::AeKey, elgamal::ElGamalKeypair},
+};
+use std::{
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+use tracing::{debug, instrument, warn};
+
+use crate::{
+    TokenClient,
+    amount::Amount,
+    authority::Authority,
+    flow::close_leftover_proof_accounts,
+    keygen,
+    mint::get_ata,
+    transfer::{
+        PreparedTransfer, TransferResult, close_proof_accounts, get_auditor_elgamal_pubkey,
+        get_recipient_elgamal_pubkey, get_transfer_fee, requires_memo,
+    },
+};
+
+/// One transfer of a batch: `amount` base units to the associated token account of `recipient`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchTransfer {
+    /// Wallet address of the recipient.
+    pub recipient: Pubkey,
+    /// Amount in base units, fee included on mints with transfer fees.
+    pub amount: u64,
+}
+
+/// Outcome of one transfer of a batch run by [`transfer_batch`].
+#[derive(Debug)]
+pub struct BatchTransferOutcome {
+    /// The requested transfer.
+    pub transfer: BatchTransfer,
+    /// The completed transfer, or why it failed or was not attempted.
+    pub result: Result<TransferResult>,
+}
+
+/// Parses a batch transfer list of a mint with `decimals` decimals: one `recipient,amount` pair
+/// per line, amounts as [`Amount`]s, in tokens or in base units with a `raw` suffix.
+///
+/// Blank lines and lines starting with `#` are ignored.
+pub fn parse_transfer_list(contents: &str, decimals: u8) -> Result<Vec<BatchTransfer>> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_number, line)| {
+            let Some((recipient, amount)) = line.split_once(',') else {
+                bail!("Line {line_number}: expected `recipient,amount`, got `{line}`");
+            };
+            Ok(BatchTransfer {
+                recipient: Pubkey::from_str(recipient.trim())
+                    .with_context(|| format!("Line {line_number}: invalid recipient"))?,
+                amount: amount
+                    .parse::<Amount>()
+                    .and_then(|amount| amount.to_base_units(decimals))
+                    .with_context(|| format!("Line {line_number}: invalid amount"))?,
+            })
+        })
+        .collect()
+}
+
+/// Transfers confidentially from `source_ata` to several recipients.
+///
+/// Every proof commits to the source balance left by the previous transfer, so the proofs are
+/// generated one after the other on a blocking thread while the proof accounts of the transfers
+/// already generated are created, up to `concurrency` at a time. The transfers themselves land in
+/// the order of `transfers`; once one fails, the source balance no longer matches the remaining
+/// proofs and those transfers are reported as not attempted. Recipients without a usable account
+/// are reported as failed without affecting the others, as are recipients requiring memos, since
+/// batch transfers carry none. All proof accounts are closed at the end, including those a
+/// failed creation left behind, and the outcomes are returned in the order of `transfers`.
+#[instrument(skip_all, fields(source = %source_ata, transfers = transfers.len()))]
+pub async fn transfer_batch(
+    token: &TokenClient,
+    owner: &Authority,
+    source_ata: &Pubkey,
+    transfers: &[BatchTransfer],
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+    concurrency: usize,
+) -> Result<Vec<BatchTransferOutcome>> {
+    let concurrency = concurrency.max(1);
+    let auditor_elgamal_pubkey = get_auditor_elgamal_pubkey(token).await?;
+    let transfer_fee = get_transfer_fee(token).await?;
+    let token_account = token.get_account_info(source_ata).await?;
+    let source_account =
+        TransferAccountInfo::new(token_account.get_extension::<ConfidentialTransferAccount>()?);
+
+    let mut results = transfers.iter().map(|_| None).collect::<Vec<_>>();
+    //Look up the recipients' ElGamal pubkeys; transfers to unusable accounts fail on their own
+    let recipients = stream::iter(transfers)
+        .map(|transfer| async move {
+            let elgamal_pubkey = get_recipient_elgamal_pubkey(token, &transfer.recipient).await?;
+            //Batch transfers carry no memo
+            let ata_pubkey = get_ata(&transfer.recipient, token.get_address());
+            if requires_memo(token, &ata_pubkey).await? {
+                bail!("{ata_pubkey} requires a memo on incoming transfers");
+            }
+            Ok(elgamal_pubkey)
+        })
+        .buffered(concurrency)
+        .collect::<Vec<_>>()
+        .await;
+    let mut jobs = vec![];
+    for (index, (transfer, recipient)) in transfers.iter().zip(recipients).enumerate() {
+        match recipient {
+            Ok(elgamal_pubkey) => jobs.push((index, *transfer, elgamal_pubkey)),
+            Err(err) => results[index] = Some(Err(err)),
+        }
+    }
+    debug!(
+        recipients = jobs.len(),
+        "Resolved recipient ElGamal pubkeys"
+    );
+
+    //Set once a transfer fails; the remaining ones are then neither proven nor submitted
+    let failed = Arc::new(AtomicBool::new(false));
+    //Generate the chained proofs off the async runtime, handing each over once it is ready
+    let (sender, receiver) = mpsc::unbounded();
+    let generation_failed = failed.clone();
+    let mint = *token.get_address();
+    let (elgamal_keypair_owned, aes_key_owned) = (elgamal_keypair.clone(), aes_key.clone());
+    let generation = tokio::task::spawn_blocking(move || {
+        let mut source_account = source_account;
+        for (index, transfer, destination_elgamal_pubkey) in jobs {
+            if generation_failed.load(Ordering::Relaxed) {
+                break;
+            }
+            let prepared = PreparedTransfer::new(
+                source_account,
+                get_ata(&transfer.recipient, &mint),
+                &destination_elgamal_pubkey,
+                transfer.amount,
+                &elgamal_keypair_owned,
+                &aes_key_owned,
+                auditor_elgamal_pubkey.as_ref(),
+                transfer_fee.as_ref(),
+            )
+            .and_then(|prepared| {
+                source_account = prepared.next(&aes_key_owned)?;
+                Ok(prepared)
+            });
+            let generated = prepared.is_ok();
+            //Stop on the first failure, the following proofs would build on it
+            if sender.unbounded_send((index, prepared)).is_err() || !generated {
+                break;
+            }
+        }
+    });
+
+    //Proof accounts cannot be managed by a multisig, so they belong to the proof authority
+    let proof_authority = owner.proof_authority();
+    let mut created = receiver
+        .map(|(index, prepared): (usize, Result<PreparedTransfer>)| {
+            let failed = &failed;
+            async move {
+                let prepared = prepared.map_err(|err| (index, err))?;
+                if failed.load(Ordering::Relaxed) {
+                    return Ok((index, prepared, None));
+                }
+                let proof_keypairs = (0..prepared.proof_count())
+                    .map(|_| keygen::new_keypair())
+                    .collect::<Vec<_>>();
+                match prepared
+                    .create_proof_accounts_at(token, proof_authority, &proof_keypairs)
+                    .await
+                {
+                    Ok(proof_signatures) => {
+                        Ok((index, prepared, Some((proof_keypairs, proof_signatures))))
+                    }
+                    Err(err) => {
+                        //The accounts created before the failure would otherwise keep their rent
+                        let addresses = proof_keypairs
+                            .iter()
+                            .map(Signer::pubkey)
+                            .collect::<Vec<_>>();
+                        if let Err(close_err) =
+                            close_leftover_proof_accounts(token, proof_authority, &addresses).await
+                        {
+                            warn!(
+                                index,
+                                "Closing the proof accounts of a failed transfer failed, \
+                                 gc-proof-accounts reclaims them: {close_err:#}"
+                            );
+                        }
+                        Err((index, err))
+                    }
+                }
+            }
+        })
+        .buffered(concurrency);
+
+    //Submit in order, stopping at the first failure
+    let mut submitted = vec![];
+    while let Some(created) = created.next().await {
+        let (index, prepared, proof_accounts) = match created {
+            Ok(created) => created,
+            Err((index, err)) => {
+                failed.store(true, Ordering::Relaxed);
+                results[index] = Some(Err(err));
+                continue;
+            }
+        };
+        let Some((proof_keypairs, proof_signatures)) = proof_accounts else {
+            continue;
+        };
+        let transfer_signature = if failed.load(Ordering::Relaxed) {
+            Err(anyhow!("An earlier transfer of the batch failed"))
+        } else {
+            prepared
+                .submit(
+                    token,
+                    owner,
+                    source_ata,
+                    &proof_keypairs,
+                    elgamal_keypair,
+                    aes_key,
+                    None,
+                )
+                .await
+        };
+        if transfer_signature.is_err() {
+            failed.store(true, Ordering::Relaxed);
+        }
+        debug!(index, signature = ?transfer_signature.as_ref().ok(), "Submitted transfer");
+        submitted.push((
+            index,
+            prepared,
+            proof_keypairs,
+            proof_signatures,
+            transfer_signature,
+        ));
+    }
+    //Proof generation is awaited once the accounts of the transfers it handed over are closed
+    let generation = generation.await;
+
+    //Close the context state accounts of every transfer, submitted or not, to recover rent
+    let closed = stream::iter(submitted)
+        .map(
+            |(index, prepared, proof_keypairs, proof_signatures, transfer_signature)| async move {
+                let closed = close_proof_accounts(token, proof_authority, &proof_keypairs).await;
+                let result = transfer_signature.and_then(|transfer_signature| {
+                    let close_signatures = closed.with_context(|| {
+                        format!("Transfer {transfer_signature} confirmed, but closing its proof accounts failed")
+                    })?;
+                    Ok(prepared.result(
+                        &proof_keypairs,
+                        proof_signatures,
+                        transfer_signature,
+                        close_signatures,
+                    ))
+                });
+                (index, result)
+            },
+        )
+        .buffer_unordered(concurrency)
+        .collect::<Vec<(usize, Result<TransferResult>)>>()
+        .await;
+    for (index, result) in closed {
+        results[index] = Some(result);
+    }
+    generation?;
+
+    Ok(transfers
+        .iter()
+        .zip(results)
+        .map(|(transfer, result)| BatchTransferOutcome {
+            transfer: *transfer,
+            result: result
+                .unwrap_or_else(|| Err(anyhow!("An earlier transfer of the batch failed"))),
+        })
+        .collect())
+}
@@ -0,0 +1,77 @@
+use anyhow::{Result, bail};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{hash::Hash, pubkey::Pubkey, signature::Signature};
+use std::{str::FromStr, time::Duration};
+use tracing::{debug, instrument};
+
+//Genesis hash of mainnet-beta, where no faucet exists
+const MAINNET_BETA_GENESIS_HASH: &str = "5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d";
+//Confirmation polls of one airdrop, one per second
+const CONFIRMATION_POLLS: usize = 60;
+//Airdrops in a row that may leave the balance unchanged before giving up
+const MAX_FRUITLESS_AIRDROPS: usize = 3;
+
+/// Airdrops made by [`airdrop_until`].
+#[derive(Debug, Clone)]
+pub struct Airdrops {
+    /// Signatures of the confirmed airdrops, in order.
+    pub signatures: Vec<Signature>,
+    /// Balance in lamports once done.
+    pub balance: u64,
+}
+
+/// Requests airdrops to `pubkey` until its balance reaches `target` lamports, asking for at most
+/// `request` lamports at a time since faucets cap every request.
+///
+/// Each airdrop is confirmed before the balance is checked again. Refuses to run against
+/// mainnet-beta, recognized by its genesis hash, and gives up once several confirmed airdrops in
+/// a row leave the balance unchanged, as rate-limited faucets do.
+#[instrument(skip_all, fields(%pubkey, target, request))]
+pub async fn airdrop_until(
+    rpc_client: &RpcClient,
+    pubkey: &Pubkey,
+    target: u64,
+    request: u64,
+) -> Result<Airdrops> {
+    if rpc_client.get_genesis_hash().await? == Hash::from_str(MAINNET_BETA_GENESIS_HASH)? {
+        bail!("Airdrops are not available on mainnet-beta; fund {pubkey} by transfer");
+    }
+    let mut signatures = vec![];
+    let mut balance = rpc_client.get_balance(pubkey).await?;
+    let mut fruitless = 0;
+    while balance < target {
+        let amount = (target - balance).min(request.max(1));
+        let signature = rpc_client.request_airdrop(pubkey, amount).await?;
+        debug!(%signature, amount, "Requested airdrop");
+        confirm(rpc_client, &signature).await?;
+        signatures.push(signature);
+        let new_balance = rpc_client.get_balance(pubkey).await?;
+        fruitless = if new_balance > balance {
+            0
+        } else {
+            fruitless + 1
+        };
+        if fruitless >= MAX_FRUITLESS_AIRDROPS {
+            bail!(
+                "Airdrops to {pubkey} no longer raise its balance of {new_balance} lamports; the faucet may be rate limiting"
+            );
+        }
+        balance = new_balance;
+        debug!(balance, "Airdrop confirmed");
+    }
+    Ok(Airdrops {
+        signatures,
+        balance,
+    })
+}
+
+// Waits until `signature` is confirmed at the client's commitment
+async fn confirm(rpc_client: &RpcClient, signature: &Signature) -> Result<()> {
+    for _ in 0..CONFIRMATION_POLLS {
+        if rpc_client.confirm_transaction(signature).await? {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    bail!("Airdrop {signature} was not confirmed in time")
+}
@@ -0,0 +1,197 @@
+use anyhow::{Context, Result, anyhow, bail};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use rand::seq::SliceRandom;
+use serde_json::{Value, json};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature, signer::Signer,
+    transaction::VersionedTransaction,
+};
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::time::sleep;
+use tracing::{debug, info};
+
+use crate::client::RpcSender;
+
+/// Most transactions a Jito bundle can hold.
+pub const MAX_BUNDLE_TRANSACTIONS: usize = 5;
+
+/// Smallest tip the block engine accepts, in lamports.
+pub const MIN_TIP_LAMPORTS: u64 = 1_000;
+
+/// Tip paid to the block engine by default, in lamports.
+pub const DEFAULT_TIP_LAMPORTS: u64 = 10_000;
+
+/// Jito block engine the packed transactions of an operation are submitted to as one bundle.
+///
+/// The transactions of a bundle land together, in order, or none of them does, so an operation
+/// interrupted halfway can no longer leave proof accounts open. The last transaction pays the
+/// block engine `tip_lamports`, in place of a priority fee.
+#[derive(Clone)]
+pub struct JitoBundle {
+    /// Block engine URL, e.g. `https://mainnet.block-engine.jito.wtf`.
+    pub block_engine_url: String,
+    /// Tip paid by the fee payer to the block engine, in lamports.
+    pub tip_lamports: u64,
+    /// RPC client the landing of the bundle is followed through.
+    pub rpc_client: Arc<RpcClient>,
+    /// Sender of the token client: in a dry run the transactions are only simulated through it,
+    /// and its timings cover the bundle.
+    pub sender: RpcSender,
+    /// Fee payer of the token client, paying the tip.
+    pub fee_payer: Arc<dyn Signer>,
+    tip_accounts: Vec<Pubkey>,
+    agent: ureq::Agent,
+}
+
+impl fmt::Debug for JitoBundle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JitoBundle")
+            .field("block_engine_url", &self.block_engine_url)
+            .field("tip_lamports", &self.tip_lamports)
+            .field("fee_payer", &self.fee_payer.pubkey())
+            .finish()
+    }
+}
+
+impl JitoBundle {
+    /// Connects to the block engine at `block_engine_url` and fetches its tip accounts, to submit
+    /// bundles paying `tip_lamports` from `fee_payer` and follow them through `rpc_client`.
+    pub async fn connect(
+        block_engine_url: &str,
+        tip_lamports: u64,
+        rpc_client: Arc<RpcClient>,
+        sender: RpcSender,
+        fee_payer: Arc<dyn Signer>,
+    ) -> Result<Self> {
+        if tip_lamports < MIN_TIP_LAMPORTS {
+            bail!("The bundle tip must be at least {MIN_TIP_LAMPORTS} lamports");
+        }
+        let mut bundle = Self {
+            block_engine_url: block_engine_url.trim_end_matches('/').to_string(),
+            tip_lamports,
+            rpc_client,
+            sender,
+            fee_payer,
+            tip_accounts: vec![],
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(30))
+                .build(),
+        };
+        let tip_accounts = bundle.call("getTipAccounts", json!([])).await?;
+        bundle.tip_accounts = serde_json::from_value::<Vec<String>>(tip_accounts)?
+            .iter()
+            .map(|account| account.parse())
+            .collect::<Result<_, _>>()
+            .context("Invalid tip account from the block engine")?;
+        if bundle.tip_accounts.is_empty() {
+            bail!("The block engine at {block_engine_url} returned no tip account");
+        }
+        debug!(
+            tip_accounts = bundle.tip_accounts.len(),
+            "Connected to block engine"
+        );
+        Ok(bundle)
+    }
+
+    /// Tip account the next bundle pays, picked at random among those of the block engine, as
+    /// it recommends to spread the write locks.
+    pub fn tip_account(&self) -> Pubkey {
+        *self
+            .tip_accounts
+            .choose(&mut rand::thread_rng())
+            .expect("connect checks there is a tip account")
+    }
+
+    /// Submits `transactions`, signed on the same recent blockhash, as one bundle and waits
+    /// until it lands, or its blockhash expires, in which case none of them was executed.
+    /// Returns the signatures of the transactions.
+    ///
+    /// In a dry run each transaction is only simulated, one by one.
+    pub async fn send(&self, transactions: &[VersionedTransaction]) -> Result<Vec<Signature>> {
+        if transactions.len() > MAX_BUNDLE_TRANSACTIONS {
+            bail!(
+                "The operation takes {} transactions, more than the {MAX_BUNDLE_TRANSACTIONS} of a bundle",
+                transactions.len()
+            );
+        }
+        let signatures = transactions
+            .iter()
+            .map(|transaction| transaction.signatures[0])
+            .collect::<Vec<_>>();
+        if self.sender.dry_run {
+            for transaction in transactions {
+                self.sender
+                    .send_versioned(&self.rpc_client, transaction)
+                    .await?;
+            }
+            return Ok(signatures);
+        }
+        let encoded = transactions
+            .iter()
+            .map(|transaction| Ok(BASE64_STANDARD.encode(bincode::serialize(transaction)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let started = Instant::now();
+        let bundle_id: String = serde_json::from_value(
+            self.call("sendBundle", json!([encoded, { "encoding": "base64" }]))
+                .await?,
+        )?;
+        let send = started.elapsed();
+        info!("Bundle submitted: {bundle_id}");
+        //A bundle lands whole or not at all, so its last transaction stands for all of them
+        let last = signatures[signatures.len() - 1];
+        let blockhash = transactions[0].message.recent_blockhash();
+        let started = Instant::now();
+        loop {
+            match self.rpc_client.get_signature_status(&last).await? {
+                Some(Ok(())) => break,
+                Some(Err(err)) => bail!("Bundle {bundle_id} failed: {err}"),
+                None => {
+                    if !self
+                        .rpc_client
+                        .is_blockhash_valid(blockhash, CommitmentConfig::processed())
+                        .await?
+                        && self.rpc_client.get_signature_status(&last).await?.is_none()
+                    {
+                        bail!(
+                            "Bundle {bundle_id} did not land before its blockhash expired; none of its transactions was executed"
+                        );
+                    }
+                    sleep(Duration::from_millis(500)).await;
+                }
+            }
+        }
+        let confirm = started.elapsed();
+        self.sender.record(transactions.len() as u32, send, confirm);
+        debug!(%bundle_id, ?send, ?confirm, "Bundle landed");
+        Ok(signatures)
+    }
+
+    // Calls `method` of the bundle API of the block engine and returns its result
+    async fn call(&self, method: &'static str, params: Value) -> Result<Value> {
+        let url = format!("{}/api/v1/bundles", self.block_engine_url);
+        let agent = self.agent.clone();
+        let mut response: Value = tokio::task::spawn_blocking(move || {
+            agent
+                .post(&url)
+                .send_json(json!({
+                    "jsonrpc": "2.0",
+                    "id": 1,
+                    "method": method,
+                    "params": params,
+                }))
+                .with_context(|| format!("{method} request to {url} failed"))?
+                .into_json::<Value>()
+                .with_context(|| format!("Invalid response from {url}"))
+        })
+        .await??;
+        if let Some(error) = response.get("error") {
+            return Err(anyhow!("Block engine {method} error: {error}"));
+        }
+        Ok(response["result"].take())
+    }
+}
@@ -0,0 +1,920 @@
+use clap::{Parser, Subcommand};
+use clap_complete::Shell;
+use confidential_transfer::{
+    amount::Amount, bundle::DEFAULT_TIP_LAMPORTS, client::DEFAULT_COMPUTE_UNIT_MARGIN_PERCENT,
+    keyring::MintRef, payment::PaymentRequest,
+};
+use solana_sdk::{native_token::sol_str_to_lamports, pubkey::Pubkey, signature::Signature};
+use spl_token_client::token::ComputeUnitLimit;
+use std::{net::SocketAddr, path::PathBuf};
+
+// Command line interface for running individual confidential transfer steps
+#[derive(Parser, Debug)]
+#[command(
+    name = "confidential-transfer",
+    version,
+    about = "Token-2022 confidential transfer client"
+)]
+pub struct Cli {
+    /// Config file [default: CT_CONFIG, or ~/.config/confidential-transfer/config.toml]; the CT_*
+    /// environment variables override its settings
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// RPC URL or cluster moniker: localnet, devnet, testnet, mainnet-beta (or l, d, t, m)
+    #[arg(short = 'u', long = "url", visible_alias = "cluster", global = true)]
+    pub url: Option<String>,
+    /// Websocket URL for subscriptions [default: derived from the RPC URL]
+    #[arg(long, global = true, value_name = "URL")]
+    pub ws_url: Option<String>,
+    /// Commitment level used for queries and confirmations [default: confirmed]
+    #[arg(long, global = true, value_parser = ["processed", "confirmed", "finalized"])]
+    pub commitment: Option<String>,
+    /// Most RPC requests awaiting their response at once [default: `max_in_flight` of the
+    /// `[rate_limit]` table, or unlimited]
+    #[arg(long, global = true, value_name = "N")]
+    pub max_in_flight: Option<usize>,
+    /// Most RPC requests started per second [default: `requests_per_second` of the
+    /// `[rate_limit]` table, or unlimited]
+    #[arg(long, global = true, value_name = "N")]
+    pub requests_per_second: Option<u32>,
+    /// Commitment level of the preflight simulation of sent transactions [default: --commitment]
+    #[arg(long, global = true, value_parser = ["processed", "confirmed", "finalized"])]
+    pub preflight_commitment: Option<String>,
+    /// Send transactions without the preflight simulation of the RPC node
+    #[arg(long, global = true, conflicts_with = "preflight_commitment")]
+    pub skip_preflight: bool,
+    /// Wallet keypair [default: CT_KEYPAIR, SOLANA_KEYPAIR, `keypair_path` from the config file or
+    /// from the Solana CLI config file, or ~/.config/solana/id.json].
+    /// Keypair arguments take a keypair file, an inline JSON array or base58 keypair, prompt:// to
+    /// type a seed phrase, usb://ledger?key=0 with the `ledger` feature, or remote://<KEY_ID>
+    #[arg(short, long, global = true, value_name = "KEYPAIR")]
+    pub keypair: Option<PathBuf>,
+    /// Keypair owning the token accounts [default: the wallet keypair]
+    #[arg(long, global = true, value_name = "KEYPAIR")]
+    pub owner: Option<PathBuf>,
+    /// SPL multisig owning the token accounts, instead of the owner keypair
+    #[arg(
+        long,
+        global = true,
+        value_name = "ADDRESS",
+        requires = "multisig_signer",
+        conflicts_with = "owner"
+    )]
+    pub multisig: Option<Pubkey>,
+    /// Keypair of a multisig signer; repeat for M of the N signers. The first one derives the
+    /// confidential keys of the account and must stay the same across commands
+    #[arg(long, global = true, value_name = "KEYPAIR", requires = "multisig")]
+    pub multisig_signer: Vec<PathBuf>,
+    /// Token account of the owner to act on, instead of its associated token account
+    #[arg(long, global = true, value_name = "ADDRESS")]
+    pub token_account: Option<Pubkey>,
+    /// Keep the owner's confidential transfer keys in a passphrase-encrypted keystore, saving
+    /// them on first use [default: ~/.config/confidential-transfer/keystore, or --keystore=DIR];
+    /// the passphrase is read from CT_KEYSTORE_PASSPHRASE or asked for
+    #[arg(
+        long,
+        global = true,
+        value_name = "DIR",
+        num_args = 0..=1,
+        require_equals = true
+    )]
+    pub keystore: Option<Option<PathBuf>>,
+    /// File tracking the owner's configured accounts, so --mint can name a mint by symbol
+    /// [default: ~/.config/confidential-transfer/keyring.json]
+    #[arg(long, global = true, value_name = "FILE")]
+    pub keyring: Option<PathBuf>,
+    /// Record created mints, configured accounts, sent transactions and decrypted balances in a
+    /// database, read back by `history` and `list-accounts`: a SQLite file
+    /// [default: ~/.config/confidential-transfer/storage.sqlite3, or --storage=FILE], or a
+    /// PostgreSQL server shared with other instances, --storage=postgres://...; needs a build with
+    /// the `storage` feature, and `postgres` for a server
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE|URL",
+        num_args = 0..=1,
+        require_equals = true
+    )]
+    pub storage: Option<Option<String>>,
+    /// Derive the owner's confidential transfer keys from a BIP39 seed phrase instead of the owner
+    /// keypair, so they can be recovered from the phrase alone; the phrase and its passphrase are
+    /// read from CT_SEED_PHRASE and CT_SEED_PASSPHRASE or asked for
+    #[arg(long, global = true)]
+    pub seed_phrase: bool,
+    /// Derivation path of the seed phrase key source
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        default_value = confidential_transfer::seed::DEFAULT_DERIVATION_PATH,
+        requires = "seed_phrase"
+    )]
+    pub derivation_path: String,
+    /// Message signed to derive confidential keys: `v1`, the spl-token CLI scheme, or
+    /// `v2:<TEMPLATE>`, a message with {key} and {account} placeholders signed as is
+    /// [default: `seed_message` from the config file, or v1]
+    #[arg(long, global = true, value_name = "SPEC")]
+    pub seed_message: Option<String>,
+    /// Derive the new mint, proof context, nonce and holding account keypairs and generated
+    /// ElGamal and AES keys from SEED instead of randomness, so test runs produce the same
+    /// addresses and failures can be replayed; for tests only, as the seed recreates the keys
+    #[arg(long, global = true, value_name = "SEED")]
+    pub seed: Option<String>,
+    /// Remote signing service resolving remote://<KEY_ID> keypair arguments; its bearer token is
+    /// read from CT_REMOTE_SIGNER_TOKEN
+    #[arg(long, global = true, value_name = "URL")]
+    pub remote_signer_url: Option<String>,
+    /// Keypair of the mint authority, used by create-mint and mint-to [default: the wallet keypair]
+    #[arg(long, global = true, value_name = "KEYPAIR")]
+    pub mint_authority: Option<PathBuf>,
+    /// Keypair paying transaction fees and rent [default: the wallet keypair]
+    #[arg(long, global = true, value_name = "KEYPAIR")]
+    pub fee_payer: Option<PathBuf>,
+    /// Keypair managing the proof context accounts, the only one able to close them and
+    /// receiving their rent [default: the owner, the mint authority for confidential-mint, the
+    /// fee payer for submit-proofs]
+    #[arg(long, global = true, value_name = "KEYPAIR")]
+    pub proof_authority: Option<PathBuf>,
+    /// Compute unit limit of every transaction, or `simulated` for the units it consumes in a
+    /// simulation run before sending it, plus --compute-unit-margin [default: the runtime
+    /// default]; not with --pack, which sets its own
+    #[arg(
+        long,
+        global = true,
+        value_name = "UNITS|simulated",
+        value_parser = parse_compute_unit_limit
+    )]
+    pub compute_unit_limit: Option<ComputeUnitLimit>,
+    /// Margin added to the simulated compute units of --compute-unit-limit=simulated, in percent
+    #[arg(
+        long,
+        global = true,
+        value_name = "PERCENT",
+        default_value_t = DEFAULT_COMPUTE_UNIT_MARGIN_PERCENT
+    )]
+    pub compute_unit_margin: u64,
+    /// Priority fee of every transaction, in micro-lamports per compute unit
+    #[arg(
+        long,
+        global = true,
+        value_name = "MICRO_LAMPORTS",
+        conflicts_with = "priority_fee_percentile"
+    )]
+    pub compute_unit_price: Option<u64>,
+    /// Priority fee of every transaction set to this percentile of the fees paid in recent slots,
+    /// fetched once per command
+    #[arg(
+        long,
+        global = true,
+        value_name = "PERCENTILE",
+        value_parser = clap::value_parser!(u8).range(0..=100)
+    )]
+    pub priority_fee_percentile: Option<u8>,
+    /// Durable nonce account the transaction is built on instead of a recent blockhash, so it
+    /// stays valid however long signing takes: one for mint-to, deposit, apply, update-mint,
+    /// enable-required-memos and disable-required-memos, and one per packed transaction, in
+    /// order, for withdraw, transfer and pay with --pack; repeat it or separate with commas
+    #[arg(long, global = true, value_name = "ADDRESS", value_delimiter = ',')]
+    pub nonce: Vec<Pubkey>,
+    /// Keypair of the nonce authority [default: the fee payer]
+    #[arg(long, global = true, value_name = "KEYPAIR")]
+    pub nonce_authority: Option<PathBuf>,
+    /// Write the transaction, built on --nonce and signed by the keys at hand, to a file of this
+    /// directory instead of sending it; keypair arguments also take a bare pubkey for a key
+    /// signing later with sign-transaction
+    #[arg(
+        long,
+        global = true,
+        value_name = "DIR",
+        requires = "nonce",
+        conflicts_with = "dry_run"
+    )]
+    pub sign_offline: Option<PathBuf>,
+    /// Address lookup table the transactions of --pack are compiled against, sent as v0
+    /// transactions so more steps fit in each; see create-lookup-table
+    #[arg(long, global = true, value_name = "ADDRESS")]
+    pub lookup_table: Option<Pubkey>,
+    /// Jito block engine URL the transactions of --pack are submitted to as one bundle, so they
+    /// land all or none
+    #[arg(long, global = true, value_name = "URL")]
+    pub jito: Option<String>,
+    /// Tip of --jito bundles, paid by the fee payer, in lamports
+    #[arg(long, global = true, value_name = "LAMPORTS", default_value_t = DEFAULT_TIP_LAMPORTS)]
+    pub jito_tip: u64,
+    /// Confirm transactions by polling their status instead of a websocket signature
+    /// subscription
+    #[arg(long, global = true)]
+    pub poll_confirmations: bool,
+    /// Build and simulate every transaction without broadcasting it
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    /// Quote the fees of every transaction, and the rent of the proof accounts, through a dry run
+    /// and ask for confirmation before sending them
+    #[arg(long, global = true, conflicts_with_all = ["dry_run", "sign_offline"])]
+    pub show_fees: bool,
+    /// Increase log verbosity (-v debug, -vv trace); overridden by RUST_LOG
+    #[arg(short, long, global = true, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+    /// Only log errors
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+    /// Print the result of withdraw, transfer and transfer-batch as JSON on stdout, with the
+    /// time spent in each stage
+    #[arg(long, global = true)]
+    pub json: bool,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Create a new mint with the ConfidentialTransferMint extension
+    CreateMint {
+        /// Decimals of the new mint [default: `decimals` from the config file, or 9]
+        #[arg(long)]
+        decimals: Option<u8>,
+        /// Base64 ElGamal pubkey of the auditor all transfer amounts are encrypted to
+        #[arg(long, value_name = "ELGAMAL_PUBKEY", group = "auditor_key")]
+        auditor: Option<String>,
+        /// ElGamal keypair file of the auditor
+        #[arg(long, value_name = "FILE", group = "auditor_key")]
+        auditor_keypair: Option<PathBuf>,
+        /// Generate a new auditor ElGamal keypair and save it to FILE
+        #[arg(long, value_name = "FILE", group = "auditor_key")]
+        new_auditor_keypair: Option<PathBuf>,
+        /// Transfer fee in basis points charged on every transfer
+        #[arg(long, value_name = "BPS", requires_all = ["maximum_fee", "withheld_keypair"])]
+        transfer_fee_basis_points: Option<u16>,
+        /// Maximum transfer fee in tokens, e.g. 12.5, or in base units with a `raw` suffix
+        #[arg(long, value_name = "AMOUNT", requires = "transfer_fee_basis_points")]
+        maximum_fee: Option<Amount>,
+        /// ElGamal keypair file withheld confidential fees are encrypted to; created if missing
+        #[arg(long, value_name = "FILE", requires = "transfer_fee_basis_points")]
+        withheld_keypair: Option<PathBuf>,
+        /// Add the ConfidentialMintBurn extension: mint and burn confidentially, encrypted supply
+        #[arg(long)]
+        confidential_mint_burn: bool,
+        /// Require the mint authority to approve each account with approve-account
+        #[arg(long)]
+        manual_approval: bool,
+    },
+    /// Change the auto-approve setting or the auditor of an existing confidential transfer mint
+    UpdateMint {
+        /// Mint to update [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Approve new accounts automatically (true) or require approve-account (false)
+        #[arg(long, value_name = "BOOL")]
+        auto_approve: Option<bool>,
+        /// Base64 ElGamal pubkey of the new auditor
+        #[arg(long, value_name = "ELGAMAL_PUBKEY", group = "auditor_update")]
+        auditor: Option<String>,
+        /// ElGamal keypair file of the new auditor
+        #[arg(long, value_name = "FILE", group = "auditor_update")]
+        auditor_keypair: Option<PathBuf>,
+        /// Generate a new auditor ElGamal keypair and save it to FILE
+        #[arg(long, value_name = "FILE", group = "auditor_update")]
+        new_auditor_keypair: Option<PathBuf>,
+        /// Remove the auditor: transfer amounts are no longer encrypted to an auditor key
+        #[arg(long, group = "auditor_update")]
+        remove_auditor: bool,
+    },
+    /// Create the owner's associated token account and configure it for confidential transfers
+    ConfigureAccount {
+        /// Mint of the token account [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Use the ElGamal pubkey from the owner's ElGamal registry, creating it if needed
+        #[arg(long)]
+        registry: bool,
+        /// Create an auxiliary token account at the address of this keypair instead of the
+        /// associated token account; use it afterwards with --token-account
+        #[arg(long, value_name = "KEYPAIR", conflicts_with = "registry")]
+        account_keypair: Option<PathBuf>,
+        /// Configure the account of this wallet from its existing registry, without its keypair
+        #[arg(long, requires = "registry")]
+        wallet: Option<Pubkey>,
+        /// Symbol to record the mint under in the keyring [default: the symbol of the mint's
+        /// token metadata]
+        #[arg(long)]
+        symbol: Option<String>,
+    },
+    /// List the token accounts the owner configured, as recorded in the keyring, or in the
+    /// database with --storage along with their latest decrypted balances
+    ListAccounts,
+    /// List the owner's latest operations recorded in the database selected with --storage
+    History {
+        /// Only list the operations on the accounts of this mint
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Number of operations to list, newest first
+        #[arg(long, default_value_t = 20)]
+        limit: u32,
+    },
+    /// Index the confidential deposits, applies, transfers and withdraws of a mint into the
+    /// database selected with --storage, from where the previous run stopped
+    Index {
+        /// Mint to index [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+    },
+    /// List the latest events of a mint indexed by `index`
+    Events {
+        /// Mint of the events [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Only list the events of this token account, as source or destination
+        #[arg(long)]
+        account: Option<Pubkey>,
+        /// Number of events to list, newest first
+        #[arg(long, default_value_t = 20)]
+        limit: u32,
+    },
+    /// Approve token accounts for confidential transfers, or list the pending ones
+    ApproveAccount {
+        /// Mint of the token accounts [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Approve every unapproved account of the mint
+        #[arg(long, conflicts_with = "accounts")]
+        all: bool,
+        /// Token accounts to approve; without any, the unapproved accounts are only listed
+        accounts: Vec<Pubkey>,
+    },
+    /// Mint transparent tokens to the owner's associated token account
+    MintTo {
+        /// Mint to issue tokens from [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Amount in tokens, e.g. 12.5, or in base units with a `raw` suffix, e.g. 12500000raw
+        amount: Amount,
+    },
+    /// Mint tokens directly into the pending confidential balance of the owner's account
+    ConfidentialMint {
+        /// Mint with the ConfidentialMintBurn extension [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Amount in tokens, e.g. 12.5, or in base units with a `raw` suffix, e.g. 12500000raw
+        amount: Amount,
+    },
+    /// Burn tokens from the available confidential balance of the owner's account
+    ConfidentialBurn {
+        /// Mint with the ConfidentialMintBurn extension [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Amount in tokens, e.g. 12.5, or in base units with a `raw` suffix, e.g. 12500000raw
+        amount: Amount,
+    },
+    /// Deposit transparent tokens into the pending confidential balance
+    Deposit {
+        /// Mint of the token account [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Amount in tokens, e.g. 12.5, or in base units with a `raw` suffix, e.g. 12500000raw
+        amount: Amount,
+        /// Memo attached to the transaction, e.g. a payment reference
+        #[arg(long)]
+        memo: Option<String>,
+        /// Apply the pending balance in the same transaction, making the deposit spendable
+        #[arg(long)]
+        and_apply: bool,
+    },
+    /// Apply the pending balance to the available confidential balance
+    Apply {
+        /// Mint of the token account [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+    },
+    /// Withdraw from the available confidential balance back to transparent tokens
+    Withdraw {
+        /// Mint of the token account [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Amount in tokens, e.g. 12.5, or in base units with a `raw` suffix, e.g. 12500000raw
+        #[arg(required_unless_present = "all")]
+        amount: Option<Amount>,
+        /// Withdraw the whole available balance, decrypted from the account
+        #[arg(long, conflicts_with = "amount")]
+        all: bool,
+        /// Memo attached to the transaction, e.g. a payment reference
+        #[arg(long)]
+        memo: Option<String>,
+        /// Send the proof account creations, the withdraw and the closes in as few transactions
+        /// as they fit in
+        #[arg(long)]
+        pack: bool,
+    },
+    /// Transfer confidential tokens to another owner's associated token account
+    Transfer {
+        /// Mint of the token accounts [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Wallet address of the recipient; its associated token account must be configured
+        #[arg(long, required_unless_present = "recipient_keypair")]
+        recipient: Option<Pubkey>,
+        /// Keypair of the recipient: its account is configured if needed and the transfer is
+        /// applied to its available balance
+        #[arg(long, value_name = "KEYPAIR", conflicts_with = "recipient")]
+        recipient_keypair: Option<PathBuf>,
+        /// Amount in tokens, e.g. 12.5, or in base units with a `raw` suffix, e.g. 12500000raw
+        amount: Amount,
+        /// Memo attached to the transfer, e.g. a payment reference; required by recipients
+        /// that enabled required memos
+        #[arg(long)]
+        memo: Option<String>,
+        /// Send the proof account creations, the transfer and the closes in as few transactions
+        /// as they fit in
+        #[arg(long)]
+        pack: bool,
+    },
+    /// Transfer confidential tokens to several recipients listed in a file
+    TransferBatch {
+        /// Mint of the token accounts [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// File with one `recipient,amount` pair per line, amounts in tokens or in base units with
+        /// a `raw` suffix; blank lines and lines starting with `#` are skipped
+        #[arg(long, value_name = "FILE")]
+        file: PathBuf,
+        /// Maximum number of recipient lookups, proof account creations and closings in flight
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
+    },
+    /// Pay the recipients of a payroll CSV once every one of them is checked, and write a results
+    /// CSV that a re-run pays the rest of
+    Payroll {
+        /// Mint of the token accounts [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// CSV of `recipient,amount` rows, amounts in tokens or in base units with a `raw` suffix,
+        /// or the results CSV of an earlier run to pay what it left unpaid
+        #[arg(long, value_name = "FILE")]
+        file: PathBuf,
+        /// Results CSV to write [default: the payroll file with the extension `results.csv`]
+        #[arg(long, value_name = "FILE")]
+        results: Option<PathBuf>,
+        /// Maximum number of recipient checks, proof account creations and closings in flight
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
+        /// Pay the valid recipients when some cannot be paid, instead of paying nobody
+        #[arg(long)]
+        skip_invalid: bool,
+    },
+    /// Pay the recurring transfers of the `[[schedule]]` tables of the config file as they come
+    /// due, catching up on the occurrences missed while not running
+    RunSchedules {
+        /// Pay the occurrences due now and exit, e.g. when started by cron or a systemd timer
+        #[arg(long)]
+        once: bool,
+    },
+    /// Show the recurring transfers of the config file, with their last and next occurrences
+    ListSchedules,
+    /// Print a payment request for a confidential transfer to the owner's token account, as a
+    /// `solana:` URI and a QR code
+    RequestPayment {
+        /// Mint of the token account [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Amount in tokens, e.g. 12.5, or in base units with a `raw` suffix, e.g. 12500000raw
+        amount: Amount,
+        /// Wait until a transfer paying the request lands
+        #[arg(long)]
+        wait: bool,
+        /// Seconds to wait for the payment with `--wait`
+        #[arg(long, value_name = "SECONDS", default_value_t = 600)]
+        timeout: u64,
+    },
+    /// Pay a payment request of `request-payment` with a confidential transfer carrying its
+    /// reference
+    Pay {
+        /// `solana:` URI of the request
+        request: PaymentRequest,
+        /// Send the proof account creations, the transfer and the closes in as few transactions
+        /// as they fit in
+        #[arg(long)]
+        pack: bool,
+    },
+    /// Wait until a transfer paying a payment request of `request-payment` lands
+    AwaitPayment {
+        /// `solana:` URI of the request
+        request: PaymentRequest,
+        /// Seconds to wait for the payment
+        #[arg(long, value_name = "SECONDS", default_value_t = 600)]
+        timeout: u64,
+    },
+    /// Show the ElGamal pubkey transfers to a wallet are encrypted to
+    RecipientKey {
+        /// Mint of the token accounts [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Wallet address of the recipient
+        recipient: Pubkey,
+    },
+    /// Print the ElGamal pubkey registered for a token account, base64 encoded, so counterparties
+    /// can check the key transfer amounts are encrypted to
+    ExportElgamalPubkey {
+        /// Mint of the owner's token account [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Token account to export the key of [default: the owner's token account]
+        account: Option<Pubkey>,
+        /// Also write the encoded pubkey to this file
+        #[arg(long, value_name = "FILE")]
+        output: Option<PathBuf>,
+        /// Also print the encoded pubkey as a QR code
+        #[arg(long)]
+        qr: bool,
+    },
+    /// Save the state of the owner's token account that withdraw and transfer proofs are
+    /// generated against, for generate-proofs on a machine without network access
+    ExportProofInputs {
+        /// Mint of the token account [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Wallet address of a transfer recipient, to generate transfer proofs for
+        #[arg(long)]
+        recipient: Option<Pubkey>,
+        /// File to write the proof inputs to
+        #[arg(long, value_name = "FILE")]
+        output: PathBuf,
+    },
+    /// Generate the proofs of an operation on the owner's token account without network access
+    /// and write them to a file, for submit-proofs
+    GenerateProofs {
+        /// Operation to generate the proofs of
+        #[arg(value_enum)]
+        kind: ProofKind,
+        /// Amount of the withdraw or transfer, in base units, as the decimals of the mint are not
+        /// known offline
+        #[arg(required_if_eq_any([("kind", "withdraw"), ("kind", "transfer")]))]
+        amount: Option<u64>,
+        /// Proof inputs written by export-proof-inputs, for withdraw and transfer proofs
+        #[arg(
+            long,
+            value_name = "FILE",
+            required_if_eq_any([("kind", "withdraw"), ("kind", "transfer")])
+        )]
+        inputs: Option<PathBuf>,
+        /// Mint of the token account, for pubkey-validity proofs [default: `mint` from the
+        /// config file]
+        #[arg(long, conflicts_with = "inputs")]
+        mint: Option<MintRef>,
+        /// File to write the proofs to
+        #[arg(long, value_name = "FILE")]
+        output: PathBuf,
+    },
+    /// Check the proofs written by generate-proofs with the local verifier, without network access
+    VerifyProofs {
+        /// Proof file written by generate-proofs
+        #[arg(long, value_name = "FILE")]
+        input: PathBuf,
+        /// Proof inputs the proofs were generated from, to also check they match the account's
+        /// ElGamal pubkey
+        #[arg(long, value_name = "FILE")]
+        inputs: Option<PathBuf>,
+    },
+    /// Verify the proofs written by generate-proofs into context state accounts managed by the
+    /// fee payer
+    SubmitProofs {
+        /// Proof file written by generate-proofs
+        #[arg(long, value_name = "FILE")]
+        input: PathBuf,
+    },
+    /// Close the proof context state accounts of the owner and the fee payer left open by
+    /// interrupted operations, reclaiming their rent
+    GcProofAccounts {
+        /// Only list the accounts and the rent they hold
+        #[arg(long)]
+        list: bool,
+    },
+    /// Create a durable nonce account, for --nonce, advanced by --nonce-authority
+    CreateNonceAccount {
+        /// Keypair of the new account [default: a new keypair, only its address is needed later]
+        #[arg(long, value_name = "KEYPAIR")]
+        nonce_keypair: Option<PathBuf>,
+    },
+    /// Sign a transaction file written by --sign-offline, with keypairs or with signatures
+    /// produced elsewhere; needs no network access
+    SignTransaction {
+        /// Transaction file
+        file: PathBuf,
+        /// Keypair to sign with; repeatable
+        #[arg(long = "signer", value_name = "KEYPAIR")]
+        signers: Vec<PathBuf>,
+        /// Signature of the transaction message produced elsewhere; repeatable
+        #[arg(long = "signature", value_name = "PUBKEY=SIGNATURE", value_parser = parse_signer_signature)]
+        signatures: Vec<(Pubkey, Signature)>,
+    },
+    /// Send fully signed transaction files written by --sign-offline
+    BroadcastTransaction {
+        /// Transaction files, sent in order
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Create an address lookup table, for --lookup-table, holding the mint, the owner's token
+    /// account and the given accounts, managed by the fee payer
+    CreateLookupTable {
+        /// Mint of the token account [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Other account to add, e.g. a frequent transfer recipient's token account; repeatable
+        #[arg(long = "account", value_name = "ADDRESS")]
+        accounts: Vec<Pubkey>,
+        /// Add the accounts missing from this existing table instead of creating one
+        #[arg(long, value_name = "ADDRESS")]
+        extend: Option<Pubkey>,
+    },
+    /// Open the deposit address of an exchange user: a wallet derived from the owner for the
+    /// user, and its associated token account configured for confidential transfers
+    OpenDepositAddress {
+        /// Mint of the token account [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Identifier of the user in the exchange
+        user_id: String,
+    },
+    /// List the deposits waiting in the deposit addresses of the exchange users, by user
+    ListDeposits {
+        /// Mint of the deposit addresses [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+    },
+    /// Credit the deposits of every exchange user and move their balances to the owner's token
+    /// account
+    SweepDeposits {
+        /// Mint of the deposit addresses [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+    },
+    /// Open an escrow with a counterparty: a confidential account owned by the 2-of-2 multisig of
+    /// the owner and the counterparty, whose keys are sealed to both of them
+    CreateEscrow {
+        /// Mint of the escrowed tokens [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Wallet address of the counterparty
+        #[arg(long)]
+        counterparty: Pubkey,
+        /// Escrow file to write, for both parties
+        #[arg(long, value_name = "FILE")]
+        output: PathBuf,
+    },
+    /// Transfer from the owner's token account to an escrow the owner is a party of
+    FundEscrow {
+        /// Escrow file of `create-escrow`
+        #[arg(long, value_name = "FILE")]
+        escrow: PathBuf,
+        /// Amount in tokens, e.g. 12.5, or in base units with a `raw` suffix, e.g. 12500000raw
+        amount: Amount,
+    },
+    /// Show the balances of an escrow, decrypted with the keys sealed to the owner
+    EscrowBalance {
+        /// Escrow file of `create-escrow`
+        #[arg(long, value_name = "FILE")]
+        escrow: PathBuf,
+    },
+    /// Release an escrow to one of its parties, signed by the owner and the other party
+    ReleaseEscrow {
+        /// Escrow file of `create-escrow`
+        #[arg(long, value_name = "FILE")]
+        escrow: PathBuf,
+        /// Party receiving the funds, at its associated token account
+        #[arg(long)]
+        to: Pubkey,
+        /// Keypair of the other party, co-signing the release
+        #[arg(long, value_name = "KEYPAIR")]
+        co_signer: PathBuf,
+        /// Amount in tokens, e.g. 12.5, or in base units with a `raw` suffix, e.g. 12500000raw
+        /// [default: the whole escrowed balance]
+        amount: Option<Amount>,
+    },
+    /// Watch the owner's associated token account and apply incoming credits automatically
+    WatchAndApply {
+        /// Mint of the token account [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Pending credits that trigger an apply, capped at the account's maximum
+        #[arg(long, default_value_t = 1)]
+        threshold: u64,
+        /// Seconds to wait before resubscribing after the subscription drops or an apply fails
+        #[arg(long, value_name = "SECONDS", default_value_t = 5)]
+        retry_delay: u64,
+        /// POST a signed JSON notification of every incoming credit to this URL; the signing
+        /// secret is read from CT_WEBHOOK_SECRET
+        #[arg(long, value_name = "URL")]
+        webhook_url: Option<String>,
+        /// Serve Prometheus metrics at `GET /metrics` on this address
+        #[arg(long, value_name = "ADDRESS")]
+        metrics_bind: Option<SocketAddr>,
+    },
+    /// Empty the owner's associated token account and close it to reclaim its rent
+    CloseAccount {
+        /// Mint of the token account [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Recipient of the rent [default: the owner, or the first multisig signer]
+        #[arg(long, value_name = "ADDRESS")]
+        destination: Option<Pubkey>,
+    },
+    /// Replace the keys of the owner's associated token account with fresh random ones, stored in
+    /// the keystore
+    ///
+    /// The account is emptied, closed and recreated with the new keys; its public and confidential
+    /// balances are moved back afterwards.
+    RotateKeys {
+        /// Mint of the token account [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+    },
+    /// Allow incoming credits of the given kinds on the owner's associated token account
+    EnableCredits {
+        /// Mint of the token account [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Confidential transfers, mints and deposits
+        #[arg(long, required_unless_present = "non_confidential")]
+        confidential: bool,
+        /// Public transfers and mints
+        #[arg(long)]
+        non_confidential: bool,
+    },
+    /// Reject incoming credits of the given kinds on the owner's associated token account
+    DisableCredits {
+        /// Mint of the token account [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Confidential transfers, mints and deposits
+        #[arg(long, required_unless_present = "non_confidential")]
+        confidential: bool,
+        /// Public transfers and mints
+        #[arg(long)]
+        non_confidential: bool,
+    },
+    /// Require a memo on every incoming transfer to the owner's associated token account
+    EnableRequiredMemos {
+        /// Mint of the token account [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+    },
+    /// Accept incoming transfers without a memo again
+    DisableRequiredMemos {
+        /// Mint of the token account [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+    },
+    /// Show the public, pending and available balances of the owner's associated token account
+    Balance {
+        /// Mint of the token account [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+    },
+    /// Decode a token account and its ConfidentialTransferAccount extension
+    InspectAccount {
+        /// Address of the token account
+        address: Pubkey,
+    },
+    /// Decode a mint, its ConfidentialTransferMint extension and its other extensions
+    InspectMint {
+        /// Address or symbol of the mint [default: `mint` from the config file]
+        address: Option<MintRef>,
+    },
+    /// Decrypt the amounts of the confidential transfers in a transaction with the auditor key
+    Audit {
+        /// ElGamal keypair file of the mint auditor
+        #[arg(long, value_name = "FILE")]
+        auditor_keypair: PathBuf,
+        /// Signature of the transfer transaction
+        signature: Signature,
+    },
+    /// Harvest the withheld confidential transfer fees of the mint's accounts into the mint
+    HarvestFees {
+        /// Mint with transfer fees [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// Token accounts to harvest; without any, every account holding withheld fees
+        accounts: Vec<Pubkey>,
+    },
+    /// Withdraw the fees withheld in the mint to the mint authority's associated token account
+    WithdrawFees {
+        /// Mint with transfer fees [default: `mint` from the config file]
+        #[arg(long)]
+        mint: Option<MintRef>,
+        /// ElGamal keypair file withheld fees are encrypted to, as given to create-mint
+        #[arg(long, value_name = "FILE")]
+        withheld_keypair: PathBuf,
+    },
+    /// Generate the wallet keypair if it does not exist yet and fund it with airdrops on
+    /// localnet, devnet or testnet
+    Bootstrap {
+        /// Balance to reach, in SOL
+        #[arg(long, value_name = "SOL", default_value = "2", value_parser = parse_sol)]
+        target: u64,
+        /// Largest single airdrop request, in SOL; public faucets cap requests
+        #[arg(long, value_name = "SOL", default_value = "1", value_parser = parse_sol)]
+        airdrop: u64,
+    },
+    /// Run the full mint -> configure -> deposit -> apply -> withdraw flow
+    Demo {
+        /// File recording the progress, for `resume`
+        /// [default: ~/.local/share/confidential-transfer/demo-state.json]
+        #[arg(long, value_name = "FILE")]
+        state: Option<PathBuf>,
+    },
+    /// Continue an interrupted demo run from its first unfinished step
+    Resume {
+        /// File recording the progress [default: the demo default]
+        #[arg(long, value_name = "FILE")]
+        state: Option<PathBuf>,
+    },
+    /// Write the owner's keys to a passphrase-encrypted backup: the keys in the keystore with
+    /// --keystore, plus those of the owner's token accounts for every --mint
+    BackupKeys {
+        /// Backup file to create
+        #[arg(long, value_name = "FILE")]
+        output: PathBuf,
+        /// Mint of a token account of the owner to include; repeat for several
+        #[arg(long)]
+        mint: Vec<MintRef>,
+    },
+    /// Restore the keys of a backup into the keystore selected with --keystore
+    RestoreKeys {
+        /// Backup file to restore
+        #[arg(long, value_name = "FILE")]
+        input: PathBuf,
+    },
+    /// Serve deposits, transfers, withdrawals and balances of the owner's accounts over HTTP, and
+    /// gRPC with --grpc, with their keys looked up in the keystore selected with --keystore
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        bind: SocketAddr,
+        /// Also serve the gRPC API on this address, streaming the progress of each operation
+        /// (`grpc` feature)
+        #[arg(long, value_name = "ADDRESS")]
+        grpc: Option<SocketAddr>,
+    },
+    /// Show the owner's accounts in a terminal dashboard: decrypted balances, pending credits and
+    /// a form sending confidential transfers (`tui` feature)
+    Tui {
+        /// Mint of a token account of the owner to show; repeat for several [default: every
+        /// account of the owner in the keyring, or `mint` from the config file]
+        #[arg(long)]
+        mint: Vec<MintRef>,
+    },
+    /// Print the completion script of a shell, e.g. `source <(confidential-transfer completions
+    /// bash)`
+    Completions {
+        /// Shell to complete commands in
+        shell: Shell,
+    },
+    /// Generate the man pages of the command and each subcommand
+    Mangen {
+        /// Write a page per command into this directory [default: print the page of the command
+        /// to stdout]
+        #[arg(long, value_name = "DIR")]
+        out_dir: Option<PathBuf>,
+    },
+}
+
+/// Operation of the proofs made by `generate-proofs`.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofKind {
+    /// Pubkey validity proof configuring the account
+    PubkeyValidity,
+    /// Equality and range proofs of a withdraw
+    Withdraw,
+    /// Equality, ciphertext validity and range proofs of a transfer, plus the fee proofs on mints
+    /// with transfer fees
+    Transfer,
+}
+
+// Parses a compute unit limit: a number of units, or `simulated`
+fn parse_compute_unit_limit(value: &str) -> Result<ComputeUnitLimit, String> {
+    if value == "simulated" {
+        return Ok(ComputeUnitLimit::Simulated);
+    }
+    value
+        .parse()
+        .map(ComputeUnitLimit::Static)
+        .map_err(|_| format!("invalid compute unit limit: {value}; expected units or `simulated`"))
+}
+
+// Parses a signature produced elsewhere, given as `<PUBKEY>=<SIGNATURE>`
+fn parse_signer_signature(value: &str) -> Result<(Pubkey, Signature), String> {
+    let (signer, signature) = value
+        .split_once('=')
+        .ok_or_else(|| format!("invalid signature: {value}; expected <PUBKEY>=<SIGNATURE>"))?;
+    Ok((
+        signer
+            .parse()
+            .map_err(|_| format!("invalid pubkey: {signer}"))?,
+        signature
+            .parse()
+            .map_err(|_| format!("invalid signature: {signature}"))?,
+    ))
+}
+
+// Parses an amount of SOL into lamports
+fn parse_sol(value: &str) -> Result<u64, String> {
+    sol_str_to_lamports(value).ok_or_else(|| format!("invalid SOL amount: {value}"))
+}
@@ -0,0 +1,784 @@
+use anyhow::{Result, anyhow, bail};
+use futures::{StreamExt, stream::BoxStream};
+use solana_banks_client::BanksClient;
+use solana_client::{
+    client_error::ClientError,
+    nonblocking::{nonce_utils, pubsub_client::PubsubClient, rpc_client::RpcClient},
+    rpc_client::SerializableTransaction,
+    rpc_config::{RpcSendTransactionConfig, RpcSignatureSubscribeConfig},
+    rpc_response::{Response, RpcSignatureResult, RpcSimulateTransactionResult},
+};
+use solana_sdk::{
+    commitment_config::{CommitmentConfig, CommitmentLevel},
+    hash::Hash,
+    instruction::CompiledInstruction,
+    message::VersionedMessage,
+    pubkey::Pubkey,
+    signature::Signature,
+    system_instruction::SystemInstruction,
+    transaction::{Transaction, TransactionError, VersionedTransaction, uses_durable_nonce},
+};
+use solana_sdk_ids::system_program;
+use spl_token_client::{
+    client::{
+        ProgramBanksClient, ProgramClient, ProgramClientResult, ProgramRpcClient,
+        ProgramRpcClientSendTransaction, RpcClientResponse, SendTransaction,
+        SendTransactionBanksClient, SendTransactionRpc, SimulateTransaction,
+        SimulateTransactionBanksClient, SimulateTransactionRpc,
+    },
+    spl_token_2022::solana_zk_sdk::zk_elgamal_proof_program::{
+        self, instruction::ProofInstruction,
+    },
+    token::{ComputeUnitLimit, TokenError, TokenResult},
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::time::{sleep, timeout};
+use tracing::{debug, info, warn};
+
+use crate::{
+    TokenClient,
+    diagnose::explain_failure,
+    metrics,
+    offline::{fee_payer_signature, missing_signers, write_transaction},
+    progress::{self, Progress},
+};
+
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+type SignatureNotifications<'a> = BoxStream<'a, Response<RpcSignatureResult>>;
+
+/// How long [`resend_on_expiry`] keeps resending a transaction by default.
+pub const DEFAULT_RESEND_DEADLINE: Duration = Duration::from_secs(120);
+//Backoff between resends, doubling from the first to the last
+const RESEND_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const RESEND_MAX_BACKOFF: Duration = Duration::from_secs(8);
+
+/// Margin added by default to the compute units consumed in simulation, in percent.
+pub const DEFAULT_COMPUTE_UNIT_MARGIN_PERCENT: u64 = 10;
+//Largest compute unit limit a transaction can set
+const MAX_COMPUTE_UNIT_LIMIT: u64 = 1_400_000;
+//Wait between status polls, and between blockhash checks while subscribed
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Transaction sender behind every [`TokenClient`](crate::TokenClient).
+///
+/// Sends and confirms transactions, or in dry-run mode only simulates them: the compute units
+/// and program logs of each simulation are logged and the would-be signature is returned, so
+/// callers see the same [`RpcClientResponse::Signature`] either way.
+///
+/// The time spent sending and confirming is cumulated in [`RpcSender::timings`], shared by the
+/// clones of a sender.
+///
+/// Simulations run to estimate compute units, for [`ComputeUnitLimit::Simulated`], report the
+/// units consumed plus [`RpcSender::compute_unit_margin_percent`], so the limit set from them
+/// leaves room for the variations between simulation and execution.
+///
+/// With a [`RpcSender::pubsub_client`], transactions are confirmed by a signature subscription,
+/// notified as soon as they land, instead of polling their status; a failed subscription falls
+/// back to polling.
+///
+/// Transactions are confirmed at the commitment of the RPC client they are sent through.
+#[derive(Debug, Clone, Default)]
+pub struct RpcSender {
+    /// Simulate instead of broadcasting.
+    pub dry_run: bool,
+    /// Margin added to the compute units consumed in simulation, in percent.
+    pub compute_unit_margin_percent: u64,
+    /// Send without the preflight simulation of the RPC node, so a failing transaction is only
+    /// reported once it lands, fee paid.
+    pub skip_preflight: bool,
+    /// Commitment the preflight simulation runs at; the commitment of the RPC client when unset.
+    pub preflight_commitment: Option<CommitmentLevel>,
+    /// Websocket client confirming transactions, if any; they are polled otherwise.
+    pub pubsub_client: Option<Arc<PubsubClient>>,
+    /// Directory transactions are written to instead of being sent, signed by the signers at
+    /// hand, to be completed offline; see [`offline`](crate::offline).
+    pub sign_offline: Option<PathBuf>,
+    timings: Arc<Mutex<TransactionTimings>>,
+    fees: Arc<Mutex<FeeEstimate>>,
+}
+
+impl RpcSender {
+    /// Sender broadcasting transactions, or only simulating them with `dry_run`, with the
+    /// [default compute unit margin](DEFAULT_COMPUTE_UNIT_MARGIN_PERCENT).
+    pub fn new(dry_run: bool) -> Self {
+        Self {
+            dry_run,
+            compute_unit_margin_percent: DEFAULT_COMPUTE_UNIT_MARGIN_PERCENT,
+            ..Self::default()
+        }
+    }
+
+    /// Time spent sending and confirming transactions so far, by this sender and its clones.
+    pub fn timings(&self) -> TransactionTimings {
+        *self.timings.lock().unwrap()
+    }
+
+    /// Cost of the transactions simulated so far in a dry run, by this sender and its clones.
+    pub fn fees(&self) -> FeeEstimate {
+        self.fees.lock().unwrap().clone()
+    }
+
+    /// Sends `transaction`, e.g. a v0 transaction the token client cannot build, the way the
+    /// transactions of a [`TokenClient`](crate::TokenClient) are: confirmed and timed, or only
+    /// simulated in a dry run.
+    pub async fn send_versioned(
+        &self,
+        client: &RpcClient,
+        transaction: &VersionedTransaction,
+    ) -> Result<Signature> {
+        if self.dry_run {
+            self.estimate_fee(client, transaction.message.clone())
+                .await
+                .map_err(|e| anyhow!(e))?;
+        }
+        self.submit(client, transaction, None)
+            .await
+            .map_err(|e| anyhow!(e))
+    }
+
+    // Adds the fee of a transaction of `message`, and the rent of the accounts it creates or
+    // closes, to the fee estimate of a dry run
+    async fn estimate_fee(
+        &self,
+        client: &RpcClient,
+        mut message: VersionedMessage,
+    ) -> ProgramClientResult<()> {
+        //The fee does not depend on the blockhash, which the node may not know, e.g. a nonce
+        message.set_recent_blockhash(client.get_latest_blockhash().await?);
+        let fee = match &message {
+            VersionedMessage::Legacy(message) => client.get_fee_for_message(message).await?,
+            VersionedMessage::V0(message) => client.get_fee_for_message(message).await?,
+        };
+        debug!(fee, "Estimated transaction fee");
+        self.fees.lock().unwrap().add(&message, fee);
+        Ok(())
+    }
+
+    // Sends and confirms `transaction`, or only simulates it in a dry run, and returns its
+    // signature; `nonce_account` is the durable nonce account it is built on, if any
+    async fn submit(
+        &self,
+        client: &RpcClient,
+        transaction: &(impl SubmittedTransaction + Sync),
+        nonce_account: Option<Pubkey>,
+    ) -> ProgramClientResult<Signature> {
+        if self.dry_run {
+            let started = Instant::now();
+            let result = client.simulate_transaction(transaction).await?.value;
+            self.record(1, started.elapsed(), Duration::ZERO);
+            let signature = *transaction.get_signature();
+            let logs = result.logs.unwrap_or_default().join("\n");
+            match result.err {
+                //Later steps of a flow usually depend on accounts created by earlier ones, so a
+                //failed simulation is reported without aborting the dry run
+                Some(err) => warn!(
+                    %signature,
+                    units_consumed = result.units_consumed,
+                    "Simulation failed: {err}\n{logs}"
+                ),
+                None => info!(
+                    %signature,
+                    units_consumed = result.units_consumed,
+                    "Simulated transaction\n{logs}"
+                ),
+            }
+            return Ok(signature);
+        }
+        //Subscribed before sending, so the notification cannot be missed
+        let subscription = match &self.pubsub_client {
+            Some(pubsub_client) => {
+                let config = RpcSignatureSubscribeConfig {
+                    commitment: Some(client.commitment()),
+                    enable_received_notification: Some(false),
+                };
+                match pubsub_client
+                    .signature_subscribe(transaction.get_signature(), Some(config))
+                    .await
+                {
+                    Ok(subscription) => Some(subscription),
+                    Err(err) => {
+                        warn!("Signature subscription failed, polling instead: {err}");
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        //Same as RpcClient::send_and_confirm_transaction, with each half timed
+        let started = Instant::now();
+        let config = RpcSendTransactionConfig {
+            skip_preflight: self.skip_preflight,
+            preflight_commitment: Some(
+                self.preflight_commitment
+                    .unwrap_or(client.commitment().commitment),
+            ),
+            ..RpcSendTransactionConfig::default()
+        };
+        let signature = match client
+            .send_transaction_with_config(transaction, config)
+            .await
+        {
+            Ok(signature) => signature,
+            //A resend of a transaction that landed, e.g. once a timed out send is retried
+            Err(err) if err.get_transaction_error() == Some(TransactionError::AlreadyProcessed) => {
+                debug!(signature = %transaction.get_signature(), "Transaction already processed");
+                *transaction.get_signature()
+            }
+            Err(err) => {
+                let message = transaction.versioned_message();
+                return Err(explain_failure(client, transaction, &message, err.into()).await);
+            }
+        };
+        let send = started.elapsed();
+        metrics::transaction_sent();
+        progress::report(Progress::Sent { signature });
+        let started = Instant::now();
+        let confirmed = match subscription {
+            Some((notifications, unsubscribe)) => {
+                let confirmed =
+                    confirm(client, transaction, nonce_account, Some(notifications)).await;
+                unsubscribe().await;
+                confirmed
+            }
+            None => confirm(client, transaction, nonce_account, None).await,
+        };
+        if let Err(err) = confirmed {
+            let message = transaction.versioned_message();
+            return Err(explain_failure(client, transaction, &message, err).await);
+        }
+        let confirm = started.elapsed();
+        metrics::observe_confirmation(confirm);
+        self.record(1, send, confirm);
+        progress::report(Progress::Confirmed { signature });
+        debug!(%signature, ?send, ?confirm, "Transaction confirmed");
+        Ok(signature)
+    }
+
+    // Adds `transactions`, sent and confirmed together, to the cumulated timings
+    pub(crate) fn record(&self, transactions: u32, send: Duration, confirm: Duration) {
+        let mut timings = self.timings.lock().unwrap();
+        timings.transactions += transactions;
+        timings.send += send;
+        timings.confirm += confirm;
+    }
+}
+
+/// Error of a transaction that did not land before its blockhash expired: it never will, so it
+/// can be signed again on a new blockhash, see [`resend_on_expiry`].
+#[derive(Debug, Clone, Copy)]
+pub struct BlockhashExpired;
+
+impl fmt::Display for BlockhashExpired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Unable to confirm transaction: its blockhash expired")
+    }
+}
+
+impl std::error::Error for BlockhashExpired {}
+
+/// Runs `send`, which builds, signs and sends a transaction on a fresh blockhash, e.g. with
+/// [`Token::process_ixs`](spl_token_client::token::Token::process_ixs), again whenever the
+/// blockhash expires before the transaction lands or the cluster does not know it yet.
+///
+/// Attempts are spaced by a backoff doubling from half a second up to 8 seconds; the last
+/// error is returned once `deadline` has passed. Other errors are returned right away.
+pub async fn resend_on_expiry<T, F, Fut>(deadline: Duration, mut send: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = TokenResult<T>>,
+{
+    let started = Instant::now();
+    let mut backoff = RESEND_INITIAL_BACKOFF;
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match send().await {
+            Err(TokenError::Client(err)) if is_expired(err.as_ref()) => {
+                if started.elapsed() + backoff > deadline {
+                    bail!("{err} (gave up after {attempt} attempts)");
+                }
+                warn!(attempt, ?backoff, "{err}; resending on a new blockhash");
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(RESEND_MAX_BACKOFF);
+            }
+            result => return Ok(result?),
+        }
+    }
+}
+
+// Whether `err` means the transaction can be signed again on a new blockhash: it expired while
+// waiting for confirmation, or the node it was sent to has not seen its blockhash yet
+fn is_expired(err: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    err.is::<BlockhashExpired>()
+        || err.downcast_ref::<ClientError>().is_some_and(|err| {
+            err.get_transaction_error() == Some(TransactionError::BlockhashNotFound)
+        })
+}
+
+/// Cost of the transactions simulated by a dry run of an [`RpcSender`], in lamports: their fees,
+/// as quoted by `getFeeForMessage`, and the rent deposited into the accounts they create, which
+/// the closes of proof context accounts refund.
+#[derive(Debug, Clone, Default)]
+pub struct FeeEstimate {
+    /// Transactions simulated.
+    pub transactions: u32,
+    /// Fees of the transactions, signatures and priority fees included.
+    pub fee_lamports: u64,
+    /// Rent deposited into the accounts the transactions create.
+    pub rent_lamports: u64,
+    /// Rent refunded by the closes of proof context accounts the transactions created.
+    pub refunded_rent_lamports: u64,
+    //Rent of the accounts created so far, until they are closed
+    created: HashMap<Pubkey, u64>,
+}
+
+impl FeeEstimate {
+    /// Lamports the transactions cost once the refunds are received.
+    pub fn net_lamports(&self) -> u64 {
+        (self.fee_lamports + self.rent_lamports).saturating_sub(self.refunded_rent_lamports)
+    }
+
+    // Adds a transaction of `message` paying `fee`, with the rent of the accounts it creates
+    // and of the proof context accounts it closes
+    fn add(&mut self, message: &VersionedMessage, fee: u64) {
+        self.transactions += 1;
+        self.fee_lamports += fee;
+        //New accounts are signers and proof accounts are never in lookup tables, so the static
+        //keys are enough
+        let keys = message.static_account_keys();
+        let account = |instruction: &CompiledInstruction, index: usize| {
+            instruction
+                .accounts
+                .get(index)
+                .and_then(|key| keys.get(usize::from(*key)))
+        };
+        for instruction in message.instructions() {
+            let Some(program) = keys.get(usize::from(instruction.program_id_index)) else {
+                continue;
+            };
+            if *program == system_program::id()
+                && let Ok(SystemInstruction::CreateAccount { lamports, .. }) =
+                    bincode::deserialize(&instruction.data)
+                && let Some(created) = account(instruction, 1)
+            {
+                self.rent_lamports += lamports;
+                self.created.insert(*created, lamports);
+            } else if *program == zk_elgamal_proof_program::id()
+                && matches!(
+                    ProofInstruction::instruction_type(&instruction.data),
+                    Some(ProofInstruction::CloseContextState)
+                )
+                && let Some(lamports) =
+                    account(instruction, 0).and_then(|closed| self.created.remove(closed))
+            {
+                self.refunded_rent_lamports += lamports;
+            }
+        }
+    }
+}
+
+/// Time spent sending and confirming transactions through an [`RpcSender`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TransactionTimings {
+    /// Transactions sent, or simulated in a dry run.
+    pub transactions: u32,
+    /// Time spent submitting them, preflight simulation included.
+    pub send: Duration,
+    /// Time spent waiting for their confirmation.
+    pub confirm: Duration,
+}
+
+impl TransactionTimings {
+    /// Timings of the transactions sent since `earlier`, an earlier reading of the same sender.
+    pub fn since(&self, earlier: &TransactionTimings) -> TransactionTimings {
+        TransactionTimings {
+            transactions: self.transactions - earlier.transactions,
+            send: self.send.saturating_sub(earlier.send),
+            confirm: self.confirm.saturating_sub(earlier.confirm),
+        }
+    }
+}
+
+/// Time an operation spent in each of its stages.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StageTimings {
+    /// Proof generation.
+    pub proof_generation: Duration,
+    /// Everything else before sending: account fetches, instruction building and signing.
+    pub transaction_build: Duration,
+    /// Submission of the transactions.
+    pub send: Duration,
+    /// Waiting for their confirmation.
+    pub confirm: Duration,
+    /// Number of transactions sent.
+    pub transactions: u32,
+}
+
+impl StageTimings {
+    /// Splits `elapsed`, the duration of an operation that spent `proof_generation` generating
+    /// proofs and sent `transactions`, into stages; the rest is counted as transaction building.
+    pub fn split(
+        elapsed: Duration,
+        proof_generation: Duration,
+        transactions: TransactionTimings,
+    ) -> Self {
+        Self {
+            proof_generation,
+            transaction_build: elapsed
+                .saturating_sub(proof_generation)
+                .saturating_sub(transactions.send)
+                .saturating_sub(transactions.confirm),
+            send: transactions.send,
+            confirm: transactions.confirm,
+            transactions: transactions.transactions,
+        }
+    }
+}
+
+impl SendTransaction for RpcSender {
+    type Output = RpcClientResponse;
+}
+
+impl SendTransactionRpc for RpcSender {
+    fn send<'a>(
+        &self,
+        client: &'a RpcClient,
+        transaction: &'a Transaction,
+    ) -> BoxFuture<'a, ProgramClientResult<Self::Output>> {
+        let sender = self.clone();
+        Box::pin(async move {
+            if let Some(dir) = &sender.sign_offline {
+                //The fee payer's signature is the one the transaction is reported and tracked by
+                let signature = fee_payer_signature(transaction)?;
+                let path = write_transaction(dir, transaction)?;
+                let missing = missing_signers(transaction)
+                    .iter()
+                    .map(Pubkey::to_string)
+                    .collect::<Vec<_>>();
+                info!(
+                    path = %path.display(),
+                    missing = %missing.join(", "),
+                    "Transaction written for offline signing"
+                );
+                return Ok(RpcClientResponse::Signature(signature));
+            }
+            if sender.dry_run {
+                sender
+                    .estimate_fee(
+                        client,
+                        VersionedMessage::Legacy(transaction.message.clone()),
+                    )
+                    .await?;
+            } else if !transaction.is_signed() {
+                return Err("Cannot send transaction: not fully signed".into());
+            }
+            let nonce_account = uses_durable_nonce(transaction).map(|advance_nonce| {
+                transaction.message.account_keys[usize::from(advance_nonce.accounts[0])]
+            });
+            let signature = sender.submit(client, transaction, nonce_account).await?;
+            Ok(RpcClientResponse::Signature(signature))
+        })
+    }
+}
+
+// Transactions an `RpcSender` submits, with the message their failures are explained from
+trait SubmittedTransaction: SerializableTransaction {
+    fn versioned_message(&self) -> VersionedMessage;
+}
+
+impl SubmittedTransaction for Transaction {
+    fn versioned_message(&self) -> VersionedMessage {
+        VersionedMessage::Legacy(self.message.clone())
+    }
+}
+
+impl SubmittedTransaction for VersionedTransaction {
+    fn versioned_message(&self) -> VersionedMessage {
+        self.message.clone()
+    }
+}
+
+// Waits until `transaction` lands, as notified by its signature subscription `notifications`,
+// or else as polled, and fails once it can no longer land; `nonce_account` is the durable nonce
+// account it is built on, if any
+async fn confirm(
+    client: &RpcClient,
+    transaction: &(impl SerializableTransaction + Sync),
+    nonce_account: Option<Pubkey>,
+    mut notifications: Option<SignatureNotifications<'_>>,
+) -> ProgramClientResult<()> {
+    let signature = transaction.get_signature();
+    loop {
+        match &mut notifications {
+            Some(stream) => match timeout(EXPIRY_CHECK_INTERVAL, stream.next()).await {
+                Ok(Some(Response {
+                    value: RpcSignatureResult::ProcessedSignature(result),
+                    ..
+                })) => {
+                    return match result.err {
+                        Some(err) => Err(err.into()),
+                        None => Ok(()),
+                    };
+                }
+                Ok(Some(_)) => continue,
+                Ok(None) => {
+                    warn!(%signature, "Signature subscription closed, polling instead");
+                    notifications = None;
+                    continue;
+                }
+                //No notification yet: check the blockhash below
+                Err(_) => {}
+            },
+            None => match client.get_signature_status(signature).await? {
+                Some(Ok(())) => return Ok(()),
+                Some(Err(err)) => return Err(err.into()),
+                None => {}
+            },
+        }
+        if !can_land(client, transaction.get_recent_blockhash(), nonce_account).await? {
+            //It may have landed since its status was read
+            return match client.get_signature_status(signature).await? {
+                Some(Ok(())) => Ok(()),
+                Some(Err(err)) => Err(err.into()),
+                None => Err(BlockhashExpired.into()),
+            };
+        }
+        if notifications.is_none() {
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+// Whether a transaction built on `recent_blockhash` can still land: the blockhash is still
+// recent or, for a transaction built on the durable nonce account `nonce_account`, the account
+// still holds it as its nonce
+async fn can_land(
+    client: &RpcClient,
+    recent_blockhash: &Hash,
+    nonce_account: Option<Pubkey>,
+) -> ProgramClientResult<bool> {
+    let Some(nonce_account) = nonce_account else {
+        return Ok(client
+            .is_blockhash_valid(recent_blockhash, CommitmentConfig::processed())
+            .await?);
+    };
+    let account = nonce_utils::get_account(client, &nonce_account).await?;
+    Ok(nonce_utils::data_from_account(&account)?.blockhash() == *recent_blockhash)
+}
+
+impl SimulateTransaction for RpcSender {
+    type SimulationOutput = RpcClientResponse;
+}
+
+impl SimulateTransactionRpc for RpcSender {
+    fn simulate<'a>(
+        &self,
+        client: &'a RpcClient,
+        transaction: &'a Transaction,
+    ) -> BoxFuture<'a, ProgramClientResult<Self::SimulationOutput>> {
+        let (dry_run, margin_percent) = (self.dry_run, self.compute_unit_margin_percent);
+        Box::pin(async move {
+            let RpcClientResponse::Simulation(result) = ProgramRpcClientSendTransaction
+                .simulate(client, transaction)
+                .await?
+            else {
+                return Err("Not a simulation result".into());
+            };
+            with_margin(result, dry_run, margin_percent)
+        })
+    }
+}
+
+impl SendTransactionBanksClient for RpcSender {
+    fn send<'a>(
+        &self,
+        client: &'a mut BanksClient,
+        transaction: Transaction,
+    ) -> BoxFuture<'a, ProgramClientResult<Self::Output>> {
+        let sender = self.clone();
+        Box::pin(async move {
+            let signature = transaction.signatures[0];
+            let started = Instant::now();
+            if sender.dry_run {
+                let result = client.simulate_transaction(transaction).await?;
+                sender.record(1, started.elapsed(), Duration::ZERO);
+                match result.result {
+                    Some(Err(err)) => warn!(%signature, "Simulation failed: {err}"),
+                    _ => info!(%signature, "Simulated transaction"),
+                }
+            } else {
+                //Banks process a transaction and confirm it in the same call
+                client.process_transaction(transaction).await?;
+                sender.record(1, started.elapsed(), Duration::ZERO);
+            }
+            Ok(RpcClientResponse::Signature(signature))
+        })
+    }
+}
+
+impl SimulateTransactionBanksClient for RpcSender {
+    fn simulate<'a>(
+        &self,
+        client: &'a mut BanksClient,
+        transaction: Transaction,
+    ) -> BoxFuture<'a, ProgramClientResult<Self::SimulationOutput>> {
+        let (dry_run, margin_percent) = (self.dry_run, self.compute_unit_margin_percent);
+        Box::pin(async move {
+            let simulation = client.simulate_transaction(transaction).await?;
+            let details = simulation.simulation_details;
+            let result = RpcSimulateTransactionResult {
+                err: simulation.result.and_then(Result::err),
+                logs: details.as_ref().map(|details| details.logs.clone()),
+                accounts: None,
+                units_consumed: details.as_ref().map(|details| details.units_consumed),
+                loaded_accounts_data_size: details
+                    .as_ref()
+                    .map(|details| details.loaded_accounts_data_size),
+                return_data: None,
+                inner_instructions: None,
+                replacement_blockhash: None,
+            };
+            with_margin(result, dry_run, margin_percent)
+        })
+    }
+}
+
+// Simulation `result` with the units consumed raised by `margin_percent`, to be used as the
+// compute unit limit; a failed simulation is an error unless in a dry run
+fn with_margin(
+    mut result: RpcSimulateTransactionResult,
+    dry_run: bool,
+    margin_percent: u64,
+) -> ProgramClientResult<RpcClientResponse> {
+    //A failing transaction consumes fewer units than it needs, so its limit would be off; dry
+    //runs report the failure when sending instead
+    if let Some(err) = &result.err
+        && !dry_run
+    {
+        let logs = result.logs.clone().unwrap_or_default().join("\n");
+        return Err(format!("Transaction simulation failed: {err}\n{logs}").into());
+    }
+    if let Some(units_consumed) = result.units_consumed {
+        let limit = units_consumed
+            .saturating_add(units_consumed * margin_percent / 100)
+            .min(MAX_COMPUTE_UNIT_LIMIT);
+        debug!(units_consumed, limit, "Estimated compute units");
+        result.units_consumed = Some(limit);
+    }
+    Ok(RpcClientResponse::Simulation(result))
+}
+
+/// Wraps `rpc_client` into the program client used to build [`TokenClient`](crate::TokenClient)s.
+pub fn program_client(
+    rpc_client: Arc<RpcClient>,
+    sender: RpcSender,
+) -> Arc<dyn ProgramClient<RpcSender>> {
+    Arc::new(ProgramRpcClient::new(rpc_client, sender))
+}
+
+/// Wraps `banks_client`, e.g. of a `solana-program-test` bank, into a program client building
+/// [`TokenClient`](crate::TokenClient)s, so every operation of this crate runs against it as
+/// against a cluster.
+///
+/// Transactions are processed and confirmed in one call, so no confirmation time is recorded.
+pub fn banks_program_client(
+    banks_client: BanksClient,
+    sender: RpcSender,
+) -> Arc<dyn ProgramClient<RpcSender>> {
+    Arc::new(ProgramBanksClient::new_from_client(
+        Arc::new(tokio::sync::Mutex::new(banks_client)),
+        sender,
+    ))
+}
+
+/// Compute budget instructions added to every transaction of a [`TokenClient`].
+///
+/// Confidential transfer proofs are expensive to verify, so under congestion transactions only
+/// land with a priority fee; the fee paid is the unit price times the unit limit, so a limit
+/// close to what the transactions use keeps it low.
+#[derive(Debug, Clone)]
+pub struct ComputeBudget {
+    /// Compute unit limit of each transaction: none, so the runtime default applies, a fixed
+    /// one, or the units the transaction consumes in simulation plus a margin (see
+    /// [`RpcSender`]).
+    pub unit_limit: ComputeUnitLimit,
+    /// Priority fee in micro-lamports per compute unit, if any.
+    pub unit_price: Option<u64>,
+}
+
+impl Default for ComputeBudget {
+    fn default() -> Self {
+        Self {
+            unit_limit: ComputeUnitLimit::Default,
+            unit_price: None,
+        }
+    }
+}
+
+impl ComputeBudget {
+    /// Makes `token` add the compute budget instructions to every transaction it sends.
+    ///
+    /// Transactions that set their own limit, like those of
+    /// [`Packing::Packed`](crate::placement::Packing::Packed), fail with a duplicate instruction
+    /// when the budget has a limit too.
+    pub fn apply(&self, token: TokenClient) -> TokenClient {
+        let token = token.with_compute_unit_limit(self.unit_limit.clone());
+        match self.unit_price {
+            Some(unit_price) => token.with_compute_unit_price(unit_price),
+            None => token,
+        }
+    }
+}
+
+/// Priority fee, in micro-lamports per compute unit, at `percentile` of the fees paid in the
+/// recent slots by transactions writing to `accounts`, or by all transactions if empty.
+///
+/// The RPC node keeps the fees of the last 150 slots; slots without any prioritized transaction
+/// count as 0, so low percentiles are usually 0.
+pub async fn recent_priority_fee(
+    rpc_client: &RpcClient,
+    accounts: &[Pubkey],
+    percentile: u8,
+) -> Result<u64> {
+    if percentile > 100 {
+        bail!("Invalid percentile {percentile}; expected 0 to 100");
+    }
+    let mut fees = rpc_client
+        .get_recent_prioritization_fees(accounts)
+        .await?
+        .into_iter()
+        .map(|fee| fee.prioritization_fee)
+        .collect::<Vec<_>>();
+    if fees.is_empty() {
+        return Ok(0);
+    }
+    fees.sort_unstable();
+    let index = (fees.len() - 1) * usize::from(percentile) / 100;
+    debug!(
+        slots = fees.len(),
+        percentile,
+        fee = fees[index],
+        "Fetched recent prioritization fees"
+    );
+    Ok(fees[index])
+}
+
+/// Commitment of `rpc_client` for `getSignaturesForAddress`, which refuses `processed`: raised to
+/// `confirmed` when below it.
+pub fn signature_history_commitment(rpc_client: &RpcClient) -> CommitmentConfig {
+    let commitment = rpc_client.commitment();
+    if commitment.is_at_least_confirmed() {
+        commitment
+    } else {
+        CommitmentConfig::confirmed()
+    }
+}
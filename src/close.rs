@@ -0,0 +1,132 @@
+use anyhow::{Result, anyhow, bail};
+use confidential_transfer_core::balance::decrypt_available_balance;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use spl_token_client::spl_token_2022::{
+    extension::{
+        BaseStateWithExtensions,
+        confidential_transfer::{
+            ConfidentialTransferAccount, account_info::EmptyAccountAccountInfo,
+        },
+        confidential_transfer_fee::ConfidentialTransferFeeAmount,
+        transfer_fee::TransferFeeAmount,
+    },
+    solana_zk_sdk::encryption::{
+        auth_encryption::AeKey, elgamal::ElGamalKeypair, pod::elgamal::PodElGamalCiphertext,
+    },
+};
+use tracing::{debug, instrument};
+
+use crate::{
+    TokenClient, authority::Authority, fees::harvest_withheld_fees, utils::into_signature,
+};
+
+/// Signatures produced by [`empty_and_close_account`].
+#[derive(Debug, Clone)]
+pub struct ClosedAccount {
+    /// Signatures harvesting the withheld fees of the account to the mint, confidential ones
+    /// first, if it held any.
+    pub harvest_signatures: Vec<Signature>,
+    /// Signature of the empty account instruction; `None` if the available balance was never
+    /// credited.
+    pub empty_signature: Option<Signature>,
+    /// Signature of the close account instruction.
+    pub close_signature: Signature,
+    /// Rent in lamports refunded to the destination.
+    pub lamports: u64,
+}
+
+/// Decommissions a confidential token account: zeroes its available balance ciphertext with
+/// `EmptyAccount` and closes it, refunding the rent to `destination`.
+///
+/// The account must hold no tokens: the public balance and the decrypted available balance must
+/// be zero and no credits may be pending, so withdraw and move the tokens out first. Withheld
+/// transfer fees, confidential and public, are harvested to the mint, since harvesting needs no
+/// authority. `EmptyAccount`
+/// carries a zero-ciphertext proof, small enough to be verified in the same transaction.
+#[instrument(skip_all, fields(ata = %ata_pubkey, %destination))]
+pub async fn empty_and_close_account(
+    token: &TokenClient,
+    owner: &Authority,
+    ata_pubkey: &Pubkey,
+    destination: &Pubkey,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+) -> Result<ClosedAccount> {
+    let token_account = token.get_account_info(ata_pubkey).await?;
+    let extension_data = token_account.get_extension::<ConfidentialTransferAccount>()?;
+    if token_account.base.amount > 0 {
+        bail!(
+            "{ata_pubkey} still holds {} public tokens; transfer or burn them first",
+            token_account.base.amount
+        );
+    }
+    if extension_data.pending_balance_lo != PodElGamalCiphertext::default()
+        || extension_data.pending_balance_hi != PodElGamalCiphertext::default()
+    {
+        bail!("{ata_pubkey} has a pending balance; apply and withdraw it first");
+    }
+    let available_balance = decrypt_available_balance(extension_data, aes_key)
+        .ok_or_else(|| anyhow!("Failed to decrypt the available balance of {ata_pubkey}"))?;
+    if available_balance > 0 {
+        bail!("{ata_pubkey} has an available balance of {available_balance}; withdraw it first");
+    }
+
+    //Withheld fees block the close; anyone may move them to the mint
+    let mut harvest_signatures =
+        match token_account.get_extension::<ConfidentialTransferFeeAmount>() {
+            Ok(fee_amount) if fee_amount.withheld_amount != PodElGamalCiphertext::default() => {
+                harvest_withheld_fees(token, &[*ata_pubkey]).await?
+            }
+            _ => vec![],
+        };
+    //Public transfers into the account withhold their fees in the clear
+    let withheld = token_account
+        .get_extension::<TransferFeeAmount>()
+        .map(|fee_amount| u64::from(fee_amount.withheld_amount))
+        .unwrap_or_default();
+    if withheld > 0 {
+        let signature =
+            into_signature(token.harvest_withheld_tokens_to_mint(&[ata_pubkey]).await?)?;
+        debug!(%signature, withheld, "Harvested public withheld fees");
+        harvest_signatures.push(signature);
+    }
+
+    //Accounts never credited still hold the empty ciphertext and need no proof
+    let empty_signature = if extension_data.available_balance != PodElGamalCiphertext::default() {
+        let signature = into_signature(
+            token
+                .confidential_transfer_empty_account(
+                    ata_pubkey,      //Ata to empty
+                    &owner.pubkey(), //Owner of the ata
+                    None,            //Zero-ciphertext proof in the same transaction
+                    Some(EmptyAccountAccountInfo::new(extension_data)),
+                    elgamal_keypair,
+                    owner.signers(),
+                )
+                .await?,
+        )?;
+        debug!(%signature, "Emptied account");
+        Some(signature)
+    } else {
+        None
+    };
+
+    let lamports = token.get_account(*ata_pubkey).await?.lamports;
+    let close_signature = into_signature(
+        token
+            .close_account(
+                ata_pubkey,      //Ata to close
+                destination,     //Destination of the rent
+                &owner.pubkey(), //Owner of the ata
+                owner.signers(),
+            )
+            .await?,
+    )?;
+    debug!(signature = %close_signature, lamports, "Closed account");
+    Ok(ClosedAccount {
+        harvest_signatures,
+        empty_signature,
+        close_signature,
+        lamports,
+    })
+}
@@ -0,0 +1,51 @@
+use anyhow::Result;
+use futures::future::{FutureExt, LocalBoxFuture, join_all};
+use std::future::Future;
+use tracing::warn;
+
+/// Transactions that do not depend on each other, sent concurrently and joined once all of them
+/// are confirmed.
+///
+/// Each send is a future sending and confirming one transaction, e.g. the creation of a proof
+/// context state account; their round trips overlap instead of adding up. A failed send does not
+/// cancel the others: [`ConcurrentSends::join`] waits for every one of them to settle, so no
+/// transaction is left in flight when it returns, and the caller can tell which accounts exist.
+pub struct ConcurrentSends<'a, T> {
+    sends: Vec<LocalBoxFuture<'a, Result<T>>>,
+}
+
+impl<T> Default for ConcurrentSends<'_, T> {
+    fn default() -> Self {
+        Self { sends: vec![] }
+    }
+}
+
+impl<'a, T> ConcurrentSends<'a, T> {
+    /// No sends yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `send`, started when the sends are joined.
+    pub fn push(&mut self, send: impl Future<Output = Result<T>> + 'a) {
+        self.sends.push(send.boxed_local());
+    }
+
+    /// Runs the sends concurrently and returns their results in the order they were pushed once
+    /// all of them have settled, or the first error, the others being logged.
+    pub async fn join(self) -> Result<Vec<T>> {
+        let mut results = vec![];
+        let mut failure = None;
+        for result in join_all(self.sends).await {
+            match result {
+                Ok(result) => results.push(result),
+                Err(err) if failure.is_none() => failure = Some(err),
+                Err(err) => warn!("Concurrent send failed: {err:#}"),
+            }
+        }
+        match failure {
+            Some(err) => Err(err),
+            None => Ok(results),
+        }
+    }
+}
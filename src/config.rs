@@ -0,0 +1,465 @@
+use anyhow::{Context, Result, anyhow, bail};
+use confidential_transfer_core::derivation::SeedMessage;
+use serde::Deserialize;
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+use std::{
+    num::{NonZeroU32, NonZeroUsize},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use crate::{
+    amount::Amount,
+    keyring::MintRef,
+    mint::TOKEN_DECIMALS,
+    retry::RetryPolicy,
+    schedule::{CatchUp, CronSchedule, ScheduledPayment},
+    throttle::RateLimit,
+};
+
+/// RPC endpoint used when neither the config file nor the command line sets one.
+pub const DEFAULT_RPC_URL: &str = "http://localhost:8899";
+
+/// Directory of this client's files, `~/.config/confidential-transfer`.
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|mut path| {
+        path.extend([".config", "confidential-transfer"]);
+        path
+    })
+}
+
+/// Default keypair of the Solana CLI, `~/.config/solana/id.json`.
+pub fn default_keypair_path() -> Result<PathBuf> {
+    let mut path = dirs::home_dir().context("Unable to get home directory")?;
+    path.extend([".config", "solana", "id.json"]);
+    Ok(path)
+}
+
+/// Resolves a cluster moniker to its public RPC URL; anything else is returned unchanged.
+///
+/// Accepts the Solana CLI shorthands: `localnet`/`localhost`/`l`, `devnet`/`d`,
+/// `testnet`/`t` and `mainnet-beta`/`mainnet`/`m`.
+pub fn cluster_url(url_or_moniker: &str) -> String {
+    match url_or_moniker {
+        "localnet" | "localhost" | "l" => DEFAULT_RPC_URL,
+        "devnet" | "d" => "https://api.devnet.solana.com",
+        "testnet" | "t" => "https://api.testnet.solana.com",
+        "mainnet-beta" | "mainnet" | "m" => "https://api.mainnet-beta.solana.com",
+        url => url,
+    }
+    .to_string()
+}
+
+/// Derives the websocket URL of an RPC URL the way the Solana CLI does: `http` becomes `ws`,
+/// `https` becomes `wss`, and an explicit port is incremented (8899 -> 8900).
+pub fn websocket_url(rpc_url: &str) -> String {
+    let (scheme, rest) = match rpc_url.split_once("://") {
+        Some(("https", rest)) => ("wss", rest),
+        Some((_, rest)) => ("ws", rest),
+        None => ("ws", rpc_url),
+    };
+    let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let authority = match authority.rsplit_once(':') {
+        Some((host, port)) => match port.parse::<u16>() {
+            Ok(port) => format!("{host}:{}", port.wrapping_add(1)),
+            Err(_) => authority.to_string(),
+        },
+        None => authority.to_string(),
+    };
+    format!("{scheme}://{authority}{path}")
+}
+
+/// Config file used instead of the default one when `--config` is not given.
+pub const CONFIG_ENV: &str = "CT_CONFIG";
+
+/// Wallet keypair of the Solana CLI, below `CT_KEYPAIR`.
+pub const SOLANA_KEYPAIR_ENV: &str = "SOLANA_KEYPAIR";
+
+//Prefix of the environment variables of earlier versions, still read as aliases
+const LEGACY_ENV_PREFIX: &str = "CONFIDENTIAL_TRANSFER_";
+
+/// Value of the environment variable `name`, or when unset of its alias of earlier versions,
+/// with `CONFIDENTIAL_TRANSFER_` in place of the `CT_` prefix, e.g.
+/// `CONFIDENTIAL_TRANSFER_API_TOKEN` for `CT_API_TOKEN`.
+pub fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().or_else(|| {
+        let alias = format!("{LEGACY_ENV_PREFIX}{}", name.strip_prefix("CT_")?);
+        std::env::var(alias).ok()
+    })
+}
+
+/// Parses a commitment level: `processed`, `confirmed` or `finalized`.
+pub fn parse_commitment(commitment: &str) -> Result<CommitmentConfig> {
+    CommitmentConfig::from_str(commitment)
+        .map_err(|_| anyhow!("Invalid commitment level: {commitment}"))
+}
+
+/// Settings read from `~/.config/confidential-transfer/config.toml`.
+///
+/// Every field is optional; unset fields fall back to the defaults of the accessor methods.
+/// The `CT_*` environment variables of [`Config::apply_env`] override the file, and command line
+/// flags are applied on top of both by overwriting the corresponding field.
+///
+/// ```toml
+/// rpc_url = "devnet" # or any RPC URL
+/// ws_url = "wss://api.devnet.solana.com" # derived from rpc_url when unset
+/// commitment = "confirmed"
+/// keypair_path = "~/.config/solana/id.json"
+/// mint = "<MINT PUBKEY>"
+/// decimals = 9
+/// seed_message = "v1" # or "v2:<TEMPLATE>"
+///
+/// [retry]
+/// max_retries = 5
+/// initial_backoff_ms = 250
+/// max_backoff_ms = 8000
+/// budget = 50
+///
+/// [rate_limit]
+/// max_in_flight = 8
+/// requests_per_second = 40
+///
+/// [[schedule]]
+/// name = "salary-alice"
+/// cron = "0 9 1 * *" # 09:00 UTC on the first of every month
+/// recipient = "<WALLET PUBKEY>"
+/// amount = "50" # in tokens, or "50_000_000 raw" in base units
+/// mint = "<MINT PUBKEY>" # or a keyring symbol; `mint` above when unset
+/// memo = "Salary"
+/// catch_up = "all" # or "latest" or "skip"
+/// ```
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// JSON-RPC endpoint of the cluster, or a cluster moniker (see [`cluster_url`]).
+    pub rpc_url: Option<String>,
+    /// Websocket endpoint for subscriptions; derived from the RPC URL when unset.
+    pub ws_url: Option<String>,
+    /// Commitment level (`processed`, `confirmed` or `finalized`).
+    pub commitment: Option<String>,
+    /// Path of the payer keypair file.
+    pub keypair_path: Option<PathBuf>,
+    /// Mint used by commands when `--mint` is not given.
+    pub mint: Option<String>,
+    /// Decimals of newly created mints.
+    pub decimals: Option<u8>,
+    /// Message signed to derive confidential keys (see [`SeedMessage`]).
+    pub seed_message: Option<String>,
+    /// Retry policy of RPC calls, the `[retry]` table.
+    pub retry: RetryConfig,
+    /// Rate limit of RPC calls, the `[rate_limit]` table.
+    pub rate_limit: RateLimitConfig,
+    /// Recurring payments, the `[[schedule]]` tables.
+    pub schedule: Vec<ScheduleConfig>,
+}
+
+/// `[retry]` table of the config file; unset fields keep the values of
+/// [`RetryPolicy::default`].
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    /// Retries of a single RPC call; 0 disables retrying.
+    pub max_retries: Option<u32>,
+    /// Bound of the wait before the first retry, in milliseconds.
+    pub initial_backoff_ms: Option<u64>,
+    /// Largest bound of the wait between retries, in milliseconds.
+    pub max_backoff_ms: Option<u64>,
+    /// Retries shared by every RPC call of a command.
+    pub budget: Option<u32>,
+}
+
+/// `[rate_limit]` table of the config file; unset fields do not limit.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// RPC requests awaiting their response at once.
+    pub max_in_flight: Option<usize>,
+    /// RPC requests started per second.
+    pub requests_per_second: Option<u32>,
+}
+
+/// `[[schedule]]` table of the config file, a recurring payment of `run-schedules`.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// Unique name of the payment.
+    pub name: String,
+    /// Cron expression of the occurrences, in UTC (see [`CronSchedule`]).
+    pub cron: String,
+    /// Wallet address of the recipient.
+    pub recipient: String,
+    /// Amount of each transfer as an [`Amount`], in tokens or in base units with a `raw` suffix.
+    pub amount: String,
+    /// Mint address or keyring symbol; the `mint` of the config when unset.
+    pub mint: Option<String>,
+    /// Memo attached to each transfer.
+    pub memo: Option<String>,
+    /// Catch-up after downtime: `all` (default), `latest` or `skip`.
+    pub catch_up: Option<String>,
+}
+
+impl Config {
+    /// Default location of the config file.
+    pub fn default_path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("config.toml"))
+    }
+
+    /// Loads the config file at `path`, or at [`Config::default_path`] when `path` is `None`.
+    ///
+    /// An explicitly given file must exist; a missing default file yields an empty config.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match Self::default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Unable to read config file {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    /// Overrides the file values with the environment variables `var` looks up, for deployments
+    /// where flags are awkward, such as containers. Empty variables are ignored.
+    ///
+    /// `CT_RPC_URL`, `CT_WS_URL`, `CT_COMMITMENT`, `CT_KEYPAIR` (or else `SOLANA_KEYPAIR`),
+    /// `CT_MINT`, `CT_DECIMALS` and `CT_SEED_MESSAGE` set the keys of the same name, and
+    /// `CT_MAX_RETRIES`, `CT_INITIAL_BACKOFF_MS`, `CT_MAX_BACKOFF_MS`, `CT_RETRY_BUDGET`,
+    /// `CT_MAX_IN_FLIGHT` and `CT_REQUESTS_PER_SECOND` those of the `[retry]` and `[rate_limit]`
+    /// tables. Numbers are checked here; other values like those of the file.
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<()> {
+        let string = |name: &str| var(name).filter(|value| !value.is_empty());
+        self.rpc_url = string("CT_RPC_URL").or(self.rpc_url.take());
+        self.ws_url = string("CT_WS_URL").or(self.ws_url.take());
+        self.commitment = string("CT_COMMITMENT").or(self.commitment.take());
+        self.keypair_path = string("CT_KEYPAIR")
+            .or_else(|| string(SOLANA_KEYPAIR_ENV))
+            .map(PathBuf::from)
+            .or(self.keypair_path.take());
+        self.mint = string("CT_MINT").or(self.mint.take());
+        self.decimals = parse_env(&string, "CT_DECIMALS")?.or(self.decimals);
+        self.seed_message = string("CT_SEED_MESSAGE").or(self.seed_message.take());
+        let retry = &mut self.retry;
+        retry.max_retries = parse_env(&string, "CT_MAX_RETRIES")?.or(retry.max_retries);
+        retry.initial_backoff_ms =
+            parse_env(&string, "CT_INITIAL_BACKOFF_MS")?.or(retry.initial_backoff_ms);
+        retry.max_backoff_ms = parse_env(&string, "CT_MAX_BACKOFF_MS")?.or(retry.max_backoff_ms);
+        retry.budget = parse_env(&string, "CT_RETRY_BUDGET")?.or(retry.budget);
+        let rate_limit = &mut self.rate_limit;
+        rate_limit.max_in_flight =
+            parse_env(&string, "CT_MAX_IN_FLIGHT")?.or(rate_limit.max_in_flight);
+        rate_limit.requests_per_second =
+            parse_env(&string, "CT_REQUESTS_PER_SECOND")?.or(rate_limit.requests_per_second);
+        Ok(())
+    }
+
+    /// RPC URL with monikers resolved, defaulting to [`DEFAULT_RPC_URL`].
+    pub fn rpc_url(&self) -> String {
+        cluster_url(self.rpc_url.as_deref().unwrap_or(DEFAULT_RPC_URL))
+    }
+
+    /// Websocket URL, defaulting to [`websocket_url`] of the RPC URL.
+    pub fn ws_url(&self) -> String {
+        self.ws_url
+            .clone()
+            .unwrap_or_else(|| websocket_url(&self.rpc_url()))
+    }
+
+    /// Commitment level, defaulting to `confirmed`.
+    pub fn commitment(&self) -> Result<CommitmentConfig> {
+        match &self.commitment {
+            Some(commitment) => parse_commitment(commitment),
+            None => Ok(CommitmentConfig::confirmed()),
+        }
+    }
+
+    /// Payer keypair path with a leading `~` expanded.
+    ///
+    /// Defaults to the `keypair_path` of the Solana CLI config file
+    /// (`~/.config/solana/cli/config.yml`) when that file exists, and to
+    /// [`default_keypair_path`] otherwise.
+    pub fn keypair_path(&self) -> Result<PathBuf> {
+        if let Some(path) = &self.keypair_path {
+            return expand_tilde(path);
+        }
+        match solana_cli_keypair_path()? {
+            Some(path) => expand_tilde(&path),
+            None => default_keypair_path(),
+        }
+    }
+
+    /// Default mint, if one is configured.
+    pub fn mint(&self) -> Result<Option<Pubkey>> {
+        self.mint
+            .as_deref()
+            .map(|mint| Pubkey::from_str(mint).map_err(|_| anyhow!("Invalid mint pubkey: {mint}")))
+            .transpose()
+    }
+
+    /// Decimals of newly created mints, defaulting to [`TOKEN_DECIMALS`].
+    pub fn decimals(&self) -> u8 {
+        self.decimals.unwrap_or(TOKEN_DECIMALS)
+    }
+
+    /// Retry policy of RPC calls, from the `[retry]` table on top of [`RetryPolicy::default`].
+    pub fn retry_policy(&self) -> Result<RetryPolicy> {
+        let default = RetryPolicy::default();
+        let policy = RetryPolicy {
+            max_retries: self.retry.max_retries.unwrap_or(default.max_retries),
+            initial_backoff: self
+                .retry
+                .initial_backoff_ms
+                .map_or(default.initial_backoff, Duration::from_millis),
+            max_backoff: self
+                .retry
+                .max_backoff_ms
+                .map_or(default.max_backoff, Duration::from_millis),
+            budget: self.retry.budget.unwrap_or(default.budget),
+        };
+        if policy.initial_backoff > policy.max_backoff {
+            bail!(
+                "Invalid retry policy: initial_backoff_ms is above max_backoff_ms ({:?} > {:?})",
+                policy.initial_backoff,
+                policy.max_backoff
+            );
+        }
+        Ok(policy)
+    }
+
+    /// Rate limit of RPC calls, from the `[rate_limit]` table; unlimited by default.
+    pub fn rate_limit(&self) -> Result<RateLimit> {
+        let max_in_flight = self
+            .rate_limit
+            .max_in_flight
+            .map(|max| {
+                NonZeroUsize::new(max)
+                    .ok_or_else(|| anyhow!("Invalid rate limit: max_in_flight must be at least 1"))
+            })
+            .transpose()?;
+        let requests_per_second = self
+            .rate_limit
+            .requests_per_second
+            .map(|rate| {
+                NonZeroU32::new(rate).ok_or_else(|| {
+                    anyhow!("Invalid rate limit: requests_per_second must be at least 1")
+                })
+            })
+            .transpose()?;
+        Ok(RateLimit {
+            max_in_flight,
+            requests_per_second,
+        })
+    }
+
+    /// Recurring payments of the `[[schedule]]` tables, checked for unique names, valid fields
+    /// and cron expressions that match some day.
+    pub fn schedules(&self) -> Result<Vec<ScheduledPayment>> {
+        let mut payments: Vec<ScheduledPayment> = vec![];
+        for schedule in &self.schedule {
+            let name = &schedule.name;
+            if name.is_empty() {
+                bail!("Invalid schedule: missing name");
+            }
+            if payments.iter().any(|payment| &payment.name == name) {
+                bail!("Invalid schedule: the name `{name}` is used twice");
+            }
+            let cron = schedule
+                .cron
+                .parse::<CronSchedule>()
+                .with_context(|| format!("Invalid schedule `{name}`"))?;
+            if cron.next_after(0).is_none() {
+                bail!("Invalid schedule `{name}`: `{cron}` never occurs");
+            }
+            if schedule.amount.is_empty() {
+                bail!("Invalid schedule `{name}`: missing amount");
+            }
+            let amount = schedule
+                .amount
+                .parse::<Amount>()
+                .with_context(|| format!("Invalid schedule `{name}`"))?;
+            if amount.is_zero() {
+                bail!("Invalid schedule `{name}`: the amount is zero");
+            }
+            payments.push(ScheduledPayment {
+                name: name.clone(),
+                cron,
+                recipient: Pubkey::from_str(&schedule.recipient)
+                    .map_err(|_| anyhow!("Invalid schedule `{name}`: invalid recipient pubkey"))?,
+                amount,
+                mint: schedule
+                    .mint
+                    .as_deref()
+                    .map(MintRef::from_str)
+                    .transpose()?,
+                memo: schedule.memo.clone(),
+                catch_up: schedule
+                    .catch_up
+                    .as_deref()
+                    .map(CatchUp::from_str)
+                    .transpose()
+                    .with_context(|| format!("Invalid schedule `{name}`"))?
+                    .unwrap_or_default(),
+            });
+        }
+        Ok(payments)
+    }
+
+    /// Seed message of the key derivation, defaulting to version 1.
+    pub fn seed_message(&self) -> Result<SeedMessage> {
+        self.seed_message
+            .as_deref()
+            .map(SeedMessage::from_str)
+            .transpose()
+            .map(Option::unwrap_or_default)
+    }
+}
+
+// Parses the environment variable `name` looked up with `var`, if set
+fn parse_env<T: FromStr>(var: &dyn Fn(&str) -> Option<String>, name: &str) -> Result<Option<T>> {
+    var(name)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| anyhow!("Invalid {name} environment variable: {value}"))
+        })
+        .transpose()
+}
+
+// Settings read from the Solana CLI config file; the others are ignored
+#[derive(Deserialize)]
+struct SolanaCliConfig {
+    #[serde(default)]
+    keypair_path: String,
+}
+
+// `keypair_path` of the Solana CLI config file, if that file exists and sets one.
+//
+// The file is parsed here rather than through `solana-cli-config`, which depends on the USB
+// support of `solana-remote-wallet` and would build it without the `ledger` feature
+fn solana_cli_keypair_path() -> Result<Option<PathBuf>> {
+    let Some(mut config_file) = dirs::home_dir() else {
+        return Ok(None);
+    };
+    config_file.extend([".config", "solana", "cli", "config.yml"]);
+    if !config_file.exists() {
+        return Ok(None);
+    }
+    let invalid = || format!("Invalid Solana CLI config file {}", config_file.display());
+    let contents = std::fs::read_to_string(&config_file).with_context(invalid)?;
+    let config = serde_yaml::from_str::<SolanaCliConfig>(&contents).with_context(invalid)?;
+    Ok(Some(config.keypair_path)
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from))
+}
+
+// Expands a leading `~/` to the home directory
+fn expand_tilde(path: &Path) -> Result<PathBuf> {
+    match path.strip_prefix("~") {
+        Ok(rest) => Ok(dirs::home_dir()
+            .context("Unable to get home directory")?
+            .join(rest)),
+        Err(_) => Ok(path.to_path_buf()),
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::Result;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use spl_token_client::spl_token_2022::{
+    extension::{
+        BaseStateWithExtensions, ExtensionType,
+        memo_transfer::{
+            MemoTransfer,
+            instruction::{disable_required_transfer_memos, enable_required_transfer_memos},
+        },
+    },
+    id as token_2022_program_id,
+    instruction::reallocate,
+};
+use tracing::{debug, instrument};
+
+use crate::{TokenClient, authority::Authority, utils::into_signature};
+
+/// Allows or rejects incoming confidential credits (confidential transfers and mints) on
+/// `ata_pubkey`.
+///
+/// Deposits count as confidential credits as well, so they are rejected while disabled.
+#[instrument(skip_all, fields(ata = %ata_pubkey, enabled))]
+pub async fn set_confidential_credits(
+    token: &TokenClient,
+    owner: &Authority,
+    ata_pubkey: &Pubkey,
+    enabled: bool,
+) -> Result<Signature> {
+    let response = if enabled {
+        token
+            .confidential_transfer_enable_confidential_credits(
+                ata_pubkey,      //Ata to update
+                &owner.pubkey(), //Owner of the ata
+                owner.signers(),
+            )
+            .await?
+    } else {
+        token
+            .confidential_transfer_disable_confidential_credits(
+                ata_pubkey,      //Ata to update
+                &owner.pubkey(), //Owner of the ata
+                owner.signers(),
+            )
+            .await?
+    };
+    into_signature(response)
+}
+
+/// Allows or rejects incoming non-confidential credits (public transfers and mints) on
+/// `ata_pubkey`, e.g. to keep a confidential-only account from receiving public tokens.
+#[instrument(skip_all, fields(ata = %ata_pubkey, enabled))]
+pub async fn set_non_confidential_credits(
+    token: &TokenClient,
+    owner: &Authority,
+    ata_pubkey: &Pubkey,
+    enabled: bool,
+) -> Result<Signature> {
+    let response = if enabled {
+        token
+            .confidential_transfer_enable_non_confidential_credits(
+                ata_pubkey,      //Ata to update
+                &owner.pubkey(), //Owner of the ata
+                owner.signers(),
+            )
+            .await?
+    } else {
+        token
+            .confidential_transfer_disable_non_confidential_credits(
+                ata_pubkey,      //Ata to update
+                &owner.pubkey(), //Owner of the ata
+                owner.signers(),
+            )
+            .await?
+    };
+    into_signature(response)
+}
+
+/// Requires a memo right before every incoming transfer to `ata_pubkey`, or stops requiring it,
+/// so that payments can be matched by their reference.
+///
+/// The `MemoTransfer` extension is added to the account first when enabling on an account
+/// without it; `fee_payer`, the payer of `token`, funds the reallocation.
+#[instrument(skip_all, fields(ata = %ata_pubkey, enabled))]
+pub async fn set_required_memos(
+    token: &TokenClient,
+    fee_payer: &Pubkey,
+    owner: &Authority,
+    ata_pubkey: &Pubkey,
+    enabled: bool,
+) -> Result<Signature> {
+    let multisig_signers = owner.multisig_signers();
+    let multisig_signers = multisig_signers.iter().collect::<Vec<_>>();
+    let mut ixs = vec![];
+    if enabled {
+        let token_account = token.get_account_info(ata_pubkey).await?;
+        if token_account.get_extension::<MemoTransfer>().is_err() {
+            debug!("Adding the MemoTransfer extension");
+            ixs.push(reallocate(
+                &token_2022_program_id(),
+                ata_pubkey,        //Ata to extend
+                fee_payer,         //Payer of the extra rent
+                &owner.pubkey(),   //Owner of the ata
+                &multisig_signers, //Multisig signers
+                &[ExtensionType::MemoTransfer],
+            )?);
+        }
+        ixs.push(enable_required_transfer_memos(
+            &token_2022_program_id(),
+            ata_pubkey,
+            &owner.pubkey(),
+            &multisig_signers,
+        )?);
+    } else {
+        ixs.push(disable_required_transfer_memos(
+            &token_2022_program_id(),
+            ata_pubkey,
+            &owner.pubkey(),
+            &multisig_signers,
+        )?);
+    }
+    into_signature(token.process_ixs(&ixs, owner.signers()).await?)
+}
@@ -0,0 +1,259 @@
+use anyhow::{Context, Result, bail};
+use curve25519_dalek::{
+    constants::{RISTRETTO_BASEPOINT_POINT as G, RISTRETTO_BASEPOINT_TABLE},
+    ristretto::RistrettoPoint,
+    scalar::Scalar,
+    traits::IsIdentity,
+};
+use memmap2::Mmap;
+use sha2::{Digest, Sha256};
+use spl_token_client::spl_token_2022::solana_zk_sdk::encryption::elgamal::{
+    ElGamalCiphertext, ElGamalSecretKey,
+};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process, thread,
+};
+use tracing::{debug, info, instrument};
+
+/// Number of giant steps stored in the table, as a power of two.
+pub const TABLE_BITS: u32 = 20;
+/// Number of baby steps searched per decryption, as a power of two.
+pub const SEARCH_BITS: u32 = 16;
+
+const MAGIC: &[u8; 4] = b"CTDT";
+const VERSION: u8 = 2;
+//Magic, version and sizes, then the SHA-256 digest of the records
+const PREFIX_LEN: usize = 8;
+const HEADER_LEN: usize = PREFIX_LEN + 32;
+//Compressed (doubled) point followed by its little-endian giant step index
+const RECORD_LEN: usize = 36;
+const BATCH_SIZE: usize = 256;
+
+/// Baby-step giant-step lookup table for decrypting ElGamal ciphertexts, cached on disk.
+///
+/// The table holds the giant steps `j * 2^SEARCH_BITS * G` for `0 < j < 2^TABLE_BITS`, sorted by
+/// their encoding. Decrypting searches `2^SEARCH_BITS` baby steps against it, which recovers any
+/// value below `2^(TABLE_BITS + SEARCH_BITS)`. The file is built once and memory-mapped on every
+/// later open, so no deserialization happens per process; its header carries a SHA-256 digest of
+/// the records, checked on open, and a corrupt file is rebuilt.
+pub struct DecodeTable {
+    mmap: Mmap,
+}
+
+impl DecodeTable {
+    /// Default location of the table file, under the user cache directory.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::cache_dir().map(|cache| {
+            cache.join(format!(
+                "confidential-transfer/decode-table-v{VERSION}-{TABLE_BITS}-{SEARCH_BITS}.bin"
+            ))
+        })
+    }
+
+    /// Opens the table at [`DecodeTable::default_path`], building it first if needed.
+    pub fn open_default() -> Result<Self> {
+        let path = Self::default_path().context("Unable to get cache directory")?;
+        Self::open(&path)
+    }
+
+    /// Memory-maps the table at `path`, (re)building it when missing or invalid.
+    pub fn open(path: &Path) -> Result<Self> {
+        match Self::map(path) {
+            Ok(table) => return Ok(table),
+            Err(err) => debug!(path = %path.display(), "No usable decode table: {err:#}"),
+        }
+        info!(path = %path.display(), "Building decode lookup table (one-time)");
+        build(path)?;
+        Self::map(path)
+    }
+
+    // Maps an existing table file and checks its header, size and checksum
+    fn map(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Unable to open decode table {}", path.display()))?;
+        //SAFETY: the file is only ever written through a temporary file that is renamed into
+        //place, so a mapped table is never modified
+        let mmap = unsafe { Mmap::map(&file)? };
+        if mmap.len() != HEADER_LEN + record_count() * RECORD_LEN
+            || mmap[..PREFIX_LEN] != header_prefix()
+        {
+            bail!("Invalid decode table {}", path.display());
+        }
+        //A truncated or interleaved write leaves records that do not match the digest
+        if Sha256::digest(&mmap[HEADER_LEN..])[..] != mmap[PREFIX_LEN..HEADER_LEN] {
+            bail!("Corrupt decode table {}", path.display());
+        }
+        Ok(Self { mmap })
+    }
+
+    /// Decrypts `ciphertext`, returning `None` when the value is out of the table's range.
+    pub fn decrypt(
+        &self,
+        elgamal_secret_key: &ElGamalSecretKey,
+        ciphertext: &ElGamalCiphertext,
+    ) -> Option<u64> {
+        self.decode(elgamal_secret_key.decrypt(ciphertext).target)
+    }
+
+    /// Solves `x * G = target` for `x < 2^(TABLE_BITS + SEARCH_BITS)`.
+    #[instrument(skip_all)]
+    pub fn decode(&self, target: RistrettoPoint) -> Option<u64> {
+        let steps = 1u64 << SEARCH_BITS;
+        let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get) as u64;
+        let chunk = steps.div_ceil(threads);
+        thread::scope(|scope| {
+            let handles = (0..steps)
+                .step_by(chunk as usize)
+                .map(|start| {
+                    scope.spawn(move || self.search(target, start, steps.min(start + chunk)))
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .filter_map(|handle| handle.join().ok().flatten())
+                .next()
+        })
+    }
+
+    // Baby steps `target - i * G` for `start <= i < end`
+    fn search(&self, target: RistrettoPoint, start: u64, end: u64) -> Option<u64> {
+        let mut point = target - &Scalar::from(start) * RISTRETTO_BASEPOINT_TABLE;
+        let mut i = start;
+        while i < end {
+            let mut batch = Vec::with_capacity(BATCH_SIZE);
+            while batch.len() < BATCH_SIZE && i < end {
+                //The identity cannot be batch compressed; it means the giant step index is 0
+                if point.is_identity() {
+                    return Some(i);
+                }
+                batch.push((point, i));
+                point -= G;
+                i += 1;
+            }
+            let points = batch.iter().map(|(point, _)| point);
+            for (compressed, (_, i)) in RistrettoPoint::double_and_compress_batch(points)
+                .iter()
+                .zip(&batch)
+            {
+                if let Some(j) = self.lookup(compressed.as_bytes()) {
+                    return Some((j << SEARCH_BITS) + i);
+                }
+            }
+        }
+        None
+    }
+
+    // Binary search of the sorted records for a doubled point encoding
+    fn lookup(&self, key: &[u8; 32]) -> Option<u64> {
+        let records = &self.mmap[HEADER_LEN..];
+        let (mut low, mut high) = (0, record_count());
+        while low < high {
+            let mid = (low + high) / 2;
+            let record = &records[mid * RECORD_LEN..(mid + 1) * RECORD_LEN];
+            match record[..32].cmp(key) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => {
+                    let index = u32::from_le_bytes(record[32..].try_into().ok()?);
+                    return Some(index.into());
+                }
+            }
+        }
+        None
+    }
+}
+
+fn record_count() -> usize {
+    (1 << TABLE_BITS) - 1
+}
+
+fn header_prefix() -> [u8; PREFIX_LEN] {
+    let mut prefix = [0; PREFIX_LEN];
+    prefix[..4].copy_from_slice(MAGIC);
+    prefix[4] = VERSION;
+    prefix[5] = TABLE_BITS as u8;
+    prefix[6] = SEARCH_BITS as u8;
+    prefix
+}
+
+// Computes the giant steps on all cores and writes them sorted to `path`
+fn build(path: &Path) -> Result<()> {
+    let count = record_count() as u64;
+    let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get) as u64;
+    let chunk = count.div_ceil(threads);
+    let mut records = thread::scope(|scope| {
+        let handles = (1..=count)
+            .step_by(chunk as usize)
+            .map(|start| scope.spawn(move || giant_steps(start, count.min(start + chunk - 1))))
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_default())
+            .collect::<Vec<_>>()
+    });
+    records.sort_unstable();
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Unable to create {}", dir.display()))?;
+    }
+    let mut digest = Sha256::new();
+    for record in &records {
+        digest.update(record);
+    }
+    //Processes building the table at the same time each write their own file, the last rename
+    //winning
+    let tmp_path = path.with_extension(format!(
+        "{}-{:016x}.tmp",
+        process::id(),
+        rand::random::<u64>()
+    ));
+    let write = || -> Result<()> {
+        let mut writer = BufWriter::new(
+            File::create(&tmp_path)
+                .with_context(|| format!("Unable to create {}", tmp_path.display()))?,
+        );
+        writer.write_all(&header_prefix())?;
+        writer.write_all(&digest.finalize())?;
+        for record in &records {
+            writer.write_all(record)?;
+        }
+        writer.into_inner()?.sync_all()?;
+        std::fs::rename(&tmp_path, path)
+            .with_context(|| format!("Unable to write decode table {}", path.display()))
+    };
+    write().inspect_err(|_| {
+        let _ = std::fs::remove_file(&tmp_path);
+    })
+}
+
+// Records for giant step indices `start..=end`
+fn giant_steps(start: u64, end: u64) -> Vec<[u8; RECORD_LEN]> {
+    let step = &Scalar::from(1u64 << SEARCH_BITS) * RISTRETTO_BASEPOINT_TABLE;
+    let mut point = &Scalar::from(start << SEARCH_BITS) * RISTRETTO_BASEPOINT_TABLE;
+    let mut records = Vec::with_capacity((end + 1 - start) as usize);
+    let mut j = start;
+    while j <= end {
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        while batch.len() < BATCH_SIZE && j <= end {
+            batch.push(point);
+            point += step;
+            j += 1;
+        }
+        let first = j - batch.len() as u64;
+        for (offset, compressed) in RistrettoPoint::double_and_compress_batch(&batch)
+            .iter()
+            .enumerate()
+        {
+            let mut record = [0; RECORD_LEN];
+            record[..32].copy_from_slice(compressed.as_bytes());
+            record[32..].copy_from_slice(&((first + offset as u64) as u32).to_le_bytes());
+            records.push(record);
+        }
+    }
+    records
+}
@@ -0,0 +1,141 @@
+use anyhow::{Result, anyhow};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use spl_token_client::spl_token_2022::{
+    extension::{
+        BaseStateWithExtensions,
+        confidential_transfer::{
+            ConfidentialTransferAccount,
+            account_info::ApplyPendingBalanceAccountInfo,
+            instruction::{
+                apply_pending_balance as apply_pending_balance_instruction,
+                deposit as deposit_instruction,
+            },
+        },
+    },
+    id as token_2022_program_id,
+    solana_zk_sdk::encryption::{
+        auth_encryption::AeKey, elgamal::ElGamalKeypair, pod::auth_encryption::PodAeCiphertext,
+    },
+};
+use tracing::instrument;
+
+use crate::{
+    TokenClient,
+    authority::Authority,
+    utils::{attach_memo, into_signature},
+};
+
+/// Deposits normal tokens into the pending confidential balance of `ata_pubkey`.
+///
+/// Converts normal tokens -> confidential tokens. The deposited amount only becomes spendable
+/// after [`apply_pending_balance`]. `memo` is attached to the deposit transaction, e.g. as a
+/// reference for reconciliation.
+#[instrument(skip_all, fields(ata = %ata_pubkey, amount))]
+pub async fn deposit(
+    token: &TokenClient,
+    owner: &Authority,
+    ata_pubkey: &Pubkey,
+    amount: u64,
+    decimals: u8,
+    memo: Option<&str>,
+) -> Result<Signature> {
+    attach_memo(token, memo);
+    let response = token
+        .confidential_transfer_deposit(
+            ata_pubkey,      //Destination ata
+            &owner.pubkey(), //Authority(owner) of the account
+            amount,          //Amount to deposit
+            decimals,        //Decimals
+            owner.signers(), //Signer(owner of the ata)
+        )
+        .await?;
+    into_signature(response)
+}
+
+/// Applies the pending balance of `ata_pubkey` to make the funds available for confidential
+/// transfers.
+///
+/// Also sets the first decryptable available balance of accounts configured from an ElGamal
+/// registry, which start without one.
+#[instrument(skip_all, fields(ata = %ata_pubkey))]
+pub async fn apply_pending_balance(
+    token: &TokenClient,
+    owner: &Authority,
+    ata_pubkey: &Pubkey,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+) -> Result<Signature> {
+    let token_account = token.get_account_info(ata_pubkey).await?;
+    let mut extension_data = *token_account.get_extension::<ConfidentialTransferAccount>()?;
+    if extension_data.decryptable_available_balance == PodAeCiphertext::default() {
+        extension_data.decryptable_available_balance = aes_key.encrypt(0).into();
+    }
+    let response = token
+        .confidential_transfer_apply_pending_balance(
+            ata_pubkey,      //Ata public key
+            &owner.pubkey(), //Owner of the ata
+            Some(ApplyPendingBalanceAccountInfo::new(&extension_data)),
+            elgamal_keypair.secret(),
+            aes_key,
+            owner.signers(), //Signers(owner must sign)
+        )
+        .await?;
+    into_signature(response)
+}
+
+/// Deposits normal tokens into `ata_pubkey` and applies its pending balance in the same
+/// transaction, so the deposit is spendable in one round trip instead of two.
+///
+/// The new decryptable available balance is computed ahead of the deposit: the available balance,
+/// the pending balance and `amount`, with the pending credit counter the deposit increments.
+/// `memo` is attached to the transaction.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip_all, fields(ata = %ata_pubkey, amount))]
+pub async fn deposit_and_apply(
+    token: &TokenClient,
+    owner: &Authority,
+    ata_pubkey: &Pubkey,
+    amount: u64,
+    decimals: u8,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+    memo: Option<&str>,
+) -> Result<Signature> {
+    let token_account = token.get_account_info(ata_pubkey).await?;
+    let mut extension_data = *token_account.get_extension::<ConfidentialTransferAccount>()?;
+    if extension_data.decryptable_available_balance == PodAeCiphertext::default() {
+        extension_data.decryptable_available_balance = aes_key.encrypt(0).into();
+    }
+    let account_info = ApplyPendingBalanceAccountInfo::new(&extension_data);
+    let applied = account_info
+        .new_decryptable_available_balance(elgamal_keypair.secret(), aes_key)
+        .map_err(|e| anyhow!("Unable to decrypt the balances of {ata_pubkey}: {e}"))?;
+    let available = aes_key
+        .decrypt(&applied)
+        .and_then(|balance| balance.checked_add(amount))
+        .ok_or_else(|| anyhow!("Unable to compute the new available balance of {ata_pubkey}"))?;
+    let multisig_signers = owner.multisig_signers();
+    let multisig_signers = multisig_signers.iter().collect::<Vec<_>>();
+    let ixs = [
+        deposit_instruction(
+            &token_2022_program_id(),
+            ata_pubkey,          //Destination ata
+            token.get_address(), //Mint
+            amount,              //Amount to deposit
+            decimals,            //Decimals
+            &owner.pubkey(),     //Authority(owner) of the account
+            &multisig_signers,   //Multisig signers
+        )?,
+        apply_pending_balance_instruction(
+            &token_2022_program_id(),
+            ata_pubkey, //Ata public key
+            //Credits expected once applied, the deposit included
+            account_info.pending_balance_credit_counter() + 1,
+            &aes_key.encrypt(available).into(),
+            &owner.pubkey(),   //Owner of the ata
+            &multisig_signers, //Multisig signers
+        )?,
+    ];
+    attach_memo(token, memo);
+    into_signature(token.process_ixs(&ixs, owner.signers()).await?)
+}
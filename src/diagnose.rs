@@ -0,0 +1,150 @@
+use num_traits::FromPrimitive;
+use solana_client::{
+    client_error::ClientError, nonblocking::rpc_client::RpcClient,
+    rpc_client::SerializableTransaction, rpc_config::RpcSimulateTransactionConfig,
+};
+use solana_sdk::{
+    instruction::InstructionError, message::VersionedMessage, pubkey::Pubkey,
+    transaction::TransactionError,
+};
+use solana_sdk_ids::{compute_budget, system_program};
+use spl_token_client::spl_token_2022::{
+    self,
+    error::TokenError,
+    solana_zk_sdk::zk_elgamal_proof_program::{self, instruction::ProofInstruction},
+};
+use std::{error::Error, fmt};
+use tracing::warn;
+
+/// Transaction that failed on chain or in preflight, explained: the instruction at fault with
+/// its program error decoded, and the program logs of the transaction simulated again.
+#[derive(Debug)]
+pub struct TransactionFailure {
+    /// Error the transaction failed with.
+    pub error: TransactionError,
+    /// `error` decoded, see [`describe`].
+    pub description: String,
+    /// Program logs of the simulation; empty when it could not run.
+    pub logs: Vec<String>,
+    source: Box<dyn Error + Send + Sync>,
+}
+
+impl fmt::Display for TransactionFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Transaction failed: {}", self.description)?;
+        if !self.logs.is_empty() {
+            write!(f, "\nProgram logs:")?;
+            for log in &self.logs {
+                write!(f, "\n  {log}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error for TransactionFailure {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+/// Transaction error behind `err`, as returned by a send or a confirmation, if any.
+pub fn transaction_error(err: &(dyn Error + Send + Sync + 'static)) -> Option<TransactionError> {
+    err.downcast_ref::<TransactionError>().cloned().or_else(|| {
+        err.downcast_ref::<ClientError>()
+            .and_then(ClientError::get_transaction_error)
+    })
+}
+
+/// Explains `err`, the failure of `transaction` of `message`, as a [`TransactionFailure`] with
+/// the logs of the transaction simulated again, on a recent blockhash and against the current
+/// state.
+///
+/// Errors carrying no transaction error, and those of a transaction that may still be sent again
+/// as is (an unknown blockhash, or one already processed), are returned unchanged.
+pub async fn explain_failure(
+    client: &RpcClient,
+    transaction: &impl SerializableTransaction,
+    message: &VersionedMessage,
+    err: Box<dyn Error + Send + Sync>,
+) -> Box<dyn Error + Send + Sync> {
+    let error = match transaction_error(err.as_ref()) {
+        None | Some(TransactionError::BlockhashNotFound | TransactionError::AlreadyProcessed) => {
+            return err;
+        }
+        Some(error) => error,
+    };
+    let config = RpcSimulateTransactionConfig {
+        sig_verify: false,
+        replace_recent_blockhash: true,
+        commitment: Some(client.commitment()),
+        ..RpcSimulateTransactionConfig::default()
+    };
+    let logs = match client
+        .simulate_transaction_with_config(transaction, config)
+        .await
+    {
+        Ok(response) => response.value.logs.unwrap_or_default(),
+        Err(simulation_err) => {
+            warn!("Unable to simulate the failed transaction again: {simulation_err}");
+            vec![]
+        }
+    };
+    Box::new(TransactionFailure {
+        description: describe(&error, message),
+        error,
+        logs,
+        source: err,
+    })
+}
+
+/// Describes `error`, of a transaction of `message`: the instruction at fault, its program, and
+/// the error of Token-2022 behind a custom error code.
+pub fn describe(error: &TransactionError, message: &VersionedMessage) -> String {
+    let TransactionError::InstructionError(index, instruction_error) = error else {
+        return error.to_string();
+    };
+    let keys = message.static_account_keys();
+    let Some(instruction) = message.instructions().get(usize::from(*index)) else {
+        return error.to_string();
+    };
+    let Some(program) = keys.get(usize::from(instruction.program_id_index)) else {
+        return error.to_string();
+    };
+    let program_name = if *program == zk_elgamal_proof_program::id() {
+        //Proof verification failures carry no code; the instruction tells which proof failed
+        match ProofInstruction::instruction_type(&instruction.data) {
+            Some(proof) => format!("ZK ElGamal proof program, {proof:?}"),
+            None => "ZK ElGamal proof program".to_string(),
+        }
+    } else {
+        program_name(program).map_or_else(|| program.to_string(), str::to_string)
+    };
+    let cause = match instruction_error {
+        InstructionError::Custom(code) if *program == spl_token_2022::id() => {
+            match TokenError::from_u32(*code) {
+                Some(token_error) => format!("{token_error} (TokenError::{token_error:?})"),
+                None => instruction_error.to_string(),
+            }
+        }
+        _ => instruction_error.to_string(),
+    };
+    format!("instruction {index} ({program_name}) failed: {cause}")
+}
+
+// Name of the programs the transactions of this crate invoke
+fn program_name(program: &Pubkey) -> Option<&'static str> {
+    if *program == spl_token_2022::id() {
+        Some("Token-2022")
+    } else if *program == system_program::id() {
+        Some("System program")
+    } else if *program == compute_budget::id() {
+        Some("Compute budget program")
+    } else if *program == spl_associated_token_account::id() {
+        Some("Associated token account program")
+    } else if *program == spl_memo::id() {
+        Some("Memo program")
+    } else {
+        None
+    }
+}
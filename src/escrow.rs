@@ -0,0 +1,482 @@
+use aes_gcm::{
+    Aes256Gcm, KeyInit, Nonce,
+    aead::{Aead, OsRng, Payload, rand_core::RngCore},
+};
+use anyhow::{Context, Result, anyhow, bail};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use curve25519_dalek::{
+    ristretto::{CompressedRistretto, RistrettoPoint},
+    scalar::Scalar,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use solana_sdk::{program_pack::Pack, pubkey::Pubkey, signature::Signature, signer::Signer};
+use spl_token_client::spl_token_2022::{
+    instruction::AuthorityType,
+    solana_zk_sdk::encryption::{
+        auth_encryption::AeKey,
+        elgamal::{ElGamalKeypair, ElGamalPubkey, ElGamalSecretKey},
+        pedersen::H,
+    },
+    state::Multisig,
+};
+use std::{path::Path, sync::Arc};
+use tracing::{debug, info, instrument};
+
+use crate::{
+    TokenClient,
+    authority::Authority,
+    balance, deposit, keygen,
+    mint::{self, get_ata},
+    placement::Packing,
+    transfer::{self, TransferResult, get_elgamal_pubkey, get_recipient_elgamal_pubkey},
+    utils::{into_signature, parse_elgamal_pubkey},
+};
+
+//Bound into every key share, so a share cannot be replayed as any other ciphertext
+const KEY_SHARE_DOMAIN: &[u8] = b"confidential-transfer:escrow-key-share-v1";
+
+//ElGamal secret key followed by the AES key
+const KEY_SHARE_PLAINTEXT_LEN: usize = 48;
+
+/// Escrow between two parties: a confidential token account owned by a 2-of-2 SPL multisig of
+/// their wallets, so funds leave it only with both signatures.
+///
+/// The account has keys of its own, generated at creation; the escrow carries them sealed to the
+/// ElGamal pubkey of each party's associated token account, so either party can decrypt the
+/// escrowed balance and generate the proofs of a release. The escrow holds no secret in the
+/// clear and can be sent to the counterparty over any channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Escrow {
+    /// Mint of the escrowed tokens.
+    pub mint: Pubkey,
+    /// Escrow token account.
+    pub account: Pubkey,
+    /// 2-of-2 multisig of the parties owning the account.
+    pub multisig: Pubkey,
+    /// Wallets of the two parties, the creator first.
+    pub parties: [Pubkey; 2],
+    /// Keys of the account sealed to each party.
+    pub shares: Vec<KeyShare>,
+}
+
+/// Keys of an escrow account sealed to one party, opened with [`KeyShare::open`].
+///
+/// The keys are encrypted with AES-256-GCM under a key agreed with the ElGamal pubkey of the
+/// party's associated token account: `r * P`, for a random `r` published as `r * H`, which only
+/// the holder of the ElGamal secret key recomputes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyShare {
+    /// Wallet of the party the keys are sealed to.
+    pub party: Pubkey,
+    /// ElGamal pubkey the keys are sealed to.
+    pub elgamal_pubkey: ElGamalPubkey,
+    /// The ephemeral point `r * H`, compressed.
+    pub ephemeral: [u8; 32],
+    /// Encrypted keys.
+    pub ciphertext: Vec<u8>,
+}
+
+impl KeyShare {
+    /// Seals `elgamal_keypair` and `aes_key` of the escrow `account` to `party`, whose
+    /// associated token account has `party_elgamal_pubkey`.
+    pub fn seal(
+        account: &Pubkey,
+        party: &Pubkey,
+        party_elgamal_pubkey: &ElGamalPubkey,
+        elgamal_keypair: &ElGamalKeypair,
+        aes_key: &AeKey,
+    ) -> Result<Self> {
+        let mut wide = [0u8; 64];
+        OsRng.fill_bytes(&mut wide);
+        let ephemeral_secret = Scalar::from_bytes_mod_order_wide(&wide);
+        let ephemeral = (ephemeral_secret * *H).compress().to_bytes();
+        let shared = ephemeral_secret * party_elgamal_pubkey.get_point();
+        let mut plaintext = elgamal_keypair.secret().as_bytes().to_vec();
+        plaintext.extend_from_slice(&<[u8; 16]>::from(aes_key.clone()));
+        let ciphertext = share_cipher(&shared, &ephemeral)
+            .encrypt(
+                &Nonce::default(),
+                Payload {
+                    msg: &plaintext,
+                    aad: &share_aad(account, party),
+                },
+            )
+            .map_err(|_| anyhow!("Failed to seal the escrow keys"))?;
+        Ok(Self {
+            party: *party,
+            elgamal_pubkey: *party_elgamal_pubkey,
+            ephemeral,
+            ciphertext,
+        })
+    }
+
+    /// Opens the share with the ElGamal keypair of the party's associated token account,
+    /// returning the keys of the escrow `account`.
+    pub fn open(
+        &self,
+        account: &Pubkey,
+        party_elgamal_keypair: &ElGamalKeypair,
+    ) -> Result<(ElGamalKeypair, AeKey)> {
+        if party_elgamal_keypair.pubkey() != &self.elgamal_pubkey {
+            bail!(
+                "The escrow keys are sealed to another ElGamal pubkey of {}, {}; were the keys of its account rotated?",
+                self.party,
+                self.elgamal_pubkey
+            );
+        }
+        let ephemeral = CompressedRistretto(self.ephemeral)
+            .decompress()
+            .ok_or_else(|| anyhow!("Invalid key share of {}", self.party))?;
+        //s^-1 * (r * H) = r * (s^-1 * H), the point the share was sealed with
+        let shared = party_elgamal_keypair.secret().get_scalar().invert() * ephemeral;
+        let plaintext = share_cipher(&shared, &self.ephemeral)
+            .decrypt(
+                &Nonce::default(),
+                Payload {
+                    msg: &self.ciphertext,
+                    aad: &share_aad(account, &self.party),
+                },
+            )
+            .map_err(|_| anyhow!("Unable to open the key share of {}", self.party))?;
+        if plaintext.len() != KEY_SHARE_PLAINTEXT_LEN {
+            bail!("Invalid key share of {}", self.party);
+        }
+        let invalid = || anyhow!("Invalid key share of {}", self.party);
+        let elgamal_keypair = ElGamalKeypair::new(
+            ElGamalSecretKey::try_from(&plaintext[..32]).map_err(|_| invalid())?,
+        );
+        let aes_key = AeKey::try_from(&plaintext[32..]).map_err(|_| invalid())?;
+        Ok((elgamal_keypair, aes_key))
+    }
+}
+
+impl Escrow {
+    /// Share of `party`, if the party is one of the escrow.
+    pub fn share(&self, party: &Pubkey) -> Result<&KeyShare> {
+        self.shares
+            .iter()
+            .find(|share| &share.party == party)
+            .ok_or_else(|| anyhow!("{party} is not a party of escrow {}", self.account))
+    }
+
+    /// Opens the share of `party` with the ElGamal keypair of its associated token account.
+    pub fn open(
+        &self,
+        party: &Pubkey,
+        party_elgamal_keypair: &ElGamalKeypair,
+    ) -> Result<(ElGamalKeypair, AeKey)> {
+        self.share(party)?
+            .open(&self.account, party_elgamal_keypair)
+    }
+
+    /// Writes the escrow as JSON to `path`, refusing to overwrite an existing file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if path.exists() {
+            bail!("Refusing to overwrite existing file {}", path.display());
+        }
+        let record = EscrowRecord {
+            mint: self.mint.to_string(),
+            account: self.account.to_string(),
+            multisig: self.multisig.to_string(),
+            parties: self.parties.map(|party| party.to_string()),
+            shares: self
+                .shares
+                .iter()
+                .map(|share| KeyShareRecord {
+                    party: share.party.to_string(),
+                    elgamal_pubkey: share.elgamal_pubkey.to_string(),
+                    ephemeral: BASE64_STANDARD.encode(share.ephemeral),
+                    ciphertext: BASE64_STANDARD.encode(&share.ciphertext),
+                })
+                .collect(),
+        };
+        std::fs::write(path, serde_json::to_vec_pretty(&record)?)
+            .with_context(|| format!("Unable to write escrow {}", path.display()))
+    }
+
+    /// Reads an escrow written by [`Escrow::save`].
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read(path)
+            .with_context(|| format!("Unable to read escrow {}", path.display()))?;
+        let record: EscrowRecord = serde_json::from_slice(&contents)
+            .with_context(|| format!("Invalid escrow {}", path.display()))?;
+        let invalid = || anyhow!("Invalid escrow {}", path.display());
+        let pubkey = |value: &str| value.parse::<Pubkey>().map_err(|_| invalid());
+        Ok(Self {
+            mint: pubkey(&record.mint)?,
+            account: pubkey(&record.account)?,
+            multisig: pubkey(&record.multisig)?,
+            parties: [pubkey(&record.parties[0])?, pubkey(&record.parties[1])?],
+            shares: record
+                .shares
+                .iter()
+                .map(|share| {
+                    Ok(KeyShare {
+                        party: pubkey(&share.party)?,
+                        elgamal_pubkey: parse_elgamal_pubkey(&share.elgamal_pubkey)?,
+                        ephemeral: BASE64_STANDARD
+                            .decode(&share.ephemeral)
+                            .ok()
+                            .and_then(|bytes| bytes.try_into().ok())
+                            .ok_or_else(invalid)?,
+                        ciphertext: BASE64_STANDARD
+                            .decode(&share.ciphertext)
+                            .map_err(|_| invalid())?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+// Escrow file contents
+#[derive(Serialize, Deserialize)]
+struct EscrowRecord {
+    mint: String,
+    account: String,
+    multisig: String,
+    parties: [String; 2],
+    shares: Vec<KeyShareRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyShareRecord {
+    party: String,
+    elgamal_pubkey: String,
+    ephemeral: String,
+    ciphertext: String,
+}
+
+/// Outcome of [`release`].
+#[derive(Debug)]
+pub struct Release {
+    /// Signature of the apply of the pending balance, if there was one.
+    pub apply_signature: Option<Signature>,
+    /// Amount released, in base units.
+    pub amount: u64,
+    /// Party the funds were released to.
+    pub recipient: Pubkey,
+    /// The transfer to the associated token account of the recipient.
+    pub transfer: TransferResult,
+}
+
+/// Opens an escrow between `creator` and `counterparty` for the mint of `token`.
+///
+/// Creates the 2-of-2 multisig of the two wallets and a token account configured with new keys,
+/// at first owned by `creator` alone so it can be configured without the counterparty, then
+/// handed to the multisig; the keys are sealed to both parties. Both parties need an approved,
+/// configured associated token account for the mint. `fee_payer` must be the payer of `token`;
+/// it funds the rent of both accounts.
+#[instrument(skip_all, fields(mint = %token.get_address(), creator = %creator.pubkey(), counterparty = %counterparty))]
+pub async fn create_escrow(
+    token: &TokenClient,
+    fee_payer: &Pubkey,
+    creator: &Authority,
+    counterparty: &Pubkey,
+) -> Result<Escrow> {
+    if !creator.multisig_signers().is_empty() {
+        bail!("A multisig cannot be a party of an escrow");
+    }
+    let parties = [creator.pubkey(), *counterparty];
+    if parties[0] == parties[1] {
+        bail!("The parties of an escrow must differ");
+    }
+    let mut party_elgamal_pubkeys = vec![];
+    for party in &parties {
+        party_elgamal_pubkeys.push(
+            get_recipient_elgamal_pubkey(token, party)
+                .await
+                .with_context(|| format!("Unable to seal the escrow keys to {party}"))?,
+        );
+    }
+    let multisig = keygen::new_keypair();
+    token
+        .create_multisig(&multisig, &[&parties[0], &parties[1]], 2)
+        .await?;
+    debug!(multisig = %multisig.pubkey(), "Created the multisig of the parties");
+    let account = keygen::new_keypair();
+    let (elgamal_keypair, aes_key) = (keygen::new_elgamal_keypair(), keygen::new_aes_key());
+    let shares = parties
+        .iter()
+        .zip(&party_elgamal_pubkeys)
+        .map(|(party, elgamal_pubkey)| {
+            KeyShare::seal(
+                &account.pubkey(),
+                party,
+                elgamal_pubkey,
+                &elgamal_keypair,
+                &aes_key,
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+    mint::create_configure_account_with_keys(
+        token,
+        fee_payer,
+        creator,
+        &account,
+        elgamal_keypair,
+        aes_key,
+    )
+    .await?;
+    let response = token
+        .set_authority(
+            &account.pubkey(),
+            &creator.pubkey(),
+            Some(&multisig.pubkey()),
+            AuthorityType::AccountOwner,
+            creator.signers(),
+        )
+        .await?;
+    into_signature(response)?;
+    info!(account = %account.pubkey(), "Escrow account handed to the multisig");
+    Ok(Escrow {
+        mint: *token.get_address(),
+        account: account.pubkey(),
+        multisig: multisig.pubkey(),
+        parties,
+        shares,
+    })
+}
+
+/// Checks `escrow` against the chain before trusting it with funds: the account holds the mint,
+/// is owned by the multisig and configured with the ElGamal pubkey of `elgamal_keypair`, the
+/// keys opened from the escrow; the multisig takes both parties to sign.
+pub async fn verify_escrow(
+    token: &TokenClient,
+    escrow: &Escrow,
+    elgamal_keypair: &ElGamalKeypair,
+) -> Result<()> {
+    if token.get_address() != &escrow.mint {
+        bail!("Escrow {} holds mint {}", escrow.account, escrow.mint);
+    }
+    let account = token.get_account_info(&escrow.account).await?;
+    if account.base.owner != escrow.multisig {
+        bail!(
+            "Escrow account {} is owned by {}, not by the multisig {}",
+            escrow.account,
+            account.base.owner,
+            escrow.multisig
+        );
+    }
+    if &get_elgamal_pubkey(token, &escrow.account).await? != elgamal_keypair.pubkey() {
+        bail!(
+            "Escrow account {} is configured with other keys than the escrow's",
+            escrow.account
+        );
+    }
+    let data = token.get_account(escrow.multisig).await?.data;
+    let multisig = Multisig::unpack(&data)
+        .map_err(|_| anyhow!("Account {} is not an SPL multisig", escrow.multisig))?;
+    let members = &multisig.signers[..usize::from(multisig.n)];
+    if multisig.m != 2
+        || multisig.n != 2
+        || escrow.parties.iter().any(|party| !members.contains(party))
+    {
+        bail!(
+            "Multisig {} is not the 2-of-2 multisig of {} and {}",
+            escrow.multisig,
+            escrow.parties[0],
+            escrow.parties[1]
+        );
+    }
+    Ok(())
+}
+
+/// Authority of the escrow account: the multisig, signed by both parties, `signers[0]` paying
+/// for and closing the proof accounts of a release.
+pub fn escrow_authority(escrow: &Escrow, signers: Vec<Arc<dyn Signer>>) -> Result<Authority> {
+    for party in &escrow.parties {
+        if !signers.iter().any(|signer| &signer.pubkey() == party) {
+            bail!(
+                "Releasing escrow {} takes the signature of {party}",
+                escrow.account
+            );
+        }
+    }
+    Authority::multisig(escrow.multisig, signers)
+}
+
+/// Releases `amount`, or the whole balance when `None`, of the escrow to the associated token
+/// account of `recipient`, one of the parties, with `authority` from [`escrow_authority`].
+///
+/// The pending balance is applied first, so funds sent to the escrow are released too.
+#[instrument(skip_all, fields(account = %escrow.account, recipient = %recipient))]
+pub async fn release(
+    token: &TokenClient,
+    escrow: &Escrow,
+    authority: &Authority,
+    recipient: &Pubkey,
+    amount: Option<u64>,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+) -> Result<Release> {
+    if !escrow.parties.contains(recipient) {
+        bail!(
+            "{recipient} is not a party of escrow {}; funds are only released to a party",
+            escrow.account
+        );
+    }
+    let pending_credits = balance::get_pending_credits(token, &escrow.account).await?;
+    let apply_signature = if pending_credits.counter > 0 {
+        Some(
+            deposit::apply_pending_balance(
+                token,
+                authority,
+                &escrow.account,
+                elgamal_keypair,
+                aes_key,
+            )
+            .await?,
+        )
+    } else {
+        None
+    };
+    let available = balance::get_available_balance(token, &escrow.account, aes_key).await?;
+    let amount = amount.unwrap_or(available);
+    if amount == 0 {
+        bail!("Escrow {} holds nothing to release", escrow.account);
+    }
+    if amount > available {
+        bail!(
+            "Escrow {} holds {}, below the {} to release",
+            escrow.account,
+            available,
+            amount
+        );
+    }
+    let transfer = transfer::transfer(
+        token,
+        authority,
+        &escrow.account,
+        &get_ata(recipient, token.get_address()),
+        amount,
+        elgamal_keypair,
+        aes_key,
+        None,
+        Packing::Separate,
+    )
+    .await?;
+    info!(amount, "Escrow released");
+    Ok(Release {
+        apply_signature,
+        amount,
+        recipient: *recipient,
+        transfer,
+    })
+}
+
+// AES-256-GCM cipher of a key share, keyed with the agreed point and the ephemeral point
+fn share_cipher(shared: &RistrettoPoint, ephemeral: &[u8; 32]) -> Aes256Gcm {
+    let key = Sha256::new()
+        .chain_update(KEY_SHARE_DOMAIN)
+        .chain_update(shared.compress().as_bytes())
+        .chain_update(ephemeral)
+        .finalize();
+    Aes256Gcm::new(&key)
+}
+
+// Associated data of a key share, binding it to the escrow account and its party
+fn share_aad(account: &Pubkey, party: &Pubkey) -> Vec<u8> {
+    [KEY_SHARE_DOMAIN, account.as_ref(), party.as_ref()].concat()
+}
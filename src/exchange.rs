@@ -0,0 +1,329 @@
+use anyhow::{Context, Result, anyhow};
+use confidential_transfer_core::derivation::SeedMessage;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::{Signer, keypair::keypair_from_seed},
+};
+use spl_token_client::spl_token_2022::solana_zk_sdk::encryption::{
+    auth_encryption::AeKey, elgamal::ElGamalKeypair,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tracing::{debug, info, instrument};
+
+use crate::{
+    TokenClient,
+    authority::Authority,
+    balance,
+    config::config_dir,
+    decode_table::DecodeTable,
+    deposit,
+    ledger::derive_with,
+    mint::{self, ConfiguredAccount},
+    placement::Packing,
+    transfer::{self, TransferResult},
+};
+
+//Prefix of the message the exchange owner signs to derive the wallet of a user
+const USER_WALLET_MESSAGE_PREFIX: &str = "confidential-transfer deposit address of user ";
+
+/// Deposit address of one user of an exchange: a wallet of their own, derived from the
+/// exchange owner, and its associated token account for the mint.
+///
+/// Every user gets a dedicated account, so each incoming credit belongs to the user of the
+/// account it lands in; nothing on chain links the accounts to the exchange but the sweeps.
+pub struct DepositAddress {
+    /// Identifier of the user in the exchange, e.g. a database id.
+    pub user_id: String,
+    /// Wallet derived for the user, the owner of the account.
+    pub wallet: Arc<Keypair>,
+    /// Mint of the account.
+    pub mint: Pubkey,
+    /// Associated token account of the wallet, the address users deposit to.
+    pub account: Pubkey,
+}
+
+impl DepositAddress {
+    /// Derives the deposit address of `user_id` for `mint` from signatures of `exchange_owner`.
+    ///
+    /// Derivation is deterministic, so the exchange stores no keys: its owner signer alone
+    /// recovers every user wallet and, through it, the account keys.
+    pub fn derive(exchange_owner: &dyn Signer, mint: &Pubkey, user_id: &str) -> Result<Self> {
+        let message = format!("{USER_WALLET_MESSAGE_PREFIX}{user_id}");
+        let signature = derive_with(exchange_owner, |signer| {
+            signer.try_sign_message(message.as_bytes())
+        })?;
+        //Rejects the default signature some signers return, which is no key material
+        if signature == Signature::default() {
+            return Err(anyhow!("The exchange owner returned an empty signature"));
+        }
+        let seed = Sha256::digest(signature.as_ref());
+        let wallet = keypair_from_seed(&seed)
+            .map_err(|e| anyhow!("Failed to derive the wallet of user {user_id}: {e}"))?;
+        Ok(Self {
+            user_id: user_id.to_string(),
+            account: mint::get_ata(&wallet.pubkey(), mint),
+            wallet: Arc::new(wallet),
+            mint: *mint,
+        })
+    }
+
+    /// Owner of the account, signing its applies and sweeps.
+    pub fn authority(&self) -> Authority {
+        Authority::single(self.wallet.clone())
+    }
+
+    /// ElGamal keypair and AES key of the account, derived from the user wallet.
+    pub fn keys(&self) -> Result<(ElGamalKeypair, AeKey)> {
+        mint::derive_keys(
+            self.wallet.as_ref(),
+            &SeedMessage::AccountAddress,
+            &self.account,
+        )
+    }
+}
+
+/// Deposit credits waiting in the pending balance of a user's account, found by
+/// [`pending_deposits`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingDeposit {
+    /// User the account belongs to.
+    pub user_id: String,
+    /// Deposit account of the user.
+    pub account: Pubkey,
+    /// Credits received since the last sweep.
+    pub credits: u64,
+    /// Amount of those credits in base units.
+    pub amount: u64,
+}
+
+/// Balances moved out of a user's account by [`sweep`].
+#[derive(Debug)]
+pub struct Sweep {
+    /// User the account belongs to.
+    pub user_id: String,
+    /// Deposit account of the user.
+    pub account: Pubkey,
+    /// Amount credited to the user by this sweep: the pending balance applied, in base units.
+    pub credited: u64,
+    /// Signature of the apply, if there was a pending balance.
+    pub apply_signature: Option<Signature>,
+    /// Amount moved to the omnibus account, including deposits applied by earlier runs whose
+    /// transfer failed.
+    pub swept: u64,
+    /// The transfer to the omnibus account, if there was an available balance.
+    pub transfer: Option<TransferResult>,
+}
+
+/// Creates the deposit account of `user_id` if needed and configures it for confidential
+/// transfers, with `fee_payer`, the payer of `token`, funding the rent.
+///
+/// Safe to re-run, like [`mint::create_configure_ata`].
+#[instrument(skip_all, fields(user_id))]
+pub async fn open_deposit_address(
+    token: &TokenClient,
+    fee_payer: &Pubkey,
+    exchange_owner: &dyn Signer,
+    user_id: &str,
+) -> Result<(DepositAddress, ConfiguredAccount)> {
+    let address = DepositAddress::derive(exchange_owner, token.get_address(), user_id)?;
+    let configured = mint::create_configure_ata(token, fee_payer, &address.authority()).await?;
+    Ok((address, configured))
+}
+
+/// Reads the accounts of `addresses` and returns the deposits waiting in their pending
+/// balances, decrypted with the keys of each user, in the order of `addresses`.
+///
+/// Accounts without pending credits are left out; accounts that cannot be read fail the scan.
+pub async fn pending_deposits(
+    token: &TokenClient,
+    addresses: &[DepositAddress],
+    decode_table: &DecodeTable,
+) -> Result<Vec<PendingDeposit>> {
+    let mut deposits = vec![];
+    for address in addresses {
+        let (elgamal_keypair, aes_key) = address.keys()?;
+        let balances = balance::get_balances(
+            token,
+            &address.account,
+            &elgamal_keypair,
+            &aes_key,
+            decode_table,
+        )
+        .await
+        .with_context(|| format!("Unable to read the account of user {}", address.user_id))?;
+        if balances.pending_credits.counter > 0 {
+            deposits.push(PendingDeposit {
+                user_id: address.user_id.clone(),
+                account: address.account,
+                credits: balances.pending_credits.counter,
+                amount: balances.pending,
+            });
+        }
+    }
+    Ok(deposits)
+}
+
+/// Applies the pending balance of the deposit account of `address`, crediting its amount to the
+/// user, and transfers the whole available balance to the `omnibus` token account.
+///
+/// The credited amount is the difference of the available balances around the apply, so
+/// credits landing during the sweep are counted by whichever sweep applies them. Each transfer
+/// raises the pending credit counter of `omnibus`: apply it regularly. The fee payer of `token`
+/// pays every transaction; user wallets need no SOL.
+#[instrument(skip_all, fields(user_id = %address.user_id, account = %address.account))]
+pub async fn sweep(
+    token: &TokenClient,
+    address: &DepositAddress,
+    omnibus: &Pubkey,
+) -> Result<Sweep> {
+    let owner = address.authority();
+    let (elgamal_keypair, aes_key) = address.keys()?;
+    let pending_credits = balance::get_pending_credits(token, &address.account).await?;
+    let available = balance::get_available_balance(token, &address.account, &aes_key).await?;
+    let (credited, apply_signature, available) = if pending_credits.counter > 0 {
+        let signature = deposit::apply_pending_balance(
+            token,
+            &owner,
+            &address.account,
+            &elgamal_keypair,
+            &aes_key,
+        )
+        .await?;
+        let applied = balance::get_available_balance(token, &address.account, &aes_key).await?;
+        (applied.saturating_sub(available), Some(signature), applied)
+    } else {
+        (0, None, available)
+    };
+    debug!(credited, available, "Pending balance applied");
+    let transfer = if available > 0 {
+        Some(
+            transfer::transfer(
+                token,
+                &owner,
+                &address.account,
+                omnibus,
+                available,
+                &elgamal_keypair,
+                &aes_key,
+                None,
+                Packing::Separate,
+            )
+            .await?,
+        )
+    } else {
+        None
+    };
+    info!(credited, swept = available, "Deposit account swept");
+    Ok(Sweep {
+        user_id: address.user_id.clone(),
+        account: address.account,
+        credited,
+        apply_signature,
+        swept: available,
+        transfer,
+    })
+}
+
+/// A deposit address opened by the exchange, as recorded in the [`DepositIndex`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepositIndexEntry {
+    /// Identifier of the user.
+    pub user_id: String,
+    /// Exchange owner the user wallet was derived from.
+    pub exchange_owner: String,
+    /// Mint of the account.
+    pub mint: String,
+    /// Deposit account of the user.
+    pub account: String,
+}
+
+/// Record of the deposit addresses opened by an exchange, to find which user an account belongs
+/// to and to list the accounts to sweep.
+///
+/// Holds no secrets: addresses are derived again from the exchange owner. Changes are kept in
+/// memory until [`DepositIndex::save`].
+#[derive(Debug, Clone)]
+pub struct DepositIndex {
+    path: PathBuf,
+    entries: Vec<DepositIndexEntry>,
+}
+
+impl DepositIndex {
+    /// Default location of the index file.
+    pub fn default_path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("deposit-addresses.json"))
+    }
+
+    /// Loads the index file at `path`; a missing file is an empty index.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self {
+                path,
+                entries: vec![],
+            });
+        }
+        let contents = std::fs::read(&path)
+            .with_context(|| format!("Unable to read deposit index {}", path.display()))?;
+        let entries = serde_json::from_slice(&contents)
+            .with_context(|| format!("Invalid deposit index {}", path.display()))?;
+        Ok(Self { path, entries })
+    }
+
+    /// Path of the index file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes the index through a temporary file, creating its directory if needed.
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Unable to create {}", parent.display()))?;
+        }
+        let tmp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec_pretty(&self.entries)?)
+            .with_context(|| format!("Unable to write deposit index {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Unable to write deposit index {}", self.path.display()))?;
+        Ok(())
+    }
+
+    /// Adds `address` of `exchange_owner`, unless already recorded.
+    pub fn record(&mut self, exchange_owner: &Pubkey, address: &DepositAddress) {
+        let entry = DepositIndexEntry {
+            user_id: address.user_id.clone(),
+            exchange_owner: exchange_owner.to_string(),
+            mint: address.mint.to_string(),
+            account: address.account.to_string(),
+        };
+        if !self.entries.contains(&entry) {
+            self.entries.push(entry);
+        }
+    }
+
+    /// User the deposit `account` belongs to, if recorded.
+    pub fn user_of(&self, account: &Pubkey) -> Option<&str> {
+        let account = account.to_string();
+        self.entries
+            .iter()
+            .find(|entry| entry.account == account)
+            .map(|entry| entry.user_id.as_str())
+    }
+
+    /// Users of `exchange_owner` with a deposit address for `mint`, in the order they were
+    /// recorded.
+    pub fn users(&self, exchange_owner: &Pubkey, mint: &Pubkey) -> Vec<&str> {
+        let (exchange_owner, mint) = (exchange_owner.to_string(), mint.to_string());
+        self.entries
+            .iter()
+            .filter(|entry| entry.exchange_owner == exchange_owner && entry.mint == mint)
+            .map(|entry| entry.user_id.as_str())
+            .collect()
+    }
+}
@@ -0,0 +1,171 @@
+use async_trait::async_trait;
+use serde_json::Value;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    rpc_custom_error::JSON_RPC_SERVER_ERROR_SEND_TRANSACTION_PREFLIGHT_FAILURE,
+    rpc_request::{RpcError, RpcRequest, RpcResponseErrorData},
+    rpc_response::RpcSimulateTransactionResult,
+    rpc_sender::{RpcSender as RpcTransport, RpcTransportStats},
+};
+use solana_sdk::{hash::Hash, transaction::TransactionError};
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+};
+use tracing::debug;
+
+/// Failure a [`FaultInjectingTransport`] answers a request with instead of the response of the
+/// transport it wraps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The request times out without reaching the node, as when it is unreachable.
+    Timeout,
+    /// The request reaches the node, then times out, as when the response is lost: a
+    /// transaction sent this way lands all the same.
+    TimeoutAfterSending,
+    /// `getLatestBlockhash` answers a blockhash the cluster does not know, as a lagging node
+    /// does, so transactions built on it are rejected or never land.
+    StaleBlockhash,
+    /// `sendTransaction` fails the preflight with `BlockhashNotFound`, as a node that has not
+    /// seen the blockhash yet answers.
+    BlockhashNotFound,
+    /// `sendTransaction` reaches the node, then fails the preflight with `AlreadyProcessed`, as
+    /// a resend of a transaction that already landed does.
+    AlreadyProcessed,
+}
+
+/// Faults to inject, each at a call of an RPC method, and those injected so far.
+///
+/// Share it between the [`FaultInjectingTransport`] and the test, which reads
+/// [`FaultPlan::injected`] back once the operation under test has recovered, or not.
+#[derive(Debug, Default)]
+pub struct FaultPlan {
+    faults: HashMap<(RpcRequest, u32), Fault>,
+    //Calls seen of each method
+    calls: Mutex<HashMap<RpcRequest, u32>>,
+    injected: Mutex<Vec<(RpcRequest, u32, Fault)>>,
+}
+
+impl FaultPlan {
+    /// Plan without faults: every request is forwarded.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Same plan, answering call number `call` of `request`, counted from 0, with `fault`.
+    pub fn inject(mut self, request: RpcRequest, call: u32, fault: Fault) -> Self {
+        self.faults.insert((request, call), fault);
+        self
+    }
+
+    /// Faults injected so far, in order, with the method and call they answered.
+    pub fn injected(&self) -> Vec<(RpcRequest, u32, Fault)> {
+        self.injected.lock().unwrap().clone()
+    }
+
+    // Fault planned for the next call of `request`, counting the call
+    fn next(&self, request: RpcRequest) -> Option<Fault> {
+        let call = {
+            let mut calls = self.calls.lock().unwrap();
+            let calls = calls.entry(request).or_default();
+            *calls += 1;
+            *calls - 1
+        };
+        let fault = *self.faults.get(&(request, call))?;
+        self.injected.lock().unwrap().push((request, call, fault));
+        Some(fault)
+    }
+}
+
+/// Transport answering the calls of `inner` chosen by a [`FaultPlan`] with a [`Fault`], for
+/// tests checking that retries, resends and resumed flows recover mid-operation.
+///
+/// Place it under a [`RetryingTransport`](crate::retry::RetryingTransport) to see injected
+/// timeouts retried, and under [`RpcClient::new_sender`] to drive the library on it, e.g. over
+/// a `solana_rpc_client::mock_sender::MockSender` or the HTTP sender of a test validator.
+///
+/// [`RpcClient::new_sender`]: solana_client::nonblocking::rpc_client::RpcClient::new_sender
+pub struct FaultInjectingTransport<T> {
+    inner: T,
+    plan: Arc<FaultPlan>,
+}
+
+impl<T> FaultInjectingTransport<T> {
+    /// Wraps `inner`, injecting the faults of `plan`.
+    pub fn new(inner: T, plan: Arc<FaultPlan>) -> Self {
+        Self { inner, plan }
+    }
+}
+
+#[async_trait]
+impl<T: RpcTransport + Send + Sync> RpcTransport for FaultInjectingTransport<T> {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        let Some(fault) = self.plan.next(request) else {
+            return self.inner.send(request, params).await;
+        };
+        debug!(%request, ?fault, "Injecting RPC fault");
+        match fault {
+            Fault::Timeout => Err(timeout(request)),
+            Fault::TimeoutAfterSending => {
+                self.inner.send(request, params).await?;
+                Err(timeout(request))
+            }
+            Fault::StaleBlockhash => {
+                let mut response = self.inner.send(request, params).await?;
+                response["value"]["blockhash"] = Value::String(Hash::new_unique().to_string());
+                Ok(response)
+            }
+            Fault::BlockhashNotFound => Err(preflight_failure(
+                request,
+                TransactionError::BlockhashNotFound,
+            )),
+            Fault::AlreadyProcessed => {
+                self.inner.send(request, params).await?;
+                Err(preflight_failure(
+                    request,
+                    TransactionError::AlreadyProcessed,
+                ))
+            }
+        }
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.inner.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.inner.url()
+    }
+}
+
+// Timeout of `request`, as the HTTP transport reports one
+fn timeout(request: RpcRequest) -> ClientError {
+    ClientError::new_with_request(
+        ClientErrorKind::Io(io::Error::new(io::ErrorKind::TimedOut, "injected timeout")),
+        request,
+    )
+}
+
+// Preflight failure of `request` with `err`, as a node answers `sendTransaction`
+fn preflight_failure(request: RpcRequest, err: TransactionError) -> ClientError {
+    ClientError::new_with_request(
+        ClientErrorKind::RpcError(RpcError::RpcResponseError {
+            code: JSON_RPC_SERVER_ERROR_SEND_TRANSACTION_PREFLIGHT_FAILURE,
+            message: format!("Transaction simulation failed: {err}"),
+            data: RpcResponseErrorData::SendTransactionPreflightFailure(
+                RpcSimulateTransactionResult {
+                    err: Some(err),
+                    logs: Some(vec![]),
+                    accounts: None,
+                    units_consumed: None,
+                    loaded_accounts_data_size: None,
+                    return_data: None,
+                    inner_instructions: None,
+                    replacement_blockhash: None,
+                },
+            ),
+        }),
+        request,
+    )
+}
@@ -0,0 +1,141 @@
+use anyhow::{Result, anyhow};
+use confidential_transfer_core::balance::decrypt_available_balance;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Signature, signer::Signer};
+use spl_token_client::spl_token_2022::{
+    extension::{
+        BaseStateWithExtensions,
+        confidential_transfer::ConfidentialTransferAccount,
+        confidential_transfer_fee::{
+            ConfidentialTransferFeeAmount, ConfidentialTransferFeeConfig,
+            account_info::WithheldTokensInfo,
+        },
+    },
+    solana_zk_sdk::encryption::{
+        auth_encryption::AeKey,
+        elgamal::{ElGamalCiphertext, ElGamalKeypair},
+        pod::elgamal::PodElGamalCiphertext,
+    },
+};
+use std::sync::Arc;
+use tracing::{debug, instrument};
+
+use crate::{
+    TokenClient, decode_table::DecodeTable, inspect::get_token_accounts,
+    transfer::get_elgamal_pubkey, utils::into_signature,
+};
+
+//Source accounts per harvest transaction, kept well below the transaction size limit
+const HARVEST_BATCH_SIZE: usize = 20;
+
+/// Withheld fees moved from the mint by [`withdraw_withheld_fees`].
+#[derive(Debug, Clone, Copy)]
+pub struct WithdrawnFees {
+    /// Withdrawn amount in base units, credited to the available balance of the destination.
+    pub amount: u64,
+    /// Signature of the withdraw instruction.
+    pub signature: Signature,
+}
+
+/// Lists the token accounts of `mint` holding confidential transfer fees not yet harvested to
+/// the mint.
+///
+/// Scans the token accounts of the mint with [`get_token_accounts`], so the RPC node must allow
+/// `getProgramAccounts` for the Token-2022 program.
+#[instrument(skip_all, fields(%mint))]
+pub async fn list_accounts_with_withheld_fees(
+    rpc_client: &RpcClient,
+    mint: &Pubkey,
+) -> Result<Vec<Pubkey>> {
+    Ok(get_token_accounts(rpc_client, mint)
+        .await?
+        .into_iter()
+        .filter(|(_, state)| {
+            //Harvesting resets the ciphertext to zero, encoded as the default ciphertext
+            state
+                .get_extension::<ConfidentialTransferFeeAmount>()
+                .is_ok_and(|extension| extension.withheld_amount != PodElGamalCiphertext::default())
+        })
+        .map(|(address, _)| address)
+        .collect())
+}
+
+/// Moves the withheld confidential fees of `sources` into the mint.
+///
+/// Harvesting is permissionless and needs no proof: the encrypted fees are added homomorphically
+/// to the withheld amount of the mint. Accounts are processed in batches, one transaction each.
+#[instrument(skip_all, fields(sources = sources.len()))]
+pub async fn harvest_withheld_fees(
+    token: &TokenClient,
+    sources: &[Pubkey],
+) -> Result<Vec<Signature>> {
+    let mut signatures = Vec::with_capacity(sources.len().div_ceil(HARVEST_BATCH_SIZE));
+    for batch in sources.chunks(HARVEST_BATCH_SIZE) {
+        let batch = batch.iter().collect::<Vec<_>>();
+        let signature = into_signature(
+            token
+                .confidential_transfer_harvest_withheld_tokens_to_mint(&batch)
+                .await?,
+        )?;
+        debug!(%signature, accounts = batch.len(), "Harvested withheld fees");
+        signatures.push(signature);
+    }
+    Ok(signatures)
+}
+
+/// Withdraws the confidential fees withheld in the mint to the available balance of
+/// `destination_ata`.
+///
+/// The withheld amount is decrypted with the withdraw withheld authority ElGamal keypair and
+/// re-encrypted under the destination's ElGamal pubkey; a ciphertext-ciphertext equality proof,
+/// small enough to travel in the same transaction, shows both ciphertexts hold the same amount.
+/// `destination_aes_key` is needed to update the decryptable available balance of the
+/// destination.
+#[instrument(skip_all, fields(destination = %destination_ata))]
+pub async fn withdraw_withheld_fees(
+    token: &TokenClient,
+    withdraw_withheld_authority: Arc<dyn Signer>,
+    destination_ata: &Pubkey,
+    withheld_elgamal_keypair: &ElGamalKeypair,
+    destination_aes_key: &AeKey,
+    decode_table: &DecodeTable,
+) -> Result<WithdrawnFees> {
+    let mint_info = token.get_mint_info().await?;
+    let withheld_amount = mint_info
+        .get_extension::<ConfidentialTransferFeeConfig>()?
+        .withheld_amount;
+    let amount = ElGamalCiphertext::try_from(withheld_amount)
+        .ok()
+        .and_then(|ciphertext| decode_table.decrypt(withheld_elgamal_keypair.secret(), &ciphertext))
+        .ok_or_else(|| anyhow!("Unable to decrypt the withheld fees; wrong withheld keypair?"))?;
+    debug!(amount, "Decrypted withheld fees");
+
+    let destination_elgamal_pubkey = get_elgamal_pubkey(token, destination_ata).await?;
+    let destination_account = token.get_account_info(destination_ata).await?;
+    let available_balance = decrypt_available_balance(
+        destination_account.get_extension::<ConfidentialTransferAccount>()?,
+        destination_aes_key,
+    )
+    .ok_or_else(|| anyhow!("Failed to decrypt the available balance of {destination_ata}"))?;
+    let new_decryptable_available_balance = available_balance
+        .checked_add(amount)
+        .map(|balance| destination_aes_key.encrypt(balance))
+        .ok_or_else(|| anyhow!("Available balance overflow"))?;
+
+    let signature = into_signature(
+        token
+            .confidential_transfer_withdraw_withheld_tokens_from_mint(
+                destination_ata,                       //Destination ata
+                &withdraw_withheld_authority.pubkey(), //Withdraw withheld authority
+                None,                                  //Proof in the same transaction
+                Some(WithheldTokensInfo::new(&withheld_amount)),
+                withheld_elgamal_keypair,
+                &destination_elgamal_pubkey,
+                &new_decryptable_available_balance.into(),
+                &[&withdraw_withheld_authority],
+            )
+            .await?,
+    )?;
+    debug!(%signature, "Withheld fees withdrawn");
+    Ok(WithdrawnFees { amount, signature })
+}
@@ -0,0 +1,733 @@
+use anyhow::{Context, Result, anyhow};
+use confidential_transfer_core::{
+    balance::decrypt_available_balance,
+    proofs::{self, TransferProofs},
+};
+use solana_sdk::{pubkey::Pubkey, signature::read_keypair_file, signer::Signer};
+use spl_token_client::{
+    client::ProgramClient,
+    spl_token_2022::{
+        extension::{
+            BaseStateWithExtensions, StateWithExtensions,
+            confidential_transfer::{
+                ConfidentialTransferAccount,
+                account_info::{TransferAccountInfo, WithdrawAccountInfo},
+            },
+        },
+        solana_zk_sdk::encryption::{
+            auth_encryption::AeKey,
+            elgamal::{ElGamalKeypair, ElGamalSecretKey},
+        },
+        state::Account,
+    },
+};
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString, c_char},
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    ptr, slice,
+    sync::Arc,
+};
+use tokio::runtime::{Builder, Runtime};
+
+use crate::{
+    authority::Authority,
+    balance::decrypt_pending_balance,
+    client::{self, ComputeBudget, RpcSender},
+    config::Config,
+    decode_table::DecodeTable,
+    mint, retry,
+    utils::parse_elgamal_pubkey,
+};
+
+//Length of an AES ciphertext of a decryptable balance
+const AE_CIPHERTEXT_LEN: usize = 36;
+//Length of an ElGamal ciphertext
+const ELGAMAL_CIPHERTEXT_LEN: usize = 64;
+
+thread_local! {
+    //Message of the last failure on this thread, for `ct_last_error`
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Outcome of every `ct_*` function that can fail; the message of a failure is read with
+/// [`ct_last_error`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CtStatus {
+    /// Success: the out parameters are set.
+    Ok = 0,
+    /// A null pointer, a string that is not UTF-8, or a malformed address, key or account.
+    InvalidArgument = 1,
+    /// The operation itself failed, e.g. its transaction or the proof generation.
+    Failed = 2,
+    /// The operation panicked; the handles it was given must not be used again.
+    Panicked = 3,
+}
+
+/// Connection to a cluster and the keypair that pays the fees and owns the accounts.
+pub struct CtClient {
+    runtime: Runtime,
+    program_client: Arc<dyn ProgramClient<RpcSender>>,
+    keypair: Arc<dyn Signer>,
+    owner: Authority,
+}
+
+/// ElGamal keypair and AES key of a token account.
+pub struct CtKeys {
+    elgamal_keypair: ElGamalKeypair,
+    aes_key: AeKey,
+}
+
+/// Decode table solving the discrete logs of pending balances.
+pub struct CtDecodeTable(DecodeTable);
+
+/// Bytes allocated by this library, freed with [`ct_bytes_free`].
+#[repr(C)]
+#[derive(Debug)]
+pub struct CtBytes {
+    /// First byte; null when empty.
+    pub data: *mut u8,
+    /// Number of bytes.
+    pub len: usize,
+}
+
+/// Decrypted balances of a token account, in base units.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CtBalances {
+    /// Normal (transparent) balance.
+    pub public_balance: u64,
+    /// Confidential credits not yet applied.
+    pub pending_balance: u64,
+    /// Confidential balance available for transfers and withdrawals.
+    pub available_balance: u64,
+}
+
+/// Proof data of a withdraw, each ready for the verify instruction of the ZK ElGamal proof
+/// program; freed with [`ct_withdraw_proofs_free`].
+#[repr(C)]
+#[derive(Debug)]
+pub struct CtWithdrawProofs {
+    /// `CiphertextCommitmentEqualityProofData`.
+    pub equality: CtBytes,
+    /// `BatchedRangeProofU64Data`.
+    pub range: CtBytes,
+    /// Remaining available balance encrypted with the AES key, for the withdraw instruction.
+    pub new_decryptable_available_balance: [u8; AE_CIPHERTEXT_LEN],
+}
+
+/// Proof data of a transfer, each ready for the verify instruction of the ZK ElGamal proof
+/// program; freed with [`ct_transfer_proofs_free`].
+#[repr(C)]
+#[derive(Debug)]
+pub struct CtTransferProofs {
+    /// `CiphertextCommitmentEqualityProofData`.
+    pub equality: CtBytes,
+    /// `BatchedGroupedCiphertext3HandlesValidityProofData`.
+    pub ciphertext_validity: CtBytes,
+    /// `BatchedRangeProofU128Data`.
+    pub range: CtBytes,
+    /// Low 16 bits of the amount encrypted to the auditor, for the transfer instruction.
+    pub auditor_ciphertext_lo: [u8; ELGAMAL_CIPHERTEXT_LEN],
+    /// High 32 bits of the amount encrypted to the auditor, for the transfer instruction.
+    pub auditor_ciphertext_hi: [u8; ELGAMAL_CIPHERTEXT_LEN],
+    /// Remaining available balance encrypted with the AES key, for the transfer instruction.
+    pub new_decryptable_available_balance: [u8; AE_CIPHERTEXT_LEN],
+}
+
+/// Message of the last failure on the calling thread, or null if none; valid until the next
+/// failing call on the thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn ct_last_error() -> *const c_char {
+    LAST_ERROR.with_borrow(|message| message.as_ref().map_or(ptr::null(), |m| m.as_ptr()))
+}
+
+/// Connects to a cluster with the keypair that pays the fees and owns the accounts.
+///
+/// Settings come from the config file at `config_path`, or the default one when null, with
+/// `rpc_url`, a URL or a moniker like `devnet`, and `keypair_path` overriding it unless null.
+/// Account keys are derived with the seed message of the config, as the CLI does.
+///
+/// # Safety
+///
+/// The strings must be null or NUL-terminated and `out` writable; free the client with
+/// [`ct_client_free`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ct_client_new(
+    config_path: *const c_char,
+    rpc_url: *const c_char,
+    keypair_path: *const c_char,
+    out: *mut *mut CtClient,
+) -> CtStatus {
+    run(|| {
+        let config_path = unsafe { optional_str_arg("config_path", config_path) }?;
+        let rpc_url = unsafe { optional_str_arg("rpc_url", rpc_url) }?;
+        let keypair_path = unsafe { optional_str_arg("keypair_path", keypair_path) }?;
+        let out = unsafe { out_arg("out", out) }?;
+        let mut config = Config::load(config_path.map(Path::new))?;
+        config.rpc_url = rpc_url.map(str::to_string).or(config.rpc_url);
+        config.keypair_path = keypair_path.map(Into::into).or(config.keypair_path);
+        let keypair_path = config.keypair_path()?;
+        let keypair = read_keypair_file(&keypair_path)
+            .map_err(|err| anyhow!("Unable to read keypair {}: {err}", keypair_path.display()))?;
+        let keypair: Arc<dyn Signer> = Arc::new(keypair);
+        let runtime = Builder::new_multi_thread().enable_all().build()?;
+        let _runtime = runtime.enter();
+        let rpc_client = Arc::new(retry::rpc_client(
+            config.rpc_url(),
+            config.commitment()?,
+            config.retry_policy()?,
+            config.rate_limit()?,
+        ));
+        //No websocket subscription outlives a call, so confirmations are polled
+        let program_client = client::program_client(rpc_client, RpcSender::new(false));
+        *out = Box::into_raw(Box::new(CtClient {
+            program_client,
+            owner: Authority::single(keypair.clone()).with_seed_message(config.seed_message()?),
+            keypair,
+            runtime,
+        }));
+        Ok(())
+    })
+}
+
+/// Frees a client; null is ignored.
+///
+/// # Safety
+///
+/// `client` must come from [`ct_client_new`] and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ct_client_free(client: *mut CtClient) {
+    if !client.is_null() {
+        drop(unsafe { Box::from_raw(client) });
+    }
+}
+
+/// Creates and configures the associated token account of the client's keypair for `mint`.
+///
+/// Sets `out_keys` to the keys of the account, `out_ata` to its address and `out_signature`
+/// to the signature of the transaction, or null if the account was already configured. Safe
+/// to re-run.
+///
+/// # Safety
+///
+/// `client` must be live, `mint` NUL-terminated and the out parameters writable; free the
+/// strings with [`ct_string_free`] and the keys with [`ct_keys_free`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ct_configure_account(
+    client: *const CtClient,
+    mint: *const c_char,
+    out_keys: *mut *mut CtKeys,
+    out_ata: *mut *mut c_char,
+    out_signature: *mut *mut c_char,
+) -> CtStatus {
+    run(|| {
+        let client = unsafe { handle_arg("client", client) }?;
+        let mint = unsafe { pubkey_arg("mint", mint) }?;
+        let (out_keys, out_ata, out_signature) = unsafe {
+            (
+                out_arg("out_keys", out_keys)?,
+                out_arg("out_ata", out_ata)?,
+                out_arg("out_signature", out_signature)?,
+            )
+        };
+        let configured = client.runtime.block_on(async {
+            let (token, _decimals) = mint::load_token(
+                client.program_client.clone(),
+                client.keypair.clone(),
+                &mint,
+                &ComputeBudget::default(),
+            )
+            .await?;
+            mint::create_configure_ata(&token, &client.keypair.pubkey(), &client.owner).await
+        })?;
+        *out_ata = string_out(configured.ata)?;
+        *out_signature = match configured.signature {
+            Some(signature) => string_out(signature)?,
+            None => ptr::null_mut(),
+        };
+        *out_keys = keys_out(configured.elgamal_keypair, configured.aes_key);
+        Ok(())
+    })
+}
+
+/// Derives the keys of the associated token account of the client's keypair for `mint`,
+/// without network access and without checking them against the account.
+///
+/// # Safety
+///
+/// `client` must be live, `mint` NUL-terminated and `out` writable; free the keys with
+/// [`ct_keys_free`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ct_keys_derive(
+    client: *const CtClient,
+    mint: *const c_char,
+    out: *mut *mut CtKeys,
+) -> CtStatus {
+    run(|| {
+        let client = unsafe { handle_arg("client", client) }?;
+        let mint = unsafe { pubkey_arg("mint", mint) }?;
+        let out = unsafe { out_arg("out", out) }?;
+        let ata = mint::get_ata(&client.keypair.pubkey(), &mint);
+        let (elgamal_keypair, aes_key) =
+            mint::derive_keys(client.keypair.as_ref(), client.owner.seed_message(), &ata)?;
+        *out = keys_out(elgamal_keypair, aes_key);
+        Ok(())
+    })
+}
+
+/// Loads keys stored by the caller: the 32-byte ElGamal secret key and the 16-byte AES key.
+///
+/// # Safety
+///
+/// `elgamal_secret_key` must point to 32 readable bytes, `aes_key` to 16 and `out` must be
+/// writable; free the keys with [`ct_keys_free`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ct_keys_from_bytes(
+    elgamal_secret_key: *const u8,
+    aes_key: *const u8,
+    out: *mut *mut CtKeys,
+) -> CtStatus {
+    run(|| {
+        let elgamal_secret_key =
+            unsafe { bytes_arg("elgamal_secret_key", elgamal_secret_key, 32) }?;
+        let aes_key = unsafe { bytes_arg("aes_key", aes_key, 16) }?;
+        let out = unsafe { out_arg("out", out) }?;
+        let secret = ElGamalSecretKey::try_from(elgamal_secret_key)
+            .map_err(|_| invalid("Invalid ElGamal secret key"))?;
+        let aes_key = AeKey::try_from(aes_key).map_err(|_| invalid("Invalid AES key"))?;
+        *out = keys_out(ElGamalKeypair::new(secret), aes_key);
+        Ok(())
+    })
+}
+
+/// Writes the 32-byte ElGamal secret key and the 16-byte AES key of `keys`, so the caller can
+/// store them.
+///
+/// # Safety
+///
+/// `keys` must be live, `out_elgamal_secret_key` must point to 32 writable bytes and
+/// `out_aes_key` to 16.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ct_keys_to_bytes(
+    keys: *const CtKeys,
+    out_elgamal_secret_key: *mut u8,
+    out_aes_key: *mut u8,
+) -> CtStatus {
+    run(|| {
+        let keys = unsafe { handle_arg("keys", keys) }?;
+        let elgamal_secret_key = keys.elgamal_keypair.secret().as_bytes();
+        let aes_key = <[u8; 16]>::from(keys.aes_key.clone());
+        unsafe {
+            out_arg("out_elgamal_secret_key", out_elgamal_secret_key)?;
+            out_arg("out_aes_key", out_aes_key)?;
+            ptr::copy_nonoverlapping(elgamal_secret_key.as_ptr(), out_elgamal_secret_key, 32);
+            ptr::copy_nonoverlapping(aes_key.as_ptr(), out_aes_key, 16);
+        }
+        Ok(())
+    })
+}
+
+/// Sets `out` to the base64 ElGamal pubkey of `keys`, as registered in the account.
+///
+/// # Safety
+///
+/// `keys` must be live and `out` writable; free the string with [`ct_string_free`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ct_keys_elgamal_pubkey(
+    keys: *const CtKeys,
+    out: *mut *mut c_char,
+) -> CtStatus {
+    run(|| {
+        let keys = unsafe { handle_arg("keys", keys) }?;
+        let out = unsafe { out_arg("out", out) }?;
+        *out = string_out(keys.elgamal_keypair.pubkey())?;
+        Ok(())
+    })
+}
+
+/// Frees keys; null is ignored.
+///
+/// # Safety
+///
+/// `keys` must come from this library and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ct_keys_free(keys: *mut CtKeys) {
+    if !keys.is_null() {
+        drop(unsafe { Box::from_raw(keys) });
+    }
+}
+
+/// Opens the decode table at `path`, or at the default location in the cache directory when
+/// null, building it there if missing.
+///
+/// # Safety
+///
+/// `path` must be null or NUL-terminated and `out` writable; free the table with
+/// [`ct_decode_table_free`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ct_decode_table_open(
+    path: *const c_char,
+    out: *mut *mut CtDecodeTable,
+) -> CtStatus {
+    run(|| {
+        let out = unsafe { out_arg("out", out) }?;
+        let decode_table = if path.is_null() {
+            DecodeTable::open_default()?
+        } else {
+            DecodeTable::open(Path::new(unsafe { str_arg("path", path) }?))?
+        };
+        *out = Box::into_raw(Box::new(CtDecodeTable(decode_table)));
+        Ok(())
+    })
+}
+
+/// Frees a decode table; null is ignored.
+///
+/// # Safety
+///
+/// `decode_table` must come from [`ct_decode_table_open`] and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ct_decode_table_free(decode_table: *mut CtDecodeTable) {
+    if !decode_table.is_null() {
+        drop(unsafe { Box::from_raw(decode_table) });
+    }
+}
+
+/// Decrypts the balances of the token account whose data is `account_data`, as fetched by the
+/// caller, with `keys`.
+///
+/// # Safety
+///
+/// `keys` and `decode_table` must be live, `account_data` must point to `account_len` readable
+/// bytes and `out` must be writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ct_decrypt_balances(
+    keys: *const CtKeys,
+    decode_table: *const CtDecodeTable,
+    account_data: *const u8,
+    account_len: usize,
+    out: *mut CtBalances,
+) -> CtStatus {
+    run(|| {
+        let keys = unsafe { handle_arg("keys", keys) }?;
+        let decode_table = unsafe { handle_arg("decode_table", decode_table) }?;
+        let account_data = unsafe { bytes_arg("account_data", account_data, account_len) }?;
+        let out = unsafe { out_arg("out", out) }?;
+        let account = StateWithExtensions::<Account>::unpack(account_data)
+            .map_err(|_| invalid("Invalid token account data"))?;
+        let extension = confidential_extension(&account)?;
+        let available_balance =
+            decrypt_available_balance(extension, &keys.aes_key).ok_or_else(|| {
+                anyhow!("The AES key does not decrypt the available balance; wrong keys?")
+            })?;
+        let pending_balance =
+            decrypt_pending_balance(extension, keys.elgamal_keypair.secret(), &decode_table.0)?;
+        *out = CtBalances {
+            public_balance: account.base.amount,
+            pending_balance,
+            available_balance,
+        };
+        Ok(())
+    })
+}
+
+/// Generates the pubkey validity proof data that configures an account for `keys`.
+///
+/// # Safety
+///
+/// `keys` must be live and `out` writable; free the bytes with [`ct_bytes_free`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ct_pubkey_validity_proof(
+    keys: *const CtKeys,
+    out: *mut CtBytes,
+) -> CtStatus {
+    run(|| {
+        let keys = unsafe { handle_arg("keys", keys) }?;
+        let out = unsafe { out_arg("out", out) }?;
+        *out = bytes_out(bytemuck::bytes_of(&proofs::pubkey_validity_proof(
+            &keys.elgamal_keypair,
+        )?));
+        Ok(())
+    })
+}
+
+/// Generates the proof data of withdrawing `amount` from the token account whose data is
+/// `account_data`, without network access.
+///
+/// # Safety
+///
+/// `keys` must be live, `account_data` must point to `account_len` readable bytes and `out`
+/// must be writable; free the proofs with [`ct_withdraw_proofs_free`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ct_withdraw_proofs(
+    keys: *const CtKeys,
+    account_data: *const u8,
+    account_len: usize,
+    amount: u64,
+    out: *mut CtWithdrawProofs,
+) -> CtStatus {
+    run(|| {
+        let keys = unsafe { handle_arg("keys", keys) }?;
+        let account_data = unsafe { bytes_arg("account_data", account_data, account_len) }?;
+        let out = unsafe { out_arg("out", out) }?;
+        let account = StateWithExtensions::<Account>::unpack(account_data)
+            .map_err(|_| invalid("Invalid token account data"))?;
+        let extension = confidential_extension(&account)?;
+        let proof_data = proofs::withdraw_proofs(
+            &WithdrawAccountInfo::new(extension),
+            amount,
+            &keys.elgamal_keypair,
+            &keys.aes_key,
+        )?;
+        *out = CtWithdrawProofs {
+            equality: bytes_out(bytemuck::bytes_of(&proof_data.equality_proof_data)),
+            range: bytes_out(bytemuck::bytes_of(&proof_data.range_proof_data)),
+            new_decryptable_available_balance: new_decryptable_balance(extension, keys, amount)?,
+        };
+        Ok(())
+    })
+}
+
+/// Frees the bytes of withdraw proofs; null is ignored.
+///
+/// # Safety
+///
+/// `proofs` must have been set by [`ct_withdraw_proofs`] and its bytes not freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ct_withdraw_proofs_free(proofs: *mut CtWithdrawProofs) {
+    if let Some(proofs) = unsafe { proofs.as_mut() } {
+        unsafe {
+            ct_bytes_free(std::mem::take(&mut proofs.equality));
+            ct_bytes_free(std::mem::take(&mut proofs.range));
+        }
+    }
+}
+
+/// Generates the proof data of transferring `amount` from the token account whose data is
+/// `account_data` to the holder of `destination_elgamal_pubkey`, encrypted to
+/// `auditor_elgamal_pubkey` as well unless null, without network access.
+///
+/// The ElGamal pubkeys are base64, as registered in the accounts and the mint. Mints with
+/// transfer fees take two more proofs, which this function does not generate.
+///
+/// # Safety
+///
+/// `keys` must be live, `account_data` must point to `account_len` readable bytes, the pubkeys
+/// must be NUL-terminated and `out` writable; free the proofs with [`ct_transfer_proofs_free`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ct_transfer_proofs(
+    keys: *const CtKeys,
+    account_data: *const u8,
+    account_len: usize,
+    amount: u64,
+    destination_elgamal_pubkey: *const c_char,
+    auditor_elgamal_pubkey: *const c_char,
+    out: *mut CtTransferProofs,
+) -> CtStatus {
+    run(|| {
+        let keys = unsafe { handle_arg("keys", keys) }?;
+        let account_data = unsafe { bytes_arg("account_data", account_data, account_len) }?;
+        let destination_elgamal_pubkey = parse_elgamal_pubkey(unsafe {
+            str_arg("destination_elgamal_pubkey", destination_elgamal_pubkey)
+        }?)
+        .map_err(invalid)?;
+        let auditor_elgamal_pubkey =
+            unsafe { optional_str_arg("auditor_elgamal_pubkey", auditor_elgamal_pubkey) }?
+                .map(|pubkey| parse_elgamal_pubkey(pubkey).map_err(invalid))
+                .transpose()?;
+        let out = unsafe { out_arg("out", out) }?;
+        let account = StateWithExtensions::<Account>::unpack(account_data)
+            .map_err(|_| invalid("Invalid token account data"))?;
+        let extension = confidential_extension(&account)?;
+        let TransferProofs::Plain(proof_data) = proofs::transfer_proofs(
+            &TransferAccountInfo::new(extension),
+            amount,
+            &keys.elgamal_keypair,
+            &keys.aes_key,
+            &destination_elgamal_pubkey,
+            auditor_elgamal_pubkey.as_ref(),
+            None,
+        )?
+        else {
+            unreachable!("Transfers without fee parameters have plain proofs");
+        };
+        let with_ciphertext = &proof_data.ciphertext_validity_proof_data_with_ciphertext;
+        *out = CtTransferProofs {
+            equality: bytes_out(bytemuck::bytes_of(&proof_data.equality_proof_data)),
+            ciphertext_validity: bytes_out(bytemuck::bytes_of(&with_ciphertext.proof_data)),
+            range: bytes_out(bytemuck::bytes_of(&proof_data.range_proof_data)),
+            auditor_ciphertext_lo: bytemuck::cast(with_ciphertext.ciphertext_lo),
+            auditor_ciphertext_hi: bytemuck::cast(with_ciphertext.ciphertext_hi),
+            new_decryptable_available_balance: new_decryptable_balance(extension, keys, amount)?,
+        };
+        Ok(())
+    })
+}
+
+/// Frees the bytes of transfer proofs; null is ignored.
+///
+/// # Safety
+///
+/// `proofs` must have been set by [`ct_transfer_proofs`] and its bytes not freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ct_transfer_proofs_free(proofs: *mut CtTransferProofs) {
+    if let Some(proofs) = unsafe { proofs.as_mut() } {
+        unsafe {
+            ct_bytes_free(std::mem::take(&mut proofs.equality));
+            ct_bytes_free(std::mem::take(&mut proofs.ciphertext_validity));
+            ct_bytes_free(std::mem::take(&mut proofs.range));
+        }
+    }
+}
+
+/// Frees bytes allocated by this library; empty bytes are ignored.
+///
+/// # Safety
+///
+/// `bytes` must come from this library and not be freed twice.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ct_bytes_free(bytes: CtBytes) {
+    if !bytes.data.is_null() {
+        drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(bytes.data, bytes.len)) });
+    }
+}
+
+/// Frees a string allocated by this library; null is ignored.
+///
+/// # Safety
+///
+/// `string` must come from this library and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ct_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(unsafe { CString::from_raw(string) });
+    }
+}
+
+impl Default for CtBytes {
+    fn default() -> Self {
+        Self {
+            data: ptr::null_mut(),
+            len: 0,
+        }
+    }
+}
+
+// Error of a malformed argument, reported as `CtStatus::InvalidArgument`
+#[derive(Debug)]
+struct InvalidArgument(String);
+
+impl fmt::Display for InvalidArgument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for InvalidArgument {}
+
+fn invalid(message: impl fmt::Display) -> anyhow::Error {
+    InvalidArgument(message.to_string()).into()
+}
+
+// Runs the body of a `ct_*` function, recording its error or panic for `ct_last_error`
+fn run(body: impl FnOnce() -> Result<()>) -> CtStatus {
+    let (status, message) = match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => return CtStatus::Ok,
+        Ok(Err(err)) if err.is::<InvalidArgument>() => {
+            (CtStatus::InvalidArgument, format!("{err:#}"))
+        }
+        Ok(Err(err)) => (CtStatus::Failed, format!("{err:#}")),
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(ToString::to_string)
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            (CtStatus::Panicked, format!("Panicked: {message}"))
+        }
+    };
+    //Messages cannot hold NUL bytes, which would end them early in C
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.set(Some(message));
+    status
+}
+
+unsafe fn str_arg<'a>(name: &str, value: *const c_char) -> Result<&'a str> {
+    if value.is_null() {
+        return Err(invalid(format!("{name} is null")));
+    }
+    unsafe { CStr::from_ptr(value) }
+        .to_str()
+        .map_err(|_| invalid(format!("{name} is not UTF-8")))
+}
+
+unsafe fn optional_str_arg<'a>(name: &str, value: *const c_char) -> Result<Option<&'a str>> {
+    match value.is_null() {
+        true => Ok(None),
+        false => unsafe { str_arg(name, value) }.map(Some),
+    }
+}
+
+unsafe fn pubkey_arg(name: &str, value: *const c_char) -> Result<Pubkey> {
+    let value = unsafe { str_arg(name, value) }?;
+    value
+        .parse()
+        .map_err(|_| invalid(format!("{name} is not an address: {value}")))
+}
+
+unsafe fn bytes_arg<'a>(name: &str, data: *const u8, len: usize) -> Result<&'a [u8]> {
+    if data.is_null() {
+        return Err(invalid(format!("{name} is null")));
+    }
+    Ok(unsafe { slice::from_raw_parts(data, len) })
+}
+
+unsafe fn handle_arg<'a, T>(name: &str, handle: *const T) -> Result<&'a T> {
+    unsafe { handle.as_ref() }.ok_or_else(|| invalid(format!("{name} is null")))
+}
+
+unsafe fn out_arg<'a, T>(name: &str, out: *mut T) -> Result<&'a mut T> {
+    unsafe { out.as_mut() }.ok_or_else(|| invalid(format!("{name} is null")))
+}
+
+fn string_out(value: impl ToString) -> Result<*mut c_char> {
+    Ok(CString::new(value.to_string())?.into_raw())
+}
+
+fn bytes_out(bytes: &[u8]) -> CtBytes {
+    let len = bytes.len();
+    CtBytes {
+        data: Box::into_raw(Box::<[u8]>::from(bytes)).cast(),
+        len,
+    }
+}
+
+fn keys_out(elgamal_keypair: ElGamalKeypair, aes_key: AeKey) -> *mut CtKeys {
+    Box::into_raw(Box::new(CtKeys {
+        elgamal_keypair,
+        aes_key,
+    }))
+}
+
+fn confidential_extension<'a>(
+    account: &'a StateWithExtensions<Account>,
+) -> Result<&'a ConfidentialTransferAccount> {
+    account
+        .get_extension::<ConfidentialTransferAccount>()
+        .map_err(|_| invalid("The account is not configured for confidential transfers"))
+}
+
+// Available balance left after taking `amount`, encrypted with the AES key of `keys`
+fn new_decryptable_balance(
+    extension: &ConfidentialTransferAccount,
+    keys: &CtKeys,
+    amount: u64,
+) -> Result<[u8; AE_CIPHERTEXT_LEN]> {
+    let available = decrypt_available_balance(extension, &keys.aes_key)
+        .context("The AES key does not decrypt the available balance")?;
+    Ok(keys.aes_key.encrypt(available - amount).to_bytes())
+}
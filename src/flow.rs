@@ -0,0 +1,198 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{pubkey::Pubkey, signature::Signature, signer::Signer};
+use spl_token_client::token::TokenError;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tracing::{debug, instrument};
+
+use crate::{TokenClient, utils::into_signature};
+
+/// Step of the end-to-end flow run by the `demo` command, in execution order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FlowStep {
+    /// Create the mint.
+    CreateMint,
+    /// Create and configure the owner's associated token account.
+    ConfigureAccount,
+    /// Mint public tokens to the account.
+    MintTo,
+    /// Deposit part of them into the pending balance.
+    Deposit,
+    /// Apply the pending balance.
+    Apply,
+    /// Withdraw part of the available balance.
+    Withdraw,
+}
+
+impl FlowStep {
+    /// Every step, in execution order.
+    pub const ALL: [FlowStep; 6] = [
+        FlowStep::CreateMint,
+        FlowStep::ConfigureAccount,
+        FlowStep::MintTo,
+        FlowStep::Deposit,
+        FlowStep::Apply,
+        FlowStep::Withdraw,
+    ];
+}
+
+/// Progress of the end-to-end flow, saved to a state file after every step so that an
+/// interrupted run can be resumed instead of started over.
+///
+/// Accounts are recorded as soon as they are known, and the withdraw proof context accounts
+/// before they are created, so a resumed run can close them and recover their rent.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FlowState {
+    /// Owner of the token account; a resumed run must use the same one.
+    #[serde(with = "optional_pubkey")]
+    pub owner: Option<Pubkey>,
+    /// Decimals of the mint.
+    pub decimals: u8,
+    /// Mint created by the flow.
+    #[serde(with = "optional_pubkey")]
+    pub mint: Option<Pubkey>,
+    /// Token account configured by the flow.
+    #[serde(with = "optional_pubkey")]
+    pub token_account: Option<Pubkey>,
+    /// Withdraw proof context accounts that may still exist.
+    #[serde(with = "pubkeys")]
+    pub proof_accounts: Vec<Pubkey>,
+    /// Available balance before the withdraw was first attempted; a different balance on resume
+    /// means the withdraw landed.
+    pub balance_before_withdraw: Option<u64>,
+    /// Completed steps, in execution order.
+    pub completed: Vec<FlowStep>,
+}
+
+impl FlowState {
+    /// Default location of the state file.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_local_dir().map(|data| data.join("confidential-transfer/demo-state.json"))
+    }
+
+    /// Loads the state file at `path`, or returns `None` if there is none.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read state file {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .map(Some)
+            .with_context(|| format!("Invalid state file {}", path.display()))
+    }
+
+    /// Writes the state to `path` through a temporary file, so an interruption never leaves a
+    /// truncated state file behind.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Unable to create {}", parent.display()))?;
+        }
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Unable to write state file {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, path)
+            .with_context(|| format!("Unable to write state file {}", path.display()))
+    }
+
+    /// Returns whether `step` already completed.
+    pub fn is_completed(&self, step: FlowStep) -> bool {
+        self.completed.contains(&step)
+    }
+
+    /// Returns the first step not completed yet, or `None` once the flow is done.
+    pub fn next_step(&self) -> Option<FlowStep> {
+        FlowStep::ALL
+            .into_iter()
+            .find(|step| !self.is_completed(*step))
+    }
+
+    /// Marks `step` as completed and saves the state to `path`.
+    pub fn complete(&mut self, step: FlowStep, path: &Path) -> Result<()> {
+        if !self.is_completed(step) {
+            self.completed.push(step);
+        }
+        self.save(path)
+    }
+}
+
+/// Closes the proof context accounts among `accounts` that still exist, refunding their rent to
+/// `authority`, and returns the signatures.
+///
+/// Accounts already closed, or never created, are skipped.
+#[instrument(skip_all, fields(accounts = accounts.len()))]
+pub async fn close_leftover_proof_accounts(
+    token: &TokenClient,
+    authority: &Arc<dyn Signer>,
+    accounts: &[Pubkey],
+) -> Result<Vec<Signature>> {
+    let mut signatures = vec![];
+    for account in accounts {
+        match token.get_account(*account).await {
+            Ok(_) => {}
+            Err(TokenError::AccountNotFound) => continue,
+            Err(err) => return Err(err.into()),
+        }
+        let signature = into_signature(
+            token
+                .confidential_transfer_close_context_state_account(
+                    account,             //Public key of the proof account
+                    &authority.pubkey(), //Destination to receive recovered rent
+                    &authority.pubkey(), //Authority that can close the account
+                    &[authority],        //Signer(authority)
+                )
+                .await?,
+        )?;
+        debug!(%account, %signature, "Closed leftover proof account");
+        signatures.push(signature);
+    }
+    Ok(signatures)
+}
+
+// Pubkeys are stored as base58 strings to keep the state file readable
+mod optional_pubkey {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+    use solana_sdk::pubkey::Pubkey;
+
+    pub fn serialize<S: Serializer>(
+        pubkey: &Option<Pubkey>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match pubkey {
+            Some(pubkey) => serializer.serialize_some(&pubkey.to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Pubkey>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|pubkey| pubkey.parse().map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+mod pubkeys {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+    use solana_sdk::pubkey::Pubkey;
+
+    pub fn serialize<S: Serializer>(pubkeys: &[Pubkey], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(pubkeys.iter().map(Pubkey::to_string))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Pubkey>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|pubkey| pubkey.parse().map_err(D::Error::custom))
+            .collect()
+    }
+}
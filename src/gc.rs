@@ -0,0 +1,121 @@
+use anyhow::{Result, anyhow};
+use solana_account_decoder_client_types::UiAccountEncoding;
+use solana_client::{
+    nonblocking::rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{pubkey::Pubkey, signature::Signature, signer::Signer};
+use spl_token_client::{
+    client::ProgramClient,
+    spl_token_2022::{
+        id as token_2022_program_id,
+        solana_zk_sdk::zk_elgamal_proof_program::{
+            self, proof_data::ProofType, state::ProofContextStateMeta,
+        },
+    },
+    token::Token,
+};
+use std::sync::Arc;
+use tracing::{debug, instrument};
+
+use crate::{
+    client::{ComputeBudget, RpcSender},
+    utils::into_signature,
+};
+
+/// Proof context state account found by [`find_proof_accounts`].
+#[derive(Debug, Clone, Copy)]
+pub struct ProofContextAccount {
+    /// Address of the account.
+    pub address: Pubkey,
+    /// Context state authority, the only key that can close the account.
+    pub authority: Pubkey,
+    /// Type of the verified proof, if known.
+    pub proof_type: Option<ProofType>,
+    /// Rent held by the account, refunded when it is closed.
+    pub lamports: u64,
+}
+
+/// Finds every proof context state account whose context state authority is `authority`.
+///
+/// Uses `getProgramAccounts` on the ZK ElGamal proof program with the authority at offset 0, which
+/// some public RPC providers disable. Accounts left open by an interrupted withdraw, transfer or
+/// `submit-proofs` are found this way, but so are accounts an operation still in progress is
+/// about to use.
+#[instrument(skip_all, fields(%authority))]
+pub async fn find_proof_accounts(
+    rpc_client: &RpcClient,
+    authority: &Pubkey,
+) -> Result<Vec<ProofContextAccount>> {
+    let config = RpcProgramAccountsConfig {
+        filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+            0,
+            authority.as_ref(),
+        ))]),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    };
+    let accounts = rpc_client
+        .get_program_accounts_with_config(&zk_elgamal_proof_program::id(), config)
+        .await?;
+    debug!(accounts = accounts.len(), "Fetched proof context accounts");
+    accounts
+        .into_iter()
+        .map(|(address, account)| {
+            let meta = ProofContextStateMeta::try_from_bytes(&account.data)
+                .map_err(|_| anyhow!("Invalid proof context account {address}"))?;
+            Ok(ProofContextAccount {
+                address,
+                authority: meta.context_state_authority,
+                proof_type: ProofType::try_from(meta.proof_type).ok(),
+                lamports: account.lamports,
+            })
+        })
+        .collect()
+}
+
+/// Closes `accounts`, all managed by `authority`, refunding their rent to `authority`.
+///
+/// Each account is closed in its own transaction, paid by `fee_payer` with `compute_budget`; the
+/// outcome of each is returned in the order of `accounts`, so one failure does not keep the rent
+/// of the others.
+#[instrument(skip_all, fields(authority = %authority.pubkey(), accounts = accounts.len()))]
+pub async fn close_proof_accounts(
+    program_client: Arc<dyn ProgramClient<RpcSender>>,
+    fee_payer: Arc<dyn Signer>,
+    compute_budget: &ComputeBudget,
+    authority: &Arc<dyn Signer>,
+    accounts: &[ProofContextAccount],
+) -> Vec<(Pubkey, Result<Signature>)> {
+    //Closing context state accounts involves no mint, so any address does for the client
+    let token = compute_budget.apply(Token::new(
+        program_client,
+        &token_2022_program_id(),
+        &Pubkey::default(),
+        None,
+        fee_payer,
+    ));
+    let mut closed = Vec::with_capacity(accounts.len());
+    for account in accounts {
+        let signature = async {
+            into_signature(
+                token
+                    .confidential_transfer_close_context_state_account(
+                        &account.address,    //Public key of the proof account
+                        &authority.pubkey(), //Destination to receive recovered rent
+                        &authority.pubkey(), //Authority that can close the account
+                        &[authority],        //Signer(authority)
+                    )
+                    .await?,
+            )
+        }
+        .await;
+        debug!(account = %account.address, signature = ?signature.as_ref().ok(), "Closed proof account");
+        closed.push((account.address, signature));
+    }
+    closed
+}
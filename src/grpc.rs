@@ -0,0 +1,295 @@
+use anyhow::Context;
+use axum::http::StatusCode;
+use futures::{Stream, stream};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use std::{future::Future, net::SocketAddr, pin::Pin};
+use tokio::sync::mpsc;
+use tonic::{Code, Request as GrpcRequest, Response, Status, transport::Server};
+
+use crate::{
+    progress::Progress,
+    server::{
+        self, AccountsRequest, ApiError, DepositRequest, Job, Outcome, Request, TransferRequest,
+        WithdrawRequest,
+    },
+};
+
+/// Messages and service generated from `proto/confidential_transfer.proto`, including the
+/// client of the service.
+pub mod proto {
+    tonic::include_proto!("confidential_transfer.v1");
+}
+
+use proto::{
+    Balance, ConfigureAccountRequest, GetBalanceRequest, OperationResult, OperationUpdate,
+    confidential_transfer_server::{ConfidentialTransfer, ConfidentialTransferServer},
+    operation_update::Update,
+    progress::Stage,
+};
+
+type UpdateStream = Pin<Box<dyn Stream<Item = Result<OperationUpdate, Status>> + Send>>;
+
+// Service handing its requests to the worker of `server::serve`, like the HTTP handlers
+struct Service {
+    jobs: mpsc::Sender<Job>,
+}
+
+// Serves the gRPC API on `bind` until `shutdown` resolves
+pub(crate) async fn serve(
+    jobs: mpsc::Sender<Job>,
+    bind: SocketAddr,
+    api_token: Option<String>,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    let service = ConfidentialTransferServer::with_interceptor(Service { jobs }, move |request| {
+        check_token(request, api_token.as_deref())
+    });
+    Server::builder()
+        .add_service(service)
+        .serve_with_shutdown(bind, shutdown)
+        .await
+        .with_context(|| format!("Unable to serve the gRPC API on {bind}"))
+}
+
+// Rejects requests without the bearer token, if one is required
+fn check_token(
+    request: GrpcRequest<()>,
+    api_token: Option<&str>,
+) -> Result<GrpcRequest<()>, Status> {
+    let Some(api_token) = api_token else {
+        return Ok(request);
+    };
+    let expected = format!("Bearer {api_token}");
+    match request.metadata().get("authorization") {
+        Some(value) if value.as_bytes() == expected.as_bytes() => Ok(request),
+        _ => Err(Status::unauthenticated("Missing or wrong API token")),
+    }
+}
+
+impl Service {
+    // Runs `request`, streaming its progress, then its result
+    fn stream(&self, request: Request) -> UpdateStream {
+        let (updates, received) = mpsc::unbounded_channel();
+        let jobs = self.jobs.clone();
+        tokio::spawn(async move {
+            let (progress, mut reported) = mpsc::unbounded_channel();
+            let outcome = server::submit(&jobs, request, Some(progress));
+            tokio::pin!(outcome);
+            let outcome = loop {
+                tokio::select! {
+                    //Progress first, so it is streamed before the result
+                    biased;
+                    Some(progress) = reported.recv() => {
+                        let _ = updates.send(Ok(progress_update(progress)));
+                    }
+                    outcome = &mut outcome => break outcome,
+                }
+            };
+            while let Ok(progress) = reported.try_recv() {
+                let _ = updates.send(Ok(progress_update(progress)));
+            }
+            let _ = updates.send(outcome.map_err(status).and_then(result_update));
+        });
+        Box::pin(stream::unfold(received, |mut received| async move {
+            received.recv().await.map(|update| (update, received))
+        }))
+    }
+}
+
+#[tonic::async_trait]
+impl ConfidentialTransfer for Service {
+    type ConfigureAccountStream = UpdateStream;
+    type DepositStream = UpdateStream;
+    type TransferStream = UpdateStream;
+    type WithdrawStream = UpdateStream;
+
+    async fn configure_account(
+        &self,
+        request: GrpcRequest<ConfigureAccountRequest>,
+    ) -> Result<Response<UpdateStream>, Status> {
+        let request = request.into_inner();
+        Ok(Response::new(self.stream(Request::Configure(
+            AccountsRequest {
+                mint: pubkey("mint", &request.mint)?,
+            },
+        ))))
+    }
+
+    async fn deposit(
+        &self,
+        request: GrpcRequest<proto::DepositRequest>,
+    ) -> Result<Response<UpdateStream>, Status> {
+        let request = request.into_inner();
+        Ok(Response::new(self.stream(Request::Deposit(
+            DepositRequest {
+                mint: pubkey("mint", &request.mint)?,
+                amount: request.amount,
+                memo: request.memo,
+                apply: request.apply,
+            },
+        ))))
+    }
+
+    async fn transfer(
+        &self,
+        request: GrpcRequest<proto::TransferRequest>,
+    ) -> Result<Response<UpdateStream>, Status> {
+        let request = request.into_inner();
+        Ok(Response::new(self.stream(Request::Transfer(
+            TransferRequest {
+                mint: pubkey("mint", &request.mint)?,
+                recipient: pubkey("recipient", &request.recipient)?,
+                amount: request.amount,
+                memo: request.memo,
+            },
+        ))))
+    }
+
+    async fn withdraw(
+        &self,
+        request: GrpcRequest<proto::WithdrawRequest>,
+    ) -> Result<Response<UpdateStream>, Status> {
+        let request = request.into_inner();
+        Ok(Response::new(self.stream(Request::Withdraw(
+            WithdrawRequest {
+                mint: pubkey("mint", &request.mint)?,
+                amount: request.amount,
+                all: request.all,
+                memo: request.memo,
+            },
+        ))))
+    }
+
+    async fn get_balance(
+        &self,
+        request: GrpcRequest<GetBalanceRequest>,
+    ) -> Result<Response<Balance>, Status> {
+        let ata = pubkey("ata", &request.into_inner().ata)?;
+        match server::submit(&self.jobs, Request::Balance(ata), None).await {
+            Ok(Outcome::Balance {
+                ata,
+                mint,
+                decimals,
+                balances,
+            }) => Ok(Response::new(Balance {
+                ata: ata.to_string(),
+                mint: mint.to_string(),
+                decimals: u32::from(decimals),
+                public: balances.public,
+                pending: balances.pending,
+                available: balances.available,
+                pending_credit_counter: balances.pending_credits.counter,
+                maximum_pending_credit_counter: balances.pending_credits.maximum,
+            })),
+            Ok(_) => Err(Status::internal("Unexpected result of a balance request")),
+            Err(err) => Err(status(err)),
+        }
+    }
+}
+
+fn pubkey(field: &str, value: &str) -> Result<Pubkey, Status> {
+    value
+        .parse()
+        .map_err(|err| Status::invalid_argument(format!("Invalid {field} {value:?}: {err}")))
+}
+
+// Status matching the HTTP status of `err`
+fn status(err: ApiError) -> Status {
+    let code = match err.status {
+        StatusCode::BAD_REQUEST => Code::InvalidArgument,
+        StatusCode::NOT_FOUND => Code::NotFound,
+        StatusCode::CONFLICT => Code::FailedPrecondition,
+        StatusCode::SERVICE_UNAVAILABLE => Code::Unavailable,
+        _ => Code::Internal,
+    };
+    Status::new(code, format!("{:#}", err.error))
+}
+
+fn progress_update(progress: Progress) -> OperationUpdate {
+    let (stage, signature, elapsed_ms) = match progress {
+        Progress::GeneratingProofs => (Stage::GeneratingProofs, None, 0.0),
+        Progress::ProofsGenerated { elapsed } => {
+            (Stage::ProofsGenerated, None, elapsed.as_secs_f64() * 1000.0)
+        }
+        Progress::Sent { signature } => (Stage::Sent, Some(signature), 0.0),
+        Progress::Confirmed { signature } => (Stage::Confirmed, Some(signature), 0.0),
+    };
+    OperationUpdate {
+        update: Some(Update::Progress(proto::Progress {
+            stage: stage.into(),
+            signature: signature
+                .map(|signature| signature.to_string())
+                .unwrap_or_default(),
+            elapsed_ms,
+        })),
+    }
+}
+
+fn result_update(outcome: Outcome) -> Result<OperationUpdate, Status> {
+    let result = match outcome {
+        Outcome::Configured {
+            ata,
+            elgamal_pubkey,
+            signature,
+        } => OperationResult {
+            account: ata.to_string(),
+            signatures: strings(signature),
+            elgamal_pubkey: elgamal_pubkey.to_string(),
+            ..OperationResult::default()
+        },
+        Outcome::Deposited {
+            ata,
+            amount,
+            signature,
+        } => OperationResult {
+            account: ata.to_string(),
+            amount,
+            signatures: strings([signature]),
+            ..OperationResult::default()
+        },
+        Outcome::Transferred {
+            destination,
+            amount,
+            result,
+        } => OperationResult {
+            account: destination.to_string(),
+            amount,
+            fee: result.fee,
+            signatures: strings(
+                (result.proof_signatures.into_iter())
+                    .chain([result.transfer_signature])
+                    .chain(result.close_signatures),
+            ),
+            ..OperationResult::default()
+        },
+        Outcome::Withdrawn { ata, result } => OperationResult {
+            account: ata.to_string(),
+            amount: result.amount,
+            signatures: strings(
+                [
+                    result.equality_proof_signature,
+                    result.range_proof_signature,
+                    Some(result.withdraw_signature),
+                    result.close_equality_signature,
+                    result.close_range_signature,
+                ]
+                .into_iter()
+                .flatten(),
+            ),
+            ..OperationResult::default()
+        },
+        Outcome::Balance { .. } | Outcome::WalletMessages { .. } | Outcome::WalletKeys { .. } => {
+            return Err(Status::internal("Unexpected result of an operation"));
+        }
+    };
+    Ok(OperationUpdate {
+        update: Some(Update::Result(result)),
+    })
+}
+
+fn strings(signatures: impl IntoIterator<Item = Signature>) -> Vec<String> {
+    signatures
+        .into_iter()
+        .map(|signature| signature.to_string())
+        .collect()
+}
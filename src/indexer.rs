@@ -0,0 +1,345 @@
+use anyhow::{Context, Result, anyhow, bail};
+#[cfg(feature = "storage")]
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcTransactionConfig};
+use solana_sdk::{
+    commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature,
+    transaction::VersionedTransaction,
+};
+use solana_transaction_status_client_types::{
+    UiLoadedAddresses, UiTransactionEncoding, option_serializer::OptionSerializer,
+};
+#[cfg(feature = "storage")]
+use spl_token_client::spl_token_2022::extension::{
+    BaseStateWithExtensions, confidential_transfer::ConfidentialTransferAccount,
+};
+use spl_token_client::spl_token_2022::{
+    extension::confidential_transfer::instruction::{
+        ConfidentialTransferInstruction, DepositInstructionData, WithdrawInstructionData,
+    },
+    id as token_2022_program_id,
+    instruction::{TokenInstruction, decode_instruction_data, decode_instruction_type},
+};
+use std::{collections::HashSet, fmt, str::FromStr};
+#[cfg(feature = "storage")]
+use tracing::{debug, instrument};
+
+#[cfg(feature = "storage")]
+use crate::{inspect, storage::Storage};
+
+//Signatures per page of getSignaturesForAddress, the most the RPC returns
+#[cfg(feature = "storage")]
+const SIGNATURES_PAGE_LIMIT: usize = 1000;
+
+/// Kind of a confidential transfer event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// Deposit of public tokens into the pending balance.
+    Deposit,
+    /// Apply of the pending balance to the available balance.
+    Apply,
+    /// Confidential transfer, with or without fee.
+    Transfer,
+    /// Withdraw from the available balance to the public balance.
+    Withdraw,
+}
+
+impl EventKind {
+    /// Name of the kind, as stored and printed.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Deposit => "deposit",
+            Self::Apply => "apply",
+            Self::Transfer => "transfer",
+            Self::Withdraw => "withdraw",
+        }
+    }
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = anyhow::Error;
+
+    fn from_str(kind: &str) -> Result<Self> {
+        Ok(match kind {
+            "deposit" => Self::Deposit,
+            "apply" => Self::Apply,
+            "transfer" => Self::Transfer,
+            "withdraw" => Self::Withdraw,
+            _ => bail!("Unknown event kind {kind:?}"),
+        })
+    }
+}
+
+/// A confidential transfer instruction of a mint, decoded from a successful transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// Transaction of the instruction.
+    pub signature: Signature,
+    /// Index of the instruction in the transaction.
+    pub instruction_index: u32,
+    /// Slot of the transaction.
+    pub slot: u64,
+    /// Unix time of the block, if the cluster knows it.
+    pub block_time: Option<i64>,
+    /// Kind of instruction.
+    pub kind: EventKind,
+    /// Mint of the account.
+    pub mint: Pubkey,
+    /// Token account operated on: the source of a transfer.
+    pub account: Pubkey,
+    /// Destination token account of a transfer.
+    pub counterparty: Option<Pubkey>,
+    /// Amount in base units of a deposit or withdraw; transfer amounts are encrypted.
+    pub amount: Option<u64>,
+}
+
+/// Transaction fetched with the keys of its accounts, those loaded from lookup tables included.
+#[derive(Debug, Clone)]
+pub struct FetchedTransaction {
+    /// Signature of the transaction.
+    pub signature: Signature,
+    /// Slot the transaction landed in.
+    pub slot: u64,
+    /// Unix time of the block, if the cluster knows it.
+    pub block_time: Option<i64>,
+    /// The transaction.
+    pub transaction: VersionedTransaction,
+    /// Keys of the accounts of the transaction, static ones first.
+    pub account_keys: Vec<Pubkey>,
+}
+
+/// Fetches transaction `signature` at `commitment`; legacy and v0 transactions are supported.
+pub async fn get_transaction(
+    rpc_client: &RpcClient,
+    signature: &Signature,
+    commitment: CommitmentConfig,
+) -> Result<FetchedTransaction> {
+    let confirmed = rpc_client
+        .get_transaction_with_config(
+            signature,
+            RpcTransactionConfig {
+                encoding: Some(UiTransactionEncoding::Base64),
+                commitment: Some(commitment),
+                max_supported_transaction_version: Some(0),
+            },
+        )
+        .await?;
+    let transaction = confirmed
+        .transaction
+        .transaction
+        .decode()
+        .context("Unable to decode transaction")?;
+    //Account keys of v0 transactions continue with the addresses loaded from lookup tables
+    let mut account_keys = transaction.message.static_account_keys().to_vec();
+    if let Some(OptionSerializer::Some(UiLoadedAddresses { writable, readonly })) =
+        confirmed.transaction.meta.map(|meta| meta.loaded_addresses)
+    {
+        for address in writable.iter().chain(&readonly) {
+            account_keys.push(Pubkey::from_str(address)?);
+        }
+    }
+    Ok(FetchedTransaction {
+        signature: *signature,
+        slot: confirmed.slot,
+        block_time: confirmed.block_time,
+        transaction,
+        account_keys,
+    })
+}
+
+/// Decodes the confidential deposits, applies, transfers and withdraws of `mint` in
+/// `transaction`, which must have succeeded.
+///
+/// Only instructions of the transaction itself are decoded, not those invoked by other
+/// programs. An apply names no mint: it is kept when its account is one of `accounts`, the
+/// accounts of the mint.
+pub fn decode_events(
+    transaction: &FetchedTransaction,
+    mint: &Pubkey,
+    accounts: &HashSet<Pubkey>,
+) -> Result<Vec<Event>> {
+    let account_keys = &transaction.account_keys;
+    let mut events = vec![];
+    for (index, instruction) in transaction
+        .transaction
+        .message
+        .instructions()
+        .iter()
+        .enumerate()
+    {
+        if account_keys.get(usize::from(instruction.program_id_index))
+            != Some(&token_2022_program_id())
+        {
+            continue;
+        }
+        let account = |position: usize| {
+            instruction
+                .accounts
+                .get(position)
+                .and_then(|&key| account_keys.get(usize::from(key)))
+                .copied()
+                .ok_or_else(|| anyhow!("Missing account {position} of instruction {index}"))
+        };
+        let Some((kind, amount)) = decode_instruction(&instruction.data)? else {
+            continue;
+        };
+        let (account, counterparty) = match kind {
+            EventKind::Apply => {
+                let account = account(0)?;
+                if !accounts.contains(&account) {
+                    continue;
+                }
+                (account, None)
+            }
+            _ => {
+                if &account(1)? != mint {
+                    continue;
+                }
+                let counterparty = match kind {
+                    EventKind::Transfer => Some(account(2)?),
+                    _ => None,
+                };
+                (account(0)?, counterparty)
+            }
+        };
+        events.push(Event {
+            signature: transaction.signature,
+            instruction_index: u32::try_from(index)?,
+            slot: transaction.slot,
+            block_time: transaction.block_time,
+            kind,
+            mint: *mint,
+            account,
+            counterparty,
+            amount,
+        });
+    }
+    Ok(events)
+}
+
+/// Outcome of [`index_mint`].
+#[cfg(feature = "storage")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IndexSummary {
+    /// Addresses walked: the mint and its confidential accounts.
+    pub addresses: usize,
+    /// New transactions of these addresses.
+    pub transactions: usize,
+    /// Events recorded.
+    pub events: usize,
+}
+
+/// Indexes the confidential transfer activity of `mint` into `storage`, from where the previous
+/// run stopped.
+///
+/// Walks the finalized transactions of the mint, then those of each of its confidential
+/// accounts, which finds the applies: they do not name the mint. Transactions are processed
+/// oldest first and every address keeps a cursor, moved with the events of each transaction,
+/// so an interrupted run resumes where it stopped. Listing the accounts takes
+/// `getProgramAccounts`; applies of accounts closed since are missed.
+#[cfg(feature = "storage")]
+#[instrument(skip_all, fields(%mint))]
+pub async fn index_mint(
+    rpc_client: &RpcClient,
+    storage: &dyn Storage,
+    mint: &Pubkey,
+) -> Result<IndexSummary> {
+    let accounts = inspect::get_token_accounts(rpc_client, mint)
+        .await?
+        .into_iter()
+        .filter(|(_, state)| state.get_extension::<ConfidentialTransferAccount>().is_ok())
+        .map(|(address, _)| address)
+        .collect::<HashSet<_>>();
+    let mut addresses = vec![*mint];
+    addresses.extend(&accounts);
+    addresses[1..].sort();
+    let mut summary = IndexSummary {
+        addresses: addresses.len(),
+        ..IndexSummary::default()
+    };
+    //A transaction touching the mint and accounts is fetched once per run
+    let mut fetched = HashSet::new();
+    for address in &addresses {
+        let cursor = storage.index_cursor(address).await?;
+        let signatures = new_signatures(rpc_client, address, cursor).await?;
+        debug!(%address, transactions = signatures.len(), "Indexing");
+        for (signature, failed) in signatures {
+            let events = if failed || !fetched.insert(signature) {
+                vec![]
+            } else {
+                let transaction =
+                    get_transaction(rpc_client, &signature, CommitmentConfig::finalized()).await?;
+                decode_events(&transaction, mint, &accounts)?
+            };
+            storage.record_events(address, &signature, &events).await?;
+            summary.transactions += 1;
+            summary.events += events.len();
+        }
+    }
+    Ok(summary)
+}
+
+// Finalized transactions of `address` after `cursor`, oldest first, with whether they failed
+#[cfg(feature = "storage")]
+async fn new_signatures(
+    rpc_client: &RpcClient,
+    address: &Pubkey,
+    cursor: Option<Signature>,
+) -> Result<Vec<(Signature, bool)>> {
+    let mut signatures = vec![];
+    let mut before = None;
+    loop {
+        let page = rpc_client
+            .get_signatures_for_address_with_config(
+                address,
+                GetConfirmedSignaturesForAddress2Config {
+                    before,
+                    until: cursor,
+                    limit: Some(SIGNATURES_PAGE_LIMIT),
+                    commitment: Some(CommitmentConfig::finalized()),
+                },
+            )
+            .await?;
+        let last_page = page.len() < SIGNATURES_PAGE_LIMIT;
+        for status in page {
+            signatures.push((status.signature.parse()?, status.err.is_some()));
+        }
+        match signatures.last() {
+            Some((signature, _)) if !last_page => before = Some(*signature),
+            _ => break,
+        }
+    }
+    signatures.reverse();
+    Ok(signatures)
+}
+
+// Kind and public amount of a Token-2022 instruction, if it is one of the indexed ones
+fn decode_instruction(data: &[u8]) -> Result<Option<(EventKind, Option<u64>)>> {
+    if !matches!(
+        TokenInstruction::unpack(data),
+        Ok(TokenInstruction::ConfidentialTransferExtension)
+    ) {
+        return Ok(None);
+    }
+    let data = &data[1..];
+    Ok(match decode_instruction_type(data)? {
+        ConfidentialTransferInstruction::Deposit => {
+            let data = decode_instruction_data::<DepositInstructionData>(data)?;
+            Some((EventKind::Deposit, Some(data.amount.into())))
+        }
+        ConfidentialTransferInstruction::Withdraw => {
+            let data = decode_instruction_data::<WithdrawInstructionData>(data)?;
+            Some((EventKind::Withdraw, Some(data.amount.into())))
+        }
+        ConfidentialTransferInstruction::Transfer
+        | ConfidentialTransferInstruction::TransferWithFee => Some((EventKind::Transfer, None)),
+        ConfidentialTransferInstruction::ApplyPendingBalance => Some((EventKind::Apply, None)),
+        _ => None,
+    })
+}
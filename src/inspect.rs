@@ -0,0 +1,221 @@
+use anyhow::{Result, anyhow, bail};
+use solana_account_decoder_client_types::UiAccountEncoding;
+use solana_client::{
+    nonblocking::rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{program_pack::Pack, pubkey::Pubkey};
+use spl_token_client::{
+    client::ProgramClient,
+    spl_token_2022::{
+        extension::{
+            AccountType, BaseStateWithExtensions, Extension, ExtensionType,
+            StateWithExtensionsOwned,
+            confidential_mint_burn::ConfidentialMintBurn,
+            confidential_transfer::{ConfidentialTransferAccount, ConfidentialTransferMint},
+            confidential_transfer_fee::ConfidentialTransferFeeConfig,
+            default_account_state::DefaultAccountState,
+            group_member_pointer::GroupMemberPointer,
+            group_pointer::GroupPointer,
+            interest_bearing_mint::InterestBearingConfig,
+            metadata_pointer::MetadataPointer,
+            mint_close_authority::MintCloseAuthority,
+            pausable::PausableConfig,
+            permanent_delegate::PermanentDelegate,
+            scaled_ui_amount::ScaledUiAmountConfig,
+            transfer_fee::TransferFeeConfig,
+            transfer_hook::TransferHook,
+        },
+        id as token_2022_program_id,
+        state::{Account, Mint},
+    },
+};
+use std::fmt::Debug;
+use tracing::{debug, instrument};
+
+use crate::client::RpcSender;
+
+//Offset of the account type byte that follows the base account state of extended accounts
+const ACCOUNT_TYPE_OFFSET: usize = Account::LEN;
+
+/// Token-2022 account state with its `ConfidentialTransferAccount` extension, if present.
+#[derive(Debug, Clone)]
+pub struct AccountInspection {
+    /// Address of the token account.
+    pub address: Pubkey,
+    /// Mint of the token account.
+    pub mint: Pubkey,
+    /// Owner of the token account.
+    pub owner: Pubkey,
+    /// Normal (transparent) token balance in base units.
+    pub amount: u64,
+    /// Extensions present on the account.
+    pub extensions: Vec<ExtensionType>,
+    /// Raw confidential transfer extension state.
+    pub confidential_transfer: Option<ConfidentialTransferAccount>,
+}
+
+/// Token-2022 mint state with its `ConfidentialTransferMint` extension, if present.
+#[derive(Debug, Clone)]
+pub struct MintInspection {
+    /// Address of the mint.
+    pub address: Pubkey,
+    /// Total supply in base units.
+    pub supply: u64,
+    /// Decimal precision.
+    pub decimals: u8,
+    /// Authority allowed to mint new tokens.
+    pub mint_authority: Option<Pubkey>,
+    /// Authority allowed to freeze token accounts.
+    pub freeze_authority: Option<Pubkey>,
+    /// Extensions present on the mint.
+    pub extensions: Vec<ExtensionType>,
+    /// Raw confidential transfer extension state.
+    pub confidential_transfer: Option<ConfidentialTransferMint>,
+    /// Debug dumps of the other fixed-size extensions, in the order of `extensions`.
+    pub other_extensions: Vec<(ExtensionType, String)>,
+}
+
+/// Fetches `address` and decodes it as a Token-2022 account, without needing its mint or keys.
+#[instrument(skip_all, fields(%address))]
+pub async fn inspect_account(
+    program_client: &dyn ProgramClient<RpcSender>,
+    address: &Pubkey,
+) -> Result<AccountInspection> {
+    let data = get_token_2022_account_data(program_client, address).await?;
+    let state = StateWithExtensionsOwned::<Account>::unpack(data)?;
+    Ok(AccountInspection {
+        address: *address,
+        mint: state.base.mint,
+        owner: state.base.owner,
+        amount: state.base.amount,
+        extensions: state.get_extension_types()?,
+        confidential_transfer: state
+            .get_extension::<ConfidentialTransferAccount>()
+            .ok()
+            .copied(),
+    })
+}
+
+/// Fetches `address` and decodes it as a Token-2022 mint, including every fixed-size extension.
+///
+/// Variable-length extensions (token metadata, groups) are only listed in `extensions`.
+#[instrument(skip_all, fields(%address))]
+pub async fn inspect_mint(
+    program_client: &dyn ProgramClient<RpcSender>,
+    address: &Pubkey,
+) -> Result<MintInspection> {
+    let data = get_token_2022_account_data(program_client, address).await?;
+    let state = StateWithExtensionsOwned::<Mint>::unpack(data)?;
+    let extensions = state.get_extension_types()?;
+    let other_extensions = extensions
+        .iter()
+        .filter_map(|&extension_type| {
+            describe_mint_extension(&state, extension_type).map(|dump| (extension_type, dump))
+        })
+        .collect();
+    Ok(MintInspection {
+        address: *address,
+        supply: state.base.supply,
+        decimals: state.base.decimals,
+        mint_authority: state.base.mint_authority.into(),
+        freeze_authority: state.base.freeze_authority.into(),
+        extensions,
+        confidential_transfer: state
+            .get_extension::<ConfidentialTransferMint>()
+            .ok()
+            .copied(),
+        other_extensions,
+    })
+}
+
+/// Fetches every Token-2022 account of `mint` that has extensions, decoded.
+///
+/// Uses `getProgramAccounts` with the mint at offset 0, which some public RPC providers disable.
+/// Accounts without extensions are skipped, as they cannot hold any confidential state.
+#[instrument(skip_all, fields(%mint))]
+pub async fn get_token_accounts(
+    rpc_client: &RpcClient,
+    mint: &Pubkey,
+) -> Result<Vec<(Pubkey, StateWithExtensionsOwned<Account>)>> {
+    let config = RpcProgramAccountsConfig {
+        filters: Some(vec![
+            RpcFilterType::Memcmp(Memcmp::new_base58_encoded(0, mint.as_ref())),
+            RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+                ACCOUNT_TYPE_OFFSET,
+                vec![AccountType::Account as u8],
+            )),
+        ]),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    };
+    let accounts = rpc_client
+        .get_program_accounts_with_config(&token_2022_program_id(), config)
+        .await?;
+    debug!(accounts = accounts.len(), "Fetched token accounts");
+    Ok(accounts
+        .into_iter()
+        .filter_map(|(address, account)| {
+            StateWithExtensionsOwned::<Account>::unpack(account.data)
+                .ok()
+                .map(|state| (address, state))
+        })
+        .collect())
+}
+
+// Data of `address`, checking that it is owned by the Token-2022 program
+pub(crate) async fn get_token_2022_account_data(
+    program_client: &dyn ProgramClient<RpcSender>,
+    address: &Pubkey,
+) -> Result<Vec<u8>> {
+    let account = program_client
+        .get_account(*address)
+        .await
+        .map_err(|err| anyhow!(err))?
+        .ok_or_else(|| anyhow!("Account {address} not found"))?;
+    if account.owner != token_2022_program_id() {
+        bail!(
+            "Account {address} is owned by {}, not the Token-2022 program",
+            account.owner
+        );
+    }
+    Ok(account.data)
+}
+
+// Debug dump of a fixed-size mint extension other than ConfidentialTransferMint
+fn describe_mint_extension(
+    state: &StateWithExtensionsOwned<Mint>,
+    extension_type: ExtensionType,
+) -> Option<String> {
+    match extension_type {
+        ExtensionType::TransferFeeConfig => describe::<TransferFeeConfig>(state),
+        ExtensionType::MintCloseAuthority => describe::<MintCloseAuthority>(state),
+        ExtensionType::DefaultAccountState => describe::<DefaultAccountState>(state),
+        ExtensionType::InterestBearingConfig => describe::<InterestBearingConfig>(state),
+        ExtensionType::PermanentDelegate => describe::<PermanentDelegate>(state),
+        ExtensionType::TransferHook => describe::<TransferHook>(state),
+        ExtensionType::ConfidentialTransferFeeConfig => {
+            describe::<ConfidentialTransferFeeConfig>(state)
+        }
+        ExtensionType::MetadataPointer => describe::<MetadataPointer>(state),
+        ExtensionType::GroupPointer => describe::<GroupPointer>(state),
+        ExtensionType::GroupMemberPointer => describe::<GroupMemberPointer>(state),
+        ExtensionType::ConfidentialMintBurn => describe::<ConfidentialMintBurn>(state),
+        ExtensionType::ScaledUiAmount => describe::<ScaledUiAmountConfig>(state),
+        ExtensionType::Pausable => describe::<PausableConfig>(state),
+        _ => None,
+    }
+}
+
+fn describe<V: Extension + bytemuck::Pod + Debug>(
+    state: &StateWithExtensionsOwned<Mint>,
+) -> Option<String> {
+    state
+        .get_extension::<V>()
+        .ok()
+        .map(|extension| format!("{extension:?}"))
+}
@@ -0,0 +1,78 @@
+use solana_sdk::{hash::hashv, signature::Keypair};
+use spl_token_client::spl_token_2022::solana_zk_sdk::encryption::{
+    auth_encryption::AeKey,
+    elgamal::{ElGamalKeypair, ElGamalSecretKey},
+};
+use std::{collections::HashMap, sync::Mutex};
+
+//Domain of the derivation, so seeded keys match no other hash of the seed
+const DOMAIN: &[u8] = b"confidential-transfer:keygen";
+
+//Seed set by `set_seed`, with how many keys of each kind were derived from it
+static SEEDED: Mutex<Option<Seeded>> = Mutex::new(None);
+
+struct Seeded {
+    seed: String,
+    counters: HashMap<&'static str, u64>,
+}
+
+/// Derives every keypair and key generated from now on from `seed` instead of the system's
+/// randomness: new mints, proof context state accounts, nonce and holding accounts, wallet
+/// keypair files, and the ElGamal keys and AES keys of rotations and key files.
+///
+/// The n-th key of each kind hashes the seed with the kind and n, so the same commands run
+/// with the same seed produce the same addresses and keys, and a failure can be replayed. Setting
+/// the seed again restarts the sequence. Anyone knowing the seed can recreate the keys: use it
+/// for tests only.
+pub fn set_seed(seed: &str) {
+    *SEEDED.lock().unwrap() = Some(Seeded {
+        seed: seed.to_string(),
+        counters: HashMap::new(),
+    });
+}
+
+/// Whether keys are derived from a seed set by [`set_seed`].
+pub fn is_seeded() -> bool {
+    SEEDED.lock().unwrap().is_some()
+}
+
+/// New keypair, derived from the seed when one is set, random otherwise.
+pub fn new_keypair() -> Keypair {
+    match next_seed("keypair") {
+        Some(seed) => Keypair::new_from_array(seed),
+        None => Keypair::new(),
+    }
+}
+
+/// New ElGamal keypair, derived from the seed when one is set, random otherwise.
+pub fn new_elgamal_keypair() -> ElGamalKeypair {
+    match next_seed("elgamal") {
+        Some(seed) => ElGamalKeypair::new(
+            ElGamalSecretKey::from_seed(&seed).expect("32 bytes are a valid ElGamal seed"),
+        ),
+        None => ElGamalKeypair::new_rand(),
+    }
+}
+
+/// New AES key, derived from the seed when one is set, random otherwise.
+pub fn new_aes_key() -> AeKey {
+    match next_seed("aes") {
+        Some(seed) => AeKey::from(<[u8; 16]>::try_from(&seed[..16]).unwrap()),
+        None => AeKey::new_rand(),
+    }
+}
+
+// Seed of the next key of `kind`, advancing its counter, or `None` when no seed is set
+fn next_seed(kind: &'static str) -> Option<[u8; 32]> {
+    let mut seeded = SEEDED.lock().unwrap();
+    let seeded = seeded.as_mut()?;
+    let counter = seeded.counters.entry(kind).or_default();
+    let hash = hashv(&[
+        DOMAIN,
+        seeded.seed.as_bytes(),
+        kind.as_bytes(),
+        &counter.to_le_bytes(),
+    ]);
+    *counter += 1;
+    Some(hash.to_bytes())
+}
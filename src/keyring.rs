@@ -0,0 +1,256 @@
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use spl_token_client::spl_token_2022::{
+    extension::BaseStateWithExtensions, solana_zk_sdk::encryption::elgamal::ElGamalPubkey,
+};
+use spl_token_metadata_interface::state::TokenMetadata;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tracing::debug;
+
+use crate::{TokenClient, config::config_dir, utils::parse_elgamal_pubkey};
+
+/// Mint named on the command line: its address, or the symbol of a mint in the keyring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MintRef {
+    /// Mint address.
+    Address(Pubkey),
+    /// Symbol of a mint the owner has an account for, matched case-insensitively.
+    Symbol(String),
+}
+
+impl FromStr for MintRef {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        if value.is_empty() {
+            bail!("Empty mint");
+        }
+        Ok(value
+            .parse()
+            .map(MintRef::Address)
+            .unwrap_or_else(|_| MintRef::Symbol(value.to_string())))
+    }
+}
+
+impl fmt::Display for MintRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MintRef::Address(mint) => write!(f, "{mint}"),
+            MintRef::Symbol(symbol) => write!(f, "{symbol}"),
+        }
+    }
+}
+
+/// A configured token account, as tracked by the [`Keyring`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyringEntry {
+    /// Owner of the token account.
+    pub owner: Pubkey,
+    /// Mint of the token account.
+    pub mint: Pubkey,
+    /// Token account address.
+    pub account: Pubkey,
+    /// ElGamal pubkey the account was configured with.
+    pub elgamal_pubkey: ElGamalPubkey,
+    /// Symbol of the mint, from its token metadata or given when the account was configured.
+    pub symbol: Option<String>,
+}
+
+/// Record of the token accounts each owner configured: mint, account and ElGamal pubkey, plus the
+/// mint symbol so commands can name the mint by symbol.
+///
+/// Holds no secrets; the keys themselves stay derived or in the
+/// [`Keystore`](crate::keystore::Keystore). Changes are kept in memory until [`Keyring::save`].
+#[derive(Debug, Clone)]
+pub struct Keyring {
+    path: PathBuf,
+    entries: Vec<KeyringEntry>,
+}
+
+// On-disk format of the keyring
+#[derive(Serialize, Deserialize)]
+struct KeyringRecord {
+    owner: String,
+    mint: String,
+    account: String,
+    elgamal_pubkey: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    symbol: Option<String>,
+}
+
+impl Keyring {
+    /// Default location of the keyring file.
+    pub fn default_path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("keyring.json"))
+    }
+
+    /// Loads the keyring file at `path`; a missing file is an empty keyring.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self {
+                path,
+                entries: vec![],
+            });
+        }
+        let contents = std::fs::read(&path)
+            .with_context(|| format!("Unable to read keyring {}", path.display()))?;
+        let records: Vec<KeyringRecord> = serde_json::from_slice(&contents)
+            .with_context(|| format!("Invalid keyring {}", path.display()))?;
+        let entries = records
+            .into_iter()
+            .map(|record| entry_from_record(record, &path))
+            .collect::<Result<Vec<_>>>()?;
+        debug!(path = %path.display(), entries = entries.len(), "Loaded keyring");
+        Ok(Self { path, entries })
+    }
+
+    /// Path of the keyring file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes the keyring through a temporary file, creating its directory if needed.
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Unable to create {}", parent.display()))?;
+        }
+        let records = self
+            .entries
+            .iter()
+            .map(|entry| KeyringRecord {
+                owner: entry.owner.to_string(),
+                mint: entry.mint.to_string(),
+                account: entry.account.to_string(),
+                elgamal_pubkey: entry.elgamal_pubkey.to_string(),
+                symbol: entry.symbol.clone(),
+            })
+            .collect::<Vec<_>>();
+        let tmp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec_pretty(&records)?)
+            .with_context(|| format!("Unable to write keyring {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Unable to write keyring {}", self.path.display()))?;
+        debug!(path = %self.path.display(), entries = self.entries.len(), "Saved keyring");
+        Ok(())
+    }
+
+    /// Adds `entry`, replacing the entry of the same account. A known symbol is kept when `entry`
+    /// has none.
+    pub fn record(&mut self, mut entry: KeyringEntry) {
+        match self
+            .entries
+            .iter_mut()
+            .find(|existing| existing.owner == entry.owner && existing.account == entry.account)
+        {
+            Some(existing) => {
+                if entry.symbol.is_none() {
+                    entry.symbol = existing.symbol.take();
+                }
+                *existing = entry;
+            }
+            None => self.entries.push(entry),
+        }
+    }
+
+    /// Removes the entry of the owner's `account`; returns whether there was one.
+    pub fn remove(&mut self, owner: &Pubkey, account: &Pubkey) -> bool {
+        let len = self.entries.len();
+        self.entries
+            .retain(|entry| entry.owner != *owner || entry.account != *account);
+        self.entries.len() != len
+    }
+
+    /// Entries of `owner`, sorted by symbol, then mint and account.
+    pub fn accounts(&self, owner: &Pubkey) -> Vec<&KeyringEntry> {
+        let mut accounts = self
+            .entries
+            .iter()
+            .filter(|entry| entry.owner == *owner)
+            .collect::<Vec<_>>();
+        accounts.sort_by_key(|entry| {
+            (
+                entry.symbol.as_deref().map(str::to_lowercase),
+                entry.mint,
+                entry.account,
+            )
+        });
+        accounts
+    }
+
+    /// Resolves `mint` for `owner`: addresses are returned as is, symbols are looked up among the
+    /// owner's accounts.
+    ///
+    /// Fails on unknown symbols and on symbols shared by several mints.
+    pub fn resolve_mint(&self, owner: &Pubkey, mint: &MintRef) -> Result<Pubkey> {
+        let symbol = match mint {
+            MintRef::Address(mint) => return Ok(*mint),
+            MintRef::Symbol(symbol) => symbol,
+        };
+        let mut mints = self
+            .entries
+            .iter()
+            .filter(|entry| {
+                entry.owner == *owner
+                    && entry
+                        .symbol
+                        .as_deref()
+                        .is_some_and(|known| known.eq_ignore_ascii_case(symbol))
+            })
+            .map(|entry| entry.mint)
+            .collect::<Vec<_>>();
+        mints.sort();
+        mints.dedup();
+        match mints.as_slice() {
+            [mint] => Ok(*mint),
+            [] => Err(anyhow!(
+                "No account of {owner} for a mint with symbol {symbol} in {}; see list-accounts",
+                self.path.display()
+            )),
+            mints => Err(anyhow!(
+                "Symbol {symbol} matches several mints ({}); pass the mint address",
+                mints
+                    .iter()
+                    .map(Pubkey::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+}
+
+/// Symbol of the mint of `token` from its `TokenMetadata` extension, if it has a non-empty one.
+pub async fn mint_symbol(token: &TokenClient) -> Result<Option<String>> {
+    let mint_info = token.get_mint_info().await?;
+    Ok(mint_info
+        .get_variable_len_extension::<TokenMetadata>()
+        .ok()
+        .map(|metadata| metadata.symbol.trim().to_string())
+        .filter(|symbol| !symbol.is_empty()))
+}
+
+fn entry_from_record(record: KeyringRecord, path: &Path) -> Result<KeyringEntry> {
+    let pubkey = |value: &str| {
+        value
+            .parse::<Pubkey>()
+            .with_context(|| format!("Invalid pubkey {value} in keyring {}", path.display()))
+    };
+    Ok(KeyringEntry {
+        owner: pubkey(&record.owner)?,
+        mint: pubkey(&record.mint)?,
+        account: pubkey(&record.account)?,
+        elgamal_pubkey: parse_elgamal_pubkey(&record.elgamal_pubkey).with_context(|| {
+            format!(
+                "Invalid ElGamal pubkey of {} in keyring {}",
+                record.account,
+                path.display()
+            )
+        })?,
+        symbol: record.symbol,
+    })
+}
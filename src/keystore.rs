@@ -0,0 +1,273 @@
+use aes_gcm::{
+    Aes256Gcm, KeyInit, Nonce,
+    aead::{Aead, OsRng, Payload, rand_core::RngCore},
+};
+use anyhow::{Context, Result, anyhow, bail};
+use argon2::Argon2;
+use base64::{Engine, prelude::BASE64_STANDARD};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use spl_token_client::spl_token_2022::solana_zk_sdk::encryption::{
+    auth_encryption::AeKey,
+    elgamal::{ElGamalKeypair, ElGamalSecretKey},
+};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+use crate::config::config_dir;
+
+const VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+//ElGamal secret key followed by the AE key
+const ELGAMAL_SECRET_KEY_LEN: usize = 32;
+const AE_KEY_LEN: usize = 16;
+
+/// Directory of passphrase-encrypted confidential transfer keys, one file per token account.
+///
+/// The ElGamal secret key and AES key of an account are encrypted with AES-256-GCM under a key
+/// stretched from the passphrase with Argon2id and a random salt. Files are keyed by owner, mint
+/// and token account; the key id is authenticated along with the ciphertext, so a file moved to
+/// another account's path fails to decrypt.
+#[derive(Debug, Clone)]
+pub struct Keystore {
+    dir: PathBuf,
+}
+
+// On-disk format of one account's keys, and of key backups
+#[derive(Serialize, Deserialize)]
+pub(crate) struct KeyFile {
+    version: u8,
+    kdf: String,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+impl Keystore {
+    /// Default keystore directory.
+    pub fn default_path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("keystore"))
+    }
+
+    /// Opens the keystore in `dir`; the directory is created on the first save.
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// File holding the keys of `account`, owned by `owner`, for `mint`.
+    pub fn path(&self, owner: &Pubkey, mint: &Pubkey, account: &Pubkey) -> PathBuf {
+        self.dir
+            .join(owner.to_string())
+            .join(mint.to_string())
+            .join(format!("{account}.json"))
+    }
+
+    /// Lists the `(mint, account)` pairs of `owner` with stored keys, sorted.
+    pub fn accounts(&self, owner: &Pubkey) -> Result<Vec<(Pubkey, Pubkey)>> {
+        let owner_dir = self.dir.join(owner.to_string());
+        if !owner_dir.exists() {
+            return Ok(vec![]);
+        }
+        let read_dir = |dir: &Path| {
+            std::fs::read_dir(dir).with_context(|| format!("Unable to read {}", dir.display()))
+        };
+        let mut accounts = vec![];
+        for mint_dir in read_dir(&owner_dir)? {
+            let mint_dir = mint_dir?.path();
+            //Skip anything not named after a mint, such as stray files
+            let Some(mint) = mint_dir
+                .file_name()
+                .and_then(|name| name.to_str()?.parse::<Pubkey>().ok())
+                .filter(|_| mint_dir.is_dir())
+            else {
+                continue;
+            };
+            for key_file in read_dir(&mint_dir)? {
+                let key_file = key_file?.path();
+                if key_file
+                    .extension()
+                    .is_none_or(|extension| extension != "json")
+                {
+                    continue;
+                }
+                if let Some(account) = key_file
+                    .file_stem()
+                    .and_then(|name| name.to_str()?.parse::<Pubkey>().ok())
+                {
+                    accounts.push((mint, account));
+                }
+            }
+        }
+        accounts.sort();
+        Ok(accounts)
+    }
+
+    /// Encrypts the keys of `account` with `passphrase` and writes them, replacing any earlier
+    /// file. Returns the path of the file.
+    pub fn save(
+        &self,
+        owner: &Pubkey,
+        mint: &Pubkey,
+        account: &Pubkey,
+        elgamal_keypair: &ElGamalKeypair,
+        aes_key: &AeKey,
+        passphrase: &str,
+    ) -> Result<PathBuf> {
+        let mut plaintext = Vec::with_capacity(ELGAMAL_SECRET_KEY_LEN + AE_KEY_LEN);
+        plaintext.extend_from_slice(elgamal_keypair.secret().as_bytes());
+        plaintext.extend_from_slice(&<[u8; AE_KEY_LEN]>::from(aes_key.clone()));
+        let key_file = seal(
+            &plaintext,
+            passphrase,
+            key_id(owner, mint, account).as_bytes(),
+        )
+        .with_context(|| format!("Failed to encrypt the keys of {account}"))?;
+
+        let path = self.path(owner, mint, account);
+        let parent = path.parent().expect("Key files live in a directory");
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create {}", parent.display()))?;
+        let tmp_path = path.with_extension("json.tmp");
+        write_private(&tmp_path, &serde_json::to_vec_pretty(&key_file)?)?;
+        std::fs::rename(&tmp_path, &path)
+            .with_context(|| format!("Unable to write key file {}", path.display()))?;
+        debug!(path = %path.display(), "Saved encrypted keys");
+        Ok(path)
+    }
+
+    /// Loads and decrypts the keys of `account`, or returns `None` if none are stored.
+    ///
+    /// Fails on a wrong passphrase or a tampered file.
+    pub fn load(
+        &self,
+        owner: &Pubkey,
+        mint: &Pubkey,
+        account: &Pubkey,
+        passphrase: &str,
+    ) -> Result<Option<(ElGamalKeypair, AeKey)>> {
+        let path = self.path(owner, mint, account);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = std::fs::read(&path)
+            .with_context(|| format!("Unable to read key file {}", path.display()))?;
+        let key_file: KeyFile = serde_json::from_slice(&contents)
+            .with_context(|| format!("Invalid key file {}", path.display()))?;
+        let plaintext = open(
+            &key_file,
+            passphrase,
+            key_id(owner, mint, account).as_bytes(),
+            &path,
+        )?;
+        if plaintext.len() != ELGAMAL_SECRET_KEY_LEN + AE_KEY_LEN {
+            bail!("Invalid key file {}", path.display());
+        }
+        let (elgamal_secret, aes_key) = plaintext.split_at(ELGAMAL_SECRET_KEY_LEN);
+        let elgamal_keypair = ElGamalKeypair::new(
+            ElGamalSecretKey::try_from(elgamal_secret)
+                .map_err(|_| anyhow!("Invalid ElGamal secret key in {}", path.display()))?,
+        );
+        let aes_key = AeKey::try_from(aes_key)
+            .map_err(|_| anyhow!("Invalid AES key in {}", path.display()))?;
+        debug!(path = %path.display(), "Loaded encrypted keys");
+        Ok(Some((elgamal_keypair, aes_key)))
+    }
+}
+
+// Identifies the account a key file belongs to; authenticated with the ciphertext
+fn key_id(owner: &Pubkey, mint: &Pubkey, account: &Pubkey) -> String {
+    format!("confidential-transfer-keystore-v{VERSION}:{owner}:{mint}:{account}")
+}
+
+// Encrypts `plaintext` under `passphrase`, authenticating `aad` along with it
+pub(crate) fn seal(plaintext: &[u8], passphrase: &str, aad: &[u8]) -> Result<KeyFile> {
+    let salt: [u8; SALT_LEN] = rand_bytes();
+    let nonce: [u8; NONCE_LEN] = rand_bytes();
+    let ciphertext = cipher(passphrase, &salt)?
+        .encrypt(
+            &Nonce::from(nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| anyhow!("Encryption failed"))?;
+    Ok(KeyFile {
+        version: VERSION,
+        kdf: "argon2id".to_string(),
+        salt: BASE64_STANDARD.encode(salt),
+        nonce: BASE64_STANDARD.encode(nonce),
+        ciphertext: BASE64_STANDARD.encode(ciphertext),
+    })
+}
+
+// Decrypts `key_file`, read from `path`, with `passphrase`; fails on a wrong passphrase or a
+// tampered file
+pub(crate) fn open(
+    key_file: &KeyFile,
+    passphrase: &str,
+    aad: &[u8],
+    path: &Path,
+) -> Result<Vec<u8>> {
+    if key_file.version != VERSION || key_file.kdf != "argon2id" {
+        bail!(
+            "Unsupported encrypted file {} (version {}, {})",
+            path.display(),
+            key_file.version,
+            key_file.kdf
+        );
+    }
+    let decode = |field: &str| {
+        BASE64_STANDARD
+            .decode(field)
+            .with_context(|| format!("Invalid encrypted file {}", path.display()))
+    };
+    let salt = decode(&key_file.salt)?;
+    let nonce: [u8; NONCE_LEN] = decode(&key_file.nonce)?
+        .try_into()
+        .map_err(|_| anyhow!("Invalid encrypted file {}", path.display()))?;
+    cipher(passphrase, &salt)?
+        .decrypt(
+            &Nonce::from(nonce),
+            Payload {
+                msg: &decode(&key_file.ciphertext)?,
+                aad,
+            },
+        )
+        .map_err(|_| {
+            anyhow!(
+                "Unable to decrypt {}: wrong passphrase or tampered file",
+                path.display()
+            )
+        })
+}
+
+// AES-256-GCM cipher keyed with the Argon2id stretch of `passphrase`
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<Aes256Gcm> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| anyhow!("Failed to derive the encryption key: {err}"))?;
+    Ok(Aes256Gcm::new(&key.into()))
+}
+
+fn rand_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+// Writes `contents` to a new file readable by the current user only
+pub(crate) fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    use std::io::Write;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Unable to write key file {}", path.display()))?;
+    file.write_all(contents)
+        .with_context(|| format!("Unable to write key file {}", path.display()))
+}
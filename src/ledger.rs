@@ -0,0 +1,124 @@
+use anyhow::{Result, anyhow, bail};
+use solana_derivation_path::DerivationPath;
+use solana_offchain_message::OffchainMessage;
+use solana_remote_wallet::{
+    locator::Locator,
+    remote_keypair::{RemoteKeypair, generate_remote_keypair},
+    remote_wallet::{RemoteWalletManager, initialize_wallet_manager},
+};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::Signature,
+    signer::{Signer, SignerError},
+};
+use std::rc::Rc;
+use tracing::debug;
+use uriparse::URIReference;
+
+//Shown on the device before the hex-encoded key derivation message
+const KEY_DERIVATION_MESSAGE_PREFIX: &str = "Derive confidential transfer keys: ";
+
+/// Returns whether `path` names a hardware wallet, such as `usb://ledger?key=0`, rather than a
+/// keypair file.
+pub fn is_remote_wallet_path(path: &str) -> bool {
+    path.starts_with("usb://")
+}
+
+/// Connects to the USB hardware wallets plugged in.
+///
+/// Only available when built with the `ledger` feature.
+pub fn connect_wallet_manager() -> Result<Rc<RemoteWalletManager>> {
+    if !cfg!(feature = "ledger") {
+        bail!("Hardware wallets are not supported by this build; rebuild with `--features ledger`");
+    }
+    let wallet_manager =
+        initialize_wallet_manager().map_err(|e| anyhow!("Unable to access USB devices: {e}"))?;
+    let devices = wallet_manager
+        .update_devices()
+        .map_err(|e| anyhow!("Unable to list USB devices: {e}"))?;
+    if devices == 0 {
+        bail!("No hardware wallet found; plug in and unlock the Ledger and open the Solana app");
+    }
+    debug!(devices, "Connected to hardware wallets");
+    Ok(wallet_manager)
+}
+
+/// Loads the Ledger signer at `uri`, e.g. `usb://ledger?key=0` or `usb://ledger/<PUBKEY>?key=0/0`
+/// to pick one of several devices. `keypair_name` names the role in device prompts.
+///
+/// The `key` query selects the account and change of the `m/44'/501'` derivation path, as with
+/// the Solana CLI. The Ledger signs transactions after confirmation on the device; confidential
+/// keys are derived from it through [`OffchainMessageSigner`].
+pub fn load_ledger_signer(
+    uri: &str,
+    keypair_name: &str,
+    wallet_manager: &RemoteWalletManager,
+) -> Result<RemoteKeypair> {
+    let uri_ref =
+        URIReference::try_from(uri).map_err(|e| anyhow!("Invalid wallet URI {uri}: {e}"))?;
+    let derivation_path = DerivationPath::from_uri_key_query(&uri_ref)
+        .map_err(|e| anyhow!("Invalid derivation path in {uri}: {e}"))?
+        .unwrap_or_default();
+    let locator =
+        Locator::new_from_uri(&uri_ref).map_err(|e| anyhow!("Invalid wallet URI {uri}: {e}"))?;
+    let signer = generate_remote_keypair(
+        locator,
+        derivation_path,
+        wallet_manager,
+        false, //Do not ask to confirm the pubkey on the device
+        keypair_name,
+    )
+    .map_err(|e| anyhow!("Unable to load {keypair_name} from {uri}: {e}"))?;
+    debug!(pubkey = %signer.pubkey, path = %signer.path, "Loaded hardware wallet signer");
+    Ok(signer)
+}
+
+/// Signer adapter for hardware wallets, which refuse to sign arbitrary bytes.
+///
+/// ElGamal and AES keys are derived from the signature of a binary message, which a Ledger would
+/// try, and fail, to parse as a transaction. Every message is instead hex-encoded into a
+/// printable off-chain message, which the device displays and signs. Signatures are
+/// deterministic, so the derived keys are too, but they differ from those of a raw signature of
+/// the same message.
+pub struct OffchainMessageSigner<'a> {
+    signer: &'a dyn Signer,
+}
+
+impl<'a> OffchainMessageSigner<'a> {
+    /// Wraps `signer`.
+    pub fn new(signer: &'a dyn Signer) -> Self {
+        Self { signer }
+    }
+}
+
+impl Signer for OffchainMessageSigner<'_> {
+    fn try_pubkey(&self) -> Result<Pubkey, SignerError> {
+        self.signer.try_pubkey()
+    }
+
+    fn try_sign_message(&self, message: &[u8]) -> Result<Signature, SignerError> {
+        let hex = message
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        let text = format!("{KEY_DERIVATION_MESSAGE_PREFIX}{hex}");
+        let offchain_message = OffchainMessage::new(0, text.as_bytes())
+            .and_then(|offchain_message| offchain_message.serialize())
+            .map_err(|e| SignerError::Custom(format!("Invalid off-chain message: {e}")))?;
+        self.signer.try_sign_message(&offchain_message)
+    }
+
+    fn is_interactive(&self) -> bool {
+        self.signer.is_interactive()
+    }
+}
+
+// Runs the key derivation `derive` with `signer`, through off-chain messages for interactive
+// signers such as hardware wallets
+pub(crate) fn derive_with<T>(signer: &dyn Signer, derive: impl FnOnce(&dyn Signer) -> T) -> T {
+    if signer.is_interactive() {
+        derive(&OffchainMessageSigner::new(signer))
+    } else {
+        derive(signer)
+    }
+}
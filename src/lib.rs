@@ -0,0 +1,79 @@
+//! Client library for Token-2022 confidential transfers.
+//!
+//! Every operation is an async function taking a [`TokenClient`] (or the program client it is
+//! built from) and returning the signatures, addresses and key material it produced instead of
+//! printing them, so it can be embedded in other services.
+
+use spl_token_client::token::Token;
+
+pub mod amount;
+pub mod approve;
+pub mod auditor;
+pub mod authority;
+pub mod backup;
+pub mod balance;
+pub mod batch;
+pub mod bootstrap;
+pub mod bundle;
+pub mod client;
+pub mod close;
+pub mod concurrent;
+pub mod config;
+pub mod credits;
+pub mod decode_table;
+pub mod deposit;
+pub mod diagnose;
+pub mod escrow;
+pub mod exchange;
+#[cfg(any(test, feature = "testing"))]
+pub mod faults;
+pub mod fees;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod flow;
+pub mod gc;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod indexer;
+pub mod inspect;
+pub mod keygen;
+pub mod keyring;
+pub mod keystore;
+pub mod ledger;
+pub mod lookup_table;
+pub mod metrics;
+pub mod mint;
+pub mod mint_burn;
+#[cfg(any(test, feature = "testing"))]
+pub mod mock;
+pub mod nonce;
+pub mod offline;
+pub mod payment;
+pub mod payroll;
+pub mod placement;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod progress;
+pub mod proofs;
+pub mod registry;
+pub mod remote_signer;
+pub mod retry;
+pub mod rotate;
+pub mod schedule;
+pub mod seed;
+pub mod server;
+#[cfg(feature = "storage")]
+pub mod storage;
+#[cfg(any(test, feature = "testing"))]
+pub mod testkit;
+pub mod throttle;
+pub mod transfer;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod utils;
+pub mod watch;
+pub mod webhook;
+pub mod withdraw;
+
+/// Token-2022 client used by all operations in this crate.
+pub type TokenClient = Token<client::RpcSender>;
@@ -0,0 +1,254 @@
+use anyhow::{Context, Result, anyhow};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    address_lookup_table::{
+        instruction::{
+            create_lookup_table as create_lookup_table_instruction,
+            extend_lookup_table as extend_lookup_table_instruction,
+        },
+        state::AddressLookupTable,
+    },
+    compute_budget::ComputeBudgetInstruction,
+    instruction::Instruction,
+    message::AddressLookupTableAccount,
+    pubkey::Pubkey,
+    signature::Signature,
+    signer::Signer,
+    sysvar,
+};
+use spl_token_client::{
+    client::ProgramClient, spl_token_2022::id as token_2022_program_id, token::Token,
+};
+use std::{fmt, sync::Arc};
+use tracing::{debug, instrument};
+
+use crate::{
+    TokenClient,
+    client::{ComputeBudget, RpcSender},
+    placement::sign_transaction,
+    utils::into_signature,
+};
+
+/// Addresses added to a lookup table per transaction, so the extend transaction stays within the
+/// size limit.
+pub const ADDRESSES_PER_EXTEND: usize = 20;
+
+/// Address lookup table the packed transactions of an operation are compiled against, sent as v0
+/// transactions.
+///
+/// The token client only builds legacy transactions, so this carries what sending v0 ones takes:
+/// the RPC client, the sender and the fee payer of the token client, and the priority fee.
+#[derive(Clone)]
+pub struct LookupTable {
+    /// Addresses in the table, as loaded by [`LookupTable::load`].
+    pub account: AddressLookupTableAccount,
+    /// RPC client the transactions are sent through.
+    pub rpc_client: Arc<RpcClient>,
+    /// Sender of the token client, so dry runs and timings cover the transactions.
+    pub sender: RpcSender,
+    /// Fee payer of the token client.
+    pub fee_payer: Arc<dyn Signer>,
+    /// Priority fee in micro-lamports per compute unit, if any.
+    pub compute_unit_price: Option<u64>,
+}
+
+impl fmt::Debug for LookupTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LookupTable")
+            .field("address", &self.account.key)
+            .field("addresses", &self.account.addresses.len())
+            .field("fee_payer", &self.fee_payer.pubkey())
+            .finish()
+    }
+}
+
+impl LookupTable {
+    /// Loads the lookup table at `address` to send packed transactions through `rpc_client`
+    /// and `sender`, paid by `fee_payer` with `compute_budget`.
+    pub async fn load(
+        rpc_client: Arc<RpcClient>,
+        sender: RpcSender,
+        fee_payer: Arc<dyn Signer>,
+        compute_budget: &ComputeBudget,
+        address: &Pubkey,
+    ) -> Result<Self> {
+        let account = get_lookup_table(&rpc_client, address).await?;
+        debug!(%address, addresses = account.addresses.len(), "Loaded lookup table");
+        Ok(Self {
+            account,
+            rpc_client,
+            sender,
+            fee_payer,
+            compute_unit_price: compute_budget.unit_price,
+        })
+    }
+
+    // Sends `instructions` as a v0 transaction compiled against the table, signed by the fee
+    // payer and `signers`, the other signers the instructions require
+    pub(crate) async fn send(
+        &self,
+        instructions: &[Instruction],
+        signers: &[&dyn Signer],
+    ) -> Result<Signature> {
+        let mut instructions = instructions.to_vec();
+        if let Some(compute_unit_price) = self.compute_unit_price {
+            instructions.push(ComputeBudgetInstruction::set_compute_unit_price(
+                compute_unit_price,
+            ));
+        }
+        let blockhash = self.rpc_client.get_latest_blockhash().await?;
+        let transaction = sign_transaction(
+            &instructions,
+            self.fee_payer.as_ref(),
+            signers,
+            Some(&self.account),
+            blockhash,
+        )?;
+        self.sender
+            .send_versioned(&self.rpc_client, &transaction)
+            .await
+    }
+}
+
+/// Addresses worth looking up for the operations on `mint` and its token `accounts`: the mint,
+/// the accounts and the instructions sysvar read by instructions with inline proofs.
+///
+/// Programs are left out: the programs a transaction invokes must be among its own account keys.
+pub fn lookup_table_addresses(mint: &Pubkey, accounts: &[Pubkey]) -> Vec<Pubkey> {
+    let mut addresses = vec![*mint, sysvar::instructions::id()];
+    for account in accounts {
+        if !addresses.contains(account) {
+            addresses.push(*account);
+        }
+    }
+    addresses
+}
+
+/// Addresses in the lookup table at `address`.
+pub async fn get_lookup_table(
+    rpc_client: &RpcClient,
+    address: &Pubkey,
+) -> Result<AddressLookupTableAccount> {
+    let account = rpc_client
+        .get_account(address)
+        .await
+        .with_context(|| format!("Unable to read lookup table {address}"))?;
+    let table = AddressLookupTable::deserialize(&account.data)
+        .map_err(|e| anyhow!("{address} is not a lookup table: {e}"))?;
+    Ok(AddressLookupTableAccount {
+        key: *address,
+        addresses: table.addresses.to_vec(),
+    })
+}
+
+/// Creates a lookup table managed by `authority`, its rent and fees paid by `fee_payer` with
+/// `compute_budget`, then extends it with `addresses`. Returns the address of the table and the
+/// signatures of the creation and of each extension.
+///
+/// A table only serves transactions from the slot after it was last extended.
+#[instrument(skip_all, fields(authority = %authority.pubkey(), addresses = addresses.len()))]
+pub async fn create_lookup_table(
+    program_client: Arc<dyn ProgramClient<RpcSender>>,
+    rpc_client: &RpcClient,
+    fee_payer: Arc<dyn Signer>,
+    compute_budget: &ComputeBudget,
+    authority: &dyn Signer,
+    addresses: &[Pubkey],
+) -> Result<(Pubkey, Vec<Signature>)> {
+    //The table address is derived from a recent slot, which the program checks
+    let recent_slot = rpc_client.get_slot().await?;
+    let (instruction, address) = create_lookup_table_instruction(
+        authority.pubkey(), //Authority that can extend the table
+        fee_payer.pubkey(), //Payer of the rent
+        recent_slot,        //Slot the address is derived from
+    );
+    let token = lookup_table_client(program_client.clone(), fee_payer.clone(), compute_budget);
+    let mut signatures = vec![into_signature(
+        token
+            .process_ixs::<[&dyn Signer; 0]>(&[instruction], &[])
+            .await?,
+    )?];
+    debug!(%address, signature = %signatures[0], "Created lookup table");
+    signatures.extend(
+        extend(
+            program_client,
+            fee_payer,
+            compute_budget,
+            &address,
+            authority,
+            addresses,
+        )
+        .await?,
+    );
+    Ok((address, signatures))
+}
+
+/// Adds `addresses` to the lookup table at `address` managed by `authority`, skipping those
+/// already in it, in transactions of [`ADDRESSES_PER_EXTEND`] addresses paid by `fee_payer`
+/// with `compute_budget`. Returns their signatures.
+#[instrument(skip_all, fields(%address, addresses = addresses.len()))]
+pub async fn extend_lookup_table(
+    program_client: Arc<dyn ProgramClient<RpcSender>>,
+    rpc_client: &RpcClient,
+    fee_payer: Arc<dyn Signer>,
+    compute_budget: &ComputeBudget,
+    address: &Pubkey,
+    authority: &dyn Signer,
+    addresses: &[Pubkey],
+) -> Result<Vec<Signature>> {
+    let table = get_lookup_table(rpc_client, address).await?;
+    let missing = addresses
+        .iter()
+        .filter(|added| !table.addresses.contains(added))
+        .copied()
+        .collect::<Vec<_>>();
+    extend(
+        program_client,
+        fee_payer,
+        compute_budget,
+        address,
+        authority,
+        &missing,
+    )
+    .await
+}
+
+// Extends the table at `address` with `addresses`, in chunks of ADDRESSES_PER_EXTEND
+async fn extend(
+    program_client: Arc<dyn ProgramClient<RpcSender>>,
+    fee_payer: Arc<dyn Signer>,
+    compute_budget: &ComputeBudget,
+    address: &Pubkey,
+    authority: &dyn Signer,
+    addresses: &[Pubkey],
+) -> Result<Vec<Signature>> {
+    let token = lookup_table_client(program_client, fee_payer.clone(), compute_budget);
+    let mut signatures = Vec::with_capacity(addresses.len().div_ceil(ADDRESSES_PER_EXTEND));
+    for chunk in addresses.chunks(ADDRESSES_PER_EXTEND) {
+        let instruction = extend_lookup_table_instruction(
+            *address,                 //Lookup table to extend
+            authority.pubkey(),       //Authority of the table
+            Some(fee_payer.pubkey()), //Payer of the extra rent
+            chunk.to_vec(),           //Addresses to add
+        );
+        let signature = into_signature(token.process_ixs(&[instruction], &[authority]).await?)?;
+        debug!(%signature, added = chunk.len(), "Extended lookup table");
+        signatures.push(signature);
+    }
+    Ok(signatures)
+}
+
+// Token client sending lookup table instructions, which involve no mint, so any address does
+fn lookup_table_client(
+    program_client: Arc<dyn ProgramClient<RpcSender>>,
+    fee_payer: Arc<dyn Signer>,
+    compute_budget: &ComputeBudget,
+) -> TokenClient {
+    compute_budget.apply(Token::new(
+        program_client,
+        &token_2022_program_id(),
+        &Pubkey::default(),
+        None,
+        fee_payer,
+    ))
+}
@@ -1,149 +1,4054 @@
-use anyhow::Result;
-use solana_client::nonblocking::rpc_client::RpcClient;
+use anyhow::{Context, Result, anyhow, bail};
+use clap::{CommandFactory, Parser};
+#[cfg(feature = "tui")]
+use confidential_transfer::tui;
+use confidential_transfer::{
+    TokenClient,
+    amount::{self, Amount},
+    approve, auditor,
+    authority::{self, Authority},
+    backup, balance, batch, bootstrap,
+    bundle::JitoBundle,
+    client::{self, ComputeBudget, RpcSender, StageTimings},
+    close,
+    config::{CONFIG_ENV, Config, env_var, parse_commitment},
+    credits,
+    decode_table::DecodeTable,
+    deposit, escrow, exchange, fees, flow, gc, inspect, keygen,
+    keyring::{self, MintRef},
+    keystore,
+    lookup_table::{self, LookupTable},
+    metrics, mint, mint_burn,
+    nonce::{self, DurableNonce, DurableNonces},
+    offline, payment, payroll,
+    placement::Packing,
+    proofs::{self, ProofBundle, ProofInputs},
+    registry,
+    remote_signer::{HttpSignerBackend, SignerBackend},
+    retry, rotate, schedule, seed, server, transfer, utils, watch, webhook, withdraw,
+};
+#[cfg(feature = "storage")]
+use confidential_transfer::{
+    indexer,
+    storage::{self, OperationKind, SqliteStorage, Storage},
+};
+use qrcode::{QrCode, render::unicode};
+use serde_json::json;
+use solana_client::nonblocking::{pubsub_client::PubsubClient, rpc_client::RpcClient};
 use solana_sdk::{
-    commitment_config::CommitmentConfig,
-    signature::Keypair,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
     signer::Signer,
-   
 };
-
 use spl_token_client::{
-    client::ProgramRpcClientSendTransaction, spl_token_2022::{extension::{BaseStateWithExtensions, confidential_transfer::{ConfidentialTransferAccount, account_info::WithdrawAccountInfo}}, solana_zk_sdk::encryption::elgamal}, token::Token
+    client::ProgramClient,
+    spl_token_2022::solana_zk_sdk::encryption::{
+        auth_encryption::AeKey,
+        elgamal::{ElGamalKeypair, ElGamalPubkey},
+        pod::elgamal::PodElGamalPubkey,
+    },
+    token::ComputeUnitLimit,
 };
-use spl_token_confidential_transfer_proof_generation::withdraw::WithdrawProofData;
 
-use std::sync::Arc;
+use std::{
+    cell::OnceCell,
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, level_filters::LevelFilter, warn};
+use tracing_subscriber::EnvFilter;
+
+mod cli;
 
-mod mint;
-mod utils;
+//Passphrase of the keystore, asked for interactively when unset
+const KEYSTORE_PASSPHRASE_ENV: &str = "CT_KEYSTORE_PASSPHRASE";
+//Seed phrase of --seed-phrase and its BIP39 passphrase, asked for interactively when unset
+const SEED_PHRASE_ENV: &str = "CT_SEED_PHRASE";
+const SEED_PASSPHRASE_ENV: &str = "CT_SEED_PASSPHRASE";
+//Passphrase of backup-keys and restore-keys archives, asked for interactively when unset
+const BACKUP_PASSPHRASE_ENV: &str = "CT_BACKUP_PASSPHRASE";
+//Bearer token of the remote signing service, if it needs one
+const REMOTE_SIGNER_TOKEN_ENV: &str = "CT_REMOTE_SIGNER_TOKEN";
+//Bearer token the HTTP and gRPC APIs require of every request, if set
+const API_TOKEN_ENV: &str = "CT_API_TOKEN";
+//Secret the payloads of --webhook-url are signed with
+const WEBHOOK_SECRET_ENV: &str = "CT_WEBHOOK_SECRET";
+//Interval between the lookups for the transfer paying a payment request
+const PAYMENT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+//Longest wait of run-schedules between checks, so clock changes and failed payments are noticed
+const SCHEDULE_MAX_WAIT_SECS: u64 = 60;
 
+use cli::{Cli, Command, ProofKind};
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    // Initialize the RPC client to connect to the local Solana cluster
-    let rpc_client = Arc::new(RpcClient::new_with_commitment(
-        String::from("http://localhost:8899"),
-        CommitmentConfig::confirmed(),
+async fn main() -> Result<ExitCode> {
+    let cli = Cli::parse();
+    init_logging(&cli);
+    if !cli.show_fees {
+        return run(cli, &OnceCell::new()).await;
+    }
+    //A dry run of the same command first, to quote the cost of its transactions
+    let preview = OnceCell::new();
+    let exit_code = run(
+        Cli {
+            dry_run: true,
+            ..Cli::parse()
+        },
+        &preview,
+    )
+    .await?;
+    let fees = preview.get().map(RpcSender::fees).unwrap_or_default();
+    if fees.transactions == 0 {
+        info!("No transaction to send");
+        return Ok(exit_code);
+    }
+    let sol = |lamports| amount::format(lamports, 9);
+    info!(
+        "Fees of {} transactions: {} SOL",
+        fees.transactions,
+        sol(fees.fee_lamports)
+    );
+    if fees.rent_lamports > 0 {
+        info!(
+            "Rent deposited: {} SOL, of which {} SOL is refunded when the proof accounts close",
+            sol(fees.rent_lamports),
+            sol(fees.refunded_rent_lamports)
+        );
+    }
+    info!("Total: {} SOL", sol(fees.net_lamports()));
+    if !confirm("Send the transactions?")? {
+        info!("Nothing sent");
+        return Ok(ExitCode::FAILURE);
+    }
+    run(cli, &OnceCell::new()).await
+}
+
+//Runs the command of `cli`, handing the sender of its transactions to `sender_cell` for the
+//caller to read its fee estimate from
+async fn run(cli: Cli, sender_cell: &OnceCell<RpcSender>) -> Result<ExitCode> {
+    //Generated from the command definitions alone, without the config file or the cluster
+    match &cli.command {
+        Command::Completions { shell } => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+            let mut script = Vec::new();
+            clap_complete::generate(*shell, &mut command, name, &mut script);
+            std::io::stdout().write_all(&script)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Command::Mangen { out_dir } => {
+            man_pages(out_dir.as_deref())?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        _ => {}
+    }
+    // Settings from the config file, overridden by the CT_* environment variables; command line
+    // flags take precedence over both
+    let config_path = cli.config.clone().or_else(|| {
+        env_var(CONFIG_ENV)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    });
+    let mut config = Config::load(config_path.as_deref())?;
+    config.apply_env(env_var)?;
+    if let Some(url) = cli.url {
+        config.rpc_url = Some(url);
+    }
+    if let Some(ws_url) = cli.ws_url {
+        config.ws_url = Some(ws_url);
+    }
+    if let Some(commitment) = cli.commitment {
+        config.commitment = Some(commitment);
+    }
+    if let Some(seed_message) = cli.seed_message {
+        config.seed_message = Some(seed_message);
+    }
+    if let Some(max_in_flight) = cli.max_in_flight {
+        config.rate_limit.max_in_flight = Some(max_in_flight);
+    }
+    if let Some(requests_per_second) = cli.requests_per_second {
+        config.rate_limit.requests_per_second = Some(requests_per_second);
+    }
+    let seed_message = config.seed_message()?;
+    //Restarts the sequence, so a fee preview and the run after it derive the same addresses
+    if let Some(seed) = &cli.seed {
+        keygen::set_seed(seed);
+        warn!("Deriving new keypairs and keys from --seed; they are only as secret as the seed");
+    }
+    // Initialize the RPC client to connect to the configured Solana cluster, retrying transient
+    // failures and pacing requests as set in the config file
+    let rpc_client = Arc::new(retry::rpc_client(
+        config.rpc_url(),
+        config.commitment()?,
+        config.retry_policy()?,
+        config.rate_limit()?,
     ));
+    // Transactions are confirmed through a signature subscription unless told to poll, or if the
+    // websocket endpoint is unreachable
+    let pubsub_client = if cli.dry_run
+        || cli.poll_confirmations
+        || cli.sign_offline.is_some()
+        || matches!(cli.command, Command::SignTransaction { .. })
+    {
+        None
+    } else {
+        let ws_url = config.ws_url();
+        match PubsubClient::new(&ws_url).await {
+            Ok(pubsub_client) => Some(Arc::new(pubsub_client)),
+            Err(err) => {
+                warn!("Unable to connect to {ws_url}, polling confirmations instead: {err}");
+                None
+            }
+        }
+    };
+    // Every transaction goes through this client; with --dry-run it is only simulated
+    let preflight_commitment = match &cli.preflight_commitment {
+        Some(commitment) => Some(parse_commitment(commitment)?.commitment),
+        None => None,
+    };
+    let mut sender = RpcSender::new(cli.dry_run);
+    sender.compute_unit_margin_percent = cli.compute_unit_margin;
+    sender.skip_preflight = cli.skip_preflight;
+    sender.preflight_commitment = preflight_commitment;
+    sender.sign_offline = cli.sign_offline.clone();
+    sender.pubsub_client = pubsub_client;
+    let _ = sender_cell.set(sender.clone());
+    let program_client = client::program_client(rpc_client.clone(), sender.clone());
+    if cli.dry_run && !cli.show_fees {
+        info!("Dry run: transactions are simulated, nothing is broadcast");
+    }
+    // Compute budget instructions of every transaction
+    let unit_price = match cli.priority_fee_percentile {
+        Some(percentile) => {
+            let unit_price = client::recent_priority_fee(&rpc_client, &[], percentile).await?;
+            info!(
+                "Priority fee at percentile {} of recent slots: {} micro-lamports per compute unit",
+                percentile, unit_price
+            );
+            Some(unit_price)
+        }
+        None => cli.compute_unit_price,
+    };
+    let compute_budget = ComputeBudget {
+        unit_limit: cli.compute_unit_limit.unwrap_or(ComputeUnitLimit::Default),
+        unit_price,
+    };
+
+    // Load the wallet keypair; it fills every role without a keypair of its own
+    if let Some(keypair) = cli.keypair {
+        config.keypair_path = Some(keypair);
+    }
+    //Runs before the wallet is loaded, since it creates it
+    if let Command::Bootstrap { target, airdrop } = cli.command {
+        return bootstrap(&rpc_client, &config.keypair_path()?, target, airdrop)
+            .await
+            .map(|()| ExitCode::SUCCESS);
+    }
+    let signers = utils::SignerSources::new(cli.remote_signer_url.as_deref().map(|url| {
+        Box::new(HttpSignerBackend::new(
+            url,
+            env_var(REMOTE_SIGNER_TOKEN_ENV),
+        )) as Box<dyn SignerBackend>
+    }));
+    let signers = match cli.sign_offline {
+        Some(_) => signers.with_offline_signers(),
+        None => signers,
+    };
+    //Runs before the wallet is loaded, on a machine that may hold none of the other keys
+    if let Command::SignTransaction {
+        file,
+        signers: signer_paths,
+        signatures,
+    } = &cli.command
+    {
+        let mut transaction = offline::read_transaction(file)?;
+        for path in signer_paths {
+            offline::sign_transaction(
+                &mut transaction,
+                load_signer(path, "signer", &signers)?.as_ref(),
+            )?;
+        }
+        for (signer, signature) in signatures {
+            offline::add_signature(&mut transaction, signer, *signature)?;
+        }
+        offline::save_transaction(file, &transaction)?;
+        let missing = offline::missing_signers(&transaction);
+        if missing.is_empty() {
+            info!("Fully signed; send it with broadcast-transaction");
+        } else {
+            for signer in missing {
+                info!("Missing signature: {}", signer);
+            }
+            info!(
+                "Message to sign (base64): {}",
+                offline::message_to_sign(&transaction)
+            );
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    let wallet = load_signer(&config.keypair_path()?, "wallet", &signers)?;
+    let owner = match cli.multisig {
+        Some(address) => {
+            let multisig_signers = cli
+                .multisig_signer
+                .iter()
+                .map(|path| load_signer(path, "multisig signer", &signers))
+                .collect::<Result<Vec<_>>>()?;
+            authority::load_multisig(program_client.as_ref(), address, multisig_signers).await?
+        }
+        None => Authority::single(load_signer_or(
+            cli.owner.as_deref(),
+            "owner",
+            &wallet,
+            &signers,
+        )?),
+    };
+    let owner = owner.with_seed_message(seed_message.clone());
+    let owner = if cli.seed_phrase {
+        let key_source = load_seed_phrase_key_source(&cli.derivation_path)?;
+        info!(
+            "Confidential keys derived from the seed phrase ({}, key source {})",
+            cli.derivation_path,
+            key_source.pubkey()
+        );
+        owner.with_key_source(Arc::new(key_source))
+    } else {
+        owner
+    };
+    let mint_authority = load_signer_or(
+        cli.mint_authority.as_deref(),
+        "mint authority",
+        &wallet,
+        &signers,
+    )?;
+    let fee_payer = load_signer_or(cli.fee_payer.as_deref(), "fee payer", &wallet, &signers)?;
+    let proof_authority = cli
+        .proof_authority
+        .as_deref()
+        .map(|path| load_signer(path, "proof authority", &signers))
+        .transpose()?;
+    let owner = match &proof_authority {
+        Some(proof_authority) => {
+            info!("Proof authority public key: {}", proof_authority.pubkey());
+            owner.with_proof_authority(proof_authority.clone())
+        }
+        None => owner,
+    };
+    let nonce_authority = load_signer_or(
+        cli.nonce_authority.as_deref(),
+        "nonce authority",
+        &fee_payer,
+        &signers,
+    )?;
+    //Token clients read the nonce once and every transaction advances it, so commands sending
+    //several take one nonce account per transaction, for their packed transactions
+    let (nonce, nonces) = match &cli.command {
+        _ if cli.nonce.is_empty() => (None, None),
+        Command::MintTo { .. }
+        | Command::Deposit { .. }
+        | Command::Apply { .. }
+        | Command::UpdateMint { .. }
+        | Command::EnableRequiredMemos { .. }
+        | Command::DisableRequiredMemos { .. } => {
+            let [account] = cli.nonce[..] else {
+                bail!("--nonce takes a single nonce account for a command sending one transaction");
+            };
+            let nonce = DurableNonce {
+                account,
+                authority: nonce_authority.clone(),
+            };
+            (Some(nonce), None)
+        }
+        Command::Withdraw { .. } | Command::Transfer { .. } | Command::Pay { .. } => {
+            let nonces = DurableNonces::new(
+                cli.nonce.clone(),
+                nonce_authority.clone(),
+                program_client.clone(),
+                fee_payer.clone(),
+                &compute_budget,
+            );
+            (None, Some(nonces))
+        }
+        _ => bail!(
+            "--nonce only applies to commands sending a single transaction and to withdraw, transfer and pay"
+        ),
+    };
+    //Large verifications are left on a recent blockhash, which expires before a file comes back
+    if nonces.is_some() && cli.sign_offline.is_some() {
+        bail!("--sign-offline only applies to commands sending a single transaction");
+    }
+    //Only packed transactions are sent as v0 transactions
+    let lookup_table = match cli.lookup_table {
+        Some(address) => Some(
+            LookupTable::load(
+                rpc_client.clone(),
+                sender.clone(),
+                fee_payer.clone(),
+                &compute_budget,
+                &address,
+            )
+            .await?,
+        ),
+        None => None,
+    };
+    let bundle = match &cli.jito {
+        Some(block_engine_url) => Some(
+            JitoBundle::connect(
+                block_engine_url,
+                cli.jito_tip,
+                rpc_client.clone(),
+                sender.clone(),
+                fee_payer.clone(),
+            )
+            .await?,
+        ),
+        None => None,
+    };
+    info!("Owner public key: {}", owner.pubkey());
+    info!("Fee payer public key: {}", fee_payer.pubkey());
+
+    let token_account = cli.token_account;
+    let keyring = KeyringAccess {
+        path: cli.keyring.clone().or_else(keyring::Keyring::default_path),
+        owner: owner.pubkey(),
+    };
+    let keystore = cli
+        .keystore
+        .map(|dir| {
+            dir.or_else(keystore::Keystore::default_path)
+                .map(|dir| KeystoreAccess {
+                    keystore: keystore::Keystore::new(dir),
+                    passphrase: OnceCell::new(),
+                })
+                .ok_or_else(|| anyhow!("Unable to locate the keystore; pass --keystore=<DIR>"))
+        })
+        .transpose()?;
+    let records = Records::open(cli.storage, owner.pubkey()).await?;
+    //Non-zero when the command succeeded but an account needs attention
+    let mut exit_code = ExitCode::SUCCESS;
+    match cli.command {
+        Command::CreateMint {
+            decimals,
+            auditor,
+            auditor_keypair,
+            new_auditor_keypair,
+            manual_approval,
+            confidential_mint_burn,
+            transfer_fee_basis_points,
+            maximum_fee,
+            withheld_keypair,
+        } => {
+            let auditor_elgamal_pubkey =
+                resolve_auditor(auditor, auditor_keypair, new_auditor_keypair)?;
+            let decimals = decimals.unwrap_or(config.decimals());
+            let transfer_fee = match (transfer_fee_basis_points, maximum_fee, withheld_keypair) {
+                (Some(basis_points), Some(maximum_fee), Some(path)) => {
+                    let withheld_keypair = if path.exists() {
+                        utils::load_elgamal_keypair(&path)?
+                    } else {
+                        let keypair = utils::create_elgamal_keypair(&path)?;
+                        info!("Withheld fee ElGamal keypair written to {}", path.display());
+                        keypair
+                    };
+                    Some(mint::TransferFeeOptions {
+                        basis_points,
+                        maximum_fee: maximum_fee.to_base_units(decimals)?,
+                        withdraw_withheld_authority_elgamal_pubkey: *withheld_keypair.pubkey(),
+                    })
+                }
+                _ => None,
+            };
+            let options = mint::MintOptions {
+                decimals,
+                auditor_elgamal_pubkey,
+                auto_approve_new_accounts: !manual_approval,
+                transfer_fee,
+                confidential_mint_burn,
+            };
+            let created = mint::initialize_mint(
+                program_client,
+                fee_payer,
+                mint_authority.as_ref(),
+                &options,
+                &compute_budget,
+            )
+            .await?;
+            info!("Mint creation transaction signature: {}", created.signature);
+            info!("Mint Account public key: {}", created.mint_keypair.pubkey());
+            records
+                .record(Record::Mint {
+                    mint: created.mint_keypair.pubkey(),
+                    authority: mint_authority.pubkey(),
+                    decimals: options.decimals,
+                    signature: created.signature,
+                })
+                .await;
+        }
+        Command::ConfigureAccount {
+            mint,
+            registry,
+            wallet,
+            account_keypair,
+            symbol,
+        } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let (token, _decimals) =
+                mint::load_token(program_client, fee_payer.clone(), &mint, &compute_budget).await?;
+            if let Some(path) = account_keypair {
+                let account_keypair = load_signer(&path, "account", &signers)?;
+                let configured = mint::create_configure_account(
+                    &token,
+                    &fee_payer.pubkey(),
+                    &owner,
+                    account_keypair.as_ref(),
+                )
+                .await?;
+                print_configured(&configured);
+                keyring
+                    .record(
+                        &token,
+                        &owner.pubkey(),
+                        &configured.ata,
+                        configured.elgamal_keypair.pubkey(),
+                        symbol,
+                    )
+                    .await;
+                records
+                    .record(Record::Account {
+                        owner: owner.pubkey(),
+                        mint,
+                        account: configured.ata,
+                        elgamal_pubkey: configured.elgamal_keypair.pubkey(),
+                        signature: configured.signature,
+                    })
+                    .await;
+                if let Some(keystore) = &keystore {
+                    keystore.save(
+                        &owner,
+                        &mint,
+                        &configured.ata,
+                        &configured.elgamal_keypair,
+                        &configured.aes_key,
+                    )?;
+                }
+                return Ok(exit_code);
+            }
+            if !registry {
+                let configured =
+                    mint::create_configure_ata(&token, &fee_payer.pubkey(), &owner).await?;
+                print_configured(&configured);
+                keyring
+                    .record(
+                        &token,
+                        &owner.pubkey(),
+                        &configured.ata,
+                        configured.elgamal_keypair.pubkey(),
+                        symbol,
+                    )
+                    .await;
+                records
+                    .record(Record::Account {
+                        owner: owner.pubkey(),
+                        mint,
+                        account: configured.ata,
+                        elgamal_pubkey: configured.elgamal_keypair.pubkey(),
+                        signature: configured.signature,
+                    })
+                    .await;
+                if let Some(keystore) = &keystore {
+                    keystore.save(
+                        &owner,
+                        &mint,
+                        &configured.ata,
+                        &configured.elgamal_keypair,
+                        &configured.aes_key,
+                    )?;
+                }
+                return Ok(exit_code);
+            }
+            let wallet = match wallet {
+                Some(wallet) => wallet,
+                None => {
+                    if !owner.multisig_signers().is_empty() {
+                        return Err(anyhow!("A multisig cannot own an ElGamal registry"));
+                    }
+                    let registered =
+                        registry::get_registered_elgamal_pubkey(&token, &owner.pubkey()).await?;
+                    if registered.is_none() {
+                        let owner_signer = owner.key_signer().as_ref();
+                        let elgamal_keypair = registry::derive_registry_keypair(owner_signer)?;
+                        let signature = registry::create_elgamal_registry(
+                            &token,
+                            &fee_payer.pubkey(),
+                            owner_signer,
+                            &elgamal_keypair,
+                        )
+                        .await?;
+                        info!(
+                            "ElGamal registry creation transaction signature: {}",
+                            signature
+                        );
+                    }
+                    owner.pubkey()
+                }
+            };
+            let (ata, signature) =
+                registry::configure_ata_with_registry(&token, &fee_payer.pubkey(), &wallet).await?;
+            info!(
+                "Confidential transfer account configuration transaction signature: {}",
+                signature
+            );
+            info!(
+                "Associated token account configured from the ElGamal registry of {}: {}",
+                wallet, ata
+            );
+            let elgamal_pubkey = transfer::get_elgamal_pubkey(&token, &ata).await?;
+            keyring
+                .record(&token, &wallet, &ata, &elgamal_pubkey, symbol)
+                .await;
+            records
+                .record(Record::Account {
+                    owner: wallet,
+                    mint,
+                    account: ata,
+                    elgamal_pubkey: &elgamal_pubkey,
+                    signature: Some(signature),
+                })
+                .await;
+        }
+        Command::UpdateMint {
+            mint,
+            auto_approve,
+            auditor,
+            auditor_keypair,
+            new_auditor_keypair,
+            remove_auditor,
+        } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let auditor_elgamal_pubkey = if remove_auditor {
+                Some(None)
+            } else {
+                resolve_auditor(auditor, auditor_keypair, new_auditor_keypair)?.map(Some)
+            };
+            if auto_approve.is_none() && auditor_elgamal_pubkey.is_none() {
+                bail!("Nothing to update: pass --auto-approve and/or an auditor option");
+            }
+            let (token, _decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            let token = with_nonce(&rpc_client, nonce.as_ref(), token).await?;
+            let updated = mint::update_mint(
+                &token,
+                mint_authority,
+                mint::MintUpdate {
+                    auto_approve_new_accounts: auto_approve,
+                    auditor_elgamal_pubkey,
+                },
+            )
+            .await?;
+            info!("Update mint transaction signature: {}", updated.signature);
+            info!(
+                "Auto-approve new accounts: {}",
+                updated.auto_approve_new_accounts
+            );
+            info!(
+                "Auditor ElGamal pubkey: {}",
+                display_option(updated.auditor_elgamal_pubkey)
+            );
+        }
+        Command::ListAccounts if records.is_open() => records.print_accounts().await?,
+        Command::ListAccounts => {
+            let keyring = keyring.load()?;
+            let accounts = keyring.accounts(&owner.pubkey());
+            info!(
+                "Accounts of {} in {}: {}",
+                owner.pubkey(),
+                keyring.path().display(),
+                accounts.len()
+            );
+            for entry in accounts {
+                println!(
+                    "{:<10} {} {} {}",
+                    entry.symbol.as_deref().unwrap_or("-"),
+                    entry.mint,
+                    entry.account,
+                    entry.elgamal_pubkey
+                );
+            }
+        }
+        Command::ApproveAccount {
+            mint,
+            all,
+            mut accounts,
+        } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            if accounts.is_empty() {
+                let unapproved = approve::list_unapproved_accounts(&rpc_client, &mint).await?;
+                info!("Unapproved accounts: {}", unapproved.len());
+                for account in &unapproved {
+                    info!("  {}", account);
+                }
+                if !all {
+                    return Ok(exit_code);
+                }
+                accounts = unapproved;
+            }
+            //The confidential transfer authority is the mint authority (see create-mint)
+            let (token, _decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            for account in accounts {
+                let signature =
+                    approve::approve_account(&token, mint_authority.clone(), &account).await?;
+                info!("Approved {}: {}", account, signature);
+            }
+        }
+        Command::MintTo { mint, amount } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let (token, decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            let amount = amount.to_base_units(decimals)?;
+            let token = with_nonce(&rpc_client, nonce.as_ref(), token).await?;
+            let ata_pubkey = owner_account(token_account, &owner, &mint);
+            let signature = mint::mint_to(&token, mint_authority, &ata_pubkey, amount).await?;
+            info!("Minted tokens transaction signature: {}", signature);
+        }
+        Command::ConfidentialMint { mint, amount } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let (token, decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            let amount = amount.to_base_units(decimals)?;
+            let ata_pubkey = owner_account(token_account, &owner, &mint);
+            let proof_authority = proof_authority.unwrap_or_else(|| mint_authority.clone());
+            let result = mint_burn::confidential_mint(
+                &token,
+                mint_authority.clone(),
+                &proof_authority,
+                &ata_pubkey,
+                amount,
+            )
+            .await?;
+            print_mint_burn(&result, "mint");
+            let supply =
+                mint_burn::get_confidential_supply(&token, mint_authority.as_ref()).await?;
+            info!("Confidential supply: {}", amount::format(supply, decimals));
+        }
+        Command::ConfidentialBurn { mint, amount } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let (token, decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            let amount = amount.to_base_units(decimals)?;
+            let ata_pubkey = owner_account(token_account, &owner, &mint);
+            let (elgamal_keypair, aes_key) =
+                account_keys(&token, &owner, &ata_pubkey, &keystore).await?;
+            let result = mint_burn::confidential_burn(
+                &token,
+                &owner,
+                &ata_pubkey,
+                amount,
+                &elgamal_keypair,
+                &aes_key,
+            )
+            .await?;
+            print_mint_burn(&result, "burn");
+        }
+        Command::Deposit {
+            mint,
+            amount,
+            memo,
+            and_apply,
+        } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let (token, decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            let amount = amount.to_base_units(decimals)?;
+            let token = with_nonce(&rpc_client, nonce.as_ref(), token).await?;
+            let ata_pubkey = owner_account(token_account, &owner, &mint);
+            if and_apply {
+                let (elgamal_keypair, aes_key) =
+                    account_keys(&token, &owner, &ata_pubkey, &keystore).await?;
+                let signature = deposit::deposit_and_apply(
+                    &token,
+                    &owner,
+                    &ata_pubkey,
+                    amount,
+                    decimals,
+                    &elgamal_keypair,
+                    &aes_key,
+                    memo.as_deref(),
+                )
+                .await?;
+                info!("Deposit and apply transaction signature: {}", signature);
+                records
+                    .record(Record::Deposit {
+                        mint,
+                        account: ata_pubkey,
+                        amount,
+                        signature,
+                    })
+                    .await;
+            } else {
+                let signature = deposit::deposit(
+                    &token,
+                    &owner,
+                    &ata_pubkey,
+                    amount,
+                    decimals,
+                    memo.as_deref(),
+                )
+                .await?;
+                info!(
+                    "Confidential transfer deposit transaction signature: {}",
+                    signature
+                );
+                records
+                    .record(Record::Deposit {
+                        mint,
+                        account: ata_pubkey,
+                        amount,
+                        signature,
+                    })
+                    .await;
+            }
+        }
+        Command::Apply { mint } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let (token, _decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            let token = with_nonce(&rpc_client, nonce.as_ref(), token).await?;
+            let ata_pubkey = owner_account(token_account, &owner, &mint);
+            let (elgamal_keypair, aes_key) =
+                account_keys(&token, &owner, &ata_pubkey, &keystore).await?;
+            let signature = deposit::apply_pending_balance(
+                &token,
+                &owner,
+                &ata_pubkey,
+                &elgamal_keypair,
+                &aes_key,
+            )
+            .await?;
+            info!("Apply pending balance transaction signature: {}", signature);
+            records
+                .record(Record::Apply {
+                    mint,
+                    account: ata_pubkey,
+                    signature,
+                })
+                .await;
+        }
+        Command::Withdraw {
+            mint,
+            amount,
+            all,
+            memo,
+            pack,
+        } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let packing = packing(
+                pack,
+                &fee_payer,
+                &compute_budget,
+                lookup_table.as_ref(),
+                bundle.as_ref(),
+                nonces.as_ref(),
+            )?;
+            let (token, decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            let ata_pubkey = owner_account(token_account, &owner, &mint);
+            let (elgamal_keypair, aes_key) =
+                account_keys(&token, &owner, &ata_pubkey, &keystore).await?;
+            let (started, sent_before) = (Instant::now(), sender.timings());
+            let result = if all {
+                withdraw::withdraw_all(
+                    &token,
+                    &owner,
+                    &ata_pubkey,
+                    decimals,
+                    &elgamal_keypair,
+                    &aes_key,
+                    memo.as_deref(),
+                    packing,
+                )
+                .await?
+            } else {
+                withdraw::withdraw(
+                    &token,
+                    &owner,
+                    &ata_pubkey,
+                    amount
+                        .ok_or_else(|| anyhow!("No amount given"))?
+                        .to_base_units(decimals)?,
+                    decimals,
+                    &elgamal_keypair,
+                    &aes_key,
+                    memo.as_deref(),
+                    packing,
+                )
+                .await?
+            };
+            let timings = StageTimings::split(
+                started.elapsed(),
+                result.proof_generation_time,
+                sender.timings().since(&sent_before),
+            );
+            info!(
+                "Withdrawn amount: {}",
+                amount::format(result.amount, decimals)
+            );
+            print_withdraw(&result);
+            print_timings(&timings);
+            records
+                .record(Record::Withdraw {
+                    mint,
+                    account: ata_pubkey,
+                    result: &result,
+                })
+                .await;
+            if cli.json {
+                print_json(&withdraw_json(&result, &timings))?;
+            }
+        }
+        Command::Transfer {
+            mint,
+            recipient,
+            recipient_keypair,
+            amount,
+            memo,
+            pack,
+        } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let (token, decimals) =
+                mint::load_token(program_client, fee_payer.clone(), &mint, &compute_budget).await?;
+            let amount = amount.to_base_units(decimals)?;
+            let recipient_signer = recipient_keypair
+                .map(|path| load_signer(&path, "recipient", &signers))
+                .transpose()?;
+            let recipient = match &recipient_signer {
+                Some(signer) => signer.pubkey(),
+                None => recipient.ok_or_else(|| anyhow!("No recipient given"))?,
+            };
+            let source_ata = owner_account(token_account, &owner, &mint);
+            let destination_ata = mint::get_ata(&recipient, &mint);
+            //With the recipient keypair at hand, configure its account first if needed
+            if let Some(recipient_signer) = &recipient_signer {
+                let configured = mint::create_configure_ata(
+                    &token,
+                    &fee_payer.pubkey(),
+                    &Authority::single(recipient_signer.clone()),
+                )
+                .await?;
+                if configured.signature.is_some() {
+                    print_configured(&configured);
+                }
+            }
+            //Without the recipient keypair nobody can make room, so refuse a full recipient
+            if recipient_signer.is_none() {
+                let pending_credits =
+                    balance::get_pending_credits(&token, &destination_ata).await?;
+                if pending_credits.urgency() == balance::ApplyUrgency::Now {
+                    bail!(
+                        "The pending credit counter of {} is at its maximum of {}; the recipient must apply its pending balance first",
+                        destination_ata,
+                        pending_credits.maximum
+                    );
+                }
+                exit_code = check_pending_credits(&pending_credits, &destination_ata);
+            }
+            let (elgamal_keypair, aes_key) =
+                account_keys(&token, &owner, &source_ata, &keystore).await?;
+            let (started, sent_before) = (Instant::now(), sender.timings());
+            let result = transfer::transfer(
+                &token,
+                &owner,
+                &source_ata,
+                &destination_ata,
+                amount,
+                &elgamal_keypair,
+                &aes_key,
+                memo.as_deref(),
+                packing(
+                    pack,
+                    &fee_payer,
+                    &compute_budget,
+                    lookup_table.as_ref(),
+                    bundle.as_ref(),
+                    nonces.as_ref(),
+                )?,
+            )
+            .await?;
+            let timings = StageTimings::split(
+                started.elapsed(),
+                result.proof_generation_time,
+                sender.timings().since(&sent_before),
+            );
+            print_transfer(&result, decimals);
+            print_timings(&timings);
+            records
+                .record(Record::Transfer {
+                    mint,
+                    source: source_ata,
+                    destination: destination_ata,
+                    amount,
+                    result: &result,
+                })
+                .await;
+            if cli.json {
+                print_json(&transfer_json(&destination_ata, amount, &result, &timings))?;
+            }
+            //Credit the transfer to the recipient's available balance
+            if let Some(recipient_signer) = recipient_signer {
+                let (elgamal_keypair, aes_key) = mint::load_keys(
+                    &token,
+                    recipient_signer.as_ref(),
+                    &seed_message,
+                    &destination_ata,
+                )
+                .await?;
+                let signature = deposit::apply_pending_balance(
+                    &token,
+                    &Authority::single(recipient_signer),
+                    &destination_ata,
+                    &elgamal_keypair,
+                    &aes_key,
+                )
+                .await?;
+                info!(
+                    "Recipient apply pending balance transaction signature: {}",
+                    signature
+                );
+            }
+        }
+        Command::TransferBatch {
+            mint,
+            file,
+            concurrency,
+        } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let contents = std::fs::read_to_string(&file)
+                .with_context(|| format!("Unable to read {}", file.display()))?;
+            let (token, decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            let transfers = batch::parse_transfer_list(&contents, decimals)?;
+            let source_ata = owner_account(token_account, &owner, &mint);
+            let (elgamal_keypair, aes_key) =
+                account_keys(&token, &owner, &source_ata, &keystore).await?;
+            let (started, sent_before) = (Instant::now(), sender.timings());
+            let outcomes = batch::transfer_batch(
+                &token,
+                &owner,
+                &source_ata,
+                &transfers,
+                &elgamal_keypair,
+                &aes_key,
+                concurrency,
+            )
+            .await?;
+            let mut failures = 0;
+            for outcome in &outcomes {
+                match &outcome.result {
+                    Ok(result) => {
+                        info!(
+                            "Transferred {} to {}: {} (proofs generated in {:?})",
+                            amount::format(outcome.transfer.amount, decimals),
+                            outcome.transfer.recipient,
+                            result.transfer_signature,
+                            result.proof_generation_time
+                        );
+                        records
+                            .record(Record::Transfer {
+                                mint,
+                                source: source_ata,
+                                destination: mint::get_ata(&outcome.transfer.recipient, &mint),
+                                amount: outcome.transfer.amount,
+                                result,
+                            })
+                            .await;
+                    }
+                    Err(err) => {
+                        failures += 1;
+                        warn!(
+                            "Transfer of {} to {} failed: {:#}",
+                            amount::format(outcome.transfer.amount, decimals),
+                            outcome.transfer.recipient,
+                            err
+                        );
+                    }
+                }
+            }
+            info!(
+                "Batch transfers succeeded: {}, failed: {}",
+                outcomes.len() - failures,
+                failures
+            );
+            //Proofs are generated while earlier transfers are sent, so the stages overlap
+            let timings = StageTimings::split(
+                started.elapsed(),
+                outcomes
+                    .iter()
+                    .filter_map(|outcome| outcome.result.as_ref().ok())
+                    .map(|result| result.proof_generation_time)
+                    .sum(),
+                sender.timings().since(&sent_before),
+            );
+            print_timings(&timings);
+            if cli.json {
+                print_json(&batch_json(&outcomes, &timings))?;
+            }
+            if failures > 0 {
+                bail!("{failures} of {} transfers failed", outcomes.len());
+            }
+        }
+        Command::Payroll {
+            mint,
+            file,
+            results,
+            concurrency,
+            skip_invalid,
+        } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let contents = std::fs::read_to_string(&file)
+                .with_context(|| format!("Unable to read {}", file.display()))?;
+            //A re-run updates the results it reads
+            let results = results.unwrap_or_else(|| {
+                if file.to_string_lossy().ends_with(".results.csv") {
+                    file.clone()
+                } else {
+                    file.with_extension("results.csv")
+                }
+            });
+            let (token, decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            let mut entries = payroll::parse_payroll(&contents, decimals)?;
+            let source_ata = owner_account(token_account, &owner, &mint);
+            let (elgamal_keypair, aes_key) =
+                account_keys(&token, &owner, &source_ata, &keystore).await?;
+            let write_results = |entries: &[payroll::PayrollEntry]| {
+                std::fs::write(&results, payroll::write_results(entries, decimals))
+                    .with_context(|| format!("Unable to write {}", results.display()))
+            };
+            let invalid = payroll::validate_recipients(&token, &mut entries, concurrency).await?;
+            let unpaid = |entries: &[payroll::PayrollEntry]| {
+                entries
+                    .iter()
+                    .filter(|entry| {
+                        matches!(
+                            entry.status,
+                            payroll::PaymentStatus::Pending | payroll::PaymentStatus::Failed
+                        )
+                    })
+                    .map(|entry| entry.amount)
+                    .sum::<u64>()
+            };
+            info!(
+                "Payroll of {} payments: {} already paid, {} invalid, {} due",
+                entries.len(),
+                entries
+                    .iter()
+                    .filter(|entry| entry.status == payroll::PaymentStatus::Paid)
+                    .count(),
+                invalid,
+                amount::format(unpaid(&entries), decimals)
+            );
+            if invalid > 0 {
+                for entry in &entries {
+                    if let Some(error) = &entry.error {
+                        warn!("Recipient {} cannot be paid: {}", entry.recipient, error);
+                    }
+                }
+                if !skip_invalid {
+                    write_results(&entries)?;
+                    bail!(
+                        "{invalid} recipients cannot be paid, nothing was paid; fix them and re-run {}, or pass --skip-invalid",
+                        results.display()
+                    );
+                }
+            }
+            let available = balance::get_available_balance(&token, &source_ata, &aes_key).await?;
+            if unpaid(&entries) > available {
+                bail!(
+                    "The available balance of {} is {}, below the {} due",
+                    source_ata,
+                    amount::format(available, decimals),
+                    amount::format(unpaid(&entries), decimals)
+                );
+            }
+            let (started, sent_before) = (Instant::now(), sender.timings());
+            let paid = payroll::pay(
+                &token,
+                &owner,
+                &source_ata,
+                &mut entries,
+                &elgamal_keypair,
+                &aes_key,
+                concurrency,
+            )
+            .await;
+            //Written even when the batch could not start, so a re-run has the latest state
+            write_results(&entries)?;
+            let paid = paid?;
+            for (transfer, result) in &paid {
+                records
+                    .record(Record::Transfer {
+                        mint,
+                        source: source_ata,
+                        destination: mint::get_ata(&transfer.recipient, &mint),
+                        amount: transfer.amount,
+                        result,
+                    })
+                    .await;
+            }
+            let timings = StageTimings::split(
+                started.elapsed(),
+                paid.iter()
+                    .map(|(_, result)| result.proof_generation_time)
+                    .sum(),
+                sender.timings().since(&sent_before),
+            );
+            print_timings(&timings);
+            let failed = entries
+                .iter()
+                .filter(|entry| entry.status == payroll::PaymentStatus::Failed)
+                .count();
+            info!(
+                "Payroll payments made: {}, failed: {}; results written to {}",
+                paid.len(),
+                failed,
+                results.display()
+            );
+            if failed > 0 {
+                bail!(
+                    "{failed} payments failed; re-run {} to pay them",
+                    results.display()
+                );
+            }
+        }
+        Command::RunSchedules { once } => {
+            let payments = config.schedules()?;
+            if payments.is_empty() {
+                bail!("No schedules: add [[schedule]] tables to the config file");
+            }
+            let mints = payments
+                .iter()
+                .map(|payment| resolve_mint(payment.mint.clone(), &config, &keyring))
+                .collect::<Result<Vec<_>>>()?;
+            //Tokens and keys are loaded upfront, so a misconfigured payment fails at start
+            let mut accounts = vec![];
+            for (index, mint) in mints.iter().enumerate() {
+                if mints[..index].contains(mint) {
+                    continue;
+                }
+                let (token, decimals) = mint::load_token(
+                    program_client.clone(),
+                    fee_payer.clone(),
+                    mint,
+                    &compute_budget,
+                )
+                .await?;
+                let source_ata = owner_account(token_account, &owner, mint);
+                let (elgamal_keypair, aes_key) =
+                    account_keys(&token, &owner, &source_ata, &keystore).await?;
+                accounts.push((*mint, decimals, token, source_ata, elgamal_keypair, aes_key));
+            }
+            let decimals = |mint: &Pubkey| {
+                accounts
+                    .iter()
+                    .find(|(loaded, ..)| loaded == mint)
+                    .map(|(_, decimals, ..)| *decimals)
+                    .expect("Every mint is loaded")
+            };
+            let amounts = payments
+                .iter()
+                .zip(&mints)
+                .map(|(payment, mint)| {
+                    payment
+                        .amount
+                        .to_base_units(decimals(mint))
+                        .with_context(|| format!("Invalid schedule `{}`", payment.name))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let mut state = load_schedule_state()?;
+            info!(
+                "Running {} scheduled payments, state in {}",
+                payments.len(),
+                state.path().display()
+            );
+            loop {
+                let now = schedule::now();
+                for ((payment, mint), &amount) in payments.iter().zip(&mints).zip(&amounts) {
+                    let (_, decimals, token, source_ata, elgamal_keypair, aes_key) = accounts
+                        .iter()
+                        .find(|(loaded, ..)| loaded == mint)
+                        .expect("Every mint is loaded");
+                    let due = state.due(payment, now);
+                    if due.dropped > 0 {
+                        info!(
+                            "Schedule {}: {} missed occurrences dropped",
+                            payment.name, due.dropped
+                        );
+                    }
+                    let destination_ata = mint::get_ata(&payment.recipient, mint);
+                    let mut paid_all = true;
+                    for &occurrence in &due.occurrences {
+                        info!(
+                            "Schedule {}: paying the occurrence of {}",
+                            payment.name,
+                            schedule::format_time(occurrence)
+                        );
+                        let result = match pay_scheduled(
+                            token,
+                            &owner,
+                            source_ata,
+                            payment,
+                            amount,
+                            *decimals,
+                            elgamal_keypair,
+                            aes_key,
+                        )
+                        .await
+                        {
+                            Ok(result) => result,
+                            Err(err) => {
+                                warn!("Schedule {}: {:#}; retrying later", payment.name, err);
+                                paid_all = false;
+                                break;
+                            }
+                        };
+                        //Saved at once, so a restart does not pay the occurrence again
+                        state.record(&payment.name, occurrence, &result.transfer_signature);
+                        state.save()?;
+                        print_transfer(&result, *decimals);
+                        records
+                            .record(Record::Transfer {
+                                mint: *mint,
+                                source: *source_ata,
+                                destination: destination_ata,
+                                amount,
+                                result: &result,
+                            })
+                            .await;
+                        if cli.json {
+                            print_json(&json!({
+                                "schedule": payment.name,
+                                "occurrence": occurrence,
+                                "destination": destination_ata.to_string(),
+                                "amount": amount,
+                                "signature": result.transfer_signature.to_string(),
+                            }))?;
+                        }
+                    }
+                    if paid_all {
+                        state.advance(&payment.name, due.through);
+                    }
+                }
+                state.save()?;
+                if once {
+                    break;
+                }
+                let next = payments
+                    .iter()
+                    .filter_map(|payment| payment.cron.next_after(now))
+                    .min()
+                    .unwrap_or(u64::MAX);
+                let wait = next
+                    .saturating_sub(schedule::now())
+                    .clamp(1, SCHEDULE_MAX_WAIT_SECS);
+                tokio::time::sleep(Duration::from_secs(wait)).await;
+            }
+        }
+        Command::ListSchedules => {
+            let payments = config.schedules()?;
+            let state = load_schedule_state()?;
+            let now = schedule::now();
+            let never = || "never".to_string();
+            if cli.json {
+                let schedules = payments
+                    .iter()
+                    .map(|payment| {
+                        let run = state.run(&payment.name);
+                        json!({
+                            "name": payment.name,
+                            "cron": payment.cron.to_string(),
+                            "recipient": payment.recipient.to_string(),
+                            "amount": payment.amount.to_string(),
+                            "mint": payment.mint.as_ref().map(ToString::to_string),
+                            "catch_up": payment.catch_up.as_str(),
+                            "last_occurrence": run.and_then(|run| run.last_occurrence),
+                            "last_signature": run.and_then(|run| run.last_signature.clone()),
+                            "next_occurrence": payment.cron.next_after(now),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                print_json(&json!(schedules))?;
+            }
+            for payment in &payments {
+                let run = state.run(&payment.name);
+                info!(
+                    "{}: {} to {} at `{}` (catch-up: {}), last paid {}, next {}",
+                    payment.name,
+                    payment.amount,
+                    payment.recipient,
+                    payment.cron,
+                    payment.catch_up,
+                    run.and_then(|run| run.last_occurrence)
+                        .map_or_else(never, schedule::format_time),
+                    payment
+                        .cron
+                        .next_after(now)
+                        .map_or_else(never, schedule::format_time)
+                );
+            }
+        }
+        Command::RequestPayment {
+            mint,
+            amount,
+            wait,
+            timeout,
+        } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let (token, decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            let amount = amount.to_base_units(decimals)?;
+            let ata_pubkey = owner_account(token_account, &owner, &mint);
+            //Refuse requests nobody could pay
+            transfer::get_elgamal_pubkey(&token, &ata_pubkey).await?;
+            let request = payment::PaymentRequest::new(ata_pubkey, mint, amount, decimals);
+            info!(
+                "Payment request of {} to {}, reference {}:",
+                request.amount, ata_pubkey, request.reference
+            );
+            //The URI alone goes to stdout, so it can be piped
+            if cli.json {
+                info!("{}", request);
+            } else {
+                println!("{request}");
+                print_qr(&request.to_string())?;
+            }
+            let signature = if wait {
+                info!("Waiting for the payment");
+                let signature = payment::wait_for_payment(
+                    &rpc_client,
+                    &request,
+                    PAYMENT_POLL_INTERVAL,
+                    Duration::from_secs(timeout),
+                )
+                .await?;
+                info!("Paid by transaction {}", signature);
+                Some(signature)
+            } else {
+                None
+            };
+            if cli.json {
+                print_json(&json!({
+                    "uri": request.to_string(),
+                    "account": ata_pubkey.to_string(),
+                    "mint": mint.to_string(),
+                    "amount": amount,
+                    "reference": request.reference.to_string(),
+                    "signature": signature.map(|signature| signature.to_string()),
+                }))?;
+            }
+        }
+        Command::Pay { request, pack } => {
+            let (token, decimals) = mint::load_token(
+                program_client,
+                fee_payer.clone(),
+                &request.mint,
+                &compute_budget,
+            )
+            .await?;
+            let amount = request.base_units(decimals)?;
+            let source_ata = owner_account(token_account, &owner, &request.mint);
+            let destination_ata = request.recipient;
+            let pending_credits = balance::get_pending_credits(&token, &destination_ata).await?;
+            if pending_credits.urgency() == balance::ApplyUrgency::Now {
+                bail!(
+                    "The pending credit counter of {} is at its maximum of {}; the recipient must apply its pending balance first",
+                    destination_ata,
+                    pending_credits.maximum
+                );
+            }
+            let (elgamal_keypair, aes_key) =
+                account_keys(&token, &owner, &source_ata, &keystore).await?;
+            info!(
+                "Paying {} to {}, reference {}",
+                request.amount, destination_ata, request.reference
+            );
+            let (started, sent_before) = (Instant::now(), sender.timings());
+            let result = transfer::transfer(
+                &token,
+                &owner,
+                &source_ata,
+                &destination_ata,
+                amount,
+                &elgamal_keypair,
+                &aes_key,
+                Some(&request.memo()),
+                packing(
+                    pack,
+                    &fee_payer,
+                    &compute_budget,
+                    lookup_table.as_ref(),
+                    bundle.as_ref(),
+                    nonces.as_ref(),
+                )?,
+            )
+            .await?;
+            let timings = StageTimings::split(
+                started.elapsed(),
+                result.proof_generation_time,
+                sender.timings().since(&sent_before),
+            );
+            print_transfer(&result, decimals);
+            print_timings(&timings);
+            records
+                .record(Record::Transfer {
+                    mint: request.mint,
+                    source: source_ata,
+                    destination: destination_ata,
+                    amount,
+                    result: &result,
+                })
+                .await;
+            if cli.json {
+                print_json(&transfer_json(&destination_ata, amount, &result, &timings))?;
+            }
+        }
+        Command::AwaitPayment { request, timeout } => {
+            info!(
+                "Waiting for the payment of {} to {}, reference {}",
+                request.amount, request.recipient, request.reference
+            );
+            let signature = payment::wait_for_payment(
+                &rpc_client,
+                &request,
+                PAYMENT_POLL_INTERVAL,
+                Duration::from_secs(timeout),
+            )
+            .await?;
+            info!("Paid by transaction {}", signature);
+            println!("{signature}");
+        }
+        Command::RecipientKey { mint, recipient } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let (token, _decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            let elgamal_pubkey = transfer::get_recipient_elgamal_pubkey(&token, &recipient).await?;
+            info!(
+                "Recipient associated token account: {}",
+                mint::get_ata(&recipient, &mint)
+            );
+            info!("Recipient ElGamal pubkey: {}", elgamal_pubkey);
+            let destination_ata = mint::get_ata(&recipient, &mint);
+            let pending_credits = balance::get_pending_credits(&token, &destination_ata).await?;
+            info!(
+                "Recipient pending credits: {} of {}",
+                pending_credits.counter, pending_credits.maximum
+            );
+            exit_code = check_pending_credits(&pending_credits, &destination_ata);
+        }
+        Command::ExportElgamalPubkey {
+            mint,
+            account,
+            output,
+            qr,
+        } => {
+            let account = match account.or(token_account) {
+                Some(account) => account,
+                None => mint::get_ata(&owner.pubkey(), &resolve_mint(mint, &config, &keyring)?),
+            };
+            let inspection = inspect::inspect_account(program_client.as_ref(), &account).await?;
+            let extension = inspection
+                .confidential_transfer
+                .ok_or_else(|| anyhow!("{account} is not configured for confidential transfers"))?;
+            let elgamal_pubkey = ElGamalPubkey::try_from(extension.elgamal_pubkey)
+                .map_err(|_| anyhow!("Invalid ElGamal pubkey registered for {account}"))?;
+            info!(
+                "ElGamal pubkey of {} (mint {}, owner {}):",
+                account, inspection.mint, inspection.owner
+            );
+            //The key alone goes to stdout, so it can be piped
+            println!("{elgamal_pubkey}");
+            if let Some(path) = output {
+                std::fs::write(&path, format!("{elgamal_pubkey}\n"))
+                    .with_context(|| format!("Unable to write {}", path.display()))?;
+                info!("ElGamal pubkey written to {}", path.display());
+            }
+            if qr {
+                print_qr(&elgamal_pubkey.to_string())?;
+            }
+        }
+        Command::ExportProofInputs {
+            mint,
+            recipient,
+            output,
+        } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let (token, _decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            let account = owner_account(token_account, &owner, &mint);
+            let inputs = proofs::fetch_proof_inputs(&token, &account, recipient.as_ref()).await?;
+            inputs.save(&output)?;
+            info!(
+                "Proof inputs of {} written to {}",
+                account,
+                output.display()
+            );
+        }
+        Command::GenerateProofs {
+            kind,
+            amount,
+            inputs,
+            mint,
+            output,
+        } => {
+            let bundle = match kind {
+                ProofKind::PubkeyValidity => {
+                    let mint = resolve_mint(mint, &config, &keyring)?;
+                    let account = owner_account(token_account, &owner, &mint);
+                    let (elgamal_keypair, _aes_key) =
+                        offline_account_keys(&owner, &mint, &account, None, &keystore)?;
+                    ProofBundle::configure_account(mint, account, &elgamal_keypair)?
+                }
+                ProofKind::Withdraw | ProofKind::Transfer => {
+                    let inputs = ProofInputs::load(
+                        &inputs.ok_or_else(|| anyhow!("No proof inputs given"))?,
+                    )?;
+                    let amount = amount.ok_or_else(|| anyhow!("No amount given"))?;
+                    let (elgamal_keypair, aes_key) = offline_account_keys(
+                        &owner,
+                        &inputs.mint,
+                        &inputs.account,
+                        Some(&inputs.elgamal_pubkey),
+                        &keystore,
+                    )?;
+                    if kind == ProofKind::Withdraw {
+                        ProofBundle::withdraw(&inputs, amount, &elgamal_keypair, &aes_key)?
+                    } else {
+                        ProofBundle::transfer(&inputs, amount, &elgamal_keypair, &aes_key)?
+                    }
+                }
+            };
+            bundle.verify(None)?;
+            bundle.save(&output)?;
+            info!(
+                "{} proofs of {} written to {}",
+                bundle.proofs.len(),
+                bundle.account,
+                output.display()
+            );
+        }
+        Command::VerifyProofs { input, inputs } => {
+            let bundle = ProofBundle::load(&input)?;
+            let registered_elgamal_pubkey = match inputs {
+                Some(inputs) => {
+                    let inputs = ProofInputs::load(&inputs)?;
+                    if inputs.account != bundle.account {
+                        bail!(
+                            "The proof inputs are for {}, but the proofs are for {}",
+                            inputs.account,
+                            bundle.account
+                        );
+                    }
+                    Some(inputs.elgamal_pubkey)
+                }
+                None => None,
+            };
+            bundle.verify(registered_elgamal_pubkey.as_ref())?;
+            info!(
+                "{} proofs of {} verify locally",
+                bundle.proofs.len(),
+                bundle.account
+            );
+        }
+        Command::SubmitProofs { input } => {
+            let bundle = ProofBundle::load(&input)?;
+            let (token, _decimals) = mint::load_token(
+                program_client,
+                fee_payer.clone(),
+                &bundle.mint,
+                &compute_budget,
+            )
+            .await?;
+            let proof_authority = proof_authority.unwrap_or_else(|| fee_payer.clone());
+            let submitted = proofs::submit_proofs(&token, &proof_authority, &bundle).await?;
+            for proof in &submitted {
+                info!(
+                    "{:?} proof verified into {}: {}",
+                    proof.proof_type, proof.account, proof.signature
+                );
+            }
+        }
+        Command::GcProofAccounts { list } => {
+            //Proof accounts are managed by the owner's proof authority, or by the fee payer for
+            //submit-proofs without --proof-authority
+            let mut authorities = vec![owner.proof_authority().clone()];
+            if fee_payer.pubkey() != owner.proof_authority().pubkey() {
+                authorities.push(fee_payer.clone());
+            }
+            let mut reclaimed = 0;
+            for authority in &authorities {
+                let accounts = gc::find_proof_accounts(&rpc_client, &authority.pubkey()).await?;
+                for account in &accounts {
+                    info!(
+                        "{:?} proof account {} of {}: {} SOL",
+                        account.proof_type,
+                        account.address,
+                        account.authority,
+                        amount::format(account.lamports, 9)
+                    );
+                }
+                if list || accounts.is_empty() {
+                    continue;
+                }
+                let closed = gc::close_proof_accounts(
+                    program_client.clone(),
+                    fee_payer.clone(),
+                    &compute_budget,
+                    authority,
+                    &accounts,
+                )
+                .await;
+                for ((address, signature), account) in closed.iter().zip(&accounts) {
+                    match signature {
+                        Ok(signature) => {
+                            reclaimed += account.lamports;
+                            info!("Closed proof account {}: {}", address, signature);
+                        }
+                        Err(err) => warn!("Closing proof account {} failed: {:#}", address, err),
+                    }
+                }
+            }
+            if !list {
+                info!("Reclaimed {} SOL", amount::format(reclaimed, 9));
+            }
+        }
+        Command::CreateNonceAccount { nonce_keypair } => {
+            let nonce_account = match nonce_keypair {
+                Some(path) => load_signer(&path, "nonce account", &signers)?,
+                None => Arc::new(keygen::new_keypair()),
+            };
+            let signature = nonce::create_nonce_account(
+                program_client,
+                fee_payer,
+                &compute_budget,
+                nonce_account.as_ref(),
+                &nonce_authority.pubkey(),
+            )
+            .await?;
+            info!(
+                "Nonce account creation transaction signature: {}",
+                signature
+            );
+            info!("Nonce account public key: {}", nonce_account.pubkey());
+            info!("Nonce authority public key: {}", nonce_authority.pubkey());
+        }
+        Command::CreateLookupTable {
+            mint,
+            accounts,
+            extend,
+        } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let mut accounts = accounts;
+            accounts.insert(0, owner_account(token_account, &owner, &mint));
+            let addresses = lookup_table::lookup_table_addresses(&mint, &accounts);
+            let (address, signatures) = match extend {
+                Some(address) => (
+                    address,
+                    lookup_table::extend_lookup_table(
+                        program_client,
+                        &rpc_client,
+                        fee_payer.clone(),
+                        &compute_budget,
+                        &address,
+                        fee_payer.as_ref(),
+                        &addresses,
+                    )
+                    .await?,
+                ),
+                None => {
+                    lookup_table::create_lookup_table(
+                        program_client,
+                        &rpc_client,
+                        fee_payer.clone(),
+                        &compute_budget,
+                        fee_payer.as_ref(),
+                        &addresses,
+                    )
+                    .await?
+                }
+            };
+            for signature in &signatures {
+                info!("Lookup table transaction signature: {}", signature);
+            }
+            info!("Lookup table address: {}", address);
+        }
+        Command::OpenDepositAddress { mint, user_id } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let (token, _decimals) =
+                mint::load_token(program_client, fee_payer.clone(), &mint, &compute_budget).await?;
+            let (address, configured) = exchange::open_deposit_address(
+                &token,
+                &fee_payer.pubkey(),
+                owner.key_source().as_ref(),
+                &user_id,
+            )
+            .await?;
+            if configured.signature.is_some() {
+                print_configured(&configured);
+            }
+            let mut index = load_deposit_index()?;
+            index.record(&owner.pubkey(), &address);
+            index.save()?;
+            info!(
+                "Deposit address of user {}: {} (wallet {})",
+                user_id,
+                address.account,
+                address.wallet.pubkey()
+            );
+            //The address alone goes to stdout, so it can be piped
+            println!("{}", address.account);
+        }
+        Command::ListDeposits { mint } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let (token, decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            let addresses = deposit_addresses(&owner, &mint)?;
+            let decode_table = DecodeTable::open_default()?;
+            let deposits = exchange::pending_deposits(&token, &addresses, &decode_table).await?;
+            info!(
+                "Pending deposits of {} users: {}",
+                addresses.len(),
+                deposits.len()
+            );
+            for deposit in &deposits {
+                println!(
+                    "{:<20} {} {:>5} {}",
+                    deposit.user_id,
+                    deposit.account,
+                    deposit.credits,
+                    amount::format(deposit.amount, decimals)
+                );
+            }
+            if cli.json {
+                print_json(&json!(
+                    deposits
+                        .iter()
+                        .map(|deposit| json!({
+                            "user_id": deposit.user_id,
+                            "account": deposit.account.to_string(),
+                            "credits": deposit.credits,
+                            "amount": deposit.amount,
+                        }))
+                        .collect::<Vec<_>>()
+                ))?;
+            }
+        }
+        Command::SweepDeposits { mint } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let (token, _decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            let omnibus = owner_account(token_account, &owner, &mint);
+            let addresses = deposit_addresses(&owner, &mint)?;
+            info!(
+                "Sweeping the deposit addresses of {} users to {}",
+                addresses.len(),
+                omnibus
+            );
+            let (mut sweeps, mut failures) = (vec![], 0);
+            for address in &addresses {
+                match exchange::sweep(&token, address, &omnibus).await {
+                    Ok(sweep) => {
+                        if sweep.swept > 0 {
+                            info!(
+                                "User {}: credited {}, swept {}",
+                                sweep.user_id, sweep.credited, sweep.swept
+                            );
+                        }
+                        sweeps.push(sweep);
+                    }
+                    Err(err) => {
+                        failures += 1;
+                        warn!("Sweep of user {} failed: {:#}", address.user_id, err);
+                    }
+                }
+            }
+            //Every sweep is a credit of the omnibus account
+            if sweeps.iter().any(|sweep| sweep.transfer.is_some()) {
+                let (elgamal_keypair, aes_key) =
+                    account_keys(&token, &owner, &omnibus, &keystore).await?;
+                let signature = deposit::apply_pending_balance(
+                    &token,
+                    &owner,
+                    &omnibus,
+                    &elgamal_keypair,
+                    &aes_key,
+                )
+                .await?;
+                info!(
+                    "Omnibus apply pending balance transaction signature: {}",
+                    signature
+                );
+                records
+                    .record(Record::Apply {
+                        mint,
+                        account: omnibus,
+                        signature,
+                    })
+                    .await;
+            }
+            if cli.json {
+                print_json(&sweeps_json(&sweeps))?;
+            }
+            if failures > 0 {
+                bail!("{failures} of {} sweeps failed", addresses.len());
+            }
+        }
+        Command::CreateEscrow {
+            mint,
+            counterparty,
+            output,
+        } => {
+            if output.exists() {
+                bail!("Refusing to overwrite existing file {}", output.display());
+            }
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let (token, _decimals) =
+                mint::load_token(program_client, fee_payer.clone(), &mint, &compute_budget).await?;
+            let escrow =
+                escrow::create_escrow(&token, &fee_payer.pubkey(), &owner, &counterparty).await?;
+            escrow.save(&output)?;
+            info!(
+                "Escrow account {} owned by multisig {}, written to {}",
+                escrow.account,
+                escrow.multisig,
+                output.display()
+            );
+            if cli.json {
+                print_json(&json!({
+                    "account": escrow.account.to_string(),
+                    "multisig": escrow.multisig.to_string(),
+                    "parties": escrow.parties.map(|party| party.to_string()),
+                    "file": output.display().to_string(),
+                }))?;
+            }
+        }
+        Command::FundEscrow { escrow, amount } => {
+            let escrow = escrow::Escrow::load(&escrow)?;
+            let (token, decimals) =
+                mint::load_token(program_client, fee_payer, &escrow.mint, &compute_budget).await?;
+            let amount = amount.to_base_units(decimals)?;
+            //Funds only go to an escrow whose keys the owner holds and whose account checks out
+            escrow_keys(&token, &owner, &keystore, &escrow).await?;
+            let source_ata = owner_account(token_account, &owner, &escrow.mint);
+            let (elgamal_keypair, aes_key) =
+                account_keys(&token, &owner, &source_ata, &keystore).await?;
+            let result = transfer::transfer(
+                &token,
+                &owner,
+                &source_ata,
+                &escrow.account,
+                amount,
+                &elgamal_keypair,
+                &aes_key,
+                None,
+                Packing::Separate,
+            )
+            .await?;
+            print_transfer(&result, decimals);
+            records
+                .record(Record::Transfer {
+                    mint: escrow.mint,
+                    source: source_ata,
+                    destination: escrow.account,
+                    amount,
+                    result: &result,
+                })
+                .await;
+        }
+        Command::EscrowBalance { escrow } => {
+            let escrow = escrow::Escrow::load(&escrow)?;
+            let (token, decimals) =
+                mint::load_token(program_client, fee_payer, &escrow.mint, &compute_budget).await?;
+            let (elgamal_keypair, aes_key) =
+                escrow_keys(&token, &owner, &keystore, &escrow).await?;
+            let balances = balance::get_balances(
+                &token,
+                &escrow.account,
+                &elgamal_keypair,
+                &aes_key,
+                &DecodeTable::open_default()?,
+            )
+            .await?;
+            print_balances(&balances, decimals);
+        }
+        Command::ReleaseEscrow {
+            escrow,
+            to,
+            co_signer,
+            amount,
+        } => {
+            let escrow = escrow::Escrow::load(&escrow)?;
+            let (token, decimals) =
+                mint::load_token(program_client, fee_payer, &escrow.mint, &compute_budget).await?;
+            let amount = amount
+                .map(|amount| amount.to_base_units(decimals))
+                .transpose()?;
+            let (elgamal_keypair, aes_key) =
+                escrow_keys(&token, &owner, &keystore, &escrow).await?;
+            let co_signer = load_signer(&co_signer, "co-signer", &signers)?;
+            let authority =
+                escrow::escrow_authority(&escrow, vec![owner.key_signer().clone(), co_signer])?;
+            let release = escrow::release(
+                &token,
+                &escrow,
+                &authority,
+                &to,
+                amount,
+                &elgamal_keypair,
+                &aes_key,
+            )
+            .await?;
+            if let Some(signature) = release.apply_signature {
+                info!(
+                    "Escrow apply pending balance transaction signature: {}",
+                    signature
+                );
+            }
+            print_transfer(&release.transfer, decimals);
+            let destination = mint::get_ata(&to, &escrow.mint);
+            records
+                .record(Record::Transfer {
+                    mint: escrow.mint,
+                    source: escrow.account,
+                    destination,
+                    amount: release.amount,
+                    result: &release.transfer,
+                })
+                .await;
+            if cli.json {
+                let apply_signature = release
+                    .apply_signature
+                    .map(|signature| signature.to_string());
+                print_json(&json!({
+                    "released": release.amount,
+                    "to": to.to_string(),
+                    "apply_signature": apply_signature,
+                    "transfer_signature": release.transfer.transfer_signature.to_string(),
+                }))?;
+            }
+        }
+        Command::WatchAndApply {
+            mint,
+            threshold,
+            retry_delay,
+            webhook_url,
+            metrics_bind,
+        } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let (token, _decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            let ata_pubkey = owner_account(token_account, &owner, &mint);
+            let (elgamal_keypair, aes_key) =
+                account_keys(&token, &owner, &ata_pubkey, &keystore).await?;
+            let options = watch::WatchOptions {
+                commitment: config.commitment()?,
+                apply_threshold: threshold,
+            };
+            let notifier = match webhook_url {
+                Some(url) => {
+                    let secret = env_var(WEBHOOK_SECRET_ENV).ok_or_else(|| {
+                        anyhow!("Webhook payloads are signed; set {WEBHOOK_SECRET_ENV}")
+                    })?;
+                    info!("Notifying {} of incoming credits", url);
+                    Some(
+                        webhook::Webhook::new(url, secret.as_bytes()).spawn_notifier(
+                            rpc_client.clone(),
+                            ata_pubkey,
+                            mint,
+                        ),
+                    )
+                }
+                None => None,
+            };
+            if let Some(bind) = metrics_bind {
+                metrics::spawn_server(bind).await?;
+            }
+            let ws_url = config.ws_url();
+            info!("Watching {} through {}", ata_pubkey, ws_url);
+            //The callbacks cannot wait for the database, so their records are written beside
+            let (recorded, mut to_record) = tokio::sync::mpsc::unbounded_channel();
+            let recorder = async {
+                while let Some(record) = to_record.recv().await {
+                    records.record(record).await;
+                }
+            };
+            //Runs until interrupted; subscription drops and failed applies are retried
+            let watcher = async {
+                loop {
+                    let watched = match PubsubClient::new(&ws_url).await {
+                        Ok(pubsub_client) => {
+                            watch::watch_and_apply(
+                                &token,
+                                &pubsub_client,
+                                &owner,
+                                &ata_pubkey,
+                                &elgamal_keypair,
+                                &aes_key,
+                                options,
+                                |credits| {
+                                    info!(
+                                        "Received {} credits at slot {}",
+                                        credits.counter_delta, credits.slot
+                                    );
+                                    let _ = recorded.send(Record::Credit {
+                                        mint,
+                                        account: ata_pubkey,
+                                        credits: credits.counter_delta,
+                                    });
+                                    if let Some(notifier) = &notifier {
+                                        let _ = notifier.send(credits);
+                                    }
+                                },
+                                |applied| {
+                                    info!(
+                                        "Applied {} pending credits, transaction signature: {}",
+                                        applied.credits, applied.signature
+                                    );
+                                    let _ = recorded.send(Record::Apply {
+                                        mint,
+                                        account: ata_pubkey,
+                                        signature: applied.signature,
+                                    });
+                                },
+                            )
+                            .await
+                        }
+                        Err(err) => Err(err.into()),
+                    };
+                    if let Err(err) = watched {
+                        warn!("{:#}; retrying in {}s", err, retry_delay);
+                    }
+                    tokio::time::sleep(Duration::from_secs(retry_delay)).await;
+                }
+            };
+            futures::future::join(watcher, recorder).await;
+        }
+        Command::CloseAccount { mint, destination } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let (token, _decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            let ata_pubkey = owner_account(token_account, &owner, &mint);
+            let (elgamal_keypair, aes_key) =
+                account_keys(&token, &owner, &ata_pubkey, &keystore).await?;
+            let destination = destination.unwrap_or_else(|| owner.key_signer().pubkey());
+            let closed = close::empty_and_close_account(
+                &token,
+                &owner,
+                &ata_pubkey,
+                &destination,
+                &elgamal_keypair,
+                &aes_key,
+            )
+            .await?;
+            for signature in &closed.harvest_signatures {
+                info!("Harvest withheld fees transaction signature: {}", signature);
+            }
+            if let Some(signature) = closed.empty_signature {
+                info!("Empty account transaction signature: {}", signature);
+            }
+            info!(
+                "Close account transaction signature: {}",
+                closed.close_signature
+            );
+            info!(
+                "Closed {}, {} lamports refunded to {}",
+                ata_pubkey, closed.lamports, destination
+            );
+            let mut keyring = keyring.load()?;
+            if keyring.remove(&owner.pubkey(), &ata_pubkey) {
+                keyring.save()?;
+                info!("Removed {} from the keyring", ata_pubkey);
+            }
+        }
+        Command::RotateKeys { mint } => {
+            let Some(store) = &keystore else {
+                bail!(
+                    "Rotated keys cannot be re-derived and are stored in the keystore; pass --keystore"
+                );
+            };
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let (token, decimals) =
+                mint::load_token(program_client, fee_payer.clone(), &mint, &compute_budget).await?;
+            let ata_pubkey = owner_account(token_account, &owner, &mint);
+            let (elgamal_keypair, aes_key) =
+                account_keys(&token, &owner, &ata_pubkey, &keystore).await?;
+            //Ask for the passphrase before any tokens move
+            store.passphrase()?;
+            let holding_account = keygen::new_keypair();
+            info!(
+                "Public tokens are parked in {} while {} is recreated",
+                holding_account.pubkey(),
+                ata_pubkey
+            );
+            let rotated = rotate::rotate_keys(
+                &token,
+                &fee_payer.pubkey(),
+                &owner,
+                &ata_pubkey,
+                decimals,
+                (&elgamal_keypair, &aes_key),
+                (keygen::new_elgamal_keypair(), keygen::new_aes_key()),
+                &holding_account,
+                |elgamal_keypair, aes_key| {
+                    store.save(&owner, &mint, &ata_pubkey, elgamal_keypair, aes_key)
+                },
+            )
+            .await?;
+            if let Some(signature) = rotated.apply_signature {
+                info!("Apply pending balance transaction signature: {}", signature);
+            }
+            if let Some(withdraw) = &rotated.withdraw {
+                print_withdraw(withdraw);
+            }
+            if let Some(holding) = &rotated.holding {
+                for signature in &holding.park_signatures {
+                    info!("Park public balance transaction signature: {}", signature);
+                }
+            }
+            for signature in &rotated.closed.harvest_signatures {
+                info!("Harvest withheld fees transaction signature: {}", signature);
+            }
+            if let Some(signature) = rotated.closed.empty_signature {
+                info!("Empty account transaction signature: {}", signature);
+            }
+            info!(
+                "Close account transaction signature: {}",
+                rotated.closed.close_signature
+            );
+            print_configured(&rotated.configured);
+            keyring
+                .record(
+                    &token,
+                    &owner.pubkey(),
+                    &ata_pubkey,
+                    rotated.configured.elgamal_keypair.pubkey(),
+                    None,
+                )
+                .await;
+            records
+                .record(Record::Account {
+                    owner: owner.pubkey(),
+                    mint,
+                    account: ata_pubkey,
+                    elgamal_pubkey: rotated.configured.elgamal_keypair.pubkey(),
+                    signature: rotated.configured.signature,
+                })
+                .await;
+            if let Some(holding) = &rotated.holding {
+                for signature in &holding.return_signatures {
+                    info!("Return public balance transaction signature: {}", signature);
+                }
+            }
+            for signature in &rotated.restore_signatures {
+                info!(
+                    "Restore confidential balance transaction signature: {}",
+                    signature
+                );
+            }
+            info!(
+                "Rotated the keys of {}; new ElGamal pubkey: {}, {} restored to the confidential balance",
+                ata_pubkey,
+                rotated.configured.elgamal_keypair.pubkey(),
+                amount::format(rotated.restored, decimals)
+            );
+        }
+        Command::EnableCredits {
+            ref mint,
+            confidential,
+            non_confidential,
+        }
+        | Command::DisableCredits {
+            ref mint,
+            confidential,
+            non_confidential,
+        } => {
+            let enabled = matches!(cli.command, Command::EnableCredits { .. });
+            let mint = resolve_mint(mint.clone(), &config, &keyring)?;
+            let (token, _decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            let ata_pubkey = owner_account(token_account, &owner, &mint);
+            let action = if enabled { "Enable" } else { "Disable" };
+            if confidential {
+                let signature =
+                    credits::set_confidential_credits(&token, &owner, &ata_pubkey, enabled).await?;
+                info!(
+                    "{} confidential credits transaction signature: {}",
+                    action, signature
+                );
+            }
+            if non_confidential {
+                let signature =
+                    credits::set_non_confidential_credits(&token, &owner, &ata_pubkey, enabled)
+                        .await?;
+                info!(
+                    "{} non-confidential credits transaction signature: {}",
+                    action, signature
+                );
+            }
+        }
+        Command::EnableRequiredMemos { ref mint } | Command::DisableRequiredMemos { ref mint } => {
+            let enabled = matches!(cli.command, Command::EnableRequiredMemos { .. });
+            let mint = resolve_mint(mint.clone(), &config, &keyring)?;
+            let (token, _decimals) =
+                mint::load_token(program_client, fee_payer.clone(), &mint, &compute_budget).await?;
+            let token = with_nonce(&rpc_client, nonce.as_ref(), token).await?;
+            let ata_pubkey = owner_account(token_account, &owner, &mint);
+            let signature = credits::set_required_memos(
+                &token,
+                &fee_payer.pubkey(),
+                &owner,
+                &ata_pubkey,
+                enabled,
+            )
+            .await?;
+            info!(
+                "{} required memos transaction signature: {}",
+                if enabled { "Enable" } else { "Disable" },
+                signature
+            );
+        }
+        Command::Balance { mint } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let (token, decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            let ata_pubkey = owner_account(token_account, &owner, &mint);
+            let (elgamal_keypair, aes_key) =
+                account_keys(&token, &owner, &ata_pubkey, &keystore).await?;
+            let decode_table = DecodeTable::open_default()?;
+            let balances = balance::get_balances(
+                &token,
+                &ata_pubkey,
+                &elgamal_keypair,
+                &aes_key,
+                &decode_table,
+            )
+            .await?;
+            print_balances(&balances, decimals);
+            records
+                .record(Record::Balance {
+                    mint,
+                    account: ata_pubkey,
+                    balances: &balances,
+                })
+                .await;
+            exit_code = check_pending_credits(&balances.pending_credits, &ata_pubkey);
+        }
+        Command::History { mint, limit } => {
+            let mint = mint
+                .map(|mint| resolve_mint(Some(mint), &config, &keyring))
+                .transpose()?;
+            records.print_history(mint.as_ref(), limit).await?;
+        }
+        Command::Index { mint } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            records.index(&rpc_client, &mint, cli.json).await?;
+        }
+        Command::Events {
+            mint,
+            account,
+            limit,
+        } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            records
+                .print_events(&mint, account.as_ref(), limit, cli.json)
+                .await?;
+        }
+        Command::InspectAccount { address } => {
+            let inspection = inspect::inspect_account(program_client.as_ref(), &address).await?;
+            let decimals = inspect::inspect_mint(program_client.as_ref(), &inspection.mint)
+                .await?
+                .decimals;
+            print_account_inspection(&inspection, decimals);
+            if let Some(extension) = &inspection.confidential_transfer {
+                exit_code =
+                    check_pending_credits(&balance::PendingCredits::new(extension), &address);
+            }
+        }
+        Command::InspectMint { address } => {
+            let address = resolve_mint(address, &config, &keyring)?;
+            let inspection = inspect::inspect_mint(program_client.as_ref(), &address).await?;
+            print_mint_inspection(&inspection);
+        }
+        Command::Audit {
+            auditor_keypair,
+            signature,
+        } => {
+            let auditor_keypair = utils::load_elgamal_keypair(&auditor_keypair)?;
+            let decode_table = DecodeTable::open_default()?;
+            let transfers = auditor::audit_transaction(
+                &rpc_client,
+                &signature,
+                auditor_keypair.secret(),
+                &decode_table,
+            )
+            .await?;
+            //The decimals of each mint, fetched once, to show the amounts in tokens
+            let mut decimals = HashMap::new();
+            for transfer in transfers {
+                let mint_decimals = match decimals.get(&transfer.mint) {
+                    Some(&mint_decimals) => mint_decimals,
+                    None => {
+                        let mint =
+                            inspect::inspect_mint(program_client.as_ref(), &transfer.mint).await?;
+                        *decimals.entry(transfer.mint).or_insert(mint.decimals)
+                    }
+                };
+                print_audited_transfer(&transfer, mint_decimals);
+            }
+        }
+        Command::HarvestFees { mint, mut accounts } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            if accounts.is_empty() {
+                accounts = fees::list_accounts_with_withheld_fees(&rpc_client, &mint).await?;
+                info!("Accounts with withheld fees: {}", accounts.len());
+            }
+            let (token, _decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            for signature in fees::harvest_withheld_fees(&token, &accounts).await? {
+                info!("Harvest withheld fees transaction signature: {}", signature);
+            }
+        }
+        Command::WithdrawFees {
+            mint,
+            withheld_keypair,
+        } => {
+            let mint = resolve_mint(mint, &config, &keyring)?;
+            let withheld_keypair = utils::load_elgamal_keypair(&withheld_keypair)?;
+            let (token, decimals) =
+                mint::load_token(program_client, fee_payer, &mint, &compute_budget).await?;
+            //The withdraw withheld authority is the mint authority (see create-mint)
+            let destination_ata = mint::get_ata(&mint_authority.pubkey(), &mint);
+            let (_elgamal_keypair, aes_key) = mint::load_keys(
+                &token,
+                mint_authority.as_ref(),
+                &seed_message,
+                &destination_ata,
+            )
+            .await?;
+            let decode_table = DecodeTable::open_default()?;
+            let withdrawn = fees::withdraw_withheld_fees(
+                &token,
+                mint_authority,
+                &destination_ata,
+                &withheld_keypair,
+                &aes_key,
+                &decode_table,
+            )
+            .await?;
+            info!(
+                "Withdrew {} withheld fees to {}: {}",
+                amount::format(withdrawn.amount, decimals),
+                destination_ata,
+                withdrawn.signature
+            );
+        }
+        Command::Completions { .. } | Command::Mangen { .. } => {
+            unreachable!("Completions and Mangen run before the config file is loaded")
+        }
+        Command::Bootstrap { .. } => unreachable!("Bootstrap runs before the wallet is loaded"),
+        Command::SignTransaction { .. } => {
+            unreachable!("SignTransaction runs before the wallet is loaded")
+        }
+        Command::BroadcastTransaction { files } => {
+            for file in files {
+                let transaction = offline::read_transaction(&file)?;
+                let signature =
+                    offline::broadcast_transaction(&rpc_client, &sender, &transaction).await?;
+                info!("{}: transaction signature: {}", file.display(), signature);
+            }
+        }
+        Command::Demo { state } => {
+            let state_path = resolve_state_path(state)?;
+            if flow::FlowState::load(&state_path)?.is_some_and(|state| state.next_step().is_some())
+            {
+                bail!(
+                    "An unfinished run is recorded in {}; continue it with `resume` or delete the file",
+                    state_path.display()
+                );
+            }
+            let state = flow::FlowState {
+                decimals: config.decimals(),
+                ..Default::default()
+            };
+            state.save(&state_path)?;
+            info!("Recording progress in {}", state_path.display());
+            run_demo(
+                program_client,
+                &compute_budget,
+                fee_payer,
+                owner,
+                mint_authority,
+                &state_path,
+                state,
+            )
+            .await?
+        }
+        Command::Resume { state } => {
+            let state_path = resolve_state_path(state)?;
+            let state = flow::FlowState::load(&state_path)?
+                .ok_or_else(|| anyhow!("No run recorded in {}", state_path.display()))?;
+            run_demo(
+                program_client,
+                &compute_budget,
+                fee_payer,
+                owner,
+                mint_authority,
+                &state_path,
+                state,
+            )
+            .await?
+        }
+        Command::BackupKeys { output, mint } => {
+            let derivation = if cli.seed_phrase {
+                format!("seed phrase, derivation path {}", cli.derivation_path)
+            } else {
+                format!("signature of {}", owner.key_source().pubkey())
+            };
+            let derivation = format!("{derivation}, seed message {}", owner.seed_message());
+            let mut entries = vec![];
+            if let Some(keystore) = &keystore {
+                for (mint, account) in keystore.keystore.accounts(&owner.pubkey())? {
+                    if let Some((elgamal_keypair, aes_key)) = keystore.keystore.load(
+                        &owner.pubkey(),
+                        &mint,
+                        &account,
+                        keystore.passphrase()?,
+                    )? {
+                        entries.push(backup::BackupEntry {
+                            owner: owner.pubkey(),
+                            mint,
+                            account,
+                            elgamal_keypair,
+                            aes_key,
+                            derivation: None,
+                        });
+                    }
+                }
+            }
+            for mint in mint {
+                let mint = resolve_mint(Some(mint), &config, &keyring)?;
+                let account = owner_account(token_account, &owner, &mint);
+                if entries.iter().any(|entry| entry.account == account) {
+                    continue;
+                }
+                let (token, _decimals) = mint::load_token(
+                    program_client.clone(),
+                    fee_payer.clone(),
+                    &mint,
+                    &compute_budget,
+                )
+                .await?;
+                let (elgamal_keypair, aes_key) =
+                    account_keys(&token, &owner, &account, &keystore).await?;
+                entries.push(backup::BackupEntry {
+                    owner: owner.pubkey(),
+                    mint,
+                    account,
+                    elgamal_keypair,
+                    aes_key,
+                    derivation: Some(derivation.clone()),
+                });
+            }
+            if entries.is_empty() {
+                bail!("No keys to back up; pass --keystore or --mint");
+            }
+            let passphrase = backup_passphrase(true)?;
+            backup::write_backup(&output, &entries, &passphrase)?;
+            for entry in &entries {
+                info!("Backed up keys of {} (mint {})", entry.account, entry.mint);
+            }
+            info!(
+                "Backup of {} accounts written to {}",
+                entries.len(),
+                output.display()
+            );
+        }
+        Command::RestoreKeys { input } => {
+            let keystore = keystore
+                .ok_or_else(|| anyhow!("Keys are restored into the keystore; pass --keystore"))?;
+            let entries = backup::read_backup(&input, &backup_passphrase(false)?)?;
+            for entry in &entries {
+                let path = keystore.keystore.save(
+                    &entry.owner,
+                    &entry.mint,
+                    &entry.account,
+                    &entry.elgamal_keypair,
+                    &entry.aes_key,
+                    keystore.passphrase()?,
+                )?;
+                info!(
+                    "Restored keys of {} (mint {}, owner {}) to {}",
+                    entry.account,
+                    entry.mint,
+                    entry.owner,
+                    path.display()
+                );
+                if let Some(derivation) = &entry.derivation {
+                    info!("  derived from: {}", derivation);
+                }
+            }
+            info!("Restored {} accounts", entries.len());
+        }
+        Command::Serve { bind, grpc } => {
+            let keystore = keystore.ok_or_else(|| {
+                anyhow!("The HTTP API looks the account keys up in the keystore; pass --keystore")
+            })?;
+            let backend = server::ApiBackend {
+                program_client,
+                fee_payer,
+                passphrase: keystore.passphrase()?.to_string(),
+                keystore: keystore.keystore,
+                owner,
+                compute_budget,
+                #[cfg(feature = "storage")]
+                storage: records.storage.clone(),
+            };
+            let api_token = env_var(API_TOKEN_ENV);
+            server::serve(backend, bind, grpc, api_token).await?;
+        }
+        #[cfg(feature = "tui")]
+        Command::Tui { mint } => {
+            //The given mints, else every account of the keyring, else the mint of the config file
+            let mut shown = vec![];
+            for mint in mint {
+                let mint = resolve_mint(Some(mint), &config, &keyring)?;
+                shown.push((mint, owner_account(token_account, &owner, &mint), None));
+            }
+            if shown.is_empty() {
+                shown = (keyring.load()?.accounts(&owner.pubkey()).into_iter())
+                    .map(|entry| (entry.mint, entry.account, entry.symbol.clone()))
+                    .collect();
+            }
+            if shown.is_empty() {
+                let mint = resolve_mint(None, &config, &keyring)?;
+                shown.push((mint, owner_account(token_account, &owner, &mint), None));
+            }
+            let mut accounts = vec![];
+            for (mint, account, symbol) in shown {
+                let (token, decimals) = mint::load_token(
+                    program_client.clone(),
+                    fee_payer.clone(),
+                    &mint,
+                    &compute_budget,
+                )
+                .await?;
+                let (elgamal_keypair, aes_key) =
+                    account_keys(&token, &owner, &account, &keystore).await?;
+                let symbol = match symbol {
+                    Some(symbol) => Some(symbol),
+                    None => keyring::mint_symbol(&token).await.ok().flatten(),
+                };
+                accounts.push(tui::DashboardAccount {
+                    symbol,
+                    account,
+                    token,
+                    decimals,
+                    elgamal_keypair,
+                    aes_key,
+                });
+            }
+            let decode_table = DecodeTable::open_default()?;
+            tui::run(&owner, &accounts, &decode_table).await?;
+        }
+        #[cfg(not(feature = "tui"))]
+        Command::Tui { .. } => {
+            bail!("The dashboard is not supported by this build; rebuild with `--features tui`")
+        }
+    }
+    Ok(exit_code)
+}
+
+// Writes the man pages of the command and every subcommand into `out_dir`, or prints the page of
+// the command without one
+fn man_pages(out_dir: Option<&Path>) -> Result<()> {
+    let command = Cli::command();
+    match out_dir {
+        Some(out_dir) => {
+            std::fs::create_dir_all(out_dir)
+                .with_context(|| format!("Failed to create {}", out_dir.display()))?;
+            clap_mangen::generate_to(command, out_dir)
+                .with_context(|| format!("Failed to write man pages to {}", out_dir.display()))?;
+            info!("Wrote man pages to {}", out_dir.display());
+        }
+        None => clap_mangen::Man::new(command).render(&mut std::io::stdout())?,
+    }
+    Ok(())
+}
+
+// Creates the wallet keypair at `path` unless it exists, then airdrops it up to `target` lamports
+async fn bootstrap(rpc_client: &RpcClient, path: &Path, target: u64, airdrop: u64) -> Result<()> {
+    let pubkey = if path.exists() {
+        let keypair = utils::load_keypair_from(path)?;
+        info!("Using the existing keypair {}", path.display());
+        keypair.pubkey()
+    } else {
+        let keypair = utils::create_keypair(path)?;
+        info!("Generated a new keypair at {}", path.display());
+        keypair.pubkey()
+    };
+    info!("Wallet public key: {}", pubkey);
+    let airdrops = bootstrap::airdrop_until(rpc_client, &pubkey, target, airdrop).await?;
+    for signature in &airdrops.signatures {
+        info!("Airdrop transaction signature: {}", signature);
+    }
+    info!(
+        "Balance of {}: {} SOL",
+        pubkey,
+        amount::format(airdrops.balance, 9)
+    );
+    Ok(())
+}
+
+// Passphrase of a key backup, from the environment or asked for; asked twice for a new backup
+fn backup_passphrase(confirm: bool) -> Result<String> {
+    if let Some(passphrase) = env_var(BACKUP_PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    let passphrase = rpassword::prompt_password("Backup passphrase: ")
+        .context("Unable to read the backup passphrase")?;
+    if confirm
+        && rpassword::prompt_password("Repeat the backup passphrase: ")
+            .context("Unable to read the backup passphrase")?
+            != passphrase
+    {
+        bail!("The passphrases do not match");
+    }
+    Ok(passphrase)
+}
+
+// Auditor ElGamal pubkey given inline, read from a keypair file or newly generated
+fn resolve_auditor(
+    auditor: Option<String>,
+    auditor_keypair: Option<PathBuf>,
+    new_auditor_keypair: Option<PathBuf>,
+) -> Result<Option<ElGamalPubkey>> {
+    Ok(match (auditor, auditor_keypair, new_auditor_keypair) {
+        (Some(pubkey), _, _) => Some(utils::parse_elgamal_pubkey(&pubkey)?),
+        (_, Some(path), _) => Some(*utils::load_elgamal_keypair(&path)?.pubkey()),
+        (_, _, Some(path)) => {
+            let keypair = utils::create_elgamal_keypair(&path)?;
+            info!("Auditor ElGamal keypair written to {}", path.display());
+            Some(*keypair.pubkey())
+        }
+        _ => None,
+    })
+}
+
+// Packing of the transactions of an operation paid by `fee_payer`, for the --pack flag, as v0
+// transactions compiled against the table of --lookup-table and bundled through the block engine
+// of --jito if any, or built on the nonce accounts of --nonce
+fn packing(
+    pack: bool,
+    fee_payer: &Arc<dyn Signer>,
+    compute_budget: &ComputeBudget,
+    lookup_table: Option<&LookupTable>,
+    bundle: Option<&JitoBundle>,
+    nonces: Option<&DurableNonces>,
+) -> Result<Packing> {
+    if !pack {
+        if lookup_table.is_some() {
+            bail!("--lookup-table only applies to packed transactions; add --pack");
+        }
+        if bundle.is_some() {
+            bail!("--jito only applies to packed transactions; add --pack");
+        }
+        //Separate transactions go through one token client, which builds them all on one nonce
+        if nonces.is_some() {
+            bail!("--nonce only applies to the packed transactions of this command; add --pack");
+        }
+        return Ok(Packing::Separate);
+    }
+    if nonces.is_some() && (lookup_table.is_some() || bundle.is_some()) {
+        bail!("--nonce builds legacy transactions sent one by one; drop --lookup-table and --jito");
+    }
+    //Packed transactions carry a compute unit limit instruction already
+    if !matches!(compute_budget.unit_limit, ComputeUnitLimit::Default) {
+        bail!("--pack sets the compute unit limit of its transactions; drop --compute-unit-limit");
+    }
+    Ok(Packing::Packed {
+        fee_payer: fee_payer.pubkey(),
+        lookup_table: lookup_table.cloned(),
+        bundle: bundle.cloned(),
+        nonces: nonces.cloned(),
+    })
+}
+
+// Token client of a single-transaction command, building it on the durable nonce of --nonce if
+// any
+async fn with_nonce(
+    rpc_client: &RpcClient,
+    nonce: Option<&DurableNonce>,
+    token: TokenClient,
+) -> Result<TokenClient> {
+    match nonce {
+        Some(nonce) => nonce.apply(rpc_client, token).await,
+        None => Ok(token),
+    }
+}
+
+// Signer of a keypair argument; see utils::resolve_signer for the accepted forms
+fn load_signer(
+    path: &Path,
+    keypair_name: &str,
+    sources: &utils::SignerSources,
+) -> Result<Arc<dyn Signer>> {
+    let uri = path
+        .to_str()
+        .ok_or_else(|| anyhow!("Invalid {keypair_name} path {}", path.display()))?;
+    utils::resolve_signer(uri, keypair_name, sources)
+}
+
+// Signer of a keypair argument, or the wallet when the argument is omitted
+fn load_signer_or(
+    path: Option<&Path>,
+    keypair_name: &str,
+    wallet: &Arc<dyn Signer>,
+    sources: &utils::SignerSources,
+) -> Result<Arc<dyn Signer>> {
+    match path {
+        Some(path) => load_signer(path, keypair_name, sources),
+        None => Ok(wallet.clone()),
+    }
+}
+
+// Logs go to stderr; RUST_LOG takes precedence over -v/-q. The dashboard of `tui` owns the
+// terminal, so it logs nothing
+fn init_logging(cli: &Cli) {
+    if matches!(cli.command, Command::Tui { .. }) {
+        return;
+    }
+    let level = match (cli.quiet, cli.verbose) {
+        (true, _) => LevelFilter::ERROR,
+        (false, 0) => LevelFilter::INFO,
+        (false, 1) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    };
+    let filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env_lossy();
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_target(false)
+        .without_time()
+        .init();
+}
+
+// Mint given on the command line, falling back to the config file
+fn resolve_mint(mint: Option<MintRef>, config: &Config, keyring: &KeyringAccess) -> Result<Pubkey> {
+    match mint {
+        Some(MintRef::Address(mint)) => Ok(mint),
+        Some(mint) => keyring.load()?.resolve_mint(&keyring.owner, &mint),
+        None => config
+            .mint()?
+            .ok_or_else(|| anyhow!("No mint given: pass --mint or set `mint` in the config file")),
+    }
+}
+
+// Key source of --seed-phrase, with the phrase and passphrase from the environment or prompted for
+fn load_seed_phrase_key_source(derivation_path: &str) -> Result<Keypair> {
+    let (seed_phrase, passphrase) = match env_var(SEED_PHRASE_ENV) {
+        Some(seed_phrase) => (
+            seed_phrase,
+            env_var(SEED_PASSPHRASE_ENV).unwrap_or_default(),
+        ),
+        None => (
+            rpassword::prompt_password("Seed phrase: ")
+                .context("Unable to read the seed phrase")?,
+            rpassword::prompt_password("Seed phrase passphrase (empty for none): ")
+                .context("Unable to read the seed phrase passphrase")?,
+        ),
+    };
+    seed::derive_key_source(&seed_phrase, &passphrase, derivation_path)
+}
+
+// Token account of the owner the account commands act on: --token-account, or else the
+// associated token account
+fn owner_account(token_account: Option<Pubkey>, owner: &Authority, mint: &Pubkey) -> Pubkey {
+    token_account.unwrap_or_else(|| mint::get_ata(&owner.pubkey(), mint))
+}
+
+// Keystore selected with --keystore; the passphrase is asked for on first use
+struct KeystoreAccess {
+    keystore: keystore::Keystore,
+    passphrase: OnceCell<String>,
+}
+
+impl KeystoreAccess {
+    fn passphrase(&self) -> Result<&str> {
+        if let Some(passphrase) = self.passphrase.get() {
+            return Ok(passphrase);
+        }
+        let passphrase = match env_var(KEYSTORE_PASSPHRASE_ENV) {
+            Some(passphrase) => passphrase,
+            None => rpassword::prompt_password("Keystore passphrase: ")
+                .context("Unable to read the keystore passphrase")?,
+        };
+        Ok(self.passphrase.get_or_init(|| passphrase))
+    }
+
+    // Stores the keys of the owner's `account` and logs where
+    fn save(
+        &self,
+        owner: &Authority,
+        mint: &Pubkey,
+        account: &Pubkey,
+        elgamal_keypair: &ElGamalKeypair,
+        aes_key: &AeKey,
+    ) -> Result<()> {
+        let path = self.keystore.save(
+            &owner.pubkey(),
+            mint,
+            account,
+            elgamal_keypair,
+            aes_key,
+            self.passphrase()?,
+        )?;
+        info!("Keys of {} saved to {}", account, path.display());
+        Ok(())
+    }
+}
+
+// Keyring file of --keyring, loaded when needed
+struct KeyringAccess {
+    path: Option<PathBuf>,
+    owner: Pubkey,
+}
+
+impl KeyringAccess {
+    fn load(&self) -> Result<keyring::Keyring> {
+        let path = self
+            .path
+            .clone()
+            .ok_or_else(|| anyhow!("Unable to locate the keyring; pass --keyring"))?;
+        keyring::Keyring::load(path)
+    }
+
+    // Records a configured account, with the mint symbol from `symbol` or the token metadata. The
+    // account is configured either way, so failures are only logged
+    async fn record(
+        &self,
+        token: &TokenClient,
+        owner: &Pubkey,
+        account: &Pubkey,
+        elgamal_pubkey: &ElGamalPubkey,
+        symbol: Option<String>,
+    ) {
+        let recorded: Result<PathBuf> = async {
+            let symbol = match symbol {
+                Some(symbol) => Some(symbol),
+                None => keyring::mint_symbol(token).await?,
+            };
+            let mut keyring = self.load()?;
+            keyring.record(keyring::KeyringEntry {
+                owner: *owner,
+                mint: *token.get_address(),
+                account: *account,
+                elgamal_pubkey: *elgamal_pubkey,
+                symbol,
+            });
+            keyring.save()?;
+            Ok(keyring.path().to_path_buf())
+        }
+        .await;
+        match recorded {
+            Ok(path) => info!("Recorded {} in the keyring {}", account, path.display()),
+            Err(err) => warn!("Unable to record {} in the keyring: {:#}", account, err),
+        }
+    }
+}
+
+// Database of --storage, if any
+#[cfg_attr(not(feature = "storage"), allow(dead_code))]
+struct Records {
+    #[cfg(feature = "storage")]
+    storage: Option<Arc<dyn Storage>>,
+    owner: Pubkey,
+}
+
+// What the commands record in the database of --storage
+#[cfg_attr(not(feature = "storage"), allow(dead_code))]
+enum Record<'a> {
+    Mint {
+        mint: Pubkey,
+        authority: Pubkey,
+        decimals: u8,
+        signature: Signature,
+    },
+    Account {
+        owner: Pubkey,
+        mint: Pubkey,
+        account: Pubkey,
+        elgamal_pubkey: &'a ElGamalPubkey,
+        signature: Option<Signature>,
+    },
+    Deposit {
+        mint: Pubkey,
+        account: Pubkey,
+        amount: u64,
+        signature: Signature,
+    },
+    Apply {
+        mint: Pubkey,
+        account: Pubkey,
+        signature: Signature,
+    },
+    Withdraw {
+        mint: Pubkey,
+        account: Pubkey,
+        result: &'a withdraw::WithdrawResult,
+    },
+    Transfer {
+        mint: Pubkey,
+        source: Pubkey,
+        destination: Pubkey,
+        amount: u64,
+        result: &'a transfer::TransferResult,
+    },
+    Credit {
+        mint: Pubkey,
+        account: Pubkey,
+        credits: u64,
+    },
+    Balance {
+        mint: Pubkey,
+        account: Pubkey,
+        balances: &'a balance::Balances,
+    },
+}
+
+impl Records {
+    async fn open(location: Option<Option<String>>, owner: Pubkey) -> Result<Self> {
+        #[cfg(feature = "storage")]
+        {
+            let storage = match location {
+                Some(location) => {
+                    let location = match location {
+                        Some(location) => location,
+                        None => SqliteStorage::default_path()
+                            .ok_or_else(|| {
+                                anyhow!("Unable to locate the database; pass --storage=<FILE|URL>")
+                            })?
+                            .to_string_lossy()
+                            .into_owned(),
+                    };
+                    Some(storage::open(&location).await?)
+                }
+                None => None,
+            };
+            Ok(Self { storage, owner })
+        }
+        #[cfg(not(feature = "storage"))]
+        {
+            if location.is_some() {
+                bail!("Storage is not supported by this build; rebuild with `--features storage`");
+            }
+            Ok(Self { owner })
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        #[cfg(feature = "storage")]
+        {
+            self.storage.is_some()
+        }
+        #[cfg(not(feature = "storage"))]
+        {
+            false
+        }
+    }
+
+    // Records what a command did. The command succeeded either way, so failures are only logged
+    async fn record(&self, record: Record<'_>) {
+        #[cfg(feature = "storage")]
+        if let Some(storage) = &self.storage {
+            let owner = &self.owner;
+            let recorded = match record {
+                Record::Mint {
+                    mint,
+                    authority,
+                    decimals,
+                    signature,
+                } => {
+                    storage
+                        .record_mint(&mint, &authority, decimals, &signature)
+                        .await
+                }
+                Record::Account {
+                    owner,
+                    mint,
+                    account,
+                    elgamal_pubkey,
+                    signature,
+                } => {
+                    storage
+                        .record_account(&owner, &mint, &account, elgamal_pubkey, signature.as_ref())
+                        .await
+                }
+                Record::Deposit {
+                    mint,
+                    account,
+                    amount,
+                    signature,
+                } => {
+                    storage
+                        .record_operation(
+                            OperationKind::Deposit,
+                            owner,
+                            &mint,
+                            &account,
+                            None,
+                            Some(amount),
+                            &[signature],
+                        )
+                        .await
+                }
+                Record::Apply {
+                    mint,
+                    account,
+                    signature,
+                } => {
+                    storage
+                        .record_operation(
+                            OperationKind::Apply,
+                            owner,
+                            &mint,
+                            &account,
+                            None,
+                            None,
+                            &[signature],
+                        )
+                        .await
+                }
+                Record::Withdraw {
+                    mint,
+                    account,
+                    result,
+                } => {
+                    storage
+                        .record_operation(
+                            OperationKind::Withdraw,
+                            owner,
+                            &mint,
+                            &account,
+                            None,
+                            Some(result.amount),
+                            &result.signatures(),
+                        )
+                        .await
+                }
+                Record::Transfer {
+                    mint,
+                    source,
+                    destination,
+                    amount,
+                    result,
+                } => {
+                    storage
+                        .record_operation(
+                            OperationKind::Transfer,
+                            owner,
+                            &mint,
+                            &source,
+                            Some(&destination),
+                            Some(amount),
+                            &result.signatures(),
+                        )
+                        .await
+                }
+                Record::Credit {
+                    mint,
+                    account,
+                    credits,
+                } => {
+                    storage
+                        .record_operation(
+                            OperationKind::Credit,
+                            owner,
+                            &mint,
+                            &account,
+                            None,
+                            Some(credits),
+                            &[],
+                        )
+                        .await
+                }
+                Record::Balance {
+                    mint,
+                    account,
+                    balances,
+                } => storage.record_balance(&mint, &account, balances).await,
+            };
+            if let Err(err) = recorded {
+                warn!("Unable to record to the database: {:#}", err);
+            }
+        }
+        #[cfg(not(feature = "storage"))]
+        let _ = record;
+    }
+
+    // Prints the accounts of the owner recorded in the database, with their latest balances
+    async fn print_accounts(&self) -> Result<()> {
+        #[cfg(feature = "storage")]
+        {
+            let accounts = self.storage()?.accounts(&self.owner).await?;
+            info!(
+                "Accounts of {} in the database: {}",
+                self.owner,
+                accounts.len()
+            );
+            for account in accounts {
+                let balance = match account.latest_balance {
+                    Some(balance) => format!(
+                        "available {} pending {} public {} (at {})",
+                        balance.available, balance.pending, balance.public, balance.recorded_at
+                    ),
+                    None => "no balance recorded".to_string(),
+                };
+                println!(
+                    "{} {} {} {}",
+                    account.mint, account.address, account.elgamal_pubkey, balance
+                );
+            }
+            Ok(())
+        }
+        #[cfg(not(feature = "storage"))]
+        bail!("Storage is not supported by this build; rebuild with `--features storage`")
+    }
+
+    // Prints the latest `limit` operations of the owner recorded in the database
+    async fn print_history(&self, mint: Option<&Pubkey>, limit: u32) -> Result<()> {
+        #[cfg(feature = "storage")]
+        {
+            for operation in self.storage()?.history(&self.owner, mint, limit).await? {
+                let signatures = operation
+                    .signatures
+                    .iter()
+                    .map(Signature::to_string)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                println!(
+                    "{} {:<8} {} {} {} {} {}",
+                    operation.recorded_at,
+                    operation.kind,
+                    operation.mint,
+                    operation.account,
+                    display_option(operation.counterparty),
+                    display_option(operation.amount),
+                    signatures
+                );
+            }
+            Ok(())
+        }
+        #[cfg(not(feature = "storage"))]
+        {
+            let _ = (mint, limit);
+            bail!("Storage is not supported by this build; rebuild with `--features storage`")
+        }
+    }
+
+    // Indexes the events of `mint` into the database
+    async fn index(&self, rpc_client: &RpcClient, mint: &Pubkey, json: bool) -> Result<()> {
+        #[cfg(feature = "storage")]
+        {
+            let summary = indexer::index_mint(rpc_client, self.storage()?, mint).await?;
+            info!(
+                "Indexed {} events from {} new transactions of {} addresses",
+                summary.events, summary.transactions, summary.addresses
+            );
+            if json {
+                print_json(&json!({
+                    "addresses": summary.addresses,
+                    "transactions": summary.transactions,
+                    "events": summary.events,
+                }))?;
+            }
+            Ok(())
+        }
+        #[cfg(not(feature = "storage"))]
+        {
+            let _ = (rpc_client, mint, json);
+            bail!("Storage is not supported by this build; rebuild with `--features storage`")
+        }
+    }
+
+    // Prints the latest `limit` indexed events of `mint`
+    async fn print_events(
+        &self,
+        mint: &Pubkey,
+        account: Option<&Pubkey>,
+        limit: u32,
+        json: bool,
+    ) -> Result<()> {
+        #[cfg(feature = "storage")]
+        {
+            let events = self.storage()?.events(mint, account, limit).await?;
+            if json {
+                let events = events
+                    .iter()
+                    .map(|event| {
+                        json!({
+                            "signature": event.signature.to_string(),
+                            "instruction_index": event.instruction_index,
+                            "slot": event.slot,
+                            "block_time": event.block_time,
+                            "kind": event.kind.as_str(),
+                            "account": event.account.to_string(),
+                            "counterparty": event.counterparty.map(|key| key.to_string()),
+                            "amount": event.amount,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                return print_json(&json!(events));
+            }
+            for event in events {
+                println!(
+                    "{} {:<8} {} {} {} {}:{}",
+                    event.slot,
+                    event.kind,
+                    event.account,
+                    display_option(event.counterparty),
+                    display_option(event.amount),
+                    event.signature,
+                    event.instruction_index
+                );
+            }
+            Ok(())
+        }
+        #[cfg(not(feature = "storage"))]
+        {
+            let _ = (mint, account, limit, json);
+            bail!("Storage is not supported by this build; rebuild with `--features storage`")
+        }
+    }
+
+    #[cfg(feature = "storage")]
+    fn storage(&self) -> Result<&dyn Storage> {
+        self.storage
+            .as_deref()
+            .ok_or_else(|| anyhow!("No database selected; pass --storage"))
+    }
+}
+
+// Keys of the owner's `account`: from the keystore when one is selected, storing them there on
+// first use, or else derived from the owner's signature
+async fn account_keys(
+    token: &TokenClient,
+    owner: &Authority,
+    account: &Pubkey,
+    keystore: &Option<KeystoreAccess>,
+) -> Result<(ElGamalKeypair, AeKey)> {
+    let Some(keystore) = keystore else {
+        return mint::load_keys(
+            token,
+            owner.key_source().as_ref(),
+            owner.seed_message(),
+            account,
+        )
+        .await;
+    };
+    let mint = token.get_address();
+    if let Some((elgamal_keypair, aes_key)) =
+        keystore
+            .keystore
+            .load(&owner.pubkey(), mint, account, keystore.passphrase()?)?
+    {
+        if *elgamal_keypair.pubkey() != transfer::get_elgamal_pubkey(token, account).await? {
+            bail!(
+                "The stored keys of {} do not match its ElGamal pubkey; was it reconfigured?",
+                account
+            );
+        }
+        return Ok((elgamal_keypair, aes_key));
+    }
+    let (elgamal_keypair, aes_key) = mint::load_keys(
+        token,
+        owner.key_source().as_ref(),
+        owner.seed_message(),
+        account,
+    )
+    .await?;
+    keystore.save(owner, mint, account, &elgamal_keypair, &aes_key)?;
+    Ok((elgamal_keypair, aes_key))
+}
+
+// Keys of the owner's `account` without network access: from the keystore when one is selected
+// and holds them, or else derived from the owner's signature and checked against
+// `registered_elgamal_pubkey` when it is known
+fn offline_account_keys(
+    owner: &Authority,
+    mint: &Pubkey,
+    account: &Pubkey,
+    registered_elgamal_pubkey: Option<&ElGamalPubkey>,
+    keystore: &Option<KeystoreAccess>,
+) -> Result<(ElGamalKeypair, AeKey)> {
+    let stored = match keystore {
+        Some(keystore) => {
+            keystore
+                .keystore
+                .load(&owner.pubkey(), mint, account, keystore.passphrase()?)?
+        }
+        None => None,
+    };
+    if let Some(keys) = stored {
+        return Ok(keys);
+    }
+    let key_source = owner.key_source();
+    match registered_elgamal_pubkey {
+        Some(registered_elgamal_pubkey) => mint::derive_matching_keys(
+            key_source.as_ref(),
+            owner.seed_message(),
+            account,
+            registered_elgamal_pubkey,
+        ),
+        None => mint::derive_keys(key_source.as_ref(), owner.seed_message(), account),
+    }
+}
+
+// Pays one occurrence of `payment`, `amount` base units of a mint with `decimals` decimals, from
+// `source_ata`, refusing without the available balance for it rather than sending transactions
+// bound to fail
+#[allow(clippy::too_many_arguments)]
+async fn pay_scheduled(
+    token: &TokenClient,
+    owner: &Authority,
+    source_ata: &Pubkey,
+    payment: &schedule::ScheduledPayment,
+    amount: u64,
+    decimals: u8,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+) -> Result<transfer::TransferResult> {
+    let available = balance::get_available_balance(token, source_ata, aes_key).await?;
+    if available < amount {
+        bail!(
+            "The available balance of {} is {}, below the {} due",
+            source_ata,
+            amount::format(available, decimals),
+            amount::format(amount, decimals)
+        );
+    }
+    transfer::transfer(
+        token,
+        owner,
+        source_ata,
+        &mint::get_ata(&payment.recipient, token.get_address()),
+        amount,
+        elgamal_keypair,
+        aes_key,
+        payment.memo.as_deref(),
+        Packing::Separate,
+    )
+    .await
+}
+
+fn load_schedule_state() -> Result<schedule::ScheduleState> {
+    let path = schedule::ScheduleState::default_path()
+        .ok_or_else(|| anyhow!("Unable to locate the schedule state"))?;
+    schedule::ScheduleState::load(path)
+}
 
-    // Load payer keypair
-    let payer = Arc::new(utils::load_keypair()?);
-    println!("Payer public key: {}", payer.pubkey());
+// Keys of the escrow account, opened with the keys of the owner's associated token account,
+// once the account is checked against the escrow
+async fn escrow_keys(
+    token: &TokenClient,
+    owner: &Authority,
+    keystore: &Option<KeystoreAccess>,
+    escrow: &escrow::Escrow,
+) -> Result<(ElGamalKeypair, AeKey)> {
+    let ata_pubkey = mint::get_ata(&owner.pubkey(), &escrow.mint);
+    let (party_elgamal_keypair, _) = account_keys(token, owner, &ata_pubkey, keystore).await?;
+    let (elgamal_keypair, aes_key) = escrow.open(&owner.pubkey(), &party_elgamal_keypair)?;
+    escrow::verify_escrow(token, escrow, &elgamal_keypair).await?;
+    Ok((elgamal_keypair, aes_key))
+}
 
+fn load_deposit_index() -> Result<exchange::DepositIndex> {
+    let path = exchange::DepositIndex::default_path()
+        .ok_or_else(|| anyhow!("Unable to locate the deposit index"))?;
+    exchange::DepositIndex::load(path)
+}
+
+// Deposit addresses of the users of `owner` for `mint`, derived again from the owner
+fn deposit_addresses(owner: &Authority, mint: &Pubkey) -> Result<Vec<exchange::DepositAddress>> {
+    let index = load_deposit_index()?;
+    index
+        .users(&owner.pubkey(), mint)
+        .into_iter()
+        .map(|user_id| exchange::DepositAddress::derive(owner.key_source().as_ref(), mint, user_id))
+        .collect()
+}
+
+fn resolve_state_path(state: Option<PathBuf>) -> Result<PathBuf> {
+    state
+        .or_else(flow::FlowState::default_path)
+        .ok_or_else(|| anyhow!("Unable to locate the state file; pass --state"))
+}
+
+// Prints `data` as a QR code of terminal block characters
+fn print_qr(data: &str) -> Result<()> {
+    let code = QrCode::new(data).context("Unable to encode the QR code")?;
+    println!(
+        "{}",
+        code.render::<unicode::Dense1x2>()
+            .dark_color(unicode::Dense1x2::Light)
+            .light_color(unicode::Dense1x2::Dark)
+            .build()
+    );
+    Ok(())
+}
+
+fn print_configured(configured: &mint::ConfiguredAccount) {
+    match configured.signature {
+        Some(signature) => {
+            info!(
+                "Confidential transfer account configuration transaction signature: {}",
+                signature
+            );
+            info!(
+                "Token account configured for confidential transfers: {}",
+                configured.ata
+            );
+        }
+        None => info!(
+            "Token account already configured for confidential transfers: {}",
+            configured.ata
+        ),
+    }
+}
+
+// Warns when the pending credit counter of `account` nears its maximum; the exit code tells
+// scripts an apply is due (3) or overdue (4)
+fn check_pending_credits(pending_credits: &balance::PendingCredits, account: &Pubkey) -> ExitCode {
+    match pending_credits.urgency() {
+        balance::ApplyUrgency::None => ExitCode::SUCCESS,
+        balance::ApplyUrgency::Soon => {
+            warn!(
+                "Pending credit counter of {} is at {} of {}; only {} more credits are accepted before an apply",
+                account,
+                pending_credits.counter,
+                pending_credits.maximum,
+                pending_credits.remaining()
+            );
+            ExitCode::from(3)
+        }
+        balance::ApplyUrgency::Now => {
+            warn!(
+                "Pending credit counter of {} is at its maximum of {}; incoming credits are rejected until the pending balance is applied",
+                account, pending_credits.maximum
+            );
+            ExitCode::from(4)
+        }
+    }
+}
+
+fn print_balances(balances: &balance::Balances, decimals: u8) {
+    info!(
+        "Public balance: {}",
+        amount::format(balances.public, decimals)
+    );
+    info!(
+        "Confidential pending balance: {}",
+        amount::format(balances.pending, decimals)
+    );
+    info!(
+        "Confidential available balance: {}",
+        amount::format(balances.available, decimals)
+    );
+    info!(
+        "Pending credits: {} of {}",
+        balances.pending_credits.counter, balances.pending_credits.maximum
+    );
+}
+
+fn print_account_inspection(inspection: &inspect::AccountInspection, decimals: u8) {
+    info!("Address: {}", inspection.address);
+    info!("Mint: {}", inspection.mint);
+    info!("Owner: {}", inspection.owner);
+    info!(
+        "Public balance: {}",
+        amount::format(inspection.amount, decimals)
+    );
+    info!("Extensions: {:?}", inspection.extensions);
+    let Some(extension) = &inspection.confidential_transfer else {
+        info!("No ConfidentialTransferAccount extension");
+        return;
+    };
+    let pending_counter = u64::from(extension.pending_balance_credit_counter);
+    let maximum_counter = u64::from(extension.maximum_pending_balance_credit_counter);
+    let expected_counter = u64::from(extension.expected_pending_balance_credit_counter);
+    let actual_counter = u64::from(extension.actual_pending_balance_credit_counter);
+    info!("Approved: {}", bool::from(extension.approved));
+    info!("ElGamal pubkey: {}", extension.elgamal_pubkey);
+    info!("Pending balance lo: {}", extension.pending_balance_lo);
+    info!("Pending balance hi: {}", extension.pending_balance_hi);
+    info!("Available balance: {}", extension.available_balance);
+    info!(
+        "Decryptable available balance: {}",
+        extension.decryptable_available_balance
+    );
+    info!(
+        "Allow confidential credits: {}",
+        bool::from(extension.allow_confidential_credits)
+    );
+    info!(
+        "Allow non-confidential credits: {}",
+        bool::from(extension.allow_non_confidential_credits)
+    );
+    info!("Pending balance credit counter: {}", pending_counter);
+    info!(
+        "Maximum pending balance credit counter: {}",
+        maximum_counter
+    );
+    info!(
+        "Expected pending balance credit counter: {}",
+        expected_counter
+    );
+    info!("Actual pending balance credit counter: {}", actual_counter);
+    //Common reasons for an account to reject credits or fail to apply
+    if !bool::from(extension.approved) {
+        warn!("Account is not approved by the confidential transfer authority");
+    }
+    if expected_counter != actual_counter {
+        warn!("Expected and actual credit counters differ; the last apply missed some credits");
+    }
+}
+
+fn print_mint_inspection(inspection: &inspect::MintInspection) {
+    info!("Address: {}", inspection.address);
+    info!(
+        "Supply: {}",
+        amount::format(inspection.supply, inspection.decimals)
+    );
+    info!("Decimals: {}", inspection.decimals);
+    info!(
+        "Mint authority: {}",
+        display_option(inspection.mint_authority)
+    );
+    info!(
+        "Freeze authority: {}",
+        display_option(inspection.freeze_authority)
+    );
+    info!("Extensions: {:?}", inspection.extensions);
+    match &inspection.confidential_transfer {
+        Some(extension) => {
+            info!(
+                "Confidential transfer authority: {}",
+                display_option(Option::<Pubkey>::from(extension.authority))
+            );
+            info!(
+                "Auto-approve new accounts: {}",
+                bool::from(extension.auto_approve_new_accounts)
+            );
+            info!(
+                "Auditor ElGamal pubkey: {}",
+                display_option(Option::<PodElGamalPubkey>::from(
+                    extension.auditor_elgamal_pubkey
+                ))
+            );
+        }
+        None => warn!("No ConfidentialTransferMint extension"),
+    }
+    for (extension_type, dump) in &inspection.other_extensions {
+        info!("{:?}: {}", extension_type, dump);
+    }
+}
+
+fn display_option<T: std::fmt::Display>(value: Option<T>) -> String {
+    value.map_or_else(|| "none".to_string(), |value| value.to_string())
+}
+
+fn print_audited_transfer(transfer: &auditor::AuditedTransfer, decimals: u8) {
+    info!("Instruction {}:", transfer.instruction_index);
+    info!("  Mint: {}", transfer.mint);
+    info!("  Source: {}", transfer.source);
+    info!("  Destination: {}", transfer.destination);
+    info!("  Amount: {}", amount::format(transfer.amount, decimals));
+}
+
+fn print_mint_burn(result: &mint_burn::MintBurnResult, operation: &str) {
+    let proofs = ["Equality", "Ciphertext validity", "Range"];
+    for (proof, signature) in proofs.iter().zip(&result.proof_signatures) {
+        info!(
+            "{} proof account creation transaction signature: {}",
+            proof, signature
+        );
+    }
+    info!(
+        "Confidential {} transaction signature: {}",
+        operation, result.signature
+    );
+    for signature in &result.close_signatures {
+        info!("Close proof account transaction signature: {}", signature);
+    }
+}
+
+fn print_withdraw(result: &withdraw::WithdrawResult) {
+    info!("Proof generation time: {:?}", result.proof_generation_time);
+    match result.equality_proof_signature {
+        Some(signature) => info!(
+            "Equality proof account creation transaction signature: {}",
+            signature
+        ),
+        None => info!("Equality proof verified in the withdraw transaction"),
+    }
+    match result.range_proof_signature {
+        Some(signature) => info!(
+            "Range proof account creation transaction signature: {}",
+            signature
+        ),
+        None => info!("Range proof verified in the withdraw transaction"),
+    }
+    info!(
+        "Confidential transfer withdraw transaction signature: {}",
+        result.withdraw_signature
+    );
+    if let Some(signature) = result.close_equality_signature {
+        info!(
+            "Close equality proof account transaction signature: {}",
+            signature
+        );
+    }
+    if let Some(signature) = result.close_range_signature {
+        info!(
+            "Close range proof account transaction signature: {}",
+            signature
+        );
+    }
+}
+
+// Stage timings of an operation, in verbose output
+fn print_timings(timings: &StageTimings) {
+    debug!(
+        "Stage timings: proof generation {:?}, transaction build {:?}, send {:?}, confirm {:?} ({} transactions)",
+        timings.proof_generation,
+        timings.transaction_build,
+        timings.send,
+        timings.confirm,
+        timings.transactions
+    );
+}
+
+// Prints a --json result on stdout, apart from the logs on stderr
+fn print_json(value: &serde_json::Value) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn timings_json(timings: &StageTimings) -> serde_json::Value {
+    let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    json!({
+        "proof_generation_ms": millis(timings.proof_generation),
+        "transaction_build_ms": millis(timings.transaction_build),
+        "send_ms": millis(timings.send),
+        "confirm_ms": millis(timings.confirm),
+        "transactions": timings.transactions,
+    })
+}
+
+fn withdraw_json(result: &withdraw::WithdrawResult, timings: &StageTimings) -> serde_json::Value {
+    let account = |account: Option<Pubkey>| account.map(|account| account.to_string());
+    let signature = |signature: Option<Signature>| signature.map(|signature| signature.to_string());
+    json!({
+        "amount": result.amount,
+        "equality_proof_account": account(result.equality_proof_account),
+        "range_proof_account": account(result.range_proof_account),
+        "signatures": {
+            "equality_proof": signature(result.equality_proof_signature),
+            "range_proof": signature(result.range_proof_signature),
+            "withdraw": result.withdraw_signature.to_string(),
+            "close_equality": signature(result.close_equality_signature),
+            "close_range": signature(result.close_range_signature),
+        },
+        "timings": timings_json(timings),
+    })
+}
+
+fn transfer_json(
+    destination: &Pubkey,
+    amount: u64,
+    result: &transfer::TransferResult,
+    timings: &StageTimings,
+) -> serde_json::Value {
+    let strings = |values: &[Signature]| values.iter().map(ToString::to_string).collect::<Vec<_>>();
+    json!({
+        "destination": destination.to_string(),
+        "amount": amount,
+        "fee": result.fee,
+        "signatures": {
+            "proof_accounts": strings(&result.proof_signatures),
+            "transfer": result.transfer_signature.to_string(),
+            "close": strings(&result.close_signatures),
+        },
+        "timings": timings_json(timings),
+    })
+}
+
+fn sweeps_json(sweeps: &[exchange::Sweep]) -> serde_json::Value {
+    let sweeps = sweeps
+        .iter()
+        .map(|sweep| {
+            json!({
+                "user_id": sweep.user_id,
+                "account": sweep.account.to_string(),
+                "credited": sweep.credited,
+                "swept": sweep.swept,
+                "apply_signature": sweep.apply_signature.map(|signature| signature.to_string()),
+                "transfer_signature": sweep
+                    .transfer
+                    .as_ref()
+                    .map(|transfer| transfer.transfer_signature.to_string()),
+            })
+        })
+        .collect::<Vec<_>>();
+    json!(sweeps)
+}
+
+fn batch_json(
+    outcomes: &[batch::BatchTransferOutcome],
+    timings: &StageTimings,
+) -> serde_json::Value {
+    let transfers = outcomes
+        .iter()
+        .map(|outcome| match &outcome.result {
+            Ok(result) => json!({
+                "recipient": outcome.transfer.recipient.to_string(),
+                "amount": outcome.transfer.amount,
+                "signature": result.transfer_signature.to_string(),
+                "proof_generation_ms": result.proof_generation_time.as_secs_f64() * 1000.0,
+            }),
+            Err(err) => json!({
+                "recipient": outcome.transfer.recipient.to_string(),
+                "amount": outcome.transfer.amount,
+                "error": format!("{err:#}"),
+            }),
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "transfers": transfers,
+        "timings": timings_json(timings),
+    })
+}
+
+fn print_transfer(result: &transfer::TransferResult, decimals: u8) {
+    let proofs = [
+        "Equality",
+        "Ciphertext validity",
+        "Range",
+        "Percentage with cap",
+        "Fee ciphertext validity",
+    ];
+    info!("Proof generation time: {:?}", result.proof_generation_time);
+    for (proof, signature) in proofs.iter().zip(&result.proof_signatures) {
+        info!(
+            "{} proof account creation transaction signature: {}",
+            proof, signature
+        );
+    }
+    info!(
+        "Confidential transfer transaction signature: {}",
+        result.transfer_signature
+    );
+    if result.fee_proof_accounts.is_some() {
+        info!(
+            "Transfer fee withheld: {}",
+            amount::format(result.fee, decimals)
+        );
+    }
+    for signature in &result.close_signatures {
+        info!("Close proof account transaction signature: {}", signature);
+    }
+}
+
+// End-to-end flow: create mint, configure ata, mint, deposit, apply and withdraw
+// Runs the end-to-end flow from the first step `state` has not completed, saving the progress
+// to `state_path` after every step
+async fn run_demo(
+    program_client: Arc<dyn ProgramClient<RpcSender>>,
+    compute_budget: &ComputeBudget,
+    fee_payer: Arc<dyn Signer>,
+    owner: Authority,
+    mint_authority: Arc<dyn Signer>,
+    state_path: &Path,
+    mut state: flow::FlowState,
+) -> Result<()> {
+    if let Some(recorded_owner) = state.owner
+        && recorded_owner != owner.pubkey()
+    {
+        bail!(
+            "The recorded run belongs to owner {}; resume it with that owner",
+            recorded_owner
+        );
+    }
+    state.owner = Some(owner.pubkey());
+    if let Some(step) = state.next_step() {
+        info!("Running the flow from step {:?}", step);
+    }
+    let decimals = state.decimals;
     // Token Mint Account creation and initialization
-    let (mint_keypair, token): (Keypair, Token<ProgramRpcClientSendTransaction>) =
-        mint::initialize_mint(rpc_client.clone(), payer.clone()).await?;
-    println!("Mint Account public key: {}", mint_keypair.pubkey());
+    let token = match state.mint {
+        Some(mint) => {
+            mint::load_token(program_client, fee_payer.clone(), &mint, compute_budget)
+                .await?
+                .0
+        }
+        None => {
+            let mint::CreatedMint {
+                mint_keypair,
+                token,
+                signature,
+            } = mint::initialize_mint(
+                program_client,
+                fee_payer.clone(),
+                mint_authority.as_ref(),
+                &mint::MintOptions {
+                    decimals,
+                    ..Default::default()
+                },
+                compute_budget,
+            )
+            .await?;
+            info!("Mint creation transaction signature: {}", signature);
+            info!("Mint Account public key: {}", mint_keypair.pubkey());
+            state.mint = Some(mint_keypair.pubkey());
+            state.complete(flow::FlowStep::CreateMint, state_path)?;
+            token
+        }
+    };
 
     // Configure token account for confidential transfers
     // ElGamal keypair for public-key cryptography (decryption and ZK proofs)
     // AES key for encryption of balance and transfer amounts
-    let (ata_pubkey,elgamal_keypair,aeskey) =
-        mint::create_configure_ata(rpc_client.clone(), payer.clone(), &mint_keypair).await?;
-    println!(
-        "Associated token account configured for confidential transfers: {}",
-        ata_pubkey
-    );
-    //Mint tokens to the newly crated ata
-    let mint_sig=token.mint_to(
-        &ata_pubkey,//destination ata
-        &payer.pubkey(),//mint authority
-        100*10u64.pow(mint::TOKEN_DECIMALS as u32),//amount to mint
-        &[&payer]//signers
-    ).await?;
-    println!("Minted tokens transaction signature: {}", mint_sig);
+    // Re-running returns the keys of the account configured earlier
+    let configured = mint::create_configure_ata(&token, &fee_payer.pubkey(), &owner).await?;
+    print_configured(&configured);
+    let ata_pubkey = configured.ata;
+    state.token_account = Some(ata_pubkey);
+    state.complete(flow::FlowStep::ConfigureAccount, state_path)?;
+    //Mint tokens to the newly created ata
+    if !state.is_completed(flow::FlowStep::MintTo) {
+        let mint_sig = mint::mint_to(
+            &token,
+            mint_authority,
+            &ata_pubkey,
+            Amount::whole(100).to_base_units(decimals)?,
+        )
+        .await?;
+        info!("Minted tokens transaction signature: {}", mint_sig);
+        state.complete(flow::FlowStep::MintTo, state_path)?;
+    }
     //Deposit token to confidential state
-    //Converts normal tokens -> confidential tokens
-    let deposit_sig=token.confidential_transfer_deposit(
-        &ata_pubkey,//deestination ata
-        &payer.pubkey(),//authority(owner) of the account
-        50*10u64.pow(mint::TOKEN_DECIMALS as u32),//amount to deposit
-        mint::TOKEN_DECIMALS,//decimals
-        &[&payer]//signer(owner of the ata)
-    ).await?;
-    println!("Confidential transfer deposit transaction signature: {}", deposit_sig);
-    //Appy pending balance to make the funds available for confidential transfers
-    let apply_signature=token.confidential_transfer_apply_pending_balance(
-        &ata_pubkey,//ata public key
-        &payer.pubkey(),//owner of the ata
-        None,//Optional new decryptable available balance
-        elgamal_keypair.secret(),
-        &aeskey,
-        &[&payer],//Signers(owner must sign)
-    ).await?;
-    println!("Apply pending balance transaction signature: {}", apply_signature);
-    println!("Confidential transfer setup complete.Tokens are now available for confidential transfers.");
+    if !state.is_completed(flow::FlowStep::Deposit) {
+        let deposit_sig = deposit::deposit(
+            &token,
+            &owner,
+            &ata_pubkey,
+            Amount::whole(50).to_base_units(decimals)?,
+            decimals,
+            None,
+        )
+        .await?;
+        info!(
+            "Confidential transfer deposit transaction signature: {}",
+            deposit_sig
+        );
+        state.complete(flow::FlowStep::Deposit, state_path)?;
+    }
+    //Apply pending balance to make the funds available for confidential transfers
+    if !state.is_completed(flow::FlowStep::Apply) {
+        let apply_sig = deposit::apply_pending_balance(
+            &token,
+            &owner,
+            &ata_pubkey,
+            &configured.elgamal_keypair,
+            &configured.aes_key,
+        )
+        .await?;
+        info!("Apply pending balance transaction signature: {}", apply_sig);
+        info!(
+            "Confidential transfer setup complete.Tokens are now available for confidential transfers."
+        );
+        state.complete(flow::FlowStep::Apply, state_path)?;
+    }
     //Withdraw tokens from confidential state back to normal tokens
-    let withdraw_amount=20*10u64.pow(mint::TOKEN_DECIMALS as u32);
-    let token_account=token.get_account_info(&mint_keypair.pubkey()).await?;
-    let extension_data=token_account.get_extension::<ConfidentialTransferAccount>()?;
-    //Confidential transfer extension information needed to construct a withdraw instruction 
-    let withdraw_account=WithdrawAccountInfo::new(
-        extension_data,
-    );
-    //create keypairs for the proof accounts
-    let equality_proof_context_state_keypair=Keypair::new();
-    let equality_proof_context_state_pubkey=equality_proof_context_state_keypair.pubkey();
-    let range_proof_context_state_keypair=Keypair::new();
-    let range_proof_context_state_pubkey=range_proof_context_state_keypair.pubkey();
-    //Withdraw proof data
-    let WithdrawProofData{
-        equality_proof_data,
-        range_proof_data,
-    }=withdraw_account.generate_proof_data(withdraw_amount, &elgamal_keypair, &aeskey)?;
-    //Generate equality proof account
-    let equality_proof_sig=token.confidential_transfer_create_context_state_account(
-        &equality_proof_context_state_pubkey,//Public key for the equality proof account
-        &payer.pubkey(),//Authority that can manage the account
-        &equality_proof_data,//Proof data for the equality proof
-        false,//Fals:combine account creation+proof verification in one transaction
-        &[&payer,&equality_proof_context_state_keypair],//signer of the new account
-    ).await?;
-    println!("Equality proof account creation transaction signature: {}", equality_proof_sig);
-    //Generate range proof account
-    let range_proof_sig=token.confidential_transfer_create_context_state_account(
-        &range_proof_context_state_pubkey,//Public key for the range proof account
-        &payer.pubkey(),//Authority that can manage the account
-        &range_proof_data,//Proof data for the range proof
-        false,//Fals:combine account creation+proof verification in one transaction
-        &[&payer,&range_proof_context_state_keypair],//signer of the new account
-    ).await?;
-    println!("Range proof account creation transaction signature: {}", range_proof_sig);
-    println!("Performing withdrawl from confidential state back to normal tokens...");
-    //Perform the withdraw from confidential state back to normal tokens
-    let withdraw_sig=token.confidential_transfer_withdraw(
-        &ata_pubkey,//Source ata
-        &payer.pubkey(),//Owner of the ata
-       Some(&spl_token_client::token::ProofAccount::ContextAccount(
-        equality_proof_context_state_pubkey//Reference to equality proof account
-       )),
-         Some(&spl_token_client::token::ProofAccount::ContextAccount(
-        range_proof_context_state_pubkey//Reference to range proof account
-         )),
-         withdraw_amount,//Amount to withdraw
-        mint::TOKEN_DECIMALS,//decimals
-        Some(withdraw_account),
-        &elgamal_keypair,
-        &aeskey,
-        &[&payer],
-    ).await?;
-    println!("Confidential transfer withdraw transaction signature: {}", withdraw_sig);
-    //Close the context state accounts to recover rent
-    println!("Closing proof context state accounts to recover rent...");
-    let close_equality_sig=token.confidential_transfer_close_context_state_account(
-        &equality_proof_context_state_pubkey,//Public key of the equality proof account
-        &payer.pubkey(),//Authority that can close the account
-        &payer.pubkey(),//Destination to receive recovered rent
-        &[&payer],//Signer(authority)
-
-    ).await?;
-    println!("Close equality proof account transaction signature: {}", close_equality_sig);
-    let close_range_sig=token.confidential_transfer_close_context_state_account(
-        &range_proof_context_state_pubkey,//Public key of the range proof account
-        &payer.pubkey(),//Authority that can close the account
-        &payer.pubkey(),//Destination to receive recovered rent
-        &[&payer],//Signer(authority)  
-    ).await?;
-    println!("Close range proof account transaction signature: {}", close_range_sig);
+    if !state.is_completed(flow::FlowStep::Withdraw) {
+        //Recover the rent of proof accounts left by an interrupted withdraw
+        if !state.proof_accounts.is_empty() {
+            let signatures = flow::close_leftover_proof_accounts(
+                &token,
+                owner.proof_authority(),
+                &state.proof_accounts,
+            )
+            .await?;
+            for signature in signatures {
+                info!("Closed leftover proof account: {}", signature);
+            }
+            state.proof_accounts.clear();
+            state.save(state_path)?;
+        }
+        let available =
+            balance::get_available_balance(&token, &ata_pubkey, &configured.aes_key).await?;
+        match state.balance_before_withdraw {
+            //The withdraw landed before the interruption
+            Some(before) if before != available => {
+                info!("Withdraw already confirmed in the interrupted run");
+            }
+            _ => {
+                state.balance_before_withdraw = Some(available);
+                let proof_keypairs = [keygen::new_keypair(), keygen::new_keypair()];
+                state.proof_accounts = proof_keypairs.iter().map(|k| k.pubkey()).collect();
+                state.save(state_path)?;
+                let result = withdraw::withdraw_with_proof_accounts(
+                    &token,
+                    &owner,
+                    &ata_pubkey,
+                    Amount::whole(20).to_base_units(decimals)?,
+                    decimals,
+                    &configured.elgamal_keypair,
+                    &configured.aes_key,
+                    None,
+                    Packing::Separate,
+                    &proof_keypairs,
+                )
+                .await?;
+                print_withdraw(&result);
+                state.proof_accounts.clear();
+            }
+        }
+        state.complete(flow::FlowStep::Withdraw, state_path)?;
+    }
+    let balances = balance::get_balances(
+        &token,
+        &ata_pubkey,
+        &configured.elgamal_keypair,
+        &configured.aes_key,
+        &DecodeTable::open_default()?,
+    )
+    .await?;
+    print_balances(&balances, decimals);
+    std::fs::remove_file(state_path)
+        .with_context(|| format!("Unable to remove state file {}", state_path.display()))?;
+    info!("Flow complete; removed state file {}", state_path.display());
     Ok(())
-}
\ No newline at end of file
+}
+
+// Asks `question` on stderr and reads the answer from stdin; anything but yes declines
+fn confirm(question: &str) -> Result<bool> {
+    eprint!("{question} [y/N] ");
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
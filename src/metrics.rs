@@ -0,0 +1,155 @@
+use anyhow::{Context, Result};
+use axum::{
+    Router,
+    http::{StatusCode, header::CONTENT_TYPE},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder, exponential_buckets,
+};
+use solana_sdk::pubkey::Pubkey;
+use std::{net::SocketAddr, sync::LazyLock, time::Duration};
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+//Registered once, on first use; every instance of the library in a process shares them
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+struct Metrics {
+    registry: Registry,
+    transactions_sent: IntCounter,
+    confirmation_seconds: Histogram,
+    proof_generation_seconds: Histogram,
+    rpc_errors: IntCounterVec,
+    pending_credits: IntGaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let transactions_sent = IntCounter::new(
+            "confidential_transfer_transactions_sent_total",
+            "Transactions accepted by the RPC node",
+        )
+        .unwrap();
+        //From 0.1s to about 100s, past the lifetime of a blockhash
+        let confirmation_seconds = Histogram::with_opts(
+            HistogramOpts::new(
+                "confidential_transfer_confirmation_seconds",
+                "Time from sending a transaction to its confirmation",
+            )
+            .buckets(exponential_buckets(0.1, 2.0, 11).unwrap()),
+        )
+        .unwrap();
+        //From 10ms to about 40s
+        let proof_generation_seconds = Histogram::with_opts(
+            HistogramOpts::new(
+                "confidential_transfer_proof_generation_seconds",
+                "Time spent generating the proofs of an operation",
+            )
+            .buckets(exponential_buckets(0.01, 2.0, 13).unwrap()),
+        )
+        .unwrap();
+        let rpc_errors = IntCounterVec::new(
+            Opts::new(
+                "confidential_transfer_rpc_errors_total",
+                "Failed RPC calls, retried ones included, by method",
+            ),
+            &["method"],
+        )
+        .unwrap();
+        let pending_credits = IntGaugeVec::new(
+            Opts::new(
+                "confidential_transfer_pending_credits",
+                "Credits waiting in the pending balance of an account, as last seen",
+            ),
+            &["account"],
+        )
+        .unwrap();
+        let registry = Registry::new();
+        for collector in [
+            Box::new(transactions_sent.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(confirmation_seconds.clone()),
+            Box::new(proof_generation_seconds.clone()),
+            Box::new(rpc_errors.clone()),
+            Box::new(pending_credits.clone()),
+        ] {
+            registry.register(collector).unwrap();
+        }
+        Self {
+            registry,
+            transactions_sent,
+            confirmation_seconds,
+            proof_generation_seconds,
+            rpc_errors,
+            pending_credits,
+        }
+    }
+}
+
+/// Sets the pending credit counter of `account`, e.g. from a balance just decrypted or a
+/// credit just seen; the backlog of applies due is read from it.
+pub fn set_pending_credits(account: &Pubkey, counter: u64) {
+    METRICS
+        .pending_credits
+        .with_label_values(&[&account.to_string()])
+        .set(i64::try_from(counter).unwrap_or(i64::MAX));
+}
+
+/// Metrics of the process in the Prometheus text format.
+pub fn render() -> String {
+    let mut rendered = Vec::new();
+    //Writing to a vector only fails on metrics with invalid names, which are fixed here
+    TextEncoder::new()
+        .encode(&METRICS.registry.gather(), &mut rendered)
+        .expect("metrics encode");
+    String::from_utf8(rendered).expect("metrics are UTF-8")
+}
+
+/// Serves [`render`] at `GET /metrics` on `bind` in the background, for the processes that have
+/// no HTTP server of their own, such as `watch-and-apply`. Fails if `bind` cannot be listened on.
+pub async fn spawn_server(bind: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(bind)
+        .await
+        .with_context(|| format!("Unable to listen on {bind}"))?;
+    if !bind.ip().is_loopback() {
+        warn!("Serving metrics on {bind}: they list the owner's accounts");
+    }
+    info!(%bind, "Serving metrics");
+    let router = Router::new().route("/metrics", get(respond));
+    tokio::spawn(async move {
+        if let Err(err) = axum::serve(listener, router).await {
+            warn!("Metrics server stopped: {err}");
+        }
+    });
+    Ok(())
+}
+
+// Answers `GET /metrics`
+pub(crate) async fn respond() -> Response {
+    (
+        StatusCode::OK,
+        [(CONTENT_TYPE, TextEncoder::new().format_type().to_string())],
+        render(),
+    )
+        .into_response()
+}
+
+pub(crate) fn transaction_sent() {
+    METRICS.transactions_sent.inc();
+}
+
+pub(crate) fn observe_confirmation(elapsed: Duration) {
+    METRICS.confirmation_seconds.observe(elapsed.as_secs_f64());
+}
+
+pub(crate) fn observe_proof_generation(elapsed: Duration) {
+    METRICS
+        .proof_generation_seconds
+        .observe(elapsed.as_secs_f64());
+}
+
+pub(crate) fn rpc_error(method: &str) {
+    METRICS.rpc_errors.with_label_values(&[method]).inc();
+}
@@ -1,133 +1,862 @@
-use anyhow::Result;
-use solana_client::nonblocking::rpc_client::RpcClient;
+use anyhow::{Result, anyhow, bail};
+use confidential_transfer_core::{derivation::SeedMessage, proofs::pubkey_validity_proof};
 use solana_sdk::{
-   
-    pubkey::Pubkey, signature::Keypair, signer::Signer, transaction::Transaction
+    account::from_account,
+    hash::Hash,
+    instruction::Instruction,
+    message::Message,
+    pubkey::Pubkey,
+    rent::Rent,
+    signature::{Keypair, Signature},
+    signer::Signer,
+    signers::Signers,
+    system_instruction::create_account,
+    sysvar,
+    transaction::Transaction,
 };
 use spl_associated_token_account::{
-    get_associated_token_address_with_program_id, instruction::create_associated_token_account,
+    get_associated_token_address_with_program_id,
+    instruction::create_associated_token_account_idempotent,
 };
 use spl_token_client::{
-    client::{ProgramRpcClient, ProgramRpcClientSendTransaction},
+    client::ProgramClient,
     spl_token_2022::{
         extension::{
-            ExtensionType,
-            confidential_transfer::instruction::{PubkeyValidityProofData, configure_account},
+            BaseStateWithExtensions, ExtensionType, StateWithExtensionsOwned,
+            confidential_mint_burn,
+            confidential_transfer::{
+                ConfidentialTransferAccount, ConfidentialTransferMint,
+                instruction::configure_account,
+            },
+            transfer_fee::TransferFeeConfig,
         },
         id as token_2022_program_id,
-        instruction::reallocate,
-        solana_zk_sdk::encryption::{auth_encryption::AeKey, elgamal::ElGamalKeypair},
+        instruction::{initialize_account3, initialize_mint2, reallocate},
+        solana_zk_sdk::{
+            encryption::{
+                auth_encryption::AeKey,
+                elgamal::{ElGamalKeypair, ElGamalPubkey},
+            },
+            zk_elgamal_proof_program::proof_data::PubkeyValidityProofData,
+        },
+        state::{Account, Mint},
     },
-    token::{ExtensionInitializationParams, Token},
+    token::{ExtensionInitializationParams, Token, TokenError},
 };
-use spl_token_confidential_transfer_proof_extraction::instruction::{ProofData, ProofLocation};
+use spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation;
 use std::sync::Arc;
+use tracing::{debug, instrument};
+
+use crate::{
+    TokenClient,
+    authority::Authority,
+    client::{ComputeBudget, DEFAULT_RESEND_DEADLINE, RpcSender, resend_on_expiry},
+    keygen,
+    ledger::derive_with,
+    placement::{ProofCost, ProofPlacement, plan_proof_placements, proof_location},
+    registry::derive_registry_keypair,
+    transfer::{
+        close_proof_accounts, create_proof_account, get_auditor_elgamal_pubkey, get_elgamal_pubkey,
+    },
+    utils::into_signature,
+};
 
+/// Default decimal precision of new mints.
 pub const TOKEN_DECIMALS: u8 = 9;
-//The maximum number of Deposit or Transfer instructions that can credit (add) to the 
+//The maximum number of Deposit or Transfer instructions that can credit (add) to the
 //pending_balance before the recipient must issue an ApplyPendingBalance instruction.
 const MAXIMUM_PENDING_BALANCE_COUNTER: u64 = 128;
 
-// Function to initialize a new token mint with ConfidentialTransferMint extension
+/// Settings of a new confidential transfer mint.
+#[derive(Debug, Clone, Copy)]
+pub struct MintOptions {
+    /// Decimal precision.
+    pub decimals: u8,
+    /// ElGamal pubkey every transfer amount is additionally encrypted to, so the holder of the
+    /// matching secret key can audit transfers.
+    pub auditor_elgamal_pubkey: Option<ElGamalPubkey>,
+    /// Approve newly configured accounts automatically; when `false` each account must be
+    /// approved by the confidential transfer authority before it can be used.
+    pub auto_approve_new_accounts: bool,
+    /// Transfer fee charged on every transfer, if any.
+    pub transfer_fee: Option<TransferFeeOptions>,
+    /// Add the `ConfidentialMintBurn` extension, so tokens are minted into and burned from
+    /// confidential balances and the supply is encrypted.
+    pub confidential_mint_burn: bool,
+}
+
+/// Transfer fee settings of a new mint.
+#[derive(Debug, Clone, Copy)]
+pub struct TransferFeeOptions {
+    /// Fee rate in basis points of the transfer amount.
+    pub basis_points: u16,
+    /// Maximum fee per transfer, in base units.
+    pub maximum_fee: u64,
+    /// ElGamal pubkey confidential transfer fees are encrypted to; the matching secret key is
+    /// needed to withdraw the withheld fees.
+    pub withdraw_withheld_authority_elgamal_pubkey: ElGamalPubkey,
+}
+
+impl Default for MintOptions {
+    fn default() -> Self {
+        Self {
+            decimals: TOKEN_DECIMALS,
+            auditor_elgamal_pubkey: None,
+            auto_approve_new_accounts: true,
+            transfer_fee: None,
+            confidential_mint_burn: false,
+        }
+    }
+}
+
+/// A newly created confidential transfer mint.
+pub struct CreatedMint {
+    /// Keypair of the mint account.
+    pub mint_keypair: Keypair,
+    /// Token client bound to the new mint.
+    pub token: TokenClient,
+    /// Signature of the mint creation transaction.
+    pub signature: Signature,
+}
+
+/// A token account configured for confidential transfers, with its client-side key material.
+pub struct ConfiguredAccount {
+    /// Token account address: the associated token account, or the auxiliary account created by
+    /// [`create_configure_account`].
+    pub ata: Pubkey,
+    /// ElGamal keypair used for decryption and zero-knowledge proofs.
+    pub elgamal_keypair: ElGamalKeypair,
+    /// AES key used to encrypt the decryptable available balance.
+    pub aes_key: AeKey,
+    /// Signature of the create + configure transaction; `None` if the account was already
+    /// configured.
+    pub signature: Option<Signature>,
+}
+
+/// Creates a new Token-2022 mint with the `ConfidentialTransferMint` extension, configured by
+/// `options`.
+///
+/// `fee_payer` pays for the transaction and the mint account and becomes the payer of the
+/// returned token client, which like the mint creation adds the instructions of
+/// `compute_budget` to every transaction. `mint_authority` is the mint authority and the
+/// confidential transfer authority, and with `options.transfer_fee` also the transfer fee config
+/// and withdraw withheld authority. With `options.confidential_mint_burn` the confidential supply
+/// is encrypted under keys derived from the mint authority (see [`derive_supply_keys`]).
+#[instrument(skip_all, fields(mint_authority = %mint_authority.pubkey(), decimals = options.decimals))]
 pub async fn initialize_mint(
-    rpc_client: Arc<RpcClient>,
-    payer: Arc<dyn Signer>,
-) -> Result<(Keypair, Token<ProgramRpcClientSendTransaction>)> {
-    let mint_keypair=Keypair::new();
-  
-    let program_client=ProgramRpcClient::new(rpc_client.clone(),ProgramRpcClientSendTransaction);
-    let token=Token::new(
-        Arc::new(program_client),
+    program_client: Arc<dyn ProgramClient<RpcSender>>,
+    fee_payer: Arc<dyn Signer>,
+    mint_authority: &dyn Signer,
+    options: &MintOptions,
+    compute_budget: &ComputeBudget,
+) -> Result<CreatedMint> {
+    let mint_keypair = keygen::new_keypair();
+    debug!(mint = %mint_keypair.pubkey(), "Generated mint keypair");
+    //Supply keys: the ElGamal pubkey encrypts the supply, the AES key its decryptable copy
+    let supply_keys = options
+        .confidential_mint_burn
+        .then(|| derive_supply_keys(mint_authority, &mint_keypair.pubkey()))
+        .transpose()?;
+    let mint_authority = &mint_authority.pubkey();
+    let token = compute_budget.apply(Token::new(
+        program_client.clone(),
         &token_2022_program_id(),
         &mint_keypair.pubkey(),
-        Some(TOKEN_DECIMALS),
-        payer.clone()
-    );
+        Some(options.decimals),
+        fee_payer.clone(),
+    ));
     //ConfidentialTransferMint extension enables confidential (private) transfers of tokens
-    let extension_init_params=vec![
-        ExtensionInitializationParams::ConfidentialTransferMint { 
-            authority: Some(payer.pubkey()), //Authority to manage confidential transfer settings
-            auto_approve_new_accounts: true, //Automatically approve new confidential transfer accounts
-            auditor_elgamal_pubkey: None //No auditor 
+    let mut extension_init_params = vec![ExtensionInitializationParams::ConfidentialTransferMint {
+        authority: Some(*mint_authority), //Authority to manage confidential transfer settings
+        auto_approve_new_accounts: options.auto_approve_new_accounts, //Skip manual approval
+        auditor_elgamal_pubkey: options.auditor_elgamal_pubkey.map(Into::into), //Optional auditor
+    }];
+    //Fee mints need both the TransferFeeConfig and the ConfidentialTransferFeeConfig extension
+    if let Some(transfer_fee) = options.transfer_fee {
+        extension_init_params.push(ExtensionInitializationParams::TransferFeeConfig {
+            transfer_fee_config_authority: Some(*mint_authority), //Authority to change the fee
+            withdraw_withheld_authority: Some(*mint_authority),   //Authority to withdraw fees
+            transfer_fee_basis_points: transfer_fee.basis_points,
+            maximum_fee: transfer_fee.maximum_fee,
+        });
+        extension_init_params.push(
+            ExtensionInitializationParams::ConfidentialTransferFeeConfig {
+                authority: Some(*mint_authority), //Authority to change the withheld ElGamal pubkey
+                withdraw_withheld_authority_elgamal_pubkey: transfer_fee
+                    .withdraw_withheld_authority_elgamal_pubkey
+                    .into(),
+            },
+        );
+    }
+    let Some((supply_elgamal_keypair, supply_aes_key)) = supply_keys else {
+        let response = token
+            .create_mint(
+                mint_authority,       //Mint authority
+                Some(mint_authority), //Freeze authority
+                extension_init_params,
+                &[&mint_keypair],
+            )
+            .await?;
+        return Ok(CreatedMint {
+            mint_keypair,
+            token,
+            signature: into_signature(response)?,
+        });
+    };
+
+    //The token client has no initialization parameters for ConfidentialMintBurn, so the mint
+    //creation transaction is assembled here
+    let mint = mint_keypair.pubkey();
+    let mut extension_types = extension_init_params
+        .iter()
+        .map(ExtensionInitializationParams::extension)
+        .collect::<Vec<_>>();
+    extension_types.push(ExtensionType::ConfidentialMintBurn);
+    let space = ExtensionType::try_calculate_account_len::<Mint>(&extension_types)?;
+    let rent = program_client
+        .get_minimum_balance_for_rent_exemption(space)
+        .await
+        .map_err(|e| anyhow!(e))?;
+    let mut ixs = vec![create_account(
+        &fee_payer.pubkey(),      //Payer of the mint account
+        &mint,                    //Mint account
+        rent,                     //Rent-exempt balance
+        space as u64,             //Space including all extensions
+        &token_2022_program_id(), //Owner program
+    )];
+    for params in extension_init_params {
+        ixs.push(params.instruction(&token_2022_program_id(), &mint)?);
+    }
+    ixs.push(confidential_mint_burn::instruction::initialize_mint(
+        &token_2022_program_id(),
+        &mint,
+        &(*supply_elgamal_keypair.pubkey()).into(), //Supply ElGamal pubkey
+        &supply_aes_key.encrypt(0).into(),          //Initial decryptable supply
+    )?);
+    ixs.push(initialize_mint2(
+        &token_2022_program_id(),
+        &mint,
+        mint_authority,       //Mint authority
+        Some(mint_authority), //Freeze authority
+        options.decimals,
+    )?);
+    let response = token.process_ixs(&ixs, &[&mint_keypair]).await?;
+    Ok(CreatedMint {
+        mint_keypair,
+        token,
+        signature: into_signature(response)?,
+    })
+}
+
+/// Builds a token client for an existing mint, reading its decimals from chain.
+///
+/// `fee_payer` pays the fees (and new account rent) of every transaction sent through the
+/// client, which adds the instructions of `compute_budget` to each. Returns the client together
+/// with the mint decimals.
+#[instrument(skip_all, fields(%mint))]
+pub async fn load_token(
+    program_client: Arc<dyn ProgramClient<RpcSender>>,
+    fee_payer: Arc<dyn Signer>,
+    mint: &Pubkey,
+    compute_budget: &ComputeBudget,
+) -> Result<(TokenClient, u8)> {
+    let decimals = Token::new(
+        program_client.clone(),
+        &token_2022_program_id(),
+        mint,
+        None,
+        fee_payer.clone(),
+    )
+    .get_mint_info()
+    .await?
+    .base
+    .decimals;
+    debug!(decimals, "Fetched mint");
+    let token = compute_budget.apply(Token::new(
+        program_client,
+        &token_2022_program_id(),
+        mint,
+        Some(decimals),
+        fee_payer,
+    ));
+    Ok((token, decimals))
+}
+
+/// Mints `amount` normal (transparent) tokens to `ata_pubkey`.
+#[instrument(skip_all, fields(ata = %ata_pubkey, amount))]
+pub async fn mint_to(
+    token: &TokenClient,
+    mint_authority: Arc<dyn Signer>,
+    ata_pubkey: &Pubkey,
+    amount: u64,
+) -> Result<Signature> {
+    let response = token
+        .mint_to(
+            ata_pubkey,               //Destination ata
+            &mint_authority.pubkey(), //Mint authority
+            amount,                   //Amount to mint
+            &[&mint_authority],       //Signers
+        )
+        .await?;
+    into_signature(response)
+}
+
+/// Changes to the confidential transfer settings of an existing mint; `None` fields keep the
+/// current value.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MintUpdate {
+    /// Approve newly configured accounts automatically.
+    pub auto_approve_new_accounts: Option<bool>,
+    /// New auditor ElGamal pubkey, or `Some(None)` to remove the auditor.
+    pub auditor_elgamal_pubkey: Option<Option<ElGamalPubkey>>,
+}
+
+/// Confidential transfer settings of a mint after [`update_mint`].
+#[derive(Debug, Clone, Copy)]
+pub struct UpdatedMint {
+    /// Whether new accounts are approved automatically.
+    pub auto_approve_new_accounts: bool,
+    /// Auditor ElGamal pubkey, if any.
+    pub auditor_elgamal_pubkey: Option<ElGamalPubkey>,
+    /// Signature of the update mint instruction.
+    pub signature: Signature,
+}
+
+/// Updates the confidential transfer settings of the mint of `token` with `UpdateMint`.
+///
+/// The instruction always sets both the auto-approve flag and the auditor, so the fields left
+/// unset in `update` are read from the mint first. `authority` must be the confidential transfer
+/// authority. A new auditor key only applies to later transfers; earlier ones stay readable with
+/// the previous key only.
+#[instrument(skip_all, fields(mint = %token.get_address(), authority = %authority.pubkey()))]
+pub async fn update_mint(
+    token: &TokenClient,
+    authority: Arc<dyn Signer>,
+    update: MintUpdate,
+) -> Result<UpdatedMint> {
+    let mint_info = token.get_mint_info().await?;
+    let extension = mint_info.get_extension::<ConfidentialTransferMint>()?;
+    match Option::<Pubkey>::from(extension.authority) {
+        Some(current) if current == authority.pubkey() => {}
+        Some(current) => bail!("The confidential transfer authority of the mint is {current}"),
+        None => bail!("The confidential transfer settings of the mint are immutable"),
+    }
+    let auto_approve_new_accounts = update
+        .auto_approve_new_accounts
+        .unwrap_or_else(|| bool::from(extension.auto_approve_new_accounts));
+    let auditor_elgamal_pubkey = match update.auditor_elgamal_pubkey {
+        Some(auditor_elgamal_pubkey) => auditor_elgamal_pubkey,
+        None => get_auditor_elgamal_pubkey(token).await?,
+    };
+    let response = token
+        .confidential_transfer_update_mint(
+            &authority.pubkey(), //Confidential transfer authority
+            auto_approve_new_accounts,
+            auditor_elgamal_pubkey.map(Into::into),
+            &[&authority],
+        )
+        .await?;
+    let signature = into_signature(response)?;
+    debug!(%signature, auto_approve_new_accounts, auditor = auditor_elgamal_pubkey.is_some(), "Updated mint");
+    Ok(UpdatedMint {
+        auto_approve_new_accounts,
+        auditor_elgamal_pubkey,
+        signature,
+    })
+}
+
+/// Returns the Token-2022 associated token account of `owner` for `mint`.
+pub fn get_ata(owner: &Pubkey, mint: &Pubkey) -> Pubkey {
+    get_associated_token_address_with_program_id(owner, mint, &token_2022_program_id())
+}
+
+/// Derives the ElGamal keypair and AES key of a token account from its owner's signature of
+/// `seed_message`.
+///
+/// Derivation is deterministic, so the keys can be re-derived for an already configured account.
+/// Hardware wallets sign the derivation messages as off-chain messages, see
+/// [`OffchainMessageSigner`](crate::ledger::OffchainMessageSigner).
+pub fn derive_keys(
+    owner: &dyn Signer,
+    seed_message: &SeedMessage,
+    ata_pubkey: &Pubkey,
+) -> Result<(ElGamalKeypair, AeKey)> {
+    derive_with(owner, |owner| seed_message.derive(owner, ata_pubkey))
+}
+
+/// Derives the keys of an existing token account, configured either with [`derive_keys`] or from
+/// the owner's ElGamal registry (see [`derive_registry_keypair`]).
+///
+/// The derived ElGamal pubkeys are checked against the one registered in the account, so keys of
+/// the wrong owner or seed message are rejected before any proof is generated. When the keys of
+/// a custom `seed_message` do not match, the default one is tried as well to tell which seed
+/// message the account was configured with.
+pub async fn load_keys(
+    token: &TokenClient,
+    owner: &dyn Signer,
+    seed_message: &SeedMessage,
+    ata_pubkey: &Pubkey,
+) -> Result<(ElGamalKeypair, AeKey)> {
+    let registered_elgamal_pubkey = get_elgamal_pubkey(token, ata_pubkey).await?;
+    derive_matching_keys(owner, seed_message, ata_pubkey, &registered_elgamal_pubkey)
+}
+
+/// Same as [`load_keys`] with the registered ElGamal pubkey already known, e.g. from
+/// [`ProofInputs`](crate::proofs::ProofInputs) on a machine without network access.
+pub fn derive_matching_keys(
+    owner: &dyn Signer,
+    seed_message: &SeedMessage,
+    ata_pubkey: &Pubkey,
+    registered_elgamal_pubkey: &ElGamalPubkey,
+) -> Result<(ElGamalKeypair, AeKey)> {
+    let (elgamal_keypair, aes_key) = derive_keys(owner, seed_message, ata_pubkey)?;
+    if elgamal_keypair.pubkey() == registered_elgamal_pubkey {
+        return Ok((elgamal_keypair, aes_key));
+    }
+    //Accounts configured from a registry share the wallet-level ElGamal keypair
+    let registry_keypair = derive_registry_keypair(owner)?;
+    if registry_keypair.pubkey() == registered_elgamal_pubkey {
+        return Ok((registry_keypair, aes_key));
+    }
+    if *seed_message != SeedMessage::default() {
+        let (default_keypair, _) = derive_keys(owner, &SeedMessage::default(), ata_pubkey)?;
+        if default_keypair.pubkey() == registered_elgamal_pubkey {
+            bail!(
+                "{ata_pubkey} was configured with keys of seed message {}, not {seed_message}",
+                SeedMessage::default()
+            );
         }
-    ];
-   
-    let transaction_sig=token
-    .create_mint(
-        &payer.pubkey(),
-        Some(&payer.pubkey()),
-        extension_init_params,
-        &[&mint_keypair],
-    ).await?;
-    println!("Mint creation transaction signature: {}", transaction_sig);
-   
-     Ok((mint_keypair, token))   
-}
-
-// Function to create and configure an associated token account (ATA) for confidential transfers
+    }
+    bail!(
+        "The ElGamal pubkey of {ata_pubkey} was not derived from {} with seed message {seed_message}",
+        owner.pubkey()
+    )
+}
+
+/// Derives the ElGamal keypair and AES key encrypting the confidential supply of `mint` from the
+/// mint authority's signature.
+///
+/// Only used by mints with the `ConfidentialMintBurn` extension. Like [`derive_keys`], the keys
+/// can be re-derived at any time, so the mint authority needs no extra key files.
+pub fn derive_supply_keys(
+    mint_authority: &dyn Signer,
+    mint: &Pubkey,
+) -> Result<(ElGamalKeypair, AeKey)> {
+    let seed = [b"confidential-supply".as_slice(), mint.as_ref()].concat();
+    derive_with(mint_authority, |mint_authority| {
+        let elgamal_keypair = ElGamalKeypair::new_from_signer(mint_authority, &seed)
+            .map_err(|e| anyhow::anyhow!("Failed to generate supply ElGamal keypair: {e}"))?;
+        let aes_key = AeKey::new_from_signer(mint_authority, &seed)
+            .map_err(|e| anyhow::anyhow!("Failed to generate supply AES key: {e}"))?;
+        Ok((elgamal_keypair, aes_key))
+    })
+}
+
+/// Creates the associated token account of `owner` for the mint of `token` and configures it for
+/// confidential transfers in a single transaction.
+///
+/// Safe to re-run: an existing associated token account is reused, and one already configured is
+/// returned with its keys without sending a transaction. `fee_payer` must be the payer of
+/// `token`; it funds the account rent and the reallocation. For a multisig owner the keys are
+/// derived from [`Authority::key_source`].
+#[instrument(skip_all, fields(mint = %token.get_address(), owner = %owner.pubkey()))]
 pub async fn create_configure_ata(
-    rpc_client: Arc<RpcClient>,
-    payer: Arc<dyn Signer>,
-    mint_keypair: &Keypair,
-) -> Result<(Pubkey,ElGamalKeypair,AeKey)> {
-     //Configure token account for confidential transfers
-    let ata_pubkey=get_associated_token_address_with_program_id(
-        &payer.pubkey(),//Owner of the token account
-        &mint_keypair.pubkey(),//Token mint
-        &token_2022_program_id(),//Token program ID
+    token: &TokenClient,
+    fee_payer: &Pubkey,
+    owner: &Authority,
+) -> Result<ConfiguredAccount> {
+    //Configure token account for confidential transfers
+    let ata_pubkey = get_ata(
+        &owner.pubkey(),     //Owner of the token account
+        token.get_address(), //Token mint
     );
-    //Step1:Creating associated token account 
-    let created_ata_ix=create_associated_token_account(
-        &payer.pubkey(),//Payer for the creation of token account
-        &payer.pubkey(),//Owner of the token account
-        &mint_keypair.pubkey(),//Token mint
-        &token_2022_program_id(),//Token program ID
+    if account_state(token, &ata_pubkey).await? == AccountState::Configured {
+        return already_configured(token, owner, ata_pubkey).await;
+    }
+    //Generate ElGamal keypair and AES key for token account
+    let (elgamal_keypair, aes_key) = derive_keys(
+        owner.key_source().as_ref(),
+        owner.seed_message(),
+        &ata_pubkey,
+    )?;
+    create_configure_ata_with_keys(token, fee_payer, owner, elgamal_keypair, aes_key).await
+}
+
+/// Like [`create_configure_ata`], but configures the associated token account with the given
+/// keys instead of ones derived from the owner.
+///
+/// The account must not be configured yet. Keys that cannot be re-derived must be stored by the
+/// caller, e.g. in a [`Keystore`](crate::keystore::Keystore).
+#[instrument(skip_all, fields(mint = %token.get_address(), owner = %owner.pubkey()))]
+pub async fn create_configure_ata_with_keys(
+    token: &TokenClient,
+    fee_payer: &Pubkey,
+    owner: &Authority,
+    elgamal_keypair: ElGamalKeypair,
+    aes_key: AeKey,
+) -> Result<ConfiguredAccount> {
+    let mint = token.get_address();
+    let mint_info = token.get_mint_info().await?;
+    let multisig_signers = owner.multisig_signers();
+    let multisig_signers = multisig_signers.iter().collect::<Vec<_>>();
+    let ata_pubkey = get_ata(&owner.pubkey(), mint);
+    //Step1:Creating associated token account, unless it already exists
+    let created_ata_ix = create_associated_token_account_idempotent(
+        fee_payer,                //Payer for the creation of token account
+        &owner.pubkey(),          //Owner of the token account
+        mint,                     //Token mint
+        &token_2022_program_id(), //Token program ID
     );
     //Step2:Reallocate the token account to include space for ConfidentialTransferAccount extension
-    let reallocate_ix=reallocate(
-        &token_2022_program_id(),//Token program ID
-        &ata_pubkey,//ATA public key
-        &payer.pubkey(),//Payer
-        &payer.pubkey(),//Token account owner
-        &[&payer.pubkey()],//Signers
-        &[ExtensionType::ConfidentialTransferAccount]//Extensions to add
+    let reallocate_ix = reallocate(
+        &token_2022_program_id(),                     //Token program ID
+        &ata_pubkey,                                  //ATA public key
+        fee_payer,                                    //Payer
+        &owner.pubkey(),                              //Token account owner
+        &multisig_signers,                            //Multisig signers
+        &confidential_account_extensions(&mint_info), //Extensions to add
+    )?;
+    let signature = send_configure(
+        token,
+        &ata_pubkey,
+        owner,
+        vec![created_ata_ix, reallocate_ix],
+        owner.signers(),
+        &elgamal_keypair,
+        &aes_key,
+    )
+    .await?;
+
+    Ok(ConfiguredAccount {
+        ata: ata_pubkey,
+        elgamal_keypair,
+        aes_key,
+        signature: Some(signature),
+    })
+}
+
+/// Unsigned transaction creating the associated token account of the wallet `owner` if needed and
+/// configuring it with the given keys, for the wallet to sign and send.
+///
+/// Meant for keys the wallet cannot derive itself, e.g. from the signatures of a browser wallet
+/// through [`SeedMessage::keys_from_signed_messages`]. The owner pays the fees and the rent, and
+/// the pubkey validity proof is verified in the same transaction, which expires with
+/// `recent_blockhash`. Multisig owners are not supported.
+pub async fn configure_ata_transaction(
+    token: &TokenClient,
+    owner: &Pubkey,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+    recent_blockhash: Hash,
+) -> Result<Transaction> {
+    let mint = token.get_address();
+    let mint_info = token.get_mint_info().await?;
+    let ata_pubkey = get_ata(owner, mint);
+    let mut ixs = vec![
+        create_associated_token_account_idempotent(owner, owner, mint, &token_2022_program_id()),
+        reallocate(
+            &token_2022_program_id(),
+            &ata_pubkey,
+            owner,
+            owner,
+            &[],
+            &confidential_account_extensions(&mint_info),
+        )?,
+    ];
+    let proof_data = pubkey_validity_proof(elgamal_keypair)?;
+    ixs.extend(configure_instructions(
+        mint,
+        &ata_pubkey,
+        owner,
+        &[],
+        aes_key,
+        proof_location(
+            ProofPlacement::InstructionData,
+            &proof_data,
+            &ata_pubkey,
+            &mut 0,
+        )?,
+    )?);
+    Ok(Transaction::new_unsigned(Message::new_with_blockhash(
+        &ixs,
+        Some(owner),
+        &recent_blockhash,
+    )))
+}
+
+/// Creates an auxiliary token account of `owner` at the address of `account_keypair` and
+/// configures it for confidential transfers in a single transaction.
+///
+/// Unlike an associated token account, the account is allocated with room for the confidential
+/// transfer extensions from the start, so no reallocation is needed. An owner can hold any number
+/// of such accounts for one mint, each with its own keys derived from its address; commands use
+/// them with `--token-account`. Like [`create_configure_ata`] it is safe to re-run: an existing
+/// account is reallocated and configured instead, and a configured one is returned as is.
+/// `fee_payer` must be the payer of `token`; it funds the account rent.
+#[instrument(skip_all, fields(mint = %token.get_address(), owner = %owner.pubkey(), account = %account_keypair.pubkey()))]
+pub async fn create_configure_account(
+    token: &TokenClient,
+    fee_payer: &Pubkey,
+    owner: &Authority,
+    account_keypair: &dyn Signer,
+) -> Result<ConfiguredAccount> {
+    let account_pubkey = account_keypair.pubkey();
+    if account_state(token, &account_pubkey).await? == AccountState::Configured {
+        return already_configured(token, owner, account_pubkey).await;
+    }
+    let (elgamal_keypair, aes_key) = derive_keys(
+        owner.key_source().as_ref(),
+        owner.seed_message(),
+        &account_pubkey,
     )?;
-    //Step3:Generate ElGamal keypair and AES key for token account
+    create_configure_account_with_keys(
+        token,
+        fee_payer,
+        owner,
+        account_keypair,
+        elgamal_keypair,
+        aes_key,
+    )
+    .await
+}
+
+/// Like [`create_configure_account`], but configures the account with the given keys instead of
+/// ones derived from its address.
+///
+/// The account must not be configured yet. Keys that cannot be re-derived must be stored by the
+/// caller.
+#[instrument(skip_all, fields(mint = %token.get_address(), owner = %owner.pubkey(), account = %account_keypair.pubkey()))]
+pub async fn create_configure_account_with_keys(
+    token: &TokenClient,
+    fee_payer: &Pubkey,
+    owner: &Authority,
+    account_keypair: &dyn Signer,
+    elgamal_keypair: ElGamalKeypair,
+    aes_key: AeKey,
+) -> Result<ConfiguredAccount> {
+    let mint = token.get_address();
+    let account_pubkey = account_keypair.pubkey();
+    let mint_info = token.get_mint_info().await?;
+    let mut ixs = vec![];
+    let mut signers = owner
+        .signers()
+        .iter()
+        .map(|signer| signer.as_ref())
+        .collect::<Vec<_>>();
+    match account_state(token, &account_pubkey).await? {
+        AccountState::Configured => {
+            bail!("Account {account_pubkey} is already configured");
+        }
+        AccountState::Missing => {
+            //Extensions the mint requires on every account, plus the confidential transfer ones
+            let mut extensions = ExtensionType::get_required_init_account_extensions(
+                &mint_info.get_extension_types()?,
+            );
+            extensions.extend(confidential_account_extensions(&mint_info));
+            let space = ExtensionType::try_calculate_account_len::<Account>(&extensions)?;
+            let rent = from_account::<Rent, _>(&token.get_account(sysvar::rent::id()).await?)
+                .ok_or_else(|| anyhow!("Invalid rent sysvar"))?;
+            debug!(space, "Creating token account");
+            //Step1:Create the account with its final size, owned by the token program
+            ixs.push(create_account(
+                fee_payer,                   //Payer of the rent
+                &account_pubkey,             //New token account
+                rent.minimum_balance(space), //Rent-exempt balance
+                space as u64,                //Account size with the extensions
+                &token_2022_program_id(),    //Owner program
+            ));
+            //Step2:Initialize the token account
+            ixs.push(initialize_account3(
+                &token_2022_program_id(),
+                &account_pubkey,
+                mint,
+                &owner.pubkey(), //Token account owner
+            )?);
+            signers.push(account_keypair);
+        }
+        AccountState::Unconfigured => {
+            //Left over from an earlier attempt or created elsewhere: make room for the extensions
+            let multisig_signers = owner.multisig_signers();
+            ixs.push(reallocate(
+                &token_2022_program_id(),
+                &account_pubkey,
+                fee_payer,
+                &owner.pubkey(),
+                &multisig_signers.iter().collect::<Vec<_>>(),
+                &confidential_account_extensions(&mint_info),
+            )?);
+        }
+    }
+    let signature = send_configure(
+        token,
+        &account_pubkey,
+        owner,
+        ixs,
+        &signers,
+        &elgamal_keypair,
+        &aes_key,
+    )
+    .await?;
+
+    Ok(ConfiguredAccount {
+        ata: account_pubkey,
+        elgamal_keypair,
+        aes_key,
+        signature: Some(signature),
+    })
+}
+
+// Configuration progress of a token account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AccountState {
+    Missing,
+    Unconfigured,
+    Configured,
+}
+
+// Looks up how far the configuration of `account` got
+pub(crate) async fn account_state(token: &TokenClient, account: &Pubkey) -> Result<AccountState> {
+    match token.get_account_info(account).await {
+        Ok(account_info) => {
+            if account_info
+                .get_extension::<ConfidentialTransferAccount>()
+                .is_ok()
+            {
+                Ok(AccountState::Configured)
+            } else {
+                Ok(AccountState::Unconfigured)
+            }
+        }
+        Err(TokenError::AccountNotFound) => Ok(AccountState::Missing),
+        Err(err) => Err(err.into()),
+    }
+}
+
+// Result for an account configured by an earlier run, with its keys re-derived
+async fn already_configured(
+    token: &TokenClient,
+    owner: &Authority,
+    account: Pubkey,
+) -> Result<ConfiguredAccount> {
+    debug!(%account, "Account already configured");
+    let (elgamal_keypair, aes_key) = load_keys(
+        token,
+        owner.key_source().as_ref(),
+        owner.seed_message(),
+        &account,
+    )
+    .await?;
+    Ok(ConfiguredAccount {
+        ata: account,
+        elgamal_keypair,
+        aes_key,
+        signature: None,
+    })
+}
+
+// Extensions the confidential transfer configuration adds to a token account of the mint: the
+// confidential transfer fee amount as well on mints with transfer fees
+fn confidential_account_extensions(
+    mint_info: &StateWithExtensionsOwned<Mint>,
+) -> Vec<ExtensionType> {
+    let mut extensions = vec![ExtensionType::ConfidentialTransferAccount];
+    if mint_info.get_extension::<TransferFeeConfig>().is_ok() {
+        extensions.push(ExtensionType::ConfidentialTransferFeeAmount);
+    }
+    extensions
+}
+
+// Sends `ixs` followed by the configuration of `account` for confidential transfers with the
+// given keys. The pubkey validity proof is verified in the same transaction when it fits, and
+// otherwise, e.g. for multisig owners with many signers, into a context state account closed
+// afterwards
+#[allow(clippy::too_many_arguments)]
+async fn send_configure<S: Signers>(
+    token: &TokenClient,
+    account: &Pubkey,
+    owner: &Authority,
+    mut ixs: Vec<Instruction>,
+    signers: &S,
     //Elgamal keypair is used to generate zero-knowledge proofs for confidential transfers
+    elgamal_keypair: &ElGamalKeypair,
     //AES key is used to encrypt and decrypt confidential balances
-    let elgamal_keypair=ElGamalKeypair::new_from_signer(&payer,&ata_pubkey.to_bytes()).expect("Failed to generate ElGamal keypair");
-    let aes_keypair=AeKey::new_from_signer(&payer, &ata_pubkey.to_bytes()).expect("Failed to generate AES key");
-    //Initial balance
-    let decryptable_balance=aes_keypair.encrypt(0);
+    aes_key: &AeKey,
+) -> Result<Signature> {
+    let mint = token.get_address();
     //Generate the proof data client side
-    let proof_data=PubkeyValidityProofData::new(&elgamal_keypair).map_err(|_|anyhow::anyhow!("Failed to generate pubkey validity proof data"))?;
-    let proof_location=ProofLocation::InstructionOffset(1.try_into()?,ProofData::InstructionData(&proof_data));
-    //Step4:Configure account for confidential transfers
-    let configure_account_ix=configure_account(
-        &token_2022_program_id(), //Program Id
-        &ata_pubkey, //Token account
-        &mint_keypair.pubkey(), //Mint account
+    let proof_data = pubkey_validity_proof(elgamal_keypair)?;
+    debug!(%account, "Generated pubkey validity proof");
+    //Stand-in for the context state account while planning; it takes the same room
+    let planned_proof_account = Pubkey::new_unique();
+    let placements = plan_proof_placements(&[ProofCost::of(&proof_data)], 0, |placements| {
+        let mut planned = ixs.clone();
+        planned.extend(configure_instructions(
+            mint,
+            account,
+            &owner.pubkey(),
+            &owner.multisig_signers(),
+            aes_key,
+            proof_location(placements[0], &proof_data, &planned_proof_account, &mut 0)?,
+        )?);
+        Ok(planned)
+    })?;
+    //Proof accounts cannot be managed by a multisig, so they belong to the proof authority
+    let proof_authority = owner.proof_authority();
+    let proof_keypair = match placements[0] {
+        ProofPlacement::InstructionData => None,
+        ProofPlacement::ContextAccount => {
+            let (proof_keypair, signature) =
+                create_proof_account(token, proof_authority, &proof_data, false).await?;
+            debug!(account = %proof_keypair.pubkey(), %signature, "Created pubkey validity proof account");
+            Some(proof_keypair)
+        }
+    };
+    let proof_account = proof_keypair
+        .as_ref()
+        .map_or(planned_proof_account, Keypair::pubkey);
+    ixs.extend(configure_instructions(
+        mint,
+        account,
+        &owner.pubkey(),
+        &owner.multisig_signers(),
+        aes_key,
+        proof_location(placements[0], &proof_data, &proof_account, &mut 0)?,
+    )?);
+    debug!(instructions = ixs.len(), "Sending configure transaction");
+    //A configure transaction whose blockhash expired cannot land anymore, so it is signed again
+    let signature = into_signature(
+        resend_on_expiry(DEFAULT_RESEND_DEADLINE, || token.process_ixs(&ixs, signers)).await?,
+    )?;
+    if let Some(proof_keypair) = proof_keypair {
+        close_proof_accounts(token, proof_authority, &[proof_keypair]).await?;
+    }
+    Ok(signature)
+}
+
+// Builds the instructions configuring `account` for confidential transfers with the AES key
+// `aes_key`: `ConfigureAccount`, followed by the pubkey validity proof verification when the
+// proof is in the instruction data
+fn configure_instructions(
+    mint: &Pubkey,
+    account: &Pubkey,
+    owner: &Pubkey,
+    multisig_signers: &[Pubkey],
+    aes_key: &AeKey,
+    proof_location: ProofLocation<PubkeyValidityProofData>,
+) -> Result<Vec<Instruction>> {
+    let multisig_signers = multisig_signers.iter().collect::<Vec<_>>();
+    //Initial balance
+    let decryptable_balance = aes_key.encrypt(0);
+    //Configure account for confidential transfers
+    let ixs = configure_account(
+        &token_2022_program_id(),    //Program Id
+        account,                     //Token account
+        mint,                        //Mint account
         &decryptable_balance.into(), //Initial balance
         MAXIMUM_PENDING_BALANCE_COUNTER,
-        &payer.pubkey(),//Token account owner
-        &[],//Additional signers
-        proof_location //Proof location
+        owner,             //Token account owner
+        &multisig_signers, //Multisig signers
+        proof_location,    //Proof location
     )?;
-    let mut ixs=vec![
-        created_ata_ix,
-        reallocate_ix,
-       
-    ];
-    ixs.extend(configure_account_ix);
-    let recent_blockhash=rpc_client.get_latest_blockhash().await?;
-    let transaction=Transaction::new_signed_with_payer(
-        &ixs,
-        Some(&payer.pubkey()),
-        &[&payer],
-        recent_blockhash,
-    );
-    let transaction_sig=rpc_client.send_and_confirm_transaction(&transaction).await?;
-    println!("Confidential transfer account configuration transaction signature: {}", transaction_sig);
-    
-    Ok((ata_pubkey,elgamal_keypair,aes_keypair))
+    Ok(ixs)
 }
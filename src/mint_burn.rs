@@ -0,0 +1,267 @@
+use anyhow::{Result, anyhow};
+use confidential_transfer_core::{balance::decrypt_available_balance, proofs::verify_proof};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::Signer,
+};
+use spl_token_client::spl_token_2022::{
+    extension::{
+        BaseStateWithExtensions,
+        confidential_mint_burn::{
+            ConfidentialMintBurn,
+            account_info::{BurnAccountInfo, SupplyAccountInfo},
+            instruction::{
+                confidential_burn_with_split_proofs, confidential_mint_with_split_proofs,
+            },
+        },
+        confidential_transfer::ConfidentialTransferAccount,
+    },
+    id as token_2022_program_id,
+    solana_zk_sdk::{
+        encryption::{
+            auth_encryption::AeKey,
+            elgamal::{ElGamalKeypair, ElGamalPubkey},
+        },
+        zk_elgamal_proof_program::proof_data::{
+            BatchedGroupedCiphertext3HandlesValidityProofData, BatchedRangeProofU128Data,
+            CiphertextCommitmentEqualityProofData,
+        },
+    },
+};
+use spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation;
+use spl_token_confidential_transfer_proof_generation::{burn::BurnProofData, mint::MintProofData};
+use std::sync::Arc;
+use tracing::{debug, instrument};
+
+use crate::{
+    TokenClient,
+    authority::Authority,
+    mint::derive_supply_keys,
+    transfer::{
+        close_proof_accounts, create_proof_account, get_auditor_elgamal_pubkey, get_elgamal_pubkey,
+    },
+    utils::into_signature,
+};
+
+/// Signatures and proof accounts produced by [`confidential_mint`] and [`confidential_burn`].
+#[derive(Debug, Clone)]
+pub struct MintBurnResult {
+    /// Context state accounts holding the verified equality, ciphertext validity and range
+    /// proofs.
+    pub proof_accounts: [Pubkey; 3],
+    /// Signatures of the three proof account creations.
+    pub proof_signatures: [Signature; 3],
+    /// Signature of the mint or burn instruction.
+    pub signature: Signature,
+    /// Signatures closing the three proof accounts.
+    pub close_signatures: Vec<Signature>,
+}
+
+/// Decrypts the confidential supply of a mint with the `ConfidentialMintBurn` extension.
+pub async fn get_confidential_supply(
+    token: &TokenClient,
+    mint_authority: &dyn Signer,
+) -> Result<u64> {
+    let mint_info = token.get_mint_info().await?;
+    let supply_account = SupplyAccountInfo::new(mint_info.get_extension::<ConfidentialMintBurn>()?);
+    let (supply_elgamal_keypair, supply_aes_key) =
+        derive_supply_keys(mint_authority, token.get_address())?;
+    Ok(supply_account.decrypted_current_supply(&supply_aes_key, &supply_elgamal_keypair)?)
+}
+
+/// Mints `amount` directly into the pending confidential balance of `destination_ata`.
+///
+/// The amount is encrypted under the destination's ElGamal pubkey, the supply ElGamal pubkey
+/// and the auditor pubkey, if configured, and added to the encrypted supply; the public supply
+/// is unchanged. The equality, ciphertext validity and range proofs are verified into context
+/// state accounts managed by `proof_authority`, usually the mint authority, and closed
+/// afterwards.
+#[instrument(skip_all, fields(destination = %destination_ata, amount))]
+pub async fn confidential_mint(
+    token: &TokenClient,
+    mint_authority: Arc<dyn Signer>,
+    proof_authority: &Arc<dyn Signer>,
+    destination_ata: &Pubkey,
+    amount: u64,
+) -> Result<MintBurnResult> {
+    let mint_info = token.get_mint_info().await?;
+    let supply_account = SupplyAccountInfo::new(mint_info.get_extension::<ConfidentialMintBurn>()?);
+    let (supply_elgamal_keypair, supply_aes_key) =
+        derive_supply_keys(mint_authority.as_ref(), token.get_address())?;
+    //Burns only update the encrypted supply, so the decryptable supply may be outdated
+    let current_supply =
+        supply_account.decrypted_current_supply(&supply_aes_key, &supply_elgamal_keypair)?;
+    let destination_elgamal_pubkey = get_elgamal_pubkey(token, destination_ata).await?;
+    let auditor_elgamal_pubkey = get_auditor_elgamal_pubkey(token).await?;
+    debug!(current_supply, "Decrypted current supply");
+
+    //Mint proof data
+    let MintProofData {
+        equality_proof_data,
+        ciphertext_validity_proof_data_with_ciphertext,
+        range_proof_data,
+    } = supply_account.generate_split_mint_proof_data(
+        amount,
+        current_supply,
+        &supply_elgamal_keypair,
+        &destination_elgamal_pubkey,
+        auditor_elgamal_pubkey.as_ref(),
+    )?;
+    debug!("Generated mint proof data");
+    let (proof_keypairs, proof_signatures) = create_proof_accounts(
+        token,
+        proof_authority,
+        &equality_proof_data,
+        &ciphertext_validity_proof_data_with_ciphertext.proof_data,
+        &range_proof_data,
+    )
+    .await?;
+
+    let new_decryptable_supply = current_supply
+        .checked_add(amount)
+        .map(|supply| supply_aes_key.encrypt(supply))
+        .ok_or_else(|| anyhow!("Confidential supply overflow"))?;
+    let ixs = confidential_mint_with_split_proofs(
+        &token_2022_program_id(),
+        destination_ata,                        //Destination ata
+        token.get_address(),                    //Mint
+        Some(*supply_elgamal_keypair.pubkey()), //Supply ElGamal pubkey
+        &ciphertext_validity_proof_data_with_ciphertext.ciphertext_lo, //Auditor ciphertexts
+        &ciphertext_validity_proof_data_with_ciphertext.ciphertext_hi,
+        &mint_authority.pubkey(), //Mint authority
+        &[],                      //Additional signers
+        ProofLocation::ContextStateAccount(&proof_keypairs[0].pubkey()),
+        ProofLocation::ContextStateAccount(&proof_keypairs[1].pubkey()),
+        ProofLocation::ContextStateAccount(&proof_keypairs[2].pubkey()),
+        &new_decryptable_supply.into(),
+    )?;
+    let signature = into_signature(token.process_ixs(&ixs, &[&mint_authority]).await?)?;
+    debug!(%signature, "Confidential mint confirmed");
+
+    let close_signatures = close_proof_accounts(token, proof_authority, &proof_keypairs).await?;
+    Ok(MintBurnResult {
+        proof_accounts: proof_keypairs.each_ref().map(Keypair::pubkey),
+        proof_signatures,
+        signature,
+        close_signatures,
+    })
+}
+
+/// Burns `amount` from the available confidential balance of `ata_pubkey`.
+///
+/// The amount is encrypted under the supply ElGamal pubkey (and the auditor pubkey, if
+/// configured) and subtracted from the encrypted supply. The proofs are verified into context
+/// state accounts managed by the owner's proof authority and closed afterwards.
+#[instrument(skip_all, fields(ata = %ata_pubkey, amount))]
+pub async fn confidential_burn(
+    token: &TokenClient,
+    owner: &Authority,
+    ata_pubkey: &Pubkey,
+    amount: u64,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+) -> Result<MintBurnResult> {
+    //Proof accounts cannot be managed by a multisig, so they belong to the proof authority
+    let proof_authority = owner.proof_authority();
+    let multisig_signers = owner.multisig_signers();
+    let mint_info = token.get_mint_info().await?;
+    let supply_elgamal_pubkey = ElGamalPubkey::try_from(
+        mint_info
+            .get_extension::<ConfidentialMintBurn>()?
+            .supply_elgamal_pubkey,
+    )
+    .map_err(|_| anyhow!("Invalid supply ElGamal pubkey"))?;
+    let auditor_elgamal_pubkey = get_auditor_elgamal_pubkey(token).await?;
+    let token_account = token.get_account_info(ata_pubkey).await?;
+    let extension_data = token_account.get_extension::<ConfidentialTransferAccount>()?;
+    //Confidential transfer extension information needed to construct a burn instruction
+    let burn_account = BurnAccountInfo::new(extension_data);
+
+    //Burn proof data
+    let BurnProofData {
+        equality_proof_data,
+        ciphertext_validity_proof_data_with_ciphertext,
+        range_proof_data,
+    } = burn_account.generate_split_burn_proof_data(
+        amount,
+        elgamal_keypair,
+        aes_key,
+        &supply_elgamal_pubkey,
+        auditor_elgamal_pubkey.as_ref(),
+    )?;
+    debug!("Generated burn proof data");
+    let (proof_keypairs, proof_signatures) = create_proof_accounts(
+        token,
+        proof_authority,
+        &equality_proof_data,
+        &ciphertext_validity_proof_data_with_ciphertext.proof_data,
+        &range_proof_data,
+    )
+    .await?;
+
+    let available_balance = decrypt_available_balance(extension_data, aes_key)
+        .ok_or_else(|| anyhow!("Failed to decrypt the available balance of {ata_pubkey}"))?;
+    let new_decryptable_available_balance = available_balance
+        .checked_sub(amount)
+        .map(|balance| aes_key.encrypt(balance))
+        .ok_or_else(|| anyhow!("Insufficient available balance"))?;
+    let ixs = confidential_burn_with_split_proofs(
+        &token_2022_program_id(),
+        ata_pubkey,                  //Source ata
+        token.get_address(),         //Mint
+        Some(supply_elgamal_pubkey), //Supply ElGamal pubkey
+        &new_decryptable_available_balance.into(),
+        &ciphertext_validity_proof_data_with_ciphertext.ciphertext_lo, //Auditor ciphertexts
+        &ciphertext_validity_proof_data_with_ciphertext.ciphertext_hi,
+        &owner.pubkey(),                              //Owner of the source ata
+        &multisig_signers.iter().collect::<Vec<_>>(), //Multisig signers
+        ProofLocation::ContextStateAccount(&proof_keypairs[0].pubkey()),
+        ProofLocation::ContextStateAccount(&proof_keypairs[1].pubkey()),
+        ProofLocation::ContextStateAccount(&proof_keypairs[2].pubkey()),
+    )?;
+    let signature = into_signature(token.process_ixs(&ixs, owner.signers()).await?)?;
+    debug!(%signature, "Confidential burn confirmed");
+
+    let close_signatures = close_proof_accounts(token, proof_authority, &proof_keypairs).await?;
+    Ok(MintBurnResult {
+        proof_accounts: proof_keypairs.each_ref().map(Keypair::pubkey),
+        proof_signatures,
+        signature,
+        close_signatures,
+    })
+}
+
+// Verifies the equality, ciphertext validity and range proofs of a mint or burn into context
+// state accounts
+async fn create_proof_accounts(
+    token: &TokenClient,
+    authority: &Arc<dyn Signer>,
+    equality_proof_data: &CiphertextCommitmentEqualityProofData,
+    ciphertext_validity_proof_data: &BatchedGroupedCiphertext3HandlesValidityProofData,
+    range_proof_data: &BatchedRangeProofU128Data,
+) -> Result<([Keypair; 3], [Signature; 3])> {
+    //Catch invalid proofs before paying for any of the accounts
+    verify_proof(equality_proof_data)?;
+    verify_proof(ciphertext_validity_proof_data)?;
+    verify_proof(range_proof_data)?;
+    let (equality_proof_keypair, equality_proof_signature) =
+        create_proof_account(token, authority, equality_proof_data, false).await?;
+    let (ciphertext_validity_proof_keypair, ciphertext_validity_proof_signature) =
+        create_proof_account(token, authority, ciphertext_validity_proof_data, false).await?;
+    //The range proof is too large to be verified in the account creation transaction
+    let (range_proof_keypair, range_proof_signature) =
+        create_proof_account(token, authority, range_proof_data, true).await?;
+    Ok((
+        [
+            equality_proof_keypair,
+            ciphertext_validity_proof_keypair,
+            range_proof_keypair,
+        ],
+        [
+            equality_proof_signature,
+            ciphertext_validity_proof_signature,
+            range_proof_signature,
+        ],
+    ))
+}
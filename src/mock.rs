@@ -0,0 +1,261 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use solana_client::rpc_response::RpcSimulateTransactionResult;
+use solana_sdk::{
+    account::Account,
+    hash::Hash,
+    instruction::{AccountMeta, Instruction},
+    nonce::state::{
+        Data as NonceData, DurableNonce, State as NonceState, Versions as NonceVersions,
+    },
+    pubkey::Pubkey,
+    rent::Rent,
+    transaction::Transaction,
+};
+use solana_sdk_ids::system_program;
+use spl_token_client::{
+    client::{ProgramClient, ProgramClientResult, RpcClientResponse},
+    spl_token_2022::{
+        extension::{
+            BaseStateWithExtensionsMut, ExtensionType, StateWithExtensionsMut,
+            confidential_transfer::{
+                ConfidentialTransferAccount, ConfidentialTransferMint,
+                DEFAULT_MAXIMUM_PENDING_BALANCE_CREDIT_COUNTER,
+            },
+        },
+        id as token_2022_program_id,
+        solana_program::program_option::COption,
+        solana_zk_sdk::encryption::{auth_encryption::AeKey, elgamal::ElGamalKeypair},
+        state::{Account as TokenAccount, AccountState, Mint},
+    },
+};
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::client::RpcSender;
+
+/// Compute units a [`MockProgramClient`] reports for every simulation by default.
+pub const DEFAULT_MOCK_UNITS_CONSUMED: u64 = 100_000;
+
+/// Program client answering from canned accounts and recording the transactions sent through
+/// it, without any RPC, so unit tests can check the instructions library functions build.
+///
+/// Sent transactions are only recorded: accounts keep their canned state, and every transaction
+/// succeeds with its first signature. Simulations succeed with `units_consumed`. Wrap it in an
+/// [`Arc`](std::sync::Arc) and pass a clone to
+/// [`Token::new`](spl_token_client::token::Token::new), keeping the other to read
+/// [`MockProgramClient::instructions`] back.
+pub struct MockProgramClient {
+    accounts: Mutex<HashMap<Pubkey, Account>>,
+    transactions: Mutex<Vec<Transaction>>,
+    rent: Rent,
+    blockhash: Hash,
+    /// Compute units reported by every simulation.
+    pub units_consumed: u64,
+}
+
+impl Default for MockProgramClient {
+    fn default() -> Self {
+        Self {
+            accounts: Mutex::default(),
+            transactions: Mutex::default(),
+            rent: Rent::default(),
+            blockhash: Hash::new_unique(),
+            units_consumed: DEFAULT_MOCK_UNITS_CONSUMED,
+        }
+    }
+}
+
+impl MockProgramClient {
+    /// Client without accounts: every address reads as nonexistent.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Same client, answering `account` for `address`.
+    pub fn with_account(self, address: Pubkey, account: Account) -> Self {
+        self.set_account(address, account);
+        self
+    }
+
+    /// Answers `account` for `address` from now on, e.g. to stage the state a later step reads.
+    pub fn set_account(&self, address: Pubkey, account: Account) {
+        self.accounts.lock().unwrap().insert(address, account);
+    }
+
+    /// Transactions sent so far, in order.
+    pub fn transactions(&self) -> Vec<Transaction> {
+        self.transactions.lock().unwrap().clone()
+    }
+
+    /// Instructions of the transactions sent so far, in order, with the signer and writable
+    /// flags of their accounts as compiled into each transaction.
+    pub fn instructions(&self) -> Vec<Instruction> {
+        self.transactions
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(transaction_instructions)
+            .collect()
+    }
+
+    /// Forgets the transactions sent so far.
+    pub fn clear_transactions(&self) {
+        self.transactions.lock().unwrap().clear();
+    }
+}
+
+#[async_trait]
+impl ProgramClient<RpcSender> for MockProgramClient {
+    async fn get_minimum_balance_for_rent_exemption(
+        &self,
+        data_len: usize,
+    ) -> ProgramClientResult<u64> {
+        Ok(self.rent.minimum_balance(data_len))
+    }
+
+    async fn get_latest_blockhash(&self) -> ProgramClientResult<Hash> {
+        Ok(self.blockhash)
+    }
+
+    async fn send_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> ProgramClientResult<RpcClientResponse> {
+        self.transactions.lock().unwrap().push(transaction.clone());
+        Ok(RpcClientResponse::Signature(transaction.signatures[0]))
+    }
+
+    async fn get_account(&self, address: Pubkey) -> ProgramClientResult<Option<Account>> {
+        Ok(self.accounts.lock().unwrap().get(&address).cloned())
+    }
+
+    async fn simulate_transaction(
+        &self,
+        _transaction: &Transaction,
+    ) -> ProgramClientResult<RpcClientResponse> {
+        Ok(RpcClientResponse::Simulation(
+            RpcSimulateTransactionResult {
+                err: None,
+                logs: Some(vec![]),
+                accounts: None,
+                units_consumed: Some(self.units_consumed),
+                loaded_accounts_data_size: None,
+                return_data: None,
+                inner_instructions: None,
+                replacement_blockhash: None,
+            },
+        ))
+    }
+}
+
+/// Instructions of `transaction`, with the signer and writable flags of their accounts as
+/// compiled into it.
+pub fn transaction_instructions(transaction: &Transaction) -> Vec<Instruction> {
+    let message = &transaction.message;
+    message
+        .instructions
+        .iter()
+        .map(|instruction| Instruction {
+            program_id: message.account_keys[usize::from(instruction.program_id_index)],
+            accounts: instruction
+                .accounts
+                .iter()
+                .map(|index| {
+                    let index = usize::from(*index);
+                    AccountMeta {
+                        pubkey: message.account_keys[index],
+                        is_signer: message.is_signer(index),
+                        is_writable: message.is_maybe_writable(index, None),
+                    }
+                })
+                .collect(),
+            data: instruction.data.clone(),
+        })
+        .collect()
+}
+
+/// Token-2022 mint account with `decimals`, minted by `mint_authority`, with the
+/// `ConfidentialTransferMint` extension managed by the same authority and approving new
+/// accounts automatically, as [`initialize_mint`](crate::mint::initialize_mint) creates them.
+pub fn confidential_mint_account(mint_authority: &Pubkey, decimals: u8) -> Result<Account> {
+    let space = ExtensionType::try_calculate_account_len::<Mint>(&[
+        ExtensionType::ConfidentialTransferMint,
+    ])?;
+    let mut data = vec![0; space];
+    let mut state = StateWithExtensionsMut::<Mint>::unpack_uninitialized(&mut data)?;
+    state.base = Mint {
+        mint_authority: COption::Some(*mint_authority),
+        supply: 0,
+        decimals,
+        is_initialized: true,
+        freeze_authority: COption::Some(*mint_authority),
+    };
+    state.pack_base();
+    let extension = state.init_extension::<ConfidentialTransferMint>(true)?;
+    extension.authority = Some(*mint_authority).try_into()?;
+    extension.auto_approve_new_accounts = true.into();
+    state.init_account_type()?;
+    Ok(Account {
+        lamports: Rent::default().minimum_balance(space),
+        data,
+        owner: token_2022_program_id(),
+        executable: false,
+        rent_epoch: 0,
+    })
+}
+
+/// Token-2022 account of `owner` for `mint`, configured for confidential transfers with
+/// `elgamal_keypair` and `aes_key` and holding `available_balance` base units in its available
+/// confidential balance, encrypted under both, with nothing pending.
+pub fn confidential_token_account(
+    mint: &Pubkey,
+    owner: &Pubkey,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+    available_balance: u64,
+) -> Result<Account> {
+    let space = ExtensionType::try_calculate_account_len::<TokenAccount>(&[
+        ExtensionType::ConfidentialTransferAccount,
+    ])?;
+    let mut data = vec![0; space];
+    let mut state = StateWithExtensionsMut::<TokenAccount>::unpack_uninitialized(&mut data)?;
+    state.base = TokenAccount {
+        mint: *mint,
+        owner: *owner,
+        state: AccountState::Initialized,
+        ..TokenAccount::default()
+    };
+    state.pack_base();
+    let extension = state.init_extension::<ConfidentialTransferAccount>(true)?;
+    extension.approved = true.into();
+    extension.elgamal_pubkey = (*elgamal_keypair.pubkey()).into();
+    extension.available_balance = elgamal_keypair.pubkey().encrypt(available_balance).into();
+    extension.decryptable_available_balance = aes_key.encrypt(available_balance).into();
+    extension.allow_confidential_credits = true.into();
+    extension.allow_non_confidential_credits = true.into();
+    extension.maximum_pending_balance_credit_counter =
+        DEFAULT_MAXIMUM_PENDING_BALANCE_CREDIT_COUNTER.into();
+    state.init_account_type()?;
+    Ok(Account {
+        lamports: Rent::default().minimum_balance(space),
+        data,
+        owner: token_2022_program_id(),
+        executable: false,
+        rent_epoch: 0,
+    })
+}
+
+/// Durable nonce account advanced by `authority`, initialized on `blockhash`, as
+/// [`create_nonce_account`](crate::nonce::create_nonce_account) creates them.
+pub fn nonce_account(authority: &Pubkey, blockhash: &Hash) -> Result<Account> {
+    let state = NonceVersions::new(NonceState::Initialized(NonceData::new(
+        *authority,
+        DurableNonce::from_blockhash(blockhash),
+        0,
+    )));
+    Ok(Account::new_data(
+        Rent::default().minimum_balance(NonceState::size()),
+        &state,
+        &system_program::id(),
+    )?)
+}
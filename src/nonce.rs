@@ -0,0 +1,212 @@
+use anyhow::{Context, Result, anyhow, bail};
+use solana_client::nonblocking::{nonce_utils, rpc_client::RpcClient};
+use solana_sdk::{
+    compute_budget::ComputeBudgetInstruction,
+    hash::Hash,
+    instruction::Instruction,
+    message::Message,
+    nonce::state::State as NonceState,
+    pubkey::Pubkey,
+    signature::Signature,
+    signer::Signer,
+    system_instruction::{
+        advance_nonce_account, create_nonce_account as create_nonce_account_instructions,
+    },
+    transaction::Transaction,
+};
+use spl_token_client::{
+    client::ProgramClient, spl_token_2022::id as token_2022_program_id, token::Token,
+};
+use std::{fmt, sync::Arc};
+use tracing::{debug, instrument};
+
+use crate::{
+    TokenClient,
+    client::{ComputeBudget, RpcSender},
+    utils::into_signature,
+};
+
+/// Durable nonce account the transactions of a token client are built on, instead of a recent
+/// blockhash, so they stay valid however long signing takes.
+#[derive(Clone)]
+pub struct DurableNonce {
+    /// Address of the nonce account.
+    pub account: Pubkey,
+    /// Nonce authority, signing the instruction advancing the nonce.
+    pub authority: Arc<dyn Signer>,
+}
+
+impl DurableNonce {
+    /// Makes `token` build its transactions on the current nonce of the account, each starting
+    /// with an instruction advancing it.
+    ///
+    /// The nonce is read once and every transaction advances it, so only the first transaction
+    /// sent through the returned client can land: use it for operations sending a single one.
+    pub async fn apply(&self, rpc_client: &RpcClient, token: TokenClient) -> Result<TokenClient> {
+        let nonce = get_nonce(rpc_client, &self.account).await?;
+        debug!(account = %self.account, %nonce, "Building on durable nonce");
+        Ok(token.with_nonce(&self.account, self.authority.clone(), &nonce))
+    }
+}
+
+/// Durable nonce accounts the packed transactions of an operation are built on, one per
+/// transaction, so an operation sending several stays valid however long signing takes.
+///
+/// The token client builds every transaction on the one nonce it was given, so this carries what
+/// building them on distinct ones takes: the program client and the fee payer of the token
+/// client, and the priority fee.
+#[derive(Clone)]
+pub struct DurableNonces {
+    /// Addresses of the nonce accounts, the n-th for the n-th transaction.
+    pub accounts: Vec<Pubkey>,
+    /// Nonce authority of every account.
+    pub authority: Arc<dyn Signer>,
+    /// Program client of the token client, so offline signing and dry runs cover the
+    /// transactions.
+    pub program_client: Arc<dyn ProgramClient<RpcSender>>,
+    /// Fee payer of the token client.
+    pub fee_payer: Arc<dyn Signer>,
+    /// Priority fee in micro-lamports per compute unit, if any.
+    pub compute_unit_price: Option<u64>,
+}
+
+impl fmt::Debug for DurableNonces {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DurableNonces")
+            .field("accounts", &self.accounts)
+            .field("authority", &self.authority.pubkey())
+            .field("fee_payer", &self.fee_payer.pubkey())
+            .finish()
+    }
+}
+
+impl DurableNonces {
+    /// Nonce `accounts` advanced by `authority`, for transactions sent through `program_client`
+    /// and paid by `fee_payer` with `compute_budget`.
+    pub fn new(
+        accounts: Vec<Pubkey>,
+        authority: Arc<dyn Signer>,
+        program_client: Arc<dyn ProgramClient<RpcSender>>,
+        fee_payer: Arc<dyn Signer>,
+        compute_budget: &ComputeBudget,
+    ) -> Self {
+        Self {
+            accounts,
+            authority,
+            program_client,
+            fee_payer,
+            compute_unit_price: compute_budget.unit_price,
+        }
+    }
+
+    /// Instruction advancing the nonce of the account of transaction `index`, first in it.
+    pub fn advance_instruction(&self, index: usize) -> Instruction {
+        advance_nonce_account(&self.accounts[index], &self.authority.pubkey())
+    }
+
+    /// Fails unless there is a nonce account for each of `transactions`, before any is sent.
+    pub fn check_count(&self, transactions: usize) -> Result<()> {
+        if self.accounts.len() < transactions {
+            bail!(
+                "The operation takes {transactions} transactions but {} nonce accounts were given; pass one per transaction",
+                self.accounts.len()
+            );
+        }
+        Ok(())
+    }
+
+    // Sends `instructions` as transaction `index`, built on the nonce of its account and signed
+    // by the fee payer, the nonce authority and `signers`, the other signers the instructions
+    // require
+    pub(crate) async fn send(
+        &self,
+        index: usize,
+        instructions: &[Instruction],
+        signers: &[&dyn Signer],
+    ) -> Result<Signature> {
+        let account = self.accounts[index];
+        //The advance must stay first, so the price goes right after it, before the operation
+        let instructions = [self.advance_instruction(index)]
+            .into_iter()
+            .chain(
+                self.compute_unit_price
+                    .map(ComputeBudgetInstruction::set_compute_unit_price),
+            )
+            .chain(instructions.iter().cloned())
+            .collect::<Vec<_>>();
+        let nonce_account = self
+            .program_client
+            .get_account(account)
+            .await
+            .map_err(|e| anyhow!(e))?
+            .ok_or_else(|| anyhow!("Nonce account {account} not found"))?;
+        let nonce = nonce_utils::data_from_account(&nonce_account)
+            .with_context(|| format!("{account} is not an initialized nonce account"))?
+            .blockhash();
+        debug!(transaction = index, %account, %nonce, "Building on durable nonce");
+        let message =
+            Message::new_with_blockhash(&instructions, Some(&self.fee_payer.pubkey()), &nonce);
+        //The nonce authority is often the fee payer, and signing twice with a key fails
+        let mut transaction_signers: Vec<&dyn Signer> = vec![];
+        for signer in [self.fee_payer.as_ref(), self.authority.as_ref()]
+            .into_iter()
+            .chain(signers.iter().copied())
+        {
+            if !transaction_signers
+                .iter()
+                .any(|added| added.pubkey() == signer.pubkey())
+            {
+                transaction_signers.push(signer);
+            }
+        }
+        let mut transaction = Transaction::new_unsigned(message);
+        transaction.try_partial_sign(&transaction_signers, nonce)?;
+        into_signature(
+            self.program_client
+                .send_transaction(&transaction)
+                .await
+                .map_err(|e| anyhow!(e))?,
+        )
+    }
+}
+
+/// Current nonce of the durable nonce account `account`.
+pub async fn get_nonce(rpc_client: &RpcClient, account: &Pubkey) -> Result<Hash> {
+    let nonce_account = nonce_utils::get_account(rpc_client, account)
+        .await
+        .with_context(|| format!("Unable to read nonce account {account}"))?;
+    Ok(nonce_utils::data_from_account(&nonce_account)
+        .with_context(|| format!("{account} is not an initialized nonce account"))?
+        .blockhash())
+}
+
+/// Creates the durable nonce account `nonce_account`, its rent and fees paid by `fee_payer` with
+/// `compute_budget`, and advanced by `authority`.
+#[instrument(skip_all, fields(nonce_account = %nonce_account.pubkey(), %authority))]
+pub async fn create_nonce_account(
+    program_client: Arc<dyn ProgramClient<RpcSender>>,
+    fee_payer: Arc<dyn Signer>,
+    compute_budget: &ComputeBudget,
+    nonce_account: &dyn Signer,
+    authority: &Pubkey,
+) -> Result<Signature> {
+    let rent = program_client
+        .get_minimum_balance_for_rent_exemption(NonceState::size())
+        .await
+        .map_err(|e| anyhow!(e))?;
+    let instructions = create_nonce_account_instructions(
+        &fee_payer.pubkey(),     //Payer of the rent
+        &nonce_account.pubkey(), //New nonce account
+        authority,               //Authority that can advance the nonce
+        rent,                    //Rent-exempt balance
+    );
+    //Creating a nonce account involves no mint, so any address does for the client
+    let token = compute_budget.apply(Token::new(
+        program_client,
+        &token_2022_program_id(),
+        &Pubkey::default(),
+        None,
+        fee_payer,
+    ));
+    into_signature(token.process_ixs(&instructions, &[nonce_account]).await?)
+}
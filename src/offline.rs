@@ -0,0 +1,177 @@
+use anyhow::{Context, Result, anyhow, bail};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    pubkey::Pubkey,
+    sanitize::Sanitize,
+    signature::Signature,
+    signer::Signer,
+    transaction::{Transaction, uses_durable_nonce},
+};
+use spl_token_client::client::SendTransactionRpc;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tracing::{debug, instrument};
+
+use crate::{client::RpcSender, utils::into_signature};
+
+/// Extension of the transaction files written for offline signing.
+pub const TRANSACTION_FILE_EXTENSION: &str = "tx";
+
+/// Writes `transaction`, signed by the signers at hand, to a file of `dir` named after its
+/// message hash, and returns its path.
+///
+/// The file holds the base64 encoded transaction, so it can be carried to the machine holding the
+/// missing keys, signed there with [`sign_transaction`] or completed with signatures produced
+/// elsewhere through [`add_signature`], and sent later with [`broadcast_transaction`].
+pub fn write_transaction(dir: &Path, transaction: &Transaction) -> Result<PathBuf> {
+    fs::create_dir_all(dir).with_context(|| format!("Unable to create {}", dir.display()))?;
+    let path = dir
+        .join(transaction.message.hash().to_string())
+        .with_extension(TRANSACTION_FILE_EXTENSION);
+    save_transaction(&path, transaction)?;
+    Ok(path)
+}
+
+/// Writes `transaction` to the transaction file at `path`, e.g. once more signatures are added.
+pub fn save_transaction(path: &Path, transaction: &Transaction) -> Result<()> {
+    fs::write(
+        path,
+        BASE64_STANDARD.encode(bincode::serialize(transaction)?),
+    )
+    .with_context(|| format!("Unable to write {}", path.display()))
+}
+
+/// Reads a transaction file written by [`write_transaction`].
+///
+/// The transaction is sanitized and must carry one signature slot per required signer, so a
+/// truncated or altered file is refused here rather than failing later.
+pub fn read_transaction(path: &Path) -> Result<Transaction> {
+    let encoded =
+        fs::read_to_string(path).with_context(|| format!("Unable to read {}", path.display()))?;
+    let bytes = BASE64_STANDARD
+        .decode(encoded.trim())
+        .with_context(|| format!("{} is not base64 encoded", path.display()))?;
+    let transaction: Transaction = bincode::deserialize(&bytes)
+        .with_context(|| format!("{} does not hold a transaction", path.display()))?;
+    transaction
+        .sanitize()
+        .with_context(|| format!("{} holds a malformed transaction", path.display()))?;
+    let signers = usize::from(transaction.message.header.num_required_signatures);
+    if transaction.signatures.len() != signers {
+        bail!(
+            "{} holds a transaction with {} signatures for {signers} signers",
+            path.display(),
+            transaction.signatures.len()
+        );
+    }
+    Ok(transaction)
+}
+
+/// Signers whose signature `transaction` still lacks.
+pub fn missing_signers(transaction: &Transaction) -> Vec<Pubkey> {
+    let signers = usize::from(transaction.message.header.num_required_signatures);
+    transaction
+        .message
+        .account_keys
+        .iter()
+        .take(signers)
+        .zip(&transaction.signatures)
+        .filter(|(_, signature)| **signature == Signature::default())
+        .map(|(signer, _)| *signer)
+        .collect()
+}
+
+/// Signature of the fee payer of `transaction`, the one identifying it.
+///
+/// Fails while the fee payer has not signed, since the transaction then has no signature to be
+/// tracked by.
+pub fn fee_payer_signature(transaction: &Transaction) -> Result<Signature> {
+    let fee_payer = *transaction
+        .message
+        .account_keys
+        .first()
+        .context("Transaction without fee payer")?;
+    let position = transaction
+        .get_signing_keypair_positions(&[fee_payer])?
+        .first()
+        .copied()
+        .flatten()
+        .with_context(|| format!("Fee payer {fee_payer} is not a signer of the transaction"))?;
+    match transaction.signatures.get(position) {
+        Some(signature) if *signature != Signature::default() => Ok(*signature),
+        _ => bail!("Fee payer {fee_payer} has not signed the transaction"),
+    }
+}
+
+/// Message of `transaction`, base64 encoded: the bytes each signer signs.
+pub fn message_to_sign(transaction: &Transaction) -> String {
+    BASE64_STANDARD.encode(transaction.message_data())
+}
+
+/// Signs `transaction` with `signer`, one of the signers it requires.
+pub fn sign_transaction(transaction: &mut Transaction, signer: &dyn Signer) -> Result<()> {
+    let recent_blockhash = transaction.message.recent_blockhash;
+    transaction
+        .try_partial_sign(&[signer], recent_blockhash)
+        .map_err(|e| anyhow!("Unable to sign with {}: {e}", signer.pubkey()))
+}
+
+/// Adds `signature`, produced elsewhere by `signer` over the message of `transaction`, e.g. by
+/// a hardware wallet or an HSM. The signature is verified first.
+pub fn add_signature(
+    transaction: &mut Transaction,
+    signer: &Pubkey,
+    signature: Signature,
+) -> Result<()> {
+    let signers = usize::from(transaction.message.header.num_required_signatures);
+    let index = transaction
+        .message
+        .account_keys
+        .iter()
+        .take(signers)
+        .position(|key| key == signer)
+        .ok_or_else(|| anyhow!("{signer} is not a signer of the transaction"))?;
+    if !signature.verify(signer.as_ref(), &transaction.message_data()) {
+        bail!("Invalid signature of {signer} for the transaction");
+    }
+    *transaction
+        .signatures
+        .get_mut(index)
+        .ok_or_else(|| anyhow!("The transaction has no signature slot for {signer}"))? = signature;
+    Ok(())
+}
+
+/// Sends `transaction`, once fully signed, through `sender` and returns its signature.
+///
+/// Transactions built on a durable nonce stay valid until the nonce is advanced, so they can be
+/// sent however long after they were written.
+#[instrument(skip_all, fields(signature = ?transaction.signatures.first()))]
+pub async fn broadcast_transaction(
+    rpc_client: &RpcClient,
+    sender: &RpcSender,
+    transaction: &Transaction,
+) -> Result<Signature> {
+    let missing = missing_signers(transaction);
+    if !missing.is_empty() {
+        bail!(
+            "The transaction still lacks the signatures of {}",
+            missing
+                .iter()
+                .map(Pubkey::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    if uses_durable_nonce(transaction).is_none() {
+        debug!("Transaction not built on a durable nonce; its blockhash may have expired");
+    }
+    into_signature(
+        sender
+            .send(rpc_client, transaction)
+            .await
+            .map_err(|e| anyhow!(e))?,
+    )
+}
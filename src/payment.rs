@@ -0,0 +1,182 @@
+use anyhow::{Context, Result, anyhow, bail};
+use solana_client::{
+    nonblocking::rpc_client::RpcClient, rpc_client::GetConfirmedSignaturesForAddress2Config,
+};
+use solana_sdk::{pubkey::Pubkey, signature::Signature, signer::Signer};
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+use tokio::time::sleep;
+use tracing::{debug, instrument};
+
+use crate::{
+    amount::{self, Amount},
+    client::signature_history_commitment,
+    keygen,
+};
+
+/// Scheme of payment request URIs, the one of Solana Pay transfer requests.
+pub const SCHEME: &str = "solana";
+//Latest transactions of the account searched for the payment at every poll
+const SIGNATURE_LOOKUP_LIMIT: usize = 100;
+
+/// Request for a confidential transfer of `amount` of `mint` to the token account `recipient`,
+/// identified by `reference`.
+///
+/// It is shared as a URI shaped like a Solana Pay transfer request,
+/// `solana:<RECIPIENT>?amount=<AMOUNT>&spl-token=<MINT>&reference=<REFERENCE>`, except that the
+/// recipient is the token account to credit rather than a wallet: confidential transfers are
+/// encrypted to the keys of that account. The amount is in tokens, e.g. `1.5`, as in Solana Pay.
+///
+/// The amount of a confidential transfer is encrypted and the reference cannot be added to its
+/// accounts, so the payer attaches the reference as the memo of the transfer, which
+/// [`wait_for_payment`] looks for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaymentRequest {
+    /// Token account to credit.
+    pub recipient: Pubkey,
+    /// Mint of the transfer.
+    pub mint: Pubkey,
+    /// Amount in tokens, as a decimal number.
+    pub amount: String,
+    /// Unique key identifying the payment, attached as the memo of the transfer.
+    pub reference: Pubkey,
+}
+
+impl PaymentRequest {
+    /// Request for `amount` base units of `mint`, which has `decimals` decimals, to `recipient`,
+    /// under a new random reference.
+    pub fn new(recipient: Pubkey, mint: Pubkey, amount: u64, decimals: u8) -> Self {
+        Self {
+            recipient,
+            mint,
+            amount: amount::format(amount, decimals),
+            reference: keygen::new_keypair().pubkey(),
+        }
+    }
+
+    /// Amount in base units of a mint with `decimals` decimals.
+    pub fn base_units(&self, decimals: u8) -> Result<u64> {
+        self.amount.parse::<Amount>()?.to_base_units(decimals)
+    }
+
+    /// Memo the transfer paying the request carries.
+    pub fn memo(&self) -> String {
+        self.reference.to_string()
+    }
+}
+
+impl fmt::Display for PaymentRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{SCHEME}:{}?amount={}&spl-token={}&reference={}",
+            self.recipient, self.amount, self.mint, self.reference
+        )
+    }
+}
+
+impl FromStr for PaymentRequest {
+    type Err = anyhow::Error;
+
+    //Parameters other than these, e.g. the `label` and `message` of Solana Pay, are ignored
+    fn from_str(uri: &str) -> Result<Self> {
+        let rest = uri
+            .strip_prefix(SCHEME)
+            .and_then(|rest| rest.strip_prefix(':'))
+            .ok_or_else(|| anyhow!("Payment requests start with `{SCHEME}:`"))?;
+        let (recipient, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let recipient = recipient
+            .parse()
+            .with_context(|| format!("Invalid recipient {recipient:?}"))?;
+        let (mut amount, mut mint, mut reference) = (None, None, None);
+        for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+            let (name, value) = parameter
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid parameter {parameter:?}"))?;
+            let slot = match name {
+                "amount" => &mut amount,
+                "spl-token" => &mut mint,
+                "reference" => &mut reference,
+                _ => continue,
+            };
+            if slot.replace(value).is_some() {
+                bail!("Parameter {name} is given twice");
+            }
+        }
+        let amount = amount.ok_or_else(|| anyhow!("The request has no amount"))?;
+        //Base units are checked against the decimals of the mint once it is loaded
+        if amount.is_empty()
+            || amount.starts_with('.')
+            || !amount.chars().all(|c| c.is_ascii_digit() || c == '.')
+        {
+            bail!("Invalid amount {amount:?}");
+        }
+        let mint = mint.ok_or_else(|| anyhow!("The request has no spl-token mint"))?;
+        let reference = reference.ok_or_else(|| anyhow!("The request has no reference"))?;
+        Ok(Self {
+            recipient,
+            mint: mint
+                .parse()
+                .with_context(|| format!("Invalid mint {mint:?}"))?,
+            amount: amount.to_owned(),
+            reference: reference
+                .parse()
+                .with_context(|| format!("Invalid reference {reference:?}"))?,
+        })
+    }
+}
+
+/// Polls the latest transactions of the recipient of `request` every `poll_interval` until one
+/// that succeeded carries its reference as memo, and returns its signature. Fails once `timeout`
+/// has elapsed.
+///
+/// A transaction already landed is found too, as long as it is among the latest hundred of the
+/// account. The amount is encrypted, so only the reference is matched: decrypt the pending
+/// balance with the recipient's keys to check it. A client at `processed` commitment looks them
+/// up at `confirmed`, which `getSignaturesForAddress` requires.
+#[instrument(skip_all, fields(recipient = %request.recipient, reference = %request.reference))]
+pub async fn wait_for_payment(
+    rpc_client: &RpcClient,
+    request: &PaymentRequest,
+    poll_interval: Duration,
+    timeout: Duration,
+) -> Result<Signature> {
+    let started = Instant::now();
+    let memo = request.memo();
+    loop {
+        let statuses = rpc_client
+            .get_signatures_for_address_with_config(
+                &request.recipient,
+                GetConfirmedSignaturesForAddress2Config {
+                    limit: Some(SIGNATURE_LOOKUP_LIMIT),
+                    commitment: Some(signature_history_commitment(rpc_client)),
+                    ..GetConfirmedSignaturesForAddress2Config::default()
+                },
+            )
+            .await?;
+        //Memos are listed as `[<LENGTH>] <MEMO>`, joined with `; `
+        let paid = statuses.into_iter().find(|status| {
+            status.err.is_none()
+                && status
+                    .memo
+                    .as_deref()
+                    .is_some_and(|memos| memos.contains(&memo))
+        });
+        if let Some(status) = paid {
+            return Ok(status.signature.parse()?);
+        }
+        if started.elapsed() >= timeout {
+            bail!(
+                "No payment of {} with reference {} within {:?}",
+                request.recipient,
+                request.reference,
+                timeout
+            );
+        }
+        debug!("Payment not found yet");
+        sleep(poll_interval).await;
+    }
+}
@@ -0,0 +1,286 @@
+use anyhow::{Context, Result, anyhow, bail};
+use futures::{StreamExt, stream};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use spl_token_client::spl_token_2022::solana_zk_sdk::encryption::{
+    auth_encryption::AeKey, elgamal::ElGamalKeypair,
+};
+use std::{fmt, str::FromStr};
+use tracing::{debug, instrument};
+
+use crate::{
+    TokenClient,
+    amount::{self, Amount},
+    authority::Authority,
+    balance::{ApplyUrgency, get_pending_credits},
+    batch::{BatchTransfer, transfer_batch},
+    mint::get_ata,
+    transfer::{TransferResult, get_recipient_elgamal_pubkey, requires_memo},
+};
+
+/// Header of the results CSV written by [`write_results`].
+pub const RESULTS_HEADER: &str = "recipient,amount,status,signature,error";
+
+/// Where a payment of a payroll stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaymentStatus {
+    /// Not paid yet.
+    Pending,
+    /// Transferred; the entry has the transfer signature.
+    Paid,
+    /// The recipient cannot be paid, see [`validate_recipients`].
+    Invalid,
+    /// The transfer failed or was not attempted after an earlier failure.
+    Failed,
+}
+
+impl PaymentStatus {
+    /// Name of the status in the results CSV.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PaymentStatus::Pending => "pending",
+            PaymentStatus::Paid => "paid",
+            PaymentStatus::Invalid => "invalid",
+            PaymentStatus::Failed => "failed",
+        }
+    }
+}
+
+impl fmt::Display for PaymentStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for PaymentStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(status: &str) -> Result<Self> {
+        match status {
+            "pending" => Ok(PaymentStatus::Pending),
+            "paid" => Ok(PaymentStatus::Paid),
+            "invalid" => Ok(PaymentStatus::Invalid),
+            "failed" => Ok(PaymentStatus::Failed),
+            _ => bail!("Unknown status `{status}`"),
+        }
+    }
+}
+
+/// One payment of a payroll: `amount` base units to the associated token account of
+/// `recipient`, and its outcome.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayrollEntry {
+    /// Wallet address of the recipient.
+    pub recipient: Pubkey,
+    /// Amount in base units, fee included on mints with transfer fees.
+    pub amount: u64,
+    /// Where the payment stands.
+    pub status: PaymentStatus,
+    /// Signature of the transfer, once paid.
+    pub signature: Option<Signature>,
+    /// Why the payment failed or the recipient is invalid.
+    pub error: Option<String>,
+}
+
+impl PayrollEntry {
+    /// Payment of `amount` to `recipient`, not made yet.
+    pub fn new(recipient: Pubkey, amount: u64) -> Self {
+        Self {
+            recipient,
+            amount,
+            status: PaymentStatus::Pending,
+            signature: None,
+            error: None,
+        }
+    }
+
+    // Marks the entry `status`, failed with `err`, kept on one line of the results
+    fn fail(&mut self, status: PaymentStatus, err: &anyhow::Error) {
+        self.status = status;
+        self.error = Some(format!("{err:#}").replace(['\n', '\r'], " "));
+    }
+}
+
+/// Parses a payroll CSV of a mint with `decimals` decimals: `recipient,amount` rows, amounts as
+/// [`Amount`]s, in tokens or in base units with a `raw` suffix, under an optional
+/// `recipient,amount` header.
+///
+/// A results CSV of [`write_results`] is a payroll too: its `status` and `signature` columns are
+/// read back, so paid rows stay paid on a re-run and the others are attempted again. Blank lines
+/// are skipped.
+pub fn parse_payroll(contents: &str, decimals: u8) -> Result<Vec<PayrollEntry>> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .filter(|(line_number, line)| *line_number > 1 || !line.starts_with("recipient,"))
+        .map(|(line_number, line)| {
+            //The error column is last and may hold commas, so it is never split
+            let mut fields = line.splitn(5, ',').map(str::trim);
+            let (Some(recipient), Some(amount)) = (fields.next(), fields.next()) else {
+                bail!("Line {line_number}: expected `recipient,amount`, got `{line}`");
+            };
+            let mut entry = PayrollEntry::new(
+                Pubkey::from_str(recipient)
+                    .with_context(|| format!("Line {line_number}: invalid recipient"))?,
+                amount
+                    .parse::<Amount>()
+                    .and_then(|amount| amount.to_base_units(decimals))
+                    .with_context(|| format!("Line {line_number}: invalid amount"))?,
+            );
+            let status = fields
+                .next()
+                .filter(|status| !status.is_empty())
+                .map(str::parse::<PaymentStatus>)
+                .transpose()
+                .with_context(|| format!("Line {line_number}: invalid status"))?;
+            //Unpaid rows are all attempted again
+            if status == Some(PaymentStatus::Paid) {
+                let signature = fields
+                    .next()
+                    .filter(|signature| !signature.is_empty())
+                    .ok_or_else(|| anyhow!("Line {line_number}: paid without a signature"))?;
+                entry.status = PaymentStatus::Paid;
+                entry.signature = Some(
+                    signature
+                        .parse()
+                        .with_context(|| format!("Line {line_number}: invalid signature"))?,
+                );
+            }
+            Ok(entry)
+        })
+        .collect()
+}
+
+/// Writes `entries` of a mint with `decimals` decimals as a results CSV under
+/// [`RESULTS_HEADER`], one row per entry in order with amounts in tokens, which
+/// [`parse_payroll`] reads back for a re-run.
+pub fn write_results(entries: &[PayrollEntry], decimals: u8) -> String {
+    let mut csv = format!("{RESULTS_HEADER}\n");
+    for entry in entries {
+        csv.push_str(&format!(
+            "{},{},{},{},{}\n",
+            entry.recipient,
+            amount::format(entry.amount, decimals),
+            entry.status,
+            entry
+                .signature
+                .map(|signature| signature.to_string())
+                .unwrap_or_default(),
+            entry.error.as_deref().map(quote).unwrap_or_default()
+        ));
+    }
+    csv
+}
+
+/// Checks that every pending recipient of `entries` can be paid, `concurrency` lookups at a
+/// time: its associated token account exists, is configured and approved for confidential
+/// transfers, requires no memo, as payroll transfers carry none, and has room in its pending
+/// credit counter. Recipients that cannot be paid are marked [`PaymentStatus::Invalid`], with the
+/// reason; returns their number.
+#[instrument(skip_all, fields(entries = entries.len()))]
+pub async fn validate_recipients(
+    token: &TokenClient,
+    entries: &mut [PayrollEntry],
+    concurrency: usize,
+) -> Result<usize> {
+    let checks = stream::iter(entries.iter())
+        .map(|entry| async move {
+            if entry.status == PaymentStatus::Paid {
+                return Ok(());
+            }
+            get_recipient_elgamal_pubkey(token, &entry.recipient).await?;
+            let ata_pubkey = get_ata(&entry.recipient, token.get_address());
+            if requires_memo(token, &ata_pubkey).await? {
+                bail!("{ata_pubkey} requires a memo on incoming transfers");
+            }
+            let pending_credits = get_pending_credits(token, &ata_pubkey).await?;
+            if pending_credits.urgency() == ApplyUrgency::Now {
+                bail!(
+                    "The pending credit counter of {ata_pubkey} is at its maximum of {}",
+                    pending_credits.maximum
+                );
+            }
+            Ok(())
+        })
+        .buffered(concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    let mut invalid = 0;
+    for (entry, check) in entries.iter_mut().zip(checks) {
+        match check {
+            Ok(()) => {
+                if entry.status == PaymentStatus::Invalid {
+                    entry.status = PaymentStatus::Pending;
+                    entry.error = None;
+                }
+            }
+            Err(err) => {
+                invalid += 1;
+                entry.fail(PaymentStatus::Invalid, &err);
+            }
+        }
+    }
+    debug!(invalid, "Validated recipients");
+    Ok(invalid)
+}
+
+/// Pays the entries of `entries` that are neither paid nor invalid from `source_ata`, through
+/// [`transfer_batch`] with `concurrency`, and records the outcome of each in its entry. Returns
+/// the transfers made, in order.
+///
+/// As in a batch, the transfers land in order and stop at the first failure; the entries left
+/// are marked failed and paid by a re-run of the results.
+pub async fn pay(
+    token: &TokenClient,
+    owner: &Authority,
+    source_ata: &Pubkey,
+    entries: &mut [PayrollEntry],
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+    concurrency: usize,
+) -> Result<Vec<(BatchTransfer, TransferResult)>> {
+    let mut due = entries
+        .iter_mut()
+        .filter(|entry| matches!(entry.status, PaymentStatus::Pending | PaymentStatus::Failed))
+        .collect::<Vec<_>>();
+    let transfers = due
+        .iter()
+        .map(|entry| BatchTransfer {
+            recipient: entry.recipient,
+            amount: entry.amount,
+        })
+        .collect::<Vec<_>>();
+    let outcomes = transfer_batch(
+        token,
+        owner,
+        source_ata,
+        &transfers,
+        elgamal_keypair,
+        aes_key,
+        concurrency,
+    )
+    .await?;
+    let mut paid = vec![];
+    for (entry, outcome) in due.iter_mut().zip(outcomes) {
+        match outcome.result {
+            Ok(result) => {
+                entry.status = PaymentStatus::Paid;
+                entry.signature = Some(result.transfer_signature);
+                entry.error = None;
+                paid.push((outcome.transfer, result));
+            }
+            Err(err) => entry.fail(PaymentStatus::Failed, &err),
+        }
+    }
+    Ok(paid)
+}
+
+// CSV field holding `value`, quoted when it contains a delimiter or a quote
+fn quote(value: &str) -> String {
+    if value.contains([',', '"']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
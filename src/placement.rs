@@ -0,0 +1,548 @@
+use anyhow::{Result, anyhow, bail};
+use bytemuck::Pod;
+use solana_sdk::{
+    compute_budget::ComputeBudgetInstruction,
+    hash::Hash,
+    instruction::Instruction,
+    message::{AddressLookupTableAccount, Message, VersionedMessage, v0},
+    packet::PACKET_DATA_SIZE,
+    pubkey::Pubkey,
+    signature::Signature,
+    signer::Signer,
+    system_instruction::transfer,
+    transaction::VersionedTransaction,
+};
+use spl_memo::build_memo;
+use spl_token_client::spl_token_2022::solana_zk_sdk::zk_elgamal_proof_program::proof_data::{
+    ProofType as ZkProofType, ZkProofData,
+};
+use spl_token_confidential_transfer_proof_extraction::instruction::{ProofData, ProofLocation};
+use std::collections::HashSet;
+use tracing::debug;
+
+use crate::{
+    TokenClient,
+    bundle::JitoBundle,
+    lookup_table::LookupTable,
+    nonce::DurableNonces,
+    utils::{attach_memo, into_signature},
+};
+
+/// Largest serialized transaction the cluster accepts.
+pub const TRANSACTION_SIZE_LIMIT: usize = PACKET_DATA_SIZE;
+
+//Fee payer of the transactions whose size is estimated, distinct from any instruction account
+const FEE_PAYER_PLACEHOLDER: Pubkey = Pubkey::new_from_array([0xff; 32]);
+
+/// Compute units left for verifying proofs in the instruction data, out of the 200_000 a
+/// transaction gets without a compute budget instruction, once the token instruction is paid for.
+pub const INLINE_VERIFY_COMPUTE_UNITS: u64 = 100_000;
+
+/// Compute unit limit requested by every packed transaction, the most a transaction can get, so
+/// proofs verified together never run out of compute units.
+pub const PACKED_COMPUTE_UNIT_LIMIT: u32 = 1_400_000;
+
+/// How an operation sends the transactions creating its proof context state accounts, the one
+/// with the operation itself and the ones closing the accounts.
+#[derive(Debug, Clone, Default)]
+#[allow(clippy::large_enum_variant)]
+pub enum Packing {
+    /// One transaction per step, through the token client.
+    #[default]
+    Separate,
+    /// As few transactions as the steps fit in, see [`pack_instructions`]. `fee_payer` must be
+    /// the fee payer of the token client: it pays for the accounts created along the way.
+    ///
+    /// With a `lookup_table`, the transactions are v0 transactions loading the accounts in the
+    /// table by index, so more steps fit in each. With a `bundle`, they are submitted together
+    /// as a Jito bundle, landing all or none. With `nonces`, each is built on a durable nonce
+    /// account of its own.
+    Packed {
+        /// Fee payer of the token client.
+        fee_payer: Pubkey,
+        /// Lookup table the transactions are compiled against, if any.
+        lookup_table: Option<LookupTable>,
+        /// Block engine the transactions are bundled through, if any.
+        bundle: Option<JitoBundle>,
+        /// Durable nonce accounts the transactions are built on, if any.
+        nonces: Option<DurableNonces>,
+    },
+}
+
+/// Transactions of an operation packed by [`pack_instructions`].
+#[derive(Debug, Clone)]
+pub struct PackedTransactions {
+    /// Instructions of each transaction, in the order they are sent.
+    pub transactions: Vec<Vec<Instruction>>,
+    /// Index in `transactions` of the transaction holding each instruction group.
+    pub group_transactions: Vec<usize>,
+    /// Index in `transactions` of the transaction holding the operation.
+    pub operation_transaction: usize,
+    /// Indices in `transactions` of those built on a recent blockhash although the others are
+    /// built on durable nonces: verifications too large to advance a nonce as well, which only
+    /// the fee payer signs.
+    pub on_blockhash: Vec<usize>,
+}
+
+impl PackedTransactions {
+    /// Number of transactions built on a durable nonce, when packed with nonces.
+    pub fn nonce_transactions(&self) -> usize {
+        self.transactions.len() - self.on_blockhash.len()
+    }
+}
+
+/// Where a proof is verified for the instruction using it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofPlacement {
+    /// In a verify instruction of the same transaction, with the proof in its instruction data.
+    InstructionData,
+    /// Into a context state account, in earlier transactions, referenced by the instruction.
+    ContextAccount,
+}
+
+/// Size and verification cost of a proof, as input to [`plan_proof_placements`].
+#[derive(Debug, Clone, Copy)]
+pub struct ProofCost {
+    /// Type of the proof.
+    pub proof_type: ZkProofType,
+    /// Size of the proof data in bytes.
+    pub size: usize,
+}
+
+impl ProofCost {
+    /// Cost of verifying `proof_data`.
+    pub fn of<ZK: Pod + ZkProofData<U>, U: Pod>(_proof_data: &ZK) -> Self {
+        Self {
+            proof_type: ZK::PROOF_TYPE,
+            size: size_of::<ZK>(),
+        }
+    }
+
+    /// Compute units the ZK ElGamal proof program charges to verify the proof, as set by the
+    /// program for each verify instruction.
+    pub fn compute_units(&self) -> u64 {
+        match self.proof_type {
+            ZkProofType::ZeroCiphertext => 6_000,
+            ZkProofType::CiphertextCiphertextEquality => 8_000,
+            ZkProofType::CiphertextCommitmentEquality => 6_400,
+            ZkProofType::PubkeyValidity => 2_600,
+            ZkProofType::PercentageWithCap => 6_500,
+            ZkProofType::BatchedRangeProofU64 => 111_000,
+            ZkProofType::BatchedRangeProofU128 => 200_000,
+            ZkProofType::BatchedRangeProofU256 => 368_000,
+            ZkProofType::GroupedCiphertext2HandlesValidity => 6_400,
+            ZkProofType::BatchedGroupedCiphertext2HandlesValidity => 13_000,
+            ZkProofType::GroupedCiphertext3HandlesValidity => 8_100,
+            ZkProofType::BatchedGroupedCiphertext3HandlesValidity => 16_400,
+            _ => u64::MAX,
+        }
+    }
+}
+
+/// Serialized size of a transaction of `instructions`, signatures included, paid by a fee payer
+/// signing none of them: the largest it can be for whichever fee payer sends it.
+pub fn transaction_size(instructions: &[Instruction]) -> usize {
+    transaction_size_with_payer(instructions, &FEE_PAYER_PLACEHOLDER)
+}
+
+/// Serialized size of a transaction of `instructions` paid by `fee_payer`, signatures included.
+pub fn transaction_size_with_payer(instructions: &[Instruction], fee_payer: &Pubkey) -> usize {
+    let message = Message::new(instructions, Some(fee_payer));
+    let signatures = usize::from(message.header.num_required_signatures);
+    short_vec_size(signatures) + signatures * 64 + message.serialize().len()
+}
+
+/// Serialized size of a v0 transaction of `instructions` paid by `fee_payer`, signatures
+/// included, with the accounts in `lookup_table` loaded from it.
+pub fn v0_transaction_size(
+    instructions: &[Instruction],
+    fee_payer: &Pubkey,
+    lookup_table: &AddressLookupTableAccount,
+) -> Result<usize> {
+    let message = v0::Message::try_compile(
+        fee_payer,
+        instructions,
+        std::slice::from_ref(lookup_table),
+        Hash::default(),
+    )?;
+    let signatures = usize::from(message.header.num_required_signatures);
+    Ok(short_vec_size(signatures)
+        + signatures * 64
+        + VersionedMessage::V0(message).serialize().len())
+}
+
+// Serialized size of a packed transaction of `instructions` paid by `fee_payer`, a v0 one
+// compiled against `lookup_table` if any
+fn packed_transaction_size(
+    instructions: &[Instruction],
+    fee_payer: &Pubkey,
+    lookup_table: Option<&AddressLookupTableAccount>,
+) -> Result<usize> {
+    match lookup_table {
+        Some(lookup_table) => v0_transaction_size(instructions, fee_payer, lookup_table),
+        None => Ok(transaction_size_with_payer(instructions, fee_payer)),
+    }
+}
+
+// Signs `instructions` on `blockhash` as a transaction paid by `fee_payer`, also signed by
+// `signers`, the other signers its instructions require: a v0 transaction compiled against
+// `lookup_table` if any, a legacy one otherwise
+pub(crate) fn sign_transaction(
+    instructions: &[Instruction],
+    fee_payer: &dyn Signer,
+    signers: &[&dyn Signer],
+    lookup_table: Option<&AddressLookupTableAccount>,
+    blockhash: Hash,
+) -> Result<VersionedTransaction> {
+    let payer = fee_payer.pubkey();
+    let message = match lookup_table {
+        Some(lookup_table) => VersionedMessage::V0(v0::Message::try_compile(
+            &payer,
+            instructions,
+            std::slice::from_ref(lookup_table),
+            blockhash,
+        )?),
+        None => VersionedMessage::Legacy(Message::new_with_blockhash(
+            instructions,
+            Some(&payer),
+            &blockhash,
+        )),
+    };
+    let mut transaction_signers = vec![fee_payer];
+    transaction_signers.extend(signers.iter().filter(|signer| signer.pubkey() != payer));
+    Ok(VersionedTransaction::try_new(
+        message,
+        &transaction_signers,
+    )?)
+}
+
+/// Bytes a memo of `memo` adds to a transaction, with the memo program key.
+pub fn memo_size(memo: Option<&str>) -> usize {
+    memo.map_or(0, |memo| {
+        //Program key and index, no accounts, then the memo as instruction data
+        32 + 1 + 1 + short_vec_size(memo.len()) + memo.len()
+    })
+}
+
+/// Chooses where each of `proofs` is verified for the transaction built by `build`.
+///
+/// Proofs are moved into the instruction data, smallest first, as long as the transaction built
+/// with them stays within [`TRANSACTION_SIZE_LIMIT`] (less `reserved_size`, for instructions
+/// the token client adds such as memos) and their verification within
+/// [`INLINE_VERIFY_COMPUTE_UNITS`]. The others are verified into context state accounts, which
+/// takes one or two more transactions each. `build` gets one placement per proof, in the order
+/// of `proofs`, and returns the instructions of the transaction using them.
+pub fn plan_proof_placements(
+    proofs: &[ProofCost],
+    reserved_size: usize,
+    build: impl Fn(&[ProofPlacement]) -> Result<Vec<Instruction>>,
+) -> Result<Vec<ProofPlacement>> {
+    let mut placements = vec![ProofPlacement::ContextAccount; proofs.len()];
+    let base_size = transaction_size(&build(&placements)?) + reserved_size;
+    if base_size > TRANSACTION_SIZE_LIMIT {
+        bail!(
+            "The transaction takes {base_size} bytes even with every proof in a context state account, over the limit of {TRANSACTION_SIZE_LIMIT}"
+        );
+    }
+    let mut candidates = (0..proofs.len()).collect::<Vec<_>>();
+    candidates.sort_by_key(|&index| proofs[index].size);
+    let mut compute_units = 0u64;
+    for index in candidates {
+        let proof_compute_units = proofs[index].compute_units();
+        if compute_units.saturating_add(proof_compute_units) > INLINE_VERIFY_COMPUTE_UNITS {
+            continue;
+        }
+        placements[index] = ProofPlacement::InstructionData;
+        let size = transaction_size(&build(&placements)?) + reserved_size;
+        if size > TRANSACTION_SIZE_LIMIT {
+            placements[index] = ProofPlacement::ContextAccount;
+            continue;
+        }
+        compute_units += proof_compute_units;
+    }
+    debug!(?placements, "Planned proof placements");
+    Ok(placements)
+}
+
+/// Location of `proof_data` for an instruction constructor: the next verify instruction after
+/// the operation when it is placed in the instruction data, counted in `inline_proofs`, or
+/// `context_account` otherwise.
+pub fn proof_location<'a, T>(
+    placement: ProofPlacement,
+    proof_data: &'a T,
+    context_account: &'a Pubkey,
+    inline_proofs: &mut i8,
+) -> Result<ProofLocation<'a, T>> {
+    Ok(match placement {
+        ProofPlacement::InstructionData => {
+            *inline_proofs += 1;
+            ProofLocation::InstructionOffset(
+                (*inline_proofs).try_into()?,
+                ProofData::InstructionData(proof_data),
+            )
+        }
+        ProofPlacement::ContextAccount => ProofLocation::ContextStateAccount(context_account),
+    })
+}
+
+/// Instruction raising the compute unit limit of a packed transaction to
+/// [`PACKED_COMPUTE_UNIT_LIMIT`].
+pub fn compute_unit_limit_instruction() -> Instruction {
+    ComputeBudgetInstruction::set_compute_unit_limit(PACKED_COMPUTE_UNIT_LIMIT)
+}
+
+/// Packs instruction `groups` into the fewest transactions paid by `fee_payer`, in order.
+///
+/// Each group stays within one transaction, so an operation and the verify instructions of its
+/// inline proofs are never split, and each transaction ends with
+/// [`compute_unit_limit_instruction`], but for the groups too large below. The group at index
+/// `operation` gets `memo` right before it, so it opens a new transaction when there is a memo;
+/// the token program only accepts the memo of a transfer when it is the previous instruction.
+///
+/// With a `lookup_table`, transaction sizes are those of v0 transactions compiled against it.
+/// With `nonces`, they leave room for the instruction advancing the nonce.
+///
+/// A group other than the operation too large for these additions, such as a range proof
+/// verification, takes a transaction of its own with those it fits with: without the nonce when
+/// only the fee payer signs it, left on a recent blockhash, see
+/// [`PackedTransactions::on_blockhash`], then without the compute unit limit instruction, within
+/// the default limit.
+pub fn pack_instructions(
+    groups: &[Vec<Instruction>],
+    operation: usize,
+    fee_payer: &Pubkey,
+    memo: Option<&str>,
+    lookup_table: Option<&AddressLookupTableAccount>,
+    nonces: Option<&DurableNonces>,
+) -> Result<PackedTransactions> {
+    let fits = |instructions: &[Instruction],
+                reserved_size: usize,
+                nonces: Option<&DurableNonces>,
+                unit_limit: bool|
+     -> Result<bool> {
+        let mut instructions = instructions.to_vec();
+        if unit_limit {
+            instructions.push(compute_unit_limit_instruction());
+        }
+        //Every nonce account takes as many bytes, so the first stands for all
+        if let Some(nonces) = nonces {
+            instructions.insert(0, nonces.advance_instruction(0));
+        }
+        let size = packed_transaction_size(&instructions, fee_payer, lookup_table)?;
+        Ok(size + reserved_size <= TRANSACTION_SIZE_LIMIT)
+    };
+    let mut transactions = vec![];
+    let mut group_transactions = Vec::with_capacity(groups.len());
+    let mut operation_transaction = 0;
+    let mut on_blockhash = vec![];
+    let mut current = vec![];
+    let mut reserved_size = 0;
+    for (index, group) in groups.iter().enumerate() {
+        if !current.is_empty() {
+            let opens_transaction = index == operation && memo.is_some();
+            let joined = [current.as_slice(), group.as_slice()].concat();
+            if opens_transaction || !fits(&joined, reserved_size, nonces, true)? {
+                current.push(compute_unit_limit_instruction());
+                transactions.push(std::mem::take(&mut current));
+                reserved_size = 0;
+            }
+        }
+        if index == operation {
+            reserved_size = memo_size(memo);
+            operation_transaction = transactions.len();
+        }
+        current.extend_from_slice(group);
+        if !fits(&current, reserved_size, nonces, true)? {
+            //The group is alone in the transaction, as it did not fit with the previous ones
+            let group_nonces = nonces.filter(|_| !signed_by_fee_payer_only(group, fee_payer));
+            let unit_limit = fits(&current, reserved_size, group_nonces, true)?;
+            if index != operation
+                && (unit_limit || fits(&current, reserved_size, group_nonces, false)?)
+            {
+                if nonces.is_some() && group_nonces.is_none() {
+                    on_blockhash.push(transactions.len());
+                }
+                group_transactions.push(transactions.len());
+                if unit_limit {
+                    current.push(compute_unit_limit_instruction());
+                }
+                transactions.push(std::mem::take(&mut current));
+                continue;
+            }
+            bail!(
+                "Instruction group {index} does not fit in a transaction of {TRANSACTION_SIZE_LIMIT} bytes"
+            );
+        }
+        group_transactions.push(transactions.len());
+    }
+    if !current.is_empty() {
+        current.push(compute_unit_limit_instruction());
+        transactions.push(current);
+    }
+    debug!(
+        groups = groups.len(),
+        transactions = transactions.len(),
+        "Packed instructions"
+    );
+    Ok(PackedTransactions {
+        transactions,
+        group_transactions,
+        operation_transaction,
+        on_blockhash,
+    })
+}
+
+// Whether no account of `instructions` but `fee_payer` has to sign them, e.g. verifications into
+// context state accounts
+fn signed_by_fee_payer_only(instructions: &[Instruction], fee_payer: &Pubkey) -> bool {
+    instructions
+        .iter()
+        .flat_map(|instruction| &instruction.accounts)
+        .all(|account| !account.is_signer || account.pubkey == *fee_payer)
+}
+
+// Sends the transactions of `packed` in order, with `memo` attached to the one holding the
+// operation. Each transaction is signed by those of `signers` its instructions require, and by
+// the fee payer of `token`. With `lookup_table`, they are sent as v0 transactions through it;
+// with `bundle`, they are submitted together as a Jito bundle; with `nonces`, each but those of
+// `on_blockhash` is built on the next nonce account
+pub(crate) async fn send_packed_transactions(
+    token: &TokenClient,
+    packed: &PackedTransactions,
+    memo: Option<&str>,
+    signers: &[&dyn Signer],
+    lookup_table: Option<&LookupTable>,
+    bundle: Option<&JitoBundle>,
+    nonces: Option<&DurableNonces>,
+) -> Result<Vec<Signature>> {
+    if let Some(bundle) = bundle {
+        let lookup_table = lookup_table.map(|table| &table.account);
+        return send_bundle(packed, memo, signers, lookup_table, bundle).await;
+    }
+    if let Some(nonces) = nonces {
+        nonces.check_count(packed.nonce_transactions())?;
+    }
+    let mut signatures = Vec::with_capacity(packed.transactions.len());
+    let mut nonce_index = 0;
+    for (index, instructions) in packed.transactions.iter().enumerate() {
+        let transaction_signers = required_signers(instructions, signers);
+        let nonces = nonces.filter(|_| !packed.on_blockhash.contains(&index));
+        let signature = match (lookup_table, nonces) {
+            (Some(lookup_table), _) => {
+                let instructions = with_memo(packed, index, memo);
+                lookup_table
+                    .send(&instructions, &transaction_signers)
+                    .await?
+            }
+            (None, Some(nonces)) => {
+                let instructions = with_memo(packed, index, memo);
+                nonce_index += 1;
+                nonces
+                    .send(nonce_index - 1, &instructions, &transaction_signers)
+                    .await?
+            }
+            (None, None) => {
+                if index == packed.operation_transaction {
+                    attach_memo(token, memo);
+                }
+                into_signature(
+                    token
+                        .process_ixs(instructions, &transaction_signers)
+                        .await?,
+                )?
+            }
+        };
+        debug!(transaction = index, %signature, "Packed transaction confirmed");
+        signatures.push(signature);
+    }
+    Ok(signatures)
+}
+
+// Signs the transactions of `packed` on one blockhash, with `memo` attached to the one holding
+// the operation and the tip of `bundle` paid by the last one, or by one more transaction when it
+// does not fit, and submits them as a bundle. Returns the signatures of those of `packed`
+async fn send_bundle(
+    packed: &PackedTransactions,
+    memo: Option<&str>,
+    signers: &[&dyn Signer],
+    lookup_table: Option<&AddressLookupTableAccount>,
+    bundle: &JitoBundle,
+) -> Result<Vec<Signature>> {
+    let fee_payer = bundle.fee_payer.pubkey();
+    let mut transactions = (0..packed.transactions.len())
+        .map(|index| with_memo(packed, index, memo))
+        .collect::<Vec<_>>();
+    let tip = transfer(&fee_payer, &bundle.tip_account(), bundle.tip_lamports);
+    let last = transactions
+        .last_mut()
+        .ok_or_else(|| anyhow!("Nothing to bundle"))?;
+    last.push(tip.clone());
+    if packed_transaction_size(last, &fee_payer, lookup_table)? > TRANSACTION_SIZE_LIMIT {
+        last.pop();
+        transactions.push(vec![tip]);
+    }
+    let blockhash = bundle.rpc_client.get_latest_blockhash().await?;
+    let signed = transactions
+        .iter()
+        .map(|instructions| {
+            sign_transaction(
+                instructions,
+                bundle.fee_payer.as_ref(),
+                &required_signers(instructions, signers),
+                lookup_table,
+                blockhash,
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut signatures = bundle.send(&signed).await?;
+    debug!(?signatures, "Bundle confirmed");
+    signatures.truncate(packed.transactions.len());
+    Ok(signatures)
+}
+
+// Instructions of the transaction at `index` of `packed`, preceded by `memo` when it holds the
+// operation, for transactions built without the token client
+fn with_memo(packed: &PackedTransactions, index: usize, memo: Option<&str>) -> Vec<Instruction> {
+    let mut instructions = packed.transactions[index].clone();
+    if let Some(memo) = memo
+        && index == packed.operation_transaction
+    {
+        instructions.insert(0, build_memo(memo.as_bytes(), &[]));
+    }
+    instructions
+}
+
+// Those of `signers` that `instructions` require, once each: signing with a key the transaction
+// does not require fails
+fn required_signers<'a>(
+    instructions: &[Instruction],
+    signers: &[&'a dyn Signer],
+) -> Vec<&'a dyn Signer> {
+    let required = instructions
+        .iter()
+        .flat_map(|instruction| &instruction.accounts)
+        .filter(|account| account.is_signer)
+        .map(|account| account.pubkey)
+        .collect::<HashSet<_>>();
+    let mut transaction_signers: Vec<&dyn Signer> = vec![];
+    for signer in signers {
+        let pubkey = signer.pubkey();
+        if required.contains(&pubkey)
+            && !transaction_signers
+                .iter()
+                .any(|added| added.pubkey() == pubkey)
+        {
+            transaction_signers.push(*signer);
+        }
+    }
+    transaction_signers
+}
+
+// Length of the compact-u16 prefix of a vector of `len` elements
+fn short_vec_size(len: usize) -> usize {
+    match len {
+        0..0x80 => 1,
+        0x80..0x4000 => 2,
+        _ => 3,
+    }
+}
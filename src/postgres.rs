@@ -0,0 +1,375 @@
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use spl_token_client::spl_token_2022::solana_zk_sdk::encryption::elgamal::ElGamalPubkey;
+use sqlx::{
+    PgPool, Row,
+    migrate::Migrator,
+    postgres::{PgPoolOptions, PgRow},
+};
+use std::{fmt, str::FromStr};
+use tracing::info;
+
+use crate::{
+    balance::Balances,
+    indexer::Event,
+    storage::{AccountRecord, BalanceRecord, OperationKind, OperationRecord, Storage, now},
+};
+
+//Applied in order on connect; sqlx holds an advisory lock meanwhile, so replicas starting together
+//migrate once
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations/postgres");
+//Connections per instance; operations run one at a time, so a few cover the queries beside them
+const MAX_CONNECTIONS: u32 = 4;
+
+/// [`Storage`] in a PostgreSQL database, shared by every instance connected to it, e.g. the
+/// replicas of `serve` behind a load balancer.
+///
+/// Only available with the `postgres` feature. The schema is in `migrations/postgres`. Postgres
+/// has no unsigned integers, so amounts above `i64::MAX` are refused.
+pub struct PostgresStorage {
+    pool: PgPool,
+}
+
+impl PostgresStorage {
+    /// Connects to the database at `url`, `postgres://[USER[:PASSWORD]@]HOST[:PORT]/DATABASE`,
+    /// and migrates its schema as needed.
+    pub async fn connect(url: &str) -> Result<Self> {
+        let pool = PgPoolOptions::new()
+            .max_connections(MAX_CONNECTIONS)
+            .connect(url)
+            .await
+            .context("Unable to connect to the database")?;
+        MIGRATOR
+            .run(&pool)
+            .await
+            .context("Unable to migrate the database")?;
+        info!("Connected to the PostgreSQL database");
+        Ok(Self { pool })
+    }
+
+    /// Connection pool, e.g. for queries of an application sharing the database.
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn record_mint(
+        &self,
+        mint: &Pubkey,
+        authority: &Pubkey,
+        decimals: u8,
+        signature: &Signature,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO mints (address, authority, decimals, signature, created_at)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (address) DO UPDATE SET
+                 authority = EXCLUDED.authority,
+                 decimals = EXCLUDED.decimals,
+                 signature = EXCLUDED.signature,
+                 created_at = EXCLUDED.created_at",
+        )
+        .bind(mint.to_string())
+        .bind(authority.to_string())
+        .bind(i16::from(decimals))
+        .bind(signature.to_string())
+        .bind(signed(now())?)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn record_account(
+        &self,
+        owner: &Pubkey,
+        mint: &Pubkey,
+        account: &Pubkey,
+        elgamal_pubkey: &ElGamalPubkey,
+        signature: Option<&Signature>,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO accounts (address, owner, mint, elgamal_pubkey, signature, configured_at)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (address) DO UPDATE SET
+                 elgamal_pubkey = EXCLUDED.elgamal_pubkey,
+                 signature = COALESCE(EXCLUDED.signature, accounts.signature)",
+        )
+        .bind(account.to_string())
+        .bind(owner.to_string())
+        .bind(mint.to_string())
+        .bind(elgamal_pubkey.to_string())
+        .bind(signature.map(Signature::to_string))
+        .bind(signed(now())?)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn record_operation(
+        &self,
+        kind: OperationKind,
+        owner: &Pubkey,
+        mint: &Pubkey,
+        account: &Pubkey,
+        counterparty: Option<&Pubkey>,
+        amount: Option<u64>,
+        signatures: &[Signature],
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO operations
+                 (kind, owner, mint, account, counterparty, amount, signatures, recorded_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        )
+        .bind(kind.as_str())
+        .bind(owner.to_string())
+        .bind(mint.to_string())
+        .bind(account.to_string())
+        .bind(counterparty.map(Pubkey::to_string))
+        .bind(amount.map(signed).transpose()?)
+        .bind(
+            signatures
+                .iter()
+                .map(Signature::to_string)
+                .collect::<Vec<_>>(),
+        )
+        .bind(signed(now())?)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn record_balance(
+        &self,
+        mint: &Pubkey,
+        account: &Pubkey,
+        balances: &Balances,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO balances
+                 (account, mint, public, pending, available, pending_credits, recorded_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(account.to_string())
+        .bind(mint.to_string())
+        .bind(signed(balances.public)?)
+        .bind(signed(balances.pending)?)
+        .bind(signed(balances.available)?)
+        .bind(signed(balances.pending_credits.counter)?)
+        .bind(signed(now())?)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn accounts(&self, owner: &Pubkey) -> Result<Vec<AccountRecord>> {
+        let rows = sqlx::query(
+            "SELECT a.address, a.owner, a.mint, a.elgamal_pubkey, a.signature, a.configured_at,
+                    b.public, b.pending, b.available, b.pending_credits,
+                    b.recorded_at AS balance_recorded_at
+             FROM accounts a
+             LEFT JOIN balances b ON b.id =
+                 (SELECT MAX(id) FROM balances WHERE account = a.address)
+             WHERE a.owner = $1
+             ORDER BY a.mint, a.address",
+        )
+        .bind(owner.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter()
+            .map(|row| {
+                Ok(AccountRecord {
+                    address: parsed(row, "address")?,
+                    owner: parsed(row, "owner")?,
+                    mint: parsed(row, "mint")?,
+                    elgamal_pubkey: row.try_get("elgamal_pubkey")?,
+                    signature: row
+                        .try_get::<Option<String>, _>("signature")?
+                        .map(|signature| parse("signature", &signature))
+                        .transpose()?,
+                    configured_at: unsigned(row.try_get("configured_at")?)?,
+                    latest_balance: match row.try_get::<Option<i64>, _>("balance_recorded_at")? {
+                        Some(recorded_at) => Some(BalanceRecord {
+                            public: unsigned(row.try_get("public")?)?,
+                            pending: unsigned(row.try_get("pending")?)?,
+                            available: unsigned(row.try_get("available")?)?,
+                            pending_credits: unsigned(row.try_get("pending_credits")?)?,
+                            recorded_at: unsigned(recorded_at)?,
+                        }),
+                        None => None,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    async fn history(
+        &self,
+        owner: &Pubkey,
+        mint: Option<&Pubkey>,
+        limit: u32,
+    ) -> Result<Vec<OperationRecord>> {
+        let rows = sqlx::query(
+            "SELECT kind, owner, mint, account, counterparty, amount, signatures, recorded_at
+             FROM operations
+             WHERE owner = $1 AND ($2::TEXT IS NULL OR mint = $2)
+             ORDER BY id DESC
+             LIMIT $3",
+        )
+        .bind(owner.to_string())
+        .bind(mint.map(Pubkey::to_string))
+        .bind(i64::from(limit))
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter()
+            .map(|row| {
+                Ok(OperationRecord {
+                    kind: parsed(row, "kind")?,
+                    owner: parsed(row, "owner")?,
+                    mint: parsed(row, "mint")?,
+                    account: parsed(row, "account")?,
+                    counterparty: row
+                        .try_get::<Option<String>, _>("counterparty")?
+                        .map(|counterparty| parse("counterparty", &counterparty))
+                        .transpose()?,
+                    amount: row
+                        .try_get::<Option<i64>, _>("amount")?
+                        .map(unsigned)
+                        .transpose()?,
+                    signatures: row
+                        .try_get::<Vec<String>, _>("signatures")?
+                        .iter()
+                        .map(|signature| parse("signatures", signature))
+                        .collect::<Result<_>>()?,
+                    recorded_at: unsigned(row.try_get("recorded_at")?)?,
+                })
+            })
+            .collect()
+    }
+
+    async fn index_cursor(&self, address: &Pubkey) -> Result<Option<Signature>> {
+        let row = sqlx::query("SELECT signature FROM index_cursors WHERE address = $1")
+            .bind(address.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+        row.map(|row| parsed(&row, "signature")).transpose()
+    }
+
+    async fn record_events(
+        &self,
+        address: &Pubkey,
+        signature: &Signature,
+        events: &[Event],
+    ) -> Result<()> {
+        let mut transaction = self.pool.begin().await?;
+        for event in events {
+            sqlx::query(
+                "INSERT INTO events
+                     (signature, instruction_index, slot, block_time, kind, mint, account,
+                      counterparty, amount)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                 ON CONFLICT (signature, instruction_index) DO NOTHING",
+            )
+            .bind(event.signature.to_string())
+            .bind(i32::try_from(event.instruction_index)?)
+            .bind(signed(event.slot)?)
+            .bind(event.block_time)
+            .bind(event.kind.as_str())
+            .bind(event.mint.to_string())
+            .bind(event.account.to_string())
+            .bind(
+                event
+                    .counterparty
+                    .map(|counterparty| counterparty.to_string()),
+            )
+            .bind(event.amount.map(signed).transpose()?)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        sqlx::query(
+            "INSERT INTO index_cursors (address, signature, indexed_at)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (address) DO UPDATE SET
+                 signature = EXCLUDED.signature,
+                 indexed_at = EXCLUDED.indexed_at",
+        )
+        .bind(address.to_string())
+        .bind(signature.to_string())
+        .bind(signed(now())?)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn events(
+        &self,
+        mint: &Pubkey,
+        account: Option<&Pubkey>,
+        limit: u32,
+    ) -> Result<Vec<Event>> {
+        let rows = sqlx::query(
+            "SELECT signature, instruction_index, slot, block_time, kind, mint, account,
+                    counterparty, amount
+             FROM events
+             WHERE mint = $1 AND ($2::TEXT IS NULL OR account = $2 OR counterparty = $2)
+             ORDER BY slot DESC, id DESC
+             LIMIT $3",
+        )
+        .bind(mint.to_string())
+        .bind(account.map(Pubkey::to_string))
+        .bind(i64::from(limit))
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter()
+            .map(|row| {
+                Ok(Event {
+                    signature: parsed(row, "signature")?,
+                    instruction_index: u32::try_from(row.try_get::<i32, _>("instruction_index")?)?,
+                    slot: unsigned(row.try_get("slot")?)?,
+                    block_time: row.try_get("block_time")?,
+                    kind: parsed(row, "kind")?,
+                    mint: parsed(row, "mint")?,
+                    account: parsed(row, "account")?,
+                    counterparty: row
+                        .try_get::<Option<String>, _>("counterparty")?
+                        .map(|counterparty| parse("counterparty", &counterparty))
+                        .transpose()?,
+                    amount: row
+                        .try_get::<Option<i64>, _>("amount")?
+                        .map(unsigned)
+                        .transpose()?,
+                })
+            })
+            .collect()
+    }
+}
+
+// Column `column` of `row`, parsed from its text
+fn parsed<T>(row: &PgRow, column: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    parse(column, &row.try_get::<String, _>(column)?)
+}
+
+fn parse<T>(column: &str, value: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|err| anyhow!("Invalid {column} {value:?} in the database: {err}"))
+}
+
+fn signed(value: u64) -> Result<i64> {
+    i64::try_from(value).with_context(|| format!("{value} is out of range for the database"))
+}
+
+fn unsigned(value: i64) -> Result<u64> {
+    u64::try_from(value).with_context(|| format!("Negative value {value} in the database"))
+}
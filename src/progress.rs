@@ -0,0 +1,44 @@
+use solana_sdk::signature::Signature;
+use std::{future::Future, time::Duration};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Stage reached by a long operation, such as a withdraw or transfer, while it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    /// Proof generation started.
+    GeneratingProofs,
+    /// Proof generation finished after `elapsed`.
+    ProofsGenerated {
+        /// Time spent generating the proofs.
+        elapsed: Duration,
+    },
+    /// A transaction was accepted by the RPC node.
+    Sent {
+        /// Signature of the transaction.
+        signature: Signature,
+    },
+    /// A transaction landed at the commitment of the RPC client.
+    Confirmed {
+        /// Signature of the transaction.
+        signature: Signature,
+    },
+}
+
+tokio::task_local! {
+    static SINK: UnboundedSender<Progress>;
+}
+
+/// Runs `operation`, sending the [`Progress`] of the library operations it awaits to `sink`
+/// as they happen, e.g. to stream it to the client of a service.
+///
+/// The progress is only reported from the task running `operation`, which is where the library
+/// generates proofs and sends transactions.
+pub async fn report_to<F: Future>(sink: UnboundedSender<Progress>, operation: F) -> F::Output {
+    SINK.scope(sink, operation).await
+}
+
+// Sends `progress` to the sink of the running `report_to`, if any
+pub(crate) fn report(progress: Progress) {
+    //Nobody listening, either outside of `report_to` or once the receiver is gone
+    let _ = SINK.try_with(|sink| sink.send(progress));
+}
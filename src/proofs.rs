@@ -0,0 +1,722 @@
+use anyhow::{Context, Result, anyhow, bail};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use bytemuck::Pod;
+use confidential_transfer_core::proofs::{
+    TransferFeeParams, TransferProofs, pubkey_validity_proof, transfer_proofs, verify_proof,
+    withdraw_proofs,
+};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{pubkey::Pubkey, signature::Signature, signer::Signer};
+use spl_token_client::spl_token_2022::{
+    extension::{
+        confidential_transfer::account_info::{TransferAccountInfo, WithdrawAccountInfo},
+        transfer_fee::TransferFee,
+    },
+    solana_zk_sdk::{
+        encryption::{
+            auth_encryption::AeKey,
+            elgamal::{ElGamalKeypair, ElGamalPubkey},
+            pod::{
+                auth_encryption::PodAeCiphertext,
+                elgamal::{PodElGamalCiphertext, PodElGamalPubkey},
+            },
+        },
+        zk_elgamal_proof_program::proof_data::{
+            BatchedGroupedCiphertext2HandlesValidityProofData,
+            BatchedGroupedCiphertext3HandlesValidityProofData, BatchedRangeProofU64Data,
+            BatchedRangeProofU128Data, BatchedRangeProofU256Data,
+            CiphertextCommitmentEqualityProofData, PercentageWithCapProofData,
+            PubkeyValidityProofData, ZkProofData,
+        },
+    },
+};
+use spl_token_confidential_transfer_proof_generation::withdraw::WithdrawProofData;
+use std::{path::Path, str::FromStr, sync::Arc, time::Instant};
+use tracing::{debug, instrument};
+
+use crate::{
+    TokenClient, metrics,
+    mint::get_ata,
+    progress::{self, Progress},
+    transfer::{
+        create_proof_account, get_auditor_elgamal_pubkey, get_confidential_transfer_account,
+        get_recipient_elgamal_pubkey, get_transfer_fee,
+    },
+    utils::parse_elgamal_pubkey,
+};
+
+//Version of the proof input and proof files
+const FILE_VERSION: u8 = 1;
+
+/// Runs the proof generation `generate` on a blocking thread, so the async runtime keeps
+/// serving other tasks while the proof math runs, and reports its [`Progress`].
+pub async fn spawn_proof_generation<T: Send + 'static>(
+    generate: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    progress::report(Progress::GeneratingProofs);
+    let started = Instant::now();
+    let generated = tokio::task::spawn_blocking(generate).await??;
+    let elapsed = started.elapsed();
+    metrics::observe_proof_generation(elapsed);
+    progress::report(Progress::ProofsGenerated { elapsed });
+    Ok(generated)
+}
+
+/// On-chain state a withdraw or transfer proof is generated against, so the proofs can be
+/// generated on a machine without network access.
+///
+/// Fetched with [`fetch_proof_inputs`] and carried over as a file with [`ProofInputs::save`].
+#[derive(Debug, Clone)]
+pub struct ProofInputs {
+    /// Mint of the token account.
+    pub mint: Pubkey,
+    /// Token account the proofs are for.
+    pub account: Pubkey,
+    /// ElGamal pubkey registered in the account; the keys used must match it.
+    pub elgamal_pubkey: ElGamalPubkey,
+    /// Available balance ciphertext of the account.
+    pub available_balance: PodElGamalCiphertext,
+    /// Available balance of the account, encrypted under its AES key.
+    pub decryptable_available_balance: PodAeCiphertext,
+    /// Recipient side of a transfer, when proof inputs for one were requested.
+    pub transfer: Option<TransferInputs>,
+}
+
+/// Recipient and mint settings a transfer proof encrypts the amount to.
+#[derive(Debug, Clone)]
+pub struct TransferInputs {
+    /// Destination token account.
+    pub destination: Pubkey,
+    /// ElGamal pubkey registered in the destination account.
+    pub destination_elgamal_pubkey: ElGamalPubkey,
+    /// Auditor ElGamal pubkey of the mint, if any.
+    pub auditor_elgamal_pubkey: Option<ElGamalPubkey>,
+    /// Transfer fee of the mint, if any.
+    pub transfer_fee: Option<TransferFeeParams>,
+}
+
+/// Fetches the proof inputs of the token account `account`, with those of a transfer to
+/// `recipient`'s associated token account when given.
+#[instrument(skip_all, fields(%account))]
+pub async fn fetch_proof_inputs(
+    token: &TokenClient,
+    account: &Pubkey,
+    recipient: Option<&Pubkey>,
+) -> Result<ProofInputs> {
+    let extension_data = get_confidential_transfer_account(token, account).await?;
+    let elgamal_pubkey = ElGamalPubkey::try_from(extension_data.elgamal_pubkey)
+        .map_err(|_| anyhow!("Invalid ElGamal pubkey registered for account {account}"))?;
+    let transfer = match recipient {
+        Some(recipient) => Some(TransferInputs {
+            destination: get_ata(recipient, token.get_address()),
+            destination_elgamal_pubkey: get_recipient_elgamal_pubkey(token, recipient).await?,
+            auditor_elgamal_pubkey: get_auditor_elgamal_pubkey(token).await?,
+            transfer_fee: get_transfer_fee(token).await?,
+        }),
+        None => None,
+    };
+    Ok(ProofInputs {
+        mint: *token.get_address(),
+        account: *account,
+        elgamal_pubkey,
+        available_balance: extension_data.available_balance,
+        decryptable_available_balance: extension_data.decryptable_available_balance,
+        transfer,
+    })
+}
+
+impl ProofInputs {
+    /// Writes the proof inputs to `path` as JSON.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = ProofInputsFile {
+            version: FILE_VERSION,
+            mint: self.mint.to_string(),
+            account: self.account.to_string(),
+            elgamal_pubkey: self.elgamal_pubkey.to_string(),
+            available_balance: self.available_balance.to_string(),
+            decryptable_available_balance: self.decryptable_available_balance.to_string(),
+            transfer: self.transfer.as_ref().map(|transfer| TransferInputsRecord {
+                destination: transfer.destination.to_string(),
+                destination_elgamal_pubkey: transfer.destination_elgamal_pubkey.to_string(),
+                auditor_elgamal_pubkey: transfer
+                    .auditor_elgamal_pubkey
+                    .as_ref()
+                    .map(ToString::to_string),
+                transfer_fee: transfer.transfer_fee.as_ref().map(|fee| TransferFeeRecord {
+                    epoch: fee.transfer_fee.epoch.into(),
+                    basis_points: fee.transfer_fee.transfer_fee_basis_points.into(),
+                    maximum_fee: fee.transfer_fee.maximum_fee.into(),
+                    withdraw_withheld_authority_elgamal_pubkey: fee
+                        .withdraw_withheld_authority_elgamal_pubkey
+                        .to_string(),
+                }),
+            }),
+        };
+        std::fs::write(path, serde_json::to_vec_pretty(&file)?)
+            .with_context(|| format!("Unable to write proof inputs {}", path.display()))
+    }
+
+    /// Reads the proof inputs written by [`ProofInputs::save`] to `path`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let invalid = || format!("Invalid proof inputs {}", path.display());
+        let contents = std::fs::read(path)
+            .with_context(|| format!("Unable to read proof inputs {}", path.display()))?;
+        let file: ProofInputsFile = serde_json::from_slice(&contents).with_context(invalid)?;
+        if file.version != FILE_VERSION {
+            bail!("Unsupported proof inputs version {}", file.version);
+        }
+        let transfer = file
+            .transfer
+            .map(|record| -> Result<TransferInputs> {
+                Ok(TransferInputs {
+                    destination: record.destination.parse()?,
+                    destination_elgamal_pubkey: parse_elgamal_pubkey(
+                        &record.destination_elgamal_pubkey,
+                    )?,
+                    auditor_elgamal_pubkey: record
+                        .auditor_elgamal_pubkey
+                        .as_deref()
+                        .map(parse_elgamal_pubkey)
+                        .transpose()?,
+                    transfer_fee: record
+                        .transfer_fee
+                        .map(|fee| -> Result<TransferFeeParams> {
+                            Ok(TransferFeeParams {
+                                transfer_fee: TransferFee {
+                                    epoch: fee.epoch.into(),
+                                    maximum_fee: fee.maximum_fee.into(),
+                                    transfer_fee_basis_points: fee.basis_points.into(),
+                                },
+                                withdraw_withheld_authority_elgamal_pubkey: parse_elgamal_pubkey(
+                                    &fee.withdraw_withheld_authority_elgamal_pubkey,
+                                )?,
+                            })
+                        })
+                        .transpose()?,
+                })
+            })
+            .transpose()
+            .with_context(invalid)?;
+        Ok(Self {
+            mint: file.mint.parse().with_context(invalid)?,
+            account: file.account.parse().with_context(invalid)?,
+            elgamal_pubkey: parse_elgamal_pubkey(&file.elgamal_pubkey).with_context(invalid)?,
+            available_balance: PodElGamalCiphertext::from_str(&file.available_balance)
+                .map_err(|_| anyhow!(invalid()))?,
+            decryptable_available_balance: PodAeCiphertext::from_str(
+                &file.decryptable_available_balance,
+            )
+            .map_err(|_| anyhow!(invalid()))?,
+            transfer,
+        })
+    }
+
+    // Fails unless `elgamal_keypair` is the one registered in the account
+    fn check_keys(&self, elgamal_keypair: &ElGamalKeypair) -> Result<()> {
+        if *elgamal_keypair.pubkey() != self.elgamal_pubkey {
+            bail!(
+                "The keys given are not the ones of {}, registered with ElGamal pubkey {}",
+                self.account,
+                self.elgamal_pubkey
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProofInputsFile {
+    version: u8,
+    mint: String,
+    account: String,
+    elgamal_pubkey: String,
+    available_balance: String,
+    decryptable_available_balance: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    transfer: Option<TransferInputsRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TransferInputsRecord {
+    destination: String,
+    destination_elgamal_pubkey: String,
+    auditor_elgamal_pubkey: Option<String>,
+    transfer_fee: Option<TransferFeeRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TransferFeeRecord {
+    epoch: u64,
+    basis_points: u16,
+    maximum_fee: u64,
+    withdraw_withheld_authority_elgamal_pubkey: String,
+}
+
+/// Operation a [`ProofBundle`] is generated for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProofPurpose {
+    /// Configuring an account: the pubkey validity proof.
+    ConfigureAccount,
+    /// A withdraw: the equality and range proofs.
+    Withdraw,
+    /// A transfer: the equality, ciphertext validity and range proofs, and the fee proofs on
+    /// mints with transfer fees.
+    Transfer,
+}
+
+/// Proof data type, naming the ZK ElGamal proof program instruction that verifies it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProofType {
+    /// ElGamal pubkey validity, for account configuration.
+    PubkeyValidity,
+    /// Equality of the new available balance ciphertext and commitment.
+    CiphertextCommitmentEquality,
+    /// Validity of the fee ciphertexts of a transfer with fee.
+    BatchedGroupedCiphertext2HandlesValidity,
+    /// Validity of the transfer amount ciphertexts.
+    BatchedGroupedCiphertext3HandlesValidity,
+    /// Fee of a transfer with fee matching the rate and cap.
+    PercentageWithCap,
+    /// Range proof of a withdraw.
+    BatchedRangeProofU64,
+    /// Range proof of a transfer.
+    BatchedRangeProofU128,
+    /// Range proof of a transfer with fee.
+    BatchedRangeProofU256,
+}
+
+impl ProofType {
+    /// Returns whether the proof is too large to be verified in the transaction creating its
+    /// context state account, so the two are sent separately.
+    pub fn needs_split_verification(self) -> bool {
+        matches!(
+            self,
+            ProofType::BatchedRangeProofU128 | ProofType::BatchedRangeProofU256
+        )
+    }
+}
+
+/// Proof data of one proof, as the bytes the ZK ElGamal proof program verifies.
+#[derive(Debug, Clone)]
+pub struct SerializedProof {
+    /// Type of the proof data.
+    pub proof_type: ProofType,
+    /// Proof data bytes.
+    pub data: Vec<u8>,
+}
+
+impl SerializedProof {
+    fn new<ZK: Pod>(proof_type: ProofType, proof_data: &ZK) -> Self {
+        Self {
+            proof_type,
+            data: bytemuck::bytes_of(proof_data).to_vec(),
+        }
+    }
+
+    // Proof data of type `ZK`, which must match `proof_type`
+    fn decode<ZK: Pod>(&self) -> Result<ZK> {
+        bytemuck::try_pod_read_unaligned(&self.data)
+            .map_err(|_| anyhow!("Invalid {:?} proof data", self.proof_type))
+    }
+}
+
+/// Proofs of one operation on a token account, generated where the account keys are and written
+/// to a file with [`ProofBundle::save`], to be verified on chain with [`submit_proofs`] from a
+/// machine with network access.
+#[derive(Debug, Clone)]
+pub struct ProofBundle {
+    /// Operation the proofs are for.
+    pub purpose: ProofPurpose,
+    /// Mint of the token account.
+    pub mint: Pubkey,
+    /// Token account the proofs are for.
+    pub account: Pubkey,
+    /// Amount of the withdraw or transfer, in base units.
+    pub amount: Option<u64>,
+    /// Destination token account of a transfer.
+    pub destination: Option<Pubkey>,
+    /// Available balance ciphertext a withdraw or transfer proof was generated against.
+    pub available_balance: Option<PodElGamalCiphertext>,
+    /// Proofs, in the order their context state accounts are created.
+    pub proofs: Vec<SerializedProof>,
+}
+
+impl ProofBundle {
+    /// Generates the pubkey validity proof configuring `account` for `elgamal_keypair`.
+    pub fn configure_account(
+        mint: Pubkey,
+        account: Pubkey,
+        elgamal_keypair: &ElGamalKeypair,
+    ) -> Result<Self> {
+        let proof_data = pubkey_validity_proof(elgamal_keypair)?;
+        Ok(Self {
+            purpose: ProofPurpose::ConfigureAccount,
+            mint,
+            account,
+            amount: None,
+            destination: None,
+            available_balance: None,
+            proofs: vec![SerializedProof::new(ProofType::PubkeyValidity, &proof_data)],
+        })
+    }
+
+    /// Generates the withdraw proofs of `amount` from the account of `inputs`.
+    pub fn withdraw(
+        inputs: &ProofInputs,
+        amount: u64,
+        elgamal_keypair: &ElGamalKeypair,
+        aes_key: &AeKey,
+    ) -> Result<Self> {
+        inputs.check_keys(elgamal_keypair)?;
+        let account = WithdrawAccountInfo {
+            available_balance: inputs.available_balance,
+            decryptable_available_balance: inputs.decryptable_available_balance,
+        };
+        let WithdrawProofData {
+            equality_proof_data,
+            range_proof_data,
+        } = withdraw_proofs(&account, amount, elgamal_keypair, aes_key)?;
+        debug!(account = %inputs.account, amount, "Generated withdraw proof data");
+        Ok(Self {
+            purpose: ProofPurpose::Withdraw,
+            mint: inputs.mint,
+            account: inputs.account,
+            amount: Some(amount),
+            destination: None,
+            available_balance: Some(inputs.available_balance),
+            proofs: vec![
+                SerializedProof::new(
+                    ProofType::CiphertextCommitmentEquality,
+                    &equality_proof_data,
+                ),
+                SerializedProof::new(ProofType::BatchedRangeProofU64, &range_proof_data),
+            ],
+        })
+    }
+
+    /// Generates the proofs of transferring `amount` from the account of `inputs` to the
+    /// recipient of its transfer inputs.
+    pub fn transfer(
+        inputs: &ProofInputs,
+        amount: u64,
+        elgamal_keypair: &ElGamalKeypair,
+        aes_key: &AeKey,
+    ) -> Result<Self> {
+        inputs.check_keys(elgamal_keypair)?;
+        let transfer = inputs.transfer.as_ref().ok_or_else(|| {
+            anyhow!("The proof inputs hold no recipient; export them with one for a transfer")
+        })?;
+        let account = TransferAccountInfo {
+            available_balance: inputs.available_balance,
+            decryptable_available_balance: inputs.decryptable_available_balance,
+        };
+        let proofs = match transfer_proofs(
+            &account,
+            amount,
+            elgamal_keypair,
+            aes_key,
+            &transfer.destination_elgamal_pubkey,
+            transfer.auditor_elgamal_pubkey.as_ref(),
+            transfer.transfer_fee.as_ref(),
+        )? {
+            TransferProofs::Plain(proof_data) => vec![
+                SerializedProof::new(
+                    ProofType::CiphertextCommitmentEquality,
+                    &proof_data.equality_proof_data,
+                ),
+                SerializedProof::new(
+                    ProofType::BatchedGroupedCiphertext3HandlesValidity,
+                    &proof_data
+                        .ciphertext_validity_proof_data_with_ciphertext
+                        .proof_data,
+                ),
+                SerializedProof::new(
+                    ProofType::BatchedRangeProofU128,
+                    &proof_data.range_proof_data,
+                ),
+            ],
+            TransferProofs::WithFee(proof_data) => vec![
+                SerializedProof::new(
+                    ProofType::CiphertextCommitmentEquality,
+                    &proof_data.equality_proof_data,
+                ),
+                SerializedProof::new(
+                    ProofType::BatchedGroupedCiphertext3HandlesValidity,
+                    &proof_data
+                        .transfer_amount_ciphertext_validity_proof_data_with_ciphertext
+                        .proof_data,
+                ),
+                SerializedProof::new(
+                    ProofType::BatchedRangeProofU256,
+                    &proof_data.range_proof_data,
+                ),
+                SerializedProof::new(
+                    ProofType::PercentageWithCap,
+                    &proof_data.percentage_with_cap_proof_data,
+                ),
+                SerializedProof::new(
+                    ProofType::BatchedGroupedCiphertext2HandlesValidity,
+                    &proof_data.fee_ciphertext_validity_proof_data,
+                ),
+            ],
+        };
+        debug!(account = %inputs.account, amount, proofs = proofs.len(), "Generated transfer proof data");
+        Ok(Self {
+            purpose: ProofPurpose::Transfer,
+            mint: inputs.mint,
+            account: inputs.account,
+            amount: Some(amount),
+            destination: Some(transfer.destination),
+            available_balance: Some(inputs.available_balance),
+            proofs,
+        })
+    }
+
+    /// Verifies every proof with [`verify_proof`], without network access.
+    ///
+    /// With `registered_elgamal_pubkey`, the ElGamal pubkey registered in the account, the
+    /// proofs must also have been generated with the keys of the account: proofs of other keys
+    /// verify on their own but fail in the withdraw or transfer instruction.
+    pub fn verify(&self, registered_elgamal_pubkey: Option<&ElGamalPubkey>) -> Result<()> {
+        for proof in &self.proofs {
+            //Pubkey the proof was generated with, for the proofs that name the account's own
+            let proof_pubkey = match proof.proof_type {
+                ProofType::PubkeyValidity => {
+                    let proof_data = proof.decode::<PubkeyValidityProofData>()?;
+                    verify_proof(&proof_data)?;
+                    Some(proof_data.context_data().pubkey)
+                }
+                ProofType::CiphertextCommitmentEquality => {
+                    let proof_data = proof.decode::<CiphertextCommitmentEqualityProofData>()?;
+                    verify_proof(&proof_data)?;
+                    Some(proof_data.context_data().pubkey)
+                }
+                ProofType::BatchedGroupedCiphertext2HandlesValidity => {
+                    verify_proof(
+                        &proof.decode::<BatchedGroupedCiphertext2HandlesValidityProofData>()?,
+                    )?;
+                    None
+                }
+                ProofType::BatchedGroupedCiphertext3HandlesValidity => {
+                    let proof_data =
+                        proof.decode::<BatchedGroupedCiphertext3HandlesValidityProofData>()?;
+                    verify_proof(&proof_data)?;
+                    Some(proof_data.context_data().first_pubkey)
+                }
+                ProofType::PercentageWithCap => {
+                    verify_proof(&proof.decode::<PercentageWithCapProofData>()?)?;
+                    None
+                }
+                ProofType::BatchedRangeProofU64 => {
+                    verify_proof(&proof.decode::<BatchedRangeProofU64Data>()?)?;
+                    None
+                }
+                ProofType::BatchedRangeProofU128 => {
+                    verify_proof(&proof.decode::<BatchedRangeProofU128Data>()?)?;
+                    None
+                }
+                ProofType::BatchedRangeProofU256 => {
+                    verify_proof(&proof.decode::<BatchedRangeProofU256Data>()?)?;
+                    None
+                }
+            };
+            match (proof_pubkey, registered_elgamal_pubkey) {
+                (Some(proof_pubkey), Some(registered_elgamal_pubkey))
+                    if proof_pubkey != PodElGamalPubkey::from(*registered_elgamal_pubkey) =>
+                {
+                    bail!(
+                        "The proofs were generated for ElGamal pubkey {proof_pubkey}, but {} registers {registered_elgamal_pubkey}; wrong owner, seed message or keystore?",
+                        self.account
+                    );
+                }
+                _ => {}
+            }
+            debug!(proof_type = ?proof.proof_type, "Verified proof locally");
+        }
+        Ok(())
+    }
+
+    /// Writes the proofs to `path` as JSON, with the proof data base64 encoded.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = ProofBundleFile {
+            version: FILE_VERSION,
+            purpose: self.purpose,
+            mint: self.mint.to_string(),
+            account: self.account.to_string(),
+            amount: self.amount,
+            destination: self.destination.as_ref().map(ToString::to_string),
+            available_balance: self.available_balance.as_ref().map(ToString::to_string),
+            proofs: self
+                .proofs
+                .iter()
+                .map(|proof| ProofRecord {
+                    proof_type: proof.proof_type,
+                    data: BASE64_STANDARD.encode(&proof.data),
+                })
+                .collect(),
+        };
+        std::fs::write(path, serde_json::to_vec_pretty(&file)?)
+            .with_context(|| format!("Unable to write proofs {}", path.display()))
+    }
+
+    /// Reads the proofs written by [`ProofBundle::save`] to `path`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let invalid = || format!("Invalid proof file {}", path.display());
+        let contents = std::fs::read(path)
+            .with_context(|| format!("Unable to read proofs {}", path.display()))?;
+        let file: ProofBundleFile = serde_json::from_slice(&contents).with_context(invalid)?;
+        if file.version != FILE_VERSION {
+            bail!("Unsupported proof file version {}", file.version);
+        }
+        Ok(Self {
+            purpose: file.purpose,
+            mint: file.mint.parse().with_context(invalid)?,
+            account: file.account.parse().with_context(invalid)?,
+            amount: file.amount,
+            destination: file
+                .destination
+                .map(|destination| destination.parse())
+                .transpose()
+                .with_context(invalid)?,
+            available_balance: file
+                .available_balance
+                .map(|balance| {
+                    PodElGamalCiphertext::from_str(&balance).map_err(|_| anyhow!(invalid()))
+                })
+                .transpose()?,
+            proofs: file
+                .proofs
+                .into_iter()
+                .map(|record| {
+                    Ok(SerializedProof {
+                        proof_type: record.proof_type,
+                        data: BASE64_STANDARD.decode(&record.data)?,
+                    })
+                })
+                .collect::<Result<_>>()
+                .with_context(invalid)?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProofBundleFile {
+    version: u8,
+    purpose: ProofPurpose,
+    mint: String,
+    account: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    amount: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    destination: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    available_balance: Option<String>,
+    proofs: Vec<ProofRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProofRecord {
+    proof_type: ProofType,
+    data: String,
+}
+
+/// Context state account created by [`submit_proofs`].
+#[derive(Debug, Clone)]
+pub struct SubmittedProof {
+    /// Type of the verified proof.
+    pub proof_type: ProofType,
+    /// Context state account holding the verified proof.
+    pub account: Pubkey,
+    /// Signature of the account creation, or of the verification when sent separately.
+    pub signature: Signature,
+}
+
+/// Verifies the proofs of `bundle` into context state accounts managed by `authority`, in the
+/// order of the bundle, and returns the accounts.
+///
+/// Withdraw and transfer proofs are checked against the current state of the account first:
+/// proofs generated against an older balance or other keys still verify, but no instruction can
+/// use them. Every proof is then verified locally with [`ProofBundle::verify`], so none of the
+/// accounts is created unless all of them can be. The accounts stay open until closed by
+/// `authority`, which recovers their rent.
+#[instrument(skip_all, fields(account = %bundle.account, purpose = ?bundle.purpose))]
+pub async fn submit_proofs(
+    token: &TokenClient,
+    authority: &Arc<dyn Signer>,
+    bundle: &ProofBundle,
+) -> Result<Vec<SubmittedProof>> {
+    if *token.get_address() != bundle.mint {
+        bail!(
+            "The proofs are for mint {}, not {}",
+            bundle.mint,
+            token.get_address()
+        );
+    }
+    let registered_elgamal_pubkey = match bundle.available_balance {
+        Some(available_balance) => {
+            let extension_data = get_confidential_transfer_account(token, &bundle.account).await?;
+            if extension_data.available_balance != available_balance {
+                bail!(
+                    "The available balance of {} changed since the proofs were generated; export the proof inputs and generate them again",
+                    bundle.account
+                );
+            }
+            Some(
+                ElGamalPubkey::try_from(extension_data.elgamal_pubkey).map_err(|_| {
+                    anyhow!("Invalid ElGamal pubkey registered for {}", bundle.account)
+                })?,
+            )
+        }
+        None => None,
+    };
+    bundle.verify(registered_elgamal_pubkey.as_ref())?;
+    let mut submitted = Vec::with_capacity(bundle.proofs.len());
+    for proof in &bundle.proofs {
+        let split = proof.proof_type.needs_split_verification();
+        let (keypair, signature) = match proof.proof_type {
+            ProofType::PubkeyValidity => {
+                let proof_data = proof.decode::<PubkeyValidityProofData>()?;
+                create_proof_account(token, authority, &proof_data, split).await?
+            }
+            ProofType::CiphertextCommitmentEquality => {
+                let proof_data = proof.decode::<CiphertextCommitmentEqualityProofData>()?;
+                create_proof_account(token, authority, &proof_data, split).await?
+            }
+            ProofType::BatchedGroupedCiphertext2HandlesValidity => {
+                let proof_data =
+                    proof.decode::<BatchedGroupedCiphertext2HandlesValidityProofData>()?;
+                create_proof_account(token, authority, &proof_data, split).await?
+            }
+            ProofType::BatchedGroupedCiphertext3HandlesValidity => {
+                let proof_data =
+                    proof.decode::<BatchedGroupedCiphertext3HandlesValidityProofData>()?;
+                create_proof_account(token, authority, &proof_data, split).await?
+            }
+            ProofType::PercentageWithCap => {
+                let proof_data = proof.decode::<PercentageWithCapProofData>()?;
+                create_proof_account(token, authority, &proof_data, split).await?
+            }
+            ProofType::BatchedRangeProofU64 => {
+                let proof_data = proof.decode::<BatchedRangeProofU64Data>()?;
+                create_proof_account(token, authority, &proof_data, split).await?
+            }
+            ProofType::BatchedRangeProofU128 => {
+                let proof_data = proof.decode::<BatchedRangeProofU128Data>()?;
+                create_proof_account(token, authority, &proof_data, split).await?
+            }
+            ProofType::BatchedRangeProofU256 => {
+                let proof_data = proof.decode::<BatchedRangeProofU256Data>()?;
+                create_proof_account(token, authority, &proof_data, split).await?
+            }
+        };
+        debug!(proof_type = ?proof.proof_type, account = %keypair.pubkey(), %signature, "Created proof account");
+        submitted.push(SubmittedProof {
+            proof_type: proof.proof_type,
+            account: keypair.pubkey(),
+            signature,
+        });
+    }
+    Ok(submitted)
+}
@@ -0,0 +1,121 @@
+use anyhow::{Result, anyhow};
+use confidential_transfer_core::proofs::pubkey_validity_proof;
+use solana_sdk::{
+    account::from_account, pubkey::Pubkey, rent::Rent, signature::Signature, signer::Signer,
+    system_instruction::transfer, sysvar,
+};
+use spl_associated_token_account::instruction::create_associated_token_account_idempotent;
+use spl_elgamal_registry::{
+    get_elgamal_registry_address,
+    instruction::create_registry,
+    state::{ELGAMAL_REGISTRY_ACCOUNT_LEN, ElGamalRegistry},
+};
+use spl_token_client::{
+    spl_token_2022::{
+        extension::confidential_transfer::instruction::configure_account_with_registry,
+        id as token_2022_program_id,
+        solana_zk_sdk::encryption::elgamal::{ElGamalKeypair, ElGamalPubkey},
+    },
+    token::TokenError,
+};
+use spl_token_confidential_transfer_proof_extraction::instruction::{ProofData, ProofLocation};
+use tracing::{debug, instrument};
+
+use crate::{TokenClient, ledger::derive_with, mint::get_ata, utils::into_signature};
+
+/// Returns the ElGamal registry account of `owner`.
+pub fn get_registry_address(owner: &Pubkey) -> Pubkey {
+    get_elgamal_registry_address(owner, &spl_elgamal_registry::id())
+}
+
+/// Derives the ElGamal keypair registered for `owner` from its signature.
+///
+/// Unlike the per-account keys of [`derive_keys`](crate::mint::derive_keys), the registry keypair
+/// is bound to the wallet, so every account configured from the registry shares it.
+pub fn derive_registry_keypair(owner: &dyn Signer) -> Result<ElGamalKeypair> {
+    derive_with(owner, |owner| {
+        ElGamalKeypair::new_from_signer(owner, get_registry_address(&owner.pubkey()).as_ref())
+            .map_err(|e| anyhow!("Failed to generate registry ElGamal keypair: {e}"))
+    })
+}
+
+/// Returns the ElGamal pubkey in the registry of `owner`, or `None` if it has no registry.
+pub async fn get_registered_elgamal_pubkey(
+    token: &TokenClient,
+    owner: &Pubkey,
+) -> Result<Option<ElGamalPubkey>> {
+    let account = match token.get_account(get_registry_address(owner)).await {
+        Ok(account) => account,
+        Err(TokenError::AccountNotFound) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let registry = bytemuck::try_from_bytes::<ElGamalRegistry>(&account.data)
+        .map_err(|_| anyhow!("Invalid ElGamal registry account of {owner}"))?;
+    ElGamalPubkey::try_from(registry.elgamal_pubkey)
+        .map(Some)
+        .map_err(|_| anyhow!("Invalid ElGamal pubkey in the registry of {owner}"))
+}
+
+/// Creates the ElGamal registry of `owner` holding the pubkey of `elgamal_keypair`.
+///
+/// The registry program expects the account to be funded before it allocates it, so
+/// `fee_payer`, the payer of `token`, transfers its rent in the same transaction. The owner signs
+/// once here, with a pubkey validity proof; accounts configured from the registry afterwards
+/// need neither.
+#[instrument(skip_all, fields(owner = %owner.pubkey()))]
+pub async fn create_elgamal_registry(
+    token: &TokenClient,
+    fee_payer: &Pubkey,
+    owner: &dyn Signer,
+    elgamal_keypair: &ElGamalKeypair,
+) -> Result<Signature> {
+    let registry_address = get_registry_address(&owner.pubkey());
+    let rent = from_account::<Rent, _>(&token.get_account(sysvar::rent::id()).await?)
+        .ok_or_else(|| anyhow!("Invalid rent sysvar"))?;
+    let proof_data = pubkey_validity_proof(elgamal_keypair)?;
+    let proof_location =
+        ProofLocation::InstructionOffset(1.try_into()?, ProofData::InstructionData(&proof_data));
+    let mut ixs = vec![transfer(
+        fee_payer,                                          //Payer of the registry rent
+        &registry_address,                                  //Registry account
+        rent.minimum_balance(ELGAMAL_REGISTRY_ACCOUNT_LEN), //Rent-exempt balance
+    )];
+    ixs.extend(create_registry(&owner.pubkey(), proof_location)?);
+    let signature = into_signature(token.process_ixs(&ixs, &[owner]).await?)?;
+    debug!(registry = %registry_address, %signature, "Created ElGamal registry");
+    Ok(signature)
+}
+
+/// Creates the associated token account of `owner` if needed and configures it for confidential
+/// transfers with the ElGamal pubkey from the owner's registry.
+///
+/// No owner signature or proof is needed, so anyone can configure accounts for a wallet with a
+/// registry; `fee_payer`, the payer of `token`, funds the account and its reallocation. The
+/// program sets the decryptable available balance to an empty ciphertext, which the owner
+/// replaces on the first apply. Returns the account and the transaction signature.
+#[instrument(skip_all, fields(mint = %token.get_address(), %owner))]
+pub async fn configure_ata_with_registry(
+    token: &TokenClient,
+    fee_payer: &Pubkey,
+    owner: &Pubkey,
+) -> Result<(Pubkey, Signature)> {
+    let mint = token.get_address();
+    let ata_pubkey = get_ata(owner, mint);
+    let ixs = [
+        create_associated_token_account_idempotent(
+            fee_payer,                //Payer for the creation of token account
+            owner,                    //Owner of the token account
+            mint,                     //Token mint
+            &token_2022_program_id(), //Token program ID
+        ),
+        configure_account_with_registry(
+            &token_2022_program_id(),
+            &ata_pubkey,
+            mint,
+            &get_registry_address(owner), //Registry holding the ElGamal pubkey
+            Some(fee_payer),              //Payer of the reallocation
+        )?,
+    ];
+    let signature = into_signature(token.process_ixs::<[&dyn Signer; 0]>(&ixs, &[]).await?)?;
+    Ok((ata_pubkey, signature))
+}
@@ -0,0 +1,160 @@
+use anyhow::{Context, Result, anyhow};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::Signature,
+    signer::{Signer, SignerError},
+};
+use std::{sync::Arc, time::Duration};
+use tracing::debug;
+
+//Off-chain messages start with the signing domain b"\xffsolana offchain"; transaction messages
+//never start with 0xff
+const OFFCHAIN_MESSAGE_PREFIX: u8 = 0xff;
+
+/// Source of signers whose private keys live outside this process.
+///
+/// Keypair arguments of the form `remote://<KEY_ID>` are resolved through a backend, so the tool
+/// can run on hosts that never see the private keys. [`HttpSignerBackend`] is the built-in
+/// implementation; other transports, such as gRPC, plug in by implementing this trait.
+pub trait SignerBackend {
+    /// Returns the signer of the key `key_id` held by the backend.
+    fn signer(&self, key_id: &str) -> Result<Arc<dyn Signer>>;
+}
+
+/// [`SignerBackend`] talking to a remote signing service over HTTP(S) with JSON bodies.
+///
+/// For a key id `<ID>` the service exposes:
+///
+/// - `GET <URL>/keys/<ID>`, returning `{"pubkey": "<BASE58>"}`;
+/// - `POST <URL>/keys/<ID>/sign-transaction` with `{"message": "<BASE64>"}`, the serialized
+///   transaction message, returning `{"signature": "<BASE58>"}`;
+/// - `POST <URL>/keys/<ID>/sign-message` with the same bodies, for the off-chain messages the
+///   confidential keys are derived from (see
+///   [`OffchainMessageSigner`](crate::ledger::OffchainMessageSigner)).
+///
+/// Requests carry `Authorization: Bearer <TOKEN>` when a token is set. Every returned signature is
+/// verified against the key's pubkey.
+#[derive(Clone)]
+pub struct HttpSignerBackend {
+    url: String,
+    token: Option<String>,
+    agent: ureq::Agent,
+}
+
+#[derive(Deserialize)]
+struct PubkeyResponse {
+    pubkey: String,
+}
+
+#[derive(Serialize)]
+struct SignRequest {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct SignResponse {
+    signature: String,
+}
+
+impl HttpSignerBackend {
+    /// Backend for the service at `url`, authenticating with the bearer `token` if given.
+    pub fn new(url: &str, token: Option<String>) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            token,
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(120))
+                .build(),
+        }
+    }
+
+    fn key_url(&self, key_id: &str) -> String {
+        format!("{}/keys/{key_id}", self.url)
+    }
+
+    fn authorize(&self, request: ureq::Request) -> ureq::Request {
+        match &self.token {
+            Some(token) => request.set("Authorization", &format!("Bearer {token}")),
+            None => request,
+        }
+    }
+}
+
+impl SignerBackend for HttpSignerBackend {
+    fn signer(&self, key_id: &str) -> Result<Arc<dyn Signer>> {
+        let url = self.key_url(key_id);
+        let response: PubkeyResponse = self
+            .authorize(self.agent.get(&url))
+            .call()
+            .with_context(|| format!("Unable to fetch remote key {key_id} from {url}"))?
+            .into_json()
+            .with_context(|| format!("Invalid response from {url}"))?;
+        let pubkey = response
+            .pubkey
+            .parse()
+            .with_context(|| format!("Invalid pubkey of remote key {key_id}"))?;
+        debug!(key_id, %pubkey, "Loaded remote signer");
+        Ok(Arc::new(HttpSigner {
+            backend: self.clone(),
+            url,
+            pubkey,
+        }))
+    }
+}
+
+// Signer of one key of an HTTP signing service
+struct HttpSigner {
+    backend: HttpSignerBackend,
+    url: String,
+    pubkey: Pubkey,
+}
+
+impl HttpSigner {
+    fn sign(&self, endpoint: &str, message: &[u8]) -> Result<Signature> {
+        let url = format!("{}/{endpoint}", self.url);
+        let response: SignResponse = self
+            .backend
+            .authorize(self.backend.agent.post(&url))
+            .send_json(SignRequest {
+                message: BASE64_STANDARD.encode(message),
+            })
+            .with_context(|| format!("Remote signing request to {url} failed"))?
+            .into_json()
+            .with_context(|| format!("Invalid response from {url}"))?;
+        let signature: Signature = response
+            .signature
+            .parse()
+            .with_context(|| format!("Invalid signature from {url}"))?;
+        if !signature.verify(self.pubkey.as_ref(), message) {
+            return Err(anyhow!(
+                "{url} returned a signature not made by {}",
+                self.pubkey
+            ));
+        }
+        Ok(signature)
+    }
+}
+
+impl Signer for HttpSigner {
+    fn try_pubkey(&self) -> Result<Pubkey, SignerError> {
+        Ok(self.pubkey)
+    }
+
+    fn try_sign_message(&self, message: &[u8]) -> Result<Signature, SignerError> {
+        let endpoint = if message.first() == Some(&OFFCHAIN_MESSAGE_PREFIX) {
+            "sign-message"
+        } else {
+            "sign-transaction"
+        };
+        self.sign(endpoint, message)
+            .map_err(|err| SignerError::Custom(format!("{err:#}")))
+    }
+
+    //The service may hold the request for approval, and only signs key derivation messages
+    //wrapped as off-chain messages
+    fn is_interactive(&self) -> bool {
+        true
+    }
+}
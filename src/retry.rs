@@ -0,0 +1,173 @@
+use async_trait::async_trait;
+use rand::Rng;
+use serde_json::Value;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    nonblocking::rpc_client::RpcClient,
+    rpc_client::RpcClientConfig,
+    rpc_custom_error::{
+        JSON_RPC_SERVER_ERROR_BLOCK_NOT_AVAILABLE, JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY,
+    },
+    rpc_request::{RpcError, RpcRequest},
+    rpc_sender::{RpcSender as RpcTransport, RpcTransportStats},
+};
+use solana_rpc_client::http_sender::HttpSender;
+use solana_sdk::commitment_config::CommitmentConfig;
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+use tokio::time::sleep;
+use tracing::warn;
+
+use crate::{
+    metrics,
+    throttle::{RateLimit, ThrottlingTransport},
+};
+
+/// How the RPC calls of a client built by [`rpc_client`] are retried after a transient failure.
+///
+/// Waits between attempts grow exponentially from `initial_backoff` up to `max_backoff`, each
+/// drawn at random below its bound ("full jitter"), so clients failing together do not retry
+/// together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries of a single call; 0 disables retrying.
+    pub max_retries: u32,
+    /// Bound of the wait before the first retry.
+    pub initial_backoff: Duration,
+    /// Largest bound of the wait between retries.
+    pub max_backoff: Duration,
+    /// Retries shared by every call of the client, so a failing endpoint cannot stall a
+    /// command for `max_retries` times each of its calls.
+    pub budget: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(8),
+            budget: 50,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Bound of the wait before retry number `retry`, counted from 0.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff)
+    }
+}
+
+/// RPC client of `url` at `commitment`, retrying its calls according to `policy` and holding
+/// them, retries included, to `rate_limit`.
+pub fn rpc_client(
+    url: String,
+    commitment: CommitmentConfig,
+    policy: RetryPolicy,
+    rate_limit: RateLimit,
+) -> RpcClient {
+    RpcClient::new_sender(
+        RetryingTransport::new(
+            ThrottlingTransport::new(HttpSender::new(url), rate_limit),
+            policy,
+        ),
+        RpcClientConfig::with_commitment(commitment),
+    )
+}
+
+/// Transport retrying the requests of `inner` that fail with a [retryable](is_retryable)
+/// error, according to a [`RetryPolicy`].
+pub struct RetryingTransport<T> {
+    inner: T,
+    policy: RetryPolicy,
+    //Retries left of the budget
+    budget: AtomicU32,
+}
+
+impl<T> RetryingTransport<T> {
+    /// Wraps `inner`, with the full budget of `policy` left.
+    pub fn new(inner: T, policy: RetryPolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            budget: AtomicU32::new(policy.budget),
+        }
+    }
+
+    /// Retries left of the budget.
+    pub fn budget_left(&self) -> u32 {
+        self.budget.load(Ordering::Relaxed)
+    }
+
+    // Takes one retry from the budget, if any is left
+    fn take_retry(&self) -> bool {
+        self.budget
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                left.checked_sub(1)
+            })
+            .is_ok()
+    }
+}
+
+#[async_trait]
+impl<T: RpcTransport + Send + Sync> RpcTransport for RetryingTransport<T> {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        let mut retry = 0;
+        loop {
+            let err = match self.inner.send(request, params.clone()).await {
+                Err(err) if is_retryable(&err) => err,
+                Err(err) => {
+                    metrics::rpc_error(&request.to_string());
+                    return Err(err);
+                }
+                result => return result,
+            };
+            metrics::rpc_error(&request.to_string());
+            if retry >= self.policy.max_retries || !self.take_retry() {
+                return Err(err);
+            }
+            let bound = self.policy.backoff(retry);
+            let wait = rand::thread_rng().gen_range(Duration::ZERO..=bound);
+            warn!(%request, retry = retry + 1, ?wait, "RPC call failed, retrying: {err}");
+            sleep(wait).await;
+            retry += 1;
+        }
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.inner.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.inner.url()
+    }
+}
+
+/// Whether `err` is transient, so the same request may succeed later: a connection failure or
+/// timeout, rate limiting (HTTP 429, once the retries of the HTTP transport are spent), a server
+/// error, or a node reporting itself unhealthy or behind.
+///
+/// Requests are resent unchanged, which is safe for transactions too: a transaction resent
+/// under the same signature lands at most once.
+pub fn is_retryable(err: &ClientError) -> bool {
+    match err.kind() {
+        ClientErrorKind::Io(_) => true,
+        ClientErrorKind::Reqwest(err) => {
+            err.is_timeout()
+                || err.is_connect()
+                || err
+                    .status()
+                    .is_some_and(|status| status.as_u16() == 429 || status.is_server_error())
+        }
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. }) => matches!(
+            *code,
+            JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY | JSON_RPC_SERVER_ERROR_BLOCK_NOT_AVAILABLE
+        ),
+        _ => false,
+    }
+}
@@ -0,0 +1,264 @@
+use anyhow::{Result, anyhow, bail};
+use confidential_transfer_core::balance::decrypt_available_balance;
+use solana_sdk::{pubkey::Pubkey, signature::Signature, signer::Signer};
+use spl_token_client::spl_token_2022::{
+    extension::{
+        BaseStateWithExtensions, confidential_transfer::ConfidentialTransferAccount,
+        transfer_fee::TransferFeeAmount,
+    },
+    solana_zk_sdk::encryption::{auth_encryption::AeKey, elgamal::ElGamalKeypair},
+};
+use tracing::{debug, instrument};
+
+use crate::{
+    TokenClient,
+    authority::Authority,
+    close::{ClosedAccount, empty_and_close_account},
+    deposit::{apply_pending_balance, deposit},
+    mint::{ConfiguredAccount, create_configure_ata_with_keys, get_ata},
+    placement::Packing,
+    utils::into_signature,
+    withdraw::{WithdrawResult, withdraw_all},
+};
+
+/// Public tokens parked in a holding account while the token account is recreated.
+#[derive(Debug, Clone)]
+pub struct HoldingAccount {
+    /// Address of the holding account, closed again by the end of the rotation.
+    pub address: Pubkey,
+    /// Public tokens moved out of the token account.
+    pub amount: u64,
+    /// Signatures creating the holding account and moving the tokens out.
+    pub park_signatures: Vec<Signature>,
+    /// Signatures moving the tokens back, harvesting the fees they were charged and closing the
+    /// holding account.
+    pub return_signatures: Vec<Signature>,
+}
+
+/// Outcome of [`rotate_keys`].
+pub struct RotatedKeys {
+    /// Signature applying the pending balance under the old keys, if any was pending.
+    pub apply_signature: Option<Signature>,
+    /// Withdrawal of the available balance under the old keys, if it was not zero.
+    pub withdraw: Option<WithdrawResult>,
+    /// Holding account of the public balance, if the account held public tokens.
+    pub holding: Option<HoldingAccount>,
+    /// Emptying and closing of the account under the old keys.
+    pub closed: ClosedAccount,
+    /// The recreated account with its new keys.
+    pub configured: ConfiguredAccount,
+    /// Signatures depositing the withdrawn balance back and applying it under the new keys.
+    pub restore_signatures: Vec<Signature>,
+    /// Amount moved back into the confidential balance.
+    pub restored: u64,
+}
+
+/// Replaces the ElGamal keypair and AES key of the owner's associated token account with
+/// `new_keys`, for instance after the old keys may have leaked.
+///
+/// Token-2022 configures an account once, so the account is emptied and recreated: the pending
+/// balance is applied and the available balance withdrawn under the old keys, the public balance
+/// is parked in the holding account at `holding_account`, and the emptied account is closed,
+/// refunding its rent to `fee_payer`. The associated token account is then recreated and
+/// configured with the new keys, the public tokens are moved back, and the withdrawn amount is
+/// deposited and applied again. The holding account is closed at the end, refunding its rent to
+/// `fee_payer` as well; should the rotation fail on the way, the owner can still move the parked
+/// tokens out of it. On mints with transfer fees both public transfers are charged, and the
+/// restored confidential balance shrinks by the fees; the fees withheld in both accounts are
+/// harvested to the mint, so the recreated account can be closed or rotated again. Settings of
+/// the old account, such as disabled credits, required memos or its approval on mints approving
+/// accounts manually, are not carried over.
+///
+/// Only associated token accounts can be rotated, since an auxiliary account could not be
+/// recreated at its address without its keypair. `fee_payer` must be the payer of `token`. The
+/// new keys cannot be re-derived from the owner, so `store_keys` is called with them once the old
+/// account is closed and before the new one is configured; the rotation stops if it fails.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip_all, fields(ata = %ata_pubkey))]
+pub async fn rotate_keys(
+    token: &TokenClient,
+    fee_payer: &Pubkey,
+    owner: &Authority,
+    ata_pubkey: &Pubkey,
+    decimals: u8,
+    old_keys: (&ElGamalKeypair, &AeKey),
+    new_keys: (ElGamalKeypair, AeKey),
+    holding_account: &dyn Signer,
+    store_keys: impl FnOnce(&ElGamalKeypair, &AeKey) -> Result<()>,
+) -> Result<RotatedKeys> {
+    let (elgamal_keypair, aes_key) = old_keys;
+    if *ata_pubkey != get_ata(&owner.pubkey(), token.get_address()) {
+        bail!(
+            "{ata_pubkey} is not the associated token account of {}; only associated token accounts can be rotated",
+            owner.pubkey()
+        );
+    }
+
+    //Step1:Fold the pending balance into the available balance
+    let token_account = token.get_account_info(ata_pubkey).await?;
+    let extension_data = token_account.get_extension::<ConfidentialTransferAccount>()?;
+    let apply_signature = if u64::from(extension_data.pending_balance_credit_counter) > 0 {
+        let signature =
+            apply_pending_balance(token, owner, ata_pubkey, elgamal_keypair, aes_key).await?;
+        debug!(%signature, "Applied pending balance");
+        Some(signature)
+    } else {
+        None
+    };
+
+    //Step2:Withdraw the available balance to the public balance
+    let token_account = token.get_account_info(ata_pubkey).await?;
+    let available_balance = decrypt_available_balance(
+        token_account.get_extension::<ConfidentialTransferAccount>()?,
+        aes_key,
+    )
+    .ok_or_else(|| anyhow!("Failed to decrypt the available balance of {ata_pubkey}"))?;
+    let withdraw = if available_balance > 0 {
+        Some(
+            withdraw_all(
+                token,
+                owner,
+                ata_pubkey,
+                decimals,
+                elgamal_keypair,
+                aes_key,
+                None,
+                Packing::Separate,
+            )
+            .await?,
+        )
+    } else {
+        None
+    };
+
+    //Step3:Park the public balance, the account can only be closed once it holds no tokens
+    let public_balance = token.get_account_info(ata_pubkey).await?.base.amount;
+    let mut holding = if public_balance > 0 {
+        let address = holding_account.pubkey();
+        let create_signature = into_signature(
+            token
+                .create_auxiliary_token_account(holding_account, &owner.pubkey())
+                .await?,
+        )?;
+        let park_signature = into_signature(
+            token
+                .transfer(
+                    ata_pubkey,      //Source ata
+                    &address,        //Holding account
+                    &owner.pubkey(), //Owner of the ata
+                    public_balance,  //Whole public balance
+                    owner.signers(),
+                )
+                .await?,
+        )?;
+        debug!(%address, public_balance, "Parked public balance");
+        Some(HoldingAccount {
+            address,
+            amount: public_balance,
+            park_signatures: vec![create_signature, park_signature],
+            return_signatures: vec![],
+        })
+    } else {
+        None
+    };
+
+    //Step4:Empty and close the account under the old keys
+    let closed = empty_and_close_account(
+        token,
+        owner,
+        ata_pubkey,
+        fee_payer,
+        elgamal_keypair,
+        aes_key,
+    )
+    .await?;
+
+    //Step5:Recreate the account and configure it with the new keys
+    let (new_elgamal_keypair, new_aes_key) = new_keys;
+    //The old keys are useless from here on, the new ones must not get lost
+    store_keys(&new_elgamal_keypair, &new_aes_key)?;
+    let configured =
+        create_configure_ata_with_keys(token, fee_payer, owner, new_elgamal_keypair, new_aes_key)
+            .await?;
+    debug!(elgamal_pubkey = %configured.elgamal_keypair.pubkey(), "Reconfigured account");
+
+    //Step6:Move the public balance back and close the holding account
+    if let Some(holding) = &mut holding {
+        let holding_info = token.get_account_info(&holding.address).await?;
+        //Transfer fees were withheld from the parked amount
+        let received = holding_info.base.amount;
+        holding.return_signatures.push(into_signature(
+            token
+                .transfer(
+                    &holding.address,
+                    ata_pubkey,
+                    &owner.pubkey(),
+                    received,
+                    owner.signers(),
+                )
+                .await?,
+        )?);
+        //Withheld fees block the close of either account; anyone may move them to the mint
+        for address in [&holding.address, ata_pubkey] {
+            if let Some(signature) = harvest_public_fees(token, address).await? {
+                holding.return_signatures.push(signature);
+            }
+        }
+        holding.return_signatures.push(into_signature(
+            token
+                .close_account(
+                    &holding.address,
+                    fee_payer,
+                    &owner.pubkey(),
+                    owner.signers(),
+                )
+                .await?,
+        )?);
+        debug!(address = %holding.address, received, "Returned public balance");
+    }
+
+    //Step7:Deposit the withdrawn balance again under the new keys
+    let public_balance = token.get_account_info(ata_pubkey).await?.base.amount;
+    let restored = available_balance.min(public_balance);
+    let mut restore_signatures = vec![];
+    if restored > 0 {
+        restore_signatures.push(deposit(token, owner, ata_pubkey, restored, decimals, None).await?);
+        restore_signatures.push(
+            apply_pending_balance(
+                token,
+                owner,
+                ata_pubkey,
+                &configured.elgamal_keypair,
+                &configured.aes_key,
+            )
+            .await?,
+        );
+        debug!(restored, "Restored confidential balance");
+    }
+
+    Ok(RotatedKeys {
+        apply_signature,
+        withdraw,
+        holding,
+        closed,
+        configured,
+        restore_signatures,
+        restored,
+    })
+}
+
+// Moves the public transfer fees withheld in `address` to the mint, if it holds any
+async fn harvest_public_fees(token: &TokenClient, address: &Pubkey) -> Result<Option<Signature>> {
+    let withheld = token
+        .get_account_info(address)
+        .await?
+        .get_extension::<TransferFeeAmount>()
+        .map(|fee_amount| u64::from(fee_amount.withheld_amount))
+        .unwrap_or_default();
+    if withheld == 0 {
+        return Ok(None);
+    }
+    let signature = into_signature(token.harvest_withheld_tokens_to_mint(&[address]).await?)?;
+    debug!(%address, %signature, withheld, "Harvested public withheld fees");
+    Ok(Some(signature))
+}
@@ -0,0 +1,419 @@
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{amount::Amount, config::config_dir, keyring::MintRef};
+
+/// How late an occurrence may run and still count as on time, for [`CatchUp::Skip`].
+pub const ON_TIME_GRACE_SECS: u64 = 300;
+
+/// Most occurrences of one payment run by a single [`ScheduleState::due`] under
+/// [`CatchUp::All`]; the others are run on the next check.
+pub const MAX_CATCH_UP_RUNS: usize = 100;
+
+//Days searched for the next occurrence: enough to reach a February 29th
+const SEARCH_DAYS: u64 = 366 * 8 + 2;
+
+const SECS_PER_DAY: u64 = 86_400;
+
+/// Current time in seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// Formats `time`, in seconds since the Unix epoch, as `YYYY-MM-DD HH:MM UTC`.
+pub fn format_time(time: u64) -> String {
+    let (year, month, day) = civil_date(time / SECS_PER_DAY);
+    let minutes = time % SECS_PER_DAY / 60;
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02} UTC",
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+/// Cron expression of a recurring payment, evaluated in UTC.
+///
+/// Five fields: minute (0-59), hour (0-23), day of month (1-31), month (1-12) and day of week
+/// (0-7, both 0 and 7 are Sunday). A field is `*`, a value, a range `a-b`, any of them stepped
+/// with `/n`, or a comma separated list of those. As in Vixie cron, a day matching either the
+/// day of month or the day of week matches when both are restricted. The macros `@yearly`,
+/// `@monthly`, `@weekly`, `@daily` and `@hourly` are accepted too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    day_of_month_restricted: bool,
+    day_of_week_restricted: bool,
+}
+
+impl CronSchedule {
+    /// First occurrence strictly after `time`, in seconds since the Unix epoch, or `None` if the
+    /// expression never matches, like `0 0 30 2 *`.
+    pub fn next_after(&self, time: u64) -> Option<u64> {
+        let start = (time / 60 + 1) * 60;
+        let first_day = start / SECS_PER_DAY;
+        for day in first_day..first_day + SEARCH_DAYS {
+            if !self.matches_day(day) {
+                continue;
+            }
+            for hour in (0..24).filter(|hour| self.hours & (1 << hour) != 0) {
+                for minute in (0..60).filter(|minute| self.minutes & (1 << minute) != 0) {
+                    let occurrence = day * SECS_PER_DAY + hour * 3_600 + minute * 60;
+                    if occurrence >= start {
+                        return Some(occurrence);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Occurrences in `(after, until]`, oldest first, at most `limit` of them.
+    pub fn occurrences(&self, after: u64, until: u64, limit: usize) -> Vec<u64> {
+        let mut occurrences = vec![];
+        let mut time = after;
+        while occurrences.len() < limit {
+            match self.next_after(time) {
+                Some(occurrence) if occurrence <= until => {
+                    occurrences.push(occurrence);
+                    time = occurrence;
+                }
+                _ => break,
+            }
+        }
+        occurrences
+    }
+
+    // Whether `day`, in days since the Unix epoch, matches the day and month fields
+    fn matches_day(&self, day: u64) -> bool {
+        let (_, month, day_of_month) = civil_date(day);
+        //1970-01-01 was a Thursday
+        let day_of_week = (day + 4) % 7;
+        if self.months & (1 << month) == 0 {
+            return false;
+        }
+        let day_of_month = self.days_of_month & (1 << day_of_month) != 0;
+        let day_of_week = self.days_of_week & (1 << day_of_week) != 0;
+        if self.day_of_month_restricted && self.day_of_week_restricted {
+            day_of_month || day_of_week
+        } else {
+            day_of_month && day_of_week
+        }
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(expression: &str) -> Result<Self> {
+        let expanded = match expression.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            expression => expression,
+        };
+        let fields = expanded.split_whitespace().collect::<Vec<_>>();
+        let [minutes, hours, days_of_month, months, days_of_week] = fields[..] else {
+            bail!(
+                "Invalid cron expression `{expression}`: expected 5 fields, got {}",
+                fields.len()
+            );
+        };
+        let field = |value: &str, name: &str, min: u64, max: u64| {
+            parse_field(value, min, max)
+                .with_context(|| format!("Invalid {name} field in cron expression `{expression}`"))
+        };
+        let mut days_of_week_bits = field(days_of_week, "day of week", 0, 7)?;
+        //7 is Sunday too
+        if days_of_week_bits & (1 << 7) != 0 {
+            days_of_week_bits = (days_of_week_bits | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            expression: expression.trim().to_string(),
+            minutes: field(minutes, "minute", 0, 59)?,
+            hours: field(hours, "hour", 0, 23)?,
+            days_of_month: field(days_of_month, "day of month", 1, 31)?,
+            months: field(months, "month", 1, 12)?,
+            days_of_week: days_of_week_bits,
+            day_of_month_restricted: !days_of_month.starts_with('*'),
+            day_of_week_restricted: !days_of_week.starts_with('*'),
+        })
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+/// What a payment does about the occurrences missed while the scheduler was not running.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CatchUp {
+    /// Run every missed occurrence, oldest first: each monthly salary is paid.
+    #[default]
+    All,
+    /// Run the latest missed occurrence once, dropping the older ones.
+    Latest,
+    /// Drop missed occurrences; only those at most [`ON_TIME_GRACE_SECS`] late run.
+    Skip,
+}
+
+impl CatchUp {
+    /// Name of the catch-up in the config file.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CatchUp::All => "all",
+            CatchUp::Latest => "latest",
+            CatchUp::Skip => "skip",
+        }
+    }
+}
+
+impl fmt::Display for CatchUp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for CatchUp {
+    type Err = anyhow::Error;
+
+    fn from_str(catch_up: &str) -> Result<Self> {
+        match catch_up {
+            "all" => Ok(CatchUp::All),
+            "latest" => Ok(CatchUp::Latest),
+            "skip" => Ok(CatchUp::Skip),
+            _ => bail!("Unknown catch-up `{catch_up}`: expected all, latest or skip"),
+        }
+    }
+}
+
+/// A recurring confidential transfer, a `[[schedule]]` table of the config file.
+#[derive(Debug, Clone)]
+pub struct ScheduledPayment {
+    /// Unique name of the payment, the key of its state.
+    pub name: String,
+    /// When the payment recurs.
+    pub cron: CronSchedule,
+    /// Wallet address of the recipient, paid to its associated token account.
+    pub recipient: Pubkey,
+    /// Amount of each transfer, resolved against the decimals of the mint when paid.
+    pub amount: Amount,
+    /// Mint of the transfer, the `mint` of the config file when unset.
+    pub mint: Option<MintRef>,
+    /// Memo attached to each transfer.
+    pub memo: Option<String>,
+    /// What to do about occurrences missed during downtime.
+    pub catch_up: CatchUp,
+}
+
+/// Progress of one payment, as saved in the [`ScheduleState`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentRun {
+    /// Occurrences up to this time, in seconds since the Unix epoch, are handled.
+    pub checked_through: u64,
+    /// Last occurrence paid.
+    pub last_occurrence: Option<u64>,
+    /// Signature of the transfer of the last occurrence paid.
+    pub last_signature: Option<String>,
+}
+
+/// Occurrences of a payment to run now, from [`ScheduleState::due`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DueRuns {
+    /// Occurrences to pay, oldest first.
+    pub occurrences: Vec<u64>,
+    /// Missed occurrences dropped by the catch-up of the payment.
+    pub dropped: usize,
+    /// Time the payment is checked through once every occurrence is paid, for
+    /// [`ScheduleState::advance`].
+    pub through: u64,
+}
+
+/// Last-run state of the scheduled payments, persisted so that a restarted scheduler knows which
+/// occurrences it missed.
+///
+/// Keyed by payment name: renaming a payment starts it afresh. Changes are kept in memory until
+/// [`ScheduleState::save`].
+#[derive(Debug, Clone)]
+pub struct ScheduleState {
+    path: PathBuf,
+    runs: BTreeMap<String, PaymentRun>,
+}
+
+impl ScheduleState {
+    /// Default location of the state file.
+    pub fn default_path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("schedule-state.json"))
+    }
+
+    /// Loads the state file at `path`; a missing file is an empty state.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self {
+                path,
+                runs: BTreeMap::new(),
+            });
+        }
+        let contents = std::fs::read(&path)
+            .with_context(|| format!("Unable to read schedule state {}", path.display()))?;
+        let runs = serde_json::from_slice(&contents)
+            .with_context(|| format!("Invalid schedule state {}", path.display()))?;
+        Ok(Self { path, runs })
+    }
+
+    /// Path of the state file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes the state through a temporary file, creating its directory if needed.
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Unable to create {}", parent.display()))?;
+        }
+        let tmp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec_pretty(&self.runs)?)
+            .with_context(|| format!("Unable to write schedule state {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Unable to write schedule state {}", self.path.display()))?;
+        Ok(())
+    }
+
+    /// Progress of the payment `name`, if it was ever checked.
+    pub fn run(&self, name: &str) -> Option<&PaymentRun> {
+        self.runs.get(name)
+    }
+
+    /// Occurrences of `payment` due at `now` since it was last checked, after its catch-up.
+    ///
+    /// A payment never checked before has nothing due: it starts at `now`, so adding a payment
+    /// does not pay its past occurrences.
+    pub fn due(&self, payment: &ScheduledPayment, now: u64) -> DueRuns {
+        let Some(run) = self.runs.get(&payment.name) else {
+            return DueRuns {
+                occurrences: vec![],
+                dropped: 0,
+                through: now,
+            };
+        };
+        let limit = match payment.catch_up {
+            CatchUp::All => MAX_CATCH_UP_RUNS,
+            CatchUp::Latest | CatchUp::Skip => usize::MAX,
+        };
+        let mut occurrences = payment.cron.occurrences(run.checked_through, now, limit);
+        //Under the limit, every occurrence until now was found
+        let through = match occurrences.last() {
+            Some(last) if occurrences.len() == limit => *last,
+            _ => now,
+        };
+        let found = occurrences.len();
+        match payment.catch_up {
+            CatchUp::All => {}
+            CatchUp::Latest => occurrences = occurrences.split_off(found.saturating_sub(1)),
+            CatchUp::Skip => {
+                occurrences.retain(|occurrence| now - occurrence <= ON_TIME_GRACE_SECS);
+                occurrences = occurrences.split_off(occurrences.len().saturating_sub(1));
+            }
+        }
+        DueRuns {
+            dropped: found - occurrences.len(),
+            occurrences,
+            through,
+        }
+    }
+
+    /// Records the payment of `occurrence` of `name` by the transfer `signature`.
+    pub fn record(&mut self, name: &str, occurrence: u64, signature: &Signature) {
+        let run = self.runs.entry(name.to_string()).or_default();
+        run.checked_through = run.checked_through.max(occurrence);
+        run.last_occurrence = Some(occurrence);
+        run.last_signature = Some(signature.to_string());
+    }
+
+    /// Marks the occurrences of `name` up to `through` handled, once its due runs are paid.
+    pub fn advance(&mut self, name: &str, through: u64) {
+        let run = self.runs.entry(name.to_string()).or_default();
+        run.checked_through = run.checked_through.max(through);
+    }
+}
+
+// Bit set of the values of a cron field, each within `min..=max`
+fn parse_field(field: &str, min: u64, max: u64) -> Result<u64> {
+    let mut bits = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u64>()
+                    .map_err(|_| anyhow!("Invalid step `{step}`"))?,
+            ),
+            None => (item, 1),
+        };
+        if step == 0 {
+            bail!("Invalid step 0");
+        }
+        let value = |value: &str| {
+            value
+                .parse::<u64>()
+                .ok()
+                .filter(|value| (min..=max).contains(value))
+                .ok_or_else(|| anyhow!("Invalid value `{value}`, expected {min} to {max}"))
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (value(first)?, value(last)?),
+            //A stepped single value runs to the end of the field, as `5/15` in minutes
+            None if item.contains('/') => (value(range)?, max),
+            None => {
+                let value = value(range)?;
+                (value, value)
+            }
+        };
+        if first > last {
+            bail!("Invalid range `{range}`");
+        }
+        for value in (first..=last).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+// Year, month and day of `days` since the Unix epoch, in the proleptic Gregorian calendar
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,50 @@
+use anyhow::{Result, anyhow};
+use bip39::Mnemonic;
+use solana_derivation_path::DerivationPath;
+use solana_sdk::signer::keypair::{
+    Keypair, keypair_from_seed, keypair_from_seed_and_derivation_path,
+};
+/// Conventional derivation path for [`derive_key_source`]: the first account of the
+/// Solana BIP44 tree, as used by most Solana wallets.
+/// Solana BIP44 tree, as used by `solana-keygen` and most wallets.
+pub const DEFAULT_DERIVATION_PATH: &str = "m/44'/501'/0'/0'";
+
+/// Derives the keypair confidential keys are derived from out of a BIP39 `seed_phrase`, its
+/// optional `passphrase` and a BIP44 `derivation_path` such as `m/44'/501'/0'/0'`.
+///
+/// The keypair stands in for the owner's signer in [`derive_keys`](crate::mint::derive_keys),
+/// see [`Authority::with_key_source`](crate::authority::Authority::with_key_source), so the
+/// ElGamal and AES keys of every account can be recovered from the seed phrase alone. When the
+/// owner's wallet was itself derived from the same phrase and path, the keys are the ones its
+/// signer derives. The phrase checksum is verified, so a typo fails instead of deriving keys
+/// that decrypt nothing.
+pub fn derive_key_source(
+    seed_phrase: &str,
+    passphrase: &str,
+    derivation_path: &str,
+) -> Result<Keypair> {
+    let derivation_path = DerivationPath::from_absolute_path_str(derivation_path)
+        .map_err(|e| anyhow!("Invalid derivation path {derivation_path}: {e}"))?;
+    keypair_from_seed_phrase(seed_phrase, passphrase, Some(derivation_path))
+}
+
+/// Recovers the keypair of a BIP39 `seed_phrase` and `passphrase` as the Solana CLI does: at
+/// `derivation_path`, or without derivation from the first 32 bytes of the seed when `None`.
+///
+/// The phrase checksum is verified.
+pub fn keypair_from_seed_phrase(
+    seed_phrase: &str,
+    passphrase: &str,
+    derivation_path: Option<DerivationPath>,
+) -> Result<Keypair> {
+    let mnemonic =
+        Mnemonic::parse_normalized(seed_phrase).map_err(|e| anyhow!("Invalid seed phrase: {e}"))?;
+    let seed = mnemonic.to_seed(passphrase);
+    match derivation_path {
+        Some(derivation_path) => {
+            keypair_from_seed_and_derivation_path(&seed, Some(derivation_path))
+        }
+        None => keypair_from_seed(&seed),
+    }
+    .map_err(|e| anyhow!("Failed to derive a keypair from the seed phrase: {e}"))
+}
@@ -0,0 +1,946 @@
+use anyhow::{Context, anyhow};
+use axum::{
+    Json, Router,
+    extract::{Path, Request as HttpRequest, State},
+    http::{HeaderMap, StatusCode, header::AUTHORIZATION},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use confidential_transfer_core::derivation::SeedMessage;
+use serde::{Deserialize, Deserializer, de::Error as _};
+use serde_json::{Value, json};
+use solana_sdk::{pubkey::Pubkey, signature::Signature, signer::Signer};
+use spl_token_client::{
+    client::ProgramClient,
+    spl_token_2022::{
+        extension::StateWithExtensions,
+        solana_zk_sdk::encryption::{
+            auth_encryption::AeKey,
+            elgamal::{ElGamalKeypair, ElGamalPubkey},
+        },
+        state::Account,
+    },
+};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{
+    net::TcpListener,
+    sync::{
+        mpsc::{self, UnboundedSender},
+        oneshot,
+    },
+};
+use tracing::{info, instrument, warn};
+
+#[cfg(feature = "storage")]
+use crate::storage::{OperationKind, Storage};
+use crate::{
+    TokenClient,
+    authority::Authority,
+    balance::{self, ApplyUrgency, Balances},
+    client::{ComputeBudget, RpcSender},
+    decode_table::DecodeTable,
+    deposit,
+    keystore::Keystore,
+    metrics,
+    mint::{self, AccountState},
+    placement::Packing,
+    progress::{self, Progress},
+    transfer::{self, TransferResult},
+    withdraw::{self, WithdrawResult},
+};
+
+//Requests waiting for the backend beyond the one it is running
+const QUEUE_LEN: usize = 64;
+
+/// What the HTTP and gRPC APIs run operations with: the owner of the accounts it manages, the fee
+/// payer, and the keystore the keys of each account are looked up in, per request.
+pub struct ApiBackend {
+    /// Program client transactions are sent through.
+    pub program_client: Arc<dyn ProgramClient<RpcSender>>,
+    /// Pays transaction fees and rent.
+    pub fee_payer: Arc<dyn Signer>,
+    /// Owner of every account the API configures and spends from.
+    pub owner: Authority,
+    /// Compute budget instructions of every transaction.
+    pub compute_budget: ComputeBudget,
+    /// Keystore holding the keys of the owner's accounts.
+    pub keystore: Keystore,
+    /// Passphrase of `keystore`.
+    pub passphrase: String,
+    /// Database the completed operations are recorded in, if any; replicas of the API share
+    /// theirs through [`PostgresStorage`](crate::postgres::PostgresStorage).
+    #[cfg(feature = "storage")]
+    pub storage: Option<Arc<dyn Storage>>,
+}
+
+// Operation requested over HTTP or gRPC, with the channel its result is sent back on and, for
+// a stream of updates, the one its progress is reported to
+pub(crate) struct Job {
+    pub(crate) request: Request,
+    pub(crate) progress: Option<UnboundedSender<Progress>>,
+    pub(crate) reply: oneshot::Sender<Result<Outcome, ApiError>>,
+}
+
+pub(crate) enum Request {
+    Configure(AccountsRequest),
+    Deposit(DepositRequest),
+    Transfer(TransferRequest),
+    Withdraw(WithdrawRequest),
+    Balance(Pubkey),
+    WalletMessages(WalletMessagesRequest),
+    WalletKeys(WalletKeysRequest),
+}
+
+#[derive(Deserialize)]
+pub(crate) struct AccountsRequest {
+    #[serde(deserialize_with = "pubkey")]
+    pub(crate) mint: Pubkey,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DepositRequest {
+    #[serde(deserialize_with = "pubkey")]
+    pub(crate) mint: Pubkey,
+    pub(crate) amount: u64,
+    pub(crate) memo: Option<String>,
+    //Also apply the pending balance
+    #[serde(default)]
+    pub(crate) apply: bool,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct TransferRequest {
+    #[serde(deserialize_with = "pubkey")]
+    pub(crate) mint: Pubkey,
+    //Wallet of the recipient, whose associated token account is credited
+    #[serde(deserialize_with = "pubkey")]
+    pub(crate) recipient: Pubkey,
+    pub(crate) amount: u64,
+    pub(crate) memo: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct WithdrawRequest {
+    #[serde(deserialize_with = "pubkey")]
+    pub(crate) mint: Pubkey,
+    pub(crate) amount: Option<u64>,
+    //Withdraw the whole available balance instead of `amount`
+    #[serde(default)]
+    pub(crate) all: bool,
+    pub(crate) memo: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct WalletMessagesRequest {
+    //Wallet owning the associated token account the keys are for
+    #[serde(deserialize_with = "pubkey")]
+    pub(crate) owner: Pubkey,
+    #[serde(deserialize_with = "pubkey")]
+    pub(crate) mint: Pubkey,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct WalletKeysRequest {
+    #[serde(deserialize_with = "pubkey")]
+    pub(crate) owner: Pubkey,
+    #[serde(deserialize_with = "pubkey")]
+    pub(crate) mint: Pubkey,
+    //Signatures of the wallet of the two messages of `WalletMessagesRequest`
+    #[serde(deserialize_with = "signature")]
+    pub(crate) elgamal_signature: Signature,
+    #[serde(deserialize_with = "signature")]
+    pub(crate) aes_signature: Signature,
+}
+
+#[cfg(feature = "storage")]
+impl Request {
+    // Mint of the accounts the request acts on, unless it is read from the account
+    fn mint(&self) -> Option<Pubkey> {
+        match self {
+            Self::Configure(request) => Some(request.mint),
+            Self::Deposit(request) => Some(request.mint),
+            Self::Transfer(request) => Some(request.mint),
+            Self::Withdraw(request) => Some(request.mint),
+            Self::Balance(_) => None,
+            Self::WalletMessages(request) => Some(request.mint),
+            Self::WalletKeys(request) => Some(request.mint),
+        }
+    }
+}
+
+// Result of a `Request`
+pub(crate) enum Outcome {
+    Configured {
+        ata: Pubkey,
+        elgamal_pubkey: ElGamalPubkey,
+        //`None` if the account was already configured
+        signature: Option<Signature>,
+    },
+    Deposited {
+        ata: Pubkey,
+        amount: u64,
+        signature: Signature,
+    },
+    Transferred {
+        destination: Pubkey,
+        amount: u64,
+        result: TransferResult,
+    },
+    Withdrawn {
+        #[cfg_attr(not(any(feature = "grpc", feature = "storage")), allow(dead_code))]
+        ata: Pubkey,
+        result: WithdrawResult,
+    },
+    Balance {
+        ata: Pubkey,
+        mint: Pubkey,
+        decimals: u8,
+        balances: Balances,
+    },
+    WalletMessages {
+        ata: Pubkey,
+        seed_message: SeedMessage,
+        //ElGamal and AES key messages
+        messages: [Vec<u8>; 2],
+    },
+    WalletKeys {
+        ata: Pubkey,
+        elgamal_pubkey: ElGamalPubkey,
+        //Base64 transaction configuring the account, left to the wallet; `None` if the account
+        //was already configured
+        transaction: Option<String>,
+    },
+}
+
+impl Outcome {
+    // Body of the HTTP response; withdrawals and transfers as printed by `--json`, minus the
+    // timings
+    fn to_json(&self) -> Value {
+        let strings =
+            |values: &[Signature]| values.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let account = |account: Option<Pubkey>| account.map(|account| account.to_string());
+        let signature =
+            |signature: Option<Signature>| signature.map(|signature| signature.to_string());
+        match self {
+            Self::Configured {
+                ata,
+                elgamal_pubkey,
+                signature,
+            } => json!({
+                "ata": ata.to_string(),
+                "elgamal_pubkey": elgamal_pubkey.to_string(),
+                "signature": signature.map(|signature| signature.to_string()),
+            }),
+            Self::Deposited {
+                ata,
+                amount,
+                signature,
+            } => json!({
+                "ata": ata.to_string(),
+                "amount": amount,
+                "signature": signature.to_string(),
+            }),
+            Self::Transferred {
+                destination,
+                amount,
+                result,
+            } => json!({
+                "destination": destination.to_string(),
+                "amount": amount,
+                "fee": result.fee,
+                "signatures": {
+                    "proof_accounts": strings(&result.proof_signatures),
+                    "transfer": result.transfer_signature.to_string(),
+                    "close": strings(&result.close_signatures),
+                },
+            }),
+            Self::Withdrawn { result, .. } => json!({
+                "amount": result.amount,
+                "equality_proof_account": account(result.equality_proof_account),
+                "range_proof_account": account(result.range_proof_account),
+                "signatures": {
+                    "equality_proof": signature(result.equality_proof_signature),
+                    "range_proof": signature(result.range_proof_signature),
+                    "withdraw": result.withdraw_signature.to_string(),
+                    "close_equality": signature(result.close_equality_signature),
+                    "close_range": signature(result.close_range_signature),
+                },
+            }),
+            Self::Balance {
+                ata,
+                mint,
+                decimals,
+                balances,
+            } => json!({
+                "ata": ata.to_string(),
+                "mint": mint.to_string(),
+                "decimals": decimals,
+                "public": balances.public,
+                "pending": balances.pending,
+                "available": balances.available,
+                "pending_credits": {
+                    "counter": balances.pending_credits.counter,
+                    "maximum": balances.pending_credits.maximum,
+                },
+            }),
+            Self::WalletMessages {
+                ata,
+                seed_message,
+                messages: [elgamal_message, aes_message],
+            } => json!({
+                "ata": ata.to_string(),
+                "seed_message": seed_message.to_string(),
+                "elgamal_message": BASE64_STANDARD.encode(elgamal_message),
+                "aes_message": BASE64_STANDARD.encode(aes_message),
+            }),
+            Self::WalletKeys {
+                ata,
+                elgamal_pubkey,
+                transaction,
+            } => json!({
+                "ata": ata.to_string(),
+                "elgamal_pubkey": elgamal_pubkey.to_string(),
+                "transaction": transaction,
+            }),
+        }
+    }
+}
+
+// Pubkeys are given as base58 strings
+fn pubkey<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pubkey, D::Error> {
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(D::Error::custom)
+}
+
+// Signatures are given as base58 strings
+fn signature<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Signature, D::Error> {
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(D::Error::custom)
+}
+
+/// Error answered by the HTTP API: its status, with `{"error": <message>}` as body. The gRPC
+/// API answers the matching status code.
+#[derive(Debug)]
+pub struct ApiError {
+    pub(crate) status: StatusCode,
+    pub(crate) error: anyhow::Error,
+}
+
+impl ApiError {
+    pub(crate) fn new(status: StatusCode, error: anyhow::Error) -> Self {
+        Self { status, error }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, error)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if self.status.is_server_error() {
+            warn!(status = %self.status, "API request failed: {:#}", self.error);
+        }
+        (
+            self.status,
+            Json(json!({ "error": format!("{:#}", self.error) })),
+        )
+            .into_response()
+    }
+}
+
+/// Serves the HTTP API on `bind`, and the gRPC API on `grpc_bind` if given, until interrupted
+/// with Ctrl-C, running every operation through `backend`:
+///
+/// - `POST /accounts` `{"mint"}` creates and configures the owner's associated token account and
+///   stores its keys in the keystore.
+/// - `POST /deposit` `{"mint", "amount", "memo"?, "apply"?}` deposits into it, and applies the
+///   pending balance with `apply`.
+/// - `POST /transfer` `{"mint", "recipient", "amount", "memo"?}` transfers to the associated
+///   token account of the `recipient` wallet.
+/// - `POST /withdraw` `{"mint", "amount" | "all": true, "memo"?}` withdraws from it.
+/// - `GET /balance/<ATA>` decrypts the balances of one of the owner's accounts, or of a wallet's
+///   account onboarded through `POST /wallets/keys`.
+/// - `POST /wallets/messages` `{"owner", "mint"}` returns the two messages, base64, a browser
+///   wallet `owner` signs to derive the keys of its associated token account, under the seed
+///   message of the owner of the API.
+/// - `POST /wallets/keys` `{"owner", "mint", "elgamal_signature", "aes_signature"}` derives the
+///   keys from the wallet's signatures of these messages, checked against `owner`, and stores
+///   them in the keystore. Unless the account is configured already, it returns the base64
+///   transaction configuring it, for the wallet to sign and send.
+/// - `GET /metrics` serves the [metrics](crate::metrics) of the process to Prometheus.
+///
+/// Pubkeys are base58 strings and amounts base units. The keys of each account are loaded from
+/// the keystore by owner, mint and account, and checked against its ElGamal pubkey. Operations
+/// run one at a time, in the order received, since they spend from the same accounts. With an
+/// `api_token`, every request must carry it as `Authorization: Bearer <TOKEN>`, over either API.
+///
+/// The gRPC API, defined in `proto/confidential_transfer.proto`, needs the `grpc` feature. With
+/// the `storage` feature, completed operations are recorded in `backend.storage`.
+pub async fn serve(
+    backend: ApiBackend,
+    bind: SocketAddr,
+    grpc_bind: Option<SocketAddr>,
+    api_token: Option<String>,
+) -> anyhow::Result<()> {
+    //Built on first use, before the first request waits for it
+    let decode_table = DecodeTable::open_default()?;
+    let (jobs, mut queue) = mpsc::channel(QUEUE_LEN);
+    let mut router = Router::new()
+        .route("/accounts", post(accounts))
+        .route("/deposit", post(deposit))
+        .route("/transfer", post(transfer))
+        .route("/withdraw", post(withdraw))
+        .route("/balance/{ata}", get(balance))
+        .route("/wallets/messages", post(wallet_messages))
+        .route("/wallets/keys", post(wallet_keys))
+        .route("/metrics", get(metrics::respond))
+        .with_state(jobs.clone());
+    match &api_token {
+        Some(api_token) => {
+            router = router.layer(middleware::from_fn_with_state(
+                Arc::new(api_token.clone()),
+                require_token,
+            ))
+        }
+        None => {
+            for bind in [Some(bind), grpc_bind].into_iter().flatten() {
+                if !bind.ip().is_loopback() {
+                    warn!(
+                        "Serving on {bind} without an API token: anyone reaching it can move funds"
+                    )
+                }
+            }
+        }
+    }
+    let listener = TcpListener::bind(bind)
+        .await
+        .with_context(|| format!("Unable to listen on {bind}"))?;
+    info!(%bind, owner = %backend.owner.pubkey(), "Serving the HTTP API");
+    let server = async {
+        axum::serve(listener, router)
+            .with_graceful_shutdown(shutdown())
+            .await?;
+        anyhow::Ok(())
+    };
+    let grpc_server = async {
+        match grpc_bind {
+            #[cfg(feature = "grpc")]
+            Some(grpc_bind) => {
+                info!(bind = %grpc_bind, "Serving the gRPC API");
+                crate::grpc::serve(jobs, grpc_bind, api_token, shutdown()).await
+            }
+            #[cfg(not(feature = "grpc"))]
+            Some(_) => {
+                drop(jobs);
+                anyhow::bail!(
+                    "The gRPC API is not supported by this build; rebuild with `--features grpc`"
+                )
+            }
+            None => {
+                drop(jobs);
+                Ok(())
+            }
+        }
+    };
+    //Signers are not `Send`, so operations run here rather than in the connection tasks, which
+    //hand them over through the queue; it closes once both servers have shut down
+    let worker = async {
+        while let Some(Job {
+            request,
+            progress,
+            reply,
+        }) = queue.recv().await
+        {
+            #[cfg(feature = "storage")]
+            let mint = request.mint();
+            let outcome = match progress {
+                Some(sink) => progress::report_to(sink, backend.run(request, &decode_table)).await,
+                None => backend.run(request, &decode_table).await,
+            };
+            #[cfg(feature = "storage")]
+            if let Ok(outcome) = &outcome {
+                backend.record(mint, outcome).await;
+            }
+            let _ = reply.send(outcome);
+        }
+        anyhow::Ok(())
+    };
+    //Either server failing, e.g. to listen, stops the other
+    tokio::try_join!(server, grpc_server, worker)?;
+    Ok(())
+}
+
+// Resolves on Ctrl-C, stopping the servers
+async fn shutdown() {
+    let _ = tokio::signal::ctrl_c().await;
+    info!("Shutting down");
+}
+
+// Rejects requests without the bearer token
+async fn require_token(
+    State(api_token): State<Arc<String>>,
+    headers: HeaderMap,
+    request: HttpRequest,
+    next: Next,
+) -> Response {
+    let expected = format!("Bearer {api_token}");
+    match headers.get(AUTHORIZATION) {
+        Some(value) if value.as_bytes() == expected.as_bytes() => next.run(request).await,
+        _ => ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            anyhow!("Missing or wrong API token"),
+        )
+        .into_response(),
+    }
+}
+
+// Queues `request` for the backend and waits for its result, reporting its progress to
+// `progress` if given
+pub(crate) async fn submit(
+    jobs: &mpsc::Sender<Job>,
+    request: Request,
+    progress: Option<UnboundedSender<Progress>>,
+) -> Result<Outcome, ApiError> {
+    let unavailable = || ApiError::new(StatusCode::SERVICE_UNAVAILABLE, anyhow!("Shutting down"));
+    let (reply, result) = oneshot::channel();
+    jobs.send(Job {
+        request,
+        progress,
+        reply,
+    })
+    .await
+    .map_err(|_| unavailable())?;
+    result.await.map_err(|_| unavailable())?
+}
+
+// Answers `request` over HTTP
+async fn respond(jobs: &mpsc::Sender<Job>, request: Request) -> Result<Json<Value>, ApiError> {
+    Ok(Json(submit(jobs, request, None).await?.to_json()))
+}
+
+async fn accounts(
+    State(jobs): State<mpsc::Sender<Job>>,
+    Json(request): Json<AccountsRequest>,
+) -> Result<Json<Value>, ApiError> {
+    respond(&jobs, Request::Configure(request)).await
+}
+
+async fn deposit(
+    State(jobs): State<mpsc::Sender<Job>>,
+    Json(request): Json<DepositRequest>,
+) -> Result<Json<Value>, ApiError> {
+    respond(&jobs, Request::Deposit(request)).await
+}
+
+async fn transfer(
+    State(jobs): State<mpsc::Sender<Job>>,
+    Json(request): Json<TransferRequest>,
+) -> Result<Json<Value>, ApiError> {
+    respond(&jobs, Request::Transfer(request)).await
+}
+
+async fn withdraw(
+    State(jobs): State<mpsc::Sender<Job>>,
+    Json(request): Json<WithdrawRequest>,
+) -> Result<Json<Value>, ApiError> {
+    respond(&jobs, Request::Withdraw(request)).await
+}
+
+async fn balance(
+    State(jobs): State<mpsc::Sender<Job>>,
+    Path(ata): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    let ata = ata.parse().map_err(|err| {
+        ApiError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Invalid account {ata}: {err}"),
+        )
+    })?;
+    respond(&jobs, Request::Balance(ata)).await
+}
+
+async fn wallet_messages(
+    State(jobs): State<mpsc::Sender<Job>>,
+    Json(request): Json<WalletMessagesRequest>,
+) -> Result<Json<Value>, ApiError> {
+    respond(&jobs, Request::WalletMessages(request)).await
+}
+
+async fn wallet_keys(
+    State(jobs): State<mpsc::Sender<Job>>,
+    Json(request): Json<WalletKeysRequest>,
+) -> Result<Json<Value>, ApiError> {
+    respond(&jobs, Request::WalletKeys(request)).await
+}
+
+impl ApiBackend {
+    #[instrument(skip_all)]
+    async fn run(&self, request: Request, decode_table: &DecodeTable) -> Result<Outcome, ApiError> {
+        match request {
+            Request::Configure(request) => self.configure(request).await,
+            Request::Deposit(request) => self.deposit(request).await,
+            Request::Transfer(request) => self.transfer(request).await,
+            Request::Withdraw(request) => self.withdraw(request).await,
+            Request::Balance(ata) => self.balance(&ata, decode_table).await,
+            Request::WalletMessages(request) => Ok(self.wallet_messages(request)),
+            Request::WalletKeys(request) => self.wallet_keys(request).await,
+        }
+    }
+
+    async fn token(&self, mint: &Pubkey) -> Result<(TokenClient, u8), ApiError> {
+        Ok(mint::load_token(
+            self.program_client.clone(),
+            self.fee_payer.clone(),
+            mint,
+            &self.compute_budget,
+        )
+        .await?)
+    }
+
+    // Keys of `owner`'s `account` from the keystore, checked against its ElGamal pubkey
+    async fn keys(
+        &self,
+        owner: &Pubkey,
+        token: &TokenClient,
+        account: &Pubkey,
+    ) -> Result<(ElGamalKeypair, AeKey), ApiError> {
+        let (elgamal_keypair, aes_key) = self
+            .keystore
+            .load(owner, token.get_address(), account, &self.passphrase)?
+            .ok_or_else(|| {
+                ApiError::new(
+                    StatusCode::NOT_FOUND,
+                    anyhow!(
+                        "No stored keys for {account}; configure it with POST /accounts or POST /wallets/keys"
+                    ),
+                )
+            })?;
+        if *elgamal_keypair.pubkey() != transfer::get_elgamal_pubkey(token, account).await? {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                anyhow!("The stored keys of {account} do not match its ElGamal pubkey"),
+            ));
+        }
+        Ok((elgamal_keypair, aes_key))
+    }
+
+    async fn configure(&self, request: AccountsRequest) -> Result<Outcome, ApiError> {
+        let (token, _decimals) = self.token(&request.mint).await?;
+        let configured =
+            mint::create_configure_ata(&token, &self.fee_payer.pubkey(), &self.owner).await?;
+        let path = self.keystore.save(
+            &self.owner.pubkey(),
+            &request.mint,
+            &configured.ata,
+            &configured.elgamal_keypair,
+            &configured.aes_key,
+            &self.passphrase,
+        )?;
+        info!(ata = %configured.ata, path = %path.display(), "Configured account");
+        Ok(Outcome::Configured {
+            ata: configured.ata,
+            elgamal_pubkey: *configured.elgamal_keypair.pubkey(),
+            signature: configured.signature,
+        })
+    }
+
+    async fn deposit(&self, request: DepositRequest) -> Result<Outcome, ApiError> {
+        let (token, decimals) = self.token(&request.mint).await?;
+        let ata = mint::get_ata(&self.owner.pubkey(), &request.mint);
+        let signature = if request.apply {
+            let (elgamal_keypair, aes_key) = self.keys(&self.owner.pubkey(), &token, &ata).await?;
+            deposit::deposit_and_apply(
+                &token,
+                &self.owner,
+                &ata,
+                request.amount,
+                decimals,
+                &elgamal_keypair,
+                &aes_key,
+                request.memo.as_deref(),
+            )
+            .await?
+        } else {
+            deposit::deposit(
+                &token,
+                &self.owner,
+                &ata,
+                request.amount,
+                decimals,
+                request.memo.as_deref(),
+            )
+            .await?
+        };
+        Ok(Outcome::Deposited {
+            ata,
+            amount: request.amount,
+            signature,
+        })
+    }
+
+    async fn transfer(&self, request: TransferRequest) -> Result<Outcome, ApiError> {
+        let (token, _decimals) = self.token(&request.mint).await?;
+        let source_ata = mint::get_ata(&self.owner.pubkey(), &request.mint);
+        let destination_ata = mint::get_ata(&request.recipient, &request.mint);
+        let pending_credits = balance::get_pending_credits(&token, &destination_ata).await?;
+        if pending_credits.urgency() == ApplyUrgency::Now {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                anyhow!(
+                    "The pending credit counter of {destination_ata} is at its maximum; the recipient must apply its pending balance first"
+                ),
+            ));
+        }
+        let (elgamal_keypair, aes_key) =
+            self.keys(&self.owner.pubkey(), &token, &source_ata).await?;
+        let result = transfer::transfer(
+            &token,
+            &self.owner,
+            &source_ata,
+            &destination_ata,
+            request.amount,
+            &elgamal_keypair,
+            &aes_key,
+            request.memo.as_deref(),
+            Packing::Separate,
+        )
+        .await?;
+        Ok(Outcome::Transferred {
+            destination: destination_ata,
+            amount: request.amount,
+            result,
+        })
+    }
+
+    async fn withdraw(&self, request: WithdrawRequest) -> Result<Outcome, ApiError> {
+        let (token, decimals) = self.token(&request.mint).await?;
+        let ata = mint::get_ata(&self.owner.pubkey(), &request.mint);
+        let (elgamal_keypair, aes_key) = self.keys(&self.owner.pubkey(), &token, &ata).await?;
+        let memo = request.memo.as_deref();
+        let result = match (request.amount, request.all) {
+            (None, true) => {
+                withdraw::withdraw_all(
+                    &token,
+                    &self.owner,
+                    &ata,
+                    decimals,
+                    &elgamal_keypair,
+                    &aes_key,
+                    memo,
+                    Packing::Separate,
+                )
+                .await?
+            }
+            (Some(amount), false) => {
+                withdraw::withdraw(
+                    &token,
+                    &self.owner,
+                    &ata,
+                    amount,
+                    decimals,
+                    &elgamal_keypair,
+                    &aes_key,
+                    memo,
+                    Packing::Separate,
+                )
+                .await?
+            }
+            _ => {
+                return Err(ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    anyhow!("Give either an amount or \"all\": true"),
+                ));
+            }
+        };
+        Ok(Outcome::Withdrawn { ata, result })
+    }
+
+    async fn balance(&self, ata: &Pubkey, decode_table: &DecodeTable) -> Result<Outcome, ApiError> {
+        let not_found = || ApiError::new(StatusCode::NOT_FOUND, anyhow!("No token account {ata}"));
+        let account = self
+            .program_client
+            .get_account(*ata)
+            .await
+            .map_err(|err| anyhow!("Unable to fetch {ata}: {err}"))?
+            .ok_or_else(not_found)?;
+        let Account { mint, owner, .. } = StateWithExtensions::<Account>::unpack(&account.data)
+            .map_err(|_| not_found())?
+            .base;
+        let (token, decimals) = self.token(&mint).await?;
+        let (elgamal_keypair, aes_key) = self.keys(&owner, &token, ata).await?;
+        let balances =
+            balance::get_balances(&token, ata, &elgamal_keypair, &aes_key, decode_table).await?;
+        Ok(Outcome::Balance {
+            ata: *ata,
+            mint,
+            decimals,
+            balances,
+        })
+    }
+
+    fn wallet_messages(&self, request: WalletMessagesRequest) -> Outcome {
+        let ata = mint::get_ata(&request.owner, &request.mint);
+        let seed_message = self.owner.seed_message().clone();
+        Outcome::WalletMessages {
+            ata,
+            messages: seed_message.messages(&ata),
+            seed_message,
+        }
+    }
+
+    async fn wallet_keys(&self, request: WalletKeysRequest) -> Result<Outcome, ApiError> {
+        let (token, _decimals) = self.token(&request.mint).await?;
+        let ata = mint::get_ata(&request.owner, &request.mint);
+        let (elgamal_keypair, aes_key) = self
+            .owner
+            .seed_message()
+            .keys_from_signed_messages(
+                &request.owner,
+                &ata,
+                &request.elgamal_signature,
+                &request.aes_signature,
+            )
+            .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err))?;
+        let transaction = match mint::account_state(&token, &ata).await? {
+            AccountState::Configured => {
+                if *elgamal_keypair.pubkey() != transfer::get_elgamal_pubkey(&token, &ata).await? {
+                    return Err(ApiError::new(
+                        StatusCode::CONFLICT,
+                        anyhow!("{ata} is configured with other keys than those of the signatures"),
+                    ));
+                }
+                None
+            }
+            AccountState::Missing | AccountState::Unconfigured => {
+                let recent_blockhash = self
+                    .program_client
+                    .get_latest_blockhash()
+                    .await
+                    .map_err(|err| anyhow!("Unable to fetch a recent blockhash: {err}"))?;
+                let transaction = mint::configure_ata_transaction(
+                    &token,
+                    &request.owner,
+                    &elgamal_keypair,
+                    &aes_key,
+                    recent_blockhash,
+                )
+                .await?;
+                let transaction = bincode::serialize(&transaction).map_err(anyhow::Error::from)?;
+                Some(BASE64_STANDARD.encode(transaction))
+            }
+        };
+        let path = self.keystore.save(
+            &request.owner,
+            &request.mint,
+            &ata,
+            &elgamal_keypair,
+            &aes_key,
+            &self.passphrase,
+        )?;
+        info!(%ata, owner = %request.owner, path = %path.display(), "Stored wallet keys");
+        Ok(Outcome::WalletKeys {
+            ata,
+            elgamal_pubkey: *elgamal_keypair.pubkey(),
+            transaction,
+        })
+    }
+}
+
+#[cfg(feature = "storage")]
+impl ApiBackend {
+    // Records a completed operation of `mint`. It succeeded either way, so failures are only
+    // logged
+    async fn record(&self, mint: Option<Pubkey>, outcome: &Outcome) {
+        let Some(storage) = &self.storage else {
+            return;
+        };
+        let owner = self.owner.pubkey();
+        let recorded = match (outcome, mint) {
+            (
+                Outcome::Configured {
+                    ata,
+                    elgamal_pubkey,
+                    signature,
+                },
+                Some(mint),
+            ) => {
+                storage
+                    .record_account(&owner, &mint, ata, elgamal_pubkey, signature.as_ref())
+                    .await
+            }
+            (
+                Outcome::Deposited {
+                    ata,
+                    amount,
+                    signature,
+                },
+                Some(mint),
+            ) => {
+                storage
+                    .record_operation(
+                        OperationKind::Deposit,
+                        &owner,
+                        &mint,
+                        ata,
+                        None,
+                        Some(*amount),
+                        &[*signature],
+                    )
+                    .await
+            }
+            (
+                Outcome::Transferred {
+                    destination,
+                    amount,
+                    result,
+                },
+                Some(mint),
+            ) => {
+                storage
+                    .record_operation(
+                        OperationKind::Transfer,
+                        &owner,
+                        &mint,
+                        &mint::get_ata(&owner, &mint),
+                        Some(destination),
+                        Some(*amount),
+                        &result.signatures(),
+                    )
+                    .await
+            }
+            (Outcome::Withdrawn { ata, result }, Some(mint)) => {
+                storage
+                    .record_operation(
+                        OperationKind::Withdraw,
+                        &owner,
+                        &mint,
+                        ata,
+                        None,
+                        Some(result.amount),
+                        &result.signatures(),
+                    )
+                    .await
+            }
+            (
+                Outcome::Balance {
+                    ata,
+                    mint,
+                    balances,
+                    ..
+                },
+                _,
+            ) => storage.record_balance(mint, ata, balances).await,
+            _ => Ok(()),
+        };
+        if let Err(err) = recorded {
+            warn!("Unable to record to the database: {:#}", err);
+        }
+    }
+}
@@ -0,0 +1,642 @@
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use rusqlite::{Connection, Row, params, types::Type};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use spl_token_client::spl_token_2022::solana_zk_sdk::encryption::elgamal::ElGamalPubkey;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::debug;
+
+use crate::{balance::Balances, config::config_dir, indexer::Event};
+
+//Migrations in order; `PRAGMA user_version` counts those applied
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE mints (
+        address TEXT PRIMARY KEY,
+        authority TEXT NOT NULL,
+        decimals INTEGER NOT NULL,
+        signature TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE TABLE accounts (
+        address TEXT PRIMARY KEY,
+        owner TEXT NOT NULL,
+        mint TEXT NOT NULL,
+        elgamal_pubkey TEXT NOT NULL,
+        signature TEXT,
+        configured_at INTEGER NOT NULL
+    );
+    CREATE INDEX accounts_owner ON accounts (owner);
+    CREATE TABLE operations (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        kind TEXT NOT NULL,
+        owner TEXT NOT NULL,
+        mint TEXT NOT NULL,
+        account TEXT NOT NULL,
+        counterparty TEXT,
+        amount INTEGER,
+        signatures TEXT NOT NULL,
+        recorded_at INTEGER NOT NULL
+    );
+    CREATE INDEX operations_owner ON operations (owner, mint);
+    CREATE TABLE balances (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        account TEXT NOT NULL,
+        mint TEXT NOT NULL,
+        public INTEGER NOT NULL,
+        pending INTEGER NOT NULL,
+        available INTEGER NOT NULL,
+        pending_credits INTEGER NOT NULL,
+        recorded_at INTEGER NOT NULL
+    );
+    CREATE INDEX balances_account ON balances (account);
+",
+    "
+    CREATE TABLE events (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        signature TEXT NOT NULL,
+        instruction_index INTEGER NOT NULL,
+        slot INTEGER NOT NULL,
+        block_time INTEGER,
+        kind TEXT NOT NULL,
+        mint TEXT NOT NULL,
+        account TEXT NOT NULL,
+        counterparty TEXT,
+        amount INTEGER,
+        UNIQUE (signature, instruction_index)
+    );
+    CREATE INDEX events_mint ON events (mint, slot);
+    CREATE INDEX events_account ON events (account, slot);
+    CREATE TABLE index_cursors (
+        address TEXT PRIMARY KEY,
+        signature TEXT NOT NULL,
+        indexed_at INTEGER NOT NULL
+    );
+",
+];
+//How long a write waits for another process holding the database, e.g. a running watcher
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Kind of an operation recorded by [`Storage::record_operation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationKind {
+    /// Deposit into the pending balance, applied right away or not.
+    Deposit,
+    /// Apply of the pending balance.
+    Apply,
+    /// Withdraw from the available balance.
+    Withdraw,
+    /// Outgoing confidential transfer.
+    Transfer,
+    /// Incoming credits seen by a watcher.
+    Credit,
+}
+
+impl OperationKind {
+    /// Name of the kind, as stored and printed.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Deposit => "deposit",
+            Self::Apply => "apply",
+            Self::Withdraw => "withdraw",
+            Self::Transfer => "transfer",
+            Self::Credit => "credit",
+        }
+    }
+}
+
+impl fmt::Display for OperationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for OperationKind {
+    type Err = anyhow::Error;
+
+    fn from_str(kind: &str) -> Result<Self> {
+        Ok(match kind {
+            "deposit" => Self::Deposit,
+            "apply" => Self::Apply,
+            "withdraw" => Self::Withdraw,
+            "transfer" => Self::Transfer,
+            "credit" => Self::Credit,
+            _ => bail!("Unknown operation kind {kind:?}"),
+        })
+    }
+}
+
+/// Token account configured by an owner, with its latest recorded balances.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountRecord {
+    /// Token account address.
+    pub address: Pubkey,
+    /// Owner of the account.
+    pub owner: Pubkey,
+    /// Mint of the account.
+    pub mint: Pubkey,
+    /// ElGamal pubkey the account is configured with, in base64.
+    pub elgamal_pubkey: String,
+    /// Signature of the configuring transaction; `None` if it was configured elsewhere.
+    pub signature: Option<Signature>,
+    /// Unix time the account was recorded at.
+    pub configured_at: u64,
+    /// Latest balance snapshot of the account, if any.
+    pub latest_balance: Option<BalanceRecord>,
+}
+
+/// Decrypted balances of an account at some point in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BalanceRecord {
+    /// Normal token balance.
+    pub public: u64,
+    /// Pending confidential balance.
+    pub pending: u64,
+    /// Available confidential balance.
+    pub available: u64,
+    /// Pending credit counter.
+    pub pending_credits: u64,
+    /// Unix time of the snapshot.
+    pub recorded_at: u64,
+}
+
+/// Operation on a token account, as recorded by [`Storage::record_operation`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OperationRecord {
+    /// Kind of operation.
+    pub kind: OperationKind,
+    /// Owner of the account.
+    pub owner: Pubkey,
+    /// Mint of the account.
+    pub mint: Pubkey,
+    /// Token account operated on.
+    pub account: Pubkey,
+    /// Other token account involved: the destination of a transfer.
+    pub counterparty: Option<Pubkey>,
+    /// Amount in base units, if known; credits only carry their count.
+    pub amount: Option<u64>,
+    /// Signatures of the transactions sent, in order.
+    pub signatures: Vec<Signature>,
+    /// Unix time the operation was recorded at.
+    pub recorded_at: u64,
+}
+
+/// Database of the mints created, accounts configured, transactions sent and balances
+/// decrypted, so history survives restarts and can be queried without the cluster.
+///
+/// Only available with the `storage` feature. [`SqliteStorage`] keeps it in a local file;
+/// [`PostgresStorage`](crate::postgres::PostgresStorage), with the `postgres` feature, in a
+/// server shared by several instances. [`open`] picks one from a path or URL.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Records a mint created with `authority` as mint authority.
+    async fn record_mint(
+        &self,
+        mint: &Pubkey,
+        authority: &Pubkey,
+        decimals: u8,
+        signature: &Signature,
+    ) -> Result<()>;
+
+    /// Records an account configured with `elgamal_pubkey`, by the transaction `signature`, or
+    /// updates its pubkey once its keys are rotated.
+    async fn record_account(
+        &self,
+        owner: &Pubkey,
+        mint: &Pubkey,
+        account: &Pubkey,
+        elgamal_pubkey: &ElGamalPubkey,
+        signature: Option<&Signature>,
+    ) -> Result<()>;
+
+    /// Records an operation of `owner` on `account`, sent as the transactions `signatures`.
+    #[allow(clippy::too_many_arguments)]
+    async fn record_operation(
+        &self,
+        kind: OperationKind,
+        owner: &Pubkey,
+        mint: &Pubkey,
+        account: &Pubkey,
+        counterparty: Option<&Pubkey>,
+        amount: Option<u64>,
+        signatures: &[Signature],
+    ) -> Result<()>;
+
+    /// Records a snapshot of the decrypted balances of `account`.
+    async fn record_balance(
+        &self,
+        mint: &Pubkey,
+        account: &Pubkey,
+        balances: &Balances,
+    ) -> Result<()>;
+
+    /// Accounts of `owner`, by mint, with their latest balance snapshot.
+    async fn accounts(&self, owner: &Pubkey) -> Result<Vec<AccountRecord>>;
+
+    /// The latest `limit` operations of `owner`, newest first, on the accounts of `mint` only if
+    /// given.
+    async fn history(
+        &self,
+        owner: &Pubkey,
+        mint: Option<&Pubkey>,
+        limit: u32,
+    ) -> Result<Vec<OperationRecord>>;
+
+    /// Latest transaction of `address` indexed by [`index_mint`](crate::indexer::index_mint).
+    async fn index_cursor(&self, address: &Pubkey) -> Result<Option<Signature>>;
+
+    /// Records the `events` of transaction `signature` of `address` and moves the cursor of
+    /// `address` to it, at once. Events already recorded through another address are skipped.
+    async fn record_events(
+        &self,
+        address: &Pubkey,
+        signature: &Signature,
+        events: &[Event],
+    ) -> Result<()>;
+
+    /// The latest `limit` indexed events of `mint`, newest first, involving `account` only if
+    /// given.
+    async fn events(
+        &self,
+        mint: &Pubkey,
+        account: Option<&Pubkey>,
+        limit: u32,
+    ) -> Result<Vec<Event>>;
+}
+
+/// Opens the database at `location`: a `postgres://` or `postgresql://` URL, which needs the
+/// `postgres` feature, or else the path of a SQLite file.
+pub async fn open(location: &str) -> Result<Arc<dyn Storage>> {
+    if location.starts_with("postgres://") || location.starts_with("postgresql://") {
+        #[cfg(feature = "postgres")]
+        return Ok(Arc::new(
+            crate::postgres::PostgresStorage::connect(location).await?,
+        ));
+        #[cfg(not(feature = "postgres"))]
+        bail!("PostgreSQL is not supported by this build; rebuild with `--features postgres`");
+    }
+    Ok(Arc::new(SqliteStorage::open(Path::new(location))?))
+}
+
+/// [`Storage`] in a SQLite file.
+///
+/// Several processes, e.g. a watcher and one-off commands, can share the file; writes wait for
+/// each other.
+pub struct SqliteStorage {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    /// Default database path, `storage.sqlite3` in the client's directory.
+    pub fn default_path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("storage.sqlite3"))
+    }
+
+    /// Opens the database at `path`, creating it and migrating its schema as needed.
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Unable to create {}", dir.display()))?;
+        }
+        let connection = Connection::open(path)
+            .with_context(|| format!("Unable to open the database {}", path.display()))?;
+        Self::new(connection)
+    }
+
+    /// Database held in memory, e.g. for tests.
+    pub fn open_in_memory() -> Result<Self> {
+        Self::new(Connection::open_in_memory()?)
+    }
+
+    fn new(mut connection: Connection) -> Result<Self> {
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version > MIGRATIONS.len() {
+            bail!("The database was written by a newer version of this tool");
+        }
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let transaction = connection.transaction()?;
+            transaction.execute_batch(migration)?;
+            transaction.pragma_update(None, "user_version", index + 1)?;
+            transaction.commit()?;
+            debug!(version = index + 1, "Migrated the database");
+        }
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        //The connection stays usable after a panic while it was held
+        self.connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn record_mint(
+        &self,
+        mint: &Pubkey,
+        authority: &Pubkey,
+        decimals: u8,
+        signature: &Signature,
+    ) -> Result<()> {
+        self.connection().execute(
+            "INSERT OR REPLACE INTO mints (address, authority, decimals, signature, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                mint.to_string(),
+                authority.to_string(),
+                decimals,
+                signature.to_string(),
+                now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    async fn record_account(
+        &self,
+        owner: &Pubkey,
+        mint: &Pubkey,
+        account: &Pubkey,
+        elgamal_pubkey: &ElGamalPubkey,
+        signature: Option<&Signature>,
+    ) -> Result<()> {
+        self.connection().execute(
+            "INSERT INTO accounts (address, owner, mint, elgamal_pubkey, signature, configured_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT (address) DO UPDATE SET
+                 elgamal_pubkey = excluded.elgamal_pubkey,
+                 signature = COALESCE(excluded.signature, signature)",
+            params![
+                account.to_string(),
+                owner.to_string(),
+                mint.to_string(),
+                elgamal_pubkey.to_string(),
+                signature.map(Signature::to_string),
+                now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    async fn record_operation(
+        &self,
+        kind: OperationKind,
+        owner: &Pubkey,
+        mint: &Pubkey,
+        account: &Pubkey,
+        counterparty: Option<&Pubkey>,
+        amount: Option<u64>,
+        signatures: &[Signature],
+    ) -> Result<()> {
+        let signatures = signatures
+            .iter()
+            .map(Signature::to_string)
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.connection().execute(
+            "INSERT INTO operations
+                 (kind, owner, mint, account, counterparty, amount, signatures, recorded_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                kind.as_str(),
+                owner.to_string(),
+                mint.to_string(),
+                account.to_string(),
+                counterparty.map(Pubkey::to_string),
+                amount,
+                signatures,
+                now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    async fn record_balance(
+        &self,
+        mint: &Pubkey,
+        account: &Pubkey,
+        balances: &Balances,
+    ) -> Result<()> {
+        self.connection().execute(
+            "INSERT INTO balances
+                 (account, mint, public, pending, available, pending_credits, recorded_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                account.to_string(),
+                mint.to_string(),
+                balances.public,
+                balances.pending,
+                balances.available,
+                balances.pending_credits.counter,
+                now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    async fn accounts(&self, owner: &Pubkey) -> Result<Vec<AccountRecord>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT a.address, a.owner, a.mint, a.elgamal_pubkey, a.signature, a.configured_at,
+                    b.public, b.pending, b.available, b.pending_credits, b.recorded_at
+             FROM accounts a
+             LEFT JOIN balances b ON b.id =
+                 (SELECT MAX(id) FROM balances WHERE account = a.address)
+             WHERE a.owner = ?1
+             ORDER BY a.mint, a.address",
+        )?;
+        let accounts = statement
+            .query_map([owner.to_string()], |row| {
+                Ok(AccountRecord {
+                    address: parsed(row, 0)?,
+                    owner: parsed(row, 1)?,
+                    mint: parsed(row, 2)?,
+                    elgamal_pubkey: row.get(3)?,
+                    signature: row
+                        .get::<_, Option<String>>(4)?
+                        .map(|signature| parse(4, &signature))
+                        .transpose()?,
+                    configured_at: row.get(5)?,
+                    latest_balance: match row.get::<_, Option<u64>>(10)? {
+                        Some(recorded_at) => Some(BalanceRecord {
+                            public: row.get(6)?,
+                            pending: row.get(7)?,
+                            available: row.get(8)?,
+                            pending_credits: row.get(9)?,
+                            recorded_at,
+                        }),
+                        None => None,
+                    },
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(accounts)
+    }
+
+    async fn history(
+        &self,
+        owner: &Pubkey,
+        mint: Option<&Pubkey>,
+        limit: u32,
+    ) -> Result<Vec<OperationRecord>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT kind, owner, mint, account, counterparty, amount, signatures, recorded_at
+             FROM operations
+             WHERE owner = ?1 AND (?2 IS NULL OR mint = ?2)
+             ORDER BY id DESC
+             LIMIT ?3",
+        )?;
+        let operations = statement
+            .query_map(
+                params![owner.to_string(), mint.map(Pubkey::to_string), limit],
+                |row| {
+                    Ok(OperationRecord {
+                        kind: parsed(row, 0)?,
+                        owner: parsed(row, 1)?,
+                        mint: parsed(row, 2)?,
+                        account: parsed(row, 3)?,
+                        counterparty: row
+                            .get::<_, Option<String>>(4)?
+                            .map(|counterparty| parse(4, &counterparty))
+                            .transpose()?,
+                        amount: row.get(5)?,
+                        signatures: row
+                            .get::<_, String>(6)?
+                            .split_whitespace()
+                            .map(|signature| parse(6, signature))
+                            .collect::<rusqlite::Result<_>>()?,
+                        recorded_at: row.get(7)?,
+                    })
+                },
+            )?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(operations)
+    }
+
+    async fn index_cursor(&self, address: &Pubkey) -> Result<Option<Signature>> {
+        let connection = self.connection();
+        let mut statement =
+            connection.prepare("SELECT signature FROM index_cursors WHERE address = ?1")?;
+        let mut rows = statement.query([address.to_string()])?;
+        Ok(match rows.next()? {
+            Some(row) => Some(parsed(row, 0)?),
+            None => None,
+        })
+    }
+
+    async fn record_events(
+        &self,
+        address: &Pubkey,
+        signature: &Signature,
+        events: &[Event],
+    ) -> Result<()> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        for event in events {
+            transaction.execute(
+                "INSERT OR IGNORE INTO events
+                     (signature, instruction_index, slot, block_time, kind, mint, account,
+                      counterparty, amount)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    event.signature.to_string(),
+                    event.instruction_index,
+                    event.slot,
+                    event.block_time,
+                    event.kind.as_str(),
+                    event.mint.to_string(),
+                    event.account.to_string(),
+                    event
+                        .counterparty
+                        .map(|counterparty| counterparty.to_string()),
+                    event.amount
+                ],
+            )?;
+        }
+        transaction.execute(
+            "INSERT OR REPLACE INTO index_cursors (address, signature, indexed_at)
+             VALUES (?1, ?2, ?3)",
+            params![address.to_string(), signature.to_string(), now()],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    async fn events(
+        &self,
+        mint: &Pubkey,
+        account: Option<&Pubkey>,
+        limit: u32,
+    ) -> Result<Vec<Event>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT signature, instruction_index, slot, block_time, kind, mint, account,
+                    counterparty, amount
+             FROM events
+             WHERE mint = ?1 AND (?2 IS NULL OR account = ?2 OR counterparty = ?2)
+             ORDER BY slot DESC, id DESC
+             LIMIT ?3",
+        )?;
+        let events = statement
+            .query_map(
+                params![mint.to_string(), account.map(Pubkey::to_string), limit],
+                |row| {
+                    Ok(Event {
+                        signature: parsed(row, 0)?,
+                        instruction_index: row.get(1)?,
+                        slot: row.get(2)?,
+                        block_time: row.get(3)?,
+                        kind: parsed(row, 4)?,
+                        mint: parsed(row, 5)?,
+                        account: parsed(row, 6)?,
+                        counterparty: row
+                            .get::<_, Option<String>>(7)?
+                            .map(|counterparty| parse(7, &counterparty))
+                            .transpose()?,
+                        amount: row.get(8)?,
+                    })
+                },
+            )?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(events)
+    }
+}
+
+// Column `index` of `row`, parsed from its text
+fn parsed<T>(row: &Row, index: usize) -> rusqlite::Result<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    parse(index, &row.get::<_, String>(index)?)
+}
+
+fn parse<T>(index: usize, value: &str) -> rusqlite::Result<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value.parse().map_err(|err: T::Err| {
+        rusqlite::Error::FromSqlConversionFailure(index, Type::Text, err.to_string().into())
+    })
+}
+
+// Unix time in seconds
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
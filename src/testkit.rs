@@ -0,0 +1,254 @@
+use anyhow::{Context, Result, bail};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    commitment_config::CommitmentConfig, native_token::LAMPORTS_PER_SOL, pubkey::Pubkey,
+    signature::Keypair, signer::Signer,
+};
+use spl_token_client::client::ProgramClient;
+use std::{
+    fs,
+    net::TcpListener,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::time::sleep;
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    bootstrap::airdrop_until,
+    client::{self, RpcSender},
+    config::websocket_url,
+};
+
+/// Feature turning the ZK ElGamal proof program off, which confidential transfers verify their
+/// proofs with. `solana-test-validator` activates every feature by default, so validators that
+/// do not also know the feature re-enabling the program would reject every proof.
+pub const DISABLE_ZK_ELGAMAL_PROOF_PROGRAM_FEATURE: &str =
+    "zkdoVwnSFnSLtGJG7irJPEYUpmb4i7sGMGcnN6T9rnC";
+
+/// Lamports airdropped to the payer of a [`TestValidator`] by default.
+pub const DEFAULT_PAYER_LAMPORTS: u64 = 100 * LAMPORTS_PER_SOL;
+
+/// How long to wait by default for a [`TestValidator`] to serve RPC requests.
+pub const DEFAULT_STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+//Readiness polls of a starting validator
+const READINESS_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Settings of a [`TestValidator`].
+#[derive(Debug, Clone)]
+pub struct TestValidatorConfig {
+    /// `solana-test-validator` binary, looked up on the `PATH` unless a path is given.
+    pub program: PathBuf,
+    /// Ledger directory; a new temporary one, removed on teardown, when unset.
+    pub ledger: Option<PathBuf>,
+    /// RPC port, the websocket port being the next one; free ports are picked when unset.
+    pub rpc_port: Option<u16>,
+    /// Features deactivated at genesis, on top of
+    /// [`DISABLE_ZK_ELGAMAL_PROOF_PROGRAM_FEATURE`].
+    pub deactivate_features: Vec<Pubkey>,
+    /// Further arguments, e.g. `--bpf-program <ADDRESS> <PATH>` to load a program.
+    pub extra_args: Vec<String>,
+    /// Lamports airdropped to the payer.
+    pub payer_lamports: u64,
+    /// How long to wait for the validator to serve RPC requests.
+    pub startup_timeout: Duration,
+}
+
+impl Default for TestValidatorConfig {
+    fn default() -> Self {
+        Self {
+            program: PathBuf::from("solana-test-validator"),
+            ledger: None,
+            rpc_port: None,
+            deactivate_features: vec![],
+            extra_args: vec![],
+            payer_lamports: DEFAULT_PAYER_LAMPORTS,
+            startup_timeout: DEFAULT_STARTUP_TIMEOUT,
+        }
+    }
+}
+
+/// Local `solana-test-validator` run as a child process, for tests of this crate and of crates
+/// built on it.
+///
+/// [`TestValidator::start`] spawns it on free ports with the features confidential transfers
+/// need, waits until it serves RPC requests and funds a new payer. The validator is killed, and
+/// its temporary ledger removed, when the value is dropped.
+pub struct TestValidator {
+    child: Child,
+    ledger: PathBuf,
+    remove_ledger: bool,
+    rpc_url: String,
+    rpc_client: Arc<RpcClient>,
+    payer: Arc<Keypair>,
+}
+
+impl TestValidator {
+    /// Starts a validator with the default [`TestValidatorConfig`].
+    pub async fn start() -> Result<Self> {
+        Self::start_with(TestValidatorConfig::default()).await
+    }
+
+    /// Starts a validator configured by `config` and funds its payer.
+    #[instrument(skip_all)]
+    pub async fn start_with(config: TestValidatorConfig) -> Result<Self> {
+        let (ledger, remove_ledger) = match config.ledger {
+            Some(ledger) => (ledger, false),
+            None => (
+                std::env::temp_dir().join(format!(
+                    "confidential-transfer-test-ledger-{}",
+                    Keypair::new().pubkey()
+                )),
+                true,
+            ),
+        };
+        let rpc_port = match config.rpc_port {
+            Some(port) => port,
+            None => free_rpc_port()?,
+        };
+        let mut command = Command::new(&config.program);
+        command
+            .arg("--ledger")
+            .arg(&ledger)
+            .args(["--reset", "--quiet", "--bind-address", "127.0.0.1"])
+            .args(["--rpc-port", &rpc_port.to_string()])
+            .args(["--faucet-port", &free_port()?.to_string()])
+            .args(["--gossip-port", &free_port()?.to_string()])
+            .args([
+                "--deactivate-feature",
+                DISABLE_ZK_ELGAMAL_PROOF_PROGRAM_FEATURE,
+            ]);
+        for feature in &config.deactivate_features {
+            command.args(["--deactivate-feature", &feature.to_string()]);
+        }
+        command
+            .args(&config.extra_args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        let child = command.spawn().with_context(|| {
+            format!(
+                "Unable to run {}; is the Solana CLI installed?",
+                config.program.display()
+            )
+        })?;
+        let rpc_url = format!("http://127.0.0.1:{rpc_port}");
+        debug!(%rpc_url, ledger = %ledger.display(), "Spawned test validator");
+        //Torn down by Drop from here on, whatever fails
+        let mut validator = Self {
+            child,
+            ledger,
+            remove_ledger,
+            rpc_client: Arc::new(RpcClient::new_with_commitment(
+                rpc_url.clone(),
+                CommitmentConfig::confirmed(),
+            )),
+            rpc_url,
+            payer: Arc::new(Keypair::new()),
+        };
+        validator.wait_until_ready(config.startup_timeout).await?;
+        airdrop_until(
+            &validator.rpc_client,
+            &validator.payer.pubkey(),
+            config.payer_lamports,
+            config.payer_lamports,
+        )
+        .await?;
+        info!(rpc_url = %validator.rpc_url, payer = %validator.payer.pubkey(), "Test validator ready");
+        Ok(validator)
+    }
+
+    /// JSON-RPC URL of the validator.
+    pub fn rpc_url(&self) -> &str {
+        &self.rpc_url
+    }
+
+    /// Websocket URL of the validator.
+    pub fn ws_url(&self) -> String {
+        websocket_url(&self.rpc_url)
+    }
+
+    /// RPC client of the validator, at the `confirmed` commitment.
+    pub fn rpc_client(&self) -> Arc<RpcClient> {
+        self.rpc_client.clone()
+    }
+
+    /// Funded keypair, to pay fees and rent and to serve as mint authority or owner.
+    pub fn payer(&self) -> Arc<Keypair> {
+        self.payer.clone()
+    }
+
+    /// Ledger directory, holding the `validator.log` of the validator.
+    pub fn ledger(&self) -> &Path {
+        &self.ledger
+    }
+
+    /// Program client of the validator sending through `sender`, to build
+    /// [`TokenClient`](crate::TokenClient)s with.
+    pub fn program_client(&self, sender: RpcSender) -> Arc<dyn ProgramClient<RpcSender>> {
+        client::program_client(self.rpc_client.clone(), sender)
+    }
+
+    // Waits until the validator answers health checks and has produced a block, failing if it
+    // exits or `startup_timeout` passes first
+    async fn wait_until_ready(&mut self, startup_timeout: Duration) -> Result<()> {
+        let started = Instant::now();
+        loop {
+            if let Some(status) = self.child.try_wait()? {
+                bail!(
+                    "solana-test-validator exited with {status}; see {}",
+                    self.ledger.join("validator.log").display()
+                );
+            }
+            if self.rpc_client.get_health().await.is_ok()
+                && self.rpc_client.get_slot().await.is_ok_and(|slot| slot > 0)
+            {
+                debug!(elapsed = ?started.elapsed(), "Test validator serving requests");
+                return Ok(());
+            }
+            if started.elapsed() > startup_timeout {
+                bail!(
+                    "solana-test-validator did not serve RPC requests within {startup_timeout:?}; see {}",
+                    self.ledger.join("validator.log").display()
+                );
+            }
+            sleep(READINESS_POLL_INTERVAL).await;
+        }
+    }
+}
+
+impl Drop for TestValidator {
+    fn drop(&mut self) {
+        if let Err(err) = self
+            .child
+            .kill()
+            .and_then(|()| self.child.wait().map(|_| ()))
+        {
+            warn!("Unable to stop the test validator: {err}");
+        }
+        if self.remove_ledger
+            && let Err(err) = fs::remove_dir_all(&self.ledger)
+        {
+            debug!("Unable to remove {}: {err}", self.ledger.display());
+        }
+    }
+}
+
+// Port free at the moment on the loopback interface
+fn free_port() -> Result<u16> {
+    Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?.port())
+}
+
+// RPC port free at the moment, with the websocket port following it free too
+fn free_rpc_port() -> Result<u16> {
+    for _ in 0..100 {
+        let port = free_port()?;
+        if port < u16::MAX && TcpListener::bind(("127.0.0.1", port + 1)).is_ok() {
+            return Ok(port);
+        }
+    }
+    bail!("No free pair of consecutive ports for the RPC and websocket servers")
+}
@@ -0,0 +1,95 @@
+use async_trait::async_trait;
+use serde_json::Value;
+use solana_client::{
+    client_error::Result as ClientResult,
+    rpc_request::RpcRequest,
+    rpc_sender::{RpcSender as RpcTransport, RpcTransportStats},
+};
+use std::{
+    num::{NonZeroU32, NonZeroUsize},
+    time::Duration,
+};
+use tokio::{
+    sync::{Mutex, Semaphore},
+    time::{Instant, sleep_until},
+};
+use tracing::trace;
+
+/// How many RPC requests a client built by [`rpc_client`](crate::retry::rpc_client) has in
+/// flight at once, and how fast it starts them; unset limits do not apply.
+///
+/// Batch operations issue hundreds of calls, which public endpoints answer with HTTP 429 once
+/// past their rate limit; pacing them below it spares the retries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Requests awaiting their response at once.
+    pub max_in_flight: Option<NonZeroUsize>,
+    /// Requests started per second, spread evenly.
+    pub requests_per_second: Option<NonZeroU32>,
+}
+
+/// Transport holding the requests of `inner` to a [`RateLimit`], shared by every call of the
+/// client: each request waits for an in-flight slot, then for its turn at the paced rate.
+pub struct ThrottlingTransport<T> {
+    inner: T,
+    in_flight: Option<Semaphore>,
+    interval: Option<Duration>,
+    //Earliest start of the next request
+    next_start: Mutex<Instant>,
+}
+
+impl<T> ThrottlingTransport<T> {
+    /// Wraps `inner`, limited by `limit`.
+    pub fn new(inner: T, limit: RateLimit) -> Self {
+        Self {
+            inner,
+            in_flight: limit.max_in_flight.map(|max| Semaphore::new(max.get())),
+            interval: limit
+                .requests_per_second
+                .map(|rate| Duration::from_secs(1) / rate.get()),
+            next_start: Mutex::new(Instant::now()),
+        }
+    }
+
+    // Waits until the next request may start at the paced rate, and books the following slot
+    async fn pace(&self, request: RpcRequest) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let start = {
+            let mut next_start = self.next_start.lock().await;
+            let start = (*next_start).max(Instant::now());
+            *next_start = start + interval;
+            start
+        };
+        if start > Instant::now() {
+            trace!(%request, wait = ?start - Instant::now(), "Pacing RPC request");
+            sleep_until(start).await;
+        }
+    }
+}
+
+#[async_trait]
+impl<T: RpcTransport + Send + Sync> RpcTransport for ThrottlingTransport<T> {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        let _permit = match &self.in_flight {
+            Some(in_flight) => Some(
+                in_flight
+                    .acquire()
+                    .await
+                    .expect("the semaphore is never closed"),
+            ),
+            None => None,
+        };
+        self.pace(request).await;
+        self.inner.send(request, params).await
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.inner.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.inner.url()
+    }
+}
@@ -0,0 +1,914 @@
+use anyhow::{Result, anyhow, bail};
+use bytemuck::Pod;
+use confidential_transfer_core::{
+    instructions::{close_context_account_instruction, context_account_instructions},
+    proofs::{TransferFeeParams, TransferProofs, transfer_proofs},
+};
+use solana_sdk::{
+    account::from_account,
+    clock::Clock,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    rent::Rent,
+    signature::{Keypair, Signature},
+    signer::Signer,
+    sysvar,
+};
+use spl_token_client::{
+    spl_token_2022::{
+        extension::{
+            BaseStateWithExtensions,
+            confidential_transfer::{
+                ConfidentialTransferAccount, ConfidentialTransferMint,
+                account_info::TransferAccountInfo,
+                instruction::{
+                    transfer as transfer_instruction,
+                    transfer_with_fee as transfer_with_fee_instruction,
+                },
+            },
+            confidential_transfer_fee::ConfidentialTransferFeeConfig,
+            memo_transfer::memo_required,
+            transfer_fee::TransferFeeConfig,
+        },
+        id as token_2022_program_id,
+        solana_zk_sdk::{
+            encryption::{
+                auth_encryption::AeKey,
+                elgamal::{ElGamalCiphertext, ElGamalKeypair, ElGamalPubkey},
+                pod::{
+                    auth_encryption::PodAeCiphertext, elgamal::PodElGamalPubkey,
+                    grouped_elgamal::PodGroupedElGamalCiphertext3Handles,
+                },
+            },
+            zk_elgamal_proof_program::proof_data::ZkProofData,
+        },
+    },
+    token::{ProofAccount, ProofAccountWithCiphertext, TokenError},
+};
+use spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation;
+use spl_token_confidential_transfer_proof_generation::{
+    CiphertextValidityProofWithAuditorCiphertext, TRANSFER_AMOUNT_LO_BITS,
+    try_combine_lo_hi_ciphertexts,
+};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{debug, instrument};
+
+use crate::{
+    TokenClient,
+    authority::Authority,
+    bundle::JitoBundle,
+    concurrent::ConcurrentSends,
+    keygen,
+    lookup_table::LookupTable,
+    mint::get_ata,
+    nonce::DurableNonces,
+    placement::{Packing, pack_instructions, send_packed_transactions},
+    proofs::spawn_proof_generation,
+    utils::{attach_memo, into_signature},
+};
+
+/// Signatures and proof accounts produced by [`transfer`].
+#[derive(Debug, Clone)]
+pub struct TransferResult {
+    /// Context state account holding the verified equality proof.
+    pub equality_proof_account: Pubkey,
+    /// Context state account holding the verified ciphertext validity proof.
+    pub ciphertext_validity_proof_account: Pubkey,
+    /// Context state account holding the verified range proof.
+    pub range_proof_account: Pubkey,
+    /// Context state accounts holding the verified percentage-with-cap and fee ciphertext
+    /// validity proofs, for mints with transfer fees.
+    pub fee_proof_accounts: Option<[Pubkey; 2]>,
+    /// Transfer fee withheld in the destination account, in base units.
+    pub fee: u64,
+    /// Signatures of the proof account creations (equality, ciphertext validity, range, then
+    /// the two fee proofs if any).
+    pub proof_signatures: Vec<Signature>,
+    /// Signature of the transfer instruction.
+    pub transfer_signature: Signature,
+    /// Signatures closing the proof accounts, in the same order.
+    pub close_signatures: Vec<Signature>,
+    /// Time spent generating the proof data.
+    pub proof_generation_time: Duration,
+}
+
+impl TransferResult {
+    /// Signatures of every transaction sent, in order.
+    pub fn signatures(&self) -> Vec<Signature> {
+        [
+            self.proof_signatures.as_slice(),
+            &[self.transfer_signature],
+            &self.close_signatures,
+        ]
+        .concat()
+    }
+}
+
+/// Returns the ElGamal pubkey registered in the `ConfidentialTransferAccount` extension of
+/// `account`.
+pub async fn get_elgamal_pubkey(token: &TokenClient, account: &Pubkey) -> Result<ElGamalPubkey> {
+    let extension_data = get_confidential_transfer_account(token, account).await?;
+    ElGamalPubkey::try_from(extension_data.elgamal_pubkey)
+        .map_err(|_| anyhow!("Invalid ElGamal pubkey registered for account {account}"))
+}
+
+/// Returns the ElGamal pubkey transfers to `recipient` must be encrypted to: the one registered
+/// in the recipient's associated token account for the mint of `token`.
+///
+/// Fails with an explanation when the account does not exist, is not configured for
+/// confidential transfers or still awaits approval.
+pub async fn get_recipient_elgamal_pubkey(
+    token: &TokenClient,
+    recipient: &Pubkey,
+) -> Result<ElGamalPubkey> {
+    let ata_pubkey = get_ata(recipient, token.get_address());
+    let extension_data = get_confidential_transfer_account(token, &ata_pubkey).await?;
+    if !bool::from(extension_data.approved) {
+        bail!("Account {ata_pubkey} of {recipient} is not approved for confidential transfers yet");
+    }
+    ElGamalPubkey::try_from(extension_data.elgamal_pubkey)
+        .map_err(|_| anyhow!("Invalid ElGamal pubkey registered for account {ata_pubkey}"))
+}
+
+/// Returns whether `account` requires a memo right before every incoming transfer, set with
+/// the `MemoTransfer` extension; transfers without one fail with `NoMemo`.
+pub async fn requires_memo(token: &TokenClient, account: &Pubkey) -> Result<bool> {
+    Ok(memo_required(&token.get_account_info(account).await?))
+}
+
+/// Returns the auditor ElGamal pubkey configured on the mint, if any.
+pub async fn get_auditor_elgamal_pubkey(token: &TokenClient) -> Result<Option<ElGamalPubkey>> {
+    let mint_info = token.get_mint_info().await?;
+    let extension_data = mint_info.get_extension::<ConfidentialTransferMint>()?;
+    Option::<PodElGamalPubkey>::from(extension_data.auditor_elgamal_pubkey)
+        .map(|pubkey| {
+            ElGamalPubkey::try_from(pubkey).map_err(|_| anyhow!("Invalid auditor ElGamal pubkey"))
+        })
+        .transpose()
+}
+
+/// Returns the transfer fee of the mint for the current epoch, or `None` when the mint has no
+/// `TransferFeeConfig` extension.
+pub async fn get_transfer_fee(token: &TokenClient) -> Result<Option<TransferFeeParams>> {
+    let mint_info = token.get_mint_info().await?;
+    let Ok(transfer_fee_config) = mint_info.get_extension::<TransferFeeConfig>() else {
+        return Ok(None);
+    };
+    let confidential_fee_config = mint_info
+        .get_extension::<ConfidentialTransferFeeConfig>()
+        .map_err(|_| anyhow!("Mint has transfer fees but no ConfidentialTransferFeeConfig"))?;
+    let withdraw_withheld_authority_elgamal_pubkey =
+        ElGamalPubkey::try_from(confidential_fee_config.withdraw_withheld_authority_elgamal_pubkey)
+            .map_err(|_| anyhow!("Invalid withdraw withheld authority ElGamal pubkey"))?;
+    //The fee schedule can change at an epoch boundary, so pick the one in effect now
+    let clock = from_account::<Clock, _>(&token.get_account(sysvar::clock::id()).await?)
+        .ok_or_else(|| anyhow!("Invalid clock sysvar"))?;
+    Ok(Some(TransferFeeParams {
+        transfer_fee: *transfer_fee_config.get_epoch_fee(clock.epoch),
+        withdraw_withheld_authority_elgamal_pubkey,
+    }))
+}
+
+/// Transfers `amount` confidentially from `source_ata` to `destination_ata`.
+///
+/// The amount is encrypted under the recipient's registered ElGamal pubkey (and the mint's
+/// auditor pubkey, if configured). The equality, ciphertext validity and range proofs are
+/// verified into context state accounts, referenced by the transfer instruction, and closed
+/// afterwards to recover rent.
+///
+/// On mints with transfer fees the fee is encrypted under the withdraw withheld authority
+/// ElGamal pubkey as well, and two more proofs are needed: a percentage-with-cap proof that the
+/// fee matches the rate and cap, and a ciphertext validity proof for the fee ciphertexts. The
+/// fee is deducted from `amount` and withheld in the destination account.
+///
+/// `memo` is attached to the transfer transaction; it is mandatory when the destination
+/// [requires memos](requires_memo).
+///
+/// With [`Packing::Packed`], the account creations, proof verifications, the transfer and the
+/// closes are sent in as few transactions as they fit in; otherwise each takes its own, and the
+/// signatures of the result are those of the transactions holding each step, which can repeat.
+#[allow(clippy::too_many_arguments)]
+pub async fn transfer(
+    token: &TokenClient,
+    owner: &Authority,
+    source_ata: &Pubkey,
+    destination_ata: &Pubkey,
+    amount: u64,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+    memo: Option<&str>,
+    packing: Packing,
+) -> Result<TransferResult> {
+    transfer_with_proof_accounts(
+        token,
+        owner,
+        source_ata,
+        destination_ata,
+        amount,
+        elgamal_keypair,
+        aes_key,
+        memo,
+        packing,
+        &std::array::from_fn(|_| keygen::new_keypair()),
+    )
+    .await
+}
+
+/// Same as [`transfer`], with the equality, ciphertext validity and range proof context state
+/// accounts created at the addresses of the first three `proof_keypairs`, and on mints with
+/// transfer fees the percentage-with-cap and fee ciphertext validity ones at the last two.
+///
+/// Lets callers record the proof accounts before they are created, so they can still be closed
+/// if the transfer fails halfway; the last two are never created on mints without transfer fees.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip_all, fields(source = %source_ata, destination = %destination_ata, amount))]
+pub async fn transfer_with_proof_accounts(
+    token: &TokenClient,
+    owner: &Authority,
+    source_ata: &Pubkey,
+    destination_ata: &Pubkey,
+    amount: u64,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+    memo: Option<&str>,
+    packing: Packing,
+    proof_keypairs: &[Keypair; 5],
+) -> Result<TransferResult> {
+    //Recipient's ElGamal pubkey the transfer amount is encrypted to
+    let destination_elgamal_pubkey = get_elgamal_pubkey(token, destination_ata).await?;
+    //Fail before creating any proof account rather than at the transfer instruction
+    if memo.is_none() && requires_memo(token, destination_ata).await? {
+        bail!("{destination_ata} requires a memo on incoming transfers");
+    }
+    let auditor_elgamal_pubkey = get_auditor_elgamal_pubkey(token).await?;
+    let transfer_fee = get_transfer_fee(token).await?;
+    debug!(
+        auditor = auditor_elgamal_pubkey.is_some(),
+        transfer_fee = ?transfer_fee.map(|params| params.transfer_fee),
+        "Fetched recipient ElGamal pubkey"
+    );
+
+    let token_account = token.get_account_info(source_ata).await?;
+    let extension_data = token_account.get_extension::<ConfidentialTransferAccount>()?;
+    //Confidential transfer extension information needed to construct a transfer instruction
+    let transfer_account = TransferAccountInfo::new(extension_data);
+    let destination_ata = *destination_ata;
+    let (elgamal_keypair_owned, aes_key_owned) = (elgamal_keypair.clone(), aes_key.clone());
+    let prepared = spawn_proof_generation(move || {
+        PreparedTransfer::new(
+            transfer_account,
+            destination_ata,
+            &destination_elgamal_pubkey,
+            amount,
+            &elgamal_keypair_owned,
+            &aes_key_owned,
+            auditor_elgamal_pubkey.as_ref(),
+            transfer_fee.as_ref(),
+        )
+    })
+    .await?;
+    debug!(fee = prepared.fee, elapsed = ?prepared.proof_generation_time, "Generated transfer proof data");
+    //Only the fee proofs use the last two accounts
+    let proof_keypairs = &proof_keypairs[..prepared.proof_count()];
+
+    if let Packing::Packed {
+        fee_payer,
+        lookup_table,
+        bundle,
+        nonces,
+    } = packing
+    {
+        let (proof_signatures, transfer_signature, close_signatures) = prepared
+            .send_packed(
+                token,
+                owner,
+                source_ata,
+                &fee_payer,
+                proof_keypairs,
+                aes_key,
+                memo,
+                lookup_table.as_ref(),
+                bundle.as_ref(),
+                nonces.as_ref(),
+            )
+            .await?;
+        debug!(signature = %transfer_signature, "Packed transfer confirmed");
+        return Ok(prepared.result(
+            proof_keypairs,
+            proof_signatures,
+            transfer_signature,
+            close_signatures,
+        ));
+    }
+    //Proof accounts cannot be managed by a multisig, so they belong to the proof authority
+    let proof_authority = owner.proof_authority();
+    let proof_signatures = prepared
+        .create_proof_accounts_at(token, proof_authority, proof_keypairs)
+        .await?;
+    debug!(signatures = ?proof_signatures, "Created proof accounts");
+    let transfer_signature = prepared
+        .submit(
+            token,
+            owner,
+            source_ata,
+            proof_keypairs,
+            elgamal_keypair,
+            aes_key,
+            memo,
+        )
+        .await?;
+    debug!(signature = %transfer_signature, "Transfer confirmed");
+
+    //Close the context state accounts to recover rent
+    let close_signatures = close_proof_accounts(token, proof_authority, proof_keypairs).await?;
+    Ok(prepared.result(
+        proof_keypairs,
+        proof_signatures,
+        transfer_signature,
+        close_signatures,
+    ))
+}
+
+/// A transfer whose proofs are generated but not yet verified on chain.
+///
+/// Proofs commit to the source balance they were generated against, so a prepared transfer can
+/// only be submitted while the source account is in that state. [`PreparedTransfer::next`] gives
+/// the state the source is left in, which lets several transfers from one account be prepared
+/// ahead and submitted in order.
+pub(crate) struct PreparedTransfer {
+    pub(crate) destination_ata: Pubkey,
+    pub(crate) amount: u64,
+    pub(crate) fee: u64,
+    destination_elgamal_pubkey: ElGamalPubkey,
+    auditor_elgamal_pubkey: Option<ElGamalPubkey>,
+    transfer_fee: Option<TransferFeeParams>,
+    source_account: TransferAccountInfo,
+    proofs: TransferProofs,
+    pub(crate) proof_generation_time: Duration,
+}
+
+impl PreparedTransfer {
+    // Generates the proof data of a transfer from a source in the state `source_account`
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        source_account: TransferAccountInfo,
+        destination_ata: Pubkey,
+        destination_elgamal_pubkey: &ElGamalPubkey,
+        amount: u64,
+        elgamal_keypair: &ElGamalKeypair,
+        aes_key: &AeKey,
+        auditor_elgamal_pubkey: Option<&ElGamalPubkey>,
+        transfer_fee: Option<&TransferFeeParams>,
+    ) -> Result<Self> {
+        let fee = match transfer_fee {
+            Some(fee_params) => fee_params
+                .transfer_fee
+                .calculate_fee(amount)
+                .ok_or_else(|| anyhow!("Transfer fee overflow"))?,
+            None => 0,
+        };
+        let started = Instant::now();
+        let proofs = transfer_proofs(
+            &source_account,
+            amount,
+            elgamal_keypair,
+            aes_key,
+            destination_elgamal_pubkey,
+            auditor_elgamal_pubkey,
+            transfer_fee,
+        )?;
+        Ok(Self {
+            destination_ata,
+            amount,
+            fee,
+            destination_elgamal_pubkey: *destination_elgamal_pubkey,
+            auditor_elgamal_pubkey: auditor_elgamal_pubkey.copied(),
+            transfer_fee: transfer_fee.copied(),
+            source_account,
+            proofs,
+            proof_generation_time: started.elapsed(),
+        })
+    }
+
+    // The transfer amount ciphertext validity proof, also carrying the auditor ciphertexts
+    fn ciphertext_validity_proof(&self) -> &CiphertextValidityProofWithAuditorCiphertext {
+        match &self.proofs {
+            TransferProofs::Plain(proof_data) => {
+                &proof_data.ciphertext_validity_proof_data_with_ciphertext
+            }
+            TransferProofs::WithFee(proof_data) => {
+                &proof_data.transfer_amount_ciphertext_validity_proof_data_with_ciphertext
+            }
+        }
+    }
+
+    /// State of the source account once this transfer is applied: the amount, encrypted under
+    /// the source pubkey in the ciphertext validity proof, is subtracted from the available
+    /// balance the same way the program does it.
+    pub(crate) fn next(&self, aes_key: &AeKey) -> Result<TransferAccountInfo> {
+        let context = self.ciphertext_validity_proof().proof_data.context_data();
+        let source_ciphertext = |grouped: &PodGroupedElGamalCiphertext3Handles| {
+            grouped
+                .try_extract_ciphertext(0)
+                .ok()
+                .and_then(|ciphertext| ElGamalCiphertext::try_from(ciphertext).ok())
+                .ok_or_else(|| anyhow!("Invalid transfer amount ciphertext"))
+        };
+        let transfer_amount_ciphertext = try_combine_lo_hi_ciphertexts(
+            &source_ciphertext(&context.grouped_ciphertext_lo)?,
+            &source_ciphertext(&context.grouped_ciphertext_hi)?,
+            TRANSFER_AMOUNT_LO_BITS,
+        )
+        .ok_or_else(|| anyhow!("Invalid transfer amount ciphertext"))?;
+        let available_balance = ElGamalCiphertext::try_from(self.source_account.available_balance)
+            .map_err(|_| anyhow!("Invalid available balance ciphertext"))?;
+        Ok(TransferAccountInfo {
+            available_balance: (available_balance - transfer_amount_ciphertext).into(),
+            decryptable_available_balance: self
+                .source_account
+                .new_decryptable_available_balance(self.amount, aes_key)?
+                .into(),
+        })
+    }
+
+    // Number of proof context state accounts of the transfer: 3, or 5 with transfer fees
+    pub(crate) fn proof_count(&self) -> usize {
+        match &self.proofs {
+            TransferProofs::Plain(_) => 3,
+            TransferProofs::WithFee(_) => 5,
+        }
+    }
+
+    // Verifies the proofs into context state accounts at `proof_keypairs` managed by `authority`:
+    // equality, ciphertext validity and range, then percentage-with-cap and fee ciphertext
+    // validity
+    pub(crate) async fn create_proof_accounts_at(
+        &self,
+        token: &TokenClient,
+        authority: &Arc<dyn Signer>,
+        proof_keypairs: &[Keypair],
+    ) -> Result<Vec<Signature>> {
+        //Catch invalid proofs before paying for any of the accounts
+        self.proofs.verify()?;
+        //The proof accounts are independent of each other, so they are created concurrently
+        let mut creations = ConcurrentSends::new();
+        match &self.proofs {
+            TransferProofs::Plain(proof_data) => {
+                //Equality and ciphertext validity proofs are verified in the account creation
+                //transaction; the range proof is too large for that
+                creations.push(create_proof_account_at(
+                    token,
+                    authority,
+                    &proof_keypairs[0],
+                    &proof_data.equality_proof_data,
+                    false,
+                ));
+                creations.push(create_proof_account_at(
+                    token,
+                    authority,
+                    &proof_keypairs[1],
+                    &proof_data
+                        .ciphertext_validity_proof_data_with_ciphertext
+                        .proof_data,
+                    false,
+                ));
+                creations.push(create_proof_account_at(
+                    token,
+                    authority,
+                    &proof_keypairs[2],
+                    &proof_data.range_proof_data,
+                    true,
+                ));
+            }
+            TransferProofs::WithFee(proof_data) => {
+                creations.push(create_proof_account_at(
+                    token,
+                    authority,
+                    &proof_keypairs[0],
+                    &proof_data.equality_proof_data,
+                    false,
+                ));
+                creations.push(create_proof_account_at(
+                    token,
+                    authority,
+                    &proof_keypairs[1],
+                    &proof_data
+                        .transfer_amount_ciphertext_validity_proof_data_with_ciphertext
+                        .proof_data,
+                    false,
+                ));
+                //The fee range proof covers the transfer amount, fee and remaining balance, so
+                //it is the 256-bit batched range proof
+                creations.push(create_proof_account_at(
+                    token,
+                    authority,
+                    &proof_keypairs[2],
+                    &proof_data.range_proof_data,
+                    true,
+                ));
+                creations.push(create_proof_account_at(
+                    token,
+                    authority,
+                    &proof_keypairs[3],
+                    &proof_data.percentage_with_cap_proof_data,
+                    false,
+                ));
+                creations.push(create_proof_account_at(
+                    token,
+                    authority,
+                    &proof_keypairs[4],
+                    &proof_data.fee_ciphertext_validity_proof_data,
+                    false,
+                ));
+            }
+        }
+        creations.join().await
+    }
+
+    // Sends the transfer instruction referencing the proof accounts from `create_proof_accounts`,
+    // preceded by `memo` if any
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn submit(
+        &self,
+        token: &TokenClient,
+        owner: &Authority,
+        source_ata: &Pubkey,
+        proof_keypairs: &[Keypair],
+        elgamal_keypair: &ElGamalKeypair,
+        aes_key: &AeKey,
+        memo: Option<&str>,
+    ) -> Result<Signature> {
+        let proof_account =
+            |index: usize| ProofAccount::ContextAccount(proof_keypairs[index].pubkey());
+        //The auditor ciphertexts are read from the proof data rather than from the context account
+        let ciphertext_validity_proof = self.ciphertext_validity_proof();
+        let ciphertext_validity_proof_account_with_ciphertext = ProofAccountWithCiphertext {
+            proof_account: proof_account(1),
+            ciphertext_lo: ciphertext_validity_proof.ciphertext_lo,
+            ciphertext_hi: ciphertext_validity_proof.ciphertext_hi,
+        };
+        attach_memo(token, memo);
+        let response = match &self.transfer_fee {
+            None => {
+                token
+                    .confidential_transfer_transfer(
+                        source_ata,            //Source ata
+                        &self.destination_ata, //Destination ata
+                        &owner.pubkey(),       //Owner of the source ata
+                        Some(&proof_account(0)),
+                        Some(&ciphertext_validity_proof_account_with_ciphertext),
+                        Some(&proof_account(2)),
+                        self.amount, //Amount to transfer
+                        Some(self.source_account),
+                        elgamal_keypair,
+                        aes_key,
+                        &self.destination_elgamal_pubkey,
+                        self.auditor_elgamal_pubkey.as_ref(),
+                        owner.signers(),
+                    )
+                    .await?
+            }
+            Some(fee_params) => {
+                token
+                    .confidential_transfer_transfer_with_fee(
+                        source_ata,            //Source ata
+                        &self.destination_ata, //Destination ata
+                        &owner.pubkey(),       //Owner of the source ata
+                        Some(&proof_account(0)),
+                        Some(&ciphertext_validity_proof_account_with_ciphertext),
+                        Some(&proof_account(3)),
+                        Some(&proof_account(4)),
+                        Some(&proof_account(2)),
+                        self.amount, //Amount to transfer, fee included
+                        Some(self.source_account),
+                        elgamal_keypair,
+                        aes_key,
+                        &self.destination_elgamal_pubkey,
+                        self.auditor_elgamal_pubkey.as_ref(),
+                        &fee_params.withdraw_withheld_authority_elgamal_pubkey,
+                        u16::from(fee_params.transfer_fee.transfer_fee_basis_points),
+                        u64::from(fee_params.transfer_fee.maximum_fee),
+                        owner.signers(),
+                    )
+                    .await?
+            }
+        };
+        into_signature(response)
+    }
+
+    // Sends the creations of the proof accounts at `proof_keypairs` and the verifications, the
+    // transfer preceded by `memo` and the closes in as few transactions as they fit in, v0 ones
+    // with `lookup_table`, bundled through `bundle` and built on `nonces` if any, and returns the
+    // signatures of the transactions verifying each proof, holding the transfer and closing each
+    // account
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn send_packed(
+        &self,
+        token: &TokenClient,
+        owner: &Authority,
+        source_ata: &Pubkey,
+        fee_payer: &Pubkey,
+        proof_keypairs: &[Keypair],
+        aes_key: &AeKey,
+        memo: Option<&str>,
+        lookup_table: Option<&LookupTable>,
+        bundle: Option<&JitoBundle>,
+        nonces: Option<&DurableNonces>,
+    ) -> Result<(Vec<Signature>, Signature, Vec<Signature>)> {
+        //Catch invalid proofs before paying for any of the accounts
+        self.proofs.verify()?;
+        //Proof accounts cannot be managed by a multisig, so they belong to the proof authority
+        let proof_authority = owner.proof_authority();
+        let authority = proof_authority.pubkey();
+        let rent = from_account::<Rent, _>(&token.get_account(sysvar::rent::id()).await?)
+            .ok_or_else(|| anyhow!("Invalid rent sysvar"))?;
+        let proof_count = self.proof_count();
+        let proof_accounts = proof_keypairs
+            .iter()
+            .map(Keypair::pubkey)
+            .collect::<Vec<_>>();
+        //Same order as `create_proof_accounts`
+        let context_instructions = match &self.proofs {
+            TransferProofs::Plain(proof_data) => vec![
+                context_account_instructions(
+                    fee_payer,
+                    &proof_accounts[0],
+                    &authority,
+                    &proof_data.equality_proof_data,
+                    &rent,
+                )?,
+                context_account_instructions(
+                    fee_payer,
+                    &proof_accounts[1],
+                    &authority,
+                    &proof_data
+                        .ciphertext_validity_proof_data_with_ciphertext
+                        .proof_data,
+                    &rent,
+                )?,
+                context_account_instructions(
+                    fee_payer,
+                    &proof_accounts[2],
+                    &authority,
+                    &proof_data.range_proof_data,
+                    &rent,
+                )?,
+            ],
+            TransferProofs::WithFee(proof_data) => vec![
+                context_account_instructions(
+                    fee_payer,
+                    &proof_accounts[0],
+                    &authority,
+                    &proof_data.equality_proof_data,
+                    &rent,
+                )?,
+                context_account_instructions(
+                    fee_payer,
+                    &proof_accounts[1],
+                    &authority,
+                    &proof_data
+                        .transfer_amount_ciphertext_validity_proof_data_with_ciphertext
+                        .proof_data,
+                    &rent,
+                )?,
+                context_account_instructions(
+                    fee_payer,
+                    &proof_accounts[2],
+                    &authority,
+                    &proof_data.range_proof_data,
+                    &rent,
+                )?,
+                context_account_instructions(
+                    fee_payer,
+                    &proof_accounts[3],
+                    &authority,
+                    &proof_data.percentage_with_cap_proof_data,
+                    &rent,
+                )?,
+                context_account_instructions(
+                    fee_payer,
+                    &proof_accounts[4],
+                    &authority,
+                    &proof_data.fee_ciphertext_validity_proof_data,
+                    &rent,
+                )?,
+            ],
+        };
+        //Account creations and verifications, the transfer, then the closes, each a group
+        let mut groups = vec![];
+        for [create, verify] in context_instructions {
+            groups.push(vec![create]);
+            groups.push(vec![verify]);
+        }
+        let operation = groups.len();
+        groups.push(self.transfer_instructions(
+            token,
+            owner,
+            source_ata,
+            &proof_accounts,
+            aes_key,
+        )?);
+        groups.extend(
+            proof_accounts
+                .iter()
+                .map(|account| vec![close_context_account_instruction(account, &authority)]),
+        );
+        let packed = pack_instructions(
+            &groups,
+            operation,
+            fee_payer,
+            memo,
+            lookup_table.map(|table| &table.account),
+            nonces,
+        )?;
+
+        let mut signers = owner
+            .signers()
+            .iter()
+            .map(|signer| signer.as_ref())
+            .collect::<Vec<&dyn Signer>>();
+        signers.push(proof_authority.as_ref());
+        signers.extend(proof_keypairs.iter().map(|keypair| keypair as &dyn Signer));
+        let signatures =
+            send_packed_transactions(token, &packed, memo, &signers, lookup_table, bundle, nonces)
+                .await?;
+        let signature = |group: usize| signatures[packed.group_transactions[group]];
+        let proof_signatures = (0..proof_count)
+            .map(|index| signature(2 * index + 1))
+            .collect();
+        let close_signatures = (0..proof_count)
+            .map(|index| signature(operation + 1 + index))
+            .collect();
+        Ok((proof_signatures, signature(operation), close_signatures))
+    }
+
+    // Transfer instruction referencing the context state accounts `proof_accounts`, in the order
+    // of `create_proof_accounts`
+    fn transfer_instructions(
+        &self,
+        token: &TokenClient,
+        owner: &Authority,
+        source_ata: &Pubkey,
+        proof_accounts: &[Pubkey],
+        aes_key: &AeKey,
+    ) -> Result<Vec<Instruction>> {
+        //Available balance the transfer leaves, encrypted under the AES key
+        let new_decryptable_available_balance: PodAeCiphertext = self
+            .source_account
+            .new_decryptable_available_balance(self.amount, aes_key)?
+            .into();
+        //The auditor ciphertexts are read from the proof data rather than from the context account
+        let ciphertext_validity_proof = self.ciphertext_validity_proof();
+        let multisig_signers = owner.multisig_signers();
+        let multisig_signers = multisig_signers.iter().collect::<Vec<_>>();
+        Ok(match self.transfer_fee {
+            None => transfer_instruction(
+                &token_2022_program_id(),
+                source_ata,            //Source ata
+                token.get_address(),   //Mint
+                &self.destination_ata, //Destination ata
+                &new_decryptable_available_balance,
+                &ciphertext_validity_proof.ciphertext_lo,
+                &ciphertext_validity_proof.ciphertext_hi,
+                &owner.pubkey(), //Owner of the source ata
+                &multisig_signers,
+                ProofLocation::ContextStateAccount(&proof_accounts[0]),
+                ProofLocation::ContextStateAccount(&proof_accounts[1]),
+                ProofLocation::ContextStateAccount(&proof_accounts[2]),
+            )?,
+            Some(_) => transfer_with_fee_instruction(
+                &token_2022_program_id(),
+                source_ata,            //Source ata
+                token.get_address(),   //Mint
+                &self.destination_ata, //Destination ata
+                &new_decryptable_available_balance,
+                &ciphertext_validity_proof.ciphertext_lo,
+                &ciphertext_validity_proof.ciphertext_hi,
+                &owner.pubkey(), //Owner of the source ata
+                &multisig_signers,
+                ProofLocation::ContextStateAccount(&proof_accounts[0]),
+                ProofLocation::ContextStateAccount(&proof_accounts[1]),
+                ProofLocation::ContextStateAccount(&proof_accounts[3]),
+                ProofLocation::ContextStateAccount(&proof_accounts[4]),
+                ProofLocation::ContextStateAccount(&proof_accounts[2]),
+            )?,
+        })
+    }
+
+    // Collects the accounts and signatures of the completed transfer
+    pub(crate) fn result(
+        &self,
+        proof_keypairs: &[Keypair],
+        proof_signatures: Vec<Signature>,
+        transfer_signature: Signature,
+        close_signatures: Vec<Signature>,
+    ) -> TransferResult {
+        let proof_accounts = proof_keypairs
+            .iter()
+            .map(Keypair::pubkey)
+            .collect::<Vec<_>>();
+        TransferResult {
+            equality_proof_account: proof_accounts[0],
+            ciphertext_validity_proof_account: proof_accounts[1],
+            range_proof_account: proof_accounts[2],
+            fee_proof_accounts: matches!(self.proofs, TransferProofs::WithFee(_))
+                .then(|| [proof_accounts[3], proof_accounts[4]]),
+            fee: self.fee,
+            proof_signatures,
+            transfer_signature,
+            close_signatures,
+            proof_generation_time: self.proof_generation_time,
+        }
+    }
+}
+
+// `ConfidentialTransferAccount` extension of `account`, with readable errors for accounts that
+// do not exist or are not configured
+pub(crate) async fn get_confidential_transfer_account(
+    token: &TokenClient,
+    account: &Pubkey,
+) -> Result<ConfidentialTransferAccount> {
+    let account_info = match token.get_account_info(account).await {
+        Ok(account_info) => account_info,
+        Err(TokenError::AccountNotFound) => {
+            bail!("Token account {account} does not exist; it must be created and configured first")
+        }
+        Err(err) => return Err(err.into()),
+    };
+    account_info
+        .get_extension::<ConfidentialTransferAccount>()
+        .copied()
+        .map_err(|_| {
+            anyhow!("Token account {account} is not configured for confidential transfers")
+        })
+}
+
+// Creates a context state account managed by `authority` and verifies `proof_data` into it.
+// Proofs too large to be verified in the account creation transaction need
+// `split_account_creation_and_proof_verification`.
+pub(crate) async fn create_proof_account<ZK: Pod + ZkProofData<U>, U: Pod>(
+    token: &TokenClient,
+    authority: &Arc<dyn Signer>,
+    proof_data: &ZK,
+    split_account_creation_and_proof_verification: bool,
+) -> Result<(Keypair, Signature)> {
+    let proof_keypair = keygen::new_keypair();
+    let signature = create_proof_account_at(
+        token,
+        authority,
+        &proof_keypair,
+        proof_data,
+        split_account_creation_and_proof_verification,
+    )
+    .await?;
+    Ok((proof_keypair, signature))
+}
+
+// Same as `create_proof_account`, at the address of `proof_keypair`
+pub(crate) async fn create_proof_account_at<ZK: Pod + ZkProofData<U>, U: Pod>(
+    token: &TokenClient,
+    authority: &Arc<dyn Signer>,
+    proof_keypair: &Keypair,
+    proof_data: &ZK,
+    split_account_creation_and_proof_verification: bool,
+) -> Result<Signature> {
+    into_signature(
+        token
+            .confidential_transfer_create_context_state_account(
+                &proof_keypair.pubkey(), //Public key for the proof account
+                &authority.pubkey(),     //Authority that can manage the account
+                proof_data,
+                split_account_creation_and_proof_verification,
+                &[proof_keypair], //Signer of the new account; the authority need not sign
+            )
+            .await?,
+    )
+}
+
+// Closes the context state accounts managed by `authority`, concurrently, refunding their rent
+// to it
+pub(crate) async fn close_proof_accounts(
+    token: &TokenClient,
+    authority: &Arc<dyn Signer>,
+    proof_keypairs: &[Keypair],
+) -> Result<Vec<Signature>> {
+    let mut closes = ConcurrentSends::new();
+    for proof_keypair in proof_keypairs {
+        closes.push(async move {
+            into_signature(
+                token
+                    .confidential_transfer_close_context_state_account(
+                        &proof_keypair.pubkey(), //Public key of the proof account
+                        &authority.pubkey(),     //Destination to receive recovered rent
+                        &authority.pubkey(),     //Authority that can close the account
+                        &[authority],            //Signer(authority)
+                    )
+                    .await?,
+            )
+        });
+    }
+    closes.join().await
+}
@@ -0,0 +1,453 @@
+use anyhow::Result;
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Block, Cell, Paragraph, Row, Table, TableState},
+};
+use solana_sdk::pubkey::Pubkey;
+use spl_token_client::spl_token_2022::solana_zk_sdk::encryption::{
+    auth_encryption::AeKey, elgamal::ElGamalKeypair,
+};
+use std::time::Duration;
+
+use crate::{
+    TokenClient,
+    amount::{self, Amount},
+    authority::Authority,
+    balance::{self, ApplyUrgency, Balances},
+    decode_table::DecodeTable,
+    mint,
+    placement::Packing,
+    transfer,
+};
+
+//Longest wait for a key press before the screen is drawn again
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Token account of the owner shown by [`run`], with the keys its balances are decrypted and
+/// its transfers proven with.
+pub struct DashboardAccount {
+    /// Symbol of the mint, if known.
+    pub symbol: Option<String>,
+    /// Token account address.
+    pub account: Pubkey,
+    /// Token client of the mint.
+    pub token: TokenClient,
+    /// Decimals of the mint.
+    pub decimals: u8,
+    /// ElGamal keypair of the account.
+    pub elgamal_keypair: ElGamalKeypair,
+    /// AES key of the account.
+    pub aes_key: AeKey,
+}
+
+/// Row of the accounts table of a [`Dashboard`].
+#[derive(Debug, Clone)]
+pub struct AccountRow {
+    /// Symbol of the mint, if known.
+    pub symbol: Option<String>,
+    /// Mint of the account.
+    pub mint: Pubkey,
+    /// Token account address.
+    pub account: Pubkey,
+    /// Decimals of the mint, balances are shown with.
+    pub decimals: u8,
+    /// Last decrypted balances; `None` until read.
+    pub balances: Option<Balances>,
+}
+
+/// What a key press asks of the loop running the [`Dashboard`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Leave the dashboard.
+    Quit,
+    /// Decrypt the balances of every account again.
+    Refresh,
+    /// Transfer `amount` base units confidentially from the account of row `row` to the
+    /// associated token account of the `recipient` wallet.
+    Transfer {
+        row: usize,
+        recipient: Pubkey,
+        amount: u64,
+        memo: Option<String>,
+    },
+}
+
+/// State of the terminal dashboard: the accounts table, the transfer form and the status line.
+///
+/// Holds no keys and sends nothing: [`handle_key`](Self::handle_key) turns key presses into
+/// [`Action`]s for the caller to run, and [`render`](Self::render) draws the state.
+#[derive(Debug)]
+pub struct Dashboard {
+    owner: Pubkey,
+    rows: Vec<AccountRow>,
+    table: TableState,
+    form: Option<TransferForm>,
+    status: String,
+}
+
+// Transfer form, open over the selected account
+#[derive(Debug, Default)]
+struct TransferForm {
+    recipient: String,
+    amount: String,
+    memo: String,
+    field: Field,
+}
+
+// Field of the transfer form being typed in
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    #[default]
+    Recipient,
+    Amount,
+    Memo,
+}
+
+impl Field {
+    fn next(self) -> Self {
+        match self {
+            Field::Recipient => Field::Amount,
+            Field::Amount => Field::Memo,
+            Field::Memo => Field::Recipient,
+        }
+    }
+
+    fn previous(self) -> Self {
+        self.next().next()
+    }
+}
+
+impl TransferForm {
+    fn input(&mut self) -> &mut String {
+        match self.field {
+            Field::Recipient => &mut self.recipient,
+            Field::Amount => &mut self.amount,
+            Field::Memo => &mut self.memo,
+        }
+    }
+}
+
+impl Dashboard {
+    /// Dashboard of `owner`'s accounts, the first one selected.
+    pub fn new(owner: Pubkey, rows: Vec<AccountRow>) -> Self {
+        let mut table = TableState::default();
+        if !rows.is_empty() {
+            table.select(Some(0));
+        }
+        Self {
+            owner,
+            rows,
+            table,
+            form: None,
+            status: "Loading balances".to_string(),
+        }
+    }
+
+    /// Rows of the accounts table.
+    pub fn rows(&self) -> &[AccountRow] {
+        &self.rows
+    }
+
+    /// Index of the selected row.
+    pub fn selected(&self) -> Option<usize> {
+        self.table.selected()
+    }
+
+    /// Shows the decrypted `balances` of row `row`.
+    pub fn set_balances(&mut self, row: usize, balances: Balances) {
+        self.rows[row].balances = Some(balances);
+    }
+
+    /// Replaces the status line.
+    pub fn set_status(&mut self, status: impl Into<String>) {
+        self.status = status.into();
+    }
+
+    /// Handles a key press, returning what the caller must do, if anything.
+    ///
+    /// Without the form open: `q` or Esc quits, the arrows or `j`/`k` select an account, `r`
+    /// refreshes the balances and `t` opens the transfer form. In the form: Tab and the arrows
+    /// move between the recipient, amount and memo, Enter submits and Esc closes it.
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<Action> {
+        if self.form.is_some() {
+            return self.handle_form_key(key);
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Some(Action::Quit),
+            KeyCode::Char('r') => return Some(Action::Refresh),
+            KeyCode::Down | KeyCode::Char('j') => self.table.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => self.table.select_previous(),
+            KeyCode::Char('t') if self.selected().is_some() => {
+                self.form = Some(TransferForm::default());
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn handle_form_key(&mut self, key: KeyEvent) -> Option<Action> {
+        let form = self.form.as_mut()?;
+        match key.code {
+            KeyCode::Esc => self.form = None,
+            KeyCode::Tab | KeyCode::Down => form.field = form.field.next(),
+            KeyCode::BackTab | KeyCode::Up => form.field = form.field.previous(),
+            KeyCode::Backspace => {
+                form.input().pop();
+            }
+            KeyCode::Char(c) => form.input().push(c),
+            KeyCode::Enter => return self.submit(),
+            _ => {}
+        }
+        None
+    }
+
+    // Transfer of the form, which is closed, or `None` with the status naming the invalid field
+    fn submit(&mut self) -> Option<Action> {
+        let form = self.form.as_ref()?;
+        let row = self.selected()?;
+        let Ok(recipient) = form.recipient.trim().parse() else {
+            self.status = format!("Invalid recipient wallet: {}", form.recipient.trim());
+            return None;
+        };
+        let decimals = self.rows[row].decimals;
+        let amount = form
+            .amount
+            .parse::<Amount>()
+            .and_then(|amount| amount.to_base_units(decimals));
+        let amount = match amount {
+            Ok(0) => {
+                self.status = format!("Invalid amount: {}", form.amount.trim());
+                return None;
+            }
+            Ok(amount) => amount,
+            Err(err) => {
+                self.status = err.to_string();
+                return None;
+            }
+        };
+        let memo = Some(form.memo.trim().to_string()).filter(|memo| !memo.is_empty());
+        self.form = None;
+        Some(Action::Transfer {
+            row,
+            recipient,
+            amount,
+            memo,
+        })
+    }
+
+    /// Draws the dashboard over the whole frame.
+    pub fn render(&mut self, frame: &mut Frame) {
+        let form_height = if self.form.is_some() { 5 } else { 0 };
+        let [header, table, form_area, status, help] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(form_height),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        frame.render_widget(
+            Paragraph::new(format!("Confidential accounts of {}", self.owner))
+                .style(Style::new().add_modifier(Modifier::BOLD)),
+            header,
+        );
+        frame.render_stateful_widget(self.table(), table, &mut self.table);
+        if let Some(form) = &self.form {
+            frame.render_widget(transfer_form(form), form_area);
+        }
+        frame.render_widget(Paragraph::new(self.status.as_str()), status);
+        let keys = if self.form.is_some() {
+            "Tab next field  Enter transfer  Esc cancel"
+        } else {
+            "↑↓ select  t transfer  r refresh  q quit"
+        };
+        frame.render_widget(
+            Paragraph::new(keys).style(Style::new().fg(Color::DarkGray)),
+            help,
+        );
+    }
+
+    fn table(&self) -> Table<'static> {
+        let header = Row::new([
+            "Symbol",
+            "Account",
+            "Public",
+            "Pending",
+            "Available",
+            "Credits",
+        ])
+        .style(Style::new().add_modifier(Modifier::BOLD));
+        let rows = self.rows.iter().map(|row| {
+            let amount = |amount| amount::format(amount, row.decimals);
+            let symbol = row.symbol.clone().unwrap_or_else(|| row.mint.to_string());
+            let Some(balances) = &row.balances else {
+                return Row::new([Cell::from(symbol), Cell::from(row.account.to_string())]);
+            };
+            let credits = &balances.pending_credits;
+            let credits_style = match credits.urgency() {
+                ApplyUrgency::None => Style::new(),
+                ApplyUrgency::Soon => Style::new().fg(Color::Yellow),
+                ApplyUrgency::Now => Style::new().fg(Color::Red),
+            };
+            Row::new([
+                Cell::from(symbol),
+                Cell::from(row.account.to_string()),
+                Cell::from(amount(balances.public)),
+                Cell::from(amount(balances.pending)),
+                Cell::from(amount(balances.available)),
+                Cell::from(format!("{} / {}", credits.counter, credits.maximum))
+                    .style(credits_style),
+            ])
+        });
+        Table::new(
+            rows,
+            [
+                Constraint::Length(12),
+                Constraint::Length(44),
+                Constraint::Min(10),
+                Constraint::Min(10),
+                Constraint::Min(10),
+                Constraint::Length(12),
+            ],
+        )
+        .header(header)
+        .block(Block::bordered().title("Accounts"))
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+    }
+}
+
+// Fields of the transfer form, the one typed in marked
+fn transfer_form(form: &TransferForm) -> Paragraph<'_> {
+    let line = |field, label, value: &str| {
+        let marker = if form.field == field { "> " } else { "  " };
+        Line::from(format!("{marker}{label:<22}{value}"))
+    };
+    Paragraph::new(vec![
+        line(Field::Recipient, "Recipient wallet", &form.recipient),
+        line(Field::Amount, "Amount", &form.amount),
+        line(Field::Memo, "Memo (optional)", &form.memo),
+    ])
+    .block(Block::bordered().title("Confidential transfer"))
+}
+
+/// Runs the dashboard of `owner`'s `accounts` in the terminal until the operator quits.
+///
+/// Balances are decrypted with `decode_table` when it opens and on refresh, and after each
+/// transfer. Transfers run in the foreground, the status line telling when they are done.
+pub async fn run(
+    owner: &Authority,
+    accounts: &[DashboardAccount],
+    decode_table: &DecodeTable,
+) -> Result<()> {
+    let rows = accounts
+        .iter()
+        .map(|account| AccountRow {
+            symbol: account.symbol.clone(),
+            mint: *account.token.get_address(),
+            account: account.account,
+            decimals: account.decimals,
+            balances: None,
+        })
+        .collect();
+    let mut dashboard = Dashboard::new(owner.pubkey(), rows);
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut dashboard, owner, accounts, decode_table).await;
+    ratatui::restore();
+    result
+}
+
+async fn event_loop(
+    terminal: &mut DefaultTerminal,
+    dashboard: &mut Dashboard,
+    owner: &Authority,
+    accounts: &[DashboardAccount],
+    decode_table: &DecodeTable,
+) -> Result<()> {
+    terminal.draw(|frame| dashboard.render(frame))?;
+    refresh(dashboard, accounts, decode_table).await;
+    loop {
+        terminal.draw(|frame| dashboard.render(frame))?;
+        //Nothing else runs while the dashboard waits for a key, so the poll may block
+        if !event::poll(POLL_INTERVAL)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match dashboard.handle_key(key) {
+            None => {}
+            Some(Action::Quit) => return Ok(()),
+            Some(Action::Refresh) => {
+                dashboard.set_status("Refreshing balances");
+                terminal.draw(|frame| dashboard.render(frame))?;
+                refresh(dashboard, accounts, decode_table).await;
+            }
+            Some(Action::Transfer {
+                row,
+                recipient,
+                amount,
+                memo,
+            }) => {
+                let source = &accounts[row];
+                let ui_amount = amount::format(amount, source.decimals);
+                dashboard.set_status(format!("Transferring {ui_amount} to {recipient}"));
+                terminal.draw(|frame| dashboard.render(frame))?;
+                let destination = mint::get_ata(&recipient, source.token.get_address());
+                let transferred = transfer::transfer(
+                    &source.token,
+                    owner,
+                    &source.account,
+                    &destination,
+                    amount,
+                    &source.elgamal_keypair,
+                    &source.aes_key,
+                    memo.as_deref(),
+                    Packing::Separate,
+                )
+                .await;
+                refresh(dashboard, accounts, decode_table).await;
+                dashboard.set_status(match transferred {
+                    Ok(result) => format!(
+                        "Transferred {ui_amount} to {destination}: {}",
+                        result.transfer_signature
+                    ),
+                    Err(err) => format!("Transfer failed: {err:#}"),
+                });
+            }
+        }
+    }
+}
+
+// Decrypts the balances of every account, reporting the first failure in the status line
+async fn refresh(
+    dashboard: &mut Dashboard,
+    accounts: &[DashboardAccount],
+    decode_table: &DecodeTable,
+) {
+    let mut failure = None;
+    for (row, account) in accounts.iter().enumerate() {
+        match balance::get_balances(
+            &account.token,
+            &account.account,
+            &account.elgamal_keypair,
+            &account.aes_key,
+            decode_table,
+        )
+        .await
+        {
+            Ok(balances) => dashboard.set_balances(row, balances),
+            Err(err) => {
+                failure
+                    .get_or_insert_with(|| format!("Unable to read {}: {err:#}", account.account));
+            }
+        }
+    }
+    dashboard.set_status(failure.unwrap_or_else(|| "Balances up to date".to_string()));
+}
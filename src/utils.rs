@@ -1,19 +1,227 @@
-
-use std::sync::Arc;
-
-use anyhow::{Context, Result};
-use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
-use dirs;
+use anyhow::{Context, Result, anyhow, bail};
+use solana_derivation_path::DerivationPath;
+use solana_remote_wallet::remote_wallet::RemoteWalletManager;
+use solana_sdk::{
+    bs58,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::{Signer, null_signer::NullSigner},
+};
 use spl_token_client::{
-    
-    client::{ProgramRpcClientSendTransaction, RpcClientResponse}, token::Token
+    client::RpcClientResponse,
+    spl_token_2022::solana_zk_sdk::encryption::{
+        elgamal::{ElGamalKeypair, ElGamalPubkey},
+        pod::elgamal::PodElGamalPubkey,
+    },
+};
+use std::{cell::OnceCell, path::Path, rc::Rc, str::FromStr, sync::Arc};
+use uriparse::URIReference;
+
+use crate::{
+    TokenClient,
+    config::Config,
+    keygen,
+    keystore::write_private,
+    ledger::{connect_wallet_manager, is_remote_wallet_path, load_ledger_signer},
+    remote_signer::SignerBackend,
+    seed::keypair_from_seed_phrase,
 };
-pub fn load_keypair()->Result<Keypair>{
-    // Load the keypair from the default Solana CLI location
-    let keypair_path=dirs::home_dir().context("Unable to get home directory")?.join(".config/solana/id.json");
+
+/// Loads the keypair the Solana CLI uses by default: the `keypair_path` of its config file, or
+/// `~/.config/solana/id.json` (see [`Config::keypair_path`]).
+pub fn load_keypair() -> Result<Keypair> {
+    load_keypair_from(&Config::default().keypair_path()?)
+}
+
+/// Loads a keypair from a Solana CLI style JSON keypair file.
+pub fn load_keypair_from(keypair_path: &Path) -> Result<Keypair> {
     // Read the keypair file
-    let file=std::fs::File::open(&keypair_path)?;
-    let keypair_bytes:Vec<u8>=serde_json::from_reader(file)?;
-    let keypair=Keypair::try_from(&keypair_bytes[..])?;
+    let file = std::fs::File::open(keypair_path)
+        .with_context(|| format!("Unable to open keypair file {}", keypair_path.display()))?;
+    let keypair_bytes: Vec<u8> = serde_json::from_reader(file)?;
+    let keypair = Keypair::try_from(&keypair_bytes[..])?;
+    Ok(keypair)
+}
+
+/// Generates a new keypair and writes it to `path` as a Solana CLI style JSON keypair file,
+/// readable by the current user only. Missing directories are created; an existing file is never
+/// overwritten.
+pub fn create_keypair(path: &Path) -> Result<Keypair> {
+    if path.exists() {
+        bail!("Refusing to overwrite existing file {}", path.display());
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create {}", parent.display()))?;
+    }
+    let keypair = keygen::new_keypair();
+    write_private(
+        path,
+        serde_json::to_string(&keypair.to_bytes().to_vec())?.as_bytes(),
+    )?;
+    Ok(keypair)
+}
+
+/// Where [`resolve_signer`] finds the signers not held in keypair files: hardware wallets,
+/// connected on first use and shared by every `usb://` signer, and the remote signing service of
+/// `remote://` signers.
+#[derive(Default)]
+pub struct SignerSources {
+    wallet_manager: OnceCell<Rc<RemoteWalletManager>>,
+    remote: Option<Box<dyn SignerBackend>>,
+    offline: bool,
+}
+
+impl SignerSources {
+    /// Sources resolving `remote://` signers through `remote`, if given.
+    pub fn new(remote: Option<Box<dyn SignerBackend>>) -> Self {
+        Self {
+            wallet_manager: OnceCell::new(),
+            remote,
+            offline: false,
+        }
+    }
+
+    /// Also resolves bare pubkeys, into signers leaving their signature to be added offline (see
+    /// [`offline`](crate::offline)).
+    pub fn with_offline_signers(mut self) -> Self {
+        self.offline = true;
+        self
+    }
+
+    // Hardware wallets, connected on first use
+    fn wallet_manager(&self) -> Result<&RemoteWalletManager> {
+        if let Some(wallet_manager) = self.wallet_manager.get() {
+            return Ok(wallet_manager);
+        }
+        let connected = connect_wallet_manager()?;
+        Ok(self.wallet_manager.get_or_init(|| connected))
+    }
+}
+
+/// Resolves a keypair argument into a signer, following the Solana CLI conventions:
+///
+/// - a path to a JSON keypair file, optionally as `file://<PATH>`;
+/// - a keypair given inline, as a JSON byte array or a base58 string;
+/// - `prompt://` or `prompt:` to type a seed phrase and its passphrase, with
+///   `?key=<ACCOUNT>[/<CHANGE>]` for a derived key as in `prompt://?key=0/0`;
+/// - `usb://ledger?key=0` for a Ledger, see [`load_ledger_signer`];
+/// - `remote://<KEY_ID>` for a key of the remote signing service of `sources`;
+/// - with [`SignerSources::with_offline_signers`], a bare pubkey for a key signing offline.
+///
+/// Existing files take precedence over the inline forms. `keypair_name` names the role in prompts
+/// and errors.
+pub fn resolve_signer(
+    uri: &str,
+    keypair_name: &str,
+    sources: &SignerSources,
+) -> Result<Arc<dyn Signer>> {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    if Path::new(path).is_file() {
+        return Ok(Arc::new(load_keypair_from(Path::new(path))?));
+    }
+    if uri.starts_with('[') {
+        let bytes: Vec<u8> = serde_json::from_str(uri)
+            .with_context(|| format!("Invalid inline keypair for {keypair_name}"))?;
+        return Ok(Arc::new(Keypair::try_from(&bytes[..]).with_context(
+            || format!("Invalid inline keypair for {keypair_name}"),
+        )?));
+    }
+    if uri.starts_with("prompt:") {
+        return Ok(Arc::new(prompt_keypair(uri, keypair_name)?));
+    }
+    if is_remote_wallet_path(uri) {
+        let signer = load_ledger_signer(uri, keypair_name, sources.wallet_manager()?)?;
+        //Signers are used from the task that resolved them, so the device handle need not be Send
+        #[allow(clippy::arc_with_non_send_sync)]
+        let signer: Arc<dyn Signer> = Arc::new(signer);
+        return Ok(signer);
+    }
+    if let Some(key_id) = uri.strip_prefix("remote://") {
+        let backend = sources
+            .remote
+            .as_ref()
+            .ok_or_else(|| anyhow!("No remote signing service to resolve {uri} with"))?;
+        return backend.signer(key_id);
+    }
+    if let Ok(bytes) = bs58::decode(uri).into_vec()
+        && bytes.len() == 64
+    {
+        return Ok(Arc::new(Keypair::try_from(&bytes[..]).with_context(
+            || format!("Invalid base58 keypair for {keypair_name}"),
+        )?));
+    }
+    if sources.offline
+        && let Ok(pubkey) = Pubkey::from_str(uri)
+    {
+        return Ok(Arc::new(NullSigner::new(&pubkey)));
+    }
+    bail!("No keypair file at {path} for {keypair_name}")
+}
+
+// Keypair of a seed phrase typed on the terminal, at the derivation path of the `key` query
+fn prompt_keypair(uri: &str, keypair_name: &str) -> Result<Keypair> {
+    let derivation_path = match uri.split_once('?') {
+        Some(_) => {
+            let uri_ref = URIReference::try_from(uri)
+                .map_err(|e| anyhow!("Invalid keypair URI {uri}: {e}"))?;
+            DerivationPath::from_uri_key_query(&uri_ref)
+                .map_err(|e| anyhow!("Invalid derivation path in {uri}: {e}"))?
+        }
+        None => None,
+    };
+    let seed_phrase = rpassword::prompt_password(format!("Seed phrase of {keypair_name}: "))
+        .context("Unable to read the seed phrase")?;
+    let passphrase = rpassword::prompt_password("Seed phrase passphrase (empty for none): ")
+        .context("Unable to read the seed phrase passphrase")?;
+    keypair_from_seed_phrase(&seed_phrase, &passphrase, derivation_path)
+}
+
+/// Loads an ElGamal keypair from a JSON keypair file.
+pub fn load_elgamal_keypair(path: &Path) -> Result<ElGamalKeypair> {
+    ElGamalKeypair::read_json_file(path).map_err(|err| {
+        anyhow!(
+            "Unable to read ElGamal keypair file {}: {err}",
+            path.display()
+        )
+    })
+}
+
+/// Generates a new random ElGamal keypair and writes it to `path`, which must not exist yet.
+pub fn create_elgamal_keypair(path: &Path) -> Result<ElGamalKeypair> {
+    if path.exists() {
+        bail!("Refusing to overwrite existing file {}", path.display());
+    }
+    let keypair = keygen::new_elgamal_keypair();
+    keypair.write_json_file(path).map_err(|err| {
+        anyhow!(
+            "Unable to write ElGamal keypair file {}: {err}",
+            path.display()
+        )
+    })?;
     Ok(keypair)
 }
+
+/// Parses a base64 encoded ElGamal pubkey.
+pub fn parse_elgamal_pubkey(encoded: &str) -> Result<ElGamalPubkey> {
+    PodElGamalPubkey::from_str(encoded)
+        .ok()
+        .and_then(|pubkey| ElGamalPubkey::try_from(pubkey).ok())
+        .ok_or_else(|| anyhow!("Invalid ElGamal pubkey: {encoded}"))
+}
+
+// Attaches `memo`, if any, to the next transaction sent through `token`. The token client puts
+// it right before the token instructions, where accounts requiring memos look for it
+pub(crate) fn attach_memo(token: &TokenClient, memo: Option<&str>) {
+    if let Some(memo) = memo {
+        token.with_memo(memo, vec![]);
+    }
+}
+
+// Extracts the transaction signature from a token client response
+pub(crate) fn into_signature(response: RpcClientResponse) -> Result<Signature> {
+    match response {
+        RpcClientResponse::Signature(signature) => Ok(signature),
+        other => bail!("Expected a transaction signature, got: {other:?}"),
+    }
+}
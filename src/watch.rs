@@ -0,0 +1,156 @@
+use anyhow::{Result, anyhow};
+use futures::StreamExt;
+use solana_account_decoder_client_types::UiAccountEncoding;
+use solana_client::{nonblocking::pubsub_client::PubsubClient, rpc_config::RpcAccountInfoConfig};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use spl_token_client::spl_token_2022::{
+    extension::{
+        BaseStateWithExtensions, StateWithExtensionsOwned,
+        confidential_transfer::ConfidentialTransferAccount,
+    },
+    solana_zk_sdk::encryption::{auth_encryption::AeKey, elgamal::ElGamalKeypair},
+    state::Account,
+};
+use tracing::{debug, instrument};
+
+use crate::{
+    TokenClient, authority::Authority, balance::get_pending_credits,
+    deposit::apply_pending_balance, metrics,
+};
+
+/// Settings of [`watch_and_apply`].
+#[derive(Debug, Clone, Copy)]
+pub struct WatchOptions {
+    /// Commitment of the account subscription; should match the commitment of the token client.
+    pub commitment: CommitmentConfig,
+    /// Pending credits that trigger an apply, capped at the account's maximum; 1 applies every
+    /// credit as soon as it lands.
+    pub apply_threshold: u64,
+}
+
+/// Incoming credits seen by [`watch_and_apply`] in an account notification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReceivedCredits {
+    /// Credits received since the previous notification.
+    pub counter_delta: u64,
+    /// Pending credit counter after them.
+    pub pending_credits: u64,
+    /// Slot of the notification.
+    pub slot: u64,
+}
+
+/// Pending balance applied by [`watch_and_apply`].
+#[derive(Debug, Clone, Copy)]
+pub struct AppliedPendingBalance {
+    /// Pending credits folded into the available balance.
+    pub credits: u64,
+    /// Signature of the apply pending balance instruction.
+    pub signature: Signature,
+}
+
+/// Watches `ata_pubkey` over a websocket subscription and applies its pending balance whenever
+/// the pending credit counter reaches `options.apply_threshold`.
+///
+/// Credits received before the subscription are applied right away. Once the counter reaches
+/// the account's maximum, incoming transfers are rejected, so the threshold is capped there.
+/// `on_credit` is called for every notification raising the pending credit counter, before the
+/// apply it may trigger, and `on_apply` after every apply. Runs until the subscription closes or
+/// an operation fails, and returns the error; callers reconnect as they see fit.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip_all, fields(ata = %ata_pubkey, threshold = options.apply_threshold))]
+pub async fn watch_and_apply(
+    token: &TokenClient,
+    pubsub_client: &PubsubClient,
+    owner: &Authority,
+    ata_pubkey: &Pubkey,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+    options: WatchOptions,
+    mut on_credit: impl FnMut(ReceivedCredits),
+    mut on_apply: impl FnMut(AppliedPendingBalance),
+) -> Result<()> {
+    let config = RpcAccountInfoConfig {
+        encoding: Some(UiAccountEncoding::Base64),
+        commitment: Some(options.commitment),
+        ..RpcAccountInfoConfig::default()
+    };
+    let (mut updates, unsubscribe) = pubsub_client
+        .account_subscribe(ata_pubkey, Some(config))
+        .await?;
+    debug!("Subscribed to account updates");
+
+    let watched: Result<()> = async {
+        //Counter the next notification is compared with
+        let mut last_credits = get_pending_credits(token, ata_pubkey).await?.counter;
+        metrics::set_pending_credits(ata_pubkey, last_credits);
+        //Credits received while nobody was watching
+        if let Some(applied) =
+            apply_if_needed(token, owner, ata_pubkey, elgamal_keypair, aes_key, options).await?
+        {
+            last_credits = 0;
+            metrics::set_pending_credits(ata_pubkey, 0);
+            on_apply(applied);
+        }
+        while let Some(update) = updates.next().await {
+            let credits = pending_credits(update.value.data.decode())?;
+            debug!(slot = update.context.slot, credits, "Account updated");
+            if credits > last_credits {
+                on_credit(ReceivedCredits {
+                    counter_delta: credits - last_credits,
+                    pending_credits: credits,
+                    slot: update.context.slot,
+                });
+            }
+            last_credits = credits;
+            metrics::set_pending_credits(ata_pubkey, credits);
+            if credits == 0 {
+                continue;
+            }
+            //The notification may already be stale; the fresh account state decides
+            if let Some(applied) =
+                apply_if_needed(token, owner, ata_pubkey, elgamal_keypair, aes_key, options).await?
+            {
+                last_credits = 0;
+                metrics::set_pending_credits(ata_pubkey, 0);
+                on_apply(applied);
+            }
+        }
+        Err(anyhow!("Account subscription of {ata_pubkey} closed"))
+    }
+    .await;
+    unsubscribe().await;
+    watched
+}
+
+// Pending credit counter of a token account notification
+fn pending_credits(data: Option<Vec<u8>>) -> Result<u64> {
+    let data = data.ok_or_else(|| anyhow!("Undecodable account notification"))?;
+    let state = StateWithExtensionsOwned::<Account>::unpack(data)?;
+    let extension = state.get_extension::<ConfidentialTransferAccount>()?;
+    Ok(u64::from(extension.pending_balance_credit_counter))
+}
+
+// Applies the pending balance if the current credit counter reaches the threshold
+async fn apply_if_needed(
+    token: &TokenClient,
+    owner: &Authority,
+    ata_pubkey: &Pubkey,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+    options: WatchOptions,
+) -> Result<Option<AppliedPendingBalance>> {
+    let token_account = token.get_account_info(ata_pubkey).await?;
+    let extension_data = token_account.get_extension::<ConfidentialTransferAccount>()?;
+    let credits = u64::from(extension_data.pending_balance_credit_counter);
+    let threshold = options.apply_threshold.clamp(
+        1,
+        u64::from(extension_data.maximum_pending_balance_credit_counter).max(1),
+    );
+    if credits < threshold {
+        return Ok(None);
+    }
+    let signature =
+        apply_pending_balance(token, owner, ata_pubkey, elgamal_keypair, aes_key).await?;
+    debug!(credits, %signature, "Applied pending balance");
+    Ok(Some(AppliedPendingBalance { credits, signature }))
+}
@@ -0,0 +1,200 @@
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use solana_client::{
+    nonblocking::rpc_client::RpcClient, rpc_client::GetConfirmedSignaturesForAddress2Config,
+};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use std::{
+    fmt::Write,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{sync::mpsc, time::sleep};
+use tracing::{debug, warn};
+
+use crate::{client::signature_history_commitment, watch::ReceivedCredits};
+
+/// Header carrying the signature of a webhook payload, `sha256=<HEX>`: the HMAC-SHA256 of the
+/// request body under the webhook secret, as computed by [`sign`].
+pub const SIGNATURE_HEADER: &str = "X-Confidential-Transfer-Signature";
+//Attempts at delivering a notification before it is dropped, and the delay between them
+const DELIVERY_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+//Latest transactions of the account searched for the one crediting it
+const SIGNATURE_LOOKUP_LIMIT: usize = 10;
+
+/// Payload posted to a [`Webhook`] when a watched account receives confidential credits.
+///
+/// The amount is not included: it is encrypted in the pending balance, and only the account's
+/// keys can decrypt it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CreditNotification {
+    /// Always `"credit"`.
+    pub event: &'static str,
+    /// Token account credited.
+    pub account: String,
+    /// Mint of the account.
+    pub mint: String,
+    /// Credits received since the previous notification.
+    pub counter_delta: u64,
+    /// Pending credit counter after them.
+    pub pending_credit_counter: u64,
+    /// Slot the credits were seen at.
+    pub slot: u64,
+    /// Transaction crediting the account in that slot, if found.
+    pub signature: Option<String>,
+    /// Unix time the notification was sent at, for receivers to reject replays.
+    pub timestamp: u64,
+}
+
+impl CreditNotification {
+    /// Notification of `credits` received by `account` of `mint` in the transaction
+    /// `signature`, timestamped now.
+    pub fn new(
+        account: &Pubkey,
+        mint: &Pubkey,
+        credits: ReceivedCredits,
+        signature: Option<Signature>,
+    ) -> Self {
+        Self {
+            event: "credit",
+            account: account.to_string(),
+            mint: mint.to_string(),
+            counter_delta: credits.counter_delta,
+            pending_credit_counter: credits.pending_credits,
+            slot: credits.slot,
+            signature: signature.map(|signature| signature.to_string()),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+}
+
+/// Endpoint notified of incoming credits, with the secret its payloads are signed with.
+#[derive(Clone)]
+pub struct Webhook {
+    url: String,
+    secret: Arc<[u8]>,
+    agent: ureq::Agent,
+}
+
+impl Webhook {
+    /// Webhook posting to `url`, signing with `secret`.
+    pub fn new(url: impl Into<String>, secret: &[u8]) -> Self {
+        Self {
+            url: url.into(),
+            secret: secret.into(),
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(10))
+                .build(),
+        }
+    }
+
+    /// Posts `notification` as JSON, signed in [`SIGNATURE_HEADER`], retrying a failed delivery
+    /// a few times; the receiver must answer a 2xx status.
+    pub async fn post(&self, notification: &CreditNotification) -> Result<()> {
+        let body = serde_json::to_vec(notification)?;
+        let signature = sign(&self.secret, &body);
+        let mut attempt = 1;
+        loop {
+            let (agent, url, body, signature) = (
+                self.agent.clone(),
+                self.url.clone(),
+                body.clone(),
+                signature.clone(),
+            );
+            let posted = tokio::task::spawn_blocking(move || {
+                agent
+                    .post(&url)
+                    .set("Content-Type", "application/json")
+                    .set(SIGNATURE_HEADER, &signature)
+                    .send_bytes(&body)
+                    .with_context(|| format!("Webhook delivery to {url} failed"))
+            })
+            .await?;
+            match posted {
+                Ok(_) => return Ok(()),
+                Err(err) if attempt < DELIVERY_ATTEMPTS => {
+                    debug!(attempt, "{err:#}; retrying");
+                    attempt += 1;
+                    sleep(RETRY_DELAY).await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Spawns the delivery of the credits of `account` of `mint` sent to the returned channel,
+    /// in order, e.g. from the `on_credit` callback of
+    /// [`watch_and_apply`](crate::watch::watch_and_apply), so the watcher never waits for the
+    /// receiver. The transaction of each credit is looked up through `rpc_client`. Failed
+    /// deliveries are logged and dropped.
+    pub fn spawn_notifier(
+        self,
+        rpc_client: Arc<RpcClient>,
+        account: Pubkey,
+        mint: Pubkey,
+    ) -> mpsc::UnboundedSender<ReceivedCredits> {
+        let (credits, mut received) = mpsc::unbounded_channel::<ReceivedCredits>();
+        tokio::spawn(async move {
+            while let Some(credit) = received.recv().await {
+                let signature = credit_signature(&rpc_client, &account, credit.slot)
+                    .await
+                    .unwrap_or_else(|err| {
+                        warn!("Unable to look up the transaction crediting {account}: {err:#}");
+                        None
+                    });
+                let notification = CreditNotification::new(&account, &mint, credit, signature);
+                match self.post(&notification).await {
+                    Ok(()) => debug!(slot = credit.slot, "Webhook notified"),
+                    Err(err) => warn!(
+                        "Dropped the credit notification of slot {}: {err:#}",
+                        credit.slot
+                    ),
+                }
+            }
+        });
+        credits
+    }
+}
+
+/// Signature of `body` under `secret`, as sent in [`SIGNATURE_HEADER`]: receivers recompute it
+/// over the raw body to authenticate a payload.
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .fold(String::from("sha256="), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+// Latest successful transaction touching `account` in `slot`
+async fn credit_signature(
+    rpc_client: &RpcClient,
+    account: &Pubkey,
+    slot: u64,
+) -> Result<Option<Signature>> {
+    let statuses = rpc_client
+        .get_signatures_for_address_with_config(
+            account,
+            GetConfirmedSignaturesForAddress2Config {
+                limit: Some(SIGNATURE_LOOKUP_LIMIT),
+                commitment: Some(signature_history_commitment(rpc_client)),
+                ..GetConfirmedSignaturesForAddress2Config::default()
+            },
+        )
+        .await?;
+    Ok(statuses
+        .into_iter()
+        .find(|status| status.slot == slot && status.err.is_none())
+        .map(|status| status.signature.parse())
+        .transpose()?)
+}
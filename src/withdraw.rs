@@ -0,0 +1,463 @@
+use anyhow::{Result, anyhow, bail};
+use confidential_transfer_core::{
+    balance::decrypt_available_balance,
+    instructions::{close_context_account_instruction, context_account_instructions},
+    proofs::{verify_proof, withdraw_proofs},
+};
+use solana_sdk::{
+    account::from_account,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    rent::Rent,
+    signature::{Keypair, Signature},
+    signer::Signer,
+    sysvar,
+};
+use spl_token_client::spl_token_2022::{
+    extension::{
+        BaseStateWithExtensions,
+        confidential_transfer::{
+            ConfidentialTransferAccount, account_info::WithdrawAccountInfo,
+            instruction::withdraw as withdraw_instruction,
+        },
+    },
+    id as token_2022_program_id,
+    solana_zk_sdk::encryption::{
+        auth_encryption::AeKey, elgamal::ElGamalKeypair, pod::auth_encryption::PodAeCiphertext,
+    },
+};
+use spl_token_confidential_transfer_proof_generation::withdraw::WithdrawProofData;
+use std::time::{Duration, Instant};
+use tracing::{debug, instrument};
+
+use crate::{
+    TokenClient,
+    authority::Authority,
+    concurrent::ConcurrentSends,
+    keygen,
+    placement::{
+        Packing, ProofCost, ProofPlacement, compute_unit_limit_instruction, memo_size,
+        pack_instructions, plan_proof_placements, proof_location, send_packed_transactions,
+    },
+    proofs::spawn_proof_generation,
+    utils::{attach_memo, into_signature},
+};
+
+/// Signatures and proof accounts produced by [`withdraw`].
+#[derive(Debug, Clone)]
+pub struct WithdrawResult {
+    /// Withdrawn amount in base units.
+    pub amount: u64,
+    /// Context state account holding the verified equality proof, unless the proof was verified
+    /// in the withdraw transaction.
+    pub equality_proof_account: Option<Pubkey>,
+    /// Context state account holding the verified range proof, unless the proof was verified in
+    /// the withdraw transaction.
+    pub range_proof_account: Option<Pubkey>,
+    /// Signature of the equality proof account creation.
+    pub equality_proof_signature: Option<Signature>,
+    /// Signature of the range proof account creation.
+    pub range_proof_signature: Option<Signature>,
+    /// Signature of the withdraw instruction.
+    pub withdraw_signature: Signature,
+    /// Signature closing the equality proof account.
+    pub close_equality_signature: Option<Signature>,
+    /// Signature closing the range proof account.
+    pub close_range_signature: Option<Signature>,
+    /// Time spent generating the proof data.
+    pub proof_generation_time: Duration,
+}
+
+impl WithdrawResult {
+    /// Signatures of every transaction sent, in order.
+    pub fn signatures(&self) -> Vec<Signature> {
+        [
+            self.equality_proof_signature,
+            self.range_proof_signature,
+            Some(self.withdraw_signature),
+            self.close_equality_signature,
+            self.close_range_signature,
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+/// Withdraws `withdraw_amount` from the available confidential balance back to normal tokens.
+///
+/// The equality and range proofs are verified in the withdraw transaction when they fit, and into
+/// context state accounts otherwise, as chosen by
+/// [`plan_proof_placements`](crate::placement::plan_proof_placements); context state accounts
+/// are referenced by the withdraw instruction and closed afterwards to recover rent. `memo` is
+/// attached to the withdraw transaction only.
+///
+/// With [`Packing::Packed`], the account creations, proof verifications, the withdraw and the
+/// closes are sent in as few transactions as they fit in; otherwise each takes its own. The
+/// signatures of the result are then those of the transactions holding each step, which can
+/// repeat.
+#[allow(clippy::too_many_arguments)]
+pub async fn withdraw(
+    token: &TokenClient,
+    owner: &Authority,
+    ata_pubkey: &Pubkey,
+    withdraw_amount: u64,
+    decimals: u8,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+    memo: Option<&str>,
+    packing: Packing,
+) -> Result<WithdrawResult> {
+    withdraw_with_proof_accounts(
+        token,
+        owner,
+        ata_pubkey,
+        withdraw_amount,
+        decimals,
+        elgamal_keypair,
+        aes_key,
+        memo,
+        packing,
+        &[keygen::new_keypair(), keygen::new_keypair()],
+    )
+    .await
+}
+
+/// Same as [`withdraw`], with the equality and range proof context state accounts, if any, created
+/// at the addresses of `proof_keypairs`.
+///
+/// Lets callers record the proof accounts before they are created, so they can still be closed
+/// if the withdraw fails halfway.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip_all, fields(ata = %ata_pubkey, amount = withdraw_amount))]
+pub async fn withdraw_with_proof_accounts(
+    token: &TokenClient,
+    owner: &Authority,
+    ata_pubkey: &Pubkey,
+    withdraw_amount: u64,
+    decimals: u8,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+    memo: Option<&str>,
+    packing: Packing,
+    proof_keypairs: &[Keypair; 2],
+) -> Result<WithdrawResult> {
+    //Proof accounts cannot be managed by a multisig, so they belong to the proof authority
+    let proof_authority = owner.proof_authority();
+    let token_account = token.get_account_info(ata_pubkey).await?;
+    let extension_data = token_account.get_extension::<ConfidentialTransferAccount>()?;
+    //Confidential transfer extension information needed to construct a withdraw instruction
+    let withdraw_account = WithdrawAccountInfo::new(extension_data);
+    //Keypairs of the proof accounts
+    let [
+        equality_proof_context_state_keypair,
+        range_proof_context_state_keypair,
+    ] = proof_keypairs;
+    let equality_proof_context_state_pubkey = equality_proof_context_state_keypair.pubkey();
+    let range_proof_context_state_pubkey = range_proof_context_state_keypair.pubkey();
+    //Withdraw proof data, generated off the async runtime
+    let (elgamal_keypair_owned, aes_key_owned) = (elgamal_keypair.clone(), aes_key.clone());
+    let (
+        WithdrawProofData {
+            equality_proof_data,
+            range_proof_data,
+        },
+        proof_generation_time,
+    ) = spawn_proof_generation(move || {
+        let started = Instant::now();
+        let proof_data = withdraw_proofs(
+            &withdraw_account,
+            withdraw_amount,
+            &elgamal_keypair_owned,
+            &aes_key_owned,
+        )?;
+        Ok((proof_data, started.elapsed()))
+    })
+    .await?;
+    debug!(elapsed = ?proof_generation_time, "Generated withdraw proof data");
+    //Catch invalid proofs before paying for any of the accounts
+    verify_proof(&equality_proof_data)?;
+    verify_proof(&range_proof_data)?;
+    //Available balance the withdraw leaves, encrypted under the AES key
+    let new_decryptable_available_balance: PodAeCiphertext = withdraw_account
+        .new_decryptable_available_balance(withdraw_amount, aes_key)?
+        .into();
+    let multisig_signers = owner.multisig_signers();
+    let multisig_signers = multisig_signers.iter().collect::<Vec<_>>();
+    //Withdraw instruction, followed by the verification of the proofs placed in it
+    let withdraw_instructions = |placements: &[ProofPlacement]| -> Result<Vec<Instruction>> {
+        let mut inline_proofs = 0;
+        Ok(withdraw_instruction(
+            &token_2022_program_id(),
+            ata_pubkey,
+            token.get_address(),
+            withdraw_amount,
+            decimals,
+            &new_decryptable_available_balance,
+            &owner.pubkey(),
+            &multisig_signers,
+            proof_location(
+                placements[0],
+                &equality_proof_data,
+                &equality_proof_context_state_pubkey,
+                &mut inline_proofs,
+            )?,
+            proof_location(
+                placements[1],
+                &range_proof_data,
+                &range_proof_context_state_pubkey,
+                &mut inline_proofs,
+            )?,
+        )?)
+    };
+    //Proofs that fit are verified in the withdraw transaction, saving their accounts' round trips;
+    //packed transactions also carry a compute unit limit instruction
+    let placements = plan_proof_placements(
+        &[
+            ProofCost::of(&equality_proof_data),
+            ProofCost::of(&range_proof_data),
+        ],
+        memo_size(memo),
+        |placements: &[ProofPlacement]| {
+            let mut instructions = withdraw_instructions(placements)?;
+            if let Packing::Packed { nonces, .. } = &packing {
+                instructions.push(compute_unit_limit_instruction());
+                if let Some(nonces) = nonces {
+                    instructions.insert(0, nonces.advance_instruction(0));
+                }
+            }
+            Ok(instructions)
+        },
+    )?;
+    let [equality_proof_placement, range_proof_placement] = [placements[0], placements[1]];
+    if let Packing::Packed {
+        fee_payer,
+        lookup_table,
+        bundle,
+        nonces,
+    } = packing
+    {
+        let rent = from_account::<Rent, _>(&token.get_account(sysvar::rent::id()).await?)
+            .ok_or_else(|| anyhow!("Invalid rent sysvar"))?;
+        let context_accounts = [
+            equality_proof_context_state_pubkey,
+            range_proof_context_state_pubkey,
+        ];
+        let context_instructions = [
+            match equality_proof_placement {
+                ProofPlacement::InstructionData => None,
+                ProofPlacement::ContextAccount => Some(context_account_instructions(
+                    &fee_payer,
+                    &context_accounts[0],
+                    &proof_authority.pubkey(),
+                    &equality_proof_data,
+                    &rent,
+                )?),
+            },
+            match range_proof_placement {
+                ProofPlacement::InstructionData => None,
+                ProofPlacement::ContextAccount => Some(context_account_instructions(
+                    &fee_payer,
+                    &context_accounts[1],
+                    &proof_authority.pubkey(),
+                    &range_proof_data,
+                    &rent,
+                )?),
+            },
+        ];
+        //Account creations and verifications, the withdraw, then the closes, each a group
+        let mut groups = vec![];
+        let mut verify_groups = [None; 2];
+        for (index, instructions) in context_instructions.into_iter().enumerate() {
+            if let Some([create, verify]) = instructions {
+                groups.push(vec![create]);
+                verify_groups[index] = Some(groups.len());
+                groups.push(vec![verify]);
+            }
+        }
+        let operation = groups.len();
+        groups.push(withdraw_instructions(&placements)?);
+        let mut close_groups = [None; 2];
+        for index in 0..2 {
+            if verify_groups[index].is_some() {
+                close_groups[index] = Some(groups.len());
+                groups.push(vec![close_context_account_instruction(
+                    &context_accounts[index],
+                    &proof_authority.pubkey(),
+                )]);
+            }
+        }
+        let packed = pack_instructions(
+            &groups,
+            operation,
+            &fee_payer,
+            memo,
+            lookup_table.as_ref().map(|table| &table.account),
+            nonces.as_ref(),
+        )?;
+        let mut signers = owner
+            .signers()
+            .iter()
+            .map(|signer| signer.as_ref())
+            .collect::<Vec<&dyn Signer>>();
+        signers.extend([
+            proof_authority.as_ref(),
+            equality_proof_context_state_keypair,
+            range_proof_context_state_keypair,
+        ]);
+        let signatures = send_packed_transactions(
+            token,
+            &packed,
+            memo,
+            &signers,
+            lookup_table.as_ref(),
+            bundle.as_ref(),
+            nonces.as_ref(),
+        )
+        .await?;
+        debug!(?signatures, "Packed withdraw confirmed");
+        let signature =
+            |group: Option<usize>| group.map(|group| signatures[packed.group_transactions[group]]);
+        return Ok(WithdrawResult {
+            amount: withdraw_amount,
+            equality_proof_account: verify_groups[0].map(|_| context_accounts[0]),
+            range_proof_account: verify_groups[1].map(|_| context_accounts[1]),
+            equality_proof_signature: signature(verify_groups[0]),
+            range_proof_signature: signature(verify_groups[1]),
+            withdraw_signature: signatures[packed.operation_transaction],
+            close_equality_signature: signature(close_groups[0]),
+            close_range_signature: signature(close_groups[1]),
+            proof_generation_time,
+        });
+    }
+    //The proof accounts are independent of each other, so they are created concurrently
+    let mut creations = ConcurrentSends::new();
+    //Generate equality proof account
+    creations.push(async {
+        let ProofPlacement::ContextAccount = equality_proof_placement else {
+            return anyhow::Ok(None);
+        };
+        let signature = into_signature(
+            token
+                .confidential_transfer_create_context_state_account(
+                    &equality_proof_context_state_pubkey, //Public key for the equality proof account
+                    &proof_authority.pubkey(), //Authority that can manage the account
+                    &equality_proof_data,      //Proof data for the equality proof
+                    false, //False:combine account creation+proof verification in one transaction
+                    &[equality_proof_context_state_keypair], //Signer of the new account
+                )
+                .await?,
+        )?;
+        debug!(account = %equality_proof_context_state_pubkey, %signature, "Created equality proof account");
+        Ok(Some(signature))
+    });
+    //Generate range proof account
+    creations.push(async {
+        let ProofPlacement::ContextAccount = range_proof_placement else {
+            return anyhow::Ok(None);
+        };
+        let signature = into_signature(
+            token
+                .confidential_transfer_create_context_state_account(
+                    &range_proof_context_state_pubkey, //Public key for the range proof account
+                    &proof_authority.pubkey(),         //Authority that can manage the account
+                    &range_proof_data,                 //Proof data for the range proof
+                    false, //False:combine account creation+proof verification in one transaction
+                    &[range_proof_context_state_keypair], //Signer of the new account
+                )
+                .await?,
+        )?;
+        debug!(account = %range_proof_context_state_pubkey, %signature, "Created range proof account");
+        Ok(Some(signature))
+    });
+    let [equality_proof_signature, range_proof_signature] =
+        <[_; 2]>::try_from(creations.join().await?).expect("one signature per proof account");
+    //Perform the withdraw from confidential state back to normal tokens
+    attach_memo(token, memo);
+    let withdraw_signature = into_signature(
+        token
+            .process_ixs(&withdraw_instructions(&placements)?, owner.signers())
+            .await?,
+    )?;
+    debug!(signature = %withdraw_signature, "Withdraw confirmed");
+    //Close the context state accounts to recover rent, concurrently as well
+    let mut closes = ConcurrentSends::new();
+    for (placement, context_state_pubkey) in [
+        (
+            equality_proof_placement,
+            equality_proof_context_state_pubkey,
+        ),
+        (range_proof_placement, range_proof_context_state_pubkey),
+    ] {
+        closes.push(async move {
+            let ProofPlacement::ContextAccount = placement else {
+                return anyhow::Ok(None);
+            };
+            Ok(Some(into_signature(
+                token
+                    .confidential_transfer_close_context_state_account(
+                        &context_state_pubkey,     //Public key of the proof account
+                        &proof_authority.pubkey(), //Destination to receive recovered rent
+                        &proof_authority.pubkey(), //Authority that can close the account
+                        &[proof_authority],        //Signer(authority)
+                    )
+                    .await?,
+            )?))
+        });
+    }
+    let [close_equality_signature, close_range_signature] =
+        <[_; 2]>::try_from(closes.join().await?).expect("one signature per proof account");
+    Ok(WithdrawResult {
+        amount: withdraw_amount,
+        equality_proof_account: equality_proof_signature
+            .map(|_| equality_proof_context_state_pubkey),
+        range_proof_account: range_proof_signature.map(|_| range_proof_context_state_pubkey),
+        equality_proof_signature,
+        range_proof_signature,
+        withdraw_signature,
+        close_equality_signature,
+        close_range_signature,
+        proof_generation_time,
+    })
+}
+
+/// Withdraws the whole available confidential balance of `ata_pubkey` back to normal tokens.
+///
+/// The available balance is decrypted with the AES key and withdrawn with [`withdraw`], so the
+/// proofs leave an available balance of exactly zero. The pending balance is not included; apply
+/// it first to withdraw it as well.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip_all, fields(ata = %ata_pubkey))]
+pub async fn withdraw_all(
+    token: &TokenClient,
+    owner: &Authority,
+    ata_pubkey: &Pubkey,
+    decimals: u8,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+    memo: Option<&str>,
+    packing: Packing,
+) -> Result<WithdrawResult> {
+    let token_account = token.get_account_info(ata_pubkey).await?;
+    let available_balance = decrypt_available_balance(
+        token_account.get_extension::<ConfidentialTransferAccount>()?,
+        aes_key,
+    )
+    .ok_or_else(|| anyhow!("Failed to decrypt the available balance of {ata_pubkey}"))?;
+    if available_balance == 0 {
+        bail!("Nothing to withdraw: the available confidential balance of {ata_pubkey} is zero");
+    }
+    debug!(available_balance, "Decrypted available balance");
+    withdraw(
+        token,
+        owner,
+        ata_pubkey,
+        available_balance,
+        decimals,
+        elgamal_keypair,
+        aes_key,
+        memo,
+        packing,
+    )
+    .await
+}
@@ -0,0 +1,93 @@
+//! Amounts given on the command line: parsing of token and raw amounts, their base units under
+//! the decimals of a mint, and the formatting of base units back into token amounts.
+
+use confidential_transfer::amount::{self, Amount};
+use proptest::prelude::*;
+
+fn base_units(value: &str, decimals: u8) -> u64 {
+    value
+        .parse::<Amount>()
+        .unwrap()
+        .to_base_units(decimals)
+        .unwrap()
+}
+
+fn error(value: &str, decimals: u8) -> String {
+    value
+        .parse::<Amount>()
+        .and_then(|amount| amount.to_base_units(decimals))
+        .unwrap_err()
+        .to_string()
+}
+
+#[test]
+fn token_amounts_scale_by_the_decimals_of_the_mint() {
+    assert_eq!(base_units("12.5", 9), 12_500_000_000);
+    assert_eq!(base_units("12.5", 1), 125);
+    assert_eq!(base_units("12", 0), 12);
+    assert_eq!(base_units("0.000000001", 9), 1);
+    assert_eq!(base_units(".5", 2), 50);
+    assert_eq!(base_units("1_000.25", 2), 100_025);
+    //Trailing zeros past the decimals of the mint are not a finer amount
+    assert_eq!(base_units("1.2300", 2), 123);
+    assert_eq!(Amount::whole(100).to_base_units(6).unwrap(), 100_000_000);
+}
+
+#[test]
+fn raw_amounts_are_base_units_whatever_the_decimals() {
+    for value in ["12_500_000 raw", "12500000raw", " 12500000   raw "] {
+        assert_eq!(value.parse::<Amount>().unwrap(), Amount::Raw(12_500_000));
+        assert_eq!(base_units(value, 9), 12_500_000);
+        assert_eq!(base_units(value, 0), 12_500_000);
+    }
+    assert_eq!(Amount::Raw(42).to_string(), "42 raw");
+    assert_eq!("1_000.50".parse::<Amount>().unwrap().to_string(), "1000.50");
+}
+
+#[test]
+fn malformed_amounts_are_refused() {
+    for value in [
+        "", ".", "raw", "-1", "+1", "1e9", "1,5", "1.2.3", "12.5 raw", "0x10", "ten",
+    ] {
+        assert!(value.parse::<Amount>().is_err(), "{value} was accepted");
+    }
+}
+
+#[test]
+fn amounts_finer_than_the_mint_or_too_large_are_refused() {
+    assert_eq!(
+        error("0.001", 2),
+        "Amount 0.001 is finer than the 2 decimals of the mint"
+    );
+    assert_eq!(
+        error("18446744074", 9),
+        "Amount 18446744074 is above 18446744073.709551615, the largest amount of a mint with 9 decimals"
+    );
+    assert_eq!(base_units("18446744073.709551615", 9), u64::MAX);
+    assert_eq!(
+        error("18446744073709551616 raw", 0),
+        "Amount 18446744073709551616 raw does not fit in 64 bits"
+    );
+    //Decimals alone overflow without any digit past the first
+    assert!(error("1", 20).contains("the largest amount of a mint with 20 decimals"));
+    assert_eq!(base_units("0", 255), 0);
+}
+
+#[test]
+fn base_units_format_as_trimmed_token_amounts() {
+    assert_eq!(amount::format(12_500_000_000, 9), "12.5");
+    assert_eq!(amount::format(1, 9), "0.000000001");
+    assert_eq!(amount::format(100, 2), "1");
+    assert_eq!(amount::format(0, 9), "0");
+    assert_eq!(amount::format(123, 0), "123");
+    assert_eq!(amount::format(u64::MAX, 9), "18446744073.709551615");
+    assert_eq!(amount::format(5, 20), "0.00000000000000000005");
+}
+
+proptest! {
+    #[test]
+    fn formatted_amounts_parse_back(base: u64, decimals in 0u8..=24) {
+        let formatted = amount::format(base, decimals);
+        prop_assert_eq!(base_units(&formatted, decimals), base);
+    }
+}
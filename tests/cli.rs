@@ -0,0 +1,47 @@
+//! Shell completions and man pages of `completions` and `mangen`, generated by the binary without
+//! a config file or cluster.
+
+use solana_sdk::pubkey::Pubkey;
+use std::process::Command;
+
+fn run(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_confidential-transfer"))
+        .args(args)
+        //No config file nor RPC endpoint is read
+        .env("HOME", "/nonexistent")
+        .args(["--url", "http://127.0.0.1:1"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn completions_cover_subcommands_and_their_flags() {
+    let bash = run(&["completions", "bash"]);
+    assert!(bash.contains("complete -F _confidential__transfer"));
+    for word in ["watch-and-apply", "--mint", "--out-dir"] {
+        assert!(
+            bash.contains(word),
+            "{word} missing from the bash completions"
+        );
+    }
+    assert!(run(&["completions", "zsh"]).starts_with("#compdef confidential-transfer"));
+    assert!(run(&["completions", "fish"]).contains("complete -c confidential-transfer"));
+}
+
+#[test]
+fn man_pages_are_written_per_subcommand() {
+    let page = run(&["mangen"]);
+    assert!(page.contains(".TH confidential-transfer 1"));
+    assert!(page.contains("confidential\\-transfer\\-transfer(1)"));
+
+    let dir = std::env::temp_dir().join(format!("mangen-{}", Pubkey::new_unique()));
+    run(&["mangen", "--out-dir", dir.to_str().unwrap()]);
+    let serve = std::fs::read_to_string(dir.join("confidential-transfer-serve.1")).unwrap();
+    assert!(serve.contains(".TH confidential-transfer-serve 1"));
+    assert!(serve.contains("\\-\\-bind"));
+    assert!(dir.join("confidential-transfer.1").exists());
+    assert!(!dir.join("confidential-transfer-help.1").exists());
+    std::fs::remove_dir_all(dir).unwrap();
+}
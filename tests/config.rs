@@ -0,0 +1,135 @@
+//! Environment variable layer of the config: `CT_*` variables over the config file, and the
+//! values left to the file.
+
+use confidential_transfer::config::{Config, env_var};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+use std::{
+    collections::HashMap,
+    num::{NonZeroU32, NonZeroUsize},
+    path::PathBuf,
+    time::Duration,
+};
+
+fn file_config() -> Config {
+    let dir = std::env::temp_dir().join(format!("config-{}", Pubkey::new_unique()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.toml");
+    std::fs::write(
+        &path,
+        r#"
+rpc_url = "devnet"
+commitment = "finalized"
+keypair_path = "/keys/file.json"
+decimals = 6
+
+[retry]
+max_retries = 2
+budget = 10
+
+[rate_limit]
+max_in_flight = 4
+"#,
+    )
+    .unwrap();
+    let config = Config::load(Some(&path)).unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+    config
+}
+
+fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let vars: HashMap<String, String> = vars
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    move |name| vars.get(name).cloned()
+}
+
+#[test]
+fn variables_override_the_file() {
+    let mint = Pubkey::new_unique();
+    let mut config = file_config();
+    config
+        .apply_env(env(&[
+            ("CT_RPC_URL", "testnet"),
+            ("CT_COMMITMENT", "processed"),
+            ("CT_KEYPAIR", "/keys/env.json"),
+            ("SOLANA_KEYPAIR", "/keys/solana.json"),
+            ("CT_MINT", &mint.to_string()),
+            ("CT_DECIMALS", "9"),
+            ("CT_INITIAL_BACKOFF_MS", "100"),
+            ("CT_RETRY_BUDGET", "3"),
+            ("CT_REQUESTS_PER_SECOND", "20"),
+        ]))
+        .unwrap();
+
+    assert_eq!(config.rpc_url(), "https://api.testnet.solana.com");
+    assert_eq!(config.ws_url(), "wss://api.testnet.solana.com");
+    assert_eq!(config.commitment().unwrap(), CommitmentConfig::processed());
+    assert_eq!(
+        config.keypair_path().unwrap(),
+        PathBuf::from("/keys/env.json")
+    );
+    assert_eq!(config.mint().unwrap(), Some(mint));
+    assert_eq!(config.decimals(), 9);
+    let retry = config.retry_policy().unwrap();
+    assert_eq!(retry.initial_backoff, Duration::from_millis(100));
+    assert_eq!(retry.budget, 3);
+    //Settings without a variable keep the values of the file
+    assert_eq!(retry.max_retries, 2);
+    let rate_limit = config.rate_limit().unwrap();
+    assert_eq!(rate_limit.max_in_flight, NonZeroUsize::new(4));
+    assert_eq!(rate_limit.requests_per_second, NonZeroU32::new(20));
+}
+
+#[test]
+fn unset_and_empty_variables_keep_the_file() {
+    let mut config = file_config();
+    config
+        .apply_env(env(&[("CT_RPC_URL", ""), ("CT_DECIMALS", "")]))
+        .unwrap();
+
+    assert_eq!(config.rpc_url(), "https://api.devnet.solana.com");
+    assert_eq!(config.commitment().unwrap(), CommitmentConfig::finalized());
+    assert_eq!(
+        config.keypair_path().unwrap(),
+        PathBuf::from("/keys/file.json")
+    );
+    assert_eq!(config.decimals(), 6);
+
+    config
+        .apply_env(env(&[("SOLANA_KEYPAIR", "/keys/solana.json")]))
+        .unwrap();
+    assert_eq!(
+        config.keypair_path().unwrap(),
+        PathBuf::from("/keys/solana.json")
+    );
+}
+
+#[test]
+fn invalid_numbers_are_refused() {
+    for (name, value) in [
+        ("CT_DECIMALS", "nine"),
+        ("CT_MAX_RETRIES", "-1"),
+        ("CT_MAX_IN_FLIGHT", "1.5"),
+    ] {
+        let err = file_config().apply_env(env(&[(name, value)])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("Invalid {name} environment variable: {value}")
+        );
+    }
+}
+
+#[test]
+fn earlier_names_are_read_when_unset() {
+    //Names of this test alone; the other tests of this file do not read the process environment
+    unsafe {
+        std::env::set_var("CONFIDENTIAL_TRANSFER_ALIAS_TEST_OLD", "old");
+        std::env::set_var("CONFIDENTIAL_TRANSFER_ALIAS_TEST_BOTH", "old");
+        std::env::set_var("CT_ALIAS_TEST_BOTH", "new");
+    }
+
+    assert_eq!(env_var("CT_ALIAS_TEST_OLD").as_deref(), Some("old"));
+    assert_eq!(env_var("CT_ALIAS_TEST_BOTH").as_deref(), Some("new"));
+    assert_eq!(env_var("CT_ALIAS_TEST_UNSET"), None);
+}
@@ -0,0 +1,99 @@
+//! Instructions built by account configuration, checked against a mock program client.
+
+use confidential_transfer::{
+    authority::Authority,
+    client::RpcSender,
+    mint,
+    mock::{MockProgramClient, confidential_mint_account},
+};
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
+use spl_token_client::{
+    client::ProgramClient,
+    spl_token_2022::{self, solana_zk_sdk::zk_elgamal_proof_program},
+    token::Token,
+};
+use std::sync::Arc;
+
+#[tokio::test]
+async fn create_configure_ata_creates_reallocates_and_configures() {
+    let payer = Arc::new(Keypair::new());
+    let mint = Pubkey::new_unique();
+    let client = Arc::new(
+        MockProgramClient::new()
+            .with_account(mint, confidential_mint_account(&payer.pubkey(), 2).unwrap()),
+    );
+    let program_client: Arc<dyn ProgramClient<RpcSender>> = client.clone();
+    let token = Token::new(
+        program_client,
+        &spl_token_2022::id(),
+        &mint,
+        Some(2),
+        payer.clone(),
+    );
+    let owner_keypair = Arc::new(Keypair::new());
+    let owner = Authority::single(owner_keypair.clone());
+
+    let configured = mint::create_configure_ata(&token, &payer.pubkey(), &owner)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        configured.ata,
+        mint::get_ata(&owner_keypair.pubkey(), &mint)
+    );
+    assert_eq!(client.transactions().len(), 1);
+    let instructions = client.instructions();
+    let programs = instructions
+        .iter()
+        .map(|instruction| instruction.program_id)
+        .collect::<Vec<_>>();
+    //Account creation first, then the reallocation and configuration by Token-2022, followed by
+    //the verification of the pubkey validity proof in the same transaction
+    assert_eq!(
+        programs,
+        [
+            spl_associated_token_account::id(),
+            spl_token_2022::id(),
+            spl_token_2022::id(),
+            zk_elgamal_proof_program::id(),
+        ]
+    );
+    for instruction in &instructions[1..3] {
+        assert_eq!(instruction.accounts[0].pubkey, configured.ata);
+        assert!(instruction.accounts[0].is_writable);
+    }
+    //The owner signs the reallocation and the configuration; the payer pays
+    let transaction = &client.transactions()[0];
+    assert_eq!(transaction.message.account_keys[0], payer.pubkey());
+    assert!(
+        transaction
+            .message
+            .account_keys
+            .iter()
+            .enumerate()
+            .any(|(index, key)| *key == owner_keypair.pubkey()
+                && transaction.message.is_signer(index))
+    );
+}
+
+#[tokio::test]
+async fn create_configure_ata_fails_without_the_mint() {
+    let payer = Arc::new(Keypair::new());
+    let client = Arc::new(MockProgramClient::new());
+    let program_client: Arc<dyn ProgramClient<RpcSender>> = client.clone();
+    let token = Token::new(
+        program_client,
+        &spl_token_2022::id(),
+        &Pubkey::new_unique(),
+        Some(2),
+        payer.clone(),
+    );
+    let owner = Authority::single(Arc::new(Keypair::new()));
+
+    assert!(
+        mint::create_configure_ata(&token, &payer.pubkey(), &owner)
+            .await
+            .is_err()
+    );
+    assert!(client.transactions().is_empty());
+}
@@ -0,0 +1,345 @@
+//! Snapshots of the instructions sent by configure-account, deposit, withdraw and transfer, and of
+//! the configure transaction left to a browser wallet, byte encoding included, so an SDK bump
+//! changing the account order or the proof offsets fails here instead of on a cluster.
+//!
+//! Ciphertexts and proofs are random at every run, so they are zeroed and summarized; everything
+//! else is built from fixed keys. After an intended change, review the new snapshots with
+//! `cargo insta review`.
+
+use bytemuck::{Pod, Zeroable, bytes_of};
+use confidential_transfer::{
+    TokenClient,
+    authority::Authority,
+    client::RpcSender,
+    deposit, mint,
+    mock::{
+        MockProgramClient, confidential_mint_account, confidential_token_account,
+        transaction_instructions,
+    },
+    offline::missing_signers,
+    placement::Packing,
+    transfer, withdraw,
+};
+use confidential_transfer_core::derivation::SeedMessage;
+use solana_sdk::{
+    hash::Hash, instruction::Instruction, pubkey::Pubkey, signature::Keypair, signer::Signer,
+    sysvar,
+};
+use solana_sdk_ids::system_program;
+use spl_token_client::{
+    client::ProgramClient,
+    spl_token_2022::{
+        self,
+        extension::confidential_transfer::instruction::{
+            ApplyPendingBalanceData, ConfidentialTransferInstruction,
+            ConfigureAccountInstructionData, TransferInstructionData, WithdrawInstructionData,
+        },
+        instruction::{TokenInstruction, decode_instruction_data, decode_instruction_type},
+        solana_zk_sdk::{
+            encryption::{auth_encryption::AeKey, elgamal::ElGamalKeypair},
+            zk_elgamal_proof_program,
+        },
+    },
+    token::Token,
+};
+use std::{collections::HashMap, fmt::Write, sync::Arc};
+
+const DECIMALS: u8 = 2;
+
+// Mock cluster holding a confidential transfer mint, with the token client of its payer and the
+// names the snapshots give to the fixed addresses
+struct Fixture {
+    client: Arc<MockProgramClient>,
+    token: TokenClient,
+    payer: Arc<Keypair>,
+    owner: Arc<Keypair>,
+    names: HashMap<Pubkey, String>,
+}
+
+impl Fixture {
+    fn new() -> Self {
+        let payer = Arc::new(Keypair::new_from_array([1; 32]));
+        let owner = Arc::new(Keypair::new_from_array([2; 32]));
+        let mint = Pubkey::new_from_array([3; 32]);
+        let client = Arc::new(MockProgramClient::new().with_account(
+            mint,
+            confidential_mint_account(&payer.pubkey(), DECIMALS).unwrap(),
+        ));
+        let program_client: Arc<dyn ProgramClient<RpcSender>> = client.clone();
+        let token = Token::new(
+            program_client,
+            &spl_token_2022::id(),
+            &mint,
+            Some(DECIMALS),
+            payer.clone(),
+        );
+        let names = [
+            (payer.pubkey(), "payer"),
+            (owner.pubkey(), "owner"),
+            (mint, "mint"),
+            (mint::get_ata(&owner.pubkey(), &mint), "owner ata"),
+            (spl_token_2022::id(), "token-2022"),
+            (spl_associated_token_account::id(), "associated token"),
+            (zk_elgamal_proof_program::id(), "zk elgamal proof"),
+            (system_program::id(), "system"),
+            (sysvar::instructions::id(), "instructions sysvar"),
+        ]
+        .into_iter()
+        .map(|(address, name)| (address, name.to_string()))
+        .collect();
+        Self {
+            client,
+            token,
+            payer,
+            owner,
+            names,
+        }
+    }
+
+    fn owner_ata(&self) -> Pubkey {
+        mint::get_ata(&self.owner.pubkey(), self.token.get_address())
+    }
+
+    // Stages an account of `owner` holding `available_balance` confidentially, returning the
+    // keys it is configured with
+    fn stage_account(
+        &self,
+        address: Pubkey,
+        owner: &Keypair,
+        available_balance: u64,
+    ) -> (ElGamalKeypair, AeKey) {
+        let (elgamal_keypair, aes_key) =
+            mint::derive_keys(owner, &SeedMessage::AccountAddress, &address).unwrap();
+        self.client.set_account(
+            address,
+            confidential_token_account(
+                self.token.get_address(),
+                &owner.pubkey(),
+                &elgamal_keypair,
+                &aes_key,
+                available_balance,
+            )
+            .unwrap(),
+        );
+        (elgamal_keypair, aes_key)
+    }
+
+    // Fixed proof context state accounts, named
+    fn proof_keypairs<const N: usize>(&mut self) -> [Keypair; N] {
+        std::array::from_fn(|index| {
+            let keypair = Keypair::new_from_array([10 + index as u8; 32]);
+            self.names
+                .insert(keypair.pubkey(), format!("proof account {index}"));
+            keypair
+        })
+    }
+
+    // The instructions sent so far, one transaction after the other
+    fn snapshot(&self) -> String {
+        let mut snapshot = String::new();
+        for (index, transaction) in self.client.transactions().iter().enumerate() {
+            writeln!(snapshot, "transaction {index}").unwrap();
+            for instruction in transaction_instructions(transaction) {
+                self.write_instruction(&mut snapshot, &instruction);
+            }
+        }
+        snapshot
+    }
+
+    fn write_instruction(&self, snapshot: &mut String, instruction: &Instruction) {
+        writeln!(snapshot, "  {}", self.name(&instruction.program_id)).unwrap();
+        for account in &instruction.accounts {
+            writeln!(
+                snapshot,
+                "    {}{}{}",
+                self.name(&account.pubkey),
+                if account.is_signer { " signer" } else { "" },
+                if account.is_writable { " writable" } else { "" },
+            )
+            .unwrap();
+        }
+        writeln!(snapshot, "    data: {}", redacted_data(instruction)).unwrap();
+    }
+
+    fn name(&self, address: &Pubkey) -> String {
+        self.names
+            .get(address)
+            .cloned()
+            .unwrap_or_else(|| address.to_string())
+    }
+}
+
+// Hex data of `instruction`, with its ciphertexts zeroed and its proof data summarized
+fn redacted_data(instruction: &Instruction) -> String {
+    let data = &instruction.data;
+    //Verifications of proof data in the instruction, rather than in a context state account
+    if instruction.program_id == zk_elgamal_proof_program::id() && data.len() > 5 {
+        return format!("{:02x} <{} bytes of proof data>", data[0], data.len() - 1);
+    }
+    if instruction.program_id != spl_token_2022::id()
+        || data.first()
+            != TokenInstruction::ConfidentialTransferExtension
+                .pack()
+                .first()
+    {
+        return hex(data);
+    }
+    let extension_data = &data[1..];
+    let redacted = match decode_instruction_type(extension_data) {
+        Ok(ConfidentialTransferInstruction::ConfigureAccount) => zeroed(
+            extension_data,
+            |data: &mut ConfigureAccountInstructionData| {
+                data.decryptable_zero_balance = Zeroable::zeroed();
+            },
+        ),
+        Ok(ConfidentialTransferInstruction::Withdraw) => {
+            zeroed(extension_data, |data: &mut WithdrawInstructionData| {
+                data.new_decryptable_available_balance = Zeroable::zeroed();
+            })
+        }
+        Ok(ConfidentialTransferInstruction::Transfer) => {
+            zeroed(extension_data, |data: &mut TransferInstructionData| {
+                data.new_source_decryptable_available_balance = Zeroable::zeroed();
+                data.transfer_amount_auditor_ciphertext_lo = Zeroable::zeroed();
+                data.transfer_amount_auditor_ciphertext_hi = Zeroable::zeroed();
+            })
+        }
+        Ok(ConfidentialTransferInstruction::ApplyPendingBalance) => {
+            zeroed(extension_data, |data: &mut ApplyPendingBalanceData| {
+                data.new_decryptable_available_balance = Zeroable::zeroed();
+            })
+        }
+        _ => extension_data.to_vec(),
+    };
+    hex(&[&data[..1], &redacted].concat())
+}
+
+// `data`, the instruction type followed by a `T`, with the fields cleared by `zero` zeroed
+fn zeroed<T: Pod>(data: &[u8], zero: impl FnOnce(&mut T)) -> Vec<u8> {
+    let mut fields = *decode_instruction_data::<T>(data).unwrap();
+    zero(&mut fields);
+    [&data[..1], bytes_of(&fields)].concat()
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[tokio::test]
+async fn configure_account() {
+    let fixture = Fixture::new();
+
+    mint::create_configure_ata(
+        &fixture.token,
+        &fixture.payer.pubkey(),
+        &Authority::single(fixture.owner.clone()),
+    )
+    .await
+    .unwrap();
+
+    insta::assert_snapshot!(fixture.snapshot());
+}
+
+#[tokio::test]
+async fn configure_account_transaction_of_a_wallet() {
+    let fixture = Fixture::new();
+    let (elgamal_keypair, aes_key) = mint::derive_keys(
+        fixture.owner.as_ref(),
+        &SeedMessage::AccountAddress,
+        &fixture.owner_ata(),
+    )
+    .unwrap();
+
+    let transaction = mint::configure_ata_transaction(
+        &fixture.token,
+        &fixture.owner.pubkey(),
+        &elgamal_keypair,
+        &aes_key,
+        Hash::default(),
+    )
+    .await
+    .unwrap();
+
+    //The wallet pays for it and signs it alone
+    assert_eq!(missing_signers(&transaction), [fixture.owner.pubkey()]);
+    let mut snapshot = String::new();
+    for instruction in transaction_instructions(&transaction) {
+        fixture.write_instruction(&mut snapshot, &instruction);
+    }
+    insta::assert_snapshot!(snapshot);
+}
+
+#[tokio::test]
+async fn deposit() {
+    let fixture = Fixture::new();
+    fixture.stage_account(fixture.owner_ata(), &fixture.owner, 0);
+
+    deposit::deposit(
+        &fixture.token,
+        &Authority::single(fixture.owner.clone()),
+        &fixture.owner_ata(),
+        1_000,
+        DECIMALS,
+        None,
+    )
+    .await
+    .unwrap();
+
+    insta::assert_snapshot!(fixture.snapshot());
+}
+
+#[tokio::test]
+async fn withdraw() {
+    let mut fixture = Fixture::new();
+    let (elgamal_keypair, aes_key) =
+        fixture.stage_account(fixture.owner_ata(), &fixture.owner, 1_000);
+    let proof_keypairs = fixture.proof_keypairs();
+
+    withdraw::withdraw_with_proof_accounts(
+        &fixture.token,
+        &Authority::single(fixture.owner.clone()),
+        &fixture.owner_ata(),
+        400,
+        DECIMALS,
+        &elgamal_keypair,
+        &aes_key,
+        None,
+        Packing::Separate,
+        &proof_keypairs,
+    )
+    .await
+    .unwrap();
+
+    insta::assert_snapshot!(fixture.snapshot());
+}
+
+#[tokio::test]
+async fn transfer() {
+    let mut fixture = Fixture::new();
+    let (elgamal_keypair, aes_key) =
+        fixture.stage_account(fixture.owner_ata(), &fixture.owner, 1_000);
+    let recipient = Keypair::new_from_array([4; 32]);
+    let recipient_ata = mint::get_ata(&recipient.pubkey(), fixture.token.get_address());
+    fixture.stage_account(recipient_ata, &recipient, 0);
+    fixture
+        .names
+        .insert(recipient_ata, "recipient ata".to_string());
+    let proof_keypairs = fixture.proof_keypairs();
+
+    transfer::transfer_with_proof_accounts(
+        &fixture.token,
+        &Authority::single(fixture.owner.clone()),
+        &fixture.owner_ata(),
+        &recipient_ata,
+        400,
+        &elgamal_keypair,
+        &aes_key,
+        None,
+        Packing::Separate,
+        &proof_keypairs,
+    )
+    .await
+    .unwrap();
+
+    insta::assert_snapshot!(fixture.snapshot());
+}
@@ -0,0 +1,127 @@
+//! Escrow key sharing: the keys of the escrow account sealed to each party, and the escrow file.
+
+use confidential_transfer::{
+    escrow::{Escrow, KeyShare},
+    keygen,
+};
+use solana_sdk::pubkey::Pubkey;
+use spl_token_client::spl_token_2022::solana_zk_sdk::encryption::{
+    auth_encryption::AeKey, elgamal::ElGamalKeypair,
+};
+
+struct Fixture {
+    escrow: Escrow,
+    elgamal_keypair: ElGamalKeypair,
+    aes_key: AeKey,
+    party_elgamal_keypairs: [ElGamalKeypair; 2],
+}
+
+fn fixture() -> Fixture {
+    let account = Pubkey::new_unique();
+    let parties = [Pubkey::new_unique(), Pubkey::new_unique()];
+    let party_elgamal_keypairs = [keygen::new_elgamal_keypair(), keygen::new_elgamal_keypair()];
+    let (elgamal_keypair, aes_key) = (keygen::new_elgamal_keypair(), keygen::new_aes_key());
+    let shares = parties
+        .iter()
+        .zip(&party_elgamal_keypairs)
+        .map(|(party, keypair)| {
+            KeyShare::seal(
+                &account,
+                party,
+                keypair.pubkey(),
+                &elgamal_keypair,
+                &aes_key,
+            )
+            .unwrap()
+        })
+        .collect();
+    Fixture {
+        escrow: Escrow {
+            mint: Pubkey::new_unique(),
+            account,
+            multisig: Pubkey::new_unique(),
+            parties,
+            shares,
+        },
+        elgamal_keypair,
+        aes_key,
+        party_elgamal_keypairs,
+    }
+}
+
+#[test]
+fn both_parties_open_the_escrow_keys() {
+    let fixture = fixture();
+
+    for (party, keypair) in fixture
+        .escrow
+        .parties
+        .iter()
+        .zip(&fixture.party_elgamal_keypairs)
+    {
+        let (elgamal_keypair, aes_key) = fixture.escrow.open(party, keypair).unwrap();
+
+        assert_eq!(elgamal_keypair.pubkey(), fixture.elgamal_keypair.pubkey());
+        assert_eq!(
+            elgamal_keypair.secret().as_bytes(),
+            fixture.elgamal_keypair.secret().as_bytes()
+        );
+        assert_eq!(
+            <[u8; 16]>::from(aes_key),
+            <[u8; 16]>::from(fixture.aes_key.clone())
+        );
+    }
+}
+
+#[test]
+fn shares_open_only_for_their_party() {
+    let fixture = fixture();
+    let [creator, counterparty] = fixture.escrow.parties;
+
+    //The keys of one party do not open the share of the other
+    assert!(
+        fixture
+            .escrow
+            .open(&creator, &fixture.party_elgamal_keypairs[1])
+            .is_err()
+    );
+    assert!(
+        fixture
+            .escrow
+            .open(&Pubkey::new_unique(), &fixture.party_elgamal_keypairs[0])
+            .is_err()
+    );
+    //Nor does a share moved to another escrow account
+    let share = fixture.escrow.share(&counterparty).unwrap();
+    assert!(
+        share
+            .open(&Pubkey::new_unique(), &fixture.party_elgamal_keypairs[1])
+            .is_err()
+    );
+    let mut tampered = share.clone();
+    tampered.ciphertext[0] ^= 1;
+    assert!(
+        tampered
+            .open(&fixture.escrow.account, &fixture.party_elgamal_keypairs[1])
+            .is_err()
+    );
+}
+
+#[test]
+fn escrow_files_round_trip_without_overwriting() {
+    let fixture = fixture();
+    let dir = std::env::temp_dir().join(format!("escrow-{}", Pubkey::new_unique()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("escrow.json");
+
+    fixture.escrow.save(&path).unwrap();
+    let loaded = Escrow::load(&path).unwrap();
+
+    assert_eq!(loaded, fixture.escrow);
+    assert!(fixture.escrow.save(&path).is_err());
+    let (elgamal_keypair, _) = loaded
+        .open(&loaded.parties[0], &fixture.party_elgamal_keypairs[0])
+        .unwrap();
+    assert_eq!(elgamal_keypair.pubkey(), fixture.elgamal_keypair.pubkey());
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,75 @@
+//! Deposit addresses of exchange users: their derivation and the index attributing accounts to
+//! users.
+
+use confidential_transfer::{
+    exchange::{DepositAddress, DepositIndex},
+    mint,
+};
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
+
+#[test]
+fn addresses_are_derived_again_from_the_exchange_owner() {
+    let exchange_owner = Keypair::new();
+    let mint = Pubkey::new_unique();
+
+    let address = DepositAddress::derive(&exchange_owner, &mint, "user-1").unwrap();
+    let again = DepositAddress::derive(&exchange_owner, &mint, "user-1").unwrap();
+
+    assert_eq!(address.wallet.pubkey(), again.wallet.pubkey());
+    assert_eq!(address.account, again.account);
+    assert_eq!(
+        address.account,
+        mint::get_ata(&address.wallet.pubkey(), &mint)
+    );
+    let (elgamal_keypair, _) = address.keys().unwrap();
+    assert_eq!(elgamal_keypair.pubkey(), again.keys().unwrap().0.pubkey());
+}
+
+#[test]
+fn every_user_and_exchange_gets_its_own_wallet() {
+    let exchange_owner = Keypair::new();
+    let mint = Pubkey::new_unique();
+    let wallet = |owner: &Keypair, user_id| {
+        DepositAddress::derive(owner, &mint, user_id)
+            .unwrap()
+            .wallet
+            .pubkey()
+    };
+
+    let first = wallet(&exchange_owner, "user-1");
+
+    assert_ne!(first, wallet(&exchange_owner, "user-2"));
+    assert_ne!(first, wallet(&Keypair::new(), "user-1"));
+    assert_ne!(first, exchange_owner.pubkey());
+    //The wallet does not depend on the mint, only the account does
+    let other_mint =
+        DepositAddress::derive(&exchange_owner, &Pubkey::new_unique(), "user-1").unwrap();
+    assert_eq!(other_mint.wallet.pubkey(), first);
+}
+
+#[test]
+fn the_index_attributes_accounts_to_users() {
+    let dir = std::env::temp_dir().join(format!("exchange-{}", Pubkey::new_unique()));
+    let path = dir.join("deposit-addresses.json");
+    let exchange_owner = Keypair::new();
+    let (mint, other_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let alice = DepositAddress::derive(&exchange_owner, &mint, "alice").unwrap();
+    let bob = DepositAddress::derive(&exchange_owner, &mint, "bob").unwrap();
+    let carol = DepositAddress::derive(&exchange_owner, &other_mint, "carol").unwrap();
+    let mut index = DepositIndex::load(path.clone()).unwrap();
+    for address in [&alice, &bob, &carol, &alice] {
+        index.record(&exchange_owner.pubkey(), address);
+    }
+    index.save().unwrap();
+
+    let index = DepositIndex::load(path).unwrap();
+
+    assert_eq!(index.user_of(&bob.account), Some("bob"));
+    assert_eq!(index.user_of(&Pubkey::new_unique()), None);
+    assert_eq!(
+        index.users(&exchange_owner.pubkey(), &mint),
+        ["alice", "bob"]
+    );
+    assert!(index.users(&Pubkey::new_unique(), &mint).is_empty());
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,124 @@
+//! Recovery of sends from RPC faults injected between the client and a mock node.
+
+use confidential_transfer::{
+    client::{self, RpcSender},
+    faults::{Fault, FaultInjectingTransport, FaultPlan},
+    retry::{RetryPolicy, RetryingTransport},
+};
+use solana_client::{
+    nonblocking::rpc_client::RpcClient, rpc_client::RpcClientConfig, rpc_request::RpcRequest,
+};
+use solana_rpc_client::mock_sender::MockSender;
+use solana_sdk::{
+    commitment_config::CommitmentConfig, hash::Hash, pubkey::Pubkey, signature::Keypair,
+    signer::Signer, system_instruction::transfer, transaction::Transaction,
+};
+use spl_token_client::{
+    client::{ProgramClient, RpcClientResponse},
+    token::TokenError,
+};
+use std::{sync::Arc, time::Duration};
+
+// Program client of a mock node answering every request successfully, with the faults of
+// `plan` injected under the retries
+fn program_client(plan: Arc<FaultPlan>) -> Arc<dyn ProgramClient<RpcSender>> {
+    let transport = RetryingTransport::new(
+        FaultInjectingTransport::new(MockSender::new("succeeds"), plan),
+        RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        },
+    );
+    let rpc_client = RpcClient::new_sender(
+        transport,
+        RpcClientConfig::with_commitment(CommitmentConfig::confirmed()),
+    );
+    client::program_client(Arc::new(rpc_client), RpcSender::new(false))
+}
+
+fn transaction() -> Transaction {
+    let payer = Keypair::new();
+    Transaction::new_signed_with_payer(
+        &[transfer(&payer.pubkey(), &Pubkey::new_unique(), 1)],
+        Some(&payer.pubkey()),
+        &[&payer],
+        Hash::new_unique(),
+    )
+}
+
+#[tokio::test]
+async fn lost_send_response_is_retried_and_confirmed() {
+    //The transaction lands but its response is lost, so the retry finds it already processed
+    let plan = Arc::new(
+        FaultPlan::new()
+            .inject(RpcRequest::SendTransaction, 0, Fault::TimeoutAfterSending)
+            .inject(RpcRequest::SendTransaction, 1, Fault::AlreadyProcessed),
+    );
+    let transaction = transaction();
+
+    let sent = program_client(plan.clone())
+        .send_transaction(&transaction)
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        sent,
+        RpcClientResponse::Signature(signature) if signature == transaction.signatures[0]
+    ));
+    assert_eq!(plan.injected().len(), 2);
+}
+
+#[tokio::test]
+async fn timed_out_confirmation_poll_is_retried() {
+    let plan =
+        Arc::new(FaultPlan::new().inject(RpcRequest::GetSignatureStatuses, 0, Fault::Timeout));
+
+    program_client(plan.clone())
+        .send_transaction(&transaction())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        plan.injected(),
+        vec![(RpcRequest::GetSignatureStatuses, 0, Fault::Timeout)]
+    );
+}
+
+#[tokio::test]
+async fn unknown_blockhash_is_resent() {
+    let plan =
+        Arc::new(FaultPlan::new().inject(RpcRequest::SendTransaction, 0, Fault::BlockhashNotFound));
+    let program_client = program_client(plan.clone());
+    let transaction = transaction();
+    let (program_client, sent_transaction) = (&program_client, &transaction);
+
+    let sent = client::resend_on_expiry(Duration::from_secs(10), move || async move {
+        program_client
+            .send_transaction(sent_transaction)
+            .await
+            .map_err(TokenError::Client)
+    })
+    .await
+    .unwrap();
+
+    assert!(matches!(
+        sent,
+        RpcClientResponse::Signature(signature) if signature == transaction.signatures[0]
+    ));
+    assert_eq!(plan.injected().len(), 1);
+}
+
+#[tokio::test]
+async fn exhausted_retries_fail_the_send() {
+    let plan = (0..=RetryPolicy::default().max_retries).fold(FaultPlan::new(), |plan, call| {
+        plan.inject(RpcRequest::SendTransaction, call, Fault::Timeout)
+    });
+
+    assert!(
+        program_client(Arc::new(plan))
+            .send_transaction(&transaction())
+            .await
+            .is_err()
+    );
+}
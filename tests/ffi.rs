@@ -0,0 +1,258 @@
+//! C ABI of `ffi`: keys handed over as bytes, proofs that verify, balances decrypted from
+//! account data, and the status and message of failures.
+#![cfg(feature = "ffi")]
+
+use confidential_transfer::{
+    ffi::{self, CtBalances, CtBytes, CtKeys, CtStatus, CtTransferProofs, CtWithdrawProofs},
+    mock::confidential_token_account,
+};
+use confidential_transfer_core::proofs::verify_proof;
+use solana_sdk::pubkey::Pubkey;
+use spl_token_client::spl_token_2022::solana_zk_sdk::{
+    encryption::{
+        auth_encryption::{AeCiphertext, AeKey},
+        elgamal::{ElGamalCiphertext, ElGamalKeypair},
+        pod::elgamal::PodElGamalCiphertext,
+    },
+    zk_elgamal_proof_program::proof_data::{
+        BatchedGroupedCiphertext3HandlesValidityProofData, BatchedRangeProofU64Data,
+        BatchedRangeProofU128Data, CiphertextCommitmentEqualityProofData, PubkeyValidityProofData,
+    },
+};
+use std::{
+    ffi::{CStr, CString},
+    ptr,
+};
+
+struct Fixture {
+    elgamal_keypair: ElGamalKeypair,
+    aes_key: AeKey,
+    keys: *mut CtKeys,
+    account_data: Vec<u8>,
+}
+
+impl Fixture {
+    fn new(available_balance: u64) -> Self {
+        let elgamal_keypair = ElGamalKeypair::new_rand();
+        let aes_key = AeKey::new_rand();
+        let account_data = confidential_token_account(
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &elgamal_keypair,
+            &aes_key,
+            available_balance,
+        )
+        .unwrap()
+        .data;
+        let aes_bytes = <[u8; 16]>::from(aes_key.clone());
+        let mut keys = ptr::null_mut();
+        let status = unsafe {
+            ffi::ct_keys_from_bytes(
+                elgamal_keypair.secret().as_bytes().as_ptr(),
+                aes_bytes.as_ptr(),
+                &mut keys,
+            )
+        };
+        assert_eq!(status, CtStatus::Ok);
+        Self {
+            elgamal_keypair,
+            aes_key,
+            keys,
+            account_data,
+        }
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        unsafe { ffi::ct_keys_free(self.keys) };
+    }
+}
+
+fn bytes<T: bytemuck::Pod>(bytes: &CtBytes) -> T {
+    bytemuck::pod_read_unaligned(unsafe { std::slice::from_raw_parts(bytes.data, bytes.len) })
+}
+
+fn last_error() -> String {
+    unsafe { CStr::from_ptr(ffi::ct_last_error()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[test]
+fn keys_round_trip_through_bytes() {
+    let fixture = Fixture::new(0);
+
+    let (mut elgamal_secret_key, mut aes_key) = ([0; 32], [0; 16]);
+    let status = unsafe {
+        ffi::ct_keys_to_bytes(
+            fixture.keys,
+            elgamal_secret_key.as_mut_ptr(),
+            aes_key.as_mut_ptr(),
+        )
+    };
+    assert_eq!(status, CtStatus::Ok);
+    assert_eq!(
+        &elgamal_secret_key,
+        fixture.elgamal_keypair.secret().as_bytes()
+    );
+    assert_eq!(aes_key, <[u8; 16]>::from(fixture.aes_key.clone()));
+
+    let mut pubkey = ptr::null_mut();
+    assert_eq!(
+        unsafe { ffi::ct_keys_elgamal_pubkey(fixture.keys, &mut pubkey) },
+        CtStatus::Ok
+    );
+    assert_eq!(
+        unsafe { CStr::from_ptr(pubkey) }.to_str().unwrap(),
+        fixture.elgamal_keypair.pubkey().to_string()
+    );
+    unsafe { ffi::ct_string_free(pubkey) };
+}
+
+#[test]
+fn generated_proofs_verify() {
+    let fixture = Fixture::new(1_000);
+
+    let mut pubkey_validity = CtBytes::default();
+    let status = unsafe { ffi::ct_pubkey_validity_proof(fixture.keys, &mut pubkey_validity) };
+    assert_eq!(status, CtStatus::Ok);
+    verify_proof(&bytes::<PubkeyValidityProofData>(&pubkey_validity)).unwrap();
+    unsafe { ffi::ct_bytes_free(pubkey_validity) };
+
+    let mut withdraw = CtWithdrawProofs {
+        equality: CtBytes::default(),
+        range: CtBytes::default(),
+        new_decryptable_available_balance: [0; 36],
+    };
+    let status = unsafe {
+        ffi::ct_withdraw_proofs(
+            fixture.keys,
+            fixture.account_data.as_ptr(),
+            fixture.account_data.len(),
+            400,
+            &mut withdraw,
+        )
+    };
+    assert_eq!(status, CtStatus::Ok);
+    verify_proof(&bytes::<CiphertextCommitmentEqualityProofData>(
+        &withdraw.equality,
+    ))
+    .unwrap();
+    verify_proof(&bytes::<BatchedRangeProofU64Data>(&withdraw.range)).unwrap();
+    let remaining = AeCiphertext::from_bytes(&withdraw.new_decryptable_available_balance).unwrap();
+    assert_eq!(fixture.aes_key.decrypt(&remaining), Some(600));
+    unsafe { ffi::ct_withdraw_proofs_free(&mut withdraw) };
+
+    let (destination, auditor) = (ElGamalKeypair::new_rand(), ElGamalKeypair::new_rand());
+    let destination_pubkey = CString::new(destination.pubkey().to_string()).unwrap();
+    let auditor_pubkey = CString::new(auditor.pubkey().to_string()).unwrap();
+    let mut transfer = CtTransferProofs {
+        equality: CtBytes::default(),
+        ciphertext_validity: CtBytes::default(),
+        range: CtBytes::default(),
+        auditor_ciphertext_lo: [0; 64],
+        auditor_ciphertext_hi: [0; 64],
+        new_decryptable_available_balance: [0; 36],
+    };
+    let status = unsafe {
+        ffi::ct_transfer_proofs(
+            fixture.keys,
+            fixture.account_data.as_ptr(),
+            fixture.account_data.len(),
+            250,
+            destination_pubkey.as_ptr(),
+            auditor_pubkey.as_ptr(),
+            &mut transfer,
+        )
+    };
+    assert_eq!(status, CtStatus::Ok, "{}", last_error());
+    verify_proof(&bytes::<CiphertextCommitmentEqualityProofData>(
+        &transfer.equality,
+    ))
+    .unwrap();
+    verify_proof(&bytes::<BatchedGroupedCiphertext3HandlesValidityProofData>(
+        &transfer.ciphertext_validity,
+    ))
+    .unwrap();
+    verify_proof(&bytes::<BatchedRangeProofU128Data>(&transfer.range)).unwrap();
+    let auditor_lo = ElGamalCiphertext::try_from(bytemuck::cast::<_, PodElGamalCiphertext>(
+        transfer.auditor_ciphertext_lo,
+    ))
+    .unwrap();
+    assert_eq!(auditor.secret().decrypt_u32(&auditor_lo), Some(250));
+    let remaining = AeCiphertext::from_bytes(&transfer.new_decryptable_available_balance).unwrap();
+    assert_eq!(fixture.aes_key.decrypt(&remaining), Some(750));
+    unsafe { ffi::ct_transfer_proofs_free(&mut transfer) };
+}
+
+#[test]
+fn balances_are_decrypted_from_account_data() {
+    let fixture = Fixture::new(1_234);
+    let mut decode_table = ptr::null_mut();
+    assert_eq!(
+        unsafe { ffi::ct_decode_table_open(ptr::null(), &mut decode_table) },
+        CtStatus::Ok
+    );
+
+    let mut balances = CtBalances::default();
+    let status = unsafe {
+        ffi::ct_decrypt_balances(
+            fixture.keys,
+            decode_table,
+            fixture.account_data.as_ptr(),
+            fixture.account_data.len(),
+            &mut balances,
+        )
+    };
+    assert_eq!(status, CtStatus::Ok, "{}", last_error());
+    assert_eq!(
+        balances,
+        CtBalances {
+            public_balance: 0,
+            pending_balance: 0,
+            available_balance: 1_234,
+        }
+    );
+    unsafe { ffi::ct_decode_table_free(decode_table) };
+}
+
+#[test]
+fn failures_report_a_status_and_a_message() {
+    let fixture = Fixture::new(100);
+    let mut balances = CtBalances::default();
+
+    let status = unsafe {
+        ffi::ct_decrypt_balances(
+            ptr::null(),
+            ptr::null(),
+            fixture.account_data.as_ptr(),
+            fixture.account_data.len(),
+            &mut balances,
+        )
+    };
+    assert_eq!(status, CtStatus::InvalidArgument);
+    assert_eq!(last_error(), "keys is null");
+
+    let mut withdraw = CtWithdrawProofs {
+        equality: CtBytes::default(),
+        range: CtBytes::default(),
+        new_decryptable_available_balance: [0; 36],
+    };
+    let status =
+        unsafe { ffi::ct_withdraw_proofs(fixture.keys, [0u8; 8].as_ptr(), 8, 1, &mut withdraw) };
+    assert_eq!(status, CtStatus::InvalidArgument);
+    assert_eq!(last_error(), "Invalid token account data");
+
+    let status = unsafe {
+        ffi::ct_withdraw_proofs(
+            fixture.keys,
+            fixture.account_data.as_ptr(),
+            fixture.account_data.len(),
+            101,
+            &mut withdraw,
+        )
+    };
+    assert_eq!(status, CtStatus::Failed);
+    assert!(!last_error().is_empty());
+}
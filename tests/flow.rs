@@ -0,0 +1,302 @@
+//! Full confidential transfer flow against a `solana-program-test` bank, which runs the
+//! Token-2022 program and the ZK ElGamal proof program in process, so no validator is needed.
+
+use confidential_transfer::{
+    TokenClient,
+    authority::Authority,
+    balance,
+    client::{self, ComputeBudget, RpcSender},
+    close, deposit,
+    mint::{self, ConfiguredAccount, MintOptions, TransferFeeOptions},
+    placement::Packing,
+    rotate, transfer, withdraw,
+};
+use solana_program_test::ProgramTest;
+use solana_sdk::{signature::Keypair, signer::Signer};
+use spl_token_client::spl_token_2022::{
+    extension::{BaseStateWithExtensions, transfer_fee::TransferFeeAmount},
+    solana_zk_sdk::encryption::{auth_encryption::AeKey, elgamal::ElGamalKeypair},
+};
+use std::sync::Arc;
+
+const DECIMALS: u8 = 2;
+
+// Starts a bank and creates a confidential transfer mint on it, with `transfer_fee` if set,
+// returning its token client and the payer, which is also the mint authority
+async fn create_mint(transfer_fee: Option<TransferFeeOptions>) -> (TokenClient, Arc<dyn Signer>) {
+    let (banks_client, payer, _) = ProgramTest::default().start().await;
+    let payer: Arc<dyn Signer> = Arc::new(payer);
+    let program_client = client::banks_program_client(banks_client, RpcSender::new(false));
+    let created = mint::initialize_mint(
+        program_client,
+        payer.clone(),
+        payer.as_ref(),
+        &MintOptions {
+            decimals: DECIMALS,
+            auditor_elgamal_pubkey: None,
+            auto_approve_new_accounts: true,
+            transfer_fee,
+            confidential_mint_burn: false,
+        },
+        &ComputeBudget::default(),
+    )
+    .await
+    .expect("mint created");
+    (created.token, payer)
+}
+
+// Fee of 1 % of every transfer, whatever its amount
+fn transfer_fee() -> TransferFeeOptions {
+    TransferFeeOptions {
+        basis_points: 100,
+        maximum_fee: u64::MAX,
+        withdraw_withheld_authority_elgamal_pubkey: *ElGamalKeypair::new_rand().pubkey(),
+    }
+}
+
+// Creates and configures the associated token account of a new owner, paid by `payer`
+async fn configure_account(
+    token: &TokenClient,
+    payer: &Arc<dyn Signer>,
+) -> (Authority, ConfiguredAccount) {
+    let owner = Authority::single(Arc::new(Keypair::new()));
+    let account = mint::create_configure_ata(token, &payer.pubkey(), &owner)
+        .await
+        .expect("account configured");
+    (owner, account)
+}
+
+#[tokio::test]
+async fn mint_configure_deposit_apply_transfer_withdraw() {
+    let (token, payer) = create_mint(None).await;
+    let (alice, alice_account) = configure_account(&token, &payer).await;
+    let (bob, bob_account) = configure_account(&token, &payer).await;
+
+    //Public tokens of alice moved into her confidential balance
+    mint::mint_to(&token, payer.clone(), &alice_account.ata, 1_000)
+        .await
+        .expect("tokens minted");
+    deposit::deposit(&token, &alice, &alice_account.ata, 1_000, DECIMALS, None)
+        .await
+        .expect("deposit");
+    deposit::apply_pending_balance(
+        &token,
+        &alice,
+        &alice_account.ata,
+        &alice_account.elgamal_keypair,
+        &alice_account.aes_key,
+    )
+    .await
+    .expect("alice's pending balance applied");
+    assert_eq!(
+        balance::get_available_balance(&token, &alice_account.ata, &alice_account.aes_key)
+            .await
+            .unwrap(),
+        1_000
+    );
+
+    transfer::transfer(
+        &token,
+        &alice,
+        &alice_account.ata,
+        &bob_account.ata,
+        400,
+        &alice_account.elgamal_keypair,
+        &alice_account.aes_key,
+        None,
+        Packing::Separate,
+    )
+    .await
+    .expect("transfer");
+    deposit::apply_pending_balance(
+        &token,
+        &bob,
+        &bob_account.ata,
+        &bob_account.elgamal_keypair,
+        &bob_account.aes_key,
+    )
+    .await
+    .expect("bob's pending balance applied");
+    assert_eq!(
+        balance::get_available_balance(&token, &alice_account.ata, &alice_account.aes_key)
+            .await
+            .unwrap(),
+        600
+    );
+    assert_eq!(
+        balance::get_available_balance(&token, &bob_account.ata, &bob_account.aes_key)
+            .await
+            .unwrap(),
+        400
+    );
+
+    let withdrawn = withdraw::withdraw(
+        &token,
+        &bob,
+        &bob_account.ata,
+        150,
+        DECIMALS,
+        &bob_account.elgamal_keypair,
+        &bob_account.aes_key,
+        None,
+        Packing::Separate,
+    )
+    .await
+    .expect("withdraw");
+    assert_eq!(withdrawn.amount, 150);
+    assert_eq!(
+        balance::get_available_balance(&token, &bob_account.ata, &bob_account.aes_key)
+            .await
+            .unwrap(),
+        250
+    );
+    let bob_token_account = token.get_account_info(&bob_account.ata).await.unwrap();
+    assert_eq!(bob_token_account.base.amount, 150);
+}
+
+#[tokio::test]
+async fn transfer_above_the_available_balance_fails() {
+    let (token, payer) = create_mint(None).await;
+    let (alice, alice_account) = configure_account(&token, &payer).await;
+    let (_, bob_account) = configure_account(&token, &payer).await;
+    mint::mint_to(&token, payer.clone(), &alice_account.ata, 100)
+        .await
+        .expect("tokens minted");
+    deposit::deposit(&token, &alice, &alice_account.ata, 100, DECIMALS, None)
+        .await
+        .expect("deposit");
+    deposit::apply_pending_balance(
+        &token,
+        &alice,
+        &alice_account.ata,
+        &alice_account.elgamal_keypair,
+        &alice_account.aes_key,
+    )
+    .await
+    .expect("pending balance applied");
+
+    let result = transfer::transfer(
+        &token,
+        &alice,
+        &alice_account.ata,
+        &bob_account.ata,
+        101,
+        &alice_account.elgamal_keypair,
+        &alice_account.aes_key,
+        None,
+        Packing::Separate,
+    )
+    .await;
+    assert!(result.is_err());
+    assert_eq!(
+        balance::get_available_balance(&token, &alice_account.ata, &alice_account.aes_key)
+            .await
+            .unwrap(),
+        100
+    );
+}
+
+#[tokio::test]
+async fn accounts_holding_public_withheld_fees_close() {
+    let (token, payer) = create_mint(Some(transfer_fee())).await;
+    let (alice, alice_account) = configure_account(&token, &payer).await;
+    let (bob, bob_account) = configure_account(&token, &payer).await;
+    mint::mint_to(&token, payer.clone(), &alice_account.ata, 1_000)
+        .await
+        .expect("tokens minted");
+    //Bob is credited 990, the other 10 are withheld in his account
+    token
+        .transfer(
+            &alice_account.ata,
+            &bob_account.ata,
+            &alice.pubkey(),
+            1_000,
+            alice.signers(),
+        )
+        .await
+        .expect("public transfer to bob");
+    token
+        .transfer(
+            &bob_account.ata,
+            &alice_account.ata,
+            &bob.pubkey(),
+            990,
+            bob.signers(),
+        )
+        .await
+        .expect("public transfer back to alice");
+
+    let closed = close::empty_and_close_account(
+        &token,
+        &bob,
+        &bob_account.ata,
+        &payer.pubkey(),
+        &bob_account.elgamal_keypair,
+        &bob_account.aes_key,
+    )
+    .await
+    .expect("account closed");
+
+    assert_eq!(closed.harvest_signatures.len(), 1);
+    assert!(token.get_account(bob_account.ata).await.is_err());
+}
+
+#[tokio::test]
+async fn rotated_accounts_on_fee_mints_close() {
+    let (token, payer) = create_mint(Some(transfer_fee())).await;
+    let (alice, alice_account) = configure_account(&token, &payer).await;
+    let (_, bob_account) = configure_account(&token, &payer).await;
+    mint::mint_to(&token, payer.clone(), &alice_account.ata, 1_000)
+        .await
+        .expect("tokens minted");
+
+    //The public balance is parked and moved back, charged a fee each way
+    let rotated = rotate::rotate_keys(
+        &token,
+        &payer.pubkey(),
+        &alice,
+        &alice_account.ata,
+        DECIMALS,
+        (&alice_account.elgamal_keypair, &alice_account.aes_key),
+        (ElGamalKeypair::new_rand(), AeKey::new_rand()),
+        &Keypair::new(),
+        |_, _| Ok(()),
+    )
+    .await
+    .expect("keys rotated");
+    let alice_token_account = token.get_account_info(&alice_account.ata).await.unwrap();
+    assert_eq!(alice_token_account.base.amount, 980);
+    assert_eq!(
+        u64::from(
+            alice_token_account
+                .get_extension::<TransferFeeAmount>()
+                .unwrap()
+                .withheld_amount
+        ),
+        0
+    );
+    token
+        .transfer(
+            &alice_account.ata,
+            &bob_account.ata,
+            &alice.pubkey(),
+            980,
+            alice.signers(),
+        )
+        .await
+        .expect("public transfer to bob");
+
+    let closed = close::empty_and_close_account(
+        &token,
+        &alice,
+        &alice_account.ata,
+        &payer.pubkey(),
+        &rotated.configured.elgamal_keypair,
+        &rotated.configured.aes_key,
+    )
+    .await
+    .expect("account closed");
+
+    assert!(closed.harvest_signatures.is_empty());
+    assert!(token.get_account(alice_account.ata).await.is_err());
+}
@@ -0,0 +1,146 @@
+//! Confidential transfer events decoded by the indexer from the instructions of a transaction.
+
+use confidential_transfer::indexer::{self, EventKind, FetchedTransaction};
+use solana_sdk::{
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::Signature,
+    transaction::{Transaction, VersionedTransaction},
+};
+use spl_token_client::spl_token_2022::{
+    self,
+    extension::confidential_transfer::instruction::{
+        apply_pending_balance, deposit, transfer, withdraw,
+    },
+};
+use spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation;
+use std::collections::HashSet;
+
+fn fetched(instructions: &[Instruction]) -> FetchedTransaction {
+    let transaction = VersionedTransaction::from(Transaction::new_with_payer(
+        instructions,
+        Some(&Pubkey::new_unique()),
+    ));
+    FetchedTransaction {
+        signature: Signature::new_unique(),
+        slot: 42,
+        block_time: Some(1_700_000_000),
+        account_keys: transaction.message.static_account_keys().to_vec(),
+        transaction,
+    }
+}
+
+#[test]
+fn confidential_instructions_of_the_mint_become_events() {
+    let program = spl_token_2022::id();
+    let (mint, other_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (source, destination, owner) = (
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    );
+    let context_state = Pubkey::new_unique();
+    let mut instructions = vec![
+        deposit(&program, &source, &mint, 1_000, 2, &owner, &[]).unwrap(),
+        apply_pending_balance(
+            &program,
+            &source,
+            1,
+            &bytemuck::Zeroable::zeroed(),
+            &owner,
+            &[],
+        )
+        .unwrap(),
+    ];
+    instructions.extend(
+        transfer(
+            &program,
+            &source,
+            &mint,
+            &destination,
+            &bytemuck::Zeroable::zeroed(),
+            &bytemuck::Zeroable::zeroed(),
+            &bytemuck::Zeroable::zeroed(),
+            &owner,
+            &[],
+            ProofLocation::ContextStateAccount(&context_state),
+            ProofLocation::ContextStateAccount(&context_state),
+            ProofLocation::ContextStateAccount(&context_state),
+        )
+        .unwrap(),
+    );
+    instructions.extend(
+        withdraw(
+            &program,
+            &source,
+            &mint,
+            250,
+            2,
+            &bytemuck::Zeroable::zeroed(),
+            &owner,
+            &[],
+            ProofLocation::ContextStateAccount(&context_state),
+            ProofLocation::ContextStateAccount(&context_state),
+        )
+        .unwrap(),
+    );
+    //Another mint, and an apply of an account of another mint
+    instructions.push(deposit(&program, &source, &other_mint, 5, 2, &owner, &[]).unwrap());
+    instructions.push(
+        apply_pending_balance(
+            &program,
+            &Pubkey::new_unique(),
+            1,
+            &bytemuck::Zeroable::zeroed(),
+            &owner,
+            &[],
+        )
+        .unwrap(),
+    );
+    let transaction = fetched(&instructions);
+
+    let events =
+        indexer::decode_events(&transaction, &mint, &HashSet::from([source, destination])).unwrap();
+
+    let decoded = events
+        .iter()
+        .map(|event| {
+            (
+                event.instruction_index,
+                event.kind,
+                event.account,
+                event.counterparty,
+                event.amount,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        decoded,
+        [
+            (0, EventKind::Deposit, source, None, Some(1_000)),
+            (1, EventKind::Apply, source, None, None),
+            (2, EventKind::Transfer, source, Some(destination), None),
+            (3, EventKind::Withdraw, source, None, Some(250)),
+        ]
+    );
+    for event in &events {
+        assert_eq!(event.signature, transaction.signature);
+        assert_eq!((event.slot, event.block_time), (42, Some(1_700_000_000)));
+        assert_eq!(event.mint, mint);
+    }
+}
+
+#[test]
+fn transactions_without_confidential_instructions_have_no_events() {
+    let mint = Pubkey::new_unique();
+    let transaction = fetched(&[spl_memo::build_memo(b"hello", &[])]);
+
+    let events = indexer::decode_events(&transaction, &mint, &HashSet::new()).unwrap();
+
+    assert!(events.is_empty());
+    assert_eq!(
+        "withdraw".parse::<EventKind>().unwrap(),
+        EventKind::Withdraw
+    );
+    assert!("burn".parse::<EventKind>().is_err());
+}
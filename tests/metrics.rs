@@ -0,0 +1,56 @@
+//! Prometheus metrics of the library, as `metrics::render` exposes them.
+
+use confidential_transfer::{metrics, proofs::spawn_proof_generation};
+use solana_sdk::pubkey::Pubkey;
+
+// Value of the sample `name` in the rendered metrics, if present
+fn sample(rendered: &str, name: &str) -> Option<f64> {
+    rendered
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+}
+
+#[tokio::test]
+async fn proof_generation_is_timed() {
+    let before = sample(
+        &metrics::render(),
+        "confidential_transfer_proof_generation_seconds_count",
+    )
+    .unwrap_or(0.0);
+
+    spawn_proof_generation(|| Ok(())).await.unwrap();
+
+    let after = sample(
+        &metrics::render(),
+        "confidential_transfer_proof_generation_seconds_count",
+    );
+    assert_eq!(after, Some(before + 1.0));
+}
+
+#[test]
+fn pending_credits_are_labelled_by_account() {
+    let account = Pubkey::new_unique();
+    let name = format!("confidential_transfer_pending_credits{{account=\"{account}\"}}");
+
+    metrics::set_pending_credits(&account, 12);
+    assert_eq!(sample(&metrics::render(), &name), Some(12.0));
+    //An apply empties the backlog
+    metrics::set_pending_credits(&account, 0);
+    assert_eq!(sample(&metrics::render(), &name), Some(0.0));
+}
+
+#[test]
+fn every_metric_is_described() {
+    let rendered = metrics::render();
+
+    for name in [
+        "confidential_transfer_transactions_sent_total",
+        "confidential_transfer_confirmation_seconds",
+        "confidential_transfer_proof_generation_seconds",
+    ] {
+        assert!(
+            rendered.contains(&format!("# HELP {name} ")),
+            "{name} is missing from:\n{rendered}"
+        );
+    }
+}
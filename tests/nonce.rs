@@ -0,0 +1,291 @@
+//! Packed withdraws and transfers built on durable nonces, one nonce account per transaction.
+
+use confidential_transfer::{
+    TokenClient,
+    authority::Authority,
+    client::{ComputeBudget, RpcSender},
+    mint,
+    mock::{
+        MockProgramClient, confidential_mint_account, confidential_token_account, nonce_account,
+        transaction_instructions,
+    },
+    nonce::DurableNonces,
+    placement::Packing,
+    transfer, withdraw,
+};
+use confidential_transfer_core::derivation::SeedMessage;
+use solana_sdk::{
+    account::Account, compute_budget::ComputeBudgetInstruction, hash::Hash, pubkey::Pubkey,
+    rent::Rent, signature::Keypair, signer::Signer, system_instruction::advance_nonce_account,
+    sysvar,
+};
+use solana_sdk_ids::system_program;
+use spl_token_client::{
+    client::ProgramClient,
+    spl_token_2022::{
+        self,
+        solana_zk_sdk::encryption::{auth_encryption::AeKey, elgamal::ElGamalKeypair},
+    },
+    token::Token,
+};
+use std::{collections::HashMap, sync::Arc};
+
+const DECIMALS: u8 = 2;
+
+// Mock cluster holding a confidential transfer mint, the rent sysvar and nonce accounts advanced
+// by the payer, with the token client of the payer and the nonce each account holds
+struct Fixture {
+    client: Arc<MockProgramClient>,
+    token: TokenClient,
+    payer: Arc<Keypair>,
+    owner: Arc<Keypair>,
+    nonces: HashMap<Pubkey, Hash>,
+}
+
+impl Fixture {
+    fn new(nonce_accounts: usize) -> Self {
+        let payer = Arc::new(Keypair::new());
+        //The wallet fills every role, as without keypair arguments, so the context state
+        //accounts of large proofs can be verified in a legacy transaction
+        let owner = payer.clone();
+        let mint = Pubkey::new_unique();
+        let client = Arc::new(
+            MockProgramClient::new()
+                .with_account(
+                    mint,
+                    confidential_mint_account(&payer.pubkey(), DECIMALS).unwrap(),
+                )
+                .with_account(
+                    sysvar::rent::id(),
+                    Account::new_data(1, &Rent::default(), &sysvar::id()).unwrap(),
+                ),
+        );
+        let mut nonces = HashMap::new();
+        for _ in 0..nonce_accounts {
+            let address = Pubkey::new_unique();
+            let account = nonce_account(&payer.pubkey(), &Hash::new_unique()).unwrap();
+            let nonce = solana_client::nonce_utils::data_from_account(&account)
+                .unwrap()
+                .blockhash();
+            client.set_account(address, account);
+            nonces.insert(address, nonce);
+        }
+        let program_client: Arc<dyn ProgramClient<RpcSender>> = client.clone();
+        let token = Token::new(
+            program_client,
+            &spl_token_2022::id(),
+            &mint,
+            Some(DECIMALS),
+            payer.clone(),
+        );
+        Self {
+            client,
+            token,
+            payer,
+            owner,
+            nonces,
+        }
+    }
+
+    fn owner_ata(&self) -> Pubkey {
+        mint::get_ata(&self.owner.pubkey(), self.token.get_address())
+    }
+
+    // Stages an account of `owner` holding `available_balance` confidentially, returning the
+    // keys it is configured with
+    fn stage_account(
+        &self,
+        address: Pubkey,
+        owner: &Keypair,
+        available_balance: u64,
+    ) -> (ElGamalKeypair, AeKey) {
+        let (elgamal_keypair, aes_key) =
+            mint::derive_keys(owner, &SeedMessage::AccountAddress, &address).unwrap();
+        self.client.set_account(
+            address,
+            confidential_token_account(
+                self.token.get_address(),
+                &owner.pubkey(),
+                &elgamal_keypair,
+                &aes_key,
+                available_balance,
+            )
+            .unwrap(),
+        );
+        (elgamal_keypair, aes_key)
+    }
+
+    // Packing building the transactions on the nonce accounts, in `accounts` order, with
+    // `unit_price` as priority fee
+    fn packing(&self, accounts: &[Pubkey], unit_price: Option<u64>) -> Packing {
+        let program_client: Arc<dyn ProgramClient<RpcSender>> = self.client.clone();
+        Packing::Packed {
+            fee_payer: self.payer.pubkey(),
+            lookup_table: None,
+            bundle: None,
+            nonces: Some(DurableNonces::new(
+                accounts.to_vec(),
+                self.payer.clone(),
+                program_client,
+                self.payer.clone(),
+                &ComputeBudget {
+                    unit_price,
+                    ..ComputeBudget::default()
+                },
+            )),
+        }
+    }
+
+    // Checks that the n-th transaction sent on a nonce advances the nonce of the n-th of
+    // `accounts` first and is built on it, that those left on a recent blockhash need no
+    // signature but the payer's, and that every transaction is fully signed
+    fn assert_built_on(&self, accounts: &[Pubkey]) {
+        let transactions = self.client.transactions();
+        let mut accounts = accounts.iter();
+        for transaction in &transactions {
+            transaction.verify().unwrap();
+            let first = &transaction_instructions(transaction)[0];
+            let advance = advance_nonce_account(&Pubkey::default(), &self.payer.pubkey());
+            if first.program_id != system_program::id() || first.data != advance.data {
+                assert_eq!(transaction.message.header.num_required_signatures, 1);
+                assert!(
+                    !self
+                        .nonces
+                        .values()
+                        .any(|nonce| *nonce == transaction.message.recent_blockhash)
+                );
+                continue;
+            }
+            let account = accounts.next().unwrap();
+            assert_eq!(first.accounts[0].pubkey, *account);
+            assert_eq!(transaction.message.recent_blockhash, self.nonces[account]);
+        }
+        //Some transaction, the operation at least, needs the owner
+        assert!(accounts.len() < self.nonces.len());
+    }
+}
+
+#[tokio::test]
+async fn packed_withdraw_uses_one_nonce_account_per_transaction() {
+    let fixture = Fixture::new(4);
+    let accounts = fixture.nonces.keys().copied().collect::<Vec<_>>();
+    let (elgamal_keypair, aes_key) =
+        fixture.stage_account(fixture.owner_ata(), &fixture.owner, 1_000);
+    let proof_keypairs = [Keypair::new(), Keypair::new()];
+
+    withdraw::withdraw_with_proof_accounts(
+        &fixture.token,
+        &Authority::single(fixture.owner.clone()),
+        &fixture.owner_ata(),
+        400,
+        DECIMALS,
+        &elgamal_keypair,
+        &aes_key,
+        None,
+        fixture.packing(&accounts, None),
+        &proof_keypairs,
+    )
+    .await
+    .unwrap();
+
+    fixture.assert_built_on(&accounts);
+}
+
+#[tokio::test]
+async fn priority_fee_follows_the_nonce_advance() {
+    let fixture = Fixture::new(4);
+    let accounts = fixture.nonces.keys().copied().collect::<Vec<_>>();
+    let (elgamal_keypair, aes_key) =
+        fixture.stage_account(fixture.owner_ata(), &fixture.owner, 1_000);
+    let proof_keypairs = [Keypair::new(), Keypair::new()];
+
+    withdraw::withdraw_with_proof_accounts(
+        &fixture.token,
+        &Authority::single(fixture.owner.clone()),
+        &fixture.owner_ata(),
+        400,
+        DECIMALS,
+        &elgamal_keypair,
+        &aes_key,
+        None,
+        fixture.packing(&accounts, Some(1_000)),
+        &proof_keypairs,
+    )
+    .await
+    .unwrap();
+
+    fixture.assert_built_on(&accounts);
+    //Second in every transaction on a nonce, ahead of the operation
+    let advance = advance_nonce_account(&Pubkey::default(), &fixture.payer.pubkey());
+    let price = ComputeBudgetInstruction::set_compute_unit_price(1_000);
+    let priced = fixture
+        .client
+        .transactions()
+        .iter()
+        .map(transaction_instructions)
+        .filter(|instructions| instructions[0].data == advance.data)
+        .inspect(|instructions| assert_eq!(instructions[1], price))
+        .count();
+    assert!(priced > 0);
+}
+
+#[tokio::test]
+async fn packed_transfer_uses_one_nonce_account_per_transaction() {
+    let fixture = Fixture::new(8);
+    let accounts = fixture.nonces.keys().copied().collect::<Vec<_>>();
+    let (elgamal_keypair, aes_key) =
+        fixture.stage_account(fixture.owner_ata(), &fixture.owner, 1_000);
+    let recipient = Keypair::new();
+    let recipient_ata = mint::get_ata(&recipient.pubkey(), fixture.token.get_address());
+    fixture.stage_account(recipient_ata, &recipient, 0);
+    let proof_keypairs: [Keypair; 5] = std::array::from_fn(|_| Keypair::new());
+
+    transfer::transfer_with_proof_accounts(
+        &fixture.token,
+        &Authority::single(fixture.owner.clone()),
+        &fixture.owner_ata(),
+        &recipient_ata,
+        400,
+        &elgamal_keypair,
+        &aes_key,
+        Some("invoice 7"),
+        fixture.packing(&accounts, None),
+        &proof_keypairs,
+    )
+    .await
+    .unwrap();
+
+    //The proof accounts take more than one transaction, each on a nonce account of its own
+    assert!(fixture.client.transactions().len() > 1);
+    fixture.assert_built_on(&accounts);
+}
+
+#[tokio::test]
+async fn packed_transfer_with_too_few_nonce_accounts_sends_nothing() {
+    let fixture = Fixture::new(1);
+    let accounts = fixture.nonces.keys().copied().collect::<Vec<_>>();
+    let (elgamal_keypair, aes_key) =
+        fixture.stage_account(fixture.owner_ata(), &fixture.owner, 1_000);
+    let recipient = Keypair::new();
+    let recipient_ata = mint::get_ata(&recipient.pubkey(), fixture.token.get_address());
+    fixture.stage_account(recipient_ata, &recipient, 0);
+    let proof_keypairs: [Keypair; 5] = std::array::from_fn(|_| Keypair::new());
+
+    let err = transfer::transfer_with_proof_accounts(
+        &fixture.token,
+        &Authority::single(fixture.owner.clone()),
+        &fixture.owner_ata(),
+        &recipient_ata,
+        400,
+        &elgamal_keypair,
+        &aes_key,
+        None,
+        fixture.packing(&accounts, None),
+        &proof_keypairs,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(err.to_string().contains("nonce accounts"), "{err}");
+    assert!(fixture.client.transactions().is_empty());
+}
@@ -0,0 +1,120 @@
+//! Transactions written for offline signing instead of being sent.
+
+use confidential_transfer::{
+    client::{self, RpcSender},
+    offline::{
+        TRANSACTION_FILE_EXTENSION, add_signature, missing_signers, read_transaction,
+        save_transaction,
+    },
+};
+use solana_client::{nonblocking::rpc_client::RpcClient, rpc_client::RpcClientConfig};
+use solana_rpc_client::mock_sender::MockSender;
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    hash::Hash,
+    message::Message,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::Signer,
+    system_instruction::transfer,
+    transaction::Transaction,
+};
+use spl_token_client::client::{ProgramClient, RpcClientResponse};
+use std::{path::Path, sync::Arc};
+
+// Program client writing every transaction to `dir` rather than sending it to the mock node
+fn program_client(dir: &Path) -> Arc<dyn ProgramClient<RpcSender>> {
+    let rpc_client = RpcClient::new_sender(
+        MockSender::new("succeeds"),
+        RpcClientConfig::with_commitment(CommitmentConfig::confirmed()),
+    );
+    let mut sender = RpcSender::new(false);
+    sender.sign_offline = Some(dir.to_path_buf());
+    client::program_client(Arc::new(rpc_client), sender)
+}
+
+// Transaction paid by `fee_payer` moving lamports out of `owner`, signed by `signers` only
+fn transaction(fee_payer: &Keypair, owner: &Keypair, signers: &[&Keypair]) -> Transaction {
+    let message = Message::new(
+        &[transfer(&owner.pubkey(), &Pubkey::new_unique(), 1)],
+        Some(&fee_payer.pubkey()),
+    );
+    let mut transaction = Transaction::new_unsigned(message);
+    transaction.partial_sign(signers, Hash::new_unique());
+    transaction
+}
+
+#[tokio::test]
+async fn transaction_is_written_under_the_fee_payer_signature() {
+    let dir = std::env::temp_dir().join(format!("offline-{}", Pubkey::new_unique()));
+    let (fee_payer, owner) = (Keypair::new(), Keypair::new());
+    let transaction = transaction(&fee_payer, &owner, &[&fee_payer]);
+
+    let sent = program_client(&dir)
+        .send_transaction(&transaction)
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        sent,
+        RpcClientResponse::Signature(signature)
+            if signature == transaction.signatures[0] && signature != Default::default()
+    ));
+    let written = read_transaction(
+        &dir.join(transaction.message.hash().to_string())
+            .with_extension(TRANSACTION_FILE_EXTENSION),
+    )
+    .unwrap();
+    assert_eq!(missing_signers(&written), vec![owner.pubkey()]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn transaction_without_the_fee_payer_signature_is_refused() {
+    let dir = std::env::temp_dir().join(format!("offline-{}", Pubkey::new_unique()));
+    let (fee_payer, owner) = (Keypair::new(), Keypair::new());
+    let transaction = transaction(&fee_payer, &owner, &[&owner]);
+
+    let err = program_client(&dir)
+        .send_transaction(&transaction)
+        .await
+        .unwrap_err();
+
+    assert!(err.to_string().contains(&fee_payer.pubkey().to_string()));
+    assert!(!dir.exists());
+}
+
+#[test]
+fn truncated_or_altered_files_are_refused() {
+    let dir = std::env::temp_dir().join(format!("offline-{}", Pubkey::new_unique()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (fee_payer, owner) = (Keypair::new(), Keypair::new());
+    let transaction = transaction(&fee_payer, &owner, &[&fee_payer]);
+    let path = dir
+        .join("transaction")
+        .with_extension(TRANSACTION_FILE_EXTENSION);
+    save_transaction(&path, &transaction).unwrap();
+    let encoded = std::fs::read_to_string(&path).unwrap();
+
+    //Cut at a base64 boundary, so the file decodes but the transaction is incomplete
+    std::fs::write(&path, &encoded[..encoded.len() / 8 * 4]).unwrap();
+    assert!(read_transaction(&path).is_err());
+    //With a signature slot too many
+    let mut altered = transaction.clone();
+    altered.signatures.push(Signature::default());
+    save_transaction(&path, &altered).unwrap();
+    let err = read_transaction(&path).unwrap_err();
+    assert!(
+        err.to_string().contains("3 signatures for 2 signers"),
+        "{err}"
+    );
+    //Without the signature slot of the owner
+    altered.signatures.truncate(1);
+    save_transaction(&path, &altered).unwrap();
+    assert!(read_transaction(&path).is_err());
+    //Transactions built elsewhere are not indexed blindly either
+    assert_eq!(missing_signers(&altered), vec![]);
+    let signature = owner.sign_message(&altered.message_data());
+    assert!(add_signature(&mut altered, &owner.pubkey(), signature).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
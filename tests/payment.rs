@@ -0,0 +1,80 @@
+//! Payment request URIs of `request-payment`, written and parsed back.
+
+use confidential_transfer::payment::PaymentRequest;
+use solana_sdk::pubkey::Pubkey;
+
+#[test]
+fn requests_round_trip_through_their_uri() {
+    let (recipient, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let request = PaymentRequest::new(recipient, mint, 1_500_000, 6);
+
+    let uri = request.to_string();
+
+    assert_eq!(
+        uri,
+        format!(
+            "solana:{recipient}?amount=1.5&spl-token={mint}&reference={}",
+            request.reference
+        )
+    );
+    let parsed: PaymentRequest = uri.parse().unwrap();
+    assert_eq!(parsed, request);
+    assert_eq!(parsed.base_units(6).unwrap(), 1_500_000);
+    assert_eq!(parsed.memo(), request.reference.to_string());
+}
+
+#[test]
+fn every_request_has_its_own_reference() {
+    let (recipient, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+    assert_ne!(
+        PaymentRequest::new(recipient, mint, 1, 0).reference,
+        PaymentRequest::new(recipient, mint, 1, 0).reference
+    );
+}
+
+#[test]
+fn solana_pay_parameters_are_ignored() {
+    let (recipient, mint, reference) = (
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    );
+    let uri = format!(
+        "solana:{recipient}?label=Shop&amount=2&spl-token={mint}&reference={reference}&message=Thanks"
+    );
+
+    let request: PaymentRequest = uri.parse().unwrap();
+
+    assert_eq!(request.amount, "2");
+    assert_eq!(request.base_units(2).unwrap(), 200);
+    assert_eq!(request.reference, reference);
+}
+
+#[test]
+fn malformed_requests_are_refused() {
+    let (recipient, mint, reference) = (
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    );
+    for uri in [
+        format!("https:{recipient}?amount=1&spl-token={mint}&reference={reference}"),
+        format!("solana:{recipient}?spl-token={mint}&reference={reference}"),
+        format!("solana:{recipient}?amount=1&reference={reference}"),
+        format!("solana:{recipient}?amount=1&spl-token={mint}"),
+        format!("solana:{recipient}?amount=-1&spl-token={mint}&reference={reference}"),
+        format!("solana:{recipient}?amount=1&amount=2&spl-token={mint}&reference={reference}"),
+        format!("solana:nope?amount=1&spl-token={mint}&reference={reference}"),
+    ] {
+        assert!(uri.parse::<PaymentRequest>().is_err(), "{uri} was accepted");
+    }
+}
+
+#[test]
+fn amounts_finer_than_the_mint_are_refused() {
+    let request = PaymentRequest::new(Pubkey::new_unique(), Pubkey::new_unique(), 1_234, 3);
+
+    assert_eq!(request.amount, "1.234");
+    assert!(request.base_units(2).is_err());
+}
@@ -0,0 +1,118 @@
+//! Payroll CSVs of `payroll`: parsing, results written for a re-run, and the recipient checks
+//! made before paying anyone, against a `MockProgramClient`.
+
+use confidential_transfer::{
+    client::RpcSender,
+    mint,
+    mock::{MockProgramClient, confidential_mint_account, confidential_token_account},
+    payroll::{self, PaymentStatus, PayrollEntry},
+};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::Signer,
+};
+use spl_token_client::{
+    client::ProgramClient,
+    spl_token_2022::{
+        self,
+        solana_zk_sdk::encryption::{auth_encryption::AeKey, elgamal::ElGamalKeypair},
+    },
+    token::Token,
+};
+use std::sync::Arc;
+
+#[test]
+fn payrolls_are_parsed_with_or_without_header() {
+    let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+    let with_header = payroll::parse_payroll(
+        &format!("recipient,amount\n{first},1\n\n{second}, 2.5\n"),
+        2,
+    )
+    .unwrap();
+    let without_header =
+        payroll::parse_payroll(&format!("{first},100 raw\n{second},2.50"), 2).unwrap();
+
+    assert_eq!(with_header, without_header);
+    assert_eq!(
+        with_header,
+        [
+            PayrollEntry::new(first, 100),
+            PayrollEntry::new(second, 250)
+        ]
+    );
+    assert!(payroll::parse_payroll("nope,1", 2).is_err());
+    assert!(payroll::parse_payroll(&format!("{first}"), 2).is_err());
+    assert!(payroll::parse_payroll(&format!("{first},-1"), 2).is_err());
+    assert!(payroll::parse_payroll(&format!("{first},0.001"), 2).is_err());
+    assert!(payroll::parse_payroll(&format!("{first},1,unknown"), 2).is_err());
+}
+
+#[test]
+fn results_are_paid_again_except_for_the_paid_rows() {
+    let signature = Signature::new_unique();
+    let mut paid = PayrollEntry::new(Pubkey::new_unique(), 100);
+    paid.status = PaymentStatus::Paid;
+    paid.signature = Some(signature);
+    let mut failed = PayrollEntry::new(Pubkey::new_unique(), 200);
+    failed.status = PaymentStatus::Failed;
+    failed.error = Some("Transaction failed: \"InsufficientFunds\", retry later".to_string());
+    let mut invalid = PayrollEntry::new(Pubkey::new_unique(), 300);
+    invalid.status = PaymentStatus::Invalid;
+    invalid.error = Some("Account not found".to_string());
+
+    let results = payroll::write_results(&[paid.clone(), failed.clone(), invalid.clone()], 2);
+
+    assert!(results.starts_with(&format!("{}\n", payroll::RESULTS_HEADER)));
+    assert!(results.contains(&format!("{},2,failed,,", failed.recipient)));
+    assert!(results.contains(",\"Transaction failed: \"\"InsufficientFunds\"\", retry later\"\n"));
+    let rerun = payroll::parse_payroll(&results, 2).unwrap();
+    assert_eq!(rerun[0], paid);
+    assert_eq!(rerun[1], PayrollEntry::new(failed.recipient, 200));
+    assert_eq!(rerun[2], PayrollEntry::new(invalid.recipient, 300));
+}
+
+#[tokio::test]
+async fn recipients_without_a_configured_account_are_invalid() {
+    let payer = Arc::new(Keypair::new());
+    let mint = Pubkey::new_unique();
+    let (configured, unknown) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let client = Arc::new(
+        MockProgramClient::new()
+            .with_account(mint, confidential_mint_account(&payer.pubkey(), 2).unwrap())
+            .with_account(
+                mint::get_ata(&configured, &mint),
+                confidential_token_account(
+                    &mint,
+                    &configured,
+                    &ElGamalKeypair::new_rand(),
+                    &AeKey::new_rand(),
+                    0,
+                )
+                .unwrap(),
+            ),
+    );
+    let program_client: Arc<dyn ProgramClient<RpcSender>> = client.clone();
+    let token = Token::new(program_client, &spl_token_2022::id(), &mint, Some(2), payer);
+    let mut paid = PayrollEntry::new(unknown, 5);
+    paid.status = PaymentStatus::Paid;
+    paid.signature = Some(Signature::new_unique());
+    let mut entries = vec![
+        PayrollEntry::new(configured, 10),
+        PayrollEntry::new(unknown, 20),
+        paid,
+    ];
+
+    let invalid = payroll::validate_recipients(&token, &mut entries, 2)
+        .await
+        .unwrap();
+
+    assert_eq!(invalid, 1);
+    assert_eq!(entries[0].status, PaymentStatus::Pending);
+    assert_eq!(entries[1].status, PaymentStatus::Invalid);
+    assert!(entries[1].error.is_some());
+    //Paid rows are not checked again
+    assert_eq!(entries[2].status, PaymentStatus::Paid);
+    assert!(client.transactions().is_empty());
+}
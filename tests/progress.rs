@@ -0,0 +1,80 @@
+//! Progress of library operations reported to the sink of `progress::report_to`.
+
+use confidential_transfer::{
+    authority::Authority,
+    client::RpcSender,
+    mint,
+    mock::{MockProgramClient, confidential_mint_account, confidential_token_account},
+    placement::Packing,
+    progress::{self, Progress},
+    withdraw,
+};
+use confidential_transfer_core::derivation::SeedMessage;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
+use spl_token_client::{client::ProgramClient, spl_token_2022, token::Token};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+#[tokio::test]
+async fn withdraw_reports_its_proof_generation() {
+    let payer = Arc::new(Keypair::new());
+    let owner = Arc::new(Keypair::new());
+    let mint = Pubkey::new_unique();
+    let ata = mint::get_ata(&owner.pubkey(), &mint);
+    let (elgamal_keypair, aes_key) =
+        mint::derive_keys(owner.as_ref(), &SeedMessage::AccountAddress, &ata).unwrap();
+    let client = Arc::new(
+        MockProgramClient::new()
+            .with_account(mint, confidential_mint_account(&payer.pubkey(), 2).unwrap())
+            .with_account(
+                ata,
+                confidential_token_account(
+                    &mint,
+                    &owner.pubkey(),
+                    &elgamal_keypair,
+                    &aes_key,
+                    1_000,
+                )
+                .unwrap(),
+            ),
+    );
+    let program_client: Arc<dyn ProgramClient<RpcSender>> = client.clone();
+    let token = Token::new(program_client, &spl_token_2022::id(), &mint, Some(2), payer);
+    let (sink, mut reported) = mpsc::unbounded_channel();
+
+    progress::report_to(
+        sink,
+        withdraw::withdraw(
+            &token,
+            &Authority::single(owner),
+            &ata,
+            400,
+            2,
+            &elgamal_keypair,
+            &aes_key,
+            None,
+            Packing::Separate,
+        ),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(reported.recv().await, Some(Progress::GeneratingProofs));
+    assert!(matches!(
+        reported.recv().await,
+        Some(Progress::ProofsGenerated { .. })
+    ));
+    //The mock client does not go through `RpcSender`, which reports the sends
+    assert_eq!(reported.recv().await, None);
+}
+
+#[tokio::test]
+async fn nothing_is_reported_outside_of_report_to() {
+    let (sink, mut reported) = mpsc::unbounded_channel();
+
+    progress::report_to(sink, async {}).await;
+    let generated = confidential_transfer::proofs::spawn_proof_generation(|| Ok(())).await;
+
+    assert!(generated.is_ok());
+    assert_eq!(reported.recv().await, None);
+}
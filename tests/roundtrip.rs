@@ -0,0 +1,197 @@
+//! Encryption round trips under keys derived the way accounts are configured, so a change of the
+//! zk-sdk that breaks decryption or key derivation fails here before it reaches a cluster.
+
+use confidential_transfer::{
+    balance::decrypt_pending_balance,
+    decode_table::{DecodeTable, SEARCH_BITS, TABLE_BITS},
+    mint::derive_keys,
+};
+use confidential_transfer_core::{balance::decrypt_decryptable_balance, derivation::SeedMessage};
+use proptest::prelude::*;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+use spl_token_client::spl_token_2022::{
+    extension::confidential_transfer::ConfidentialTransferAccount,
+    solana_zk_sdk::encryption::{
+        auth_encryption::AeKey, elgamal::ElGamalKeypair, pod::auth_encryption::PodAeCiphertext,
+    },
+};
+use std::sync::OnceLock;
+
+//Largest amount a transfer carries, split into the 16 low and 32 high bits of pending balances
+const MAX_TRANSFER_AMOUNT: u64 = (1 << 48) - 1;
+//Largest value the decode table solves
+const MAX_DECODED: u64 = (1 << (TABLE_BITS + SEARCH_BITS)) - 1;
+
+// Decode table shared by every case, built into the cache directory on first use
+fn decode_table() -> &'static DecodeTable {
+    static TABLE: OnceLock<DecodeTable> = OnceLock::new();
+    TABLE.get_or_init(|| DecodeTable::open_default().expect("decode table"))
+}
+
+// Seed messages of both derivation versions
+fn seed_message() -> impl Strategy<Value = SeedMessage> {
+    prop_oneof![
+        Just(SeedMessage::AccountAddress),
+        "[a-z]{0,12}"
+            .prop_map(|prefix| SeedMessage::Template(format!("{prefix}:{{key}}:{{account}}"))),
+    ]
+}
+
+// Keys of a random owner and token account
+fn keys() -> impl Strategy<Value = (ElGamalKeypair, AeKey)> {
+    (any::<[u8; 32]>(), any::<[u8; 32]>(), seed_message()).prop_map(
+        |(owner, account, seed_message)| {
+            derive_keys(
+                &Keypair::new_from_array(owner),
+                &seed_message,
+                &Pubkey::new_from_array(account),
+            )
+            .expect("keys derived")
+        },
+    )
+}
+
+proptest! {
+    #[test]
+    fn decryptable_balance_round_trips(amount in any::<u64>(), (_, aes_key) in keys()) {
+        let ciphertext = PodAeCiphertext::from(aes_key.encrypt(amount));
+        prop_assert_eq!(decrypt_decryptable_balance(ciphertext, &aes_key), Some(amount));
+    }
+
+    #[test]
+    fn derivation_is_deterministic(
+        owner in any::<[u8; 32]>(),
+        account in any::<[u8; 32]>(),
+        seed_message in seed_message(),
+    ) {
+        let owner = Keypair::new_from_array(owner);
+        let account = Pubkey::new_from_array(account);
+        let (elgamal_keypair, aes_key) = derive_keys(&owner, &seed_message, &account).unwrap();
+        let (again_elgamal_keypair, again_aes_key) =
+            derive_keys(&owner, &seed_message, &account).unwrap();
+        prop_assert_eq!(elgamal_keypair.pubkey(), again_elgamal_keypair.pubkey());
+        //An AES key decrypts only what it encrypted
+        prop_assert_eq!(again_aes_key.decrypt(&aes_key.encrypt(42)), Some(42));
+    }
+
+    #[test]
+    fn signed_messages_derive_the_same_keys(
+        owner in any::<[u8; 32]>(),
+        account in any::<[u8; 32]>(),
+        seed_message in seed_message(),
+    ) {
+        let owner = Keypair::new_from_array(owner);
+        let account = Pubkey::new_from_array(account);
+        let (elgamal_keypair, aes_key) = derive_keys(&owner, &seed_message, &account).unwrap();
+        //Signatures made elsewhere, e.g. by a web wallet, of the messages
+        let [elgamal_message, aes_message] = seed_message.messages(&account);
+        let (signed_elgamal_keypair, signed_aes_key) = SeedMessage::keys_from_signatures(
+            &owner.sign_message(&elgamal_message),
+            &owner.sign_message(&aes_message),
+        )
+        .unwrap();
+        prop_assert_eq!(elgamal_keypair.pubkey(), signed_elgamal_keypair.pubkey());
+        prop_assert_eq!(<[u8; 16]>::from(aes_key), <[u8; 16]>::from(signed_aes_key.clone()));
+        //Signatures handed over as bytes are checked against the owner before use
+        let (verified_elgamal_keypair, _) = seed_message
+            .keys_from_signed_messages(
+                &owner.pubkey(),
+                &account,
+                &owner.sign_message(&elgamal_message),
+                &owner.sign_message(&aes_message),
+            )
+            .unwrap();
+        prop_assert_eq!(elgamal_keypair.pubkey(), verified_elgamal_keypair.pubkey());
+        prop_assert!(
+            seed_message
+                .keys_from_signed_messages(
+                    &Pubkey::new_unique(),
+                    &account,
+                    &owner.sign_message(&elgamal_message),
+                    &owner.sign_message(&aes_message),
+                )
+                .is_err()
+        );
+        prop_assert!(
+            seed_message
+                .keys_from_signed_messages(
+                    &owner.pubkey(),
+                    &account,
+                    &owner.sign_message(&aes_message),
+                    &owner.sign_message(&elgamal_message),
+                )
+                .is_err()
+        );
+        //Version 1 keys are those of the zk-sdk signer derivation
+        if seed_message == SeedMessage::AccountAddress {
+            let zk_elgamal_keypair =
+                ElGamalKeypair::new_from_signer(&owner, account.as_ref()).unwrap();
+            prop_assert_eq!(zk_elgamal_keypair.pubkey(), signed_elgamal_keypair.pubkey());
+            let zk_aes_key = AeKey::new_from_signer(&owner, account.as_ref()).unwrap();
+            prop_assert_eq!(<[u8; 16]>::from(zk_aes_key), <[u8; 16]>::from(signed_aes_key));
+        }
+    }
+}
+
+proptest! {
+    //Every case solves discrete logs, so fewer of them
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn elgamal_ciphertext_decodes(amount in 0..=MAX_DECODED, (elgamal_keypair, _) in keys()) {
+        let ciphertext = elgamal_keypair.pubkey().encrypt(amount);
+        prop_assert_eq!(
+            decode_table().decrypt(elgamal_keypair.secret(), &ciphertext),
+            Some(amount)
+        );
+    }
+
+    #[test]
+    fn pending_balance_round_trips(
+        amount in 0..=MAX_TRANSFER_AMOUNT,
+        (elgamal_keypair, _) in keys(),
+    ) {
+        let pubkey = elgamal_keypair.pubkey();
+        let extension = ConfidentialTransferAccount {
+            pending_balance_lo: pubkey.encrypt(amount & 0xffff).into(),
+            pending_balance_hi: pubkey.encrypt(amount >> 16).into(),
+            ..ConfidentialTransferAccount::default()
+        };
+        prop_assert_eq!(
+            decrypt_pending_balance(&extension, elgamal_keypair.secret(), decode_table()).unwrap(),
+            amount
+        );
+    }
+}
+
+#[test]
+fn corrupt_decode_table_is_rebuilt() {
+    let default_path = DecodeTable::default_path().expect("cache directory");
+    decode_table();
+    let dir = std::env::temp_dir().join(format!("decode-table-{}", Pubkey::new_unique()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("decode-table.bin");
+    //A flipped bit in the records keeps the header and the size intact
+    let mut bytes = std::fs::read(&default_path).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    std::fs::write(&path, &bytes).unwrap();
+
+    let table = DecodeTable::open(&path).unwrap();
+    let elgamal_keypair = ElGamalKeypair::new_rand();
+    let ciphertext = elgamal_keypair.pubkey().encrypt(MAX_DECODED);
+    assert_eq!(
+        table.decrypt(elgamal_keypair.secret(), &ciphertext),
+        Some(MAX_DECODED)
+    );
+    assert_eq!(
+        std::fs::read(&path).unwrap(),
+        std::fs::read(&default_path).unwrap()
+    );
+    //No temporary file is left behind
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
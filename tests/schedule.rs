@@ -0,0 +1,184 @@
+//! Recurring payments of `run-schedules`: cron expressions, and the occurrences due after
+//! downtime under each catch-up.
+
+use confidential_transfer::{
+    amount::Amount,
+    config::{Config, ScheduleConfig},
+    schedule::{self, CatchUp, CronSchedule, ON_TIME_GRACE_SECS, ScheduleState, ScheduledPayment},
+};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+
+//2026-10-17 12:00 UTC, a Saturday
+const NOW: u64 = 1_792_238_400;
+const DAY: u64 = 86_400;
+
+fn payment(cron: &str, catch_up: CatchUp) -> ScheduledPayment {
+    ScheduledPayment {
+        name: "salary".to_string(),
+        cron: cron.parse().unwrap(),
+        recipient: Pubkey::new_unique(),
+        amount: Amount::whole(100),
+        mint: None,
+        memo: None,
+        catch_up,
+    }
+}
+
+fn state() -> ScheduleState {
+    let dir = std::env::temp_dir().join(format!("schedule-{}", Pubkey::new_unique()));
+    ScheduleState::load(dir.join("schedule-state.json")).unwrap()
+}
+
+#[test]
+fn cron_expressions_find_their_next_occurrence() {
+    let next = |cron: &str| {
+        let cron: CronSchedule = cron.parse().unwrap();
+        schedule::format_time(cron.next_after(NOW).unwrap())
+    };
+
+    assert_eq!(schedule::format_time(NOW), "2026-10-17 12:00 UTC");
+    assert_eq!(next("0 9 1 * *"), "2026-11-01 09:00 UTC");
+    assert_eq!(next("@monthly"), "2026-11-01 00:00 UTC");
+    assert_eq!(next("30 8 * * 1-5"), "2026-10-19 08:30 UTC");
+    assert_eq!(next("*/15 * * * 7"), "2026-10-18 00:00 UTC");
+    assert_eq!(next("0 0 29 2 *"), "2028-02-29 00:00 UTC");
+    //Restricting both days matches either of them
+    assert_eq!(next("0 0 13 * 5"), "2026-10-23 00:00 UTC");
+    assert_eq!(next("0 12 * * *"), "2026-10-18 12:00 UTC");
+    let never: CronSchedule = "0 0 30 2 *".parse().unwrap();
+    assert_eq!(never.next_after(0), None);
+}
+
+#[test]
+fn malformed_cron_expressions_are_refused() {
+    for cron in [
+        "",
+        "* * * *",
+        "* * * * * *",
+        "60 * * * *",
+        "* 24 * * *",
+        "* * 0 * *",
+        "* * * 13 *",
+        "* * * * 8",
+        "*/0 * * * *",
+        "5-1 * * * *",
+        "a * * * *",
+        "@fortnightly",
+    ] {
+        assert!(cron.parse::<CronSchedule>().is_err(), "{cron} was accepted");
+    }
+}
+
+#[test]
+fn schedule_amounts_are_in_tokens_unless_raw() {
+    let config = |amount: &str| Config {
+        schedule: vec![ScheduleConfig {
+            name: "salary".to_string(),
+            cron: "@monthly".to_string(),
+            recipient: Pubkey::new_unique().to_string(),
+            amount: amount.to_string(),
+            ..ScheduleConfig::default()
+        }],
+        ..Config::default()
+    };
+    let amount = |value: &str| {
+        config(value)
+            .schedules()
+            .map(|payments| payments[0].amount.clone())
+    };
+
+    assert_eq!(
+        amount("50").unwrap().to_base_units(9).unwrap(),
+        50_000_000_000
+    );
+    assert_eq!(amount("50_000 raw").unwrap(), Amount::Raw(50_000));
+    for refused in ["", "0", "0.0 ", "-5", "5 tokens"] {
+        assert!(amount(refused).is_err(), "{refused:?} was accepted");
+    }
+}
+
+#[test]
+fn new_payments_start_now() {
+    let payment = payment("0 9 1 * *", CatchUp::All);
+    let mut state = state();
+
+    let due = state.due(&payment, NOW);
+
+    assert!(due.occurrences.is_empty());
+    state.advance(&payment.name, due.through);
+    assert_eq!(state.run(&payment.name).unwrap().checked_through, NOW);
+    assert!(state.due(&payment, NOW + DAY).occurrences.is_empty());
+}
+
+#[test]
+fn every_missed_occurrence_is_paid_by_default() {
+    let payment = payment("0 9 1 * *", CatchUp::All);
+    let mut state = state();
+    state.advance(&payment.name, NOW - 100 * DAY);
+
+    let due = state.due(&payment, NOW);
+
+    let paid = due
+        .occurrences
+        .iter()
+        .map(|occurrence| schedule::format_time(*occurrence))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paid,
+        [
+            "2026-08-01 09:00 UTC",
+            "2026-09-01 09:00 UTC",
+            "2026-10-01 09:00 UTC"
+        ]
+    );
+    assert_eq!(due.dropped, 0);
+    assert_eq!(due.through, NOW);
+}
+
+#[test]
+fn catch_up_latest_pays_once_and_skip_pays_nothing_late() {
+    let mut state = state();
+    state.advance("salary", NOW - 100 * DAY);
+
+    let latest = state.due(&payment("0 9 1 * *", CatchUp::Latest), NOW);
+    let skip = state.due(&payment("0 9 1 * *", CatchUp::Skip), NOW);
+
+    assert_eq!(latest.occurrences.len(), 1);
+    assert_eq!(
+        schedule::format_time(latest.occurrences[0]),
+        "2026-10-01 09:00 UTC"
+    );
+    assert_eq!(latest.dropped, 2);
+    assert!(skip.occurrences.is_empty());
+    assert_eq!(skip.dropped, 3);
+    assert_eq!(skip.through, NOW);
+    //An occurrence within the grace period is on time
+    let on_time = state.due(
+        &payment("0 12 * * *", CatchUp::Skip),
+        NOW + ON_TIME_GRACE_SECS,
+    );
+    assert_eq!(on_time.occurrences, [NOW]);
+}
+
+#[test]
+fn paid_occurrences_survive_a_restart() {
+    let payment = payment("0 9 1 * *", CatchUp::All);
+    let mut state = state();
+    state.advance(&payment.name, NOW - 100 * DAY);
+    let due = state.due(&payment, NOW);
+    let signature = Signature::new_unique();
+    //Only the first occurrence was paid before the scheduler stopped
+    state.record(&payment.name, due.occurrences[0], &signature);
+    state.save().unwrap();
+
+    let restarted = ScheduleState::load(state.path().to_path_buf()).unwrap();
+
+    let run = restarted.run(&payment.name).unwrap();
+    assert_eq!(run.last_occurrence, Some(due.occurrences[0]));
+    assert_eq!(run.last_signature, Some(signature.to_string()));
+    assert_eq!(
+        restarted.due(&payment, NOW).occurrences,
+        due.occurrences[1..]
+    );
+    std::fs::remove_dir_all(state.path().parent().unwrap()).unwrap();
+}
@@ -0,0 +1,126 @@
+//! Terminal dashboard of `tui`: the accounts table as drawn, and the key presses of the transfer
+//! form turned into transfers.
+#![cfg(feature = "tui")]
+
+use confidential_transfer::{
+    balance::{Balances, PendingCredits},
+    tui::{AccountRow, Action, Dashboard},
+};
+use ratatui::{
+    Terminal,
+    backend::TestBackend,
+    crossterm::event::{KeyCode, KeyEvent},
+};
+use solana_sdk::pubkey::Pubkey;
+
+fn dashboard() -> Dashboard {
+    let row = |symbol: &str| AccountRow {
+        symbol: Some(symbol.to_string()),
+        mint: Pubkey::new_unique(),
+        account: Pubkey::new_unique(),
+        decimals: 2,
+        balances: None,
+    };
+    Dashboard::new(Pubkey::new_unique(), vec![row("USDC"), row("EURC")])
+}
+
+fn press(dashboard: &mut Dashboard, keys: &str) -> Option<Action> {
+    keys.chars()
+        .map(|key| dashboard.handle_key(KeyEvent::from(KeyCode::Char(key))))
+        .last()
+        .flatten()
+}
+
+fn key(dashboard: &mut Dashboard, code: KeyCode) -> Option<Action> {
+    dashboard.handle_key(KeyEvent::from(code))
+}
+
+fn screen(dashboard: &mut Dashboard) -> String {
+    let mut terminal = Terminal::new(TestBackend::new(140, 14)).unwrap();
+    terminal.draw(|frame| dashboard.render(frame)).unwrap();
+    terminal.backend().to_string()
+}
+
+#[test]
+fn balances_are_drawn_in_ui_amounts() {
+    let mut dashboard = dashboard();
+    dashboard.set_balances(
+        1,
+        Balances {
+            public: 50,
+            pending: 1_234,
+            available: 100_000,
+            pending_credits: PendingCredits {
+                counter: 60,
+                maximum: 65_536,
+            },
+        },
+    );
+    dashboard.set_status("Balances up to date");
+
+    let screen = screen(&mut dashboard);
+
+    let eurc = screen.lines().find(|line| line.contains("EURC")).unwrap();
+    let eurc_account = dashboard.rows()[1].account.to_string();
+    for shown in [eurc_account.as_str(), "0.5", "12.34", "1000", "60 / 65536"] {
+        assert!(eurc.contains(shown), "{shown} missing from {eurc}");
+    }
+    assert!(screen.contains("Balances up to date"));
+}
+
+#[test]
+fn the_transfer_form_sends_from_the_selected_account() {
+    let mut dashboard = dashboard();
+    let recipient = Pubkey::new_unique();
+
+    assert_eq!(key(&mut dashboard, KeyCode::Down), None);
+    assert_eq!(press(&mut dashboard, "t"), None);
+    assert_eq!(press(&mut dashboard, &recipient.to_string()), None);
+    key(&mut dashboard, KeyCode::Tab);
+    press(&mut dashboard, "25");
+    key(&mut dashboard, KeyCode::Tab);
+    press(&mut dashboard, "invoice 7");
+    assert!(screen(&mut dashboard).contains("Confidential transfer"));
+
+    assert_eq!(
+        key(&mut dashboard, KeyCode::Enter),
+        Some(Action::Transfer {
+            row: 1,
+            recipient,
+            amount: 2_500,
+            memo: Some("invoice 7".to_string()),
+        })
+    );
+    //The form is closed, so keys act on the table again
+    assert_eq!(press(&mut dashboard, "q"), Some(Action::Quit));
+}
+
+#[test]
+fn invalid_fields_keep_the_form_open() {
+    let mut dashboard = dashboard();
+    press(&mut dashboard, "t");
+    press(&mut dashboard, "nobody");
+
+    assert_eq!(key(&mut dashboard, KeyCode::Enter), None);
+    assert!(screen(&mut dashboard).contains("Invalid recipient wallet: nobody"));
+
+    for _ in 0.."nobody".len() {
+        key(&mut dashboard, KeyCode::Backspace);
+    }
+    press(&mut dashboard, &Pubkey::new_unique().to_string());
+    key(&mut dashboard, KeyCode::Tab);
+    press(&mut dashboard, "0.001");
+    assert_eq!(key(&mut dashboard, KeyCode::Enter), None);
+    assert!(
+        screen(&mut dashboard).contains("Amount 0.001 is finer than the 2 decimals of the mint")
+    );
+    for _ in 0.."0.001".len() {
+        key(&mut dashboard, KeyCode::Backspace);
+    }
+    press(&mut dashboard, "0");
+    assert_eq!(key(&mut dashboard, KeyCode::Enter), None);
+    assert!(screen(&mut dashboard).contains("Invalid amount: 0"));
+
+    assert_eq!(key(&mut dashboard, KeyCode::Esc), None);
+    assert_eq!(press(&mut dashboard, "r"), Some(Action::Refresh));
+}
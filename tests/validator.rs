@@ -0,0 +1,71 @@
+//! Flow against a local `solana-test-validator` spawned by the testkit, covering what a
+//! `solana-program-test` bank does not: RPC sends, confirmations and websocket subscriptions.
+//!
+//! Needs the Solana CLI on the `PATH`; run with `cargo test -- --ignored`.
+
+use confidential_transfer::{
+    authority::Authority,
+    balance,
+    client::{ComputeBudget, RpcSender},
+    deposit,
+    mint::{self, MintOptions},
+    testkit::TestValidator,
+};
+use solana_client::nonblocking::pubsub_client::PubsubClient;
+use solana_sdk::{signature::Keypair, signer::Signer};
+use std::sync::Arc;
+
+#[tokio::test]
+#[ignore = "spawns solana-test-validator"]
+async fn deposit_and_apply_on_a_test_validator() {
+    let validator = TestValidator::start().await.expect("validator started");
+    let payer: Arc<dyn Signer> = validator.payer();
+    let mut sender = RpcSender::new(false);
+    sender.pubsub_client = Some(Arc::new(
+        PubsubClient::new(&validator.ws_url())
+            .await
+            .expect("websocket connected"),
+    ));
+    let created = mint::initialize_mint(
+        validator.program_client(sender),
+        payer.clone(),
+        payer.as_ref(),
+        &MintOptions {
+            decimals: 2,
+            auditor_elgamal_pubkey: None,
+            auto_approve_new_accounts: true,
+            transfer_fee: None,
+            confidential_mint_burn: false,
+        },
+        &ComputeBudget::default(),
+    )
+    .await
+    .expect("mint created");
+    let token = created.token;
+    let owner = Authority::single(Arc::new(Keypair::new()));
+    let account = mint::create_configure_ata(&token, &payer.pubkey(), &owner)
+        .await
+        .expect("account configured");
+
+    mint::mint_to(&token, payer.clone(), &account.ata, 500)
+        .await
+        .expect("tokens minted");
+    deposit::deposit_and_apply(
+        &token,
+        &owner,
+        &account.ata,
+        500,
+        2,
+        &account.elgamal_keypair,
+        &account.aes_key,
+        None,
+    )
+    .await
+    .expect("deposit and apply");
+    assert_eq!(
+        balance::get_available_balance(&token, &account.ata, &account.aes_key)
+            .await
+            .unwrap(),
+        500
+    );
+}
@@ -0,0 +1,88 @@
+//! Signing and delivery of credit notifications, against a local HTTP receiver.
+
+use confidential_transfer::{
+    watch::ReceivedCredits,
+    webhook::{CreditNotification, SIGNATURE_HEADER, Webhook, sign},
+};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    thread,
+};
+
+#[test]
+fn signature_is_the_hmac_sha256_of_the_body() {
+    //RFC 4231, test case 2
+    assert_eq!(
+        sign(b"Jefe", b"what do ya want for nothing?"),
+        "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
+
+// Accepts one request, answers 200 and returns its headers and body
+fn receive_one(listener: TcpListener) -> (Vec<(String, String)>, Vec<u8>) {
+    let (stream, _) = listener.accept().unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut headers = vec![];
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(": ") {
+            headers.push((name.to_ascii_lowercase(), value.to_string()));
+        }
+    }
+    let length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .map(|(_, value)| value.parse().unwrap())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    (&stream)
+        .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+        .unwrap();
+    (headers, body)
+}
+
+#[tokio::test]
+async fn notification_is_posted_signed() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/credits", listener.local_addr().unwrap());
+    let receiver = thread::spawn(move || receive_one(listener));
+    let (account, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let notification = CreditNotification::new(
+        &account,
+        &mint,
+        ReceivedCredits {
+            counter_delta: 2,
+            pending_credits: 5,
+            slot: 42,
+        },
+        Some(Signature::default()),
+    );
+
+    Webhook::new(url, b"secret")
+        .post(&notification)
+        .await
+        .unwrap();
+
+    let (headers, body) = receiver.join().unwrap();
+    let signature = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(SIGNATURE_HEADER))
+        .map(|(_, value)| value.clone());
+    assert_eq!(signature, Some(sign(b"secret", &body)));
+    let payload: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(payload["event"], "credit");
+    assert_eq!(payload["account"], account.to_string());
+    assert_eq!(payload["mint"], mint.to_string());
+    assert_eq!(payload["counter_delta"], 2);
+    assert_eq!(payload["pending_credit_counter"], 5);
+    assert_eq!(payload["slot"], 42);
+    assert_eq!(payload["signature"], Signature::default().to_string());
+}